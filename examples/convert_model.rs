@@ -4,7 +4,7 @@ use image::ImageReader;
 use nalgebra as na;
 
 fn main() {
-    env_logger::init();
+    tracing_subscriber::fmt::init();
     let img = ImageReader::open("data/tum_vi_with_chart.png")
         .unwrap()
         .decode()
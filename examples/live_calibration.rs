@@ -0,0 +1,109 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use aprilgrid::detector::TagDetector;
+use camera_intrinsic_calibration::board::create_default_6x6_board;
+use camera_intrinsic_calibration::coverage::suggest_next_capture;
+use camera_intrinsic_calibration::incremental::{calibrate_live, IncrementalCalibrator};
+use camera_intrinsic_calibration::observer::NoopObserver;
+use camera_intrinsic_calibration::types::CalibParams;
+use camera_intrinsic_calibration::visualization::{
+    log_capture_suggestion, log_image_at, send_default_blueprint,
+};
+use camera_intrinsic_model::*;
+use image::ImageReader;
+
+/// Images waiting for the detection, the new ones are dropped while it is full.
+const QUEUE_SIZE: usize = 2;
+
+/// Calibrate while a capture tool keeps writing png files into a folder.
+/// cargo run -r --example live_calibration -- <image_folder> [model]
+fn main() {
+    tracing_subscriber::fmt::init();
+    let args: Vec<String> = std::env::args().collect();
+    let folder = args
+        .get(1)
+        .expect("usage: live_calibration <image_folder> [model]");
+    let model = GenericModel::from_str(args.get(2).map_or("eucm", |s| s.as_str())).unwrap();
+
+    let recording = rerun::RecordingStreamBuilder::new("live_calibration")
+        .spawn()
+        .unwrap();
+    send_default_blueprint(&recording, 1);
+    let detector = TagDetector::new(&aprilgrid::TagFamily::T36H11, None);
+    let board = create_default_6x6_board();
+    let mut calibrator = IncrementalCalibrator::new(
+        &model,
+        CalibParams {
+            fixed_focal: None,
+            disabled_distortion_num: 0,
+            one_focal: false,
+            plateau: None,
+        },
+    );
+    let converged = AtomicBool::new(false);
+    let mut seen = HashSet::new();
+    let mut pending = Vec::new();
+    let mut frame_idx = 0;
+    // capture stage, new images of the folder in the order of the file names
+    let images = std::iter::from_fn(|| loop {
+        if converged.load(Ordering::Relaxed) {
+            return None;
+        }
+        if let Some(path) = pending.pop() {
+            let Ok(img) = ImageReader::open(&path).unwrap().decode() else {
+                continue;
+            };
+            let time_ns = frame_idx * 100_000_000;
+            frame_idx += 1;
+            log_image_at(&recording, "/cam0", time_ns, &img);
+            return Some((time_ns, img));
+        }
+        let mut new_paths: Vec<_> = std::fs::read_dir(folder)
+            .unwrap()
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "png") && !seen.contains(p))
+            .collect();
+        if new_paths.is_empty() {
+            std::thread::sleep(Duration::from_millis(200));
+            continue;
+        }
+        new_paths.sort_by(|a, b| b.cmp(a));
+        seen.extend(new_paths.iter().cloned());
+        pending = new_paths;
+    });
+    calibrate_live(
+        &mut calibrator,
+        images,
+        &detector,
+        &board,
+        QUEUE_SIZE,
+        &NoopObserver,
+        |calibrator| {
+            let model = calibrator.model().unwrap();
+            for (i, p) in model.params().iter().enumerate() {
+                recording
+                    .log(format!("/params/{}", i), &rerun::Scalar::new(*p))
+                    .unwrap();
+            }
+            if let Some(Some(frame_feature)) = calibrator.frames().last() {
+                let suggestion = suggest_next_capture(
+                    calibrator.frames(),
+                    frame_feature.img_w_h,
+                    calibrator.t_cam_boards(),
+                );
+                log_capture_suggestion(&recording, "/cam0", &suggestion);
+            }
+            converged.store(calibrator.is_converged(), Ordering::Relaxed);
+        },
+    );
+    let model = calibrator.model().unwrap();
+    println!(
+        "converged with {} frames\n{}",
+        calibrator.frames().len(),
+        serde_json::to_string_pretty(model).unwrap()
+    );
+    model_to_json("live_calibration.json", model);
+}
@@ -10,7 +10,7 @@ use image::ImageReader;
 use nalgebra as na;
 
 fn main() {
-    env_logger::init();
+    tracing_subscriber::fmt::init();
     let params = na::dvector![471.019, 470.243, 367.122, 246.741, 0.67485];
     let model = GenericModel::UCM(UCM::new(&params, 752, 480));
 
@@ -31,7 +31,15 @@ fn main() {
                     let id = k * 4 + i as u32;
                     if let Some(p3d) = board.id_to_3d.get(&id) {
                         let p2d = Vec2::new(p.0, p.1);
-                        Some((id, FeaturePoint { p2d, p3d: *p3d }))
+                        Some((
+                            id,
+                            FeaturePoint {
+                                p2d,
+                                p3d: *p3d,
+                                time_ns: None,
+                                blur: None,
+                            },
+                        ))
                     } else {
                         None
                     }
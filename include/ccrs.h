@@ -0,0 +1,97 @@
+#ifndef CCRS_H
+#define CCRS_H
+
+/* Generated with cbindgen, see scripts/generate_c_header.sh */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define CCRS_OK 0
+
+#define CCRS_ERROR_NULL_POINTER -1
+
+#define CCRS_ERROR_INVALID_ARGUMENT -2
+
+#define CCRS_ERROR_NOT_ENOUGH_CORNERS -3
+
+#define CCRS_ERROR_CALIBRATION_FAILED -4
+
+#define CCRS_ERROR_NOT_CALIBRATED -5
+
+#define CCRS_ERROR_BUFFER_TOO_SMALL -6
+
+typedef struct CcrsCalibSession CcrsCalibSession;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Create a session for the given model name ("ucm", "eucm", "kb4", "opencv5", "eucmt", "ftheta").
+// Returns null if the model name is unknown.
+//
+// # Safety
+// `model_name` must be a valid null terminated string.
+CcrsCalibSession *ccrs_session_create(const char *model_name);
+
+// # Safety
+// `session` must come from `ccrs_session_create` and must not be used afterwards.
+void ccrs_session_destroy(CcrsCalibSession *session);
+
+// Add the detected corners of one frame. `p2ds` holds `num * 2` pixel coordinates
+// and `p3ds` holds `num * 3` board coordinates in meters.
+//
+// # Safety
+// All arrays must hold at least the number of elements described above.
+int ccrs_session_add_frame(CcrsCalibSession *session,
+                           int64_t time_ns,
+                           uint32_t width,
+                           uint32_t height,
+                           const uint32_t *ids,
+                           const float *p2ds,
+                           const float *p3ds,
+                           size_t num);
+
+// Run the calibration on all added frames. A non positive `fixed_focal` lets the focal be optimized.
+//
+// # Safety
+// `session` must come from `ccrs_session_create`.
+int ccrs_session_calibrate(CcrsCalibSession *session,
+                           bool one_focal,
+                           size_t disabled_distortion_num,
+                           double fixed_focal);
+
+// Number of intrinsic parameters of the session model.
+//
+// # Safety
+// `session` must come from `ccrs_session_create`.
+size_t ccrs_session_params_len(const CcrsCalibSession *session);
+
+// Dimension of the parameter covariance, 0 before a successful calibration. It's `params_len`
+// in the layout of the params, also with `one_focal` or a fixed focal, where fy is fx and its
+// row and column repeat the ones of fx.
+//
+// # Safety
+// `session` must come from `ccrs_session_create`.
+size_t ccrs_session_covariance_dim(const CcrsCalibSession *session);
+
+// Copy the calibrated parameters into `out`, which holds `len` elements.
+//
+// # Safety
+// `out` must be valid for `len` writes.
+int ccrs_session_get_params(const CcrsCalibSession *session, double *out, size_t len);
+
+// Copy the row major parameter covariance into `out`, which holds `len` elements
+// (at least `covariance_dim * covariance_dim`, see `ccrs_session_covariance_dim`).
+//
+// # Safety
+// `out` must be valid for `len` writes.
+int ccrs_session_get_covariance(const CcrsCalibSession *session, double *out, size_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CCRS_H */
@@ -0,0 +1,2 @@
+rustup target add wasm32-unknown-unknown
+cargo build -r --lib --target wasm32-unknown-unknown --no-default-features
@@ -0,0 +1,2 @@
+# cargo install cbindgen
+cbindgen --config cbindgen.toml --crate camera-intrinsic-calibration --output include/ccrs.h
@@ -0,0 +1,66 @@
+use camera_intrinsic_model::GenericModel;
+
+/// Pixel `p` of the calibrated image is `(p + 0.5) * scale - 0.5 - offset` in the new image.
+/// Distortion params of every model are in normalized coordinates and stay the same.
+pub fn transform_intrinsics(
+    model: &GenericModel<f64>,
+    scale: (f64, f64),
+    offset: (f64, f64),
+    new_w_h: (u32, u32),
+) -> GenericModel<f64> {
+    let mut params = model.params();
+    params[0] *= scale.0;
+    params[1] *= scale.1;
+    params[2] = (params[2] + 0.5) * scale.0 - 0.5 - offset.0;
+    params[3] = (params[3] + 0.5) * scale.1 - 0.5 - offset.1;
+    let mut new_model = model.new_from_params(&params);
+    new_model.set_w_h(new_w_h.0, new_w_h.1);
+    new_model
+}
+
+/// Calibration of the crop window (x, y, w, h) of the sensor.
+pub fn crop(model: &GenericModel<f64>, roi: (u32, u32, u32, u32)) -> GenericModel<f64> {
+    let (x, y, w, h) = roi;
+    transform_intrinsics(model, (1.0, 1.0), (x as f64, y as f64), (w, h))
+}
+
+/// Calibration of the `factor` x `factor` binned image.
+pub fn bin(model: &GenericModel<f64>, factor: u32) -> GenericModel<f64> {
+    let s = 1.0 / factor as f64;
+    transform_intrinsics(
+        model,
+        (s, s),
+        (0.0, 0.0),
+        (
+            model.width() as u32 / factor,
+            model.height() as u32 / factor,
+        ),
+    )
+}
+
+/// Calibration of the image scaled by `factor`.
+pub fn scale(model: &GenericModel<f64>, factor: f64) -> GenericModel<f64> {
+    transform_intrinsics(
+        model,
+        (factor, factor),
+        (0.0, 0.0),
+        (
+            (model.width() * factor).round() as u32,
+            (model.height() * factor).round() as u32,
+        ),
+    )
+}
+
+/// Calibration of the image resized to `new_width` x `new_height`, the aspect ratio may change.
+/// No distortion re-fit is needed since the distortion params are independent of the resolution.
+pub fn rescale(model: &GenericModel<f64>, new_width: u32, new_height: u32) -> GenericModel<f64> {
+    transform_intrinsics(
+        model,
+        (
+            new_width as f64 / model.width(),
+            new_height as f64 / model.height(),
+        ),
+        (0.0, 0.0),
+        (new_width, new_height),
+    )
+}
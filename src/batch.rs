@@ -0,0 +1,164 @@
+use camera_intrinsic_model::GenericModel;
+use nalgebra as na;
+use rayon::prelude::*;
+
+/// Points of a rayon task.
+const CHUNK_LEN: usize = 4096;
+/// Points projected together in the same loop, the closed-form models compile to simd.
+const LANES: usize = 8;
+
+/// Runs `kernel` on chunks of `input` in parallel, `kernel` fills the outputs of a chunk.
+fn map_chunks<I: Sync, O: Send + Clone>(
+    input: &[I],
+    empty: O,
+    kernel: impl Fn(&[I], &mut [O]) + Sync,
+) -> Vec<O> {
+    let mut output = vec![empty; input.len()];
+    input
+        .par_chunks(CHUNK_LEN)
+        .zip(output.par_chunks_mut(CHUNK_LEN))
+        .for_each(|(input, output)| kernel(input, output));
+    output
+}
+
+/// `f` from the camera frame (x, y, z) to the pixel (u, v) on `LANES` points at a time, the
+/// points outside of the `w` x `h` image are `None`.
+#[inline(always)]
+fn project_lanes(
+    p3ds: &[na::Vector3<f64>],
+    p2ds: &mut [Option<na::Vector2<f64>>],
+    w: f64,
+    h: f64,
+    f: impl Fn(f64, f64, f64) -> (f64, f64),
+) {
+    for (p3ds, p2ds) in p3ds.chunks(LANES).zip(p2ds.chunks_mut(LANES)) {
+        let (mut x, mut y, mut z) = ([0.0; LANES], [0.0; LANES], [1.0; LANES]);
+        for (i, p) in p3ds.iter().enumerate() {
+            (x[i], y[i], z[i]) = (p.x, p.y, p.z);
+        }
+        let (mut u, mut v) = ([0.0; LANES], [0.0; LANES]);
+        for i in 0..LANES {
+            (u[i], v[i]) = f(x[i], y[i], z[i]);
+        }
+        for (i, p2d) in p2ds.iter_mut().enumerate() {
+            let outside = u[i] < 0.0 || u[i] > w || v[i] < 0.0 || v[i] > h;
+            *p2d = (!outside).then(|| na::Vector2::new(u[i], v[i]));
+        }
+    }
+}
+
+/// `f` from the pixel (u, v) to the ray (x, y, 1) on `LANES` points at a time, the pixels
+/// outside of the `w` x `h` image are `None`.
+#[inline(always)]
+fn unproject_lanes(
+    p2ds: &[na::Vector2<f64>],
+    p3ds: &mut [Option<na::Vector3<f64>>],
+    w: f64,
+    h: f64,
+    f: impl Fn(f64, f64) -> (f64, f64),
+) {
+    for (p2ds, p3ds) in p2ds.chunks(LANES).zip(p3ds.chunks_mut(LANES)) {
+        let (mut u, mut v) = ([0.0; LANES], [0.0; LANES]);
+        for (i, p) in p2ds.iter().enumerate() {
+            (u[i], v[i]) = (p.x, p.y);
+        }
+        let (mut x, mut y) = ([0.0; LANES], [0.0; LANES]);
+        for i in 0..LANES {
+            (x[i], y[i]) = f(u[i], v[i]);
+        }
+        for (i, p3d) in p3ds.iter_mut().enumerate() {
+            let outside = u[i] < 0.0 || u[i] > w - 1.0 || v[i] < 0.0 || v[i] > h - 1.0;
+            *p3d = (!outside).then(|| na::Vector3::new(x[i], y[i], 1.0));
+        }
+    }
+}
+
+/// Same as `GenericModel::project`, but the params are read once per batch and the closed-form
+/// models (UCM, EUCM and KB4) are projected `LANES` points at a time.
+pub fn project(
+    model: &GenericModel<f64>,
+    p3ds: &[na::Vector3<f64>],
+) -> Vec<Option<na::Vector2<f64>>> {
+    let (w, h) = (model.width(), model.height());
+    let p = model.params();
+    let (fx, fy, cx, cy) = (p[0], p[1], p[2], p[3]);
+    map_chunks(p3ds, None, |p3ds, p2ds| match model {
+        GenericModel::UCM(_) | GenericModel::EUCM(_) => {
+            let alpha = p[4];
+            let beta = if let GenericModel::EUCM(_) = model {
+                p[5]
+            } else {
+                1.0
+            };
+            project_lanes(p3ds, p2ds, w, h, |x, y, z| {
+                let rho = (beta * (x * x + y * y) + z * z).sqrt();
+                let norm = alpha * rho + (1.0 - alpha) * z;
+                (fx * (x / norm) + cx, fy * (y / norm) + cy)
+            })
+        }
+        GenericModel::KannalaBrandt4(_) => {
+            let (k1, k2, k3, k4) = (p[4], p[5], p[6], p[7]);
+            project_lanes(p3ds, p2ds, w, h, |x, y, z| {
+                let (xn, yn) = (x / z, y / z);
+                let r = (xn * xn + yn * yn).sqrt();
+                let theta = r.atan();
+                let theta2 = theta * theta;
+                let theta4 = theta2 * theta2;
+                let theta6 = theta2 * theta4;
+                let theta8 = theta2 * theta6;
+                let theta_d = theta * (1.0 + k1 * theta2 + k2 * theta4 + k3 * theta6 + k4 * theta8);
+                let d = theta_d / r;
+                (fx * (xn * d) + cx, fy * (yn * d) + cy)
+            })
+        }
+        _ => project_lanes(p3ds, p2ds, w, h, |x, y, z| {
+            let p2d = model.project_one(&na::Vector3::new(x, y, z));
+            (p2d.x, p2d.y)
+        }),
+    })
+}
+
+/// Same as `GenericModel::unproject`, but the params are read once per batch and the models
+/// with closed-form unprojection (UCM and EUCM) are unprojected `LANES` points at a time.
+pub fn unproject(
+    model: &GenericModel<f64>,
+    p2ds: &[na::Vector2<f64>],
+) -> Vec<Option<na::Vector3<f64>>> {
+    let (w, h) = (model.width(), model.height());
+    let p = model.params();
+    let (fx, fy, cx, cy) = (p[0], p[1], p[2], p[3]);
+    map_chunks(p2ds, None, |p2ds, p3ds| match model {
+        GenericModel::UCM(_) => {
+            let alpha = p[4];
+            let xi = alpha / (1.0 - alpha);
+            unproject_lanes(p2ds, p3ds, w, h, |u, v| {
+                let mx = (1.0 - alpha) * ((u - cx) / fx);
+                let my = (1.0 - alpha) * ((v - cy) / fy);
+                let r2 = mx * mx + my * my;
+                let n = (1.0 + (1.0 - xi * xi) * r2).sqrt();
+                let k = (xi + n) / (1.0 + r2);
+                let z = k - xi;
+                (k * mx / z, k * my / z)
+            })
+        }
+        GenericModel::EUCM(_) => {
+            let (alpha, beta) = (p[4], p[5]);
+            let gamma = 1.0 - alpha;
+            unproject_lanes(p2ds, p3ds, w, h, |u, v| {
+                let mx = (u - cx) / fx;
+                let my = (v - cy) / fy;
+                let r2 = mx * mx + my * my;
+                let tmp1 = 1.0 - alpha * alpha * beta * r2;
+                let tmp2 = alpha * (1.0 - (alpha - gamma) * beta * r2).sqrt() + gamma;
+                let k = tmp1 / tmp2;
+                (mx / k, my / k)
+            })
+        }
+        _ => {
+            for (p2d, p3d) in p2ds.iter().zip(p3ds.iter_mut()) {
+                let outside = p2d.x < 0.0 || p2d.x > w - 1.0 || p2d.y < 0.0 || p2d.y > h - 1.0;
+                *p3d = (!outside).then(|| model.unproject_one(p2d));
+            }
+        }
+    })
+}
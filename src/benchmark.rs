@@ -0,0 +1,68 @@
+use camera_intrinsic_model::*;
+use serde::{Deserialize, Serialize};
+
+use crate::compare::compare_models;
+use crate::types::CalibrationReport;
+
+/// A dataset in the euroc format with published calibrations of its cameras, e.g. the calib
+/// sequences of TUM-VI converted to model jsons.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkDataset {
+    pub name: String,
+    pub path: String,
+    /// Board config json, the default board if missing.
+    #[serde(default)]
+    pub board_config: Option<String>,
+    /// Model json of every camera, the cameras are calibrated with the same model.
+    pub references: Vec<String>,
+}
+
+/// Calibration of a camera of a benchmark dataset against its published calibration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkResult {
+    pub dataset: String,
+    pub cam_idx: usize,
+    pub reprojection_mean: f64,
+    /// Of the pixels unprojected by the reference and projected by the calibration, in px.
+    pub projection_mean: f64,
+    pub projection_max: f64,
+    pub passed: bool,
+}
+
+impl BenchmarkResult {
+    /// Passes if the calibration projects the rays of the reference within
+    /// `max_projection_error` px on average. The params aren't compared since different
+    /// params can describe the same rays.
+    pub fn new(
+        dataset: &str,
+        reference: &GenericModel<f64>,
+        calibrated: &GenericModel<f64>,
+        report: &CalibrationReport,
+        max_projection_error: f64,
+    ) -> BenchmarkResult {
+        let comparison = compare_models(reference, calibrated, 8);
+        BenchmarkResult {
+            dataset: dataset.to_string(),
+            cam_idx: report.cam_idx,
+            reprojection_mean: report.mean_error,
+            projection_mean: comparison.projection_mean,
+            projection_max: comparison.projection_max,
+            passed: comparison.projection_mean <= max_projection_error,
+        }
+    }
+}
+
+/// Written by `bench-dataset`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkReport {
+    pub max_projection_error: f64,
+    /// Datasets whose cameras couldn't be calibrated.
+    pub failed_datasets: Vec<String>,
+    pub results: Vec<BenchmarkResult>,
+}
+
+impl BenchmarkReport {
+    pub fn passed(&self) -> bool {
+        self.failed_datasets.is_empty() && self.results.iter().all(|r| r.passed)
+    }
+}
@@ -0,0 +1,387 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use aprilgrid::detector::TagDetector;
+use aprilgrid::TagFamily;
+use camera_intrinsic_calibration::board::{board_config_from_json, Board, BoardConfig};
+use camera_intrinsic_calibration::coverage::{suggest_next_capture, CaptureSuggestion};
+use camera_intrinsic_calibration::data_loader::{load_euroc, load_others, DEFAULT_QUEUE_SIZE};
+use camera_intrinsic_calibration::detected_points::FrameFeature;
+use camera_intrinsic_calibration::frame_filter::FrameFilter;
+use camera_intrinsic_calibration::logging::init_tracing;
+use camera_intrinsic_calibration::observer::{NoopObserver, PipelineObserver};
+use camera_intrinsic_calibration::types::CalibParams;
+use camera_intrinsic_calibration::util::{init_and_calibrate_one_camera_with_trials, validation};
+use camera_intrinsic_model::{model_to_json, GenericModel};
+use eframe::egui;
+use image::DynamicImage;
+
+const TAG_FAMILIES: [&str; 5] = ["t16h5", "t25h7", "t25h9", "t36h11", "t36h11b1"];
+const MODELS: [&str; 6] = ["ucm", "eucm", "kb4", "opencv5", "eucmt", "ftheta"];
+
+/// Image and detected corners.
+type Preview = (DynamicImage, Vec<(f32, f32)>);
+
+/// Keeps the latest detection for the preview.
+struct GuiObserver {
+    ctx: egui::Context,
+    latest: Mutex<Option<Preview>>,
+    loaded: AtomicUsize,
+    detected: AtomicUsize,
+}
+
+impl PipelineObserver for GuiObserver {
+    fn on_frame_detected(
+        &self,
+        _cam_idx: usize,
+        _time_ns: i64,
+        img: &DynamicImage,
+        frame_feature: Option<&FrameFeature>,
+    ) {
+        self.loaded.fetch_add(1, Ordering::Relaxed);
+        let corners = if let Some(f) = frame_feature {
+            self.detected.fetch_add(1, Ordering::Relaxed);
+            f.features.values().map(|p| (p.p2d.x, p.p2d.y)).collect()
+        } else {
+            Vec::new()
+        };
+        *self.latest.lock().unwrap() = Some((img.clone(), corners));
+        self.ctx.request_repaint();
+    }
+}
+
+struct CalibrationResult {
+    model: GenericModel<f64>,
+    avg_reprojection_error: f64,
+    median_reprojection_error: f64,
+}
+
+enum Job {
+    Idle,
+    Detecting,
+    Calibrating,
+}
+
+struct CalibrationApp {
+    dataset_path: String,
+    euroc_format: bool,
+    board_config_path: String,
+    tag_family: usize,
+    model: usize,
+    cam_num: usize,
+    one_focal: bool,
+    job: Arc<Mutex<Job>>,
+    observer: Arc<GuiObserver>,
+    preview: Option<(egui::TextureHandle, Vec<(f32, f32)>)>,
+    frames: Arc<Mutex<Vec<Vec<Option<FrameFeature>>>>>,
+    results: Arc<Mutex<Vec<Option<CalibrationResult>>>>,
+    status: Arc<Mutex<String>>,
+    suggestion: Arc<Mutex<Option<CaptureSuggestion>>>,
+}
+
+impl CalibrationApp {
+    fn new(ctx: &egui::Context) -> CalibrationApp {
+        CalibrationApp {
+            dataset_path: String::new(),
+            euroc_format: true,
+            board_config_path: String::new(),
+            tag_family: 3,
+            model: 1,
+            cam_num: 1,
+            one_focal: false,
+            job: Arc::new(Mutex::new(Job::Idle)),
+            observer: Arc::new(GuiObserver {
+                ctx: ctx.clone(),
+                latest: Mutex::new(None),
+                loaded: AtomicUsize::new(0),
+                detected: AtomicUsize::new(0),
+            }),
+            preview: None,
+            frames: Arc::new(Mutex::new(Vec::new())),
+            results: Arc::new(Mutex::new(Vec::new())),
+            status: Arc::new(Mutex::new("Select a dataset.".to_string())),
+            suggestion: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn start_detection(&self, ctx: &egui::Context) {
+        let board = if self.board_config_path.is_empty() {
+            Board::from_config(&BoardConfig::default())
+        } else {
+            Board::from_config(&board_config_from_json(&self.board_config_path))
+        };
+        let detector = TagDetector::new(
+            &TagFamily::from_str(TAG_FAMILIES[self.tag_family]).unwrap(),
+            None,
+        );
+        let (dataset_path, euroc_format, cam_num) =
+            (self.dataset_path.clone(), self.euroc_format, self.cam_num);
+        let (job, observer, frames, results, status, suggestion) = (
+            self.job.clone(),
+            self.observer.clone(),
+            self.frames.clone(),
+            self.results.clone(),
+            self.status.clone(),
+            self.suggestion.clone(),
+        );
+        let ctx = ctx.clone();
+        *job.lock().unwrap() = Job::Detecting;
+        self.observer.loaded.store(0, Ordering::Relaxed);
+        self.observer.detected.store(0, Ordering::Relaxed);
+        results.lock().unwrap().clear();
+        std::thread::spawn(move || {
+            let loaded = if euroc_format {
+                load_euroc(
+                    &dataset_path,
+                    &detector,
+                    &board,
+                    0,
+                    1,
+                    &FrameFilter::default(),
+                    cam_num,
+                    DEFAULT_QUEUE_SIZE,
+                    &*observer,
+                )
+            } else {
+                load_others(
+                    &dataset_path,
+                    &detector,
+                    &board,
+                    0,
+                    1,
+                    &FrameFilter::default(),
+                    cam_num,
+                    DEFAULT_QUEUE_SIZE,
+                    &*observer,
+                )
+            };
+            *status.lock().unwrap() = format!(
+                "Detected the board in {} of {} images.",
+                observer.detected.load(Ordering::Relaxed),
+                observer.loaded.load(Ordering::Relaxed)
+            );
+            *suggestion.lock().unwrap() = loaded.first().and_then(|cam0_frames| {
+                let img_w_h = cam0_frames.iter().flatten().next()?.img_w_h;
+                Some(suggest_next_capture(cam0_frames, img_w_h, &[]))
+            });
+            *frames.lock().unwrap() = loaded;
+            *job.lock().unwrap() = Job::Idle;
+            ctx.request_repaint();
+        });
+    }
+
+    fn start_calibration(&self, ctx: &egui::Context) {
+        let model = GenericModel::from_str(MODELS[self.model]).unwrap();
+        let one_focal = self.one_focal;
+        let (job, frames, results, status) = (
+            self.job.clone(),
+            self.frames.clone(),
+            self.results.clone(),
+            self.status.clone(),
+        );
+        let ctx = ctx.clone();
+        *job.lock().unwrap() = Job::Calibrating;
+        std::thread::spawn(move || {
+            let cams_frames = frames.lock().unwrap().clone();
+            let calib_params = CalibParams {
+                fixed_focal: None,
+                disabled_distortion_num: 0,
+                one_focal,
+                plateau: None,
+            };
+            let calibrated: Vec<_> = (0..cams_frames.len())
+                .map(|cam_idx| {
+                    let (model, rtvec_map) = std::panic::catch_unwind(|| {
+                        init_and_calibrate_one_camera_with_trials(
+                            cam_idx,
+                            &cams_frames,
+                            &model,
+                            &NoopObserver,
+                            &calib_params,
+                            3,
+                        )
+                    })
+                    .ok()
+                    .flatten()?;
+                    let report = validation(
+                        cam_idx,
+                        &model,
+                        &rtvec_map,
+                        &cams_frames[cam_idx],
+                        &NoopObserver,
+                    );
+                    Some(CalibrationResult {
+                        model,
+                        avg_reprojection_error: report.avg_99_percent,
+                        median_reprojection_error: report.median_error,
+                    })
+                })
+                .collect();
+            *status.lock().unwrap() = format!(
+                "Calibrated {} of {} cameras.",
+                calibrated.iter().flatten().count(),
+                calibrated.len()
+            );
+            *results.lock().unwrap() = calibrated;
+            *job.lock().unwrap() = Job::Idle;
+            ctx.request_repaint();
+        });
+    }
+
+    fn update_preview(&mut self, ctx: &egui::Context) {
+        if let Some((img, corners)) = self.observer.latest.lock().unwrap().take() {
+            let rgb = img.to_rgb8();
+            let color_image = egui::ColorImage::from_rgb(
+                [rgb.width() as usize, rgb.height() as usize],
+                rgb.as_raw(),
+            );
+            let texture = ctx.load_texture("preview", color_image, Default::default());
+            self.preview = Some((texture, corners));
+        }
+    }
+
+    fn settings_ui(&mut self, ui: &mut egui::Ui, idle: bool) {
+        egui::Grid::new("settings").num_columns(2).show(ui, |ui| {
+            ui.label("Dataset folder");
+            ui.text_edit_singleline(&mut self.dataset_path);
+            ui.end_row();
+            ui.label("Dataset format");
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.euroc_format, true, "euroc");
+                ui.radio_value(&mut self.euroc_format, false, "general");
+            });
+            ui.end_row();
+            ui.label("Board config (optional)");
+            ui.text_edit_singleline(&mut self.board_config_path);
+            ui.end_row();
+            ui.label("Tag family");
+            egui::ComboBox::from_id_source("tag_family")
+                .selected_text(TAG_FAMILIES[self.tag_family])
+                .show_index(ui, &mut self.tag_family, TAG_FAMILIES.len(), |i| {
+                    TAG_FAMILIES[i]
+                });
+            ui.end_row();
+            ui.label("Cameras");
+            ui.add(egui::DragValue::new(&mut self.cam_num).range(1..=8));
+            ui.end_row();
+            ui.label("Model");
+            egui::ComboBox::from_id_source("model")
+                .selected_text(MODELS[self.model])
+                .show_index(ui, &mut self.model, MODELS.len(), |i| MODELS[i]);
+            ui.end_row();
+            ui.label("One focal");
+            ui.checkbox(&mut self.one_focal, "");
+            ui.end_row();
+        });
+        ui.horizontal(|ui| {
+            let can_detect = idle && !self.dataset_path.is_empty();
+            if ui
+                .add_enabled(can_detect, egui::Button::new("Load and detect"))
+                .clicked()
+            {
+                self.start_detection(ui.ctx());
+            }
+            let can_calibrate = idle && !self.frames.lock().unwrap().is_empty();
+            if ui
+                .add_enabled(can_calibrate, egui::Button::new("Calibrate"))
+                .clicked()
+            {
+                self.start_calibration(ui.ctx());
+            }
+        });
+    }
+
+    fn results_ui(&self, ui: &mut egui::Ui) {
+        if let Some(suggestion) = self.suggestion.lock().unwrap().as_ref() {
+            ui.label(format!("Next capture: {}", suggestion.message));
+        }
+        for (cam_idx, result) in self.results.lock().unwrap().iter().enumerate() {
+            ui.separator();
+            ui.heading(format!("cam{}", cam_idx));
+            let Some(result) = result else {
+                ui.label("Calibration failed.");
+                continue;
+            };
+            ui.label(format!(
+                "Avg reprojection error of 99%: {:.4} px",
+                result.avg_reprojection_error
+            ));
+            ui.label(format!(
+                "Median reprojection error: {:.4} px",
+                result.median_reprojection_error
+            ));
+            let mut json = serde_json::to_string_pretty(&result.model).unwrap();
+            ui.add(egui::TextEdit::multiline(&mut json).code_editor());
+            if ui.button("Save").clicked() {
+                let path = format!("cam{}.json", cam_idx);
+                model_to_json(&path, &result.model);
+                *self.status.lock().unwrap() = format!("Saved {}", path);
+            }
+        }
+    }
+}
+
+impl eframe::App for CalibrationApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.update_preview(ctx);
+        let idle = matches!(*self.job.lock().unwrap(), Job::Idle);
+        egui::SidePanel::left("controls")
+            .min_width(320.0)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    self.settings_ui(ui, idle);
+                    ui.separator();
+                    match *self.job.lock().unwrap() {
+                        Job::Idle => ui.label(self.status.lock().unwrap().as_str()),
+                        Job::Detecting => ui.label(format!(
+                            "Detecting... {} / {} images with board",
+                            self.observer.detected.load(Ordering::Relaxed),
+                            self.observer.loaded.load(Ordering::Relaxed)
+                        )),
+                        Job::Calibrating => ui.label("Calibrating..."),
+                    };
+                    self.results_ui(ui);
+                });
+            });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let Some((texture, corners)) = &self.preview else {
+                ui.label("No image yet.");
+                return;
+            };
+            let response = ui.add(egui::Image::new(texture).shrink_to_fit());
+            let rect = response.rect;
+            let scale = rect.width() / texture.size()[0] as f32;
+            let painter = ui.painter_at(rect);
+            for (x, y) in corners {
+                let p = rect.min + egui::vec2(x + 0.5, y + 0.5) * scale;
+                painter.circle_filled(p, 2.0, egui::Color32::GREEN);
+            }
+            if let Some(suggestion) = self.suggestion.lock().unwrap().as_ref() {
+                let (x, y, w, h) = suggestion.region;
+                let min = rect.min + egui::vec2(x, y) * scale;
+                painter.rect_stroke(
+                    egui::Rect::from_min_size(min, egui::vec2(w, h) * scale),
+                    0.0,
+                    egui::Stroke::new(2.0, egui::Color32::YELLOW),
+                );
+                painter.text(
+                    min + egui::vec2(4.0, 4.0),
+                    egui::Align2::LEFT_TOP,
+                    &suggestion.message,
+                    egui::FontId::proportional(16.0),
+                    egui::Color32::YELLOW,
+                );
+            }
+        });
+    }
+}
+
+fn main() -> eframe::Result {
+    init_tracing(false);
+    eframe::run_native(
+        "ccrs",
+        eframe::NativeOptions::default(),
+        Box::new(|cc| Ok(Box::new(CalibrationApp::new(&cc.egui_ctx)))),
+    )
+}
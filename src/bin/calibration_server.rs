@@ -0,0 +1,72 @@
+use aprilgrid::detector::TagDetector;
+use aprilgrid::TagFamily;
+use camera_intrinsic_calibration::board::{board_config_from_json, Board, BoardConfig};
+use camera_intrinsic_calibration::logging::init_tracing;
+use camera_intrinsic_calibration::service::{CalibrationService, ServiceOptions};
+use clap::Parser;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(version, about, author)]
+struct CCRSServerCli {
+    /// address to listen on
+    #[arg(long, default_value = "0.0.0.0:8080")]
+    address: String,
+
+    /// tag_family: ["t16h5", "t25h7", "t25h9", "t36h11", "t36h11b1"]
+    #[arg(long, value_enum, default_value = "t36h11")]
+    tag_family: TagFamily,
+
+    /// board config used by `POST /detect`
+    #[arg(long)]
+    board_config: Option<String>,
+
+    /// write logs as json lines
+    #[arg(long, action)]
+    json_log: bool,
+
+    /// threads serving http requests
+    #[arg(long, default_value = "4")]
+    http_workers: usize,
+
+    /// threads running calibration jobs, at least one
+    #[arg(long, default_value = "2", value_parser = clap::value_parser!(u64).range(1..))]
+    job_workers: u64,
+
+    /// jobs waiting for a job worker, more are rejected with 503
+    #[arg(long, default_value = "16")]
+    max_queued_jobs: usize,
+
+    /// seconds finished jobs are kept for `GET /jobs/{id}`
+    #[arg(long, default_value = "3600")]
+    job_ttl_s: u64,
+
+    /// largest request body in MB, larger ones are rejected with 413
+    #[arg(long, default_value = "64")]
+    max_body_mb: usize,
+}
+
+fn main() {
+    let cli = CCRSServerCli::parse();
+    init_tracing(cli.json_log);
+    let detector = TagDetector::new(&cli.tag_family, None);
+    let board = if let Some(board_config_path) = cli.board_config {
+        Board::from_config(&board_config_from_json(&board_config_path))
+    } else {
+        Board::from_config(&BoardConfig::default())
+    };
+    let options = ServiceOptions {
+        http_workers: cli.http_workers,
+        job_workers: cli.job_workers as usize,
+        max_queued_jobs: cli.max_queued_jobs,
+        job_ttl: Duration::from_secs(cli.job_ttl_s),
+        max_body_bytes: cli.max_body_mb << 20,
+    };
+    let service = match CalibrationService::new(detector, board, options) {
+        Ok(service) => service,
+        Err(e) => panic!("Failed to start the server: {}", e),
+    };
+    if let Err(e) = service.serve(&cli.address) {
+        panic!("Failed to start the server: {}", e);
+    }
+}
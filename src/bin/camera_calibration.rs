@@ -1,102 +1,2870 @@
 use aprilgrid::detector::TagDetector;
 use aprilgrid::TagFamily;
+use camera_intrinsic_calibration::adjust::rescale;
+use camera_intrinsic_calibration::benchmark::{BenchmarkDataset, BenchmarkReport, BenchmarkResult};
+use camera_intrinsic_calibration::blur::CornerBlurDetector;
 use camera_intrinsic_calibration::board::Board;
 use camera_intrinsic_calibration::board::{
     board_config_from_json, board_config_to_json, BoardConfig,
 };
-use camera_intrinsic_calibration::data_loader::{load_euroc, load_others};
-use camera_intrinsic_calibration::detected_points::FrameFeature;
-use camera_intrinsic_calibration::io::{extrinsics_to_json, write_report};
-use camera_intrinsic_calibration::types::{CalibParams, Extrinsics, RvecTvec, ToRvecTvec};
+use camera_intrinsic_calibration::checkpoint::CameraCheckpoint;
+use camera_intrinsic_calibration::compare::compare_models;
+use camera_intrinsic_calibration::consistency::{round_trip_check, REGION_GRID};
+use camera_intrinsic_calibration::coverage::{
+    corner_coverage, coverage_heatmap, residual_heatmap, CoverageScore,
+};
+use camera_intrinsic_calibration::data_loader::{
+    euroc_image_paths, load_depth_images, load_euroc, load_events, load_exposure_csv, load_image,
+    load_imu, load_lidar_scans, load_others, load_robot_poses_csv, load_temperature_csv,
+    others_image_paths, path_to_timestamp, projector_capture_paths, DEFAULT_QUEUE_SIZE,
+};
+use camera_intrinsic_calibration::detected_points::{
+    filter_clipped_frames, image_to_option_feature_frame, FrameFeature, TagDetection, MIN_CORNERS,
+};
+use camera_intrinsic_calibration::dry_run::{predict_param_correlation, DatasetQuality};
+use camera_intrinsic_calibration::events::EventCountReconstruction;
+use camera_intrinsic_calibration::frame_filter::{parse_range, FrameFilter};
+#[cfg(feature = "gpu")]
+use camera_intrinsic_calibration::gpu::{GpuTagDetector, GpuUndistorter};
+use camera_intrinsic_calibration::hand_eye::{calibrate_hand_eye, calibrate_non_overlapping_rig};
+use camera_intrinsic_calibration::holdout::{split_holdout, HoldoutStats};
+use camera_intrinsic_calibration::imu::{
+    calibrate_camera_imu, refine_camera_imu, CamImuCalibration, ImuSample, SplineOptions,
+};
+use camera_intrinsic_calibration::init_diagnostic::InitDiagnostic;
+use camera_intrinsic_calibration::io::{
+    benchmark_datasets_from_json, benchmark_report_to_json, calibration_reports_to_json,
+    camchain_imucam_to_yaml, camchain_to_yaml, camera_checkpoint_from_json,
+    camera_checkpoint_to_json, camera_lidar_to_json, coverage_to_json, dataset_quality_to_json,
+    depth_color_alignment_to_json, detections_from_json, detections_to_json, extrinsics_from_json,
+    extrinsics_to_json, flat_port_to_json, frame_influences_to_json, hand_eye_to_json,
+    holdout_to_json, init_diagnostic_to_json, inverse_response_to_json, inverse_response_to_pcalib,
+    known_distance_errors_to_json, known_distances_from_json, line_scan_to_json,
+    monte_carlo_to_json, multi_plane_config_from_json, multi_plane_config_to_json,
+    observability_to_json, outlier_frames_to_json, param_correlations_to_json,
+    pipeline_profile_to_json, rolling_shutter_to_json, scale_check_to_json,
+    session_residuals_to_json, stereo_depth_to_json, stereo_rectification_to_opencv_yaml,
+    surveyed_boards_from_json, telecentric_to_json, thermal_drift_to_json, time_offsets_to_json,
+    trigger_delays_to_json, validation_to_json, vehicle_alignment_to_json,
+    write_corner_residuals_csv, write_error_histogram_csv, write_report, write_residual_grid_csv,
+    write_residual_vs_radius_csv, zoom_calibration_to_json, zoom_datasets_from_json,
+};
+use camera_intrinsic_calibration::jackknife::{leave_one_out, FrameInfluence, MAX_FOCAL_SHIFT};
+use camera_intrinsic_calibration::lidar::{calibrate_camera_lidar, LidarCalibParams};
+use camera_intrinsic_calibration::line_scan::calib_line_scan;
+use camera_intrinsic_calibration::logging::init_tracing;
+use camera_intrinsic_calibration::lut::UnprojectionLut;
+use camera_intrinsic_calibration::monte_carlo::{monte_carlo_spread, ParamSpread, Perturbation};
+use camera_intrinsic_calibration::multi_plane::{calib_multi_plane, MultiPlaneTarget};
+use camera_intrinsic_calibration::observability::{
+    Observability, ParamCorrelation, MAX_CORRELATION,
+};
+use camera_intrinsic_calibration::observer::{NoopObserver, PipelineObserver};
+use camera_intrinsic_calibration::optimization::Plateau;
+use camera_intrinsic_calibration::outliers::{
+    calib_camera_without_outlier_frames, rank_outlier_frames, OutlierFrame,
+};
+use camera_intrinsic_calibration::overlay::draw_detection_overlay;
+use camera_intrinsic_calibration::profile::profile_pipeline;
+use camera_intrinsic_calibration::projector::{
+    calib_projector, gray_code_patterns, projector_frame, ProjectorCodes,
+};
+use camera_intrinsic_calibration::refraction::{calib_flat_port, FlatPortParams};
+use camera_intrinsic_calibration::remap::{Border, Interpolation};
+use camera_intrinsic_calibration::response::calib_inverse_response;
+use camera_intrinsic_calibration::rgbd::{depth_color_alignment, DepthColorParams, DepthImage};
+use camera_intrinsic_calibration::rolling_shutter::calib_rolling_shutter;
+use camera_intrinsic_calibration::scale::{
+    board_scale_check, known_distance_errors, BaselineScaleCheck, BoardScaleCheck, KnownDistance,
+    KnownDistanceError, ScaleCheck, MAX_SCALE_ERROR,
+};
+use camera_intrinsic_calibration::session::{merge_sessions, session_residuals, SessionResidual};
+use camera_intrinsic_calibration::stereo::{
+    epipolar_errors, stereo_depth_errors, EpipolarStats, StereoRectification,
+};
+use camera_intrinsic_calibration::straightness::{straightness_errors, StraightnessStats};
+use camera_intrinsic_calibration::subsample::{
+    select_frames_by_information_gain, select_informative_frames,
+};
+use camera_intrinsic_calibration::telecentric::calib_telecentric;
+use camera_intrinsic_calibration::thermal::calib_thermal_drift;
+use camera_intrinsic_calibration::thermal_detection::{Polarity, ThermalTagDetector};
+use camera_intrinsic_calibration::time_offset::{
+    align_frames, estimate_time_offset, estimate_trigger_delay,
+};
+use camera_intrinsic_calibration::types::{
+    CalibParams, CalibrationReport, CornerResidual, Extrinsics, RvecTvec, ToRvecTvec,
+};
+#[cfg(feature = "gpu")]
+use camera_intrinsic_calibration::undistort::undistort_folder_with;
+use camera_intrinsic_calibration::undistort::{
+    optimal_new_camera_matrix, undistort_folder, undistort_video, TargetProjection, Undistorter,
+};
 use camera_intrinsic_calibration::util::*;
+use camera_intrinsic_calibration::validate::ValidationStats;
+use camera_intrinsic_calibration::vehicle::{align_to_vehicle, SurveyedBoard};
 use camera_intrinsic_calibration::visualization::*;
+use camera_intrinsic_calibration::watch::{frame_time_ns, DetectionCache, WatchCalibration};
+use camera_intrinsic_calibration::zoom::{ZoomCalibration, ZoomPosition};
 use camera_intrinsic_model::*;
-use clap::{Parser, ValueEnum};
-use log::trace;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use image::{DynamicImage, ImageReader};
+use rayon::prelude::*;
 use std::collections::HashMap;
+use std::ops::{Range, RangeInclusive};
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 use time::OffsetDateTime;
+use tracing::{info, trace, warn};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum DatasetFormat {
+    Euroc,
+    General,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum PerturbationArg {
+    Noise,
+    Resample,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum FrameSelectionArg {
+    Coverage,
+    Information,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Projection {
+    Pinhole,
+    Equirectangular,
+    Cylindrical,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum InterpolationArg {
+    Nearest,
+    Bilinear,
+    Bicubic,
+    Lanczos3,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum PolarityArg {
+    Auto,
+    Normal,
+    Inverted,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum BorderArg {
+    Constant,
+    Replicate,
+    Reflect,
+}
+
+/// Frame filters shared by the calibration and the detect and validate commands, by the index of
+/// the frame in the sequence, the one of `detections.json` and of the reports, or by its time
+/// stamp, e.g. to leave out a bad segment of a capture.
+#[derive(Args)]
+struct FrameFilterArgs {
+    /// frames left out, e.g. `3,10-20`
+    #[arg(long, value_delimiter = ',', value_parser = parse_range::<usize>)]
+    skip_frames: Vec<RangeInclusive<usize>>,
+
+    /// only these frames are used, e.g. `0-99`
+    #[arg(long, value_delimiter = ',', value_parser = parse_range::<usize>)]
+    use_frames: Vec<RangeInclusive<usize>>,
+
+    /// frames with time stamps in ns in these ranges are left out
+    #[arg(long, value_delimiter = ',', value_parser = parse_range::<i64>)]
+    skip_times: Vec<RangeInclusive<i64>>,
+
+    /// only frames with time stamps in ns in these ranges are used
+    #[arg(long, value_delimiter = ',', value_parser = parse_range::<i64>)]
+    use_times: Vec<RangeInclusive<i64>>,
+}
+
+impl FrameFilterArgs {
+    /// The filter of the frames to detect, at most `max_images` of the kept ones.
+    fn filter(&self, max_images: usize) -> FrameFilter {
+        FrameFilter {
+            skip_frames: self.skip_frames.clone(),
+            use_frames: self.use_frames.clone(),
+            skip_times_ns: self.skip_times.clone(),
+            use_times_ns: self.use_times.clone(),
+            max_frames: Some(max_images),
+        }
+    }
+
+    /// Filters detections that are loaded rather than detected.
+    fn apply(&self, cams_frames: &mut [Vec<Option<FrameFeature>>], max_images: usize) {
+        let removed = self.filter(max_images).apply(cams_frames);
+        if removed > 0 {
+            info!("{} frames with detections filtered out", removed);
+        }
+    }
+}
+
+/// Early stop of the optimizations on a plateau of the error, off by default. The last
+/// iterations of big problems take long and barely change the result.
+#[derive(Args)]
+struct PlateauArgs {
+    /// stop when the error decreased less than `--plateau-min-decrease` over this many
+    /// iterations together
+    #[arg(long)]
+    plateau_window: Option<usize>,
+
+    /// relative decrease of the error over the plateau window to keep optimizing
+    #[arg(long, default_value_t = 1e-4, requires = "plateau_window")]
+    plateau_min_decrease: f64,
+}
+
+impl PlateauArgs {
+    fn plateau(&self) -> Option<Plateau> {
+        self.plateau_window.map(|window| Plateau {
+            window,
+            min_rel_decrease: self.plateau_min_decrease,
+        })
+    }
+}
+
+/// Remap options shared by the undistort and rectify commands.
+#[derive(Args)]
+struct RemapArgs {
+    #[arg(long, value_enum, default_value = "bilinear")]
+    interpolation: InterpolationArg,
+
+    /// how pixels outside of the source image are sampled
+    #[arg(long, value_enum, default_value = "constant")]
+    border: BorderArg,
+
+    /// value of the constant border
+    #[arg(long, default_value_t = 0.0)]
+    border_value: f64,
+
+    /// remap 8 bit images on the gpu, falls back to the cpu without an adapter
+    #[cfg(feature = "gpu")]
+    #[arg(long)]
+    gpu: bool,
+}
+
+impl RemapArgs {
+    fn apply(&self, undistorter: Undistorter) -> Undistorter {
+        let interpolation = match self.interpolation {
+            InterpolationArg::Nearest => Interpolation::Nearest,
+            InterpolationArg::Bilinear => Interpolation::Bilinear,
+            InterpolationArg::Bicubic => Interpolation::Bicubic,
+            InterpolationArg::Lanczos3 => Interpolation::Lanczos3,
+        };
+        let border = match self.border {
+            BorderArg::Constant => Border::Constant(self.border_value),
+            BorderArg::Replicate => Border::Replicate,
+            BorderArg::Reflect => Border::Reflect,
+        };
+        undistorter.with_interpolation(interpolation, border)
+    }
+
+    fn undistort_folder(&self, undistorter: &Undistorter, input: &str, output: &str) -> usize {
+        #[cfg(feature = "gpu")]
+        if self.gpu {
+            if let Some(gpu) = GpuUndistorter::new(undistorter) {
+                return undistort_folder_with(|img| gpu.undistort(img), input, output);
+            }
+            warn!("gpu remap is not available, use the cpu");
+        }
+        undistort_folder(undistorter, input, output)
+    }
+}
+
+#[derive(Parser)]
+#[command(
+    version,
+    about,
+    author,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct CCRSCli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// path to image folder
+    #[arg(required_unless_present_any = ["features", "resume"])]
+    path: Option<String>,
+
+    /// calibrate from the corners of a features file instead of detecting the board in images,
+    /// e.g. the `detections.json` of `ccrs detect` or of another detector. It's a list per camera
+    /// of its frames, `null` or `{"time_ns": t, "img_w_h": [w, h], "features": {"id": {"p2d":
+    /// [x, y], "p3d": [x, y, z]}}}`, with the board points in meters
+    #[arg(long, conflicts_with = "event_window_ms")]
+    features: Option<String>,
+
+    /// tag_family: ["t16h5", "t25h7", "t25h9", "t36h11", "t36h11b1"]
+    #[arg(long, value_enum, default_value = "t36h11")]
+    tag_family: TagFamily,
+
+    /// calibrate event cameras from the `cam{i}/events.txt` of the dataset, `width height` and
+    /// `timestamp [s] x y polarity` lines, accumulated into frames of this many ms. The board
+    /// should blink, e.g. shown on a flashing monitor, a few times in every frame
+    #[arg(long, value_parser = parse_event_window_ms)]
+    event_window_ms: Option<f64>,
+
+    /// detect the board of thermal or IR cameras with low contrast, an uneven temperature or a
+    /// low resolution, `inverted` when the tags are brighter than the squares around them
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "auto")]
+    thermal_detection: Option<PolarityArg>,
+
+    /// estimate the motion blur of every detected corner and down-weight its residual along the
+    /// blur, for handheld captures
+    #[arg(long)]
+    corner_blur: bool,
+
+    /// model: ["ucm", "eucm", "kb4", "opencv5", "eucmt", "ftheta"]
+    #[arg(short, long, value_enum, default_value = "eucm")]
+    model: GenericModel<f64>,
+
+    #[arg(long, default_value_t = 0)]
+    start_idx: usize,
+
+    #[arg(long, default_value_t = 1)]
+    step: usize,
+
+    #[arg(long, default_value_t = 600)]
+    max_images: usize,
+
+    #[arg(long, default_value_t = 1)]
+    cam_num: usize,
+
+    #[arg(long)]
+    board_config: Option<String>,
+
+    /// json of a rigid target of several boards at known poses in the frame of the first one,
+    /// `{"planes": [{"board", "rvec", "tvec", "refine"}]}`, used instead of the board config, the
+    /// poses of the planes with `refine` are calibrated and written to `cam{i}_multi_plane.json`
+    #[arg(long, conflicts_with = "board_config")]
+    multi_plane_config: Option<String>,
+
+    #[arg(short, long)]
+    output_folder: Option<String>,
+
+    /// resume an interrupted run, or run its later stages again with other options, in its
+    /// output folder. Its `detections.json` is used instead of detecting the board, and the
+    /// cameras whose `checkpoints/cam{i}_calib.json` have the same model, params and frames
+    /// aren't calibrated again
+    #[arg(long, conflicts_with_all = ["output_folder", "features"])]
+    resume: Option<String>,
+
+    #[arg(long, value_enum, default_value = "euroc")]
+    dataset_format: DatasetFormat,
+
+    #[arg(long, action)]
+    one_focal: bool,
+
+    #[arg(long, default_value_t = 0)]
+    disabled_distortion_num: usize,
+
+    #[arg(long)]
+    fixed_focal: Option<f64>,
+
+    /// stop before the calibration and write the detection rate, the pose diversity, the blur
+    /// and the observability of the params predicted from the initialization of every camera to
+    /// `dataset_quality.json`, to check a dataset before spending minutes on it
+    #[arg(long, action)]
+    dry_run: bool,
+
+    /// stop after the initialization and write the frame pair, the homography, its lambda, the
+    /// focal candidates and the initial UCM of every attempt to `cam{i}_init.json`, to debug a
+    /// dataset that doesn't initialize
+    #[arg(long, action)]
+    init_only: bool,
+
+    /// warn about frames with more clipped (0 or 255) pixels around the board than this ratio
+    #[arg(long, default_value_t = 0.5)]
+    max_clipped_ratio: f32,
+
+    /// exclude the clipped frames instead of only warning
+    #[arg(long, action)]
+    exclude_clipped_frames: bool,
+
+    /// imu csv in the euroc format for camera-imu calibration, e.g. `mav0/imu0/data.csv`, or a
+    /// ROS1 bag `.bag` with `sensor_msgs/Imu` messages
+    #[arg(long)]
+    imu: Option<String>,
+
+    /// imu topic of the `--imu` rosbag, the only `sensor_msgs/Imu` topic by default
+    #[arg(long)]
+    imu_topic: Option<String>,
+
+    /// time between the control points of the imu trajectory spline in ms
+    #[arg(long, default_value_t = 50.0)]
+    imu_knot_ms: f64,
+
+    /// standard deviation of a gyro sample in rad/s
+    #[arg(long, default_value_t = 0.01)]
+    imu_gyro_noise: f64,
+
+    /// standard deviation of an accelerometer sample in m/s^2
+    #[arg(long, default_value_t = 0.1)]
+    imu_accel_noise: f64,
+
+    /// max camera-imu time offset to search in ms
+    #[arg(long, default_value_t = 100.0)]
+    imu_max_time_offset_ms: f64,
+
+    /// estimate the time offsets of the cameras to cam0 from the board pose trajectories and
+    /// associate the frames by the corrected time, for cameras without hardware sync
+    #[arg(long, action)]
+    estimate_time_offsets: bool,
+
+    /// max camera time offset to search in ms
+    #[arg(long, default_value_t = 100.0)]
+    camera_max_time_offset_ms: f64,
+
+    /// warn if the trigger delay or jitter of a camera to cam0 is larger than this in ms
+    #[arg(long, default_value_t = 0.5)]
+    max_trigger_delay_ms: f64,
+
+    /// robot flange poses csv `timestamp [ns],tx,ty,tz,qx,qy,qz,qw` of the images for hand-eye
+    /// calibration of cameras mounted on the flange
+    #[arg(long)]
+    robot_poses: Option<String>,
+
+    /// folder of lidar scans of the board `{time_stamp}.pcd` or `{time_stamp}.ply` for
+    /// camera-lidar calibration
+    #[arg(long)]
+    lidar: Option<String>,
+
+    /// max distance of the lidar board points to the board plane in m
+    #[arg(long, default_value_t = 0.02)]
+    lidar_plane_threshold: f64,
+
+    /// size of the board outside of the corners in m
+    #[arg(long, default_value_t = 0.05)]
+    lidar_board_margin: f64,
+
+    /// folder of 16 bit depth images `{time_stamp}.png` registered to `--depth-cam` to validate
+    /// the depth to color alignment of an RGB-D device calibrated as a rig
+    #[arg(long)]
+    depth: Option<String>,
+
+    /// camera the depth images are registered to, usually the IR camera
+    #[arg(long, default_value_t = 1)]
+    depth_cam: usize,
+
+    /// color camera of the RGB-D device
+    #[arg(long, default_value_t = 0)]
+    color_cam: usize,
+
+    /// depth of a unit of the depth images in m
+    #[arg(long, default_value_t = 0.001)]
+    depth_scale: f64,
+
+    /// extrinsics of cameras without a shared field of view from the motion of the rig, every
+    /// camera sees its own static board
+    #[arg(long, action)]
+    non_overlapping: bool,
+
+    /// `detections.json` of other sessions of the same rig to calibrate together, every session
+    /// keeps its own board poses
+    #[arg(long, num_args = 1..)]
+    sessions: Vec<String>,
+
+    /// json list of `{"time_ns", "t_vehicle_board": {"rvec", "tvec"}}` of boards at surveyed
+    /// poses in the images of cameras fixed on a vehicle, to align the cameras to the vehicle
+    #[arg(long)]
+    surveyed_boards: Option<String>,
+
+    /// json list of `{"corner0", "corner1", "distance_m"}` of board corners with independently
+    /// measured distances, reconstructed with the calibration in the held out frames if any,
+    /// else in all frames, and written to `known_distances.json`
+    #[arg(long)]
+    known_distances: Option<String>,
+
+    /// baseline between cam0 and cam1 measured on the rig in m, to check the printed board
+    /// size against the board config
+    #[arg(long)]
+    expected_baseline: Option<f64>,
+
+    /// refine each camera with a rolling shutter model and estimate the line delay, needs
+    /// consecutive frames of a moving board
+    #[arg(long, action)]
+    rolling_shutter: bool,
+
+    /// known line delay in ns of rolling shutter cameras, e.g. from the sensor datasheet, every
+    /// corner gets its capture time from its row and `--rolling-shutter` only estimates the
+    /// motion of the board
+    #[arg(long)]
+    line_delay_ns: Option<f64>,
+
+    /// temperature csv `timestamp [ns], temperature [°C]` to fit the drift of the focal and the
+    /// principal point with the temperature, written to `cam{i}_thermal.json`
+    #[arg(long)]
+    temperatures: Option<String>,
+
+    /// degree of the polynomial of the thermal drift, 1 for linear or 2 for quadratic
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2))]
+    thermal_degree: u8,
+
+    /// output folder of the calibration of the cameras in the air, to calibrate the flat port
+    /// of their underwater housing from the captures in the water, written to
+    /// `cam{i}_flat_port.json`
+    #[arg(long)]
+    flat_port: Option<String>,
+
+    /// refractive index of the water, 1.34 for sea water and 1.33 for fresh water
+    #[arg(long, default_value_t = 1.34)]
+    water_index: f64,
+
+    /// refractive index of the port, 1.49 for acrylic, 1.52 for glass and 1.77 for sapphire
+    #[arg(long, default_value_t = 1.49)]
+    port_index: f64,
+
+    /// thickness of the port in m
+    #[arg(long, default_value_t = 0.01)]
+    port_thickness: f64,
+
+    /// write images with the detected corners and reprojections drawn to `overlays/`
+    #[arg(long, action)]
+    export_overlays: bool,
+
+    /// leave this fraction of the frames out of the calibration and report their reprojection
+    /// errors with the board poses solved by PnP in `holdout.json`
+    #[arg(long)]
+    holdout_fraction: Option<f64>,
+
+    /// calibrate with only this many of the frames, the ones adding the most image coverage and
+    /// board pose diversity, to bound the solve time of long recordings
+    #[arg(long)]
+    max_frames: Option<usize>,
+
+    /// how the `--max-frames` are picked, `coverage` by the image coverage and the board pose
+    /// diversity, `information` by the decrease of the predicted covariance of the intrinsics
+    /// calibrated with a few of the frames first
+    #[arg(long, value_enum, default_value = "coverage")]
+    frame_selection: FrameSelectionArg,
+
+    /// re-run the calibration of every camera this many times with perturbed observations and
+    /// write the spreads of the params to `monte_carlo.json`
+    #[arg(long)]
+    monte_carlo_runs: Option<usize>,
+
+    /// calibrate every camera again without each of its frames and write how much every frame
+    /// moves the params to `leave_one_out.json`, one calibration per frame
+    #[arg(long, action)]
+    leave_one_out: bool,
+
+    /// unproject the corners of the straightness and epipolar checks with a table of the rays
+    /// of every pixel, faster for models without closed-form unprojection
+    #[arg(long)]
+    unproject_lut: bool,
+
+    /// `noise` adds the estimated corner noise to the reprojected corners, `resample` draws
+    /// the frames with replacement
+    #[arg(long, value_enum, default_value = "noise")]
+    monte_carlo_mode: PerturbationArg,
+
+    /// cell size in px of the mean reprojection errors over the image in
+    /// `cam{i}_residual_grid.csv` and `cam{i}_residual_grid.png`
+    #[arg(long, default_value_t = 32)]
+    residual_grid_cell_size: u32,
+
+    /// calibrate every camera again without the frames with much larger reprojection errors
+    /// than the others, the outlier frames of the final calibration are listed in
+    /// `outlier_frames.json` either way
+    #[arg(long, action)]
+    drop_outlier_frames: bool,
+
+    /// write the reprojection error of every corner to `cam{i}_residuals.csv`
+    #[arg(long, action)]
+    export_residuals: bool,
+
+    /// number of threads of the board detection and the solvers, all cores by default
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// images read ahead of the board detection, bounds the images in memory of long recordings
+    #[arg(long, default_value_t = DEFAULT_QUEUE_SIZE)]
+    queue_size: usize,
+
+    /// filter the images for the board detection on the gpu, falls back to the cpu without an
+    /// adapter
+    #[cfg(feature = "gpu")]
+    #[arg(long)]
+    gpu_detection: bool,
+
+    /// write logs as json lines
+    #[arg(long, action)]
+    json_log: bool,
+
+    #[command(flatten)]
+    frame_filter: FrameFilterArgs,
+
+    #[command(flatten)]
+    plateau: PlateauArgs,
+}
+
+/// Event windows of at least 1 ns, the frames are timed in ns.
+fn parse_event_window_ms(s: &str) -> Result<f64, String> {
+    let window_ms: f64 = s.parse().map_err(|_| format!("invalid window '{}'", s))?;
+    if (window_ms * 1e6) as i64 > 0 {
+        Ok(window_ms)
+    } else {
+        Err(format!("the event window {} ms is shorter than 1 ns", s))
+    }
+}
+
+impl CCRSCli {
+    /// Only missing when running a subcommand or calibrating from a features file.
+    fn dataset_root(&self) -> &str {
+        self.path.as_deref().unwrap()
+    }
+
+    fn tag_detector(&self) -> Box<dyn TagDetection> {
+        let detector = self.board_detector();
+        if self.corner_blur {
+            Box::new(CornerBlurDetector::new(detector))
+        } else {
+            detector
+        }
+    }
+
+    fn board_detector(&self) -> Box<dyn TagDetection> {
+        #[cfg(feature = "gpu")]
+        if self.gpu_detection {
+            if let Some(detector) = GpuTagDetector::new(&self.tag_family) {
+                return self.with_thermal_detection(detector);
+            }
+            warn!("gpu detection is not available, use the cpu");
+        }
+        self.with_thermal_detection(TagDetector::new(&self.tag_family, None))
+    }
+
+    fn with_thermal_detection<D: TagDetection + 'static>(
+        &self,
+        detector: D,
+    ) -> Box<dyn TagDetection> {
+        let Some(polarity) = self.thermal_detection else {
+            return Box::new(detector);
+        };
+        let polarity = match polarity {
+            PolarityArg::Auto => Polarity::Auto,
+            PolarityArg::Normal => Polarity::Normal,
+            PolarityArg::Inverted => Polarity::Inverted,
+        };
+        Box::new(ThermalTagDetector::new(detector, polarity))
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Undistort the images in a folder to a pinhole camera
+    Undistort(UndistortArgs),
+    /// Compute the stereo rectification of two cameras calibrated with extrinsics
+    Rectify(RectifyArgs),
+    /// Rescale a calibration to another image resolution
+    Rescale(RescaleArgs),
+    /// Check that unproject and project of a calibrated model are consistent
+    Check(CheckArgs),
+    /// Compare two calibrations of the same camera
+    Compare(CompareArgs),
+    /// Calibrate datasets with published calibrations and report pass or fail
+    BenchDataset(BenchDatasetArgs),
+    /// Time every stage of the calibration of a camera and report the peak memory
+    Profile(ProfileArgs),
+    /// Calibrate a zoom lens at several positions into one interpolated table
+    Zoom(ZoomArgs),
+    /// Estimate the inverse response of a camera from a static scene at several exposure times
+    Response(ResponseArgs),
+    /// Calibrate a line-scan camera from images of board sweeps with a constant motion
+    LineScan(LineScanArgs),
+    /// Calibrate a telecentric lens with an orthographic model
+    Telecentric(TelecentricArgs),
+    /// Calibrate a projector and a camera from gray code patterns projected on the board
+    Projector(ProjectorArgs),
+    /// Write the gray code patterns of a projector for the projector calibration
+    ProjectorPatterns(ProjectorPatternsArgs),
+    /// Only detect the board and write the features to calibrate on another machine
+    Detect(DetectArgs),
+    /// Check an existing calibration with new images of the board, only the board poses are solved
+    Validate(ValidateArgs),
+    /// Watch a folder the images are written to and calibrate again as new frames arrive
+    Watch(WatchArgs),
+}
+
+#[derive(Args)]
+struct UndistortArgs {
+    /// camera model json
+    model: String,
+
+    /// input image folder or video
+    input: String,
+
+    /// output image folder or video, the pinhole camera is saved as `pinhole.json` or next to the video
+    output: String,
+
+    #[arg(long, value_enum, default_value = "pinhole")]
+    projection: Projection,
+
+    /// 0.0 crops the invalid pixels, 1.0 keeps the whole field of view
+    #[arg(long, default_value_t = 0.0)]
+    balance: f64,
+
+    /// horizontal field of view of a centered pinhole camera or a panorama in degrees
+    #[arg(long, conflicts_with_all = ["balance", "zoom"])]
+    fov: Option<f64>,
+
+    /// vertical field of view of an equirectangular panorama in degrees, defaults to keep the aspect ratio
+    #[arg(long)]
+    vfov: Option<f64>,
+
+    /// focal of a centered pinhole camera relative to the calibrated focal
+    #[arg(long, conflicts_with = "balance")]
+    zoom: Option<f64>,
+
+    /// like OpenCV getOptimalNewCameraMatrix, 0.0 keeps only valid pixels, 1.0 keeps all source pixels
+    #[arg(long, conflicts_with_all = ["balance", "fov", "zoom"])]
+    alpha: Option<f64>,
+
+    /// output width, defaults to the calibrated width
+    #[arg(long)]
+    width: Option<u32>,
+
+    /// output height, defaults to the calibrated height
+    #[arg(long)]
+    height: Option<u32>,
+
+    /// drop the output pixels that the table of the rays of every source pixel doesn't map back,
+    /// e.g. beyond the domain of a fisheye model
+    #[arg(long)]
+    unproject_lut: bool,
+
+    #[command(flatten)]
+    remap: RemapArgs,
+}
+
+#[derive(Args)]
+struct RectifyArgs {
+    /// calibration output folder with cam{i}.json and extrinsics.json
+    calib_folder: String,
+
+    /// output folder
+    output: String,
+
+    #[arg(long, default_value_t = 0)]
+    cam0: usize,
+
+    #[arg(long, default_value_t = 1)]
+    cam1: usize,
+
+    /// rectified width, defaults to the calibrated width
+    #[arg(long)]
+    width: Option<u32>,
+
+    /// rectified height, defaults to the calibrated height
+    #[arg(long)]
+    height: Option<u32>,
+
+    /// images of cam0 to rectify
+    #[arg(long)]
+    input0: Option<String>,
+
+    /// images of cam1 to rectify
+    #[arg(long)]
+    input1: Option<String>,
+
+    #[command(flatten)]
+    remap: RemapArgs,
+}
+
+#[derive(Args)]
+struct RescaleArgs {
+    /// camera model json
+    model: String,
+
+    /// output camera model json
+    output: String,
+
+    #[arg(long)]
+    width: u32,
+
+    #[arg(long)]
+    height: u32,
+}
+
+fn camera_straightness(
+    cam_idx: usize,
+    model: &GenericModel<f64>,
+    lut: Option<&UnprojectionLut>,
+    frames: &[Option<FrameFeature>],
+) -> StraightnessStats {
+    let stats = StraightnessStats::new(&straightness_errors(model, lut, frames));
+    info!(
+        "cam{} median straightness error: {:.5} px of {} lines",
+        cam_idx, stats.median_error, stats.num_lines
+    );
+    stats
+}
+
+fn camera_holdout(
+    cam_idx: usize,
+    model: &GenericModel<f64>,
+    rtvec_map: &HashMap<usize, RvecTvec>,
+    frames: &[Option<FrameFeature>],
+    holdout_frames: &[Vec<Option<FrameFeature>>],
+) -> Option<HoldoutStats> {
+    let stats = HoldoutStats::new(
+        cam_idx,
+        model,
+        rtvec_map,
+        frames,
+        holdout_frames.get(cam_idx)?,
+    );
+    info!(
+        "cam{} reprojection rms {:.5} px of {} frames, held out {:.5} px of {} frames",
+        cam_idx,
+        stats.train_rms,
+        stats.num_train_frames,
+        stats.holdout_rms,
+        stats.num_holdout_frames
+    );
+    Some(stats)
+}
+
+fn camera_correlation(
+    cli: &CCRSCli,
+    cam_idx: usize,
+    model: &GenericModel<f64>,
+    rtvec_map: &HashMap<usize, RvecTvec>,
+    frames: &[Option<FrameFeature>],
+) -> Option<ParamCorrelation> {
+    let xy_same_focal = cli.one_focal || cli.fixed_focal.is_some();
+    let covariance = intrinsics_covariance(model, rtvec_map, frames, xy_same_focal)?;
+    let correlation = ParamCorrelation::new(cam_idx, model, &covariance, xy_same_focal);
+    for (name0, name1, r) in correlation.correlated_pairs(MAX_CORRELATION) {
+        info!(
+            "cam{} {} and {} are {:.1}% correlated",
+            cam_idx,
+            name0,
+            name1,
+            r * 100.0
+        );
+    }
+    Some(correlation)
+}
+
+fn camera_observability(correlation: &ParamCorrelation) -> Observability {
+    let observability = Observability::new(correlation);
+    info!(
+        "cam{} intrinsics condition number {:.1}",
+        observability.cam_idx, observability.condition_number
+    );
+    for direction in &observability.weak_directions {
+        warn!("cam{} {}", observability.cam_idx, direction.message());
+    }
+    observability
+}
+
+fn camera_outlier_frames(
+    cam_idx: usize,
+    model: &GenericModel<f64>,
+    rtvec_map: &HashMap<usize, RvecTvec>,
+    frames: &[Option<FrameFeature>],
+    in_rig: bool,
+) -> Vec<OutlierFrame> {
+    let outliers = rank_outlier_frames(cam_idx, model, rtvec_map, frames, in_rig);
+    // the worst offenders, all of them are in the json
+    for o in outliers.iter().take(5) {
+        warn!(
+            "cam{} frame {} at {} mean error {:.3} px, max {:.3} px of {} corners, likely {:?}",
+            cam_idx, o.frame_idx, o.time_ns, o.mean_error, o.max_error, o.num_points, o.causes
+        );
+    }
+    outliers
+}
+
+fn camera_leave_one_out(
+    cli: &CCRSCli,
+    cam_idx: usize,
+    model: &GenericModel<f64>,
+    rtvec_map: &HashMap<usize, RvecTvec>,
+    frames: &[Option<FrameFeature>],
+) -> Vec<FrameInfluence> {
+    if !cli.leave_one_out {
+        return Vec::new();
+    }
+    let calib_params = CalibParams {
+        fixed_focal: cli.fixed_focal.filter(|_| cam_idx == 0),
+        disabled_distortion_num: cli.disabled_distortion_num,
+        one_focal: cli.one_focal,
+        plateau: cli.plateau.plateau(),
+    };
+    let influences = leave_one_out(cam_idx, model, rtvec_map, frames, &calib_params);
+    for influence in influences
+        .iter()
+        .filter(|f| f.focal_shift > MAX_FOCAL_SHIFT)
+    {
+        warn!(
+            "cam{} frame {} at {} moves the focal by {:.2} px on its own",
+            cam_idx, influence.frame_idx, influence.time_ns, influence.focal_shift
+        );
+    }
+    influences
+}
+
+fn camera_monte_carlo(
+    cli: &CCRSCli,
+    cam_idx: usize,
+    model: &GenericModel<f64>,
+    rtvec_map: &HashMap<usize, RvecTvec>,
+    frames: &[Option<FrameFeature>],
+) -> Option<ParamSpread> {
+    let num_runs = cli.monte_carlo_runs?;
+    let calib_params = CalibParams {
+        fixed_focal: cli.fixed_focal.filter(|_| cam_idx == 0),
+        disabled_distortion_num: cli.disabled_distortion_num,
+        one_focal: cli.one_focal,
+        plateau: cli.plateau.plateau(),
+    };
+    let perturbation = match cli.monte_carlo_mode {
+        PerturbationArg::Noise => Perturbation::Noise,
+        PerturbationArg::Resample => Perturbation::Resample,
+    };
+    let spread = monte_carlo_spread(
+        cam_idx,
+        model,
+        rtvec_map,
+        frames,
+        &calib_params,
+        perturbation,
+        num_runs,
+        cam_idx as u64 * num_runs as u64,
+    )?;
+    for (i, std_dev) in spread.std_dev.iter().enumerate() {
+        let linearized = spread
+            .linearized_std_dev
+            .as_ref()
+            .and_then(|s| s.get(i))
+            .map(|s| format!("{:.6}", s))
+            .unwrap_or_else(|| "-".to_string());
+        info!(
+            "cam{} param {} Monte Carlo std {:.6}, linearized std {}",
+            cam_idx, i, std_dev, linearized
+        );
+    }
+    Some(spread)
+}
+
+fn camera_known_distances(
+    known_distances: &[KnownDistance],
+    cam_idx: usize,
+    model: &GenericModel<f64>,
+    rtvec_map: &HashMap<usize, RvecTvec>,
+    frames: &[Option<FrameFeature>],
+    holdout_frames: &[Vec<Option<FrameFeature>>],
+) -> Vec<KnownDistanceError> {
+    if known_distances.is_empty() {
+        return Vec::new();
+    }
+    // the held out frames aren't fitted by the calibration
+    let errors = match holdout_frames
+        .get(cam_idx)
+        .filter(|f| f.iter().any(|f| f.is_some()))
+    {
+        Some(holdout) => {
+            let rtvecs: HashMap<_, _> = holdout
+                .iter()
+                .enumerate()
+                .filter_map(|(i, f)| Some((i, solve_board_pose(model, f.as_ref()?)?)))
+                .collect();
+            known_distance_errors(cam_idx, model, &rtvecs, holdout, known_distances)
+        }
+        None => known_distance_errors(cam_idx, model, rtvec_map, frames, known_distances),
+    };
+    for e in &errors {
+        info!(
+            "cam{} corners {} - {}: {:.2} mm measured {:.2} mm, rms error {:.2} mm of {} frames",
+            cam_idx,
+            e.corner0,
+            e.corner1,
+            e.distance_m * 1e3,
+            e.mean_distance_m * 1e3,
+            e.rms_error_m * 1e3,
+            e.num_frames
+        );
+    }
+    errors
+}
+
+fn camera_scale_check(
+    cam_idx: usize,
+    model: &GenericModel<f64>,
+    rtvec_map: &HashMap<usize, RvecTvec>,
+    frames: &[Option<FrameFeature>],
+) -> Option<BoardScaleCheck> {
+    let check = board_scale_check(cam_idx, model, rtvec_map, frames)?;
+    info!(
+        "cam{} measured tag size {:.4} pitch {:.4} of the board config",
+        cam_idx, check.tag_size_ratio, check.tag_pitch_ratio
+    );
+    if check.spacing_error().abs() > MAX_SCALE_ERROR {
+        warn!(
+            "cam{} tag spacing of the board is {:.2}% off the board config",
+            cam_idx,
+            check.spacing_error() * 100.0
+        );
+    }
+    Some(check)
+}
+
+#[derive(Args)]
+struct CheckArgs {
+    /// camera model json
+    model: String,
+
+    /// sample every n pixels
+    #[arg(long, default_value_t = 1)]
+    step: u32,
+}
+
+fn run_check(args: &CheckArgs) {
+    let model = model_from_json(&args.model);
+    let stats = round_trip_check(&model, args.step);
+    info!(
+        "{} samples, round trip error mean {:.2e} px, max {:.2e} px at {:?}",
+        stats.num_samples, stats.mean_error, stats.max_error, stats.max_error_pixel
+    );
+    if stats.is_consistent() {
+        info!("unproject and project are consistent");
+        return;
+    }
+    warn!(
+        "{} non-finite and {} diverged round trips",
+        stats.num_non_finite, stats.num_diverged
+    );
+    let (w, h) = (model.width(), model.height());
+    for (i, &failures) in stats.region_failures.iter().enumerate() {
+        if failures > 0 {
+            let (row, col) = (i / REGION_GRID, i % REGION_GRID);
+            warn!(
+                "{} failures in x {:.0}..{:.0} y {:.0}..{:.0}",
+                failures,
+                col as f64 * w / REGION_GRID as f64,
+                (col + 1) as f64 * w / REGION_GRID as f64,
+                row as f64 * h / REGION_GRID as f64,
+                (row + 1) as f64 * h / REGION_GRID as f64,
+            );
+        }
+    }
+}
+
+#[derive(Args)]
+struct CompareArgs {
+    /// camera model json
+    model0: String,
+
+    /// camera model json of the same camera
+    model1: String,
+
+    /// sample every n pixels
+    #[arg(long, default_value_t = 4)]
+    step: u32,
+}
+
+fn run_compare(args: &CompareArgs) {
+    let model0 = model_from_json(&args.model0);
+    let model1 = model_from_json(&args.model1);
+    if model0.width() != model1.width() || model0.height() != model1.height() {
+        warn!("the models have different resolutions, rescale one of them first");
+        return;
+    }
+    let comparison = compare_models(&model0, &model1, args.step);
+    for (i, delta) in comparison.param_deltas.iter().enumerate() {
+        info!(
+            "param {}: {:.6} -> {:.6} ({:+.6})",
+            i,
+            model0.params()[i],
+            model1.params()[i],
+            delta
+        );
+    }
+    info!(
+        "projection difference mean {:.4} px, max {:.4} px at {:?}",
+        comparison.projection_mean, comparison.projection_max, comparison.projection_max_pixel
+    );
+    info!(
+        "undistortion map difference mean {:.4} px, max {:.4} px at {:?}",
+        comparison.undistortion_mean,
+        comparison.undistortion_max,
+        comparison.undistortion_max_pixel
+    );
+}
+
+#[derive(Args)]
+struct BenchDatasetArgs {
+    /// json list of `{"name", "path", "board_config", "references"}` of euroc datasets with the
+    /// published model json of every camera
+    manifest: String,
+
+    /// benchmark report json
+    #[arg(short, long, default_value = "benchmark.json")]
+    output: String,
+
+    #[arg(long, value_enum, default_value = "t36h11")]
+    tag_family: TagFamily,
+
+    /// max mean projection difference to the published calibration in px
+    #[arg(long, default_value_t = 0.5)]
+    max_projection_error: f64,
+
+    #[arg(long, default_value_t = 1)]
+    step: usize,
+
+    #[arg(long, default_value_t = 600)]
+    max_images: usize,
+}
+
+/// Intrinsics of the cameras of a benchmark dataset calibrated one by one.
+fn bench_dataset(
+    args: &BenchDatasetArgs,
+    detector: &TagDetector,
+    dataset: &BenchmarkDataset,
+) -> Option<Vec<BenchmarkResult>> {
+    let board = Board::from_config(
+        &dataset
+            .board_config
+            .as_ref()
+            .map(|path| board_config_from_json(path))
+            .unwrap_or_default(),
+    );
+    let references: Vec<_> = dataset
+        .references
+        .iter()
+        .map(|path| model_from_json(path))
+        .collect();
+    let cams_detected_feature_frames = load_euroc(
+        &dataset.path,
+        detector,
+        &board,
+        0,
+        args.step,
+        &FrameFilter {
+            max_frames: Some(args.max_images),
+            ..Default::default()
+        },
+        references.len(),
+        DEFAULT_QUEUE_SIZE,
+        &NoopObserver,
+    );
+    let calib_params = CalibParams {
+        fixed_focal: None,
+        disabled_distortion_num: 0,
+        one_focal: false,
+        plateau: None,
+    };
+    references
+        .iter()
+        .enumerate()
+        .map(|(cam_idx, reference)| {
+            let (model, rtvec_map) = init_and_calibrate_one_camera_with_trials(
+                cam_idx,
+                &cams_detected_feature_frames,
+                reference,
+                &NoopObserver,
+                &calib_params,
+                3,
+            )?;
+            let report = validation(
+                cam_idx,
+                &model,
+                &rtvec_map,
+                &cams_detected_feature_frames[cam_idx],
+                &NoopObserver,
+            );
+            let result = BenchmarkResult::new(
+                &dataset.name,
+                reference,
+                &model,
+                &report,
+                args.max_projection_error,
+            );
+            info!(
+                "{} cam{} projection difference mean {:.4} px, max {:.4} px: {}",
+                dataset.name,
+                cam_idx,
+                result.projection_mean,
+                result.projection_max,
+                if result.passed { "pass" } else { "fail" }
+            );
+            Some(result)
+        })
+        .collect()
+}
+
+fn run_bench_dataset(args: &BenchDatasetArgs) {
+    let detector = TagDetector::new(&args.tag_family, None);
+    let mut report = BenchmarkReport {
+        max_projection_error: args.max_projection_error,
+        failed_datasets: Vec::new(),
+        results: Vec::new(),
+    };
+    for dataset in benchmark_datasets_from_json(&args.manifest) {
+        info!("benchmark {}", dataset.name);
+        match bench_dataset(args, &detector, &dataset) {
+            Some(results) => report.results.extend(results),
+            None => {
+                warn!("{} calibration failed", dataset.name);
+                report.failed_datasets.push(dataset.name);
+            }
+        }
+    }
+    benchmark_report_to_json(&args.output, &report);
+    if !report.passed() {
+        warn!("benchmark failed");
+        std::process::exit(1);
+    }
+    info!("benchmark passed");
+}
+
+#[derive(Args)]
+struct ProfileArgs {
+    /// path to image folder
+    path: String,
+
+    #[arg(long, value_enum, default_value = "t36h11")]
+    tag_family: TagFamily,
+
+    #[arg(short, long, value_enum, default_value = "eucm")]
+    model: GenericModel<f64>,
+
+    #[arg(long, value_enum, default_value = "euroc")]
+    dataset_format: DatasetFormat,
+
+    #[arg(long, default_value_t = 0)]
+    cam_idx: usize,
+
+    #[arg(long, default_value_t = 0)]
+    start_idx: usize,
+
+    #[arg(long, default_value_t = 1)]
+    step: usize,
+
+    #[arg(long, default_value_t = 600)]
+    max_images: usize,
+
+    #[arg(long)]
+    board_config: Option<String>,
+
+    /// images read and detected at a time
+    #[arg(long, default_value_t = DEFAULT_QUEUE_SIZE)]
+    queue_size: usize,
+
+    /// also write the breakdown to this json
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+fn run_profile(args: &ProfileArgs) {
+    let detector = TagDetector::new(&args.tag_family, None);
+    let board = Board::from_config(
+        &args
+            .board_config
+            .as_ref()
+            .map(|path| board_config_from_json(path))
+            .unwrap_or_default(),
+    );
+    let mut paths = match args.dataset_format {
+        DatasetFormat::Euroc => {
+            euroc_image_paths(&args.path, args.cam_idx, args.start_idx, args.step)
+        }
+        DatasetFormat::General => {
+            others_image_paths(&args.path, args.cam_idx, args.start_idx, args.step)
+        }
+    };
+    paths.truncate(args.max_images);
+    let calib_params = CalibParams {
+        fixed_focal: None,
+        disabled_distortion_num: 0,
+        one_focal: false,
+        plateau: None,
+    };
+    let profile = profile_pipeline(
+        args.cam_idx,
+        &paths,
+        &detector,
+        &board,
+        &args.model,
+        &calib_params,
+        args.queue_size,
+    );
+    profile.log();
+    if let Some(output) = &args.output {
+        pipeline_profile_to_json(output, &profile);
+    }
+}
+
+#[derive(Args)]
+struct ZoomArgs {
+    /// json list of `{"position", "path"}` of euroc datasets of the lens at every zoom or focus
+    /// position, e.g. the focal length in mm
+    manifest: String,
+
+    /// zoom table json
+    #[arg(short, long, default_value = "zoom.json")]
+    output: String,
+
+    #[arg(long, value_enum, default_value = "t36h11")]
+    tag_family: TagFamily,
+
+    #[arg(short, long, value_enum, default_value = "eucm")]
+    model: GenericModel<f64>,
+
+    #[arg(long)]
+    board_config: Option<String>,
+
+    #[arg(long, default_value_t = 1)]
+    step: usize,
+
+    #[arg(long, default_value_t = 600)]
+    max_images: usize,
+}
+
+fn run_zoom(args: &ZoomArgs) {
+    let detector = TagDetector::new(&args.tag_family, None);
+    let board = Board::from_config(
+        &args
+            .board_config
+            .as_ref()
+            .map(|path| board_config_from_json(path))
+            .unwrap_or_default(),
+    );
+    let calib_params = CalibParams {
+        fixed_focal: None,
+        disabled_distortion_num: 0,
+        one_focal: false,
+        plateau: None,
+    };
+    let positions: Vec<_> = zoom_datasets_from_json(&args.manifest)
+        .iter()
+        .filter_map(|dataset| {
+            let frames = load_euroc(
+                &dataset.path,
+                &detector,
+                &board,
+                0,
+                args.step,
+                &FrameFilter {
+                    max_frames: Some(args.max_images),
+                    ..Default::default()
+                },
+                1,
+                DEFAULT_QUEUE_SIZE,
+                &NoopObserver,
+            );
+            let Some((model, rtvec_map)) = init_and_calibrate_one_camera_with_trials(
+                0,
+                &frames,
+                &args.model,
+                &NoopObserver,
+                &calib_params,
+                3,
+            ) else {
+                warn!("position {} calibration failed", dataset.position);
+                return None;
+            };
+            let report = validation(0, &model, &rtvec_map, &frames[0], &NoopObserver);
+            info!(
+                "position {}: params {:?}, reprojection mean {:.4} px",
+                dataset.position,
+                model.params().as_slice(),
+                report.mean_error
+            );
+            Some(ZoomPosition {
+                position: dataset.position,
+                model,
+                reprojection_mean: report.mean_error,
+            })
+        })
+        .collect();
+    let zoom = ZoomCalibration::new(positions);
+    for warning in zoom.warnings() {
+        warn!("{}", warning);
+    }
+    zoom_calibration_to_json(&args.output, &zoom);
+}
+
+#[derive(Args)]
+struct ResponseArgs {
+    /// path to image folder of a static scene, e.g. the board or a gray chart, captured at
+    /// several exposure times without moving the camera or changing the lighting
+    path: String,
+
+    /// exposure csv `timestamp [ns], exposure [ms]` of the images
+    exposures: String,
+
+    /// inverse response in the `pcalib.txt` format of DSO
+    #[arg(short, long, default_value = "pcalib.txt")]
+    output: String,
+
+    /// also write the response with the gamma and the fit error to this json
+    #[arg(long)]
+    json: Option<String>,
+
+    #[arg(long, value_enum, default_value = "euroc")]
+    dataset_format: DatasetFormat,
+
+    #[arg(long, default_value_t = 0)]
+    cam_idx: usize,
+}
+
+fn run_response(args: &ResponseArgs) {
+    let paths = match args.dataset_format {
+        DatasetFormat::Euroc => euroc_image_paths(&args.path, args.cam_idx, 0, 1),
+        DatasetFormat::General => others_image_paths(&args.path, args.cam_idx, 0, 1),
+    };
+    let exposures: HashMap<_, _> = load_exposure_csv(&args.exposures).into_iter().collect();
+    let captures: Vec<_> = paths
+        .par_iter()
+        .filter_map(|path| {
+            let Some(&exposure) = exposures.get(&path_to_timestamp(path)) else {
+                warn!("no exposure of {}", path.display());
+                return None;
+            };
+            let img = load_image(path)
+                .map_err(|e| warn!("failed to load {}: {}", path.display(), e))
+                .ok()?;
+            Some((exposure, img.to_luma8()))
+        })
+        .collect();
+    info!("{} images with an exposure", captures.len());
+    let Some(response) = calib_inverse_response(&captures) else {
+        warn!("response calibration failed");
+        return;
+    };
+    info!(
+        "gamma {:.3}, rms error {:.3}, {} pixel values unobserved",
+        response.gamma,
+        response.rms_error,
+        response.unobserved.len()
+    );
+    if !response.is_monotonic() {
+        warn!("the response is not monotonic, the scene or the camera may have moved");
+    }
+    inverse_response_to_pcalib(&args.output, &response);
+    if let Some(json) = &args.json {
+        inverse_response_to_json(json, &response);
+    }
+}
+
+#[derive(Args)]
+struct LineScanArgs {
+    /// path to image folder of the sweeps, every image is the lines of a sweep of the board by
+    /// the same motion, e.g. of a conveyor or a linear stage, with the board tilted
+    path: String,
+
+    /// line-scan calibration json
+    #[arg(short, long, default_value = "line_scan.json")]
+    output: String,
+
+    #[arg(long, value_enum, default_value = "t36h11")]
+    tag_family: TagFamily,
+
+    #[arg(long)]
+    board_config: Option<String>,
+
+    #[arg(long, value_enum, default_value = "euroc")]
+    dataset_format: DatasetFormat,
+
+    #[arg(long, default_value_t = 0)]
+    cam_idx: usize,
+}
+
+/// Detections of a camera of a dataset for the subcommands with their own camera model.
+fn detect_camera_frames(
+    path: &str,
+    tag_family: &TagFamily,
+    board_config: &Option<String>,
+    dataset_format: &DatasetFormat,
+    cam_idx: usize,
+) -> Vec<Option<FrameFeature>> {
+    let detector = TagDetector::new(tag_family, None);
+    let board = Board::from_config(
+        &board_config
+            .as_ref()
+            .map(|path| board_config_from_json(path))
+            .unwrap_or_default(),
+    );
+    let load = match dataset_format {
+        DatasetFormat::Euroc => load_euroc,
+        DatasetFormat::General => load_others,
+    };
+    let mut frames = load(
+        path,
+        &detector,
+        &board,
+        0,
+        1,
+        &FrameFilter::default(),
+        cam_idx + 1,
+        DEFAULT_QUEUE_SIZE,
+        &NoopObserver,
+    );
+    let frames = frames.swap_remove(cam_idx);
+    info!(
+        "{} images with detections",
+        frames.iter().filter(|f| f.is_some()).count()
+    );
+    frames
+}
+
+fn run_line_scan(args: &LineScanArgs) {
+    let sweeps = detect_camera_frames(
+        &args.path,
+        &args.tag_family,
+        &args.board_config,
+        &args.dataset_format,
+        args.cam_idx,
+    );
+    let Some(calibration) = calib_line_scan(&sweeps, &NoopObserver) else {
+        warn!("line-scan calibration failed");
+        return;
+    };
+    line_scan_to_json(&args.output, &calibration);
+}
+
+#[derive(Args)]
+struct TelecentricArgs {
+    /// path to image folder of the board at several tilts under the telecentric lens
+    path: String,
+
+    /// telecentric calibration json
+    #[arg(short, long, default_value = "telecentric.json")]
+    output: String,
+
+    #[arg(long, value_enum, default_value = "t36h11")]
+    tag_family: TagFamily,
+
+    #[arg(long)]
+    board_config: Option<String>,
+
+    #[arg(long, value_enum, default_value = "euroc")]
+    dataset_format: DatasetFormat,
+
+    #[arg(long, default_value_t = 0)]
+    cam_idx: usize,
+}
+
+fn run_telecentric(args: &TelecentricArgs) {
+    let frames = detect_camera_frames(
+        &args.path,
+        &args.tag_family,
+        &args.board_config,
+        &args.dataset_format,
+        args.cam_idx,
+    );
+    let Some(calibration) = calib_telecentric(&frames, &NoopObserver) else {
+        warn!("telecentric calibration failed");
+        return;
+    };
+    telecentric_to_json(&args.output, &calibration);
+}
+
+#[derive(Args)]
+struct ProjectorArgs {
+    /// path to a folder with a subfolder for every board pose of the captures of the patterns
+    /// of `projector-patterns` in the same order, e.g. named the same
+    path: String,
+
+    #[arg(long)]
+    projector_width: u32,
+
+    #[arg(long)]
+    projector_height: u32,
+
+    /// the camera is written to `cam0.json`, the projector to `cam1.json` and the camera to
+    /// projector pose to `extrinsics.json`
+    #[arg(short, long, default_value = "projector")]
+    output_folder: String,
+
+    #[arg(long, value_enum, default_value = "t36h11")]
+    tag_family: TagFamily,
+
+    #[arg(long)]
+    board_config: Option<String>,
+
+    /// model: ["ucm", "eucm", "kb4", "opencv5", "eucmt", "ftheta"]
+    #[arg(long, value_enum, default_value = "opencv5")]
+    camera_model: GenericModel<f64>,
+
+    /// model: ["ucm", "eucm", "kb4", "opencv5", "eucmt", "ftheta"]
+    #[arg(long, value_enum, default_value = "opencv5")]
+    projector_model: GenericModel<f64>,
+}
+
+fn run_projector(args: &ProjectorArgs) {
+    let detector = TagDetector::new(&args.tag_family, None);
+    let board = Board::from_config(
+        &args
+            .board_config
+            .as_ref()
+            .map(|path| board_config_from_json(path))
+            .unwrap_or_default(),
+    );
+    let projector_w_h = (args.projector_width, args.projector_height);
+    let (camera_frames, projector_frames): (Vec<_>, Vec<_>) = projector_capture_paths(&args.path)
+        .par_iter()
+        .enumerate()
+        .map(|(i, paths)| {
+            let captures: Option<Vec<_>> = paths
+                .iter()
+                .map(|path| {
+                    load_image(path)
+                        .map_err(|e| warn!("failed to load {}: {}", path.display(), e))
+                        .ok()
+                })
+                .collect();
+            // the board is detected under the white pattern
+            let camera_frame = image_to_option_feature_frame(
+                &detector,
+                captures.as_ref()?.first()?,
+                &board,
+                MIN_CORNERS,
+                i as i64,
+            )?;
+            let lumas: Vec<_> = captures?.iter().map(|c| c.to_luma8()).collect();
+            let projector_frame = ProjectorCodes::decode(&lumas, projector_w_h)
+                .and_then(|codes| projector_frame(&camera_frame, &codes, projector_w_h));
+            Some((Some(camera_frame), projector_frame))
+        })
+        .map(|frames| frames.unwrap_or((None, None)))
+        .unzip();
+    info!(
+        "{} board poses with detections, {} decoded in the projector",
+        camera_frames.iter().flatten().count(),
+        projector_frames.iter().flatten().count()
+    );
+    let calib_params = CalibParams {
+        fixed_focal: None,
+        disabled_distortion_num: 0,
+        one_focal: false,
+        plateau: None,
+    };
+    let Some(calibration) = calib_projector(
+        &camera_frames,
+        &projector_frames,
+        &args.camera_model,
+        &args.projector_model,
+        &calib_params,
+        &NoopObserver,
+    ) else {
+        warn!("projector calibration failed");
+        return;
+    };
+    std::fs::create_dir_all(&args.output_folder).expect("Valid path");
+    model_to_json(
+        &format!("{}/cam0.json", args.output_folder),
+        &calibration.camera,
+    );
+    model_to_json(
+        &format!("{}/cam1.json", args.output_folder),
+        &calibration.projector,
+    );
+    extrinsics_to_json(
+        &format!("{}/extrinsics.json", args.output_folder),
+        &calibration.extrinsics(),
+    );
+}
+
+#[derive(Args)]
+struct ProjectorPatternsArgs {
+    /// folder of the patterns, `000.png` to project first
+    output_folder: String,
+
+    #[arg(long)]
+    projector_width: u32,
+
+    #[arg(long)]
+    projector_height: u32,
+}
+
+fn run_projector_patterns(args: &ProjectorPatternsArgs) {
+    std::fs::create_dir_all(&args.output_folder).expect("Valid path");
+    let patterns = gray_code_patterns(args.projector_width, args.projector_height);
+    for (i, pattern) in patterns.iter().enumerate() {
+        pattern
+            .save(format!("{}/{:03}.png", args.output_folder, i))
+            .unwrap();
+    }
+    info!("{} patterns written", patterns.len());
+}
+
+#[derive(Args)]
+struct DetectArgs {
+    /// path to image folder
+    path: String,
+
+    /// features of every camera and frame with the time stamps, the corner ids and their image
+    /// and board points, the same as the `detections.json` of a calibration
+    #[arg(short, long, default_value = "detections.json")]
+    output: String,
+
+    #[arg(long, value_enum, default_value = "t36h11")]
+    tag_family: TagFamily,
+
+    #[arg(long)]
+    board_config: Option<String>,
+
+    #[arg(long, value_enum, default_value = "euroc")]
+    dataset_format: DatasetFormat,
+
+    #[arg(long, default_value_t = 1)]
+    cam_num: usize,
+
+    #[arg(long, default_value_t = 0)]
+    start_idx: usize,
+
+    #[arg(long, default_value_t = 1)]
+    step: usize,
+
+    #[arg(long, default_value_t = 600)]
+    max_images: usize,
+
+    /// images read ahead of the board detection, bounds the images in memory of long recordings
+    #[arg(long, default_value_t = DEFAULT_QUEUE_SIZE)]
+    queue_size: usize,
+
+    #[command(flatten)]
+    frame_filter: FrameFilterArgs,
+}
+
+fn run_detect(args: &DetectArgs) {
+    let detector = TagDetector::new(&args.tag_family, None);
+    let board = Board::from_config(
+        &args
+            .board_config
+            .as_ref()
+            .map(|path| board_config_from_json(path))
+            .unwrap_or_default(),
+    );
+    let load = match args.dataset_format {
+        DatasetFormat::Euroc => load_euroc,
+        DatasetFormat::General => load_others,
+    };
+    let now = Instant::now();
+    let cams_detected_feature_frames = load(
+        &args.path,
+        &detector,
+        &board,
+        args.start_idx,
+        args.step,
+        &args.frame_filter.filter(args.max_images),
+        args.cam_num,
+        args.queue_size,
+        &NoopObserver,
+    );
+    for (cam_idx, frames) in cams_detected_feature_frames.iter().enumerate() {
+        info!(
+            "cam{}: {} of {} images with detections",
+            cam_idx,
+            frames.iter().flatten().count(),
+            frames.len()
+        );
+    }
+    info!(
+        "detecting feature took {:.6} sec",
+        now.elapsed().as_secs_f64()
+    );
+    detections_to_json(&args.output, &cams_detected_feature_frames);
+}
+
+#[derive(Args)]
+struct ValidateArgs {
+    /// calibration output folder with cam{i}.json
+    calib_folder: String,
+
+    /// path to the new image folder
+    path: String,
+
+    /// reprojection errors of every camera, written as json
+    #[arg(short, long, default_value = "validation.json")]
+    output: String,
+
+    /// fail above this reprojection rms in px
+    #[arg(long, default_value_t = 1.0)]
+    max_rms: f64,
+
+    #[arg(long, value_enum, default_value = "t36h11")]
+    tag_family: TagFamily,
+
+    #[arg(long)]
+    board_config: Option<String>,
+
+    #[arg(long, value_enum, default_value = "euroc")]
+    dataset_format: DatasetFormat,
+
+    #[arg(long, default_value_t = 1)]
+    cam_num: usize,
+
+    #[arg(long, default_value_t = 0)]
+    start_idx: usize,
+
+    #[arg(long, default_value_t = 1)]
+    step: usize,
+
+    #[arg(long, default_value_t = 600)]
+    max_images: usize,
+
+    /// images read ahead of the board detection, bounds the images in memory of long recordings
+    #[arg(long, default_value_t = DEFAULT_QUEUE_SIZE)]
+    queue_size: usize,
+
+    #[command(flatten)]
+    frame_filter: FrameFilterArgs,
+}
+
+fn run_validate(args: &ValidateArgs) {
+    let detector = TagDetector::new(&args.tag_family, None);
+    let board = Board::from_config(
+        &args
+            .board_config
+            .as_ref()
+            .map(|path| board_config_from_json(path))
+            .unwrap_or_default(),
+    );
+    let load = match args.dataset_format {
+        DatasetFormat::Euroc => load_euroc,
+        DatasetFormat::General => load_others,
+    };
+    let cams_detected_feature_frames = load(
+        &args.path,
+        &detector,
+        &board,
+        args.start_idx,
+        args.step,
+        &args.frame_filter.filter(args.max_images),
+        args.cam_num,
+        args.queue_size,
+        &NoopObserver,
+    );
+    let mut all_stats = Vec::new();
+    let mut passed = true;
+    for (cam_idx, frames) in cams_detected_feature_frames.iter().enumerate() {
+        let model = model_from_json(&format!("{}/cam{}.json", args.calib_folder, cam_idx));
+        let Some(stats) = ValidationStats::new(cam_idx, &model, frames) else {
+            passed = false;
+            continue;
+        };
+        info!(
+            "cam{}: {} corners of {} frames, rms {:.3} px, median {:.3} px, p95 {:.3} px, max {:.3} px",
+            cam_idx, stats.num_corners, stats.num_frames, stats.rms, stats.median, stats.p95, stats.max
+        );
+        if stats.num_failed_frames > 0 {
+            warn!(
+                "cam{}: the board pose of {} frames couldn't be solved",
+                cam_idx, stats.num_failed_frames
+            );
+        }
+        if let Some(time_ns) = stats.worst_frame_time_ns {
+            info!(
+                "cam{}: worst frame {} rms {:.3} px",
+                cam_idx, time_ns, stats.worst_frame_rms
+            );
+        }
+        if stats.passes(args.max_rms) {
+            info!("cam{}: pass", cam_idx);
+        } else {
+            warn!(
+                "cam{}: fail, the calibration doesn't fit the new images, recalibrate",
+                cam_idx
+            );
+            passed = false;
+        }
+        all_stats.push(stats);
+    }
+    validation_to_json(&args.output, &all_stats);
+    if !passed {
+        std::process::exit(1);
+    }
+}
+
+#[derive(Args)]
+struct WatchArgs {
+    /// path to the image folder the frames are written to
+    path: String,
+
+    /// output folder of the detections and the latest calibration of every camera
+    #[arg(short, long, default_value = "watch")]
+    output: String,
+
+    /// model: ["ucm", "eucm", "kb4", "opencv5", "eucmt", "ftheta"]
+    #[arg(short, long, value_enum, default_value = "eucm")]
+    model: GenericModel<f64>,
+
+    /// calibrate again after this many new frames with detections
+    #[arg(long, default_value_t = 20)]
+    min_new_frames: usize,
+
+    /// seconds between the scans of the folder
+    #[arg(long, default_value_t = 2.0)]
+    poll_interval: f64,
+
+    #[arg(long, value_enum, default_value = "t36h11")]
+    tag_family: TagFamily,
+
+    #[arg(long)]
+    board_config: Option<String>,
+
+    #[arg(long, value_enum, default_value = "euroc")]
+    dataset_format: DatasetFormat,
+
+    #[arg(long, default_value_t = 1)]
+    cam_num: usize,
+
+    #[arg(long, action)]
+    one_focal: bool,
+
+    #[arg(long, default_value_t = 0)]
+    disabled_distortion_num: usize,
+
+    /// images read ahead of the board detection, bounds the images in memory of long recordings
+    #[arg(long, default_value_t = DEFAULT_QUEUE_SIZE)]
+    queue_size: usize,
+
+    #[command(flatten)]
+    frame_filter: FrameFilterArgs,
+
+    #[command(flatten)]
+    plateau: PlateauArgs,
+}
+
+fn run_watch(args: &WatchArgs) {
+    let detector = TagDetector::new(&args.tag_family, None);
+    let board = Board::from_config(
+        &args
+            .board_config
+            .as_ref()
+            .map(|path| board_config_from_json(path))
+            .unwrap_or_default(),
+    );
+    std::fs::create_dir_all(&args.output).expect("Valid path");
+    let calib_params = CalibParams {
+        fixed_focal: None,
+        disabled_distortion_num: args.disabled_distortion_num,
+        one_focal: args.one_focal,
+        plateau: args.plateau.plateau(),
+    };
+    let frame_filter = args.frame_filter.filter(usize::MAX);
+    let mut cache = DetectionCache::new(args.cam_num);
+    let mut calibrations: Vec<_> = (0..args.cam_num)
+        .map(|_| WatchCalibration::default())
+        .collect();
+    let mut new_frames = 0;
+    info!("watching {}", args.path);
+    loop {
+        let cams_frames = (0..args.cam_num)
+            .map(|cam_idx| {
+                let paths = match args.dataset_format {
+                    DatasetFormat::Euroc => euroc_image_paths(&args.path, cam_idx, 0, 1),
+                    DatasetFormat::General => others_image_paths(&args.path, cam_idx, 0, 1),
+                };
+                let frames: Vec<_> = paths
+                    .into_iter()
+                    .map(|path| (frame_time_ns(&path), path))
+                    .collect();
+                frame_filter
+                    .select(frames.into_iter())
+                    .map(|(_, frame)| frame)
+                    .collect()
+            })
+            .collect();
+        let detected = cache.update(cams_frames, &detector, &board, args.queue_size);
+        if detected > 0 {
+            new_frames += detected;
+            info!(
+                "{} new frames with detections, {} since the last calibration",
+                detected, new_frames
+            );
+        }
+        if new_frames >= args.min_new_frames {
+            new_frames = 0;
+            let cams_detected_feature_frames = cache.frames();
+            detections_to_json(
+                &format!("{}/detections.json", args.output),
+                &cams_detected_feature_frames,
+            );
+            for (cam_idx, frames) in cams_detected_feature_frames.iter().enumerate() {
+                let Some((model, rtvec_map)) = calibrations[cam_idx].calibrate(
+                    cam_idx,
+                    &cams_detected_feature_frames,
+                    &args.model,
+                    &calib_params,
+                ) else {
+                    warn!("cam{} calibration failed, wait for more frames", cam_idx);
+                    continue;
+                };
+                let residuals = corner_residuals(&model, &rtvec_map, frames);
+                let rms = (residuals.iter().map(|r| r.norm * r.norm).sum::<f64>()
+                    / residuals.len().max(1) as f64)
+                    .sqrt();
+                info!(
+                    "cam{} calibrated with {} frames, rms {:.3} px, params {:?}",
+                    cam_idx,
+                    rtvec_map.len(),
+                    rms,
+                    model.params().as_slice()
+                );
+                model_to_json(&format!("{}/cam{}.json", args.output, cam_idx), &model);
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_secs_f64(args.poll_interval));
+    }
+}
+
+fn run_rescale(args: &RescaleArgs) {
+    let model = model_from_json(&args.model);
+    let rescaled = rescale(&model, args.width, args.height);
+    info!("Rescaled params {:?}", rescaled.params().as_slice());
+    model_to_json(&args.output, &rescaled);
+}
+
+fn run_rectify(args: &RectifyArgs) {
+    let model0 = model_from_json(&format!("{}/cam{}.json", args.calib_folder, args.cam0));
+    let model1 = model_from_json(&format!("{}/cam{}.json", args.calib_folder, args.cam1));
+    let extrinsics = extrinsics_from_json(&format!("{}/extrinsics.json", args.calib_folder));
+    let t_i_0 = extrinsics.rtvecs();
+    let t_1_0 = t_i_0[args.cam1].to_na_isometry3() * t_i_0[args.cam0].to_na_isometry3().inverse();
+    let w_h = (
+        args.width.unwrap_or(model0.width() as u32),
+        args.height.unwrap_or(model0.height() as u32),
+    );
+    let rectification = StereoRectification::new(&model0, &model1, &t_1_0, Some(w_h));
+    std::fs::create_dir_all(&args.output).expect("Valid path");
+    stereo_rectification_to_opencv_yaml(
+        &format!("{}/stereo_rectification.yaml", args.output),
+        &rectification,
+    );
+    let (undistorter0, undistorter1) = rectification.undistorters(&model0, &model1);
+    model_to_json(
+        &format!("{}/pinhole.json", args.output),
+        &undistorter0.pinhole_model().unwrap(),
+    );
+    info!(
+        "Rectified cam{} and cam{}, baseline {:?}",
+        args.cam0,
+        args.cam1,
+        rectification.baseline.as_slice()
+    );
+    for (cam_idx, input, undistorter) in [
+        (args.cam0, &args.input0, undistorter0),
+        (args.cam1, &args.input1, undistorter1),
+    ] {
+        if let Some(input) = input {
+            let output = format!("{}/cam{}", args.output, cam_idx);
+            let num = args
+                .remap
+                .undistort_folder(&args.remap.apply(undistorter), input, &output);
+            info!("Rectified {} images to {}", num, output);
+        }
+    }
+}
+
+fn run_undistort(args: &UndistortArgs) {
+    let model = model_from_json(&args.model);
+    let w_h = (
+        args.width.unwrap_or(model.width() as u32),
+        args.height.unwrap_or(model.height() as u32),
+    );
+    let hfov = args.fov.unwrap_or(180.0).to_radians();
+    let undistorter = match args.projection {
+        Projection::Pinhole => {
+            if let Some(fov) = args.fov {
+                Undistorter::with_fov(&model, fov, Some(w_h))
+            } else if let Some(zoom) = args.zoom {
+                Undistorter::with_zoom(&model, zoom, Some(w_h))
+            } else if let Some(alpha) = args.alpha {
+                let (camera_matrix, roi) = optimal_new_camera_matrix(&model, alpha, Some(w_h));
+                info!("Valid region (x, y, w, h) {:?}", roi);
+                Undistorter::new(&model, &camera_matrix, w_h, None)
+            } else {
+                Undistorter::with_balance(&model, args.balance, Some(w_h))
+            }
+        }
+        Projection::Equirectangular => {
+            let vfov = args
+                .vfov
+                .map_or(hfov * w_h.1 as f64 / w_h.0 as f64, f64::to_radians);
+            Undistorter::with_projection(
+                &model,
+                TargetProjection::Equirectangular { hfov, vfov },
+                w_h,
+            )
+        }
+        Projection::Cylindrical => {
+            Undistorter::with_projection(&model, TargetProjection::Cylindrical { hfov }, w_h)
+        }
+    };
+    let undistorter = if args.unproject_lut {
+        undistorter.with_lut(&UnprojectionLut::new(&model))
+    } else {
+        undistorter
+    };
+    let undistorter = args.remap.apply(undistorter);
+    let (hfov, vfov) = undistorter.fov_deg();
+    info!(
+        "{:?} field of view {:.1} x {:.1} deg",
+        args.projection, hfov, vfov
+    );
+    let now = Instant::now();
+    let is_video = std::path::Path::new(&args.input).is_file();
+    let num = if is_video {
+        match undistort_video(&undistorter, &args.input, &args.output) {
+            Ok(num) => num,
+            Err(e) => {
+                warn!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        args.remap
+            .undistort_folder(&undistorter, &args.input, &args.output)
+    };
+    if let Some(pinhole) = undistorter.pinhole_model() {
+        let pinhole_path = if is_video {
+            std::path::Path::new(&args.output)
+                .with_extension("json")
+                .display()
+                .to_string()
+        } else {
+            format!("{}/pinhole.json", args.output)
+        };
+        model_to_json(&pinhole_path, &pinhole);
+    }
+    info!(
+        "Undistorted {} frames to {} in {:.2} sec.",
+        num,
+        args.output,
+        now.elapsed().as_secs_f64()
+    );
+}
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
-enum DatasetFormat {
-    Euroc,
-    General,
+/// Load up to `num` detected frames spread evenly over the sequence.
+fn load_preview_images(
+    cli: &CCRSCli,
+    cam_idx: usize,
+    feature_frames: &[Option<FrameFeature>],
+    num: usize,
+) -> Vec<(i64, DynamicImage)> {
+    let img_paths = image_paths(cli, cam_idx);
+    let detected: Vec<_> = feature_frames
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| f.as_ref().map(|f| (i, f.time_ns)))
+        .collect();
+    let step = (detected.len() / num).max(1);
+    detected
+        .iter()
+        .step_by(step)
+        .take(num)
+        .filter_map(|(i, time_ns)| {
+            let img = ImageReader::open(img_paths.get(*i)?).ok()?.decode().ok()?;
+            Some((*time_ns, img))
+        })
+        .collect()
 }
 
-#[derive(Parser)]
-#[command(version, about, author)]
-struct CCRSCli {
-    /// path to image folder
-    path: String,
+/// Save the intrinsics and the residual analysis, and log them to the visualizer.
+/// Associates the frames and board poses of every camera with cam0 by the estimated time
+/// offsets.
+fn align_cameras_by_time(
+    cli: &CCRSCli,
+    output_folder: &str,
+    cams_frames: &mut Vec<Vec<Option<FrameFeature>>>,
+    cam_rtvecs: &mut Vec<HashMap<usize, RvecTvec>>,
+) {
+    let mut time_offsets_ns = vec![0];
+    for cam_idx in 1..cams_frames.len() {
+        let Some(offset) = estimate_time_offset(
+            &cams_frames[0],
+            &cam_rtvecs[0],
+            &cams_frames[cam_idx],
+            &cam_rtvecs[cam_idx],
+            (cli.camera_max_time_offset_ms * 1e6) as i64,
+        ) else {
+            warn!("cam{} time offset estimation failed", cam_idx);
+            return;
+        };
+        info!("cam{} time offset {:.3} ms", cam_idx, offset as f64 * 1e-6);
+        time_offsets_ns.push(offset);
+    }
+    time_offsets_to_json(
+        &format!("{}/time_offsets.json", output_folder),
+        &time_offsets_ns,
+    );
+    let aligned = align_frames(cams_frames, &time_offsets_ns);
+    // every old frame index is used once, the frames are associated one to one
+    let reordered: Vec<_> = aligned
+        .iter()
+        .zip(cams_frames.iter_mut())
+        .map(|(indexes, frames)| {
+            indexes
+                .iter()
+                .map(|i| i.and_then(|i| frames[i].take()))
+                .collect()
+        })
+        .collect();
+    *cams_frames = reordered;
+    *cam_rtvecs = aligned
+        .iter()
+        .zip(cam_rtvecs.iter())
+        .map(|(indexes, rtvecs)| {
+            indexes
+                .iter()
+                .enumerate()
+                .filter_map(|(new, old)| Some((new, rtvecs.get(&(*old)?)?.clone())))
+                .collect()
+        })
+        .collect();
+}
 
-    /// tag_family: ["t16h5", "t25h7", "t25h9", "t36h11", "t36h11b1"]
-    #[arg(long, value_enum, default_value = "t36h11")]
-    tag_family: TagFamily,
+fn check_trigger_delays(
+    cli: &CCRSCli,
+    output_folder: &str,
+    cams_frames: &[Vec<Option<FrameFeature>>],
+    cam_rtvecs: &[HashMap<usize, RvecTvec>],
+) {
+    let trigger_delays: Vec<_> = (1..cam_rtvecs.len())
+        .filter_map(|cam_idx| {
+            estimate_trigger_delay(
+                cam_idx,
+                &cams_frames[0],
+                &cam_rtvecs[0],
+                &cam_rtvecs[cam_idx],
+            )
+        })
+        .collect();
+    for delay in &trigger_delays {
+        info!(
+            "cam{} trigger delay {:.3} ms jitter {:.3} ms of {} frames",
+            delay.cam_idx,
+            delay.delay_ns * 1e-6,
+            delay.jitter_ns * 1e-6,
+            delay.num_frames
+        );
+        let max_delay_ns = cli.max_trigger_delay_ms * 1e6;
+        if delay.delay_ns.abs() > max_delay_ns || delay.jitter_ns > max_delay_ns {
+            warn!(
+                "cam{} isn't synced with cam0, the extrinsics are biased by the motion during the delay",
+                delay.cam_idx
+            );
+        }
+    }
+    trigger_delays_to_json(
+        &format!("{}/trigger_delays.json", output_folder),
+        &trigger_delays,
+    );
+}
 
-    /// model: ["ucm", "eucm", "kb4", "opencv5", "eucmt", "ftheta"]
-    #[arg(short, long, value_enum, default_value = "eucm")]
-    model: GenericModel<f64>,
+fn calibrate_vehicle(
+    surveyed_boards: &[SurveyedBoard],
+    output_folder: &str,
+    cam_idx: usize,
+    intrinsic: &GenericModel<f64>,
+    rtvec_map: &HashMap<usize, RvecTvec>,
+    feature_frames: &[Option<FrameFeature>],
+) {
+    if surveyed_boards.is_empty() {
+        return;
+    }
+    let Some(alignment) = align_to_vehicle(intrinsic, rtvec_map, feature_frames, surveyed_boards)
+    else {
+        warn!("cam{} vehicle alignment failed", cam_idx);
+        return;
+    };
+    info!(
+        "cam{} T_vehicle_cam rvec {} tvec {}",
+        cam_idx,
+        alignment.t_vehicle_cam.na_rvec(),
+        alignment.t_vehicle_cam.na_tvec()
+    );
+    vehicle_alignment_to_json(
+        &format!("{}/cam{}_vehicle.json", output_folder, cam_idx),
+        &alignment,
+    );
+}
 
-    #[arg(long, default_value_t = 0)]
-    start_idx: usize,
+fn check_depth_color(
+    cli: &CCRSCli,
+    depth_images: &[(i64, DepthImage)],
+    output_folder: &str,
+    camera_intrinsics: &[GenericModel<f64>],
+    t_i_0: &[RvecTvec],
+    board_rtvecs: &HashMap<usize, RvecTvec>,
+    cams_detected_feature_frames: &[Vec<Option<FrameFeature>>],
+) {
+    if depth_images.is_empty() {
+        return;
+    }
+    let (depth_cam, color_cam) = (cli.depth_cam, cli.color_cam);
+    if depth_cam >= camera_intrinsics.len() || color_cam >= camera_intrinsics.len() {
+        warn!(
+            "cam{} or cam{} of the depth to color alignment is not calibrated",
+            depth_cam, color_cam
+        );
+        return;
+    }
+    let t_depth_0 = t_i_0[depth_cam].to_na_isometry3();
+    let t_color_depth = t_i_0[color_cam].to_na_isometry3() * t_depth_0.inverse();
+    let depth_rtvecs: HashMap<_, _> = board_rtvecs
+        .iter()
+        .map(|(k, t_0_b)| (*k, (t_depth_0 * t_0_b.to_na_isometry3()).to_rvec_tvec()))
+        .collect();
+    let params = DepthColorParams {
+        depth_scale: cli.depth_scale,
+        ..Default::default()
+    };
+    let Some(alignment) = depth_color_alignment(
+        depth_cam,
+        color_cam,
+        &camera_intrinsics[depth_cam],
+        &camera_intrinsics[color_cam],
+        &t_color_depth,
+        &depth_rtvecs,
+        &cams_detected_feature_frames[depth_cam],
+        &cams_detected_feature_frames[color_cam],
+        depth_images,
+        &params,
+    ) else {
+        warn!(
+            "cam{} - cam{} depth to color alignment failed",
+            depth_cam, color_cam
+        );
+        return;
+    };
+    info!(
+        "cam{} depth to cam{} color median error {:.4} px of {} corners in {} frames, depth bias {:.4} m rms {:.4} m",
+        depth_cam,
+        color_cam,
+        alignment.median_error,
+        alignment.num_corners,
+        alignment.num_frames,
+        alignment.depth_bias,
+        alignment.depth_rms
+    );
+    depth_color_alignment_to_json(&format!("{}/depth_color.json", output_folder), &alignment);
+}
 
-    #[arg(long, default_value_t = 1)]
-    step: usize,
+fn calibrate_lidar(
+    cli: &CCRSCli,
+    scans: &[(i64, Vec<nalgebra::Vector3<f64>>)],
+    output_folder: &str,
+    cam_idx: usize,
+    rtvec_map: &HashMap<usize, RvecTvec>,
+    feature_frames: &[Option<FrameFeature>],
+) {
+    if scans.is_empty() {
+        return;
+    }
+    let params = LidarCalibParams {
+        plane_threshold: cli.lidar_plane_threshold,
+        board_margin: cli.lidar_board_margin,
+        ..Default::default()
+    };
+    let Some(calib) = calibrate_camera_lidar(scans, feature_frames, rtvec_map, &params) else {
+        warn!("cam{} camera-lidar calibration failed", cam_idx);
+        return;
+    };
+    info!(
+        "cam{} T_cam_lidar rvec {} tvec {}",
+        cam_idx,
+        calib.t_cam_lidar.na_rvec(),
+        calib.t_cam_lidar.na_tvec()
+    );
+    camera_lidar_to_json(
+        &format!("{}/cam{}_lidar.json", output_folder, cam_idx),
+        &calib,
+    );
+}
 
-    #[arg(long, default_value_t = 600)]
-    max_images: usize,
+fn calibrate_robot(
+    robot_poses: &HashMap<i64, nalgebra::Isometry3<f64>>,
+    output_folder: &str,
+    cam_idx: usize,
+    rtvec_map: &HashMap<usize, RvecTvec>,
+    feature_frames: &[Option<FrameFeature>],
+) {
+    if robot_poses.is_empty() {
+        return;
+    }
+    let frame_robot_poses: HashMap<_, _> = feature_frames
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| Some((i, *robot_poses.get(&f.as_ref()?.time_ns)?)))
+        .collect();
+    let Some(calib) = calibrate_hand_eye(&frame_robot_poses, rtvec_map) else {
+        warn!("cam{} hand-eye calibration failed", cam_idx);
+        return;
+    };
+    info!(
+        "cam{} T_flange_cam rvec {} tvec {}, error rms {:.5} rad {:.5} m, {} outliers",
+        cam_idx,
+        calib.t_flange_cam.na_rvec(),
+        calib.t_flange_cam.na_tvec(),
+        calib.rotation_rms,
+        calib.translation_rms,
+        calib.outliers.len()
+    );
+    hand_eye_to_json(
+        &format!("{}/cam{}_hand_eye.json", output_folder, cam_idx),
+        &calib,
+    );
+}
 
-    #[arg(long, default_value_t = 1)]
-    cam_num: usize,
+fn calibrate_imu(
+    cli: &CCRSCli,
+    imu: &[ImuSample],
+    cam_idx: usize,
+    intrinsic: &GenericModel<f64>,
+    rtvec_map: &HashMap<usize, RvecTvec>,
+    feature_frames: &[Option<FrameFeature>],
+) -> Option<(usize, CamImuCalibration)> {
+    if imu.is_empty() {
+        return None;
+    }
+    let board_frames: Vec<_> = rtvec_map
+        .iter()
+        .filter_map(|(&i, rtvec)| Some((feature_frames[i].as_ref()?, rtvec.to_na_isometry3())))
+        .collect();
+    let board_poses: Vec<_> = board_frames
+        .iter()
+        .map(|(frame, pose)| (frame.time_ns, *pose))
+        .collect();
+    let max_time_offset_ns = (cli.imu_max_time_offset_ms * 1e6) as i64;
+    let Some(init) = calibrate_camera_imu(&board_poses, imu, max_time_offset_ns) else {
+        warn!("cam{} camera-imu calibration failed", cam_idx);
+        return None;
+    };
+    let options = SplineOptions {
+        knot_ns: (cli.imu_knot_ms * 1e6) as i64,
+        gyro_noise: cli.imu_gyro_noise,
+        accel_noise: cli.imu_accel_noise,
+        max_time_offset_ns,
+    };
+    let calib =
+        refine_camera_imu(intrinsic, &board_frames, imu, &init, &options).unwrap_or_else(|| {
+            warn!(
+                "cam{} spline camera-imu refinement failed, keep the initial estimate",
+                cam_idx
+            );
+            init
+        });
+    info!(
+        "cam{} T_cam_imu rvec {} tvec {}, time offset {:.3} ms",
+        cam_idx,
+        calib.t_cam_imu.rotation.scaled_axis(),
+        calib.t_cam_imu.translation.vector,
+        calib.time_offset_ns as f64 * 1e-6
+    );
+    Some((cam_idx, calib))
+}
 
-    #[arg(long)]
-    board_config: Option<String>,
+/// Cells with fewer corners than this aren't compared with the overall error.
+const MIN_GRID_CELL_CORNERS: usize = 10;
 
-    #[arg(short, long)]
-    output_folder: Option<String>,
+fn export_residual_grid(
+    cli: &CCRSCli,
+    output_folder: &str,
+    cam_idx: usize,
+    intrinsic: &GenericModel<f64>,
+    residuals: &[CornerResidual],
+) {
+    let img_w_h = (intrinsic.width() as u32, intrinsic.height() as u32);
+    let cell_size = cli.residual_grid_cell_size;
+    let cells = residual_grid(residuals, img_w_h, cell_size);
+    write_residual_grid_csv(
+        &format!("{}/cam{}_residual_grid.csv", output_folder, cam_idx),
+        &cells,
+    );
+    let mean_error = residuals.iter().map(|r| r.norm).sum::<f64>() / residuals.len().max(1) as f64;
+    residual_heatmap(&cells, img_w_h, cell_size, mean_error * 4.0)
+        .save(format!(
+            "{}/cam{}_residual_grid.png",
+            output_folder, cam_idx
+        ))
+        .unwrap();
+    if let Some(worst) = cells
+        .iter()
+        .filter(|c| c.count >= MIN_GRID_CELL_CORNERS)
+        .max_by(|a, b| a.mean_error.partial_cmp(&b.mean_error).unwrap())
+    {
+        if worst.mean_error > mean_error * 3.0 {
+            warn!(
+                "cam{} mean reprojection error {:.3} px at x {} y {} is much larger than {:.3} px of the image",
+                cam_idx,
+                worst.mean_error,
+                worst.col as u32 * cell_size,
+                worst.row as u32 * cell_size,
+                mean_error
+            );
+        }
+    }
+}
 
-    #[arg(long, value_enum, default_value = "euroc")]
-    dataset_format: DatasetFormat,
+fn export_camera_results(
+    visualizer: &dyn Visualizer,
+    cli: &CCRSCli,
+    output_folder: &str,
+    cam_idx: usize,
+    intrinsic: &GenericModel<f64>,
+    rtvec_map: &HashMap<usize, RvecTvec>,
+    feature_frames: &[Option<FrameFeature>],
+) {
+    let topic = format!("/cam{}", cam_idx);
+    model_to_json(&format!("{}/cam{}.json", output_folder, cam_idx), intrinsic);
+    log_distortion_field(visualizer, &topic, intrinsic, 32);
+    log_undistorted_previews(
+        visualizer,
+        &topic,
+        intrinsic,
+        &load_preview_images(cli, cam_idx, feature_frames, 5),
+    );
+    let radius_bins = residual_vs_radius(intrinsic, rtvec_map, feature_frames, 20.0);
+    write_residual_vs_radius_csv(
+        &format!("{}/cam{}_residual_vs_radius.csv", output_folder, cam_idx),
+        &radius_bins,
+    );
+    log_residual_vs_radius(visualizer, &topic, &radius_bins);
+    let residuals = corner_residuals(intrinsic, rtvec_map, feature_frames);
+    export_residual_grid(cli, output_folder, cam_idx, intrinsic, &residuals);
+    if cli.export_residuals {
+        write_corner_residuals_csv(
+            &format!("{}/cam{}_residuals.csv", output_folder, cam_idx),
+            &residuals,
+        );
+    }
+    if cli.export_overlays {
+        export_overlays(
+            cli,
+            output_folder,
+            cam_idx,
+            intrinsic,
+            rtvec_map,
+            feature_frames,
+        );
+    }
+}
 
-    #[arg(long, action)]
-    one_focal: bool,
+fn image_paths(cli: &CCRSCli, cam_idx: usize) -> Vec<PathBuf> {
+    // calibrating from a features file without images
+    let Some(dataset_root) = cli.path.as_deref() else {
+        return Vec::new();
+    };
+    match cli.dataset_format {
+        DatasetFormat::Euroc => euroc_image_paths(dataset_root, cam_idx, cli.start_idx, cli.step),
+        DatasetFormat::General => {
+            others_image_paths(dataset_root, cam_idx, cli.start_idx, cli.step)
+        }
+    }
+}
 
-    #[arg(long, default_value_t = 0)]
-    disabled_distortion_num: usize,
+fn export_overlays(
+    cli: &CCRSCli,
+    output_folder: &str,
+    cam_idx: usize,
+    intrinsic: &GenericModel<f64>,
+    rtvec_map: &HashMap<usize, RvecTvec>,
+    feature_frames: &[Option<FrameFeature>],
+) {
+    let overlay_folder = format!("{}/overlays/cam{}", output_folder, cam_idx);
+    std::fs::create_dir_all(&overlay_folder).expect("Valid path");
+    let img_paths = image_paths(cli, cam_idx);
+    rtvec_map.par_iter().for_each(|(&i, rtvec)| {
+        // frames of merged sessions don't have images
+        let (Some(frame_feature), Some(img_path)) = (&feature_frames[i], img_paths.get(i)) else {
+            return;
+        };
+        let img = ImageReader::open(img_path).unwrap().decode().unwrap();
+        let reprojections = reproject_frame(intrinsic, rtvec, frame_feature);
+        draw_detection_overlay(&img, frame_feature, Some(&reprojections))
+            .save(format!("{}/{}.png", overlay_folder, frame_feature.time_ns))
+            .unwrap();
+    });
+}
 
-    #[arg(long)]
-    fixed_focal: Option<f64>,
+/// inputs shared by the post-processing of every calibrated camera
+struct PostprocessInputs<'a> {
+    cli: &'a CCRSCli,
+    output_folder: &'a str,
+    recording: &'a rerun::RecordingStream,
+    observer: &'a dyn PipelineObserver,
+    cams_frames: &'a [Vec<Option<FrameFeature>>],
+    holdout_frames: &'a [Vec<Option<FrameFeature>>],
+    known_distances: &'a [KnownDistance],
+    sessions: &'a [Range<usize>],
+    imu: &'a [ImuSample],
+    robot_poses: &'a HashMap<i64, nalgebra::Isometry3<f64>>,
+    lidar_scans: &'a [(i64, Vec<nalgebra::Vector3<f64>>)],
+    surveyed_boards: &'a [SurveyedBoard],
 }
 
-fn main() {
-    env_logger::init();
+/// per camera results written once all cameras are post-processed
+#[derive(Default)]
+struct CameraOutputs {
+    reports: Vec<CalibrationReport>,
+    straightness: Vec<StraightnessStats>,
+    cam_imu: Vec<(usize, CamImuCalibration)>,
+    session_residual: Vec<SessionResidual>,
+    scale_checks: Vec<BoardScaleCheck>,
+    holdout: Vec<HoldoutStats>,
+    known_distance_checks: Vec<KnownDistanceError>,
+    monte_carlo: Vec<ParamSpread>,
+    frame_influences: Vec<FrameInfluence>,
+    outlier_frames: Vec<OutlierFrame>,
+    correlations: Vec<ParamCorrelation>,
+}
+
+/// Validates and exports one calibrated camera, `in_rig` when the board poses come from the
+/// calibration with extrinsics.
+fn postprocess_camera(
+    inputs: &PostprocessInputs,
+    outputs: &mut CameraOutputs,
+    cam_idx: usize,
+    intrinsic: &GenericModel<f64>,
+    lut: Option<&UnprojectionLut>,
+    rtvec_map: &HashMap<usize, RvecTvec>,
+    in_rig: bool,
+) {
+    let cli = inputs.cli;
+    let frames = &inputs.cams_frames[cam_idx];
+    outputs.reports.push(validation(
+        cam_idx,
+        intrinsic,
+        rtvec_map,
+        frames,
+        inputs.observer,
+    ));
+    outputs
+        .straightness
+        .push(camera_straightness(cam_idx, intrinsic, lut, frames));
+    outputs
+        .scale_checks
+        .extend(camera_scale_check(cam_idx, intrinsic, rtvec_map, frames));
+    outputs.holdout.extend(camera_holdout(
+        cam_idx,
+        intrinsic,
+        rtvec_map,
+        frames,
+        inputs.holdout_frames,
+    ));
+    outputs.known_distance_checks.extend(camera_known_distances(
+        inputs.known_distances,
+        cam_idx,
+        intrinsic,
+        rtvec_map,
+        frames,
+        inputs.holdout_frames,
+    ));
+    outputs.monte_carlo.extend(camera_monte_carlo(
+        cli, cam_idx, intrinsic, rtvec_map, frames,
+    ));
+    outputs.frame_influences.extend(camera_leave_one_out(
+        cli, cam_idx, intrinsic, rtvec_map, frames,
+    ));
+    outputs.outlier_frames.extend(camera_outlier_frames(
+        cam_idx, intrinsic, rtvec_map, frames, in_rig,
+    ));
+    outputs.correlations.extend(camera_correlation(
+        cli, cam_idx, intrinsic, rtvec_map, frames,
+    ));
+    info!(
+        "Cam {} final params{}{}",
+        cam_idx,
+        if in_rig { " with extrinsic" } else { "" },
+        serde_json::to_string_pretty(intrinsic).unwrap()
+    );
+    export_camera_results(
+        inputs.recording,
+        cli,
+        inputs.output_folder,
+        cam_idx,
+        intrinsic,
+        rtvec_map,
+        frames,
+    );
+    outputs.cam_imu.extend(calibrate_imu(
+        cli, inputs.imu, cam_idx, intrinsic, rtvec_map, frames,
+    ));
+    calibrate_robot(
+        inputs.robot_poses,
+        inputs.output_folder,
+        cam_idx,
+        rtvec_map,
+        frames,
+    );
+    calibrate_lidar(
+        cli,
+        inputs.lidar_scans,
+        inputs.output_folder,
+        cam_idx,
+        rtvec_map,
+        frames,
+    );
+    calibrate_vehicle(
+        inputs.surveyed_boards,
+        inputs.output_folder,
+        cam_idx,
+        intrinsic,
+        rtvec_map,
+        frames,
+    );
+    outputs.session_residual.extend(session_residuals(
+        cam_idx,
+        intrinsic,
+        rtvec_map,
+        frames,
+        inputs.sessions,
+    ));
+}
+
+/// Writes the reports of all post-processed cameras.
+fn write_camera_outputs(
+    inputs: &PostprocessInputs,
+    outputs: CameraOutputs,
+    baseline: Option<BaselineScaleCheck>,
+) {
+    let output_folder = inputs.output_folder;
+    calibration_reports_to_json(&format!("{}/report.json", output_folder), &outputs.reports);
+    for report in &outputs.reports {
+        write_error_histogram_csv(
+            &format!(
+                "{}/cam{}_error_histogram.csv",
+                output_folder, report.cam_idx
+            ),
+            &report.histogram,
+        );
+    }
+    if !outputs.cam_imu.is_empty() {
+        camchain_imucam_to_yaml(
+            &format!("{}/camchain-imucam.yaml", output_folder),
+            &outputs.cam_imu,
+        );
+    }
+    if inputs.sessions.len() > 1 {
+        session_residuals_to_json(
+            &format!("{}/session_residuals.json", output_folder),
+            &outputs.session_residual,
+        );
+    }
+    if !outputs.holdout.is_empty() {
+        holdout_to_json(&format!("{}/holdout.json", output_folder), &outputs.holdout);
+    }
+    if !outputs.known_distance_checks.is_empty() {
+        known_distance_errors_to_json(
+            &format!("{}/known_distances.json", output_folder),
+            &outputs.known_distance_checks,
+        );
+    }
+    if !outputs.monte_carlo.is_empty() {
+        monte_carlo_to_json(
+            &format!("{}/monte_carlo.json", output_folder),
+            &outputs.monte_carlo,
+        );
+    }
+    if !outputs.frame_influences.is_empty() {
+        frame_influences_to_json(
+            &format!("{}/leave_one_out.json", output_folder),
+            &outputs.frame_influences,
+        );
+    }
+    outlier_frames_to_json(
+        &format!("{}/outlier_frames.json", output_folder),
+        &outputs.outlier_frames,
+    );
+    param_correlations_to_json(
+        &format!("{}/correlation.json", output_folder),
+        &outputs.correlations,
+    );
+    observability_to_json(
+        &format!("{}/observability.json", output_folder),
+        &outputs
+            .correlations
+            .iter()
+            .map(camera_observability)
+            .collect::<Vec<_>>(),
+    );
+    scale_check_to_json(
+        &format!("{}/scale_check.json", output_folder),
+        &ScaleCheck {
+            boards: outputs.scale_checks,
+            baseline,
+        },
+    );
+}
 
+fn main() {
     let cli = CCRSCli::parse();
-    let detector = TagDetector::new(&cli.tag_family, None);
-    let board = if let Some(board_config_path) = cli.board_config {
-        Board::from_config(&board_config_from_json(&board_config_path))
+    init_tracing(cli.json_log);
+    if let Some(command) = &cli.command {
+        match command {
+            Command::Undistort(args) => run_undistort(args),
+            Command::Rectify(args) => run_rectify(args),
+            Command::Rescale(args) => run_rescale(args),
+            Command::Check(args) => run_check(args),
+            Command::Compare(args) => run_compare(args),
+            Command::BenchDataset(args) => run_bench_dataset(args),
+            Command::Profile(args) => run_profile(args),
+            Command::Zoom(args) => run_zoom(args),
+            Command::Response(args) => run_response(args),
+            Command::LineScan(args) => run_line_scan(args),
+            Command::Telecentric(args) => run_telecentric(args),
+            Command::Projector(args) => run_projector(args),
+            Command::ProjectorPatterns(args) => run_projector_patterns(args),
+            Command::Detect(args) => run_detect(args),
+            Command::Validate(args) => run_validate(args),
+            Command::Watch(args) => run_watch(args),
+        }
+        return;
+    }
+    if let Some(threads) = cli.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .unwrap();
+    }
+    let detector = cli.tag_detector();
+    let multi_plane_config = cli
+        .multi_plane_config
+        .as_ref()
+        .map(|path| multi_plane_config_from_json(path));
+    let multi_plane_target = multi_plane_config
+        .as_ref()
+        .map(MultiPlaneTarget::from_config);
+    let board = if let Some(target) = &multi_plane_target {
+        target.board()
+    } else if let Some(board_config_path) = &cli.board_config {
+        Board::from_config(&board_config_from_json(board_config_path))
     } else {
         let config = BoardConfig::default();
         board_config_to_json("default_board_config.json", &config);
         Board::from_config(&config)
     };
-    let dataset_root = &cli.path;
     let now = Instant::now();
-    let output_folder = if let Some(output_folder) = cli.output_folder {
-        output_folder
-    } else {
-        let now = OffsetDateTime::now_local().unwrap();
-        format!(
-            "results/{}{:02}{:02}_{:02}_{:02}_{:02}",
-            now.year(),
-            now.month() as u8,
-            now.day(),
-            now.hour(),
-            now.minute(),
-            now.second(),
-        )
-    };
-    std::fs::create_dir_all(&output_folder).expect("Valid path");
+    let output_folder =
+        if let Some(output_folder) = cli.resume.clone().or(cli.output_folder.clone()) {
+            output_folder
+        } else {
+            let now = OffsetDateTime::now_local().unwrap();
+            format!(
+                "results/{}{:02}{:02}_{:02}_{:02}_{:02}",
+                now.year(),
+                now.month() as u8,
+                now.day(),
+                now.hour(),
+                now.minute(),
+                now.second(),
+            )
+        };
+    std::fs::create_dir_all(format!("{}/checkpoints", output_folder)).expect("Valid path");
 
     let recording = rerun::RecordingStreamBuilder::new("calibration")
         .save(format!("{}/logging.rrd", output_folder))
@@ -104,92 +2872,524 @@ fn main() {
     recording
         .log_static("/", &rerun::ViewCoordinates::RDF)
         .unwrap();
-    trace!("Start loading data");
-    println!("Start loading images and detecting charts.");
-    let mut cams_detected_feature_frames: Vec<Vec<Option<FrameFeature>>> = match cli.dataset_format
-    {
-        DatasetFormat::Euroc => load_euroc(
-            dataset_root,
-            &detector,
-            &board,
-            cli.start_idx,
-            cli.step,
-            cli.cam_num,
-            Some(&recording),
-        ),
-        DatasetFormat::General => load_others(
-            dataset_root,
-            &detector,
-            &board,
-            cli.start_idx,
-            cli.step,
-            cli.cam_num,
-            Some(&recording),
-        ),
+    let resumed_detections = cli
+        .resume
+        .as_ref()
+        .map(|folder| format!("{}/detections.json", folder))
+        .filter(|path| Path::new(path).exists());
+    let loaded_features = cli.features.clone().or(resumed_detections).map(|path| {
+        info!("Loading the features of {}", path);
+        detections_from_json(&path)
+    });
+    if loaded_features.is_none() && cli.path.is_none() {
+        warn!("no detections.json to resume from, give the image folder to detect the board");
+        return;
+    }
+    send_default_blueprint(
+        &recording,
+        loaded_features.as_ref().map_or(cli.cam_num, |f| f.len()),
+    );
+    let observer = VisualizerObserver {
+        visualizer: recording.clone(),
     };
+    trace!("Start loading data");
+    let frame_filter = cli.frame_filter.filter(cli.max_images);
+    let mut cams_detected_feature_frames: Vec<Vec<Option<FrameFeature>>> =
+        if let Some(mut loaded_features) = loaded_features {
+            cli.frame_filter.apply(&mut loaded_features, cli.max_images);
+            loaded_features
+        } else if let Some(window_ms) = cli.event_window_ms {
+            info!("Start loading events and detecting charts.");
+            load_events(
+                cli.dataset_root(),
+                &*detector,
+                &board,
+                cli.start_idx,
+                cli.step,
+                &frame_filter,
+                cli.cam_num,
+                (window_ms * 1e6) as i64,
+                &EventCountReconstruction::default(),
+                &observer,
+            )
+        } else {
+            info!("Start loading images and detecting charts.");
+            let dataset_root = cli.dataset_root();
+            match cli.dataset_format {
+                DatasetFormat::Euroc => load_euroc(
+                    dataset_root,
+                    &*detector,
+                    &board,
+                    cli.start_idx,
+                    cli.step,
+                    &frame_filter,
+                    cli.cam_num,
+                    cli.queue_size,
+                    &observer,
+                ),
+                DatasetFormat::General => load_others(
+                    dataset_root,
+                    &*detector,
+                    &board,
+                    cli.start_idx,
+                    cli.step,
+                    &frame_filter,
+                    cli.cam_num,
+                    cli.queue_size,
+                    &observer,
+                ),
+            }
+        };
     let duration_sec = now.elapsed().as_secs_f64();
-    println!("detecting feature took {:.6} sec", duration_sec);
-    println!("total: {} images", cams_detected_feature_frames[0].len());
-    cams_detected_feature_frames
-        .iter_mut()
-        .for_each(|f| f.truncate(cli.max_images));
-    println!(
+    info!("detecting feature took {:.6} sec", duration_sec);
+    info!("total: {} images", cams_detected_feature_frames[0].len());
+    info!(
         "avg: {} sec",
         duration_sec / cams_detected_feature_frames[0].len() as f64
     );
-    let (calibrated_intrinsics, cam_rtvecs): (Vec<_>, Vec<_>) = cams_detected_feature_frames
+    for (cam_idx, feature_frames) in cams_detected_feature_frames.iter_mut().enumerate() {
+        let clipped = filter_clipped_frames(
+            feature_frames,
+            cli.max_clipped_ratio,
+            !cli.exclude_clipped_frames,
+        );
+        if clipped > 0 {
+            warn!("cam{} has {} clipped frames", cam_idx, clipped);
+        }
+        if let Some(line_delay_ns) = cli.line_delay_ns {
+            for f in feature_frames.iter_mut().flatten() {
+                f.set_rolling_shutter_times(line_delay_ns);
+            }
+        }
+    }
+    // the detections resumed from are kept as they are for the next runs
+    if cli.resume.is_none() {
+        detections_to_json(
+            &format!("{}/detections.json", output_folder),
+            &cams_detected_feature_frames,
+        );
+    }
+    let sessions = if cli.sessions.is_empty() {
+        Vec::new()
+    } else {
+        let (merged, sessions) = merge_sessions(
+            std::iter::once(std::mem::take(&mut cams_detected_feature_frames))
+                .chain(cli.sessions.iter().map(|path| detections_from_json(path)))
+                .collect(),
+        );
+        cams_detected_feature_frames = merged;
+        sessions
+    };
+    let holdout_frames = cli
+        .holdout_fraction
+        .map(|fraction| split_holdout(&mut cams_detected_feature_frames, fraction))
+        .unwrap_or_default();
+    if let Some(max_frames) = cli.max_frames {
+        let removed = match cli.frame_selection {
+            FrameSelectionArg::Coverage => {
+                select_informative_frames(&mut cams_detected_feature_frames, max_frames)
+            }
+            FrameSelectionArg::Information => select_frames_by_information_gain(
+                &mut cams_detected_feature_frames,
+                max_frames,
+                &cli.model,
+                &CalibParams {
+                    fixed_focal: cli.fixed_focal,
+                    disabled_distortion_num: cli.disabled_distortion_num,
+                    one_focal: cli.one_focal,
+                    plateau: cli.plateau.plateau(),
+                },
+            ),
+        };
+        info!(
+            "kept the {} most informative frames, {} removed",
+            max_frames, removed
+        );
+    }
+    let mut coverage_scores = Vec::new();
+    for (cam_idx, feature_frames) in cams_detected_feature_frames.iter().enumerate() {
+        let Some(img_w_h) = feature_frames.iter().flatten().map(|f| f.img_w_h).next() else {
+            continue;
+        };
+        let cell_size = 16;
+        let counts = corner_coverage(feature_frames, img_w_h, cell_size);
+        let score = CoverageScore::new(cam_idx, &counts);
+        info!(
+            "cam{} coverage: {:.0}% of the image, {:.0}% of the radius, {:.0}% of the periphery",
+            cam_idx,
+            score.cell_fraction * 100.0,
+            score.radial_coverage * 100.0,
+            score.periphery_fraction * 100.0
+        );
+        for warning in score.warnings() {
+            warn!("cam{} {}", cam_idx, warning);
+        }
+        coverage_scores.push(score);
+        let heatmap = coverage_heatmap(&counts, img_w_h, cell_size);
+        log_coverage_heatmap(&recording, &format!("/cam{}", cam_idx), &heatmap);
+        heatmap
+            .save(format!("{}/cam{}_coverage.png", output_folder, cam_idx))
+            .unwrap();
+    }
+    coverage_to_json(
+        &format!("{}/coverage.json", output_folder),
+        &coverage_scores,
+    );
+    if cli.dry_run {
+        let mut qualities = Vec::new();
+        for (cam_idx, feature_frames) in cams_detected_feature_frames.iter().enumerate() {
+            let mut quality = DatasetQuality::new(cam_idx, feature_frames);
+            info!(
+                "cam{}: {} of {} images with detections, {:.0} corners per frame",
+                cam_idx, quality.num_detected_frames, quality.num_images, quality.mean_corners
+            );
+            info!(
+                "cam{}: {} tilted frames about {} axes, max tilt {:.0} deg, board size {:.2} to {:.2} of the image diagonal",
+                cam_idx,
+                quality.num_tilted_frames,
+                quality.num_tilt_axes,
+                quality.max_tilt_deg,
+                quality.board_size_range.0,
+                quality.board_size_range.1
+            );
+            info!(
+                "cam{}: {:.0}% of the corners blurred, {:.1} px on average",
+                cam_idx,
+                quality.blurred_corner_fraction * 100.0,
+                quality.mean_blur_px
+            );
+            for warning in quality.warnings() {
+                warn!("cam{} {}", cam_idx, warning);
+            }
+            let calib_params = CalibParams {
+                fixed_focal: if cam_idx == 0 { cli.fixed_focal } else { None },
+                disabled_distortion_num: cli.disabled_distortion_num,
+                one_focal: cli.one_focal,
+                plateau: cli.plateau.plateau(),
+            };
+            quality.observability =
+                predict_param_correlation(cam_idx, feature_frames, &cli.model, &calib_params)
+                    .map(|correlation| camera_observability(&correlation));
+            if quality.observability.is_none() {
+                warn!("cam{} observability couldn't be predicted", cam_idx);
+            }
+            qualities.push(quality);
+        }
+        dataset_quality_to_json(
+            &format!("{}/dataset_quality.json", output_folder),
+            &qualities,
+        );
+        return;
+    }
+    if cli.init_only {
+        for (cam_idx, feature_frames) in cams_detected_feature_frames.iter().enumerate() {
+            let fixed_focal = if cam_idx == 0 { cli.fixed_focal } else { None };
+            let Some(diagnostic) = InitDiagnostic::new(cam_idx, feature_frames, fixed_focal) else {
+                continue;
+            };
+            info!(
+                "cam{} init frames {} and {}, {} common corners",
+                cam_idx,
+                diagnostic.frame_idxs.0,
+                diagnostic.frame_idxs.1,
+                diagnostic.num_common_corners
+            );
+            for (i, attempt) in diagnostic.attempts.iter().enumerate() {
+                info!(
+                    "cam{} attempt {}: lambda {:.4}, focal candidates {:?}, init focal {:?}",
+                    cam_idx, i, attempt.lambda, attempt.focal_candidates, attempt.init_focal
+                );
+                if let Some(failure) = &attempt.failure {
+                    warn!("cam{} attempt {} failed, {}", cam_idx, i, failure);
+                }
+            }
+            if let Some(ucm) = diagnostic.initialized() {
+                info!("cam{} initial UCM {:?}", cam_idx, ucm.params().as_slice());
+            } else {
+                warn!("cam{} didn't initialize", cam_idx);
+            }
+            init_diagnostic_to_json(
+                &format!("{}/cam{}_init.json", output_folder, cam_idx),
+                &diagnostic,
+            );
+        }
+        return;
+    }
+    let temperatures = cli
+        .temperatures
+        .as_ref()
+        .map(|path| load_temperature_csv(path))
+        .unwrap_or_default();
+    let (calibrated, dropped_frames): (Vec<_>, Vec<_>) = cams_detected_feature_frames
         .iter()
         .enumerate()
         .map(|(cam_idx, feature_frames)| {
             let topic = format!("/cam{}", cam_idx);
             log_feature_frames(&recording, &topic, feature_frames);
-            let mut calibrated_result: Option<(GenericModel<f64>, HashMap<usize, RvecTvec>)> = None;
             let max_trials = 3;
             let cam0_fixed_focal = if cam_idx == 0 { cli.fixed_focal } else { None };
             let calib_params = CalibParams {
                 fixed_focal: cam0_fixed_focal,
                 disabled_distortion_num: cli.disabled_distortion_num,
                 one_focal: cli.one_focal,
+                plateau: cli.plateau.plateau(),
             };
-            for trial in 0..max_trials {
-                calibrated_result = init_and_calibrate_one_camera(
-                    cam_idx,
-                    &cams_detected_feature_frames,
-                    &cli.model,
-                    &recording,
-                    &calib_params,
-                    trial > 0,
-                );
-                if calibrated_result.is_some() {
-                    break;
+            let mut refined_target = None;
+            let calibrated_result = if let Some(target) = &multi_plane_target {
+                calib_multi_plane(feature_frames, target, &cli.model, &calib_params, &observer).map(
+                    |calib| {
+                        let config = calib.config(multi_plane_config.as_ref().unwrap());
+                        multi_plane_config_to_json(
+                            &format!("{}/cam{}_multi_plane.json", output_folder, cam_idx),
+                            &config,
+                        );
+                        refined_target = Some(MultiPlaneTarget::from_config(&config));
+                        (calib.model, calib.rtvecs)
+                    },
+                )
+            } else {
+                let checkpoint_path =
+                    format!("{}/checkpoints/cam{}_calib.json", output_folder, cam_idx);
+                let checkpoint = (cli.resume.is_some() && Path::new(&checkpoint_path).exists())
+                    .then(|| camera_checkpoint_from_json(&checkpoint_path))
+                    .filter(|c| c.matches(feature_frames, &cli.model, &calib_params));
+                if let Some(checkpoint) = checkpoint {
+                    info!("cam{} resumed from {}", cam_idx, checkpoint_path);
+                    observer.on_calibration_done(cam_idx, &checkpoint.model, &checkpoint.rtvecs);
+                    Some((checkpoint.model, checkpoint.rtvecs))
+                } else {
+                    let calibrated_result = init_and_calibrate_one_camera_with_trials(
+                        cam_idx,
+                        &cams_detected_feature_frames,
+                        &cli.model,
+                        &observer,
+                        &calib_params,
+                        max_trials,
+                    );
+                    if let Some((model, rtvecs)) = &calibrated_result {
+                        camera_checkpoint_to_json(
+                            &checkpoint_path,
+                            &CameraCheckpoint::new(model, rtvecs, &calib_params),
+                        );
+                    }
+                    calibrated_result
                 }
-            }
+            };
             if calibrated_result.is_none() {
                 panic!(
                     "Failed to calibrate cam{} after {} times",
                     cam_idx, max_trials
                 );
             }
-            let (final_result, rtvec_map) = calibrated_result.unwrap();
-            (final_result, rtvec_map)
+            let (mut final_result, mut rtvec_map) = calibrated_result.unwrap();
+            // the later refinements need the calibrated poses of the planes
+            let refined_frames;
+            let feature_frames = if let Some(target) = &refined_target {
+                let mut frames = feature_frames.clone();
+                target.update_frames(&mut frames);
+                refined_frames = frames;
+                &refined_frames
+            } else {
+                feature_frames
+            };
+            let mut dropped = Vec::new();
+            let mut line_delay_ns = None;
+            if cli.drop_outlier_frames {
+                if let Some((model, rtvecs, frames)) = calib_camera_without_outlier_frames(
+                    cam_idx,
+                    &final_result,
+                    &rtvec_map,
+                    feature_frames,
+                    &calib_params,
+                    &observer,
+                ) {
+                    info!("cam{} dropped {} outlier frames", cam_idx, frames.len());
+                    final_result = model;
+                    rtvec_map = rtvecs;
+                    dropped = frames;
+                }
+            }
+            if cli.rolling_shutter {
+                if let Some(calib) = calib_rolling_shutter(
+                    feature_frames,
+                    &final_result,
+                    &rtvec_map,
+                    cli.one_focal || cam0_fixed_focal.is_some(),
+                    &observer,
+                ) {
+                    rolling_shutter_to_json(
+                        &format!("{}/cam{}_rolling_shutter.json", output_folder, cam_idx),
+                        &calib.rolling_shutter(),
+                    );
+                    final_result = calib.model;
+                    rtvec_map = calib.rtvecs;
+                    line_delay_ns = Some(calib.line_delay_ns);
+                } else {
+                    warn!("cam{} rolling shutter calibration failed", cam_idx);
+                }
+            }
+            if cli.temperatures.is_some() {
+                if let Some(thermal_drift) = calib_thermal_drift(
+                    feature_frames,
+                    &final_result,
+                    &rtvec_map,
+                    &temperatures,
+                    cli.thermal_degree as usize,
+                    cli.one_focal || cam0_fixed_focal.is_some(),
+                    &observer,
+                ) {
+                    thermal_drift_to_json(
+                        &format!("{}/cam{}_thermal.json", output_folder, cam_idx),
+                        &thermal_drift,
+                    );
+                } else {
+                    warn!("cam{} thermal drift calibration failed", cam_idx);
+                }
+            }
+            if let Some(air_folder) = &cli.flat_port {
+                let air_model = model_from_json(&format!("{}/cam{}.json", air_folder, cam_idx));
+                let params = FlatPortParams {
+                    n_glass: cli.port_index,
+                    n_water: cli.water_index,
+                    glass_thickness: cli.port_thickness,
+                };
+                if let Some(flat_port) =
+                    calib_flat_port(feature_frames, &air_model, &rtvec_map, &params, &observer)
+                {
+                    flat_port_to_json(
+                        &format!("{}/cam{}_flat_port.json", output_folder, cam_idx),
+                        &flat_port,
+                    );
+                } else {
+                    warn!("cam{} flat port calibration failed", cam_idx);
+                }
+            }
+            (
+                (final_result, rtvec_map),
+                (dropped, line_delay_ns, refined_target),
+            )
         })
         .unzip();
-    let t_cam_i_0_init = init_camera_extrinsic(&cam_rtvecs);
-    for t in &t_cam_i_0_init {
-        println!("r {} t {}", t.na_rvec(), t.na_tvec());
-    }
-    if let Some((camera_intrinsics, t_i_0, board_rtvecs)) = calib_all_camera_with_extrinsics(
-        &calibrated_intrinsics,
-        &t_cam_i_0_init,
-        &cam_rtvecs,
-        &cams_detected_feature_frames,
-        cli.one_focal || cli.fixed_focal.is_some(),
-        cli.disabled_distortion_num,
-        cli.fixed_focal.is_some(),
-    ) {
-        let mut rep_rms = Vec::new();
+    let (calibrated_intrinsics, mut cam_rtvecs): (Vec<_>, Vec<_>) = calibrated.into_iter().unzip();
+    // the rig calibration would bring the dropped frames back
+    for (frames, (dropped, line_delay_ns, refined_target)) in
+        cams_detected_feature_frames.iter_mut().zip(dropped_frames)
+    {
+        for i in dropped {
+            frames[i] = None;
+        }
+        if let Some(target) = refined_target {
+            target.update_frames(frames);
+        }
+        if let Some(line_delay_ns) = line_delay_ns {
+            for f in frames.iter_mut().flatten() {
+                f.set_rolling_shutter_times(line_delay_ns);
+            }
+        }
+    }
+    if cli.estimate_time_offsets && cam_rtvecs.len() > 1 {
+        align_cameras_by_time(
+            &cli,
+            &output_folder,
+            &mut cams_detected_feature_frames,
+            &mut cam_rtvecs,
+        );
+    }
+    if !cli.non_overlapping && cam_rtvecs.len() > 1 {
+        check_trigger_delays(
+            &cli,
+            &output_folder,
+            &cams_detected_feature_frames,
+            &cam_rtvecs,
+        );
+    }
+    let imu = cli
+        .imu
+        .as_ref()
+        .map(|path| load_imu(path, cli.imu_topic.as_deref()))
+        .unwrap_or_default();
+    let robot_poses: HashMap<_, _> = cli
+        .robot_poses
+        .as_ref()
+        .map(|path| load_robot_poses_csv(path))
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    let surveyed_boards = cli
+        .surveyed_boards
+        .as_ref()
+        .map(|path| surveyed_boards_from_json(path))
+        .unwrap_or_default();
+    let known_distances = cli
+        .known_distances
+        .as_ref()
+        .map(|path| known_distances_from_json(path))
+        .unwrap_or_default();
+    let lidar_scans = cli
+        .lidar
+        .as_ref()
+        .map(|folder| load_lidar_scans(folder))
+        .unwrap_or_default();
+    let depth_images = cli
+        .depth
+        .as_ref()
+        .map(|folder| load_depth_images(folder))
+        .unwrap_or_default();
+    // the boards seen by non-overlapping cameras at the same time aren't the same board
+    let extrinsic_result = (!cli.non_overlapping)
+        .then(|| init_camera_extrinsic(&cam_rtvecs))
+        .flatten()
+        .and_then(|t_cam_i_0_init| {
+            for t in &t_cam_i_0_init {
+                info!("r {} t {}", t.na_rvec(), t.na_tvec());
+            }
+            calib_all_camera_with_extrinsics(
+                &calibrated_intrinsics,
+                &t_cam_i_0_init,
+                &cam_rtvecs,
+                &cams_detected_feature_frames,
+                cli.one_focal || cli.fixed_focal.is_some(),
+                cli.disabled_distortion_num,
+                cli.fixed_focal.is_some(),
+                cli.plateau.plateau(),
+                &observer,
+            )
+        })
+        .map(|(camera_intrinsics, t_i_0, board_rtvecs)| {
+            refine_all_camera_with_extrinsics(
+                &camera_intrinsics,
+                &t_i_0,
+                &board_rtvecs,
+                &cams_detected_feature_frames,
+                cli.one_focal || cli.fixed_focal.is_some(),
+                cli.disabled_distortion_num,
+                cli.fixed_focal.is_some(),
+                cli.plateau.plateau(),
+                &observer,
+            )
+            .unwrap_or((camera_intrinsics, t_i_0, board_rtvecs))
+        });
+    let inputs = PostprocessInputs {
+        cli: &cli,
+        output_folder: &output_folder,
+        recording: &recording,
+        observer: &observer,
+        cams_frames: &cams_detected_feature_frames,
+        holdout_frames: &holdout_frames,
+        known_distances: &known_distances,
+        sessions: &sessions,
+        imu: &imu,
+        robot_poses: &robot_poses,
+        lidar_scans: &lidar_scans,
+        surveyed_boards: &surveyed_boards,
+    };
+    let mut outputs = CameraOutputs::default();
+    if let Some((camera_intrinsics, t_i_0, board_rtvecs)) = extrinsic_result {
+        let luts: Vec<_> = camera_intrinsics
+            .iter()
+            .map(|intrinsic| cli.unproject_lut.then(|| UnprojectionLut::new(intrinsic)))
+            .collect();
         for (cam_idx, intrinsic) in camera_intrinsics.iter().enumerate() {
-            model_to_json(&format!("{}/cam{}.json", output_folder, cam_idx), intrinsic);
             let new_rtvec_map: HashMap<usize, RvecTvec> = board_rtvecs
                 .iter()
                 .map(|(k, t_0_b)| {
@@ -199,52 +3399,157 @@ fn main() {
                     )
                 })
                 .collect();
-            recording
-                .log_static(
-                    format!("/cam{}", cam_idx),
-                    &na_isometry3_to_rerun_transform3d(&t_i_0[cam_idx].to_na_isometry3().inverse()),
-                )
-                .unwrap();
-            let rep = validation(
+            recording.log_transform(
+                &format!("/cam{}", cam_idx),
+                &t_i_0[cam_idx].to_na_isometry3().inverse(),
+            );
+            postprocess_camera(
+                &inputs,
+                &mut outputs,
                 cam_idx,
                 intrinsic,
+                luts[cam_idx].as_ref(),
                 &new_rtvec_map,
-                &cams_detected_feature_frames[cam_idx],
-                Some(&recording),
-            );
-            rep_rms.push(rep);
-            println!(
-                "Cam {} final params with extrinsic{}",
-                cam_idx,
-                serde_json::to_string_pretty(intrinsic).unwrap()
+                true,
             );
         }
-        write_report(&format!("{}/report.txt", output_folder), true, &rep_rms);
-
+        let mut epipolar = Vec::new();
+        let mut stereo_depth = Vec::new();
+        for cam0 in 0..camera_intrinsics.len() {
+            for cam1 in cam0 + 1..camera_intrinsics.len() {
+                let t_1_0 = t_i_0[cam1].to_na_isometry3() * t_i_0[cam0].to_na_isometry3().inverse();
+                let errors = epipolar_errors(
+                    &camera_intrinsics[cam0],
+                    &camera_intrinsics[cam1],
+                    [luts[cam0].as_ref(), luts[cam1].as_ref()],
+                    &t_1_0,
+                    &cams_detected_feature_frames[cam0],
+                    &cams_detected_feature_frames[cam1],
+                );
+                let stats = EpipolarStats::new(cam0, cam1, &errors);
+                info!(
+                    "cam{} - cam{} median epipolar error: {:.5} px from {} corners",
+                    cam0, cam1, stats.median_error, stats.count
+                );
+                epipolar.push(stats);
+                let depth = stereo_depth_errors(
+                    cam0,
+                    cam1,
+                    &camera_intrinsics[cam0],
+                    &camera_intrinsics[cam1],
+                    &t_1_0,
+                    &cams_detected_feature_frames[cam0],
+                    &cams_detected_feature_frames[cam1],
+                );
+                for bin in &depth.bins {
+                    info!(
+                        "cam{} - cam{} depth error rms at {:.1}-{:.1} m: {:.5} m ({:.3}%), board scale error {:.3}% of {} corners",
+                        cam0,
+                        cam1,
+                        bin.min_distance,
+                        bin.max_distance,
+                        bin.depth_rms,
+                        bin.relative_depth_rms * 100.0,
+                        bin.board_scale_error * 100.0,
+                        bin.num_corners
+                    );
+                }
+                stereo_depth.push(depth);
+            }
+        }
+        stereo_depth_to_json(
+            &format!("{}/stereo_depth.json", output_folder),
+            &stereo_depth,
+        );
+        check_depth_color(
+            &cli,
+            &depth_images,
+            &output_folder,
+            &camera_intrinsics,
+            &t_i_0,
+            &board_rtvecs,
+            &cams_detected_feature_frames,
+        );
+        write_report(
+            &format!("{}/report.txt", output_folder),
+            true,
+            &outputs.reports,
+            &outputs.straightness,
+            &epipolar,
+        );
+        let baseline = cli
+            .expected_baseline
+            .filter(|_| t_i_0.len() > 1)
+            .map(|expected| {
+                let check = BaselineScaleCheck::new(&t_i_0, 0, 1, expected);
+                info!(
+                    "cam0 - cam1 baseline {:.4} m, expected {:.4} m",
+                    check.baseline_m, check.expected_baseline_m
+                );
+                if (check.board_scale() - 1.0).abs() > MAX_SCALE_ERROR {
+                    warn!(
+                        "the printed board is {:.4} times the size of the board config",
+                        check.board_scale()
+                    );
+                }
+                check
+            });
+        write_camera_outputs(&inputs, outputs, baseline);
         extrinsics_to_json(
             &format!("{}/extrinsics.json", output_folder),
             &Extrinsics::new(&t_i_0),
         );
+        if let Err(e) = camchain_to_yaml(
+            &format!("{}/camchain.yaml", output_folder),
+            &camera_intrinsics,
+            &t_i_0,
+        ) {
+            warn!("camchain.yaml is not written, {}", e);
+        }
     } else {
-        let mut rep_rms = Vec::new();
+        if cli.non_overlapping && cam_rtvecs.len() > 1 {
+            if let Some(t_i_0) = calibrate_non_overlapping_rig(&cam_rtvecs) {
+                for (cam_idx, t) in t_i_0.iter().enumerate() {
+                    info!("cam{} r {} t {}", cam_idx, t.na_rvec(), t.na_tvec());
+                    recording
+                        .log_transform(&format!("/cam{}", cam_idx), &t.to_na_isometry3().inverse());
+                }
+                extrinsics_to_json(
+                    &format!("{}/extrinsics.json", output_folder),
+                    &Extrinsics::new(&t_i_0),
+                );
+                if let Err(e) = camchain_to_yaml(
+                    &format!("{}/camchain.yaml", output_folder),
+                    &calibrated_intrinsics,
+                    &t_i_0,
+                ) {
+                    warn!("camchain.yaml is not written, {}", e);
+                }
+            } else {
+                warn!("non-overlapping rig calibration failed");
+            }
+        }
         for (cam_idx, (intrinsic, rtvec_map)) in
-            calibrated_intrinsics.iter().zip(cam_rtvecs).enumerate()
+            calibrated_intrinsics.iter().zip(&cam_rtvecs).enumerate()
         {
-            let rep = validation(
+            let lut = cli.unproject_lut.then(|| UnprojectionLut::new(intrinsic));
+            postprocess_camera(
+                &inputs,
+                &mut outputs,
                 cam_idx,
                 intrinsic,
-                &rtvec_map,
-                &cams_detected_feature_frames[cam_idx],
-                Some(&recording),
-            );
-            rep_rms.push(rep);
-            println!(
-                "Cam {} final params{}",
-                cam_idx,
-                serde_json::to_string_pretty(intrinsic).unwrap()
+                lut.as_ref(),
+                rtvec_map,
+                false,
             );
-            model_to_json(&format!("{}/cam{}.json", output_folder, cam_idx), intrinsic);
         }
-        write_report(&format!("{}/report.txt", output_folder), false, &rep_rms);
+        write_report(
+            &format!("{}/report.txt", output_folder),
+            false,
+            &outputs.reports,
+            &outputs.straightness,
+            &[],
+        );
+        write_camera_outputs(&inputs, outputs, None);
     }
 }
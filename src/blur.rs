@@ -0,0 +1,231 @@
+use std::f64::consts::PI;
+
+use std::collections::HashMap;
+
+use image::{DynamicImage, GrayImage};
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+
+use crate::detected_points::{FrameFeature, TagDetection};
+
+/// Radius in px of the neighborhood of a corner the blur is estimated in.
+const WINDOW_RADIUS: i32 = 8;
+/// Intensity range of a neighborhood below which the edges are too weak for an estimate.
+const MIN_CONTRAST: f64 = 20.0;
+/// Directions searched over 180 degrees.
+const ANGLE_STEPS: usize = 36;
+/// Orientation steps between the normals of the two edges of a corner.
+const MIN_EDGE_SEPARATION: usize = 6;
+/// Steps in px of the coarse and fine search of the blur length and the optics width.
+const COARSE_STEP: f64 = 1.0;
+const FINE_STEP: f64 = 0.25;
+/// Longest blur in px searched, about the size of the neighborhood.
+const MAX_LENGTH: f64 = 16.0;
+const MIN_OPTICS_WIDTH: f64 = 1.0;
+const OPTICS_STEPS: usize = 4;
+/// Blur lengths in px below this are within the noise of the estimate and left out.
+const MIN_BLUR_LENGTH: f64 = 2.0;
+
+/// Motion blur around a corner, a box kernel of `length` px along the direction `angle` in rad
+/// from the x axis of the image. The blur of the edges only shows the blur across each of them,
+/// which is the same for the direction mirrored about `edge_angle`, the normal of one of the
+/// edges, so either direction could be the one of the motion.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CornerBlur {
+    pub angle: f32,
+    pub length: f32,
+    pub edge_angle: f32,
+}
+
+fn unit(angle: f64) -> na::Vector2<f64> {
+    na::Vector2::new(angle.cos(), angle.sin())
+}
+
+impl CornerBlur {
+    /// Square root of the information of the corner relative to a sharp one of a 1 px standard
+    /// deviation. A box kernel of length `l` adds `l^2 / 12` px^2 to the variance along the blur,
+    /// which is averaged over the blur direction and its mirror.
+    pub fn sqrt_information(&self) -> na::Matrix2<f64> {
+        let d = unit(self.angle as f64);
+        let n = unit(self.edge_angle as f64);
+        let mirrored = n * (2.0 * n.dot(&d)) - d;
+        let variance = (self.length as f64).powi(2) / 12.0;
+        let covariance = na::Matrix2::identity()
+            + (d * d.transpose() + mirrored * mirrored.transpose()) * (variance / 2.0);
+        let eigen = covariance.symmetric_eigen();
+        eigen.eigenvectors
+            * na::Matrix2::from_diagonal(&eigen.eigenvalues.map(|v| 1.0 / v.sqrt()))
+            * eigen.eigenvectors.transpose()
+    }
+}
+
+/// Estimates the motion blur around `p2d`. The edges of a corner stay straight edges when
+/// blurred, an edge with the normal `n` is a ramp of about `sqrt(l^2 |n.d|^2 + w^2)` px across
+/// it, the box kernel of length `l` along `d` projected on the normal with the blur `w` of the
+/// optics, and `1 / |n.t|` as wide along a direction `t`. The width of the neighborhood along a
+/// direction is the contrast over its steepest derivative, which is the narrower edge along it,
+/// and `l`, `d` and `w` are searched for the best fit of the widths over all directions.
+pub fn estimate_corner_blur(luma: &GrayImage, p2d: &glam::Vec2) -> Option<CornerBlur> {
+    let (cx, cy) = (p2d.x.round() as i32, p2d.y.round() as i32);
+    let (w, h) = (luma.width() as i32, luma.height() as i32);
+    if cx - WINDOW_RADIUS < 1
+        || cy - WINDOW_RADIUS < 1
+        || cx + WINDOW_RADIUS >= w - 1
+        || cy + WINDOW_RADIUS >= h - 1
+    {
+        return None;
+    }
+    let at = |x: i32, y: i32| luma.get_pixel(x as u32, y as u32)[0] as f64;
+    let mut gradients = Vec::new();
+    let mut values = Vec::new();
+    for y in cy - WINDOW_RADIUS..=cy + WINDOW_RADIUS {
+        for x in cx - WINDOW_RADIUS..=cx + WINDOW_RADIUS {
+            gradients.push(na::Vector2::new(
+                (at(x + 1, y) - at(x - 1, y)) / 2.0,
+                (at(x, y + 1) - at(x, y - 1)) / 2.0,
+            ));
+            values.push(at(x, y));
+        }
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let contrast = values[values.len() * 95 / 100] - values[values.len() * 5 / 100];
+    if contrast < MIN_CONTRAST {
+        return None;
+    }
+    let angle_of = |i: usize| PI * i as f64 / ANGLE_STEPS as f64;
+    let widths: Vec<_> = (0..ANGLE_STEPS)
+        .map(|i| {
+            let t = unit(angle_of(i));
+            let peak = gradients
+                .iter()
+                .map(|g| g.dot(&t).abs())
+                .fold(f64::EPSILON, f64::max);
+            contrast / peak
+        })
+        .collect();
+    // the blur doesn't turn the gradients, the edge normals are the two strongest orientations
+    let mut orientations = vec![0.0; ANGLE_STEPS];
+    for g in &gradients {
+        let bin = (g.y.atan2(g.x).rem_euclid(PI) / PI * ANGLE_STEPS as f64) as usize;
+        orientations[bin % ANGLE_STEPS] += g.norm_squared();
+    }
+    let strongest = |bins: &[f64]| (0..ANGLE_STEPS).max_by(|&a, &b| bins[a].total_cmp(&bins[b]));
+    let n1 = strongest(&orientations)?;
+    for (i, o) in orientations.iter_mut().enumerate() {
+        let apart = (i + ANGLE_STEPS - n1) % ANGLE_STEPS;
+        if apart.min(ANGLE_STEPS - apart) < MIN_EDGE_SEPARATION {
+            *o = 0.0;
+        }
+    }
+    let n2 = strongest(&orientations)?;
+    let normals = [unit(angle_of(n1)), unit(angle_of(n2))];
+    let inverse_cosines: Vec<_> = (0..ANGLE_STEPS)
+        .map(|i| normals.map(|n| 1.0 / n.dot(&unit(angle_of(i))).abs().max(f64::EPSILON)))
+        .collect();
+    let fit_error = |angle: f64, length: f64, optics: f64| -> f64 {
+        let edges = normals
+            .map(|n| (length * length * n.dot(&unit(angle)).powi(2) + optics * optics).sqrt());
+        widths
+            .iter()
+            .zip(&inverse_cosines)
+            .map(|(width, c)| ((edges[0] * c[0]).min(edges[1] * c[1]) - width).powi(2))
+            .sum()
+    };
+    // the fit is the same for the blur direction mirrored about the first normal, so a quarter
+    // turn from it is searched, coarse first and then around the best coarse fit
+    let search = |angles: &[f64], lengths: &[f64], optics: &[f64]| {
+        let mut best = (0.0, 0.0, 0.0, f64::MAX);
+        for &angle in angles {
+            for &length in lengths {
+                for &o in optics {
+                    let error = fit_error(angle, length, o);
+                    if error < best.3 {
+                        best = (angle, length, o, error);
+                    }
+                }
+            }
+        }
+        best
+    };
+    let steps = |begin: f64, step: f64, n: usize| {
+        (0..n).map(|i| begin + step * i as f64).collect::<Vec<_>>()
+    };
+    let coarse = search(
+        &steps(angle_of(n1), angle_of(1), ANGLE_STEPS / 2 + 1),
+        &steps(0.0, COARSE_STEP, (MAX_LENGTH / COARSE_STEP) as usize + 1),
+        &steps(MIN_OPTICS_WIDTH, COARSE_STEP, OPTICS_STEPS),
+    );
+    let best = search(
+        &steps(coarse.0 - angle_of(1), angle_of(1) / 2.0, 5),
+        &steps(
+            (coarse.1 - COARSE_STEP).max(0.0),
+            FINE_STEP,
+            2 * (COARSE_STEP / FINE_STEP) as usize + 1,
+        ),
+        &steps(
+            (coarse.2 - COARSE_STEP).max(MIN_OPTICS_WIDTH),
+            FINE_STEP,
+            2 * (COARSE_STEP / FINE_STEP) as usize + 1,
+        ),
+    );
+    let (angle, length) = (best.0.rem_euclid(PI), best.1);
+    (length >= MIN_BLUR_LENGTH).then(|| CornerBlur {
+        angle: angle as f32,
+        length: length as f32,
+        edge_angle: angle_of(n1) as f32,
+    })
+}
+
+/// Estimates the motion blur of every corner of a frame, corners without a noticeable blur are
+/// left sharp. Returns the number of blurred corners.
+pub fn set_corner_blurs(luma: &GrayImage, frame_feature: &mut FrameFeature) -> usize {
+    let mut blurred = 0;
+    for fp in frame_feature.features.values_mut() {
+        fp.blur = estimate_corner_blur(luma, &fp.p2d);
+        blurred += fp.blur.is_some() as usize;
+    }
+    blurred
+}
+
+/// Tag detection that also estimates the motion blur of every detected corner, which
+/// down-weights the reprojection residuals along the blur.
+pub struct CornerBlurDetector<D: TagDetection> {
+    detector: D,
+}
+
+impl<D: TagDetection> CornerBlurDetector<D> {
+    pub fn new(detector: D) -> CornerBlurDetector<D> {
+        CornerBlurDetector { detector }
+    }
+}
+
+impl<D: TagDetection> TagDetection for CornerBlurDetector<D> {
+    fn detect_tags(&self, img: &DynamicImage) -> HashMap<u32, [(f32, f32); 4]> {
+        self.detector.detect_tags(img)
+    }
+
+    fn corner_blurs(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoTags;
+
+    impl TagDetection for NoTags {
+        fn detect_tags(&self, _img: &DynamicImage) -> HashMap<u32, [(f32, f32); 4]> {
+            HashMap::new()
+        }
+    }
+
+    #[test]
+    fn corner_blurs_opt_in() {
+        let plain: Box<dyn TagDetection> = Box::new(NoTags);
+        assert!(!plain.corner_blurs());
+        let blur: Box<dyn TagDetection> = Box::new(CornerBlurDetector::new(plain));
+        assert!(blur.corner_blurs());
+    }
+}
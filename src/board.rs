@@ -1,8 +1,10 @@
 use glam;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, io::Write};
+use std::collections::HashMap;
+#[cfg(feature = "io")]
+use std::io::Write;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BoardConfig {
     tag_size_meter: f32,
     tag_spacing: f32,
@@ -11,12 +13,14 @@ pub struct BoardConfig {
     first_id: u32,
 }
 
+#[cfg(feature = "io")]
 pub fn board_config_to_json(output_path: &str, board_config: &BoardConfig) {
     let j = serde_json::to_string_pretty(board_config).unwrap();
     let mut file = std::fs::File::create(output_path).unwrap();
     file.write_all(j.as_bytes()).unwrap();
 }
 
+#[cfg(feature = "io")]
 pub fn board_config_from_json(file_path: &str) -> BoardConfig {
     let contents =
         std::fs::read_to_string(file_path).expect("Should have been able to read the file");
@@ -61,7 +65,7 @@ impl Board {
         for r in 0..tag_rows {
             for c in 0..tag_cols {
                 let start_x = (c as f32) * tag_size_meter * (1.0 + tag_spacing);
-                let start_y = -1.0 * (r as f32) * tag_size_meter * (1.0 + tag_spacing);
+                let start_y = -(r as f32) * tag_size_meter * (1.0 + tag_spacing);
                 id_to_3d.insert(
                     count_id,
                     glam::Vec3 {
@@ -0,0 +1,54 @@
+use std::collections::{HashMap, HashSet};
+
+use camera_intrinsic_model::*;
+use serde::{Deserialize, Serialize};
+
+use crate::detected_points::FrameFeature;
+use crate::types::{CalibParams, RvecTvec};
+
+/// Intrinsics and board poses of the first calibration of a camera, written during a run so an
+/// interrupted run, or one with other options for the later stages, resumes after it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraCheckpoint {
+    pub model: GenericModel<f64>,
+    pub rtvecs: HashMap<usize, RvecTvec>,
+    pub fixed_focal: Option<f64>,
+    pub disabled_distortion_num: usize,
+    pub one_focal: bool,
+}
+
+impl CameraCheckpoint {
+    pub fn new(
+        model: &GenericModel<f64>,
+        rtvecs: &HashMap<usize, RvecTvec>,
+        calib_params: &CalibParams,
+    ) -> CameraCheckpoint {
+        CameraCheckpoint {
+            model: *model,
+            rtvecs: rtvecs.clone(),
+            fixed_focal: calib_params.fixed_focal,
+            disabled_distortion_num: calib_params.disabled_distortion_num,
+            one_focal: calib_params.one_focal,
+        }
+    }
+
+    /// Whether it was calibrated with the same model, params and frames, otherwise the camera
+    /// is calibrated again.
+    pub fn matches(
+        &self,
+        frames: &[Option<FrameFeature>],
+        target_model: &GenericModel<f64>,
+        calib_params: &CalibParams,
+    ) -> bool {
+        let detected: HashSet<_> = frames
+            .iter()
+            .enumerate()
+            .filter_map(|(i, f)| f.as_ref().map(|_| i))
+            .collect();
+        std::mem::discriminant(&self.model) == std::mem::discriminant(target_model)
+            && self.fixed_focal == calib_params.fixed_focal
+            && self.disabled_distortion_num == calib_params.disabled_distortion_num
+            && self.one_focal == calib_params.one_focal
+            && self.rtvecs.keys().copied().collect::<HashSet<_>>() == detected
+    }
+}
@@ -0,0 +1,94 @@
+use camera_intrinsic_model::GenericModel;
+use nalgebra as na;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const MAX_ROUND_TRIP_ERROR: f64 = 1e-3;
+
+/// Differences of two calibrations of the same camera.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelComparison {
+    /// `params1 - params0`, empty if the models are different types.
+    pub param_deltas: Vec<f64>,
+    /// Of the valid pixels of `model0` unprojected by `model0` and projected by `model1`, in px.
+    pub projection_mean: f64,
+    pub projection_max: f64,
+    pub projection_max_pixel: (f64, f64),
+    /// Of the source pixels of the undistortion maps of both models to the same pinhole
+    /// camera, in px.
+    pub undistortion_mean: f64,
+    pub undistortion_max: f64,
+    pub undistortion_max_pixel: (f64, f64),
+}
+
+/// Mean, max and the pixel of the max of the finite distances.
+fn summarize(distances: &[(na::Vector2<f64>, Option<f64>)]) -> (f64, f64, (f64, f64)) {
+    let finite: Vec<_> = distances
+        .iter()
+        .filter_map(|(p, d)| Some((p, d.filter(|d| d.is_finite())?)))
+        .collect();
+    let mean = finite.iter().map(|(_, d)| d).sum::<f64>() / finite.len().max(1) as f64;
+    let (max_pixel, max) = finite
+        .iter()
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .map(|(p, d)| ((p.x, p.y), *d))
+        .unwrap_or(((0.0, 0.0), 0.0));
+    (mean, max, max_pixel)
+}
+
+/// Compares the params, and the projections and undistortion maps sampled every `step`
+/// pixels. The undistortion maps are to the pinhole camera with the focal and principal point
+/// of `model0`, so a recalibration that only moved the params around without changing the
+/// rays shows up as small pixel differences.
+pub fn compare_models(
+    model0: &GenericModel<f64>,
+    model1: &GenericModel<f64>,
+    step: u32,
+) -> ModelComparison {
+    let (params0, params1) = (model0.params(), model1.params());
+    let param_deltas = if std::mem::discriminant(model0) == std::mem::discriminant(model1) {
+        (params1 - &params0).iter().copied().collect()
+    } else {
+        tracing::warn!("different camera models, params aren't compared");
+        Vec::new()
+    };
+    let (w, h) = (model0.width() as u32, model0.height() as u32);
+    let pixels: Vec<_> = (0..h)
+        .step_by(step as usize)
+        .flat_map(|y| (0..w).step_by(step as usize).map(move |x| (x, y)))
+        .map(|(x, y)| na::Vector2::new(x as f64, y as f64))
+        .collect();
+    let projection: Vec<_> = pixels
+        .par_iter()
+        .map(|p| {
+            let ray = model0.unproject_one(p);
+            // pixels outside of the valid area of model0 don't round trip
+            let valid = (model0.project_one(&ray) - p).norm() < MAX_ROUND_TRIP_ERROR;
+            (*p, valid.then(|| (model1.project_one(&ray) - p).norm()))
+        })
+        .collect();
+    let (fx, fy, cx, cy) = (params0[0], params0[1], params0[2], params0[3]);
+    let undistortion: Vec<_> = pixels
+        .par_iter()
+        .map(|p| {
+            let ray = na::Vector3::new((p.x - cx) / fx, (p.y - cy) / fy, 1.0);
+            let (p0, p1) = (model0.project_one(&ray), model1.project_one(&ray));
+            let in_image =
+                |p: &na::Vector2<f64>| p.x >= 0.0 && p.y >= 0.0 && p.x < w as f64 && p.y < h as f64;
+            // pinhole pixels outside of both images aren't in the map
+            let distance = (in_image(&p0) || in_image(&p1)).then(|| (p1 - p0).norm());
+            (*p, distance)
+        })
+        .collect();
+    let (projection_mean, projection_max, projection_max_pixel) = summarize(&projection);
+    let (undistortion_mean, undistortion_max, undistortion_max_pixel) = summarize(&undistortion);
+    ModelComparison {
+        param_deltas,
+        projection_mean,
+        projection_max,
+        projection_max_pixel,
+        undistortion_mean,
+        undistortion_max,
+        undistortion_max_pixel,
+    }
+}
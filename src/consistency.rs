@@ -0,0 +1,90 @@
+use camera_intrinsic_model::GenericModel;
+use nalgebra as na;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Round trips farther than this in px are counted as diverged.
+pub const MAX_ROUND_TRIP_ERROR: f64 = 1.0;
+
+/// The image is split into `REGION_GRID` x `REGION_GRID` regions to locate the failures.
+pub const REGION_GRID: usize = 8;
+
+/// Result of the unproject -> project round trips on a dense grid of pixels.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoundTripStats {
+    pub num_samples: usize,
+    /// Unprojection or projection gave NaN or infinity.
+    pub num_non_finite: usize,
+    /// Finite but farther than `MAX_ROUND_TRIP_ERROR` from the pixel.
+    pub num_diverged: usize,
+    /// Of the finite round trips, in px.
+    pub mean_error: f64,
+    pub max_error: f64,
+    pub max_error_pixel: (f64, f64),
+    /// Failed samples of each region, row major.
+    pub region_failures: Vec<usize>,
+}
+
+impl RoundTripStats {
+    pub fn is_consistent(&self) -> bool {
+        self.num_non_finite == 0 && self.num_diverged == 0
+    }
+}
+
+/// Samples every `step` pixels, catches models optimized into numerically unstable params.
+pub fn round_trip_check(model: &GenericModel<f64>, step: u32) -> RoundTripStats {
+    let (w, h) = (model.width() as u32, model.height() as u32);
+    let p2ds: Vec<_> = (0..h)
+        .step_by(step as usize)
+        .flat_map(|y| (0..w).step_by(step as usize).map(move |x| (x, y)))
+        .map(|(x, y)| na::Vector2::new(x as f64, y as f64))
+        .collect();
+    let errors: Vec<Option<f64>> = p2ds
+        .par_iter()
+        .map(|p2d| {
+            let p3d = model.unproject_one(p2d);
+            let p = model.project_one(&p3d);
+            let error = (p - p2d).norm();
+            error.is_finite().then_some(error)
+        })
+        .collect();
+    let mut stats = RoundTripStats {
+        num_samples: p2ds.len(),
+        num_non_finite: 0,
+        num_diverged: 0,
+        mean_error: 0.0,
+        max_error: 0.0,
+        max_error_pixel: (0.0, 0.0),
+        region_failures: vec![0; REGION_GRID * REGION_GRID],
+    };
+    let mut num_finite = 0;
+    for (p2d, error) in p2ds.iter().zip(&errors) {
+        let failed = match error {
+            Some(e) => {
+                num_finite += 1;
+                stats.mean_error += e;
+                if *e > stats.max_error {
+                    stats.max_error = *e;
+                    stats.max_error_pixel = (p2d.x, p2d.y);
+                }
+                if *e > MAX_ROUND_TRIP_ERROR {
+                    stats.num_diverged += 1;
+                    true
+                } else {
+                    false
+                }
+            }
+            None => {
+                stats.num_non_finite += 1;
+                true
+            }
+        };
+        if failed {
+            let col = (p2d.x as usize * REGION_GRID / w as usize).min(REGION_GRID - 1);
+            let row = (p2d.y as usize * REGION_GRID / h as usize).min(REGION_GRID - 1);
+            stats.region_failures[row * REGION_GRID + col] += 1;
+        }
+    }
+    stats.mean_error /= num_finite.max(1) as f64;
+    stats
+}
@@ -0,0 +1,225 @@
+use image::RgbImage;
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+
+use crate::detected_points::FrameFeature;
+use crate::types::ResidualCell;
+
+/// Number of detected corners in each `cell_size` x `cell_size` cell of the image.
+pub fn corner_coverage(
+    frame_features: &[Option<FrameFeature>],
+    img_w_h: (u32, u32),
+    cell_size: u32,
+) -> na::DMatrix<u32> {
+    let rows = img_w_h.1.div_ceil(cell_size) as usize;
+    let cols = img_w_h.0.div_ceil(cell_size) as usize;
+    let mut counts = na::DMatrix::zeros(rows, cols);
+    for f in frame_features.iter().flatten() {
+        for fp in f.features.values() {
+            if fp.p2d.x < 0.0 || fp.p2d.y < 0.0 {
+                continue;
+            }
+            let r = fp.p2d.y as usize / cell_size as usize;
+            let c = fp.p2d.x as usize / cell_size as usize;
+            if r < rows && c < cols {
+                counts[(r, c)] += 1;
+            }
+        }
+    }
+    counts
+}
+
+/// Render the coverage at full image resolution, cells without corners are black.
+pub fn coverage_heatmap(
+    counts: &na::DMatrix<u32>,
+    img_w_h: (u32, u32),
+    cell_size: u32,
+) -> RgbImage {
+    let max_count = counts.max().max(1) as f64;
+    RgbImage::from_fn(img_w_h.0, img_w_h.1, |x, y| {
+        let count = counts[((y / cell_size) as usize, (x / cell_size) as usize)];
+        if count == 0 {
+            image::Rgb([0, 0, 0])
+        } else {
+            // log scale so a few dense cells don't hide the sparse ones
+            let v = (count as f64).ln_1p() / max_count.ln_1p();
+            let c = colorous::INFERNO.eval_continuous(0.2 + 0.8 * v);
+            image::Rgb([c.r, c.g, c.b])
+        }
+    })
+}
+
+/// Render the mean reprojection errors of `residual_grid` at full image resolution, cells
+/// without corners are black. The colors are scaled to `max_error` px.
+pub fn residual_heatmap(
+    cells: &[ResidualCell],
+    img_w_h: (u32, u32),
+    cell_size: u32,
+    max_error: f64,
+) -> RgbImage {
+    let cols = img_w_h.0.div_ceil(cell_size) as usize;
+    RgbImage::from_fn(img_w_h.0, img_w_h.1, |x, y| {
+        let cell = &cells[(y / cell_size) as usize * cols + (x / cell_size) as usize];
+        if cell.count == 0 {
+            image::Rgb([0, 0, 0])
+        } else {
+            let c = colorous::TURBO.eval_continuous((cell.mean_error / max_error).clamp(0.0, 1.0));
+            image::Rgb([c.r, c.g, c.b])
+        }
+    })
+}
+
+/// Cells farther than this from the image center, relative to the image corners, are the
+/// periphery.
+pub const PERIPHERY_RADIUS: f64 = 0.7;
+pub const MIN_CELL_FRACTION: f64 = 0.5;
+pub const MIN_RADIAL_COVERAGE: f64 = 0.85;
+pub const MIN_PERIPHERY_FRACTION: f64 = 0.3;
+
+/// How much of the image the detected corners cover, from `corner_coverage`. Radii are from
+/// the image center relative to the image corners.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoverageScore {
+    pub cam_idx: usize,
+    /// Of the cells with corners.
+    pub cell_fraction: f64,
+    /// Largest radius of the cells with corners.
+    pub radial_coverage: f64,
+    /// Of the periphery cells with corners.
+    pub periphery_fraction: f64,
+    /// Outer 3x3 region with the fewest covered periphery cells.
+    pub worst_region: String,
+}
+
+impl CoverageScore {
+    pub fn new(cam_idx: usize, counts: &na::DMatrix<u32>) -> CoverageScore {
+        let (rows, cols) = counts.shape();
+        let center = na::Vector2::new(cols as f64 / 2.0, rows as f64 / 2.0);
+        let max_radius = center.norm();
+        let mut radial_coverage: f64 = 0.0;
+        // (covered cells, cells)
+        let mut periphery = (0, 0);
+        let mut region_periphery = [[(0, 0); 3]; 3];
+        for r in 0..rows {
+            for c in 0..cols {
+                let covered = counts[(r, c)] > 0;
+                let cell_center = na::Vector2::new(c as f64 + 0.5, r as f64 + 0.5);
+                let radius = (cell_center - center).norm() / max_radius;
+                if covered {
+                    radial_coverage = radial_coverage.max(radius);
+                }
+                if radius > PERIPHERY_RADIUS {
+                    for cells in [
+                        &mut region_periphery[r * 3 / rows][c * 3 / cols],
+                        &mut periphery,
+                    ] {
+                        cells.0 += covered as usize;
+                        cells.1 += 1;
+                    }
+                }
+            }
+        }
+        let (wr, wc) = (0..9)
+            .map(|i| (i / 3, i % 3))
+            .filter(|&(r, c)| region_periphery[r][c].1 > 0)
+            .min_by(|&(r0, c0), &(r1, c1)| {
+                let fraction = |(covered, total): (usize, usize)| covered as f64 / total as f64;
+                fraction(region_periphery[r0][c0])
+                    .partial_cmp(&fraction(region_periphery[r1][c1]))
+                    .unwrap()
+            })
+            .unwrap_or((1, 1));
+        CoverageScore {
+            cam_idx,
+            cell_fraction: counts.iter().filter(|&&count| count > 0).count() as f64
+                / counts.len().max(1) as f64,
+            radial_coverage,
+            periphery_fraction: periphery.0 as f64 / periphery.1.max(1) as f64,
+            worst_region: REGION_NAMES[wr][wc].to_string(),
+        }
+    }
+
+    /// What to capture more of, the distortion beyond the covered radius is extrapolated.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.cell_fraction < MIN_CELL_FRACTION {
+            warnings.push(format!(
+                "corners cover only {:.0}% of the image, move the board over the whole image",
+                self.cell_fraction * 100.0
+            ));
+        }
+        if self.radial_coverage < MIN_RADIAL_COVERAGE {
+            warnings.push(format!(
+                "corners reach only {:.0}% of the image radius, the distortion outside is extrapolated, hold the board closer to the image corners",
+                self.radial_coverage * 100.0
+            ));
+        }
+        if self.periphery_fraction < MIN_PERIPHERY_FRACTION {
+            warnings.push(format!(
+                "corners cover only {:.0}% of the image periphery, capture more of the {}",
+                self.periphery_fraction * 100.0,
+                self.worst_region
+            ));
+        }
+        warnings
+    }
+}
+
+const REGION_NAMES: [[&str; 3]; 3] = [
+    ["top-left", "top", "top-right"],
+    ["left", "center", "right"],
+    ["bottom-left", "bottom", "bottom-right"],
+];
+
+/// Where to hold the board next.
+#[derive(Debug, Clone)]
+pub struct CaptureSuggestion {
+    /// (x, y, w, h) in pixels.
+    pub region: (f32, f32, f32, f32),
+    /// Suggested board tilt if there are too few tilted poses.
+    pub tilt_deg: Option<f64>,
+    pub message: String,
+}
+
+/// Suggest the least covered of the 3x3 image regions, and a tilt if less than a third of
+/// `t_cam_boards` are tilted more than 30 degree from the image plane.
+pub fn suggest_next_capture(
+    frame_features: &[Option<FrameFeature>],
+    img_w_h: (u32, u32),
+    t_cam_boards: &[na::Isometry3<f64>],
+) -> CaptureSuggestion {
+    let mut region_counts = [[0; 3]; 3];
+    for f in frame_features.iter().flatten() {
+        for fp in f.features.values() {
+            let r = ((fp.p2d.y * 3.0 / img_w_h.1 as f32) as usize).min(2);
+            let c = ((fp.p2d.x * 3.0 / img_w_h.0 as f32) as usize).min(2);
+            region_counts[r][c] += 1;
+        }
+    }
+    let (r, c) = (0..9)
+        .map(|i| (i / 3, i % 3))
+        .min_by_key(|&(r, c)| region_counts[r][c])
+        .unwrap();
+    let (w, h) = (img_w_h.0 as f32 / 3.0, img_w_h.1 as f32 / 3.0);
+    let tilted = t_cam_boards
+        .iter()
+        .filter(|t| {
+            let normal = t.rotation * na::Vector3::z();
+            normal.z.abs().acos().to_degrees() > 30.0
+        })
+        .count();
+    let tilt_deg = if !t_cam_boards.is_empty() && tilted * 3 < t_cam_boards.len() {
+        Some(45.0)
+    } else {
+        None
+    };
+    let mut message = format!("move the board to the {}", REGION_NAMES[r][c]);
+    if let Some(tilt) = tilt_deg {
+        message = format!("tilt the board ~{}° and {}", tilt, message);
+    }
+    CaptureSuggestion {
+        region: (c as f32 * w, r as f32 * h, w, h),
+        tilt_deg,
+        message,
+    }
+}
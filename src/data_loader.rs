@@ -1,19 +1,26 @@
 use std::collections::HashMap;
-use std::path::Path;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::sync_channel;
 
-use crate::board::{self, Board};
-use crate::detected_points::{FeaturePoint, FrameFeature};
-use crate::visualization::log_image_as_compressed;
-use aprilgrid::detector::TagDetector;
-use glam::Vec2;
+use crate::board;
+use crate::detected_points::{
+    image_to_option_feature_frame, FrameFeature, TagDetection, MIN_CORNERS,
+};
+use crate::events::{Event, FrameReconstruction};
+use crate::frame_filter::FrameFilter;
+use crate::imu::ImuSample;
+use crate::observer::PipelineObserver;
+use crate::rgbd::DepthImage;
 use glob::glob;
-use image::{DynamicImage, ImageReader};
+use image::{DynamicImage, GrayImage, ImageReader};
 use indicatif::ParallelProgressIterator;
+use memmap2::Mmap;
+use nalgebra as na;
 use rayon::prelude::*;
 
-const MIN_CORNERS: usize = 24;
-
-fn path_to_timestamp(path: &Path) -> i64 {
+/// Timestamp [ns] of the image or scan name, 0 if the name is not a number.
+pub fn path_to_timestamp(path: &Path) -> i64 {
     let time_ns: i64 = path
         .file_stem()
         .unwrap()
@@ -24,140 +31,939 @@ fn path_to_timestamp(path: &Path) -> i64 {
     time_ns
 }
 
-fn image_to_option_feature_frame(
-    tag_detector: &TagDetector,
-    img: &DynamicImage,
-    board: &Board,
-    min_corners: usize,
-    time_ns: i64,
-) -> Option<FrameFeature> {
-    let detected_tag = tag_detector.detect(img);
-    let tags_expand_ids: HashMap<u32, FeaturePoint> = detected_tag
-        .iter()
-        .flat_map(|(k, v)| {
-            v.iter()
-                .enumerate()
-                .filter_map(|(i, p)| {
-                    let id = k * 4 + i as u32;
-                    if let Some(p3d) = board.id_to_3d.get(&id) {
-                        let p2d = Vec2::new(p.0, p.1);
-                        Some((id, FeaturePoint { p2d, p3d: *p3d }))
-                    } else {
-                        None
-                    }
-                })
-                .collect::<Vec<_>>()
-        })
+/// Sorted image paths of `cam_idx` in the euroc format, after `start_idx` and `step`.
+pub fn euroc_image_paths(
+    root_folder: &str,
+    cam_idx: usize,
+    start_idx: usize,
+    step: usize,
+) -> Vec<PathBuf> {
+    let img_paths =
+        glob(format!("{}/mav0/cam{}/data/*.png", root_folder, cam_idx).as_str()).expect("failed");
+    let mut sorted_path: Vec<_> = img_paths.map(|p| p.unwrap()).collect();
+    sorted_path.sort();
+    sorted_path
+        .into_iter()
+        .skip(start_idx)
+        .step_by(step)
+        .collect()
+}
+
+/// Sorted image paths of `cam_idx` in the general format, after `start_idx` and `step`.
+pub fn others_image_paths(
+    root_folder: &str,
+    cam_idx: usize,
+    start_idx: usize,
+    step: usize,
+) -> Vec<PathBuf> {
+    let img_paths =
+        glob(format!("{}/**/cam{}/**/*.png", root_folder, cam_idx).as_str()).expect("failed");
+    let mut sorted_path: Vec<_> = img_paths.map(|p| p.unwrap()).collect();
+    sorted_path.sort();
+    sorted_path
+        .into_iter()
+        .skip(start_idx)
+        .step_by(step)
+        .collect()
+}
+
+/// Sorted image paths of every subfolder of `root_folder`, the captures of the patterns of a
+/// board pose for the projector calibration.
+pub fn projector_capture_paths(root_folder: &str) -> Vec<Vec<PathBuf>> {
+    let mut folders: Vec<_> = glob(format!("{}/*/", root_folder).as_str())
+        .expect("failed")
+        .map(|p| p.unwrap())
         .collect();
-    if tags_expand_ids.len() < min_corners {
-        None
-    } else {
-        Some(FrameFeature {
-            time_ns,
-            img_w_h: (img.width(), img.height()),
-            features: tags_expand_ids,
+    folders.sort();
+    folders
+        .iter()
+        .map(|folder| {
+            let img_paths = glob(format!("{}/*.png", folder.display()).as_str()).expect("failed");
+            let mut sorted_path: Vec<_> = img_paths.map(|p| p.unwrap()).collect();
+            sorted_path.sort();
+            sorted_path
         })
+        .collect()
+}
+
+/// Images read ahead of the board detection by default.
+pub const DEFAULT_QUEUE_SIZE: usize = 8;
+
+/// Maps the encoded image into memory instead of reading it to the heap, the pages are only
+/// loaded while decoding.
+fn map_image(path: &Path) -> std::io::Result<Mmap> {
+    let file = std::fs::File::open(path)?;
+    // the dataset isn't modified while calibrating
+    unsafe { Mmap::map(&file) }
+}
+
+fn decode_image(path: &Path, encoded: &[u8]) -> image::ImageResult<DynamicImage> {
+    let encoded = std::io::Cursor::new(encoded);
+    match image::ImageFormat::from_path(path) {
+        Ok(format) => ImageReader::with_format(encoded, format).decode(),
+        Err(_) => ImageReader::new(encoded).with_guessed_format()?.decode(),
     }
 }
 
+/// Maps and decodes an image.
+pub fn load_image(path: &Path) -> image::ImageResult<DynamicImage> {
+    decode_image(path, &map_image(path)?)
+}
+
+/// Detects the board in the `(time_ns, path)` frames of a camera. The images are mapped one
+/// after another on a reader thread and sent through a queue of `queue_size` to the detection
+/// on the global rayon thread pool, so only a few of them are in memory at a time however long
+/// the recording is. A frame that can't be read or decoded is `None`.
+pub fn detect_frames(
+    frames: Vec<(i64, PathBuf)>,
+    cam_idx: usize,
+    tag_detector: &dyn TagDetection,
+    board: &board::Board,
+    queue_size: usize,
+    observer: &dyn PipelineObserver,
+) -> Vec<Option<FrameFeature>> {
+    let frame_num = frames.len() as u64;
+    let (sender, receiver) = sync_channel(queue_size.max(1));
+    let mut time_frame: Vec<_> = std::thread::scope(|s| {
+        s.spawn(move || {
+            for (time_ns, path) in frames {
+                let encoded = map_image(&path);
+                if sender.send((time_ns, path, encoded)).is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+            .into_iter()
+            .par_bridge()
+            .progress_count(frame_num)
+            .map(|(time_ns, path, encoded)| {
+                let img = match encoded
+                    .map_err(image::ImageError::from)
+                    .and_then(|encoded| decode_image(&path, &encoded))
+                {
+                    Ok(img) => img,
+                    Err(e) => {
+                        tracing::warn!("failed to load {}: {}", path.display(), e);
+                        return (time_ns, None);
+                    }
+                };
+                let frame_feature =
+                    image_to_option_feature_frame(tag_detector, &img, board, MIN_CORNERS, time_ns);
+                observer.on_frame_detected(cam_idx, time_ns, &img, frame_feature.as_ref());
+                (time_ns, frame_feature)
+            })
+            .collect()
+    });
+    time_frame.sort_by_key(|a| a.0);
+    time_frame.into_iter().map(|f| f.1).collect()
+}
+
+/// `detect_frames` of the `(time_ns, path)` frames of a sequence kept by `filter`, at their
+/// index in the sequence.
+fn detect_filtered_frames(
+    frames: impl Iterator<Item = (i64, PathBuf)>,
+    filter: &FrameFilter,
+    cam_idx: usize,
+    tag_detector: &dyn TagDetection,
+    board: &board::Board,
+    queue_size: usize,
+    observer: &dyn PipelineObserver,
+) -> Vec<Option<FrameFeature>> {
+    let (indexes, frames): (Vec<_>, Vec<_>) = filter.select(frames).unzip();
+    let features = detect_frames(frames, cam_idx, tag_detector, board, queue_size, observer);
+    let mut frame_features = vec![None; indexes.last().map_or(0, |i| i + 1)];
+    for (i, frame_feature) in indexes.into_iter().zip(features) {
+        frame_features[i] = frame_feature;
+    }
+    frame_features
+}
+
+/// Detects the board in the images of every camera kept by `filter`, streamed through
+/// `detect_frames`.
+#[allow(clippy::too_many_arguments)]
 pub fn load_euroc(
     root_folder: &str,
-    tag_detector: &TagDetector,
+    tag_detector: &dyn TagDetection,
     board: &board::Board,
     start_idx: usize,
     step: usize,
+    filter: &FrameFilter,
     cam_num: usize,
-    recording_option: Option<&rerun::RecordingStream>,
+    queue_size: usize,
+    observer: &dyn PipelineObserver,
 ) -> Vec<Vec<Option<FrameFeature>>> {
     (0..cam_num)
         .map(|cam_idx| {
-            log::trace!("loading cam{}", cam_idx);
-            let img_paths =
-                glob(format!("{}/mav0/cam{}/data/*.png", root_folder, cam_idx).as_str())
-                    .expect("failed");
-            let mut sorted_path: Vec<_> = img_paths.collect();
-            sorted_path.sort_by(|a, b| a.as_ref().unwrap().cmp(b.as_ref().unwrap()));
-            let new_paths: Vec<_> = sorted_path.iter().skip(start_idx).step_by(step).collect();
-            let mut time_frame: Vec<_> = new_paths
-                .iter()
-                .par_bridge()
-                .progress_count(new_paths.len() as u64)
-                .map(|path| {
-                    let path = path.as_ref().unwrap();
-                    let time_ns = path_to_timestamp(path);
-                    let img = ImageReader::open(path).unwrap().decode().unwrap();
-                    if let Some(recording) = recording_option {
-                        recording.set_time_nanos("stable", time_ns);
-                        let topic = format!("/cam{}", cam_idx);
-                        log_image_as_compressed(recording, &topic, &img, image::ImageFormat::Jpeg);
-                    };
-                    (
-                        time_ns,
-                        image_to_option_feature_frame(
-                            tag_detector,
-                            &img,
-                            board,
-                            MIN_CORNERS,
-                            time_ns,
-                        ),
-                    )
-                })
-                .collect();
-            time_frame.sort_by(|a, b| a.0.cmp(&b.0));
-            time_frame.iter().map(|f| f.1.clone()).collect()
+            let _span = tracing::info_span!("detection", cam_idx).entered();
+            tracing::trace!("loading cam{}", cam_idx);
+            let frames = euroc_image_paths(root_folder, cam_idx, start_idx, step)
+                .into_iter()
+                .map(|path| (path_to_timestamp(&path), path));
+            detect_filtered_frames(
+                frames,
+                filter,
+                cam_idx,
+                tag_detector,
+                board,
+                queue_size,
+                observer,
+            )
         })
         .collect()
 }
 
+/// `load_euroc` for the general format, the frame times are 100 ms apart.
+#[allow(clippy::too_many_arguments)]
 pub fn load_others(
     root_folder: &str,
-    tag_detector: &TagDetector,
+    tag_detector: &dyn TagDetection,
     board: &board::Board,
     start_idx: usize,
     step: usize,
+    filter: &FrameFilter,
     cam_num: usize,
-    recording_option: Option<&rerun::RecordingStream>,
+    queue_size: usize,
+    observer: &dyn PipelineObserver,
 ) -> Vec<Vec<Option<FrameFeature>>> {
     (0..cam_num)
         .map(|cam_idx| {
-            let img_paths = glob(format!("{}/**/cam{}/**/*.png", root_folder, cam_idx).as_str())
-                .expect("failed");
-            log::trace!("loading cam{}", cam_idx);
-            let mut sorted_path: Vec<_> = img_paths.collect();
-            sorted_path.sort_by(|a, b| a.as_ref().unwrap().cmp(b.as_ref().unwrap()));
-            let new_paths: Vec<_> = sorted_path
-                .iter()
-                .skip(start_idx)
-                .step_by(step)
+            let _span = tracing::info_span!("detection", cam_idx).entered();
+            tracing::trace!("loading cam{}", cam_idx);
+            let frames = others_image_paths(root_folder, cam_idx, start_idx, step)
+                .into_iter()
                 .enumerate()
-                .collect();
-            let mut time_frame: Vec<_> = new_paths
-                .iter()
+                .map(|(idx, path)| (idx as i64 * 100000000, path));
+            detect_filtered_frames(
+                frames,
+                filter,
+                cam_idx,
+                tag_detector,
+                board,
+                queue_size,
+                observer,
+            )
+        })
+        .collect()
+}
+
+/// `timestamp [s] x y polarity` of an event, polarity 0 or 1.
+fn parse_event(line: &str) -> Option<Event> {
+    let mut values = line.split_whitespace();
+    let time_s: f64 = values.next()?.parse().ok()?;
+    Some(Event {
+        time_ns: (time_s * 1e9).round() as i64,
+        x: values.next()?.parse().ok()?,
+        y: values.next()?.parse().ok()?,
+        polarity: values.next()?.parse::<u8>().ok()? > 0,
+    })
+}
+
+/// Sensor size of the `width height` first line of the events, or of the events if it's missing.
+fn event_resolution(file_path: &str) -> Option<(u32, u32)> {
+    let file = std::fs::File::open(file_path).ok()?;
+    let mut lines = BufReader::new(file).lines().map_while(Result::ok);
+    let first = lines.next()?;
+    let header: Vec<u32> = first
+        .split_whitespace()
+        .map(|v| v.parse())
+        .collect::<Result<_, _>>()
+        .unwrap_or_default();
+    if let [width, height] = header[..] {
+        return Some((width, height));
+    }
+    let (w, h) = std::iter::once(first)
+        .chain(lines)
+        .filter_map(|line| parse_event(&line))
+        .fold((0, 0), |(w, h), e| {
+            (w.max(e.x as u32 + 1), h.max(e.y as u32 + 1))
+        });
+    (w > 0 && h > 0).then_some((w, h))
+}
+
+/// Frames of `window_ns` reconstructed from the events of an e2vid or rpg style text file, an
+/// optional `width height` first line and time sorted `timestamp [s] x y polarity` lines. The
+/// events are streamed and a frame is reconstructed when the iterator reaches its window, so
+/// only the events of one window are in memory at a time. Every frame is timed at the middle of
+/// its window. A `window_ns` below 1 ns gives no frames.
+pub fn load_event_frames<'a>(
+    file_path: &str,
+    window_ns: i64,
+    reconstruction: &'a dyn FrameReconstruction,
+) -> impl Iterator<Item = (i64, GrayImage)> + 'a {
+    let source = if window_ns <= 0 {
+        tracing::warn!("invalid event window of {} ns", window_ns);
+        None
+    } else if let Some((width, height)) = event_resolution(file_path) {
+        let file = std::fs::File::open(file_path).expect("Should have been able to read the file");
+        Some((width, height, file))
+    } else {
+        tracing::warn!("no events in {}", file_path);
+        None
+    };
+    source.into_iter().flat_map(move |(width, height, file)| {
+        let mut events = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| parse_event(&line))
+            .peekable();
+        std::iter::from_fn(move || {
+            let first = events.next()?;
+            let window_idx = first.time_ns.div_euclid(window_ns);
+            let mut window = vec![first];
+            while let Some(event) =
+                events.next_if(|e| e.time_ns.div_euclid(window_ns) == window_idx)
+            {
+                window.push(event);
+            }
+            let frame = reconstruction.reconstruct(&window, width, height);
+            Some((window_idx * window_ns + window_ns / 2, frame))
+        })
+    })
+}
+
+/// Detects the board in the frames reconstructed from `cam{i}/events.txt` of every event camera,
+/// windows of `window_ns` with `reconstruction`, the frames left out by `filter` aren't
+/// reconstructed.
+#[allow(clippy::too_many_arguments)]
+pub fn load_events(
+    root_folder: &str,
+    tag_detector: &dyn TagDetection,
+    board: &board::Board,
+    start_idx: usize,
+    step: usize,
+    filter: &FrameFilter,
+    cam_num: usize,
+    window_ns: i64,
+    reconstruction: &dyn FrameReconstruction,
+    observer: &dyn PipelineObserver,
+) -> Vec<Vec<Option<FrameFeature>>> {
+    (0..cam_num)
+        .map(|cam_idx| {
+            let _span = tracing::info_span!("detection", cam_idx).entered();
+            let path = format!("{}/cam{}/events.txt", root_folder, cam_idx);
+            let frames = load_event_frames(&path, window_ns, reconstruction)
+                .skip(start_idx)
+                .step_by(step);
+            let idx_frame: Vec<_> = filter
+                .select(frames)
                 .par_bridge()
-                .progress_count(new_paths.len() as u64)
-                .map(|(idx, path)| {
-                    let path = path.as_ref().unwrap();
-                    let time_ns = *idx as i64 * 100000000;
-                    let img = ImageReader::open(path).unwrap().decode().unwrap();
-                    if let Some(recording) = recording_option {
-                        recording.set_time_nanos("stable", time_ns);
-                        let topic = format!("/cam{}", cam_idx);
-                        log_image_as_compressed(recording, &topic, &img, image::ImageFormat::Jpeg);
-                    };
-                    (
+                .map(|(i, (time_ns, frame))| {
+                    let img = DynamicImage::ImageLuma8(frame);
+                    let frame_feature = image_to_option_feature_frame(
+                        tag_detector,
+                        &img,
+                        board,
+                        MIN_CORNERS,
                         time_ns,
-                        image_to_option_feature_frame(
-                            tag_detector,
-                            &img,
-                            board,
-                            MIN_CORNERS,
-                            time_ns,
-                        ),
-                    )
+                    );
+                    observer.on_frame_detected(cam_idx, time_ns, &img, frame_feature.as_ref());
+                    (i, frame_feature)
                 })
                 .collect();
-            time_frame.sort_by(|a, b| a.0.cmp(&b.0));
-            time_frame.iter().map(|f| f.1.clone()).collect()
+            tracing::info!("cam{}: {} frames from the events", cam_idx, idx_frame.len());
+            let frame_num = idx_frame.iter().map(|f| f.0 + 1).max().unwrap_or(0);
+            let mut frame_features = vec![None; frame_num];
+            for (i, frame_feature) in idx_frame {
+                frame_features[i] = frame_feature;
+            }
+            frame_features
+        })
+        .collect()
+}
+
+/// Imu csv in the euroc format `timestamp [ns], w_x, w_y, w_z [rad/s], a_x, a_y, a_z [m/s^2]`,
+/// e.g. `mav0/imu0/data.csv`. Lines starting with `#` are skipped.
+pub fn load_imu_csv(file_path: &str) -> Vec<ImuSample> {
+    let contents =
+        std::fs::read_to_string(file_path).expect("Should have been able to read the file");
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let values: Vec<f64> = line
+                .split(',')
+                .map(|v| v.trim().parse())
+                .collect::<Result<_, _>>()
+                .ok()?;
+            if values.len() < 7 {
+                return None;
+            }
+            Some(ImuSample {
+                time_ns: line.split(',').next()?.trim().parse().ok()?,
+                gyro: na::Vector3::new(values[1], values[2], values[3]),
+                accel: na::Vector3::new(values[4], values[5], values[6]),
+            })
+        })
+        .collect()
+}
+
+/// Record header fields `name=value` of a rosbag.
+fn rosbag_fields(mut bytes: &[u8]) -> Option<HashMap<&str, &[u8]>> {
+    let mut fields = HashMap::new();
+    while !bytes.is_empty() {
+        let len = u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+        let field = bytes.get(4..4 + len)?;
+        let eq = field.iter().position(|&b| b == b'=')?;
+        fields.insert(std::str::from_utf8(&field[..eq]).ok()?, &field[eq + 1..]);
+        bytes = &bytes[4 + len..];
+    }
+    Some(fields)
+}
+
+/// Header fields and data of a rosbag record.
+type RosbagRecord<'a> = (HashMap<&'a str, &'a [u8]>, &'a [u8]);
+
+/// Records of a rosbag, the ones of the uncompressed chunks included.
+fn rosbag_records<'a>(mut bytes: &'a [u8], records: &mut Vec<RosbagRecord<'a>>) -> Option<()> {
+    let read_len = |b: &[u8]| Some(u32::from_le_bytes(b.get(..4)?.try_into().ok()?) as usize);
+    while !bytes.is_empty() {
+        let header_len = read_len(bytes)?;
+        let header = rosbag_fields(bytes.get(4..4 + header_len)?)?;
+        let data_len = read_len(&bytes[4 + header_len..])?;
+        let data = bytes.get(8 + header_len..8 + header_len + data_len)?;
+        bytes = &bytes[8 + header_len + data_len..];
+        // chunk
+        if header.get("op") == Some(&&[0x05][..]) {
+            if header.get("compression") != Some(&&b"none"[..]) {
+                tracing::warn!(
+                    "compressed rosbag chunks are not supported, run `rosbag decompress` first"
+                );
+                return None;
+            }
+            rosbag_records(data, records)?;
+        } else {
+            records.push((header, data));
+        }
+    }
+    Some(())
+}
+
+/// `sensor_msgs/Imu` message, the time of its header, the angular velocity and the linear
+/// acceleration after the orientation and the covariances.
+fn decode_ros_imu(data: &[u8]) -> Option<ImuSample> {
+    let u32_at = |i: usize| Some(u32::from_le_bytes(data.get(i..i + 4)?.try_into().ok()?));
+    let f64_at = |i: usize| Some(f64::from_le_bytes(data.get(i..i + 8)?.try_into().ok()?));
+    let (secs, nsecs) = (u32_at(4)? as i64, u32_at(8)? as i64);
+    // seq, stamp, frame_id, orientation and its covariance
+    let angular_velocity = 16 + u32_at(12)? as usize + 13 * 8;
+    let linear_acceleration = angular_velocity + 12 * 8;
+    let vec3 = |i: usize| {
+        Some(na::Vector3::new(
+            f64_at(i)?,
+            f64_at(i + 8)?,
+            f64_at(i + 16)?,
+        ))
+    };
+    Some(ImuSample {
+        time_ns: secs * 1_000_000_000 + nsecs,
+        gyro: vec3(angular_velocity)?,
+        accel: vec3(linear_acceleration)?,
+    })
+}
+
+/// `sensor_msgs/Imu` messages of `topic` of a ROS1 bag, or of its only imu topic, sorted by
+/// time. Compressed chunks aren't supported.
+pub fn load_imu_rosbag(file_path: &str, topic: Option<&str>) -> Vec<ImuSample> {
+    let contents = std::fs::read(file_path).expect("Should have been able to read the file");
+    let Some(body) = contents.strip_prefix(b"#ROSBAG V2.0\n".as_slice()) else {
+        tracing::warn!("{} is not a rosbag v2.0", file_path);
+        return Vec::new();
+    };
+    let mut records = Vec::new();
+    if rosbag_records(body, &mut records).is_none() {
+        tracing::warn!("failed to read {}", file_path);
+        return Vec::new();
+    }
+    let field_str = |fields: &HashMap<&str, &[u8]>, name: &str| {
+        fields
+            .get(name)
+            .and_then(|v| std::str::from_utf8(v).ok())
+            .map(|v| v.to_string())
+    };
+    // connection records, their type is in the fields of the data
+    let mut imu_topics = HashMap::new();
+    for (header, data) in &records {
+        if header.get("op") != Some(&&[0x07][..]) {
+            continue;
+        }
+        let is_imu = rosbag_fields(data)
+            .and_then(|fields| field_str(&fields, "type"))
+            .is_some_and(|t| t == "sensor_msgs/Imu");
+        if let (true, Some(conn), Some(conn_topic)) =
+            (is_imu, header.get("conn"), field_str(header, "topic"))
+        {
+            imu_topics.insert(conn.to_vec(), conn_topic);
+        }
+    }
+    let mut topics: Vec<_> = imu_topics.values().cloned().collect();
+    topics.sort();
+    topics.dedup();
+    let Some(topic) = topic
+        .map(|t| t.to_string())
+        .or_else(|| topics.first().cloned())
+    else {
+        tracing::warn!("no sensor_msgs/Imu topic in {}", file_path);
+        return Vec::new();
+    };
+    if topics.len() > 1 {
+        tracing::info!("imu topics {:?}, use {}", topics, topic);
+    }
+    let mut samples: Vec<_> = records
+        .iter()
+        .filter(|(header, _)| {
+            header.get("op") == Some(&&[0x02][..])
+                && header
+                    .get("conn")
+                    .and_then(|conn| imu_topics.get(*conn))
+                    .is_some_and(|t| *t == topic)
+        })
+        .filter_map(|(_, data)| decode_ros_imu(data))
+        .collect();
+    samples.sort_by_key(|s| s.time_ns);
+    samples
+}
+
+/// Imu samples of a euroc csv or of a ROS1 bag `.bag`.
+pub fn load_imu(file_path: &str, topic: Option<&str>) -> Vec<ImuSample> {
+    if file_path.ends_with(".bag") {
+        load_imu_rosbag(file_path, topic)
+    } else {
+        load_imu_csv(file_path)
+    }
+}
+
+/// `timestamp [ns], value` rows sorted by time. Lines starting with `#` are skipped.
+fn load_timestamped_csv(file_path: &str) -> Vec<(i64, f64)> {
+    let contents =
+        std::fs::read_to_string(file_path).expect("Should have been able to read the file");
+    let mut samples: Vec<_> = contents
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut values = line.split(',').map(|v| v.trim());
+            Some((values.next()?.parse().ok()?, values.next()?.parse().ok()?))
+        })
+        .collect();
+    samples.sort_by_key(|s: &(i64, f64)| s.0);
+    samples
+}
+
+/// Temperature readings `timestamp [ns], temperature [°C]` sorted by time, e.g. of the sensor
+/// board of the camera. Lines starting with `#` are skipped.
+pub fn load_temperature_csv(file_path: &str) -> Vec<(i64, f64)> {
+    load_timestamped_csv(file_path)
+}
+
+/// Exposure times `timestamp [ns], exposure [ms]` of the images sorted by time, the timestamps
+/// are the ones of the image names. Lines starting with `#` are skipped.
+pub fn load_exposure_csv(file_path: &str) -> Vec<(i64, f64)> {
+    load_timestamped_csv(file_path)
+}
+
+/// Robot flange poses `T_base_flange` as `timestamp [ns],tx,ty,tz,qx,qy,qz,qw` rows, the
+/// timestamps are the ones of the images.
+pub fn load_robot_poses_csv(file_path: &str) -> Vec<(i64, na::Isometry3<f64>)> {
+    let contents =
+        std::fs::read_to_string(file_path).expect("Should have been able to read the file");
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let values: Vec<f64> = line
+                .split(',')
+                .map(|v| v.trim().parse())
+                .collect::<Result<_, _>>()
+                .ok()?;
+            if values.len() < 8 {
+                return None;
+            }
+            let rotation = na::UnitQuaternion::from_quaternion(na::Quaternion::new(
+                values[7], values[4], values[5], values[6],
+            ));
+            Some((
+                line.split(',').next()?.trim().parse().ok()?,
+                na::Isometry3::from_parts(
+                    na::Vector3::new(values[1], values[2], values[3]).into(),
+                    rotation,
+                ),
+            ))
+        })
+        .collect()
+}
+
+/// Little endian scalar of `size` bytes, `kind` is `F`loat, `U`nsigned or `I`nteger.
+fn read_scalar(bytes: &[u8], kind: char, size: usize) -> Option<f64> {
+    let b = bytes.get(..size)?;
+    Some(match (kind, size) {
+        ('F', 4) => f32::from_le_bytes(b.try_into().ok()?) as f64,
+        ('F', 8) => f64::from_le_bytes(b.try_into().ok()?),
+        ('U', 1) => b[0] as f64,
+        ('U', 2) => u16::from_le_bytes(b.try_into().ok()?) as f64,
+        ('U', 4) => u32::from_le_bytes(b.try_into().ok()?) as f64,
+        ('I', 1) => b[0] as i8 as f64,
+        ('I', 2) => i16::from_le_bytes(b.try_into().ok()?) as f64,
+        ('I', 4) => i32::from_le_bytes(b.try_into().ok()?) as f64,
+        _ => return None,
+    })
+}
+
+/// Points of fields `(name, kind, size)` stored one after another from `ascii` lines or `binary`
+/// records.
+fn read_points(
+    fields: &[(String, char, usize)],
+    num_points: usize,
+    data: &[u8],
+    binary: bool,
+) -> Option<Vec<na::Vector3<f64>>> {
+    let xyz_idx = ["x", "y", "z"].map(|n| fields.iter().position(|f| f.0 == n));
+    let xyz_idx = [xyz_idx[0]?, xyz_idx[1]?, xyz_idx[2]?];
+    let points: Vec<_> = if binary {
+        let offsets: Vec<_> = fields
+            .iter()
+            .scan(0, |offset, f| {
+                let o = *offset;
+                *offset += f.2;
+                Some(o)
+            })
+            .collect();
+        let record_size: usize = fields.iter().map(|f| f.2).sum();
+        data.chunks_exact(record_size)
+            .take(num_points)
+            .map(|record| {
+                let v = xyz_idx.map(|i| {
+                    read_scalar(&record[offsets[i]..], fields[i].1, fields[i].2).unwrap_or(f64::NAN)
+                });
+                na::Vector3::new(v[0], v[1], v[2])
+            })
+            .collect()
+    } else {
+        std::str::from_utf8(data)
+            .ok()?
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .take(num_points)
+            .filter_map(|l| {
+                let values: Vec<_> = l.split_whitespace().collect();
+                let v = xyz_idx.map(|i| values.get(i).and_then(|v| v.parse().ok()));
+                Some(na::Vector3::new(v[0]?, v[1]?, v[2]?))
+            })
+            .collect()
+    };
+    Some(
+        points
+            .into_iter()
+            .filter(|p| p.iter().all(|v| v.is_finite()))
+            .collect(),
+    )
+}
+
+/// Header lines until `end`, and the bytes after it.
+fn split_header<'a>(bytes: &'a [u8], end: &str) -> Option<(Vec<String>, &'a [u8])> {
+    let mut lines = Vec::new();
+    let mut begin = 0;
+    while let Some(len) = bytes[begin..].iter().position(|&b| b == b'\n') {
+        let line = String::from_utf8_lossy(&bytes[begin..begin + len])
+            .trim()
+            .to_string();
+        begin += len + 1;
+        let is_end = line.starts_with(end);
+        lines.push(line);
+        if is_end {
+            return Some((lines, &bytes[begin..]));
+        }
+    }
+    None
+}
+
+fn load_pcd(bytes: &[u8]) -> Option<Vec<na::Vector3<f64>>> {
+    let (header, data) = split_header(bytes, "DATA")?;
+    let values = |key: &str| -> Vec<String> {
+        header
+            .iter()
+            .find(|l| l.split_whitespace().next() == Some(key))
+            .map(|l| l.split_whitespace().skip(1).map(String::from).collect())
+            .unwrap_or_default()
+    };
+    let names = values("FIELDS");
+    let sizes = values("SIZE");
+    let types = values("TYPE");
+    let counts = values("COUNT");
+    let mut fields = Vec::new();
+    for (i, name) in names.iter().enumerate() {
+        let size = sizes.get(i)?.parse().ok()?;
+        let kind = types.get(i)?.chars().next()?;
+        let count = counts.get(i).and_then(|c| c.parse().ok()).unwrap_or(1);
+        for _ in 0..count {
+            fields.push((name.clone(), kind, size));
+        }
+    }
+    let num_points = values("POINTS").first()?.parse().ok()?;
+    match values("DATA").first()?.as_str() {
+        "ascii" => read_points(&fields, num_points, data, false),
+        "binary" => read_points(&fields, num_points, data, true),
+        format => {
+            tracing::warn!("pcd data {} isn't supported", format);
+            None
+        }
+    }
+}
+
+fn load_ply(bytes: &[u8]) -> Option<Vec<na::Vector3<f64>>> {
+    let (header, data) = split_header(bytes, "end_header")?;
+    let mut binary = false;
+    let mut num_points = None;
+    let mut fields = Vec::new();
+    for line in &header {
+        let words: Vec<_> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", "ascii", ..] => binary = false,
+            ["format", "binary_little_endian", ..] => binary = true,
+            ["format", format, ..] => {
+                tracing::warn!("ply format {} isn't supported", format);
+                return None;
+            }
+            ["element", "vertex", n] => num_points = n.parse().ok(),
+            // only the properties of the vertices, which come first
+            ["element", ..] if num_points.is_some() => break,
+            ["property", kind, name] if num_points.is_some() => {
+                let (kind, size) = match *kind {
+                    "char" | "int8" => ('I', 1),
+                    "uchar" | "uint8" => ('U', 1),
+                    "short" | "int16" => ('I', 2),
+                    "ushort" | "uint16" => ('U', 2),
+                    "int" | "int32" => ('I', 4),
+                    "uint" | "uint32" => ('U', 4),
+                    "float" | "float32" => ('F', 4),
+                    "double" | "float64" => ('F', 8),
+                    _ => return None,
+                };
+                fields.push((name.to_string(), kind, size));
+            }
+            _ => {}
+        }
+    }
+    read_points(&fields, num_points?, data, binary)
+}
+
+/// Points of a `.pcd` (ascii or binary) or `.ply` (ascii or binary little endian) file.
+pub fn load_point_cloud(path: &Path) -> Option<Vec<na::Vector3<f64>>> {
+    let bytes = std::fs::read(path).ok()?;
+    match path.extension()?.to_str()? {
+        "pcd" => load_pcd(&bytes),
+        "ply" => load_ply(&bytes),
+        _ => None,
+    }
+}
+
+/// Point clouds named `{time_stamp}.pcd` or `{time_stamp}.ply` in `folder`, sorted by time.
+pub fn load_lidar_scans(folder: &str) -> Vec<(i64, Vec<na::Vector3<f64>>)> {
+    let mut paths: Vec<_> = ["pcd", "ply"]
+        .iter()
+        .flat_map(|ext| glob(&format!("{}/*.{}", folder, ext)).expect("failed"))
+        .map(|p| p.unwrap())
+        .collect();
+    paths.sort();
+    paths
+        .par_iter()
+        .filter_map(|path| {
+            let Some(points) = load_point_cloud(path) else {
+                tracing::warn!("failed to load {}", path.display());
+                return None;
+            };
+            Some((path_to_timestamp(path), points))
+        })
+        .collect()
+}
+
+/// 16 bit depth images named `{time_stamp}.png` in `folder`, sorted by time.
+pub fn load_depth_images(folder: &str) -> Vec<(i64, DepthImage)> {
+    let mut paths: Vec<_> = glob(&format!("{}/*.png", folder))
+        .expect("failed")
+        .map(|p| p.unwrap())
+        .collect();
+    paths.sort();
+    paths
+        .par_iter()
+        .filter_map(|path| match load_image(path) {
+            Ok(DynamicImage::ImageLuma16(depth)) => Some((path_to_timestamp(path), depth)),
+            Ok(_) => {
+                tracing::warn!("{} is not a 16 bit depth image", path.display());
+                None
+            }
+            Err(e) => {
+                tracing::warn!("failed to load {}: {}", path.display(), e);
+                None
+            }
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, value: &[u8]) -> Vec<u8> {
+        let mut bytes = ((name.len() + 1 + value.len()) as u32)
+            .to_le_bytes()
+            .to_vec();
+        bytes.extend(name.as_bytes());
+        bytes.push(b'=');
+        bytes.extend(value);
+        bytes
+    }
+
+    fn record(fields: &[Vec<u8>], data: &[u8]) -> Vec<u8> {
+        let header = fields.concat();
+        let mut bytes = (header.len() as u32).to_le_bytes().to_vec();
+        bytes.extend(header);
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    fn connection(conn: u32, topic: &str, msg_type: &str) -> Vec<u8> {
+        record(
+            &[
+                field("op", &[0x07]),
+                field("conn", &conn.to_le_bytes()),
+                field("topic", topic.as_bytes()),
+            ],
+            &[
+                field("topic", topic.as_bytes()),
+                field("type", msg_type.as_bytes()),
+            ]
+            .concat(),
+        )
+    }
+
+    fn imu_message(conn: u32, time_ns: u64, gyro: [f64; 3], accel: [f64; 3]) -> Vec<u8> {
+        let mut data = 7u32.to_le_bytes().to_vec();
+        data.extend(((time_ns / 1_000_000_000) as u32).to_le_bytes());
+        data.extend(((time_ns % 1_000_000_000) as u32).to_le_bytes());
+        data.extend(3u32.to_le_bytes());
+        data.extend(b"imu");
+        // orientation and its covariance, then the angular velocity and its covariance
+        let values = [
+            vec![0.0; 13],
+            gyro.to_vec(),
+            vec![0.0; 9],
+            accel.to_vec(),
+            vec![0.0; 9],
+        ];
+        for v in values.concat() {
+            data.extend(v.to_le_bytes());
+        }
+        record(
+            &[
+                field("op", &[0x02]),
+                field("conn", &conn.to_le_bytes()),
+                field("time", &time_ns.to_le_bytes()),
+            ],
+            &data,
+        )
+    }
+
+    /// Counts the images it's run on and detects no tags.
+    struct CountingDetector(std::sync::atomic::AtomicUsize);
+
+    impl TagDetection for CountingDetector {
+        fn detect_tags(&self, _img: &DynamicImage) -> HashMap<u32, [(f32, f32); 4]> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            HashMap::new()
+        }
+    }
+
+    #[test]
+    fn frames_filtered_before_detection() {
+        let folder = std::env::temp_dir().join("ccrs_filter_test/mav0/cam0/data");
+        std::fs::create_dir_all(&folder).unwrap();
+        for i in 0..10 {
+            GrayImage::new(8, 8)
+                .save(folder.join(format!("{}.png", 1000 + i)))
+                .unwrap();
+        }
+        let root = std::env::temp_dir().join("ccrs_filter_test");
+        let detector = CountingDetector(Default::default());
+        let filter = FrameFilter {
+            skip_frames: vec![0..=2],
+            skip_times_ns: vec![1005..=1005],
+            max_frames: Some(3),
+            ..Default::default()
+        };
+        let frames = load_euroc(
+            root.to_str().unwrap(),
+            &detector,
+            &board::Board::from_config(&Default::default()),
+            0,
+            1,
+            &filter,
+            1,
+            DEFAULT_QUEUE_SIZE,
+            &crate::observer::NoopObserver,
+        );
+        // frames 3, 4 and 6 are the first three kept, the ones after aren't detected
+        assert_eq!(detector.0.into_inner(), 3);
+        assert_eq!(frames[0].len(), 7);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn event_frames_by_window() {
+        let path = std::env::temp_dir().join("ccrs_events_test.txt");
+        std::fs::write(
+            &path,
+            "4 3\n0.001 0 0 1\n0.004 1 1 0\n0.012 2 2 1\n0.031 3 2 1\n0.035 1 2 0\n",
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+        let reconstruction = crate::events::EventCountReconstruction::default();
+        let mut frames = load_event_frames(path, 10_000_000, &reconstruction);
+        let (time_ns, frame) = frames.next().unwrap();
+        assert_eq!(time_ns, 5_000_000);
+        assert_eq!(frame.dimensions(), (4, 3));
+        // the empty window of 20 - 30 ms has no frame
+        let times: Vec<_> = frames.map(|(t, _)| t).collect();
+        assert_eq!(times, [15_000_000, 35_000_000]);
+        assert_eq!(load_event_frames(path, 0, &reconstruction).count(), 0);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn imu_from_rosbag() {
+        let chunk = [
+            connection(0, "/imu0", "sensor_msgs/Imu"),
+            connection(1, "/cam0/image_raw", "sensor_msgs/Image"),
+            imu_message(0, 1_500_000_000, [0.1, 0.2, 0.3], [0.0, 0.0, 9.81]),
+            record(
+                &[field("op", &[0x02]), field("conn", &1u32.to_le_bytes())],
+                &[0; 16],
+            ),
+            imu_message(0, 1_000_000_000, [0.4, 0.5, 0.6], [1.0, 2.0, 3.0]),
+        ]
+        .concat();
+        let bag = [
+            b"#ROSBAG V2.0\n".to_vec(),
+            record(&[field("op", &[0x03])], &[b' '; 8]),
+            record(
+                &[
+                    field("op", &[0x05]),
+                    field("compression", b"none"),
+                    field("size", &(chunk.len() as u32).to_le_bytes()),
+                ],
+                &chunk,
+            ),
+        ]
+        .concat();
+        let path = std::env::temp_dir().join("ccrs_imu_test.bag");
+        std::fs::write(&path, bag).unwrap();
+        let samples = load_imu(path.to_str().unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].time_ns, 1_000_000_000);
+        assert_eq!(samples[0].gyro, na::Vector3::new(0.4, 0.5, 0.6));
+        assert_eq!(samples[0].accel, na::Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(samples[1].time_ns, 1_500_000_000);
+        assert_eq!(samples[1].accel, na::Vector3::new(0.0, 0.0, 9.81));
+    }
+}
@@ -1,15 +1,204 @@
-use glam;
+use aprilgrid::detector::TagDetector;
+use glam::{self, Vec2};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Debug, Clone, Copy)]
+use crate::blur::{set_corner_blurs, CornerBlur};
+use crate::board::Board;
+
+pub const MIN_CORNERS: usize = 24;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct FeaturePoint {
     pub p2d: glam::Vec2,
     pub p3d: glam::Vec3,
+    /// Capture time of the row of the corner of a rolling shutter camera, `None` for a global
+    /// shutter where all corners are captured at the time of the frame.
+    #[serde(default)]
+    pub time_ns: Option<i64>,
+    /// Motion blur around the corner, `None` if it's sharp.
+    #[serde(default)]
+    pub blur: Option<CornerBlur>,
+}
+
+impl FeaturePoint {
+    /// Capture time of the corner in the frame taken at `frame_time_ns`.
+    pub fn capture_time_ns(&self, frame_time_ns: i64) -> i64 {
+        self.time_ns.unwrap_or(frame_time_ns)
+    }
+}
+
+/// Ratio of clipped pixels inside the bounding box of the detected corners.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ExposureStats {
+    pub overexposed_ratio: f32,
+    pub underexposed_ratio: f32,
 }
 
-#[derive(Debug, Clone)]
+impl ExposureStats {
+    pub fn is_clipped(&self, max_clipped_ratio: f32) -> bool {
+        self.overexposed_ratio > max_clipped_ratio || self.underexposed_ratio > max_clipped_ratio
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FrameFeature {
     pub time_ns: i64,
     pub img_w_h: (u32, u32),
     pub features: HashMap<u32, FeaturePoint>,
+    #[serde(default)]
+    pub exposure: Option<ExposureStats>,
+}
+
+impl FrameFeature {
+    /// Sets the capture time of every corner from its row, the rows of a rolling shutter camera
+    /// are exposed `line_delay_ns` apart and the time of the frame is the one of the middle row.
+    pub fn set_rolling_shutter_times(&mut self, line_delay_ns: f64) {
+        let middle_row = self.img_w_h.1 as f64 / 2.0;
+        for fp in self.features.values_mut() {
+            let dt = (fp.p2d.y as f64 - middle_row) * line_delay_ns;
+            fp.time_ns = Some(self.time_ns + dt.round() as i64);
+        }
+    }
+}
+
+/// Saturated tags bias the corner positions, count the pixels at 255 and 0 around the board.
+pub fn board_exposure(img: &DynamicImage, features: &HashMap<u32, FeaturePoint>) -> ExposureStats {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+    for fp in features.values() {
+        min_x = min_x.min(fp.p2d.x);
+        min_y = min_y.min(fp.p2d.y);
+        max_x = max_x.max(fp.p2d.x);
+        max_y = max_y.max(fp.p2d.y);
+    }
+    let luma = img.to_luma8();
+    let x0 = min_x.max(0.0) as u32;
+    let y0 = min_y.max(0.0) as u32;
+    let x1 = (max_x.max(0.0) as u32).min(luma.width().saturating_sub(1));
+    let y1 = (max_y.max(0.0) as u32).min(luma.height().saturating_sub(1));
+    let (mut over, mut under, mut total) = (0, 0, 0);
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            let v = luma.get_pixel(x, y)[0];
+            if v == 255 {
+                over += 1;
+            } else if v == 0 {
+                under += 1;
+            }
+            total += 1;
+        }
+    }
+    let total = total.max(1) as f32;
+    ExposureStats {
+        overexposed_ratio: over as f32 / total,
+        underexposed_ratio: under as f32 / total,
+    }
+}
+
+/// Drop frames with more than `max_clipped_ratio` clipped pixels around the board,
+/// or only warn about them if `keep`. Returns the number of clipped frames.
+pub fn filter_clipped_frames(
+    frame_features: &mut [Option<FrameFeature>],
+    max_clipped_ratio: f32,
+    keep: bool,
+) -> usize {
+    let mut clipped = 0;
+    for f in frame_features.iter_mut() {
+        let Some(exposure) = f.as_ref().and_then(|f| f.exposure) else {
+            continue;
+        };
+        if exposure.is_clipped(max_clipped_ratio) {
+            clipped += 1;
+            tracing::warn!(
+                time_ns = f.as_ref().unwrap().time_ns,
+                exposure.overexposed_ratio,
+                exposure.underexposed_ratio,
+                "board is clipped{}",
+                if keep { "" } else { ", frame excluded" }
+            );
+            if !keep {
+                *f = None;
+            }
+        }
+    }
+    clipped
+}
+
+/// Detects the corners of the tags of a board, `TagDetector` on the cpu or
+/// `gpu::GpuTagDetector`.
+pub trait TagDetection: Sync {
+    /// Corners of the detected tags by tag id.
+    fn detect_tags(&self, img: &DynamicImage) -> HashMap<u32, [(f32, f32); 4]>;
+
+    /// Whether the motion blur of every detected corner is estimated, see
+    /// `blur::CornerBlurDetector`.
+    fn corner_blurs(&self) -> bool {
+        false
+    }
+}
+
+impl<D: TagDetection + ?Sized> TagDetection for Box<D> {
+    fn detect_tags(&self, img: &DynamicImage) -> HashMap<u32, [(f32, f32); 4]> {
+        (**self).detect_tags(img)
+    }
+
+    fn corner_blurs(&self) -> bool {
+        (**self).corner_blurs()
+    }
+}
+
+impl TagDetection for TagDetector {
+    fn detect_tags(&self, img: &DynamicImage) -> HashMap<u32, [(f32, f32); 4]> {
+        self.detect(img)
+    }
+}
+
+pub fn image_to_option_feature_frame(
+    tag_detector: &dyn TagDetection,
+    img: &DynamicImage,
+    board: &Board,
+    min_corners: usize,
+    time_ns: i64,
+) -> Option<FrameFeature> {
+    let detected_tag = tag_detector.detect_tags(img);
+    let tags_expand_ids: HashMap<u32, FeaturePoint> = detected_tag
+        .iter()
+        .flat_map(|(k, v)| {
+            v.iter()
+                .enumerate()
+                .filter_map(|(i, p)| {
+                    let id = k * 4 + i as u32;
+                    if let Some(p3d) = board.id_to_3d.get(&id) {
+                        let p2d = Vec2::new(p.0, p.1);
+                        Some((
+                            id,
+                            FeaturePoint {
+                                p2d,
+                                p3d: *p3d,
+                                time_ns: None,
+                                blur: None,
+                            },
+                        ))
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    if tags_expand_ids.len() < min_corners {
+        None
+    } else {
+        let mut frame_feature = FrameFeature {
+            time_ns,
+            img_w_h: (img.width(), img.height()),
+            exposure: Some(board_exposure(img, &tags_expand_ids)),
+            features: tags_expand_ids,
+        };
+        if tag_detector.corner_blurs() {
+            set_corner_blurs(&img.to_luma8(), &mut frame_feature);
+        }
+        Some(frame_feature)
+    }
 }
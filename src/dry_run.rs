@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use camera_intrinsic_model::*;
+use serde::{Deserialize, Serialize};
+
+use crate::detected_points::FrameFeature;
+use crate::init_diagnostic::InitDiagnostic;
+use crate::observability::{Observability, ParamCorrelation};
+use crate::subsample::frame_descriptor;
+use crate::types::CalibParams;
+use crate::util::{convert_model, intrinsics_covariance, solve_board_pose};
+
+/// Frames with a board tilted more than this in degrees constrain the focal.
+const MIN_TILT_DEG: f64 = 15.0;
+/// Below this fraction of the images with detections the board is often out of view or too
+/// small.
+const MIN_DETECTION_RATE: f64 = 0.5;
+/// Above this fraction of blurred corners the board moves too fast for the exposure.
+const MAX_BLURRED_FRACTION: f64 = 0.2;
+
+/// Quality of the frames of a camera from the detections alone, before the calibration spends
+/// minutes on them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatasetQuality {
+    pub cam_idx: usize,
+    pub num_images: usize,
+    pub num_detected_frames: usize,
+    pub detection_rate: f64,
+    pub mean_corners: f64,
+    /// Frames with the board tilted more than `MIN_TILT_DEG`.
+    pub num_tilted_frames: usize,
+    pub max_tilt_deg: f64,
+    /// Of the 4 tilt axes, horizontal, vertical and the two diagonals, the ones the tilted
+    /// frames are tilted about.
+    pub num_tilt_axes: usize,
+    /// Smallest and largest size of the board relative to the image diagonal.
+    pub board_size_range: (f64, f64),
+    pub blurred_corner_fraction: f64,
+    /// Mean length in px of the blur of the blurred corners.
+    pub mean_blur_px: f64,
+    /// Predicted from the initialization, `None` if it failed.
+    pub observability: Option<Observability>,
+}
+
+impl DatasetQuality {
+    pub fn new(cam_idx: usize, frames: &[Option<FrameFeature>]) -> DatasetQuality {
+        let detected: Vec<_> = frames.iter().flatten().collect();
+        let num_corners: usize = detected.iter().map(|f| f.features.len()).sum();
+        let descriptors: Vec<_> = detected
+            .iter()
+            .filter_map(|f| frame_descriptor(f))
+            .collect();
+        // the descriptor tilt is normalized by 90 degrees and its direction angle is doubled
+        let tilts: Vec<_> = descriptors
+            .iter()
+            .map(|d| (d[3].hypot(d[4]) * 90.0, d[4].atan2(d[3])))
+            .collect();
+        let mut tilt_axes = [false; 4];
+        for &(tilt_deg, angle) in &tilts {
+            if tilt_deg > MIN_TILT_DEG {
+                let axis = (angle.rem_euclid(std::f64::consts::TAU) / std::f64::consts::FRAC_PI_2
+                    + 0.5) as usize
+                    % 4;
+                tilt_axes[axis] = true;
+            }
+        }
+        let blurs: Vec<_> = detected
+            .iter()
+            .flat_map(|f| f.features.values())
+            .filter_map(|fp| fp.blur.as_ref())
+            .collect();
+        DatasetQuality {
+            cam_idx,
+            num_images: frames.len(),
+            num_detected_frames: detected.len(),
+            detection_rate: detected.len() as f64 / frames.len().max(1) as f64,
+            mean_corners: num_corners as f64 / detected.len().max(1) as f64,
+            num_tilted_frames: tilts.iter().filter(|t| t.0 > MIN_TILT_DEG).count(),
+            max_tilt_deg: tilts.iter().map(|t| t.0).fold(0.0, f64::max),
+            num_tilt_axes: tilt_axes.iter().filter(|&&a| a).count(),
+            board_size_range: descriptors
+                .iter()
+                .map(|d| (d[2], d[2]))
+                .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1)))
+                .unwrap_or_default(),
+            blurred_corner_fraction: blurs.len() as f64 / num_corners.max(1) as f64,
+            mean_blur_px: blurs.iter().map(|b| b.length as f64).sum::<f64>()
+                / blurs.len().max(1) as f64,
+            observability: None,
+        }
+    }
+
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.detection_rate < MIN_DETECTION_RATE {
+            warnings.push(format!(
+                "the board is detected in only {:.0}% of the images, keep it in view and large enough",
+                self.detection_rate * 100.0
+            ));
+        }
+        if self.num_tilted_frames < 3 {
+            warnings.push(format!(
+                "only {} frames with the board tilted more than {} degrees, the focal is poorly constrained by frontal views",
+                self.num_tilted_frames, MIN_TILT_DEG
+            ));
+        } else if self.num_tilt_axes < 2 {
+            warnings.push(
+                "the board is always tilted about the same axis, tilt it about another one"
+                    .to_string(),
+            );
+        }
+        if self.blurred_corner_fraction > MAX_BLURRED_FRACTION {
+            warnings.push(format!(
+                "{:.0}% of the corners are blurred by {:.1} px on average, move the board slower or shorten the exposure",
+                self.blurred_corner_fraction * 100.0,
+                self.mean_blur_px
+            ));
+        }
+        warnings
+    }
+}
+
+/// Correlation of the intrinsics of `target_model` predicted without the calibration, from the
+/// initialized UCM converted to it and the board poses of all frames solved with it.
+pub fn predict_param_correlation(
+    cam_idx: usize,
+    frames: &[Option<FrameFeature>],
+    target_model: &GenericModel<f64>,
+    calib_params: &CalibParams,
+) -> Option<ParamCorrelation> {
+    let diagnostic = InitDiagnostic::new(cam_idx, frames, calib_params.fixed_focal)?;
+    let Some(ucm) = diagnostic.initialized() else {
+        tracing::warn!("cam{} didn't initialize", cam_idx);
+        return None;
+    };
+    let mut model = *target_model;
+    model.set_w_h(ucm.width().round() as u32, ucm.height().round() as u32);
+    convert_model(ucm, &mut model, calib_params.disabled_distortion_num);
+    let rtvecs: HashMap<_, _> = frames
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| Some((i, solve_board_pose(&model, f.as_ref()?)?)))
+        .collect();
+    let xy_same_focal = calib_params.one_focal || calib_params.fixed_focal.is_some();
+    let covariance = intrinsics_covariance(&model, &rtvecs, frames, xy_same_focal)?;
+    Some(ParamCorrelation::new(
+        cam_idx,
+        &model,
+        &covariance,
+        xy_same_focal,
+    ))
+}
@@ -0,0 +1,59 @@
+use image::GrayImage;
+
+/// Share of the pixels with the most events clipped to white, the hot pixels of the sensor fire
+/// all the time and would darken everything else.
+const COUNT_CLIP: f64 = 0.01;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    pub time_ns: i64,
+    pub x: u16,
+    pub y: u16,
+    pub polarity: bool,
+}
+
+/// Reconstructs an intensity frame from the events of a time window, the hook for e2vid-style
+/// reconstruction networks.
+pub trait FrameReconstruction: Sync {
+    fn reconstruct(&self, events: &[Event], width: u32, height: u32) -> GrayImage;
+}
+
+/// Counts the events of every pixel in the window. With a blinking board, e.g. a board shown on
+/// a flashing monitor, the blinking squares fire and the tags don't, so the counts look like
+/// the board with the usual polarity. The counts are blurred by `blur_sigma` px against the
+/// noise of the few events of a pixel.
+pub struct EventCountReconstruction {
+    pub blur_sigma: f32,
+}
+
+impl Default for EventCountReconstruction {
+    fn default() -> Self {
+        EventCountReconstruction { blur_sigma: 1.0 }
+    }
+}
+
+impl FrameReconstruction for EventCountReconstruction {
+    fn reconstruct(&self, events: &[Event], width: u32, height: u32) -> GrayImage {
+        let mut counts = image::ImageBuffer::<image::Luma<f32>, _>::new(width, height);
+        for e in events {
+            if (e.x as u32) < width && (e.y as u32) < height {
+                counts.get_pixel_mut(e.x as u32, e.y as u32)[0] += 1.0;
+            }
+        }
+        if self.blur_sigma > 0.0 {
+            counts = imageproc::filter::gaussian_blur_f32(&counts, self.blur_sigma);
+        }
+        let mut sorted = counts.as_raw().clone();
+        let clip_idx = ((sorted.len() as f64 * (1.0 - COUNT_CLIP)) as usize).min(sorted.len() - 1);
+        let max = sorted
+            .select_nth_unstable_by(clip_idx, |a, b| a.total_cmp(b))
+            .1
+            .max(f32::EPSILON);
+        let pixels = counts
+            .as_raw()
+            .iter()
+            .map(|&c| (c / max * 255.0).min(255.0) as u8)
+            .collect();
+        GrayImage::from_raw(width, height, pixels).unwrap()
+    }
+}
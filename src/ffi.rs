@@ -0,0 +1,307 @@
+//! Minimal C ABI so the calibrator can be embedded in C/C++ pipelines.
+//!
+//! Build the shared library with
+//! `cargo rustc --release --lib --features ffi --crate-type cdylib`
+//! and include `include/ccrs.h`.
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, CStr};
+use std::str::FromStr;
+
+use camera_intrinsic_model::GenericModel;
+use nalgebra as na;
+
+use crate::detected_points::{FeaturePoint, FrameFeature, MIN_CORNERS};
+use crate::observer::NoopObserver;
+use crate::types::{CalibParams, RvecTvec};
+use crate::util::{init_and_calibrate_one_camera_with_trials, intrinsics_covariance};
+
+pub const CCRS_OK: c_int = 0;
+pub const CCRS_ERROR_NULL_POINTER: c_int = -1;
+pub const CCRS_ERROR_INVALID_ARGUMENT: c_int = -2;
+pub const CCRS_ERROR_NOT_ENOUGH_CORNERS: c_int = -3;
+pub const CCRS_ERROR_CALIBRATION_FAILED: c_int = -4;
+pub const CCRS_ERROR_NOT_CALIBRATED: c_int = -5;
+pub const CCRS_ERROR_BUFFER_TOO_SMALL: c_int = -6;
+
+pub struct CalibSession {
+    model: GenericModel<f64>,
+    frames: Vec<Option<FrameFeature>>,
+    result: Option<(GenericModel<f64>, HashMap<usize, RvecTvec>)>,
+    covariance: Option<na::DMatrix<f64>>,
+}
+
+/// Create a session for the given model name ("ucm", "eucm", "kb4", "opencv5", "eucmt", "ftheta").
+/// Returns null if the model name is unknown.
+///
+/// # Safety
+/// `model_name` must be a valid null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ccrs_session_create(model_name: *const c_char) -> *mut CalibSession {
+    if model_name.is_null() {
+        return std::ptr::null_mut();
+    }
+    let Ok(name) = CStr::from_ptr(model_name).to_str() else {
+        return std::ptr::null_mut();
+    };
+    let Ok(model) = GenericModel::from_str(name) else {
+        return std::ptr::null_mut();
+    };
+    Box::into_raw(Box::new(CalibSession {
+        model,
+        frames: Vec::new(),
+        result: None,
+        covariance: None,
+    }))
+}
+
+/// # Safety
+/// `session` must come from `ccrs_session_create` and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn ccrs_session_destroy(session: *mut CalibSession) {
+    if !session.is_null() {
+        drop(Box::from_raw(session));
+    }
+}
+
+/// Add the detected corners of one frame. `p2ds` holds `num * 2` pixel coordinates
+/// and `p3ds` holds `num * 3` board coordinates in meters.
+///
+/// # Safety
+/// All arrays must hold at least the number of elements described above.
+#[no_mangle]
+pub unsafe extern "C" fn ccrs_session_add_frame(
+    session: *mut CalibSession,
+    time_ns: i64,
+    width: u32,
+    height: u32,
+    ids: *const u32,
+    p2ds: *const f32,
+    p3ds: *const f32,
+    num: usize,
+) -> c_int {
+    let Some(session) = session.as_mut() else {
+        return CCRS_ERROR_NULL_POINTER;
+    };
+    if ids.is_null() || p2ds.is_null() || p3ds.is_null() {
+        return CCRS_ERROR_NULL_POINTER;
+    }
+    if width == 0 || height == 0 {
+        return CCRS_ERROR_INVALID_ARGUMENT;
+    }
+    let ids = std::slice::from_raw_parts(ids, num);
+    let p2ds = std::slice::from_raw_parts(p2ds, num * 2);
+    let p3ds = std::slice::from_raw_parts(p3ds, num * 3);
+    let features: HashMap<u32, FeaturePoint> = ids
+        .iter()
+        .enumerate()
+        .map(|(i, &id)| {
+            let p2d = glam::Vec2::new(p2ds[i * 2], p2ds[i * 2 + 1]);
+            let p3d = glam::Vec3::new(p3ds[i * 3], p3ds[i * 3 + 1], p3ds[i * 3 + 2]);
+            (
+                id,
+                FeaturePoint {
+                    p2d,
+                    p3d,
+                    time_ns: None,
+                    blur: None,
+                },
+            )
+        })
+        .collect();
+    session.result = None;
+    session.covariance = None;
+    if features.len() < MIN_CORNERS {
+        session.frames.push(None);
+        return CCRS_ERROR_NOT_ENOUGH_CORNERS;
+    }
+    session.frames.push(Some(FrameFeature {
+        time_ns,
+        img_w_h: (width, height),
+        features,
+        exposure: None,
+    }));
+    CCRS_OK
+}
+
+/// Run the calibration on all added frames. A non positive `fixed_focal` lets the focal be optimized.
+///
+/// # Safety
+/// `session` must come from `ccrs_session_create`.
+#[no_mangle]
+pub unsafe extern "C" fn ccrs_session_calibrate(
+    session: *mut CalibSession,
+    one_focal: bool,
+    disabled_distortion_num: usize,
+    fixed_focal: f64,
+) -> c_int {
+    let Some(session) = session.as_mut() else {
+        return CCRS_ERROR_NULL_POINTER;
+    };
+    let calib_params = CalibParams {
+        fixed_focal: if fixed_focal > 0.0 {
+            Some(fixed_focal)
+        } else {
+            None
+        },
+        disabled_distortion_num,
+        one_focal,
+        plateau: None,
+    };
+    let cams_detected_feature_frames = vec![session.frames.clone()];
+    let model = session.model;
+    let calibrated = std::panic::catch_unwind(|| {
+        init_and_calibrate_one_camera_with_trials(
+            0,
+            &cams_detected_feature_frames,
+            &model,
+            &NoopObserver,
+            &calib_params,
+            3,
+        )
+    });
+    match calibrated {
+        Ok(Some((intrinsic, rtvec_map))) => {
+            let xy_same_focal = calib_params.one_focal || calib_params.fixed_focal.is_some();
+            session.covariance = std::panic::catch_unwind(|| {
+                intrinsics_covariance(&intrinsic, &rtvec_map, &session.frames, xy_same_focal)
+            })
+            .unwrap_or(None);
+            session.result = Some((intrinsic, rtvec_map));
+            CCRS_OK
+        }
+        _ => CCRS_ERROR_CALIBRATION_FAILED,
+    }
+}
+
+/// Number of intrinsic parameters of the session model.
+///
+/// # Safety
+/// `session` must come from `ccrs_session_create`.
+#[no_mangle]
+pub unsafe extern "C" fn ccrs_session_params_len(session: *const CalibSession) -> usize {
+    session.as_ref().map_or(0, |s| s.model.params().len())
+}
+
+/// Dimension of the parameter covariance, 0 before a successful calibration. It's `params_len`
+/// in the layout of the params, also with `one_focal` or a fixed focal, where fy is fx and its
+/// row and column repeat the ones of fx.
+///
+/// # Safety
+/// `session` must come from `ccrs_session_create`.
+#[no_mangle]
+pub unsafe extern "C" fn ccrs_session_covariance_dim(session: *const CalibSession) -> usize {
+    session
+        .as_ref()
+        .and_then(|s| s.covariance.as_ref())
+        .map_or(0, |c| c.nrows())
+}
+
+/// Copy the calibrated parameters into `out`, which holds `len` elements.
+///
+/// # Safety
+/// `out` must be valid for `len` writes.
+#[no_mangle]
+pub unsafe extern "C" fn ccrs_session_get_params(
+    session: *const CalibSession,
+    out: *mut f64,
+    len: usize,
+) -> c_int {
+    let (Some(session), false) = (session.as_ref(), out.is_null()) else {
+        return CCRS_ERROR_NULL_POINTER;
+    };
+    let Some((intrinsic, _)) = &session.result else {
+        return CCRS_ERROR_NOT_CALIBRATED;
+    };
+    let params = intrinsic.params();
+    if len < params.len() {
+        return CCRS_ERROR_BUFFER_TOO_SMALL;
+    }
+    std::slice::from_raw_parts_mut(out, params.len()).copy_from_slice(params.as_slice());
+    CCRS_OK
+}
+
+/// Copy the row major parameter covariance into `out`, which holds `len` elements
+/// (at least `covariance_dim * covariance_dim`, see `ccrs_session_covariance_dim`).
+///
+/// # Safety
+/// `out` must be valid for `len` writes.
+#[no_mangle]
+pub unsafe extern "C" fn ccrs_session_get_covariance(
+    session: *const CalibSession,
+    out: *mut f64,
+    len: usize,
+) -> c_int {
+    let (Some(session), false) = (session.as_ref(), out.is_null()) else {
+        return CCRS_ERROR_NULL_POINTER;
+    };
+    let Some(covariance) = &session.covariance else {
+        return CCRS_ERROR_NOT_CALIBRATED;
+    };
+    let n = covariance.nrows();
+    if len < n * n {
+        return CCRS_ERROR_BUFFER_TOO_SMALL;
+    }
+    let out = std::slice::from_raw_parts_mut(out, n * n);
+    for r in 0..n {
+        for c in 0..n {
+            out[r * n + c] = covariance[(r, c)];
+        }
+    }
+    CCRS_OK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{eucm, frames};
+
+    #[test]
+    fn covariance_dim_with_one_focal() {
+        let (frames, _) = frames(&eucm(), 8);
+        unsafe {
+            let session = ccrs_session_create(c"eucm".as_ptr());
+            for frame_feature in frames.iter().flatten() {
+                let ids: Vec<u32> = frame_feature.features.keys().copied().collect();
+                let p2ds: Vec<f32> = ids
+                    .iter()
+                    .flat_map(|id| frame_feature.features[id].p2d.to_array())
+                    .collect();
+                let p3ds: Vec<f32> = ids
+                    .iter()
+                    .flat_map(|id| frame_feature.features[id].p3d.to_array())
+                    .collect();
+                let (w, h) = frame_feature.img_w_h;
+                let added = ccrs_session_add_frame(
+                    session,
+                    frame_feature.time_ns,
+                    w,
+                    h,
+                    ids.as_ptr(),
+                    p2ds.as_ptr(),
+                    p3ds.as_ptr(),
+                    ids.len(),
+                );
+                assert_eq!(added, CCRS_OK);
+            }
+            assert_eq!(ccrs_session_covariance_dim(session), 0);
+            assert_eq!(ccrs_session_calibrate(session, true, 0, 0.0), CCRS_OK);
+            let params_len = ccrs_session_params_len(session);
+            let dim = ccrs_session_covariance_dim(session);
+            assert_eq!(dim, params_len);
+            let mut covariance = vec![0.0; dim * dim];
+            assert_eq!(
+                ccrs_session_get_covariance(session, covariance.as_mut_ptr(), dim * dim - 1),
+                CCRS_ERROR_BUFFER_TOO_SMALL
+            );
+            assert_eq!(
+                ccrs_session_get_covariance(session, covariance.as_mut_ptr(), dim * dim),
+                CCRS_OK
+            );
+            assert!((0..dim).all(|i| covariance[i * dim + i] > 0.0));
+            // fy is fx
+            assert_eq!(covariance[0], covariance[dim + 1]);
+            assert_eq!(covariance[1], covariance[dim + 1]);
+            ccrs_session_destroy(session);
+        }
+    }
+}
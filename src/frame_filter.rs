@@ -0,0 +1,136 @@
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::detected_points::FrameFeature;
+
+/// Parses `a-b` or `a` into an inclusive range.
+pub fn parse_range<T: FromStr + Copy + PartialOrd>(s: &str) -> Result<RangeInclusive<T>, String> {
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<T>()
+            .map_err(|_| format!("invalid value '{}' in '{}'", v, s))
+    };
+    let (start, end) = match s.split_once('-') {
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => (parse(s)?, parse(s)?),
+    };
+    if start > end {
+        return Err(format!("'{}' ends before it starts", s));
+    }
+    Ok(start..=end)
+}
+
+/// Frames to leave out of the calibration by their index in the sequence, the one of
+/// `detections.json` and of the reports, or by their time stamp. A frame is kept if it's in the
+/// used ranges, all frames without any, and in none of the skipped ranges. At most `max_frames`
+/// of the kept frames are used.
+#[derive(Debug, Clone, Default)]
+pub struct FrameFilter {
+    pub skip_frames: Vec<RangeInclusive<usize>>,
+    pub use_frames: Vec<RangeInclusive<usize>>,
+    pub skip_times_ns: Vec<RangeInclusive<i64>>,
+    pub use_times_ns: Vec<RangeInclusive<i64>>,
+    pub max_frames: Option<usize>,
+}
+
+impl FrameFilter {
+    pub fn is_empty(&self) -> bool {
+        self.skip_frames.is_empty()
+            && self.use_frames.is_empty()
+            && self.skip_times_ns.is_empty()
+            && self.use_times_ns.is_empty()
+            && self.max_frames.is_none()
+    }
+
+    pub fn keeps(&self, frame_idx: usize, time_ns: i64) -> bool {
+        self.keeps_index(frame_idx)
+            && (self.use_times_ns.is_empty() || in_any(&self.use_times_ns, time_ns))
+            && !in_any(&self.skip_times_ns, time_ns)
+    }
+
+    fn keeps_index(&self, frame_idx: usize) -> bool {
+        (self.use_frames.is_empty() || in_any(&self.use_frames, frame_idx))
+            && !in_any(&self.skip_frames, frame_idx)
+    }
+
+    /// The `(frame_idx, frame)` of the kept `(time_ns, _)` frames of a sequence, so the others
+    /// are never loaded or detected.
+    pub fn select<'a, T, I>(&'a self, frames: I) -> impl Iterator<Item = (usize, (i64, T))> + 'a
+    where
+        I: Iterator<Item = (i64, T)> + 'a,
+    {
+        frames
+            .enumerate()
+            .filter(|(i, (time_ns, _))| self.keeps(*i, *time_ns))
+            .take(self.max_frames.unwrap_or(usize::MAX))
+    }
+
+    /// Removes the frames that aren't kept from every camera, the indexes of the others don't
+    /// change, and cuts the frames after the last used one. A frame without detections has no
+    /// time stamp and is kept by its index. Returns the number of frames with detections removed.
+    pub fn apply(&self, cams_frames: &mut [Vec<Option<FrameFeature>>]) -> usize {
+        let mut removed = 0;
+        for frames in cams_frames.iter_mut() {
+            let mut kept = 0;
+            let mut end = frames.len();
+            for (i, frame) in frames.iter_mut().enumerate() {
+                if self.max_frames.is_some_and(|max_frames| kept >= max_frames) {
+                    end = i;
+                    break;
+                }
+                let keeps = match frame {
+                    Some(f) => self.keeps(i, f.time_ns),
+                    None => self.keeps_index(i),
+                };
+                if keeps {
+                    kept += 1;
+                } else if frame.take().is_some() {
+                    removed += 1;
+                }
+            }
+            removed += frames[end..].iter().filter(|f| f.is_some()).count();
+            frames.truncate(end);
+        }
+        removed
+    }
+}
+
+fn in_any<T: PartialOrd>(ranges: &[RangeInclusive<T>], v: T) -> bool {
+    ranges.iter().any(|r| r.contains(&v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(time_ns: i64) -> Option<FrameFeature> {
+        Some(FrameFeature {
+            time_ns,
+            img_w_h: (640, 480),
+            features: Default::default(),
+            exposure: None,
+        })
+    }
+
+    #[test]
+    fn max_frames_counted_after_filter() {
+        let filter = FrameFilter {
+            use_frames: vec![4..=9],
+            skip_times_ns: vec![60..=60],
+            max_frames: Some(3),
+            ..Default::default()
+        };
+        let frames = (0..10).map(|i| (i as i64 * 10, ()));
+        let kept: Vec<_> = filter.select(frames).map(|(i, _)| i).collect();
+        assert_eq!(kept, [4, 5, 7]);
+
+        let mut cams_frames = vec![(0..10).map(|i| frame(i * 10)).collect::<Vec<_>>()];
+        cams_frames[0][5] = None;
+        let removed = filter.apply(&mut cams_frames);
+        // the frame without detections at 5 is kept by its index
+        assert_eq!(cams_frames[0].len(), 8);
+        let kept: Vec<_> = (0..8).filter(|&i| cams_frames[0][i].is_some()).collect();
+        assert_eq!(kept, [4, 7]);
+        assert_eq!(removed, 7);
+    }
+}
@@ -0,0 +1,608 @@
+use std::collections::{HashMap, HashSet};
+
+use aprilgrid::detector::{
+    best_tag, bit_code, decode_positions, rochade_refine, try_find_best_board, DetectorParams,
+};
+use aprilgrid::image_util::{self, GrayImagef32};
+use aprilgrid::saddle::Saddle;
+use aprilgrid::{tag_families, TagFamily};
+use image::{DynamicImage, GrayImage};
+use wgpu::util::DeviceExt;
+
+use crate::detected_points::TagDetection;
+use crate::remap::{Border, Interpolation};
+use crate::undistort::Undistorter;
+
+const WORKGROUP_SIZE: u32 = 16;
+
+const REMAP_SHADER: &str = r#"
+struct Params {
+    src_w: u32,
+    src_h: u32,
+    dst_w: u32,
+    dst_h: u32,
+    nearest: u32,
+    border_mode: u32,
+    border_value: u32,
+    _pad: u32,
+}
+
+@group(0) @binding(0) var<storage, read> src: array<u32>;
+@group(0) @binding(1) var<storage, read> xmap: array<f32>;
+@group(0) @binding(2) var<storage, read> ymap: array<f32>;
+@group(0) @binding(3) var<storage, read_write> dst: array<u32>;
+@group(0) @binding(4) var<uniform> params: Params;
+
+fn border_index(i: i32, n: i32) -> i32 {
+    if (i >= 0 && i < n) {
+        return i;
+    }
+    if (params.border_mode == 1u) {
+        return clamp(i, 0, n - 1);
+    }
+    if (params.border_mode == 2u) {
+        if (n == 1) {
+            return 0;
+        }
+        let period = 2 * (n - 1);
+        let j = ((i % period) + period) % period;
+        return select(period - j, j, j < n);
+    }
+    return -1;
+}
+
+fn fetch(x: i32, y: i32) -> vec4<f32> {
+    let c = border_index(x, i32(params.src_w));
+    let r = border_index(y, i32(params.src_h));
+    if (c < 0 || r < 0) {
+        return unpack4x8unorm(params.border_value);
+    }
+    return unpack4x8unorm(src[u32(r) * params.src_w + u32(c)]);
+}
+
+fn is_nan(v: f32) -> bool {
+    return (bitcast<u32>(v) & 0x7fffffffu) > 0x7f800000u;
+}
+
+@compute @workgroup_size(16, 16)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.dst_w || id.y >= params.dst_h) {
+        return;
+    }
+    let idx = id.y * params.dst_w + id.x;
+    let sx = xmap[idx];
+    let sy = ymap[idx];
+    var color: vec4<f32>;
+    if (is_nan(sx) || is_nan(sy)) {
+        color = unpack4x8unorm(params.border_value);
+    } else if (params.nearest == 1u) {
+        color = fetch(i32(round(sx)), i32(round(sy)));
+    } else {
+        let x0 = floor(sx);
+        let y0 = floor(sy);
+        let dx = sx - x0;
+        let dy = sy - y0;
+        let c = i32(x0);
+        let r = i32(y0);
+        color = mix(
+            mix(fetch(c, r), fetch(c + 1, r), dx),
+            mix(fetch(c, r + 1), fetch(c + 1, r + 1), dx),
+            dy,
+        );
+    }
+    dst[idx] = pack4x8unorm(color);
+}
+"#;
+
+/// Remaps 8 bit images with a wgpu compute shader, the maps are uploaded once.
+/// Other image types go through the cpu path of the `Undistorter`.
+pub struct GpuUndistorter<'a> {
+    undistorter: &'a Undistorter,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    xmap: wgpu::Buffer,
+    ymap: wgpu::Buffer,
+}
+
+impl<'a> GpuUndistorter<'a> {
+    /// `None` without a gpu adapter or for interpolations other than nearest and bilinear,
+    /// use `Undistorter::undistort` then.
+    pub fn new(undistorter: &'a Undistorter) -> Option<GpuUndistorter<'a>> {
+        if !matches!(
+            undistorter.interpolation,
+            Interpolation::Nearest | Interpolation::Bilinear
+        ) {
+            return None;
+        }
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter =
+            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                required_features: wgpu::Features::empty(),
+                required_limits: adapter.limits(),
+            },
+            None,
+        ))
+        .ok()?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("remap"),
+            source: wgpu::ShaderSource::Wgsl(REMAP_SHADER.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("remap"),
+            layout: None,
+            module: &module,
+            entry_point: "main",
+            compilation_options: Default::default(),
+        });
+        let (xmap, ymap) = undistorter.maps();
+        let storage = |map: &nalgebra::DMatrix<f32>| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(map.as_slice()),
+                usage: wgpu::BufferUsages::STORAGE,
+            })
+        };
+        let (xmap, ymap) = (storage(xmap), storage(ymap));
+        Some(GpuUndistorter {
+            undistorter,
+            device,
+            queue,
+            pipeline,
+            xmap,
+            ymap,
+        })
+    }
+
+    pub fn undistort(&self, img: &DynamicImage) -> DynamicImage {
+        match img {
+            DynamicImage::ImageLuma8(_) => {
+                DynamicImage::ImageLuma8(self.remap_rgba8(img).to_luma8())
+            }
+            DynamicImage::ImageLumaA8(_) => {
+                DynamicImage::ImageLumaA8(self.remap_rgba8(img).to_luma_alpha8())
+            }
+            DynamicImage::ImageRgb8(_) => DynamicImage::ImageRgb8(self.remap_rgba8(img).to_rgb8()),
+            DynamicImage::ImageRgba8(_) => self.remap_rgba8(img),
+            _ => self.undistorter.undistort(img),
+        }
+    }
+
+    fn remap_rgba8(&self, img: &DynamicImage) -> DynamicImage {
+        let src = img.to_rgba8();
+        let (dst_w, dst_h) = self.undistorter.w_h;
+        let (border_mode, border_value) = match self.undistorter.border {
+            Border::Constant(v) => (0u32, u32::from_ne_bytes([v.clamp(0.0, 255.0) as u8; 4])),
+            Border::Replicate => (1, 0),
+            Border::Reflect => (2, 0),
+        };
+        let params = [
+            src.width(),
+            src.height(),
+            dst_w,
+            dst_h,
+            (self.undistorter.interpolation == Interpolation::Nearest) as u32,
+            border_mode,
+            border_value,
+            0,
+        ];
+        let src_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: src.as_raw(),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+        let params_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&params),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+        let dst_size = (dst_w * dst_h * 4) as u64;
+        let dst_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: dst_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let read_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: dst_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                &src_buffer,
+                &self.xmap,
+                &self.ymap,
+                &dst_buffer,
+                &params_buffer,
+            ]
+            .iter()
+            .enumerate()
+            .map(|(i, buffer)| wgpu::BindGroupEntry {
+                binding: i as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect::<Vec<_>>(),
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(
+                dst_w.div_ceil(WORKGROUP_SIZE),
+                dst_h.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+        encoder.copy_buffer_to_buffer(&dst_buffer, 0, &read_buffer, 0, dst_size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = read_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.device.poll(wgpu::Maintain::Wait);
+        let pixels = slice.get_mapped_range().to_vec();
+        read_buffer.unmap();
+        DynamicImage::ImageRgba8(image::RgbaImage::from_raw(dst_w, dst_h, pixels).unwrap())
+    }
+}
+
+const SADDLE_SHADER: &str = r#"
+struct Params {
+    w: u32,
+    h: u32,
+    radius: u32,
+    _pad: u32,
+}
+
+@group(0) @binding(0) var<storage, read> src: array<f32>;
+@group(0) @binding(1) var<storage, read_write> dst: array<f32>;
+@group(0) @binding(2) var<storage, read> weights: array<f32>;
+@group(0) @binding(3) var<uniform> params: Params;
+
+fn at(x: i32, y: i32) -> f32 {
+    let c = clamp(x, 0, i32(params.w) - 1);
+    let r = clamp(y, 0, i32(params.h) - 1);
+    return src[u32(r) * params.w + u32(c)];
+}
+
+@compute @workgroup_size(16, 16)
+fn blur_x(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.w || id.y >= params.h) {
+        return;
+    }
+    var acc = 0.0;
+    for (var i = 0u; i <= 2u * params.radius; i++) {
+        acc += weights[i] * at(i32(id.x + i) - i32(params.radius), i32(id.y));
+    }
+    dst[id.y * params.w + id.x] = acc;
+}
+
+@compute @workgroup_size(16, 16)
+fn blur_y(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.w || id.y >= params.h) {
+        return;
+    }
+    var acc = 0.0;
+    for (var i = 0u; i <= 2u * params.radius; i++) {
+        acc += weights[i] * at(i32(id.x), i32(id.y + i) - i32(params.radius));
+    }
+    dst[id.y * params.w + id.x] = acc;
+}
+
+@compute @workgroup_size(16, 16)
+fn hessian(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.w || id.y >= params.h) {
+        return;
+    }
+    let idx = id.y * params.w + id.x;
+    if (id.x == 0u || id.y == 0u || id.x == params.w - 1u || id.y == params.h - 1u) {
+        dst[idx] = 0.0;
+        return;
+    }
+    let x = i32(id.x);
+    let y = i32(id.y);
+    let lxx = at(x - 1, y) - 2.0 * at(x, y) + at(x + 1, y);
+    let lyy = at(x, y - 1) - 2.0 * at(x, y) + at(x, y + 1);
+    let lxy = (at(x + 1, y - 1) - at(x - 1, y - 1) + at(x - 1, y + 1) - at(x + 1, y + 1)) / 4.0;
+    dst[idx] = lxx * lyy - lxy * lxy;
+}
+"#;
+
+/// Sigma of the blur before the saddle response, same as `TagDetector`.
+const SADDLE_BLUR_SIGMA: f32 = 1.5;
+
+/// Same as the private tables of `TagDetector::new`.
+fn tag_family_bits(tag_family: &TagFamily) -> (u8, u8, u8, &'static [u64]) {
+    match tag_family {
+        TagFamily::T16H5 => (4, 2, 1, &tag_families::T16H5),
+        TagFamily::T25H7 => (5, 2, 2, &tag_families::T25H7),
+        TagFamily::T25H9 => (5, 2, 2, &tag_families::T25H9),
+        TagFamily::T36H11 => (6, 2, 3, &tag_families::T36H11),
+        TagFamily::T36H11B1 => (6, 1, 3, &tag_families::T36H11),
+    }
+}
+
+/// Normalized gaussian weights of radius `ceil(2 sigma)`, like `imageproc`'s blur.
+fn gaussian_weights(sigma: f32) -> Vec<f32> {
+    let radius = (2.0 * sigma).ceil() as i32;
+    let weights: Vec<f32> = (-radius..=radius)
+        .map(|x| (-(x * x) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = weights.iter().sum();
+    weights.iter().map(|w| w / sum).collect()
+}
+
+/// `TagDetector` with the blur and the saddle response of every pixel in wgpu compute shaders,
+/// the saddle refinement and the board decoding stay on the cpu. The filters are most of the
+/// detection time of large images, e.g. live 4k streams.
+pub struct GpuTagDetector {
+    edge: u8,
+    border: u8,
+    hamming_distance: u8,
+    code_list: &'static [u64],
+    detector_params: DetectorParams,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    bind_group_layout: wgpu::BindGroupLayout,
+    blur_x: wgpu::ComputePipeline,
+    blur_y: wgpu::ComputePipeline,
+    hessian: wgpu::ComputePipeline,
+    weights: wgpu::Buffer,
+    radius: u32,
+}
+
+impl GpuTagDetector {
+    /// `None` without a gpu adapter, use `TagDetector` then.
+    pub fn new(tag_family: &TagFamily) -> Option<GpuTagDetector> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter =
+            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                required_features: wgpu::Features::empty(),
+                required_limits: adapter.limits(),
+            },
+            None,
+        ))
+        .ok()?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("saddle"),
+            source: wgpu::ShaderSource::Wgsl(SADDLE_SHADER.into()),
+        });
+        let storage = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("saddle"),
+            entries: &[
+                storage(0, true),
+                storage(1, false),
+                storage(2, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("saddle"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&layout),
+                module: &module,
+                entry_point,
+                compilation_options: Default::default(),
+            })
+        };
+        let (blur_x, blur_y, hessian) =
+            (pipeline("blur_x"), pipeline("blur_y"), pipeline("hessian"));
+        let weights = gaussian_weights(SADDLE_BLUR_SIGMA);
+        let radius = (weights.len() / 2) as u32;
+        let weights = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&weights),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let (edge, border, hamming_distance, code_list) = tag_family_bits(tag_family);
+        Some(GpuTagDetector {
+            edge,
+            border,
+            hamming_distance,
+            code_list,
+            detector_params: DetectorParams::default_params(),
+            device,
+            queue,
+            bind_group_layout,
+            blur_x,
+            blur_y,
+            hessian,
+            weights,
+            radius,
+        })
+    }
+
+    /// Blurred image and its saddle response `lxx * lyy - lxy^2`.
+    fn filter(&self, img: &DynamicImage) -> (GrayImagef32, GrayImagef32) {
+        let luma = img.to_luma32f();
+        let (w, h) = luma.dimensions();
+        let size = (w * h * 4) as u64;
+        let params = [w, h, self.radius, 0];
+        let src = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(luma.as_raw()),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+        let params = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&params),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+        let buffer = |usage: wgpu::BufferUsages| {
+            self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let output = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC;
+        let read = wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST;
+        let (tmp, blur, response) = (buffer(output), buffer(output), buffer(output));
+        let (blur_read, response_read) = (buffer(read), buffer(read));
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        for (pipeline, input, output) in [
+            (&self.blur_x, &src, &tmp),
+            (&self.blur_y, &tmp, &blur),
+            (&self.hessian, &blur, &response),
+        ] {
+            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &self.bind_group_layout,
+                entries: &[input, output, &self.weights, &params]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, buffer)| wgpu::BindGroupEntry {
+                        binding: i as u32,
+                        resource: buffer.as_entire_binding(),
+                    })
+                    .collect::<Vec<_>>(),
+            });
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(w.div_ceil(WORKGROUP_SIZE), h.div_ceil(WORKGROUP_SIZE), 1);
+        }
+        encoder.copy_buffer_to_buffer(&blur, 0, &blur_read, 0, size);
+        encoder.copy_buffer_to_buffer(&response, 0, &response_read, 0, size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let (blur_slice, response_slice) = (blur_read.slice(..), response_read.slice(..));
+        blur_slice.map_async(wgpu::MapMode::Read, |_| {});
+        response_slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.device.poll(wgpu::Maintain::Wait);
+        let to_image = |slice: wgpu::BufferSlice| {
+            let pixels = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+            GrayImagef32::from_raw(w, h, pixels).unwrap()
+        };
+        let images = (to_image(blur_slice), to_image(response_slice));
+        blur_read.unmap();
+        response_read.unmap();
+        images
+    }
+
+    /// Same as `TagDetector::refined_saddle_points` with the filters on the gpu.
+    pub fn refined_saddle_points(&self, img: &DynamicImage) -> Vec<Saddle> {
+        let (blur, mut response) = self.filter(img);
+        let min_response = response.iter().fold(f32::MAX, |acc, e| acc.min(*e));
+        let mut centers = Vec::new();
+        for r in 1..response.height() - 1 {
+            for c in 1..response.width() - 1 {
+                let mut cluster = Vec::new();
+                image_util::pixel_bfs(&mut response, &mut cluster, c, r, min_response * 0.05);
+                if !cluster.is_empty() {
+                    let (sx, sy) = cluster.iter().fold((0.0, 0.0), |(ax, ay), (ex, ey)| {
+                        (ax + *ex as f32, ay + *ey as f32)
+                    });
+                    centers.push((sx / cluster.len() as f32, sy / cluster.len() as f32));
+                }
+            }
+        }
+        let saddle_points = rochade_refine(&blur, &centers, 2);
+        let smax = saddle_points.iter().fold(f32::MIN, |acc, s| acc.max(s.k)) / 10.0;
+        saddle_points
+            .into_iter()
+            .filter(|s| {
+                s.k >= smax
+                    && s.phi >= self.detector_params.min_saddle_angle
+                    && s.phi <= self.detector_params.max_saddle_angle
+            })
+            .collect()
+    }
+
+    fn try_decode_quad(
+        &self,
+        img_grey: &GrayImage,
+        quad_points: &[(f32, f32)],
+    ) -> Option<(u32, [(f32, f32); 4])> {
+        let homo_points = decode_positions(
+            img_grey.width(),
+            img_grey.height(),
+            quad_points,
+            self.border,
+            self.edge,
+            0.5,
+        )?;
+        let bits = bit_code(img_grey, &homo_points, 10, 3)?;
+        let (tag_id, rotation) = best_tag(bits, self.hamming_distance, self.code_list, self.edge)?;
+        let mut corners = quad_points.to_owned();
+        corners.rotate_left(rotation);
+        corners.reverse();
+        Some((tag_id as u32, corners.try_into().ok()?))
+    }
+}
+
+impl TagDetection for GpuTagDetector {
+    fn detect_tags(&self, img: &DynamicImage) -> HashMap<u32, [(f32, f32); 4]> {
+        let mut detected_tags = HashMap::new();
+        let img_grey = img.to_luma8();
+        let mut refined = self.refined_saddle_points(img);
+        for _ in 0..self.detector_params.max_num_of_boards {
+            let Some(best_board_indexes) = try_find_best_board(&refined) else {
+                continue;
+            };
+            let mut indexes_to_remove = HashSet::new();
+            for quad_indexes in best_board_indexes {
+                let quad_points: Vec<_> = quad_indexes.iter().map(|i| refined[*i].p).collect();
+                if let Some((tag_id, corners)) = self.try_decode_quad(&img_grey, &quad_points) {
+                    detected_tags.insert(tag_id, corners);
+                    indexes_to_remove.extend(quad_indexes);
+                }
+            }
+            refined = refined
+                .into_iter()
+                .enumerate()
+                .filter_map(|(i, s)| (!indexes_to_remove.contains(&i)).then_some(s))
+                .collect();
+        }
+        detected_tags
+    }
+}
@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+use tiny_solver::loss_functions::HuberLoss;
+use tiny_solver::Optimizer;
+
+use crate::optimization::factors::HandEyeFactor;
+use crate::types::{DVecVec3, RvecTvec, ToRvecTvec};
+
+/// Pairs of frames rotating less than this don't constrain the rotation axis.
+const MIN_PAIR_ROTATION: f64 = 1.0 * std::f64::consts::PI / 180.0;
+/// Frames with a residual larger than this times the median are rejected.
+const OUTLIER_RATIO: f64 = 3.0;
+const MIN_FRAMES: usize = 3;
+
+/// Result of `calibrate_hand_eye` for a camera mounted on the robot flange.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandEyeCalibration {
+    /// Transforms points from the camera to the flange.
+    pub t_flange_cam: RvecTvec,
+    /// Transforms points from the board to the robot base.
+    pub t_base_board: RvecTvec,
+    /// Covariance of `[rvec, tvec]` of `t_flange_cam`.
+    pub covariance: [[f64; 6]; 6],
+    /// rad
+    pub rotation_rms: f64,
+    /// m
+    pub translation_rms: f64,
+    /// Frame indexes rejected as outliers.
+    pub outliers: Vec<usize>,
+}
+
+struct HandEyeFrame {
+    idx: usize,
+    t_base_flange: na::Isometry3<f64>,
+    t_cam_board: na::Isometry3<f64>,
+}
+
+fn median(values: &[f64]) -> f64 {
+    let mut values = values.to_vec();
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    values[values.len() / 2]
+}
+
+/// Closed form `AX = XB` of all frame pairs, Park and Martin.
+fn solve_ax_xb(frames: &[HandEyeFrame]) -> Option<na::Isometry3<f64>> {
+    let mut pairs = Vec::new();
+    for (i, fi) in frames.iter().enumerate() {
+        for fj in &frames[i + 1..] {
+            // flange and camera motions from i to j
+            let a = fj.t_base_flange.inverse() * fi.t_base_flange;
+            let b = fj.t_cam_board * fi.t_cam_board.inverse();
+            if a.rotation.angle() > MIN_PAIR_ROTATION && b.rotation.angle() > MIN_PAIR_ROTATION {
+                pairs.push((a, b));
+            }
+        }
+    }
+    if pairs.len() < 2 {
+        return None;
+    }
+    // the rotation axes satisfy alpha = r_x * beta
+    let m = pairs
+        .iter()
+        .map(|(a, b)| a.rotation.scaled_axis() * b.rotation.scaled_axis().transpose())
+        .sum::<na::Matrix3<f64>>();
+    let svd = m.svd(true, true);
+    let (u, v_t) = (svd.u?, svd.v_t?);
+    let d = (u * v_t).determinant().signum();
+    let r_x = na::Rotation3::from_matrix_unchecked(
+        u * na::Matrix3::from_diagonal(&na::Vector3::new(1.0, 1.0, d)) * v_t,
+    );
+    // (r_a - I) * t_x = r_x * t_b - t_a
+    let mut lhs = na::DMatrix::<f64>::zeros(pairs.len() * 3, 3);
+    let mut rhs = na::DVector::<f64>::zeros(pairs.len() * 3);
+    for (i, (a, b)) in pairs.iter().enumerate() {
+        lhs.fixed_view_mut::<3, 3>(i * 3, 0)
+            .copy_from(&(a.rotation.to_rotation_matrix().matrix() - na::Matrix3::identity()));
+        rhs.fixed_rows_mut::<3>(i * 3)
+            .copy_from(&(r_x * b.translation.vector - a.translation.vector));
+    }
+    let t_x = lhs.svd(true, true).solve(&rhs, 1e-12).ok()?;
+    Some(na::Isometry3::from_parts(
+        na::Vector3::new(t_x[0], t_x[1], t_x[2]).into(),
+        r_x.into(),
+    ))
+}
+
+/// Rotation and translation error of each frame.
+fn frame_residuals(
+    frames: &[HandEyeFrame],
+    t_flange_cam: &na::Isometry3<f64>,
+    t_base_board: &na::Isometry3<f64>,
+) -> Vec<(f64, f64)> {
+    frames
+        .iter()
+        .map(|f| {
+            let t_diff = t_base_board.inverse() * f.t_base_flange * t_flange_cam * f.t_cam_board;
+            (t_diff.rotation.angle(), t_diff.translation.vector.norm())
+        })
+        .collect()
+}
+
+/// The board pose in the robot base frame of the frame agreeing with the most others.
+fn init_base_board(
+    frames: &[HandEyeFrame],
+    t_flange_cam: &na::Isometry3<f64>,
+) -> na::Isometry3<f64> {
+    let candidates: Vec<_> = frames
+        .iter()
+        .map(|f| f.t_base_flange * t_flange_cam * f.t_cam_board)
+        .collect();
+    *candidates
+        .iter()
+        .min_by(|a, b| {
+            let cost = |c: &na::Isometry3<f64>| {
+                candidates
+                    .iter()
+                    .map(|o| (o.translation.vector - c.translation.vector).norm())
+                    .sum::<f64>()
+            };
+            cost(a).partial_cmp(&cost(b)).unwrap()
+        })
+        .unwrap()
+}
+
+fn build_problem(
+    frames: &[HandEyeFrame],
+    rotation_weight: f64,
+    translation_weight: f64,
+    robust: bool,
+) -> tiny_solver::Problem {
+    let mut problem = tiny_solver::Problem::new();
+    for f in frames {
+        let cost = HandEyeFactor::new(
+            &f.t_base_flange,
+            &f.t_cam_board,
+            rotation_weight,
+            translation_weight,
+        );
+        problem.add_residual_block(
+            6,
+            &[
+                ("flange_cam_rvec", 3),
+                ("flange_cam_tvec", 3),
+                ("base_board_rvec", 3),
+                ("base_board_tvec", 3),
+            ],
+            Box::new(cost),
+            if robust {
+                Some(Box::new(HuberLoss::new(1.0)))
+            } else {
+                None
+            },
+        );
+    }
+    problem
+}
+
+fn to_values(
+    t_flange_cam: &na::Isometry3<f64>,
+    t_base_board: &na::Isometry3<f64>,
+) -> HashMap<String, na::DVector<f64>> {
+    let x = t_flange_cam.to_rvec_tvec();
+    let y = t_base_board.to_rvec_tvec();
+    HashMap::from([
+        ("flange_cam_rvec".to_string(), x.na_rvec()),
+        ("flange_cam_tvec".to_string(), x.na_tvec()),
+        ("base_board_rvec".to_string(), y.na_rvec()),
+        ("base_board_tvec".to_string(), y.na_tvec()),
+    ])
+}
+
+fn from_values(values: &HashMap<String, na::DVector<f64>>, name: &str) -> na::Isometry3<f64> {
+    na::Isometry3::new(
+        values[&format!("{}_tvec", name)].to_vec3(),
+        values[&format!("{}_rvec", name)].to_vec3(),
+    )
+}
+
+/// Per component standard deviations of the rotation and translation errors.
+fn residual_sigmas(residuals: &[(f64, f64)]) -> (f64, f64) {
+    let n = residuals.len() as f64 * 3.0;
+    let rotation = (residuals.iter().map(|r| r.0 * r.0).sum::<f64>() / n).sqrt();
+    let translation = (residuals.iter().map(|r| r.1 * r.1).sum::<f64>() / n).sqrt();
+    // exact data
+    (rotation.max(1e-12), translation.max(1e-12))
+}
+
+/// Estimates the camera to flange transform of a camera mounted on a robot arm from the flange
+/// poses `T_base_flange` and the board poses `T_cam_board` of `calib_camera`, both by frame
+/// index, with the board fixed in the robot base frame.
+/// `AX = XB` of all frame pairs initializes the transform, which is refined together with the
+/// board pose in the base frame. Frames with errors far above the median are rejected and the
+/// covariance comes from the jacobian weighted by the errors of the remaining frames.
+pub fn calibrate_hand_eye(
+    robot_poses: &HashMap<usize, na::Isometry3<f64>>,
+    board_poses: &HashMap<usize, RvecTvec>,
+) -> Option<HandEyeCalibration> {
+    let mut frames: Vec<_> = board_poses
+        .iter()
+        .filter_map(|(&idx, rtvec)| {
+            Some(HandEyeFrame {
+                idx,
+                t_base_flange: *robot_poses.get(&idx)?,
+                t_cam_board: rtvec.to_na_isometry3(),
+            })
+        })
+        .collect();
+    frames.sort_by_key(|f| f.idx);
+    let mut outliers = Vec::new();
+    loop {
+        if frames.len() < MIN_FRAMES {
+            tracing::warn!("not enough robot poses with board poses for hand-eye calibration");
+            return None;
+        }
+        let Some(t_flange_cam) = solve_ax_xb(&frames) else {
+            tracing::warn!("robot poses don't rotate enough for hand-eye calibration");
+            return None;
+        };
+        let t_base_board = init_base_board(&frames, &t_flange_cam);
+        let (rotation_sigma, translation_sigma) =
+            residual_sigmas(&frame_residuals(&frames, &t_flange_cam, &t_base_board));
+        let problem = build_problem(&frames, 1.0 / rotation_sigma, 1.0 / translation_sigma, true);
+        let values = tiny_solver::GaussNewtonOptimizer {}.optimize(
+            &problem,
+            &to_values(&t_flange_cam, &t_base_board),
+            None,
+        )?;
+        let t_flange_cam = from_values(&values, "flange_cam");
+        let t_base_board = from_values(&values, "base_board");
+        let residuals = frame_residuals(&frames, &t_flange_cam, &t_base_board);
+        let rotation_threshold =
+            median(&residuals.iter().map(|r| r.0).collect::<Vec<_>>()) * OUTLIER_RATIO;
+        let translation_threshold =
+            median(&residuals.iter().map(|r| r.1).collect::<Vec<_>>()) * OUTLIER_RATIO;
+        let (inliers, rejected): (Vec<_>, Vec<_>) =
+            frames.into_iter().zip(&residuals).partition(|(_, r)| {
+                r.0 <= rotation_threshold.max(1e-9) && r.1 <= translation_threshold.max(1e-9)
+            });
+        frames = inliers.into_iter().map(|(f, _)| f).collect();
+        if !rejected.is_empty() {
+            for (f, r) in rejected {
+                tracing::warn!(
+                    "frame {} rejected for hand-eye calibration, error {:.4} rad {:.4} m",
+                    f.idx,
+                    r.0,
+                    r.1
+                );
+                outliers.push(f.idx);
+            }
+            continue;
+        }
+
+        let residuals = frame_residuals(&frames, &t_flange_cam, &t_base_board);
+        let (rotation_sigma, translation_sigma) = residual_sigmas(&residuals);
+        let problem = build_problem(
+            &frames,
+            1.0 / rotation_sigma,
+            1.0 / translation_sigma,
+            false,
+        );
+        let (residual, jac) = problem.compute_residual_and_jacobian(&values);
+        let jac = jac.to_dense();
+        let jac = na::DMatrix::<f64>::from_fn(jac.nrows(), jac.ncols(), |r, c| *jac.get(r, c));
+        let dof = (frames.len() * 6).checked_sub(12).filter(|&d| d > 0)?;
+        let scale = residual.norm_l2().powi(2) / dof as f64;
+        let full_covariance = (jac.transpose() * &jac).try_inverse()? * scale;
+        let columns = [
+            problem.variable_name_to_col_idx_dict["flange_cam_rvec"],
+            problem.variable_name_to_col_idx_dict["flange_cam_tvec"],
+        ];
+        let index = |i: usize| columns[i / 3] + i % 3;
+        let mut covariance = [[0.0; 6]; 6];
+        for (r, row) in covariance.iter_mut().enumerate() {
+            for (c, v) in row.iter_mut().enumerate() {
+                *v = full_covariance[(index(r), index(c))];
+            }
+        }
+        let rms = |values: Vec<f64>| {
+            (values.iter().map(|v| v * v).sum::<f64>() / values.len() as f64).sqrt()
+        };
+        outliers.sort();
+        return Some(HandEyeCalibration {
+            t_flange_cam: t_flange_cam.to_rvec_tvec(),
+            t_base_board: t_base_board.to_rvec_tvec(),
+            covariance,
+            rotation_rms: rms(residuals.iter().map(|r| r.0).collect()),
+            translation_rms: rms(residuals.iter().map(|r| r.1).collect()),
+            outliers,
+        });
+    }
+}
+
+/// `T_cam_i_cam_0` of cameras without a shared field of view, from the board poses of a rig
+/// moving around static boards, like `init_camera_extrinsic`. Every camera sees its own board
+/// at the same time as cam0 sees another one, and the motions of the cameras are related by
+/// `AX = XB` like a camera on a robot flange.
+pub fn calibrate_non_overlapping_rig(
+    cam_rtvecs: &[HashMap<usize, RvecTvec>],
+) -> Option<Vec<RvecTvec>> {
+    let t_board_cam0: HashMap<_, _> = cam_rtvecs
+        .first()?
+        .iter()
+        .map(|(&i, rtvec)| (i, rtvec.to_na_isometry3().inverse()))
+        .collect();
+    let mut t_i_0 = vec![na::Isometry3::identity().to_rvec_tvec()];
+    for (cam_idx, rtvecs) in cam_rtvecs.iter().enumerate().skip(1) {
+        let Some(calib) = calibrate_hand_eye(&t_board_cam0, rtvecs) else {
+            tracing::warn!("cam{} motion doesn't match the motion of cam0", cam_idx);
+            return None;
+        };
+        tracing::info!(
+            "cam{} motion error rms {:.5} rad {:.5} m, {} outliers",
+            cam_idx,
+            calib.rotation_rms,
+            calib.translation_rms,
+            calib.outliers.len()
+        );
+        t_i_0.push(
+            calib
+                .t_flange_cam
+                .to_na_isometry3()
+                .inverse()
+                .to_rvec_tvec(),
+        );
+    }
+    Some(t_i_0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::rtvec;
+
+    fn pose(r: [f64; 3], t: [f64; 3]) -> na::Isometry3<f64> {
+        na::Isometry3::from_parts(
+            na::Vector3::from(t).into(),
+            na::UnitQuaternion::from_scaled_axis(na::Vector3::from(r)),
+        )
+    }
+
+    #[test]
+    fn recovers_flange_cam_and_rejects_outlier() {
+        let t_flange_cam = pose([0.1, -0.2, 1.5], [0.05, 0.02, 0.1]);
+        let t_base_board = pose([0.0, 0.1, 0.3], [0.6, -0.1, 0.0]);
+        let mut robot_poses = HashMap::new();
+        let mut board_poses = HashMap::new();
+        for i in 0..10 {
+            let a = i as f64;
+            let t_base_flange = pose(
+                [0.3 * a.sin(), 0.3 * a.cos(), 0.5 * (0.7 * a).sin()],
+                [0.5 + 0.1 * a.cos(), 0.1 * a.sin(), 0.6],
+            );
+            let t_cam_board = (t_base_flange * t_flange_cam).inverse() * t_base_board;
+            robot_poses.insert(i, t_base_flange);
+            board_poses.insert(i, rtvec(&t_cam_board));
+        }
+        // a frame with a wrong robot pose
+        let bad = robot_poses[&4] * pose([0.0, 0.0, 0.2], [0.05, 0.0, 0.0]);
+        robot_poses.insert(4, bad);
+
+        let calibration = calibrate_hand_eye(&robot_poses, &board_poses).unwrap();
+        assert_eq!(calibration.outliers, [4]);
+        let estimated = calibration.t_flange_cam.to_na_isometry3();
+        assert!((estimated.translation.vector - t_flange_cam.translation.vector).norm() < 1e-6);
+        assert!(estimated.rotation.angle_to(&t_flange_cam.rotation) < 1e-6);
+        assert!(calibration.translation_rms < 1e-6);
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use camera_intrinsic_model::*;
+use serde::{Deserialize, Serialize};
+
+use crate::detected_points::FrameFeature;
+use crate::types::RvecTvec;
+use crate::util::{corner_residuals, solve_board_pose};
+
+/// Takes `fraction` of the frames out of `cams_frames`, spread evenly over the sequence, and
+/// returns them `[cam][frame]` with the same indexes. Every camera loses the same frames so the
+/// rig keeps its shared board poses.
+pub fn split_holdout(
+    cams_frames: &mut [Vec<Option<FrameFeature>>],
+    fraction: f64,
+) -> Vec<Vec<Option<FrameFeature>>> {
+    let len = cams_frames.iter().map(|f| f.len()).max().unwrap_or(0);
+    let valid: Vec<_> = (0..len)
+        .filter(|&i| {
+            cams_frames
+                .iter()
+                .any(|f| matches!(f.get(i), Some(Some(_))))
+        })
+        .collect();
+    let mut holdout: Vec<_> = cams_frames.iter().map(|f| vec![None; f.len()]).collect();
+    for (n, &i) in valid.iter().enumerate() {
+        if (n as f64 * fraction).floor() == ((n + 1) as f64 * fraction).floor() {
+            continue;
+        }
+        for (frames, holdout_frames) in cams_frames.iter_mut().zip(&mut holdout) {
+            if let Some(frame) = frames.get_mut(i) {
+                holdout_frames[i] = frame.take();
+            }
+        }
+    }
+    holdout
+}
+
+/// Reprojection errors of the frames used by the calibration and of the held out frames, whose
+/// board poses are solved with the calibrated intrinsics. A held out rms much larger than the
+/// training one means the model overfits, e.g. with too many distortion params.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HoldoutStats {
+    pub cam_idx: usize,
+    pub num_train_frames: usize,
+    pub num_holdout_frames: usize,
+    pub train_rms: f64,
+    pub holdout_rms: f64,
+    pub holdout_median: f64,
+}
+
+impl HoldoutStats {
+    pub fn new(
+        cam_idx: usize,
+        model: &GenericModel<f64>,
+        train_rtvecs: &HashMap<usize, RvecTvec>,
+        train_frames: &[Option<FrameFeature>],
+        holdout_frames: &[Option<FrameFeature>],
+    ) -> HoldoutStats {
+        let holdout_rtvecs: HashMap<_, _> = holdout_frames
+            .iter()
+            .enumerate()
+            .filter_map(|(i, f)| Some((i, solve_board_pose(model, f.as_ref()?)?)))
+            .collect();
+        let rms = |errors: &[f64]| {
+            (errors.iter().map(|e| e * e).sum::<f64>() / errors.len().max(1) as f64).sqrt()
+        };
+        let train: Vec<_> = corner_residuals(model, train_rtvecs, train_frames)
+            .iter()
+            .map(|r| r.norm)
+            .collect();
+        let mut holdout: Vec<_> = corner_residuals(model, &holdout_rtvecs, holdout_frames)
+            .iter()
+            .map(|r| r.norm)
+            .collect();
+        holdout.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        HoldoutStats {
+            cam_idx,
+            num_train_frames: train_rtvecs.len(),
+            num_holdout_frames: holdout_rtvecs.len(),
+            train_rms: rms(&train),
+            holdout_rms: rms(&holdout),
+            holdout_median: holdout.get(holdout.len() / 2).copied().unwrap_or(0.0),
+        }
+    }
+}
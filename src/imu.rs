@@ -0,0 +1,603 @@
+use std::collections::HashMap;
+
+use camera_intrinsic_model::GenericModel;
+use nalgebra as na;
+use tiny_solver::loss_functions::HuberLoss;
+use tiny_solver::{Optimizer, Problem};
+
+use crate::detected_points::FrameFeature;
+use crate::optimization::factors::{SplineAccelFactor, SplineGyroFactor, SplineReprojectionFactor};
+use crate::spline::{PoseSpline, KNOT_DIM};
+use crate::types::Vec3DVec;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ImuSample {
+    pub time_ns: i64,
+    /// rad/s
+    pub gyro: na::Vector3<f64>,
+    /// m/s^2
+    pub accel: na::Vector3<f64>,
+}
+
+/// Result of `calibrate_camera_imu`.
+#[derive(Debug, Clone)]
+pub struct CamImuCalibration {
+    /// Transforms points from the imu to the camera.
+    pub t_cam_imu: na::Isometry3<f64>,
+    /// Like kalibr `timeshift_cam_imu`, t_imu = t_cam + time_offset.
+    pub time_offset_ns: i64,
+    pub gyro_bias: na::Vector3<f64>,
+    pub accel_bias: na::Vector3<f64>,
+    /// In the board frame, its norm should be close to 9.81.
+    pub gravity: na::Vector3<f64>,
+}
+
+/// Camera motion at the middle of three consecutive board poses, from finite differences.
+struct CameraMotion {
+    begin_ns: i64,
+    end_ns: i64,
+    /// Rotation of the camera to the board frame.
+    r_b_c: na::Rotation3<f64>,
+    /// In the camera frame.
+    angular_velocity: na::Vector3<f64>,
+    /// Of the camera center in the board frame.
+    acceleration: na::Vector3<f64>,
+    /// Second derivative of `r_b_c`.
+    r_b_c_dd: na::Matrix3<f64>,
+}
+
+fn second_derivative<T>(values: [T; 3], h1: f64, h2: f64) -> T
+where
+    T: std::ops::Sub<Output = T>
+        + std::ops::Mul<f64, Output = T>
+        + std::ops::Add<Output = T>
+        + Copy,
+{
+    ((values[2] - values[1]) * (1.0 / h2) + (values[0] - values[1]) * (1.0 / h1))
+        * (2.0 / (h1 + h2))
+}
+
+fn camera_motions(board_poses: &[(i64, na::Isometry3<f64>)]) -> Vec<CameraMotion> {
+    let mut poses: Vec<_> = board_poses
+        .iter()
+        .map(|(t, t_c_b)| (*t, t_c_b.inverse()))
+        .collect();
+    poses.sort_by_key(|p| p.0);
+    let mut dts: Vec<_> = poses.windows(2).map(|w| w[1].0 - w[0].0).collect();
+    dts.sort();
+    let Some(&median_dt) = dts.get(dts.len() / 2) else {
+        return Vec::new();
+    };
+    // skip gaps of missing detections
+    let max_dt = median_dt * 3 / 2;
+    poses
+        .windows(3)
+        .filter(|w| w[1].0 - w[0].0 <= max_dt && w[2].0 - w[1].0 <= max_dt)
+        .map(|w| {
+            let (h1, h2) = (
+                (w[1].0 - w[0].0) as f64 * 1e-9,
+                (w[2].0 - w[1].0) as f64 * 1e-9,
+            );
+            let rotations = [0, 1, 2].map(|i| w[i].1.rotation.to_rotation_matrix());
+            let angular_velocity =
+                (rotations[0].inverse() * rotations[2]).scaled_axis() / (h1 + h2);
+            CameraMotion {
+                begin_ns: w[0].0,
+                end_ns: w[2].0,
+                r_b_c: rotations[1],
+                angular_velocity,
+                acceleration: second_derivative(
+                    [0, 1, 2].map(|i| w[i].1.translation.vector),
+                    h1,
+                    h2,
+                ),
+                r_b_c_dd: second_derivative(rotations.map(|r| *r.matrix()), h1, h2),
+            }
+        })
+        .collect()
+}
+
+/// Linear interpolation of the samples at `time_ns`, `imu` is sorted by time.
+fn interpolate_imu(imu: &[ImuSample], time_ns: i64) -> Option<ImuSample> {
+    let i = imu.partition_point(|s| s.time_ns <= time_ns);
+    if i == 0 || i == imu.len() {
+        return None;
+    }
+    let (s0, s1) = (&imu[i - 1], &imu[i]);
+    let w = (time_ns - s0.time_ns) as f64 / (s1.time_ns - s0.time_ns) as f64;
+    Some(ImuSample {
+        time_ns,
+        gyro: s0.gyro.lerp(&s1.gyro, w),
+        accel: s0.accel.lerp(&s1.accel, w),
+    })
+}
+
+/// Mean gyro and accel in [begin_ns, end_ns] of the linearly interpolated samples.
+fn mean_imu(
+    imu: &[ImuSample],
+    begin_ns: i64,
+    end_ns: i64,
+) -> Option<(na::Vector3<f64>, na::Vector3<f64>)> {
+    let first = interpolate_imu(imu, begin_ns)?;
+    let last = interpolate_imu(imu, end_ns)?;
+    let begin = imu.partition_point(|s| s.time_ns <= begin_ns);
+    let end = imu.partition_point(|s| s.time_ns < end_ns);
+    let samples: Vec<_> = std::iter::once(&first)
+        .chain(&imu[begin..end])
+        .chain(std::iter::once(&last))
+        .collect();
+    // trapezoidal rule
+    let (mut gyro, mut accel) = (na::Vector3::zeros(), na::Vector3::zeros());
+    for w in samples.windows(2) {
+        let dt = (w[1].time_ns - w[0].time_ns) as f64;
+        gyro += (w[0].gyro + w[1].gyro) * dt / 2.0;
+        accel += (w[0].accel + w[1].accel) * dt / 2.0;
+    }
+    let duration = (end_ns - begin_ns) as f64;
+    Some((gyro / duration, accel / duration))
+}
+
+/// Mean squared `residual` of the camera motions and the imu shifted by `offset_ns`.
+fn offset_cost(
+    motions: &[CameraMotion],
+    imu: &[ImuSample],
+    offset_ns: i64,
+    residual: impl Fn(&CameraMotion, &na::Vector3<f64>) -> f64,
+) -> Option<f64> {
+    let diffs: Vec<_> = motions
+        .iter()
+        .filter_map(|m| {
+            let (gyro, _) = mean_imu(imu, m.begin_ns + offset_ns, m.end_ns + offset_ns)?;
+            Some(residual(m, &gyro))
+        })
+        .collect();
+    // offsets with only a few overlapping samples aren't comparable
+    (diffs.len() * 2 >= motions.len()).then(|| diffs.iter().sum::<f64>() / diffs.len() as f64)
+}
+
+/// Offset with the min cost in `center +- radius`, refined by a parabola through the neighbors.
+pub(crate) fn search_offset(
+    center_ns: i64,
+    radius_ns: i64,
+    step_ns: i64,
+    cost: impl Fn(i64) -> Option<f64>,
+) -> Option<i64> {
+    let costs: Vec<_> = (-radius_ns / step_ns..=radius_ns / step_ns)
+        .filter_map(|i| {
+            let offset = center_ns + i * step_ns;
+            Some((offset, cost(offset)?))
+        })
+        .collect();
+    let best = (0..costs.len()).min_by(|&a, &b| costs[a].1.partial_cmp(&costs[b].1).unwrap())?;
+    let mut offset = costs[best].0;
+    if best > 0 && best + 1 < costs.len() {
+        let (c0, c1, c2) = (costs[best - 1].1, costs[best].1, costs[best + 1].1);
+        let denom = c0 - 2.0 * c1 + c2;
+        if denom > 0.0 {
+            offset += ((c0 - c2) / (2.0 * denom) * step_ns as f64) as i64;
+        }
+    }
+    Some(offset)
+}
+
+fn gyro_pairs(
+    motions: &[CameraMotion],
+    imu: &[ImuSample],
+    offset_ns: i64,
+) -> Vec<(na::Vector3<f64>, na::Vector3<f64>)> {
+    motions
+        .iter()
+        .filter_map(|m| {
+            let (gyro, _) = mean_imu(imu, m.begin_ns + offset_ns, m.end_ns + offset_ns)?;
+            Some((m.angular_velocity, gyro))
+        })
+        .collect()
+}
+
+/// Rotation `r` and bias `b` minimizing |r * a + b - b_i|, Kabsch with centroids.
+pub(crate) fn align_vectors(
+    pairs: &[(na::Vector3<f64>, na::Vector3<f64>)],
+) -> (na::Rotation3<f64>, na::Vector3<f64>) {
+    let n = pairs.len() as f64;
+    let mean_a = pairs.iter().map(|p| p.0).sum::<na::Vector3<f64>>() / n;
+    let mean_b = pairs.iter().map(|p| p.1).sum::<na::Vector3<f64>>() / n;
+    let h = pairs
+        .iter()
+        .map(|(a, b)| (b - mean_b) * (a - mean_a).transpose())
+        .sum::<na::Matrix3<f64>>();
+    let svd = h.svd(true, true);
+    let (u, v_t) = (svd.u.unwrap(), svd.v_t.unwrap());
+    let d = (u * v_t).determinant().signum();
+    let r = u * na::Matrix3::from_diagonal(&na::Vector3::new(1.0, 1.0, d)) * v_t;
+    let r = na::Rotation3::from_matrix_unchecked(r);
+    (r, mean_b - r * mean_a)
+}
+
+/// Camera-imu extrinsics and time offset from the board poses `T_cam_board` of a sequence with
+/// rich rotation and acceleration, and the imu samples of the same time span.
+/// The time offset is searched within `max_time_offset_ns` by matching angular speeds, the
+/// rotation and gyro bias align the angular velocities, then the translation, gravity and
+/// accel bias are solved linearly from the accelerations of the camera poses.
+pub fn calibrate_camera_imu(
+    board_poses: &[(i64, na::Isometry3<f64>)],
+    imu: &[ImuSample],
+    max_time_offset_ns: i64,
+) -> Option<CamImuCalibration> {
+    let mut imu = imu.to_vec();
+    imu.sort_by_key(|s| s.time_ns);
+    let motions = camera_motions(board_poses);
+    if motions.len() < 10 {
+        tracing::warn!("not enough consecutive board poses for camera-imu calibration");
+        return None;
+    }
+
+    // angular speeds don't depend on the rotation, then refine with the aligned velocities
+    let time_offset_ns = search_offset(0, max_time_offset_ns, 1_000_000, |offset| {
+        offset_cost(&motions, &imu, offset, |m, gyro| {
+            (m.angular_velocity.norm() - gyro.norm()).powi(2)
+        })
+    })?;
+    let (r_imu_cam, gyro_bias) = align_vectors(&gyro_pairs(&motions, &imu, time_offset_ns));
+    let time_offset_ns = search_offset(time_offset_ns, 5_000_000, 100_000, |offset| {
+        offset_cost(&motions, &imu, offset, |m, gyro| {
+            (r_imu_cam * m.angular_velocity + gyro_bias - gyro).norm_squared()
+        })
+    })?;
+    let (r_imu_cam, gyro_bias) = align_vectors(&gyro_pairs(&motions, &imu, time_offset_ns));
+    let r_cam_imu = r_imu_cam.inverse();
+
+    let samples: Vec<_> = motions
+        .iter()
+        .filter_map(|m| {
+            let imu = mean_imu(&imu, m.begin_ns + time_offset_ns, m.end_ns + time_offset_ns)?;
+            Some((m, imu))
+        })
+        .collect();
+
+    // r_b_imu * (f - b_a) = p_dd + r_b_c_dd * t_cam_imu - g
+    let mut a = na::DMatrix::<f64>::zeros(samples.len() * 3, 9);
+    let mut b = na::DVector::<f64>::zeros(samples.len() * 3);
+    for (i, (m, (_, accel))) in samples.iter().enumerate() {
+        let r_b_imu = m.r_b_c * r_cam_imu;
+        a.fixed_view_mut::<3, 3>(i * 3, 0).copy_from(&m.r_b_c_dd);
+        a.fixed_view_mut::<3, 3>(i * 3, 3)
+            .copy_from(&-na::Matrix3::identity());
+        a.fixed_view_mut::<3, 3>(i * 3, 6)
+            .copy_from(r_b_imu.matrix());
+        b.fixed_rows_mut::<3>(i * 3)
+            .copy_from(&(r_b_imu * accel - m.acceleration));
+    }
+    let x = a.svd(true, true).solve(&b, 1e-12).ok()?;
+    let t_cam_imu = na::Vector3::new(x[0], x[1], x[2]);
+    let gravity = na::Vector3::new(x[3], x[4], x[5]);
+    let accel_bias = na::Vector3::new(x[6], x[7], x[8]);
+    tracing::info!(
+        "camera-imu time offset {:.3} ms, gravity norm {:.3} m/s^2",
+        time_offset_ns as f64 * 1e-6,
+        gravity.norm()
+    );
+    Some(CamImuCalibration {
+        t_cam_imu: na::Isometry3::from_parts(t_cam_imu.into(), r_cam_imu.into()),
+        time_offset_ns,
+        gyro_bias,
+        accel_bias,
+        gravity,
+    })
+}
+
+/// Options of `refine_camera_imu`.
+#[derive(Debug, Clone, Copy)]
+pub struct SplineOptions {
+    /// Time between the control points of the imu trajectory.
+    pub knot_ns: i64,
+    /// Standard deviation of a gyro sample in rad/s.
+    pub gyro_noise: f64,
+    /// Standard deviation of an accelerometer sample in m/s^2.
+    pub accel_noise: f64,
+    pub max_time_offset_ns: i64,
+}
+
+impl Default for SplineOptions {
+    fn default() -> Self {
+        SplineOptions {
+            knot_ns: 50_000_000,
+            gyro_noise: 0.01,
+            accel_noise: 0.1,
+            max_time_offset_ns: 100_000_000,
+        }
+    }
+}
+
+/// Rebuilds of the problem with the segments of the corners at the refined time offset.
+const MAX_SEGMENT_PASSES: usize = 3;
+
+fn knot_name(i: usize) -> String {
+    format!("knot{}", i)
+}
+
+/// Joint continuous-time refinement of `init`, e.g. of `calibrate_camera_imu`, like kalibr.
+/// The imu trajectory in the board frame is a `PoseSpline` fitted to the board poses
+/// `T_cam_board` of `board_frames`, then the spline, `T_cam_imu`, the time offset, the gyro and
+/// accel biases and the gravity are optimized together with the reprojection errors of the
+/// corners at their capture time and the residuals of the gyro and accel samples. The
+/// intrinsics `model` stay fixed and the biases are constant over the sequence.
+pub fn refine_camera_imu(
+    model: &GenericModel<f64>,
+    board_frames: &[(&FrameFeature, na::Isometry3<f64>)],
+    imu: &[ImuSample],
+    init: &CamImuCalibration,
+    options: &SplineOptions,
+) -> Option<CamImuCalibration> {
+    let mut imu = imu.to_vec();
+    imu.sort_by_key(|s| s.time_ns);
+    let first_ns = board_frames.iter().map(|(f, _)| f.time_ns).min()?;
+    let last_ns = board_frames.iter().map(|(f, _)| f.time_ns).max()?;
+    let max_offset_ms = options.max_time_offset_ns as f64 * 1e-6;
+    let mut calib = init.clone();
+    let mut spline: Option<PoseSpline> = None;
+    for _ in 0..MAX_SEGMENT_PASSES {
+        let offset_ns = calib.time_offset_ns;
+        let spline_ref = match &spline {
+            Some(spline) => spline,
+            None => {
+                // T_board_imu = T_board_cam * T_cam_imu at the imu time of the frames
+                let body_poses: Vec<_> = board_frames
+                    .iter()
+                    .map(|(f, t_cam_board)| {
+                        (
+                            f.time_ns + offset_ns,
+                            t_cam_board.inverse() * calib.t_cam_imu,
+                        )
+                    })
+                    .collect();
+                spline.insert(PoseSpline::fit(
+                    &body_poses,
+                    first_ns + offset_ns - options.knot_ns,
+                    last_ns + offset_ns + options.knot_ns,
+                    options.knot_ns,
+                )?)
+            }
+        };
+        let knot_s = spline_ref.knot_s();
+        let mut problem = Problem::new();
+        let mut initial_values = HashMap::new();
+        for (i, knot) in spline_ref.knots.iter().enumerate() {
+            initial_values.insert(
+                knot_name(i),
+                na::DVector::from_column_slice(knot.as_slice()),
+            );
+        }
+        let knot_names = |i: usize| [0, 1, 2, 3].map(|k| knot_name(i + k));
+
+        for (frame, _) in board_frames {
+            for fp in frame.features.values() {
+                let capture_ns = fp.capture_time_ns(frame.time_ns);
+                let Some((i, segment_ns)) = spline_ref.segment(capture_ns + offset_ns) else {
+                    continue;
+                };
+                let cost = SplineReprojectionFactor::new(
+                    model,
+                    fp,
+                    &spline_ref.r_ref,
+                    (capture_ns - segment_ns) as f64 * 1e-9,
+                    knot_s,
+                );
+                let knots = knot_names(i);
+                problem.add_residual_block(
+                    2,
+                    &[
+                        (&knots[0], KNOT_DIM),
+                        (&knots[1], KNOT_DIM),
+                        (&knots[2], KNOT_DIM),
+                        (&knots[3], KNOT_DIM),
+                        ("rvec_cam_imu", 3),
+                        ("tvec_cam_imu", 3),
+                        ("time_offset_ms", 1),
+                    ],
+                    Box::new(cost),
+                    Some(Box::new(HuberLoss::new(1.0))),
+                );
+            }
+        }
+        let mut imu_samples = 0;
+        for sample in &imu {
+            let Some((i, segment_ns)) = spline_ref.segment(sample.time_ns) else {
+                continue;
+            };
+            let u = (sample.time_ns - segment_ns) as f64 / options.knot_ns as f64;
+            let knots = knot_names(i);
+            let knot_variables = knots.each_ref().map(|k| (k.as_str(), KNOT_DIM));
+            let gyro_cost = SplineGyroFactor::new(&sample.gyro, u, knot_s, options.gyro_noise);
+            problem.add_residual_block(
+                3,
+                &[knot_variables.as_slice(), &[("gyro_bias", 3)]].concat(),
+                Box::new(gyro_cost),
+                None,
+            );
+            let accel_cost = SplineAccelFactor::new(
+                &sample.accel,
+                &spline_ref.r_ref,
+                u,
+                knot_s,
+                options.accel_noise,
+            );
+            problem.add_residual_block(
+                3,
+                &[
+                    knot_variables.as_slice(),
+                    &[("accel_bias", 3), ("gravity", 3)],
+                ]
+                .concat(),
+                Box::new(accel_cost),
+                None,
+            );
+            imu_samples += 1;
+        }
+        if imu_samples == 0 {
+            tracing::warn!("no imu samples during the board frames");
+            return None;
+        }
+        problem.set_variable_bounds("time_offset_ms", 0, -max_offset_ms, max_offset_ms);
+        initial_values.insert(
+            "rvec_cam_imu".to_string(),
+            calib.t_cam_imu.rotation.scaled_axis().to_dvec(),
+        );
+        initial_values.insert(
+            "tvec_cam_imu".to_string(),
+            calib.t_cam_imu.translation.vector.to_dvec(),
+        );
+        initial_values.insert(
+            "time_offset_ms".to_string(),
+            na::dvector![offset_ns as f64 * 1e-6],
+        );
+        initial_values.insert("gyro_bias".to_string(), calib.gyro_bias.to_dvec());
+        initial_values.insert("accel_bias".to_string(), calib.accel_bias.to_dvec());
+        initial_values.insert("gravity".to_string(), calib.gravity.to_dvec());
+
+        let result =
+            tiny_solver::GaussNewtonOptimizer {}.optimize(&problem, &initial_values, None)?;
+        let vec3 = |name: &str| na::Vector3::new(result[name][0], result[name][1], result[name][2]);
+        calib = CamImuCalibration {
+            t_cam_imu: na::Isometry3::new(vec3("tvec_cam_imu"), vec3("rvec_cam_imu")),
+            time_offset_ns: (result["time_offset_ms"][0] * 1e6).round() as i64,
+            gyro_bias: vec3("gyro_bias"),
+            accel_bias: vec3("accel_bias"),
+            gravity: vec3("gravity"),
+        };
+        let mut refined = spline.take()?;
+        for (i, knot) in refined.knots.iter_mut().enumerate() {
+            *knot = na::Vector6::from_column_slice(result[&knot_name(i)].as_slice());
+        }
+        spline = Some(refined);
+        // the segments of the corners were picked at the previous offset, a small shift only
+        // extends the polynomial of a segment a little into the next one
+        if (calib.time_offset_ns - offset_ns).abs() < options.knot_ns / 100 {
+            break;
+        }
+    }
+    tracing::info!(
+        "spline camera-imu time offset {:.3} ms, gravity norm {:.3} m/s^2",
+        calib.time_offset_ns as f64 * 1e-6,
+        calib.gravity.norm()
+    );
+    Some(calib)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spline::{so3_exp, so3_right_jacobian};
+    use crate::test_util;
+
+    const DURATION_NS: i64 = 2_000_000_000;
+    const IMU_DT_NS: i64 = 10_000_000;
+    const FRAME_DT_NS: i64 = 100_000_000;
+
+    /// Smooth imu trajectory around `t_board_imu0`, the pose, angular velocity in the imu frame
+    /// and acceleration in the board frame at `t` seconds.
+    fn imu_motion(
+        t_board_imu0: &na::Isometry3<f64>,
+        t: f64,
+    ) -> (na::Isometry3<f64>, na::Vector3<f64>, na::Vector3<f64>) {
+        let w = na::Vector3::new(2.1, 2.9, 1.7);
+        let amplitude = na::Vector3::new(0.25, 0.2, 0.15);
+        let phase = na::Vector3::new(0.0, 0.5, 1.0);
+        let phi = na::Vector3::from_fn(|i, _| amplitude[i] * (w[i] * t + phase[i]).sin());
+        let phi_d = na::Vector3::from_fn(|i, _| amplitude[i] * w[i] * (w[i] * t + phase[i]).cos());
+        let v = na::Vector3::new(2.3, 1.9, 2.7);
+        let reach = na::Vector3::new(0.15, 0.1, 0.08);
+        let offset = na::Vector3::from_fn(|i, _| reach[i] * (v[i] * t).sin());
+        let acceleration = na::Vector3::from_fn(|i, _| -reach[i] * v[i] * v[i] * (v[i] * t).sin());
+        let rotation = t_board_imu0.rotation.to_rotation_matrix().matrix() * so3_exp(&phi);
+        let pose = na::Isometry3::from_parts(
+            (t_board_imu0.translation.vector + offset).into(),
+            na::UnitQuaternion::from_matrix(&rotation),
+        );
+        (pose, so3_right_jacobian(&phi) * phi_d, acceleration)
+    }
+
+    #[test]
+    fn spline_refines_camera_imu() {
+        let model = test_util::eucm();
+        let board = test_util::board();
+        let t_cam_imu = na::Isometry3::new(
+            na::Vector3::new(0.03, -0.01, 0.02),
+            na::Vector3::new(0.02, -1.55, 0.01),
+        );
+        let t_board_imu0 = test_util::board_poses(&board, 1)[0].inverse() * t_cam_imu;
+        let time_offset_ns = 5_000_000;
+        let gyro_bias = na::Vector3::new(0.01, -0.02, 0.005);
+        let accel_bias = na::Vector3::new(0.1, -0.05, 0.08);
+        let gravity = na::Vector3::new(0.5, -9.7, 1.3).normalize() * 9.81;
+
+        let imu: Vec<_> = (0..DURATION_NS / IMU_DT_NS)
+            .map(|i| {
+                let time_ns = i * IMU_DT_NS;
+                let (pose, angular_velocity, acceleration) =
+                    imu_motion(&t_board_imu0, time_ns as f64 * 1e-9);
+                ImuSample {
+                    time_ns,
+                    gyro: angular_velocity + gyro_bias,
+                    accel: pose.rotation.inverse() * (acceleration - gravity) + accel_bias,
+                }
+            })
+            .collect();
+        // camera time + offset = imu time
+        let frames: Vec<_> = (2..DURATION_NS / FRAME_DT_NS - 2)
+            .map(|i| {
+                let time_ns = i * FRAME_DT_NS;
+                let (t_board_imu, _, _) =
+                    imu_motion(&t_board_imu0, (time_ns + time_offset_ns) as f64 * 1e-9);
+                let t_cam_board = t_cam_imu * t_board_imu.inverse();
+                let mut frame = test_util::project_frame(&model, &board, &t_cam_board, time_ns);
+                // a few corners are enough, the debug build evaluates the jacobians slowly
+                frame.features.retain(|id, _| id % 4 == 0);
+                (frame, t_cam_board)
+            })
+            .collect();
+        let board_frames: Vec<_> = frames.iter().map(|(f, pose)| (f, *pose)).collect();
+
+        let init = CamImuCalibration {
+            t_cam_imu: t_cam_imu
+                * na::Isometry3::new(
+                    na::Vector3::new(0.01, 0.01, -0.01),
+                    na::Vector3::new(0.02, -0.01, 0.02),
+                ),
+            time_offset_ns: 0,
+            gyro_bias: na::Vector3::zeros(),
+            accel_bias: na::Vector3::zeros(),
+            gravity: na::Vector3::new(0.0, -9.81, 0.0),
+        };
+        let calib = refine_camera_imu(
+            &model,
+            &board_frames,
+            &imu,
+            &init,
+            &SplineOptions::default(),
+        )
+        .unwrap();
+        assert!(
+            (calib.time_offset_ns - time_offset_ns).abs() < 200_000,
+            "time offset {} ns",
+            calib.time_offset_ns
+        );
+        let error = calib.t_cam_imu.inverse() * t_cam_imu;
+        assert!(error.rotation.angle() < 1e-3, "{}", error.rotation.angle());
+        assert!(
+            error.translation.vector.norm() < 5e-3,
+            "{}",
+            error.translation.vector
+        );
+        assert!(
+            (calib.gyro_bias - gyro_bias).norm() < 2e-3,
+            "{}",
+            calib.gyro_bias
+        );
+        assert!(
+            (calib.accel_bias - accel_bias).norm() < 0.05,
+            "{}",
+            calib.accel_bias
+        );
+        assert!((calib.gravity - gravity).norm() < 0.05, "{}", calib.gravity);
+    }
+}
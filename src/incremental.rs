@@ -0,0 +1,299 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, sync_channel, TrySendError};
+
+use camera_intrinsic_model::GenericModel;
+use image::DynamicImage;
+use nalgebra as na;
+use rayon::prelude::*;
+use tracing::{debug, info};
+
+use crate::board::Board;
+use crate::detected_points::{
+    image_to_option_feature_frame, FrameFeature, TagDetection, MIN_CORNERS,
+};
+use crate::observer::PipelineObserver;
+use crate::optimization::IncrementalProblem;
+use crate::types::CalibParams;
+use crate::util::init_and_calibrate_one_camera_with_trials;
+
+/// Board center and size in the image, normalized by the image diagonal.
+fn frame_signature(frame_feature: &FrameFeature) -> na::Vector3<f64> {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+    for fp in frame_feature.features.values() {
+        min_x = min_x.min(fp.p2d.x as f64);
+        min_y = min_y.min(fp.p2d.y as f64);
+        max_x = max_x.max(fp.p2d.x as f64);
+        max_y = max_y.max(fp.p2d.y as f64);
+    }
+    let (w, h) = frame_feature.img_w_h;
+    let diagonal = ((w * w + h * h) as f64).sqrt();
+    na::Vector3::new(
+        (min_x + max_x) / 2.0 / diagonal,
+        (min_y + max_y) / 2.0 / diagonal,
+        ((max_x - min_x) * (max_y - min_y)).sqrt() / diagonal,
+    )
+}
+
+/// Signatures of the kept frames.
+#[derive(Default)]
+struct FrameSelector {
+    signatures: Vec<na::Vector3<f64>>,
+}
+
+impl FrameSelector {
+    /// Keep the frame if it has enough corners and differs from the kept ones by at least
+    /// `min_signature_distance`, returns whether it was kept.
+    fn select(&mut self, frame_feature: &FrameFeature, min_signature_distance: f64) -> bool {
+        if frame_feature.features.len() < MIN_CORNERS {
+            return false;
+        }
+        let signature = frame_signature(frame_feature);
+        if self
+            .signatures
+            .iter()
+            .any(|s| (s - signature).norm() < min_signature_distance)
+        {
+            return false;
+        }
+        self.signatures.push(signature);
+        true
+    }
+}
+
+/// Calibrate a single camera while frames keep coming in.
+///
+/// Frames too similar to the ones already used are skipped, the problem is re-solved
+/// every `resolve_every` new frames starting from the last params and board poses, and calibration is
+/// converged when the params change less than `tolerance` (relative) for `stable_solves` solves.
+pub struct IncrementalCalibrator {
+    pub target_model: GenericModel<f64>,
+    pub calib_params: CalibParams,
+    pub min_signature_distance: f64,
+    pub resolve_every: usize,
+    pub tolerance: f64,
+    pub stable_solves: usize,
+    frames: Vec<Option<FrameFeature>>,
+    selector: FrameSelector,
+    new_frames: usize,
+    model: Option<GenericModel<f64>>,
+    problem: Option<IncrementalProblem>,
+    t_cam_boards: Vec<na::Isometry3<f64>>,
+    history: Vec<na::DVector<f64>>,
+}
+
+impl IncrementalCalibrator {
+    pub fn new(
+        target_model: &GenericModel<f64>,
+        calib_params: CalibParams,
+    ) -> IncrementalCalibrator {
+        IncrementalCalibrator {
+            target_model: *target_model,
+            calib_params,
+            min_signature_distance: 0.03,
+            resolve_every: 5,
+            tolerance: 1e-3,
+            stable_solves: 3,
+            frames: Vec::new(),
+            selector: FrameSelector::default(),
+            new_frames: 0,
+            model: None,
+            problem: None,
+            t_cam_boards: Vec::new(),
+            history: Vec::new(),
+        }
+    }
+
+    /// Keep the frame if it is informative, returns whether it was kept.
+    pub fn add_frame(&mut self, frame_feature: &FrameFeature) -> bool {
+        if !self
+            .selector
+            .select(frame_feature, self.min_signature_distance)
+        {
+            return false;
+        }
+        self.insert_frame(frame_feature.clone());
+        true
+    }
+
+    /// Add a frame that is already selected.
+    fn insert_frame(&mut self, frame_feature: FrameFeature) {
+        if let Some(problem) = &mut self.problem {
+            problem.add_frame(self.frames.len(), frame_feature.clone());
+        }
+        self.frames.push(Some(frame_feature));
+        self.new_frames += 1;
+    }
+
+    /// Add the frame and re-solve if enough new frames are kept.
+    /// Returns the new estimate if the problem was solved.
+    pub fn push(
+        &mut self,
+        frame_feature: &FrameFeature,
+        observer: &dyn PipelineObserver,
+    ) -> Option<GenericModel<f64>> {
+        if self.add_frame(frame_feature) && self.new_frames >= self.resolve_every {
+            self.solve(observer)
+        } else {
+            None
+        }
+    }
+
+    pub fn solve(&mut self, observer: &dyn PipelineObserver) -> Option<GenericModel<f64>> {
+        if self.frames.len() < 2 {
+            return None;
+        }
+        self.new_frames = 0;
+        let (model, rtvec_map) = if let Some(problem) = &mut self.problem {
+            problem.solve(observer)?
+        } else {
+            let (model, rtvec_map) = init_and_calibrate_one_camera_with_trials(
+                0,
+                std::slice::from_ref(&self.frames),
+                &self.target_model,
+                observer,
+                &self.calib_params,
+                3,
+            )?;
+            self.problem = Some(IncrementalProblem::from_calibration(
+                &model,
+                &rtvec_map,
+                &self.frames,
+                &self.calib_params,
+            ));
+            (model, rtvec_map)
+        };
+        let mut rtvecs: Vec<_> = rtvec_map.into_iter().collect();
+        rtvecs.sort_by_key(|r| r.0);
+        self.t_cam_boards = rtvecs.iter().map(|r| r.1.to_na_isometry3()).collect();
+        if let Some(last) = self.history.last() {
+            debug!(
+                relative_change = (model.params() - last).norm() / last.norm(),
+                "incremental solve"
+            );
+        }
+        info!(frames = self.frames.len(), "params {}", model.params());
+        self.history.push(model.params());
+        self.model = Some(model);
+        self.model
+    }
+
+    pub fn model(&self) -> Option<&GenericModel<f64>> {
+        self.model.as_ref()
+    }
+
+    /// Board poses of the last solve.
+    pub fn t_cam_boards(&self) -> &[na::Isometry3<f64>] {
+        &self.t_cam_boards
+    }
+
+    pub fn frames(&self) -> &[Option<FrameFeature>] {
+        &self.frames
+    }
+
+    /// Params after each solve.
+    pub fn history(&self) -> &[na::DVector<f64>] {
+        &self.history
+    }
+
+    pub fn is_converged(&self) -> bool {
+        if self.history.len() <= self.stable_solves {
+            return false;
+        }
+        self.history
+            .windows(2)
+            .rev()
+            .take(self.stable_solves)
+            .all(|w| (&w[1] - &w[0]).norm() / w[0].norm() < self.tolerance)
+    }
+}
+
+/// Live calibration with the capture, board detection, frame selection and optimization as
+/// concurrent stages connected by channels, so the solver never blocks the capture and the
+/// other way around. An image is dropped when `queue_size` images are already waiting for the
+/// detection, and the frames selected while a solve runs are added to the next one. `on_solve`
+/// is called after every solve. Returns when `calibrator` converges or `images` ends.
+pub fn calibrate_live(
+    calibrator: &mut IncrementalCalibrator,
+    images: impl Iterator<Item = (i64, DynamicImage)> + Send,
+    tag_detector: &dyn TagDetection,
+    board: &Board,
+    queue_size: usize,
+    observer: &dyn PipelineObserver,
+    mut on_solve: impl FnMut(&IncrementalCalibrator),
+) {
+    let (image_sender, image_receiver) = sync_channel(queue_size.max(1));
+    let (detected_sender, detected_receiver) = channel();
+    let (selected_sender, selected_receiver) = channel();
+    let mut selector = std::mem::take(&mut calibrator.selector);
+    let min_signature_distance = calibrator.min_signature_distance;
+    let done = &AtomicBool::new(false);
+    calibrator.selector = std::thread::scope(|s| {
+        s.spawn(move || {
+            let mut dropped = 0;
+            for image in images {
+                if done.load(Ordering::Relaxed) {
+                    break;
+                }
+                match image_sender.try_send(image) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => dropped += 1,
+                    Err(TrySendError::Disconnected(_)) => break,
+                }
+            }
+            debug!(dropped, "capture stopped");
+        });
+        s.spawn(move || {
+            // stops once the selection is gone
+            let _ = image_receiver.into_iter().par_bridge().try_for_each_with(
+                detected_sender,
+                |sender, (time_ns, img)| {
+                    let frame_feature = image_to_option_feature_frame(
+                        tag_detector,
+                        &img,
+                        board,
+                        MIN_CORNERS,
+                        time_ns,
+                    );
+                    observer.on_frame_detected(0, time_ns, &img, frame_feature.as_ref());
+                    match frame_feature {
+                        Some(frame_feature) => sender.send(frame_feature).map_err(drop),
+                        None => Ok(()),
+                    }
+                },
+            );
+        });
+        let selection = s.spawn(move || {
+            for frame_feature in detected_receiver {
+                if selector.select(&frame_feature, min_signature_distance)
+                    && selected_sender.send(frame_feature).is_err()
+                {
+                    break;
+                }
+            }
+            selector
+        });
+        while let Ok(frame_feature) = selected_receiver.recv() {
+            calibrator.insert_frame(frame_feature);
+            for frame_feature in selected_receiver.try_iter() {
+                calibrator.insert_frame(frame_feature);
+            }
+            if calibrator.new_frames >= calibrator.resolve_every
+                && calibrator.solve(observer).is_some()
+            {
+                on_solve(calibrator);
+                if calibrator.is_converged() {
+                    break;
+                }
+            }
+        }
+        if !calibrator.is_converged()
+            && calibrator.new_frames > 0
+            && calibrator.solve(observer).is_some()
+        {
+            on_solve(calibrator);
+        }
+        done.store(true, Ordering::Relaxed);
+        drop(selected_receiver);
+        selection.join().unwrap()
+    });
+}
@@ -0,0 +1,158 @@
+use camera_intrinsic_model::*;
+use serde::{Deserialize, Serialize};
+
+use crate::detected_points::FrameFeature;
+use crate::optimization::{
+    homography_focal_candidates, homography_to_focal, init_pose, radial_distortion_homography,
+};
+use crate::types::RvecTvec;
+use crate::util::{find_best_two_frames_idx, init_ucm, rtvec_to_na_dvec, INIT_ATTEMPTS};
+
+/// Corners of the minimal solver of the homography with a division model distortion.
+const MIN_COMMON_CORNERS: usize = 6;
+
+/// Every step of one initialization of a UCM from a pair of frames, the homography with a
+/// division model distortion between them, the focal from it and the UCM refined from there.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InitAttempt {
+    /// Division model distortion of the homography, in the image normalized by half its size.
+    pub lambda: f32,
+    /// Row major homography from the corners of the first frame to the second one.
+    pub homography: [[f32; 3]; 3],
+    /// Unit plane focals of the two constraints of the homography on the rotation.
+    pub focal_candidates: (Option<f32>, Option<f32>),
+    pub unit_plane_focal: Option<f32>,
+    /// Focal in px and alpha the UCM is refined from.
+    pub init_focal: Option<f64>,
+    pub init_alpha: f64,
+    pub ucm: Option<GenericModel<f64>>,
+    /// Why the attempt failed, `None` if it initialized the UCM.
+    pub failure: Option<String>,
+}
+
+impl InitAttempt {
+    pub fn new(
+        frame_feature0: &FrameFeature,
+        frame_feature1: &FrameFeature,
+        fixed_focal: Option<f64>,
+    ) -> InitAttempt {
+        // initialize focal length and undistorted p2d for init poses
+        let (lambda, h_mat) = radial_distortion_homography(frame_feature0, frame_feature1);
+        let mut attempt = InitAttempt {
+            lambda,
+            homography: std::array::from_fn(|r| std::array::from_fn(|c| h_mat[(r, c)])),
+            focal_candidates: homography_focal_candidates(&h_mat),
+            unit_plane_focal: homography_to_focal(&h_mat),
+            init_focal: None,
+            init_alpha: lambda.abs() as f64,
+            ucm: None,
+            failure: None,
+        };
+
+        // focal
+        let Some(unit_plane_focal) = attempt.unit_plane_focal else {
+            attempt.failure = Some("no focal from the homography".to_string());
+            return attempt;
+        };
+        tracing::debug!(unit_plane_focal, "focal from homography");
+
+        // poses
+        let (rvec0, tvec0) = rtvec_to_na_dvec(init_pose(frame_feature0, lambda));
+        let (rvec1, tvec1) = rtvec_to_na_dvec(init_pose(frame_feature1, lambda));
+        let rtvec0 = RvecTvec::new(&rvec0, &tvec0);
+        let rtvec1 = RvecTvec::new(&rvec1, &tvec1);
+
+        let half_w = frame_feature0.img_w_h.0 as f64 / 2.0;
+        let half_h = frame_feature0.img_w_h.1 as f64 / 2.0;
+        let half_img_size = half_h.max(half_w);
+        let init_f = fixed_focal.unwrap_or(unit_plane_focal as f64 * half_img_size);
+        tracing::debug!(init_f, "initial focal");
+        attempt.init_focal = Some(init_f);
+        match init_ucm(
+            frame_feature0,
+            frame_feature1,
+            &rtvec0,
+            &rtvec1,
+            init_f,
+            attempt.init_alpha,
+            fixed_focal.is_some(),
+        ) {
+            None => attempt.failure = Some("the focal and alpha didn't converge".to_string()),
+            Some(ucm) if ucm.params()[0] == 0.0 => {
+                attempt.failure = Some("the UCM has a zero focal".to_string())
+            }
+            Some(ucm) => attempt.ucm = Some(ucm),
+        }
+        attempt
+    }
+}
+
+/// The initialization of a camera without the calibration after it, to find out why a dataset
+/// doesn't initialize. The frame pair is the one the calibration picks first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InitDiagnostic {
+    pub cam_idx: usize,
+    pub frame_idxs: (usize, usize),
+    pub frame_times_ns: (i64, i64),
+    pub num_corners: (usize, usize),
+    /// Corners seen in both frames, the homography is fitted to them.
+    pub num_common_corners: usize,
+    /// Attempts up to the first that initialized the UCM, the homography is a RANSAC so they
+    /// differ.
+    pub attempts: Vec<InitAttempt>,
+}
+
+impl InitDiagnostic {
+    /// `None` with less than two frames with detections.
+    pub fn new(
+        cam_idx: usize,
+        frames: &[Option<FrameFeature>],
+        fixed_focal: Option<f64>,
+    ) -> Option<InitDiagnostic> {
+        let Some((idx0, idx1)) = find_best_two_frames_idx(frames, false) else {
+            tracing::warn!("cam{} has less than two frames with detections", cam_idx);
+            return None;
+        };
+        let frame_feature0 = frames[idx0].as_ref()?;
+        let frame_feature1 = frames[idx1].as_ref()?;
+        let num_common_corners = frame_feature0
+            .features
+            .keys()
+            .filter(|id| frame_feature1.features.contains_key(id))
+            .count();
+        let max_attempts = if num_common_corners < MIN_COMMON_CORNERS {
+            tracing::warn!(
+                "cam{} frames {} and {} have {} common corners, the homography needs {}",
+                cam_idx,
+                idx0,
+                idx1,
+                num_common_corners,
+                MIN_COMMON_CORNERS
+            );
+            0
+        } else {
+            INIT_ATTEMPTS
+        };
+        let mut attempts = Vec::new();
+        for _ in 0..max_attempts {
+            let attempt = InitAttempt::new(frame_feature0, frame_feature1, fixed_focal);
+            let initialized = attempt.ucm.is_some();
+            attempts.push(attempt);
+            if initialized {
+                break;
+            }
+        }
+        Some(InitDiagnostic {
+            cam_idx,
+            frame_idxs: (idx0, idx1),
+            frame_times_ns: (frame_feature0.time_ns, frame_feature1.time_ns),
+            num_corners: (frame_feature0.features.len(), frame_feature1.features.len()),
+            num_common_corners,
+            attempts,
+        })
+    }
+
+    pub fn initialized(&self) -> Option<&GenericModel<f64>> {
+        self.attempts.last()?.ucm.as_ref()
+    }
+}
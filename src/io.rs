@@ -1,6 +1,43 @@
 use std::io::Write;
 
-use crate::types::Extrinsics;
+use camera_intrinsic_model::GenericModel;
+use nalgebra as na;
+
+use crate::benchmark::{BenchmarkDataset, BenchmarkReport};
+use crate::checkpoint::CameraCheckpoint;
+use crate::coverage::CoverageScore;
+use crate::detected_points::FrameFeature;
+use crate::dry_run::DatasetQuality;
+use crate::hand_eye::HandEyeCalibration;
+use crate::holdout::HoldoutStats;
+use crate::imu::CamImuCalibration;
+use crate::init_diagnostic::InitDiagnostic;
+use crate::jackknife::FrameInfluence;
+use crate::lidar::CameraLidarCalibration;
+use crate::line_scan::LineScanCalibration;
+use crate::monte_carlo::ParamSpread;
+use crate::multi_plane::MultiPlaneConfig;
+use crate::observability::{Observability, ParamCorrelation};
+use crate::outliers::OutlierFrame;
+use crate::profile::PipelineProfile;
+use crate::refraction::FlatPort;
+use crate::response::InverseResponse;
+use crate::rgbd::DepthColorAlignment;
+use crate::rolling_shutter::RollingShutter;
+use crate::scale::{KnownDistance, KnownDistanceError, ScaleCheck};
+use crate::session::SessionResidual;
+use crate::stereo::{EpipolarStats, StereoDepthStats, StereoRectification};
+use crate::straightness::StraightnessStats;
+use crate::telecentric::TelecentricCalibration;
+use crate::thermal::ThermalDrift;
+use crate::time_offset::TriggerDelay;
+use crate::types::{
+    CalibrationReport, CornerResidual, ErrorHistogram, Extrinsics, RadiusBin, ResidualCell,
+    RvecTvec,
+};
+use crate::validate::ValidationStats;
+use crate::vehicle::{SurveyedBoard, VehicleAlignment};
+use crate::zoom::{ZoomCalibration, ZoomDataset};
 
 pub fn extrinsics_to_json(output_path: &str, extrinsic: &Extrinsics) {
     let j = serde_json::to_string_pretty(extrinsic).unwrap();
@@ -8,14 +45,665 @@ pub fn extrinsics_to_json(output_path: &str, extrinsic: &Extrinsics) {
     file.write_all(j.as_bytes()).unwrap();
 }
 
-pub fn write_report(output_path: &str, with_extrinsic: bool, rep_rms: &[(f64, f64)]) {
+pub fn rolling_shutter_to_json(output_path: &str, rolling_shutter: &RollingShutter) {
+    let j = serde_json::to_string_pretty(rolling_shutter).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn thermal_drift_to_json(output_path: &str, thermal_drift: &ThermalDrift) {
+    let j = serde_json::to_string_pretty(thermal_drift).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn flat_port_to_json(output_path: &str, flat_port: &FlatPort) {
+    let j = serde_json::to_string_pretty(flat_port).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn hand_eye_to_json(output_path: &str, calibration: &HandEyeCalibration) {
+    let j = serde_json::to_string_pretty(calibration).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn camera_lidar_to_json(output_path: &str, calibration: &CameraLidarCalibration) {
+    let j = serde_json::to_string_pretty(calibration).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+/// Time offsets of the cameras to cam0, `t_cam0 = t_cam_i + offset`.
+pub fn time_offsets_to_json(output_path: &str, time_offsets_ns: &[i64]) {
+    let j = serde_json::to_string_pretty(time_offsets_ns).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn trigger_delays_to_json(output_path: &str, trigger_delays: &[TriggerDelay]) {
+    let j = serde_json::to_string_pretty(trigger_delays).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+/// Detections `[cam][frame]` of a session or of `ccrs detect`, to calibrate again, elsewhere or
+/// merged with other sessions.
+pub fn detections_to_json(output_path: &str, cams_frames: &[Vec<Option<FrameFeature>>]) {
+    let j = serde_json::to_string(cams_frames).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn detections_from_json(file_path: &str) -> Vec<Vec<Option<FrameFeature>>> {
+    let contents =
+        std::fs::read_to_string(file_path).expect("Should have been able to read the file");
+    serde_json::from_str(&contents).unwrap()
+}
+
+pub fn session_residuals_to_json(output_path: &str, residuals: &[SessionResidual]) {
+    let j = serde_json::to_string_pretty(residuals).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn coverage_to_json(output_path: &str, scores: &[CoverageScore]) {
+    let j = serde_json::to_string_pretty(scores).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn holdout_to_json(output_path: &str, holdout: &[HoldoutStats]) {
+    let j = serde_json::to_string_pretty(holdout).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn camera_checkpoint_to_json(output_path: &str, checkpoint: &CameraCheckpoint) {
+    let j = serde_json::to_string_pretty(checkpoint).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn camera_checkpoint_from_json(file_path: &str) -> CameraCheckpoint {
+    let contents =
+        std::fs::read_to_string(file_path).expect("Should have been able to read the file");
+    serde_json::from_str(&contents).unwrap()
+}
+
+pub fn dataset_quality_to_json(output_path: &str, qualities: &[DatasetQuality]) {
+    let j = serde_json::to_string_pretty(qualities).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn init_diagnostic_to_json(output_path: &str, diagnostic: &InitDiagnostic) {
+    let j = serde_json::to_string_pretty(diagnostic).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn validation_to_json(output_path: &str, stats: &[ValidationStats]) {
+    let j = serde_json::to_string_pretty(stats).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn frame_influences_to_json(output_path: &str, influences: &[FrameInfluence]) {
+    let j = serde_json::to_string_pretty(influences).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn known_distances_from_json(file_path: &str) -> Vec<KnownDistance> {
+    let contents =
+        std::fs::read_to_string(file_path).expect("Should have been able to read the file");
+    serde_json::from_str(&contents).unwrap()
+}
+
+pub fn known_distance_errors_to_json(output_path: &str, errors: &[KnownDistanceError]) {
+    let j = serde_json::to_string_pretty(errors).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn monte_carlo_to_json(output_path: &str, spreads: &[ParamSpread]) {
+    let j = serde_json::to_string_pretty(spreads).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn observability_to_json(output_path: &str, observability: &[Observability]) {
+    let j = serde_json::to_string_pretty(observability).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn outlier_frames_to_json(output_path: &str, outliers: &[OutlierFrame]) {
+    let j = serde_json::to_string_pretty(outliers).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn param_correlations_to_json(output_path: &str, correlations: &[ParamCorrelation]) {
+    let j = serde_json::to_string_pretty(correlations).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn scale_check_to_json(output_path: &str, scale_check: &ScaleCheck) {
+    let j = serde_json::to_string_pretty(scale_check).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn stereo_depth_to_json(output_path: &str, stats: &[StereoDepthStats]) {
+    let j = serde_json::to_string_pretty(stats).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn benchmark_datasets_from_json(file_path: &str) -> Vec<BenchmarkDataset> {
+    let contents =
+        std::fs::read_to_string(file_path).expect("Should have been able to read the file");
+    serde_json::from_str(&contents).unwrap()
+}
+
+pub fn benchmark_report_to_json(output_path: &str, report: &BenchmarkReport) {
+    let j = serde_json::to_string_pretty(report).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn zoom_datasets_from_json(file_path: &str) -> Vec<ZoomDataset> {
+    let contents =
+        std::fs::read_to_string(file_path).expect("Should have been able to read the file");
+    serde_json::from_str(&contents).unwrap()
+}
+
+pub fn zoom_calibration_to_json(output_path: &str, zoom: &ZoomCalibration) {
+    let j = serde_json::to_string_pretty(zoom).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn zoom_calibration_from_json(file_path: &str) -> ZoomCalibration {
+    let contents =
+        std::fs::read_to_string(file_path).expect("Should have been able to read the file");
+    serde_json::from_str(&contents).unwrap()
+}
+
+pub fn pipeline_profile_to_json(output_path: &str, profile: &PipelineProfile) {
+    let j = serde_json::to_string_pretty(profile).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn inverse_response_to_json(output_path: &str, response: &InverseResponse) {
+    let j = serde_json::to_string_pretty(response).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn line_scan_to_json(output_path: &str, calibration: &LineScanCalibration) {
+    let j = serde_json::to_string_pretty(calibration).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn telecentric_to_json(output_path: &str, calibration: &TelecentricCalibration) {
+    let j = serde_json::to_string_pretty(calibration).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn depth_color_alignment_to_json(output_path: &str, alignment: &DepthColorAlignment) {
+    let j = serde_json::to_string_pretty(alignment).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn multi_plane_config_from_json(file_path: &str) -> MultiPlaneConfig {
+    let contents =
+        std::fs::read_to_string(file_path).expect("Should have been able to read the file");
+    serde_json::from_str(&contents).unwrap()
+}
+
+pub fn multi_plane_config_to_json(output_path: &str, config: &MultiPlaneConfig) {
+    let j = serde_json::to_string_pretty(config).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn surveyed_boards_from_json(file_path: &str) -> Vec<SurveyedBoard> {
+    let contents =
+        std::fs::read_to_string(file_path).expect("Should have been able to read the file");
+    serde_json::from_str(&contents).unwrap()
+}
+
+pub fn vehicle_alignment_to_json(output_path: &str, alignment: &VehicleAlignment) {
+    let j = serde_json::to_string_pretty(alignment).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn extrinsics_from_json(file_path: &str) -> Extrinsics {
+    let contents =
+        std::fs::read_to_string(file_path).expect("Should have been able to read the file");
+    serde_json::from_str(&contents).unwrap()
+}
+
+fn opencv_yaml_matrix<R: na::Dim, C: na::Dim, S: na::RawStorage<f64, R, C>>(
+    name: &str,
+    m: &na::Matrix<f64, R, C, S>,
+) -> String {
+    // OpenCV matrices are row major
+    let data: Vec<_> = m
+        .row_iter()
+        .flat_map(|r| r.iter().map(|v| format!("{:e}", v)).collect::<Vec<_>>())
+        .collect();
+    format!(
+        "{}: !!opencv-matrix\n   rows: {}\n   cols: {}\n   dt: d\n   data: [ {} ]\n",
+        name,
+        m.nrows(),
+        m.ncols(),
+        data.join(", ")
+    )
+}
+
+/// Write `R1`, `R2`, `P1`, `P2` and `Q` in the OpenCV `FileStorage` yaml format.
+pub fn stereo_rectification_to_opencv_yaml(output_path: &str, rectification: &StereoRectification) {
+    let (p0, p1) = rectification.projection_matrices();
+    let mut s = String::from("%YAML:1.0\n---\n");
+    s += format!(
+        "image_width: {}\nimage_height: {}\n",
+        rectification.w_h.0, rectification.w_h.1
+    )
+    .as_str();
+    s += &opencv_yaml_matrix("R1", rectification.r0.matrix());
+    s += &opencv_yaml_matrix("R2", rectification.r1.matrix());
+    s += &opencv_yaml_matrix("P1", &p0);
+    s += &opencv_yaml_matrix("P2", &p1);
+    s += &opencv_yaml_matrix("Q", &rectification.disparity_to_depth());
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(s.as_bytes()).unwrap();
+}
+
+/// Kalibr style 4x4 matrix as a list of rows.
+fn kalibr_yaml_matrix(name: &str, m: &na::Matrix4<f64>) -> String {
+    let mut s = format!("  {}:\n", name);
+    for r in 0..4 {
+        s += format!(
+            "  - [{}, {}, {}, {}]\n",
+            m[(r, 0)],
+            m[(r, 1)],
+            m[(r, 2)],
+            m[(r, 3)]
+        )
+        .as_str();
+    }
+    s
+}
+
+/// Kalibr camera model, intrinsics, distortion model and distortion coeffs. EUCMT and f-theta
+/// have no Kalibr equivalent.
+fn kalibr_intrinsics(
+    model: &GenericModel<f64>,
+) -> Option<(&'static str, Vec<f64>, &'static str, Vec<f64>)> {
+    let p = model.params();
+    match model {
+        GenericModel::EUCM(_) => Some((
+            "eucm",
+            vec![p[4], p[5], p[0], p[1], p[2], p[3]],
+            "none",
+            vec![],
+        )),
+        GenericModel::UCM(_) => {
+            // kalibr omni is the unified model with xi instead of alpha
+            let s = 1.0 / (1.0 - p[4]);
+            Some((
+                "omni",
+                vec![p[4] * s, p[0] * s, p[1] * s, p[2], p[3]],
+                "none",
+                vec![],
+            ))
+        }
+        GenericModel::OpenCVModel5(_) => {
+            if p[8] != 0.0 {
+                tracing::warn!("kalibr radtan has no k3, dropped k3 {}", p[8]);
+            }
+            Some((
+                "pinhole",
+                vec![p[0], p[1], p[2], p[3]],
+                "radtan",
+                vec![p[4], p[5], p[6], p[7]],
+            ))
+        }
+        GenericModel::KannalaBrandt4(_) => Some((
+            "pinhole",
+            vec![p[0], p[1], p[2], p[3]],
+            "equidistant",
+            vec![p[4], p[5], p[6], p[7]],
+        )),
+        GenericModel::EUCMT(_) | GenericModel::Ftheta(_) => None,
+    }
+}
+
+fn yaml_list(v: &[f64]) -> String {
+    let v: Vec<_> = v.iter().map(|v| v.to_string()).collect();
+    format!("[{}]", v.join(", "))
+}
+
+/// Kalibr style `camchain.yaml` of a rig with the intrinsics of each camera, `T_cn_cnm1` from
+/// the previous camera to this one and `T_rig_cam` from this camera to cam0, the rig frame.
+/// Fails for the models without a Kalibr equivalent, EUCMT and f-theta.
+pub fn camchain_yaml(models: &[GenericModel<f64>], t_i_0: &[RvecTvec]) -> Result<String, String> {
     let mut s = String::new();
-    s += format!("Calibrate with extrinsics: {}\n\n", with_extrinsic).as_str();
-    for (cam_idx, &(avg_rep, med_rep)) in rep_rms.iter().enumerate() {
+    for (cam_idx, (model, t_cam_rig)) in models.iter().zip(t_i_0).enumerate() {
+        let (camera_model, intrinsics, distortion_model, distortion_coeffs) =
+            kalibr_intrinsics(model)
+                .ok_or_else(|| format!("cam{} model has no kalibr equivalent", cam_idx))?;
+        s += format!(
+            "cam{}:\n  camera_model: {}\n  intrinsics: {}\n  distortion_model: {}\n  distortion_coeffs: {}\n",
+            cam_idx,
+            camera_model,
+            yaml_list(&intrinsics),
+            distortion_model,
+            yaml_list(&distortion_coeffs)
+        )
+        .as_str();
+        s += format!(
+            "  resolution: [{}, {}]\n",
+            model.width() as u32,
+            model.height() as u32
+        )
+        .as_str();
+        let t_cam_rig = t_cam_rig.to_na_isometry3();
+        if cam_idx > 0 {
+            let t_cn_cnm1 = t_cam_rig * t_i_0[cam_idx - 1].to_na_isometry3().inverse();
+            s += &kalibr_yaml_matrix("T_cn_cnm1", &t_cn_cnm1.to_homogeneous());
+        }
+        s += &kalibr_yaml_matrix("T_rig_cam", &t_cam_rig.inverse().to_homogeneous());
+    }
+    Ok(s)
+}
+
+/// Writes `camchain_yaml`, nothing is written for a model without a Kalibr equivalent.
+pub fn camchain_to_yaml(
+    output_path: &str,
+    models: &[GenericModel<f64>],
+    t_i_0: &[RvecTvec],
+) -> Result<(), String> {
+    let s = camchain_yaml(models, t_i_0)?;
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(s.as_bytes()).unwrap();
+    Ok(())
+}
+
+/// Kalibr style `camchain-imucam.yaml` with `T_cam_imu` and `timeshift_cam_imu` of each camera.
+pub fn camchain_imucam_to_yaml(output_path: &str, calibrations: &[(usize, CamImuCalibration)]) {
+    let mut s = String::new();
+    for (cam_idx, calib) in calibrations {
         s += format!("cam{}:\n", cam_idx).as_str();
-        s += format!("    average reprojection error: {:.5} px\n", avg_rep).as_str();
-        s += format!("    median  reprojection error: {:.5} px\n\n", med_rep).as_str();
+        s += &kalibr_yaml_matrix("T_cam_imu", &calib.t_cam_imu.to_homogeneous());
+        s += format!(
+            "  timeshift_cam_imu: {}\n",
+            calib.time_offset_ns as f64 * 1e-9
+        )
+        .as_str();
+    }
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(s.as_bytes()).unwrap();
+}
+
+pub fn calibration_reports_to_json(output_path: &str, reports: &[CalibrationReport]) {
+    let j = serde_json::to_string_pretty(reports).unwrap();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+}
+
+pub fn write_report(
+    output_path: &str,
+    with_extrinsic: bool,
+    reports: &[CalibrationReport],
+    straightness: &[StraightnessStats],
+    epipolar: &[EpipolarStats],
+) {
+    let mut s = String::new();
+    s += format!("Calibrate with extrinsics: {}\n\n", with_extrinsic).as_str();
+    for (report, lines) in reports.iter().zip(straightness) {
+        s += format!("cam{}:\n", report.cam_idx).as_str();
+        s += format!(
+            "    average reprojection error: {:.5} px\n",
+            report.avg_99_percent
+        )
+        .as_str();
+        s += format!(
+            "    median  reprojection error: {:.5} px\n",
+            report.median_error
+        )
+        .as_str();
+        s += format!(
+            "    99%     reprojection error: {:.5} px\n",
+            report.p99_error
+        )
+        .as_str();
+        s += format!(
+            "    reprojection errors above {} px: {} of {}\n",
+            report.histogram.max_error, report.histogram.overflow, report.num_points
+        )
+        .as_str();
+        s += format!(
+            "    median  straightness error: {:.5} px of {} lines\n\n",
+            lines.median_error, lines.num_lines
+        )
+        .as_str();
+    }
+    for stats in epipolar {
+        s += format!("cam{} - cam{}:\n", stats.cam0, stats.cam1).as_str();
+        s += format!("    average epipolar error: {:.5} px\n", stats.mean_error).as_str();
+        s += format!("    median  epipolar error: {:.5} px\n", stats.median_error).as_str();
+        s += format!("    max     epipolar error: {:.5} px\n\n", stats.max_error).as_str();
+    }
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(s.as_bytes()).unwrap();
+}
+
+pub fn write_corner_residuals_csv(output_path: &str, residuals: &[CornerResidual]) {
+    let mut s = String::from("frame_idx,time_ns,corner_id,u,v,du,dv,norm\n");
+    for r in residuals {
+        s += format!(
+            "{},{},{},{:.4},{:.4},{:.6},{:.6},{:.6}\n",
+            r.frame_idx, r.time_ns, r.corner_id, r.u, r.v, r.du, r.dv, r.norm
+        )
+        .as_str();
+    }
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(s.as_bytes()).unwrap();
+}
+
+pub fn write_error_histogram_csv(output_path: &str, histogram: &ErrorHistogram) {
+    let mut s = String::from("error_begin,error_end,count\n");
+    for (i, count) in histogram.counts.iter().enumerate() {
+        s += format!(
+            "{:.4},{:.4},{}\n",
+            i as f64 * histogram.bin_size,
+            (i + 1) as f64 * histogram.bin_size,
+            count
+        )
+        .as_str();
     }
+    s += format!("{:.4},inf,{}\n", histogram.max_error, histogram.overflow).as_str();
     let mut file = std::fs::File::create(output_path).unwrap();
     file.write_all(s.as_bytes()).unwrap();
 }
+
+pub fn write_residual_grid_csv(output_path: &str, cells: &[ResidualCell]) {
+    let mut s = String::from("row,col,count,mean_error,mean_du,mean_dv\n");
+    for c in cells {
+        s += format!(
+            "{},{},{},{:.6},{:.6},{:.6}\n",
+            c.row, c.col, c.count, c.mean_error, c.mean_du, c.mean_dv
+        )
+        .as_str();
+    }
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(s.as_bytes()).unwrap();
+}
+
+pub fn write_residual_vs_radius_csv(output_path: &str, bins: &[RadiusBin]) {
+    let mut s = String::from("radius_begin,radius_end,count,mean_error,median_error\n");
+    for b in bins {
+        s += format!(
+            "{},{},{},{:.6},{:.6}\n",
+            b.radius_begin, b.radius_end, b.count, b.mean_error, b.median_error
+        )
+        .as_str();
+    }
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(s.as_bytes()).unwrap();
+}
+
+/// `pcalib.txt` of DSO, the 256 values of the inverse response on one line.
+pub fn inverse_response_to_pcalib(output_path: &str, response: &InverseResponse) {
+    let values: Vec<_> = response
+        .values
+        .iter()
+        .map(|v| format!("{:.6}", v))
+        .collect();
+    let mut file = std::fs::File::create(output_path).unwrap();
+    file.write_all(format!("{}\n", values.join(" ")).as_bytes())
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use camera_intrinsic_model::{KannalaBrandt4, OpenCVModel5, EUCMT};
+
+    use super::*;
+    use crate::test_util::rtvec;
+
+    /// Kalibr output of the EuRoC stereo rig.
+    const EUROC_CAMCHAIN: &str = "cam0:
+  cam_overlaps: [1]
+  camera_model: pinhole
+  distortion_coeffs: [-0.28340811, 0.07395907, 0.00019359, 1.76187114e-05]
+  distortion_model: radtan
+  intrinsics: [458.654, 457.296, 367.215, 248.375]
+  resolution: [752, 480]
+  rostopic: /cam0/image_raw
+cam1:
+  T_cn_cnm1:
+  - [0.9999972564779, 0.002312067192424, 0.000376008102415, -0.110073808127187]
+  - [-0.002317135723281, 0.999898048506644, 0.014089835846683, 0.000399121547014]
+  - [-0.000343393120525, -0.014090668452714, 0.999900662637729, -0.000853702503357]
+  - [0.0, 0.0, 0.0, 1.0]
+  cam_overlaps: [0]
+  camera_model: pinhole
+  distortion_coeffs: [-0.28368365, 0.07451284, -0.00010473, -3.55590700e-05]
+  distortion_model: equidistant
+  intrinsics: [457.587, 456.134, 379.999, 255.238]
+  resolution: [752, 480]
+  rostopic: /cam1/image_raw
+";
+
+    /// The numeric and the string fields of each camera, a matrix is flattened row by row.
+    type KalibrCam = (HashMap<String, Vec<f64>>, HashMap<String, String>);
+
+    fn parse_camchain(s: &str) -> Vec<KalibrCam> {
+        let mut cams: Vec<KalibrCam> = Vec::new();
+        let mut key = String::new();
+        for line in s.lines() {
+            if !line.starts_with(' ') {
+                cams.push(Default::default());
+                continue;
+            }
+            let (numbers, strings) = cams.last_mut().unwrap();
+            let line = line.trim();
+            let (k, v) = match line.strip_prefix("- ") {
+                Some(row) => (key.clone(), row),
+                None => {
+                    let (k, v) = line.split_once(':').unwrap();
+                    key = k.to_string();
+                    (key.clone(), v.trim())
+                }
+            };
+            if let Some(list) = v.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+                numbers
+                    .entry(k)
+                    .or_default()
+                    .extend(list.split(", ").map(|v| v.parse::<f64>().unwrap()));
+            } else if !v.is_empty() {
+                strings.insert(k, v.to_string());
+            }
+        }
+        cams
+    }
+
+    #[test]
+    fn camchain_round_trip() {
+        let cams = parse_camchain(EUROC_CAMCHAIN);
+        let models: Vec<_> = cams
+            .iter()
+            .map(|(n, s)| {
+                let (i, d) = (&n["intrinsics"], &n["distortion_coeffs"]);
+                let r = &n["resolution"];
+                let params = na::dvector![i[0], i[1], i[2], i[3], d[0], d[1], d[2], d[3]];
+                let (w, h) = (r[0] as u32, r[1] as u32);
+                match s["distortion_model"].as_str() {
+                    "radtan" => {
+                        GenericModel::OpenCVModel5(OpenCVModel5::new(&params.push(0.0), w, h))
+                    }
+                    _ => GenericModel::KannalaBrandt4(KannalaBrandt4::new(&params, w, h)),
+                }
+            })
+            .collect();
+        let t = &cams[1].0["T_cn_cnm1"];
+        let t_1_0 = na::Matrix4::from_row_slice(t);
+        let rotation = na::Rotation3::from_matrix(&t_1_0.fixed_view::<3, 3>(0, 0).into_owned());
+        let t_1_0 = na::Isometry3::from_parts(
+            na::Translation3::new(t[3], t[7], t[11]),
+            na::UnitQuaternion::from_rotation_matrix(&rotation),
+        );
+        let t_i_0 = vec![rtvec(&na::Isometry3::identity()), rtvec(&t_1_0)];
+
+        let written = parse_camchain(&camchain_yaml(&models, &t_i_0).unwrap());
+        assert_eq!(written.len(), cams.len());
+        for (w, c) in written.iter().zip(&cams) {
+            for k in ["camera_model", "distortion_model"] {
+                assert_eq!(w.1[k], c.1[k]);
+            }
+            for k in ["intrinsics", "distortion_coeffs", "resolution"] {
+                assert_eq!(w.0[k], c.0[k]);
+            }
+        }
+        let t_written = &written[1].0["T_cn_cnm1"];
+        for (a, b) in t_written.iter().zip(t) {
+            assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+        }
+        let t_rig_cam1 = na::Matrix4::from_row_slice(&written[1].0["T_rig_cam"]);
+        let identity = t_rig_cam1 * na::Matrix4::from_row_slice(t_written);
+        assert!((identity - na::Matrix4::identity()).norm() < 1e-9);
+    }
+
+    #[test]
+    fn camchain_refuses_models_without_kalibr_equivalent() {
+        let eucmt = GenericModel::EUCMT(EUCMT::new(
+            &na::dvector![480.0, 482.0, 640.0, 400.0, 0.6, 1.1, 0.0, 0.0],
+            1280,
+            800,
+        ));
+        let models = [crate::test_util::eucm(), eucmt];
+        let t_i_0 = vec![rtvec(&na::Isometry3::identity()); 2];
+        let err = camchain_yaml(&models, &t_i_0).unwrap_err();
+        assert!(err.contains("cam1"));
+
+        let path = std::env::temp_dir().join("camchain_refused.yaml");
+        let _ = std::fs::remove_file(&path);
+        assert!(camchain_to_yaml(path.to_str().unwrap(), &models, &t_i_0).is_err());
+        assert!(!path.exists());
+    }
+}
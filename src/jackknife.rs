@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use camera_intrinsic_model::*;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::detected_points::FrameFeature;
+use crate::observer::NoopObserver;
+use crate::optimization::IncrementalProblem;
+use crate::types::{CalibParams, RvecTvec};
+
+/// Frames moving the focal more than this in px when left out are reported.
+pub const MAX_FOCAL_SHIFT: f64 = 2.0;
+
+/// How much the intrinsics move when a frame is left out of the calibration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameInfluence {
+    pub cam_idx: usize,
+    pub frame_idx: usize,
+    pub time_ns: i64,
+    /// Params without the frame minus the params with all frames, in the layout of
+    /// `camera.params()`.
+    pub param_shifts: Vec<f64>,
+    /// Largest shift of fx and fy in px.
+    pub focal_shift: f64,
+}
+
+/// Jackknife of the calibration, every frame with a board pose is left out once and the camera
+/// is calibrated again starting from `model` and the board poses. Sorted by the focal shift, a
+/// frame that shifts the focal by several px on its own is almost always bad data.
+pub fn leave_one_out(
+    cam_idx: usize,
+    model: &GenericModel<f64>,
+    rtvecs: &HashMap<usize, RvecTvec>,
+    frame_feature_list: &[Option<FrameFeature>],
+    calib_params: &CalibParams,
+) -> Vec<FrameInfluence> {
+    let params = model.params();
+    let frame_indexes: Vec<_> = rtvecs
+        .keys()
+        .copied()
+        .filter(|&i| matches!(frame_feature_list.get(i), Some(Some(_))))
+        .collect();
+    let mut influences: Vec<_> = frame_indexes
+        .par_iter()
+        .filter_map(|&i| {
+            let mut problem = IncrementalProblem::from_calibration(
+                model,
+                rtvecs,
+                frame_feature_list,
+                calib_params,
+            );
+            let time_ns = problem.remove_frame(i)?.time_ns;
+            let (calibrated, _) = problem.solve(&NoopObserver)?;
+            let param_shifts: Vec<_> = (calibrated.params() - &params).iter().copied().collect();
+            Some(FrameInfluence {
+                cam_idx,
+                frame_idx: i,
+                time_ns,
+                focal_shift: param_shifts[0].abs().max(param_shifts[1].abs()),
+                param_shifts,
+            })
+        })
+        .collect();
+    influences.sort_by(|a, b| b.focal_shift.partial_cmp(&a.focal_shift).unwrap());
+    influences
+}
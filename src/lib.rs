@@ -1,8 +1,69 @@
+pub mod adjust;
+pub mod batch;
+pub mod benchmark;
+pub mod blur;
 pub mod board;
+pub mod checkpoint;
+pub mod compare;
+pub mod consistency;
+pub mod coverage;
+#[cfg(feature = "io")]
 pub mod data_loader;
 pub mod detected_points;
+pub mod dry_run;
+pub mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod frame_filter;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+pub mod hand_eye;
+pub mod holdout;
+pub mod imu;
+pub mod incremental;
+pub mod init_diagnostic;
+#[cfg(feature = "io")]
 pub mod io;
+pub mod jackknife;
+pub mod lidar;
+pub mod line_scan;
+pub mod logging;
+pub mod lut;
+pub mod monte_carlo;
+pub mod multi_plane;
+pub mod observability;
+pub mod observer;
 pub mod optimization;
+pub mod outliers;
+pub mod overlay;
+#[cfg(feature = "io")]
+pub mod profile;
+pub mod projector;
+pub mod refraction;
+pub mod remap;
+pub mod response;
+pub mod rgbd;
+pub mod rolling_shutter;
+pub mod scale;
+#[cfg(feature = "service")]
+pub mod service;
+pub mod session;
+pub mod spline;
+pub mod stereo;
+pub mod straightness;
+pub mod subsample;
+pub mod telecentric;
+#[cfg(test)]
+mod test_util;
+pub mod thermal;
+pub mod thermal_detection;
+pub mod time_offset;
 pub mod types;
+pub mod undistort;
 pub mod util;
+pub mod validate;
+pub mod vehicle;
 pub mod visualization;
+#[cfg(feature = "io")]
+pub mod watch;
+pub mod zoom;
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use nalgebra as na;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use tiny_solver::loss_functions::HuberLoss;
+use tiny_solver::Optimizer;
+
+use crate::detected_points::FrameFeature;
+use crate::optimization::factors::LidarBoardPointFactor;
+use crate::types::{DVecVec3, RvecTvec, ToRvecTvec};
+
+const RANSAC_ITERATIONS: usize = 500;
+/// Planes found one after another in a scan until one has the size of the board.
+const MAX_PLANES: usize = 5;
+const MIN_PLANE_POINTS: usize = 30;
+
+pub struct LidarCalibParams {
+    /// Max distance of the board points to the plane in m.
+    pub plane_threshold: f64,
+    /// Max time difference of a scan and an image.
+    pub max_time_diff_ns: i64,
+    /// Size of the board outside of the corners in m.
+    pub board_margin: f64,
+}
+
+impl Default for LidarCalibParams {
+    fn default() -> Self {
+        LidarCalibParams {
+            plane_threshold: 0.02,
+            max_time_diff_ns: 20_000_000,
+            board_margin: 0.05,
+        }
+    }
+}
+
+/// Result of `calibrate_camera_lidar`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraLidarCalibration {
+    /// Transforms points from the lidar to the camera.
+    pub t_cam_lidar: RvecTvec,
+    pub num_scans: usize,
+    /// Distance of the board points to the board plane of the camera in m.
+    pub plane_rms: f64,
+}
+
+/// `(normal, d)` of the plane `normal * p = d`.
+type Plane = (na::Vector3<f64>, f64);
+
+/// Plane through the points, the normal points to the origin.
+fn fit_plane(points: &[na::Vector3<f64>]) -> Option<Plane> {
+    let centroid = points.iter().sum::<na::Vector3<f64>>() / points.len() as f64;
+    let covariance = points
+        .iter()
+        .map(|p| (p - centroid) * (p - centroid).transpose())
+        .sum::<na::Matrix3<f64>>();
+    let eigen = covariance.symmetric_eigen();
+    let (min_idx, _) = eigen
+        .eigenvalues
+        .iter()
+        .enumerate()
+        .min_by(|a, b| a.1.partial_cmp(b.1).unwrap())?;
+    let mut normal = eigen.eigenvectors.column(min_idx).into_owned();
+    if normal.dot(&centroid) > 0.0 {
+        normal = -normal;
+    }
+    Some((normal, normal.dot(&centroid)))
+}
+
+/// Points of the largest plane, RANSAC.
+fn ransac_plane(points: &[na::Vector3<f64>], threshold: f64) -> Option<Vec<usize>> {
+    let mut rng = rand::thread_rng();
+    let mut best = Vec::new();
+    for _ in 0..RANSAC_ITERATIONS {
+        let sample: Vec<_> = points.choose_multiple(&mut rng, 3).collect();
+        if sample.len() < 3 {
+            return None;
+        }
+        let normal = (sample[1] - sample[0]).cross(&(sample[2] - sample[0]));
+        let Some(normal) = normal.try_normalize(1e-9) else {
+            continue;
+        };
+        let d = normal.dot(sample[0]);
+        let inliers: Vec<_> = (0..points.len())
+            .filter(|&i| (normal.dot(&points[i]) - d).abs() < threshold)
+            .collect();
+        if inliers.len() > best.len() {
+            best = inliers;
+        }
+    }
+    (best.len() >= MIN_PLANE_POINTS).then_some(best)
+}
+
+/// Points of the first plane no larger than the board, e.g. not the ground or a wall.
+fn board_points(
+    scan: &[na::Vector3<f64>],
+    board_radius: f64,
+    threshold: f64,
+) -> Option<Vec<na::Vector3<f64>>> {
+    let mut remaining = scan.to_vec();
+    for _ in 0..MAX_PLANES {
+        let inliers = ransac_plane(&remaining, threshold)?;
+        let plane: Vec<_> = inliers.iter().map(|&i| remaining[i]).collect();
+        let centroid = plane.iter().sum::<na::Vector3<f64>>() / plane.len() as f64;
+        if plane.iter().all(|p| (p - centroid).norm() < board_radius) {
+            return Some(plane);
+        }
+        let mut is_inlier = vec![false; remaining.len()];
+        for i in inliers {
+            is_inlier[i] = true;
+        }
+        remaining = remaining
+            .into_iter()
+            .zip(is_inlier)
+            .filter_map(|(p, inlier)| (!inlier).then_some(p))
+            .collect();
+    }
+    None
+}
+
+/// Rotation and translation mapping the lidar planes to the camera planes, needs three planes
+/// with independent normals.
+fn init_from_planes(planes: &[(Plane, Plane)]) -> Option<na::Isometry3<f64>> {
+    let m = planes
+        .iter()
+        .map(|((n_c, _), (n_l, _))| n_c * n_l.transpose())
+        .sum::<na::Matrix3<f64>>();
+    let svd = m.svd(true, true);
+    let (u, v_t) = (svd.u?, svd.v_t?);
+    let d = (u * v_t).determinant().signum();
+    let r = na::Rotation3::from_matrix_unchecked(
+        u * na::Matrix3::from_diagonal(&na::Vector3::new(1.0, 1.0, d)) * v_t,
+    );
+    // n_c * t = d_c - d_l
+    let lhs = na::DMatrix::from_fn(planes.len(), 3, |r, c| planes[r].0 .0[c]);
+    let rhs = na::DVector::from_fn(planes.len(), |r, _| planes[r].0 .1 - planes[r].1 .1);
+    let t = lhs.svd(true, true).solve(&rhs, 1e-12).ok()?;
+    Some(na::Isometry3::from_parts(
+        na::Vector3::new(t[0], t[1], t[2]).into(),
+        r.into(),
+    ))
+}
+
+/// Estimates the lidar to camera transform from lidar scans `(time_ns, points)` of the board
+/// and the board poses `rtvecs` of the camera frames taken at the same time.
+/// The board is the first plane in a scan no larger than the board, so it should be held away
+/// from other objects. The planes initialize the transform, which is refined with the distances
+/// of the board points to the board plane and the board extent of the camera.
+pub fn calibrate_camera_lidar(
+    scans: &[(i64, Vec<na::Vector3<f64>>)],
+    frame_feature_list: &[Option<FrameFeature>],
+    rtvecs: &HashMap<usize, RvecTvec>,
+    params: &LidarCalibParams,
+) -> Option<CameraLidarCalibration> {
+    let (board_min, board_max) = frame_feature_list
+        .iter()
+        .flatten()
+        .flat_map(|f| f.features.values())
+        .fold(
+            (
+                na::Vector2::repeat(f64::INFINITY),
+                na::Vector2::repeat(f64::NEG_INFINITY),
+            ),
+            |(min, max), fp| {
+                let p = na::Vector2::new(fp.p3d.x as f64, fp.p3d.y as f64);
+                (min.inf(&p), max.sup(&p))
+            },
+        );
+    let board_min = board_min - na::Vector2::repeat(params.board_margin);
+    let board_max = board_max + na::Vector2::repeat(params.board_margin);
+    let board_radius = (board_max - board_min).norm() / 2.0 + params.plane_threshold;
+
+    let mut frames = Vec::new();
+    for (time_ns, scan) in scans {
+        let Some((frame_idx, _)) = frame_feature_list
+            .iter()
+            .enumerate()
+            .filter_map(|(i, f)| Some((i, (f.as_ref()?.time_ns - time_ns).abs())))
+            .filter(|(i, dt)| *dt <= params.max_time_diff_ns && rtvecs.contains_key(i))
+            .min_by_key(|(_, dt)| *dt)
+        else {
+            continue;
+        };
+        let Some(points) = board_points(scan, board_radius, params.plane_threshold) else {
+            tracing::warn!("board not found in the lidar scan {}", time_ns);
+            continue;
+        };
+        let t_cam_board = rtvecs[&frame_idx].to_na_isometry3();
+        let mut normal = t_cam_board.rotation * na::Vector3::z();
+        if normal.dot(&t_cam_board.translation.vector) > 0.0 {
+            normal = -normal;
+        }
+        let camera_plane = (normal, normal.dot(&t_cam_board.translation.vector));
+        frames.push((t_cam_board, camera_plane, fit_plane(&points)?, points));
+    }
+    if frames.len() < 3 {
+        tracing::warn!("not enough lidar scans with the board for camera-lidar calibration");
+        return None;
+    }
+    let planes: Vec<_> = frames.iter().map(|f| (f.1, f.2)).collect();
+    let t_cam_lidar = init_from_planes(&planes)?;
+
+    let mut problem = tiny_solver::Problem::new();
+    for (t_cam_board, _, _, points) in &frames {
+        for p in points {
+            let cost = LidarBoardPointFactor::new(p, t_cam_board, &board_min, &board_max);
+            problem.add_residual_block(
+                3,
+                &[("rvec", 3), ("tvec", 3)],
+                Box::new(cost),
+                Some(Box::new(HuberLoss::new(params.plane_threshold))),
+            );
+        }
+    }
+    let init = t_cam_lidar.to_rvec_tvec();
+    let initial_values = HashMap::from([
+        ("rvec".to_string(), init.na_rvec()),
+        ("tvec".to_string(), init.na_tvec()),
+    ]);
+    let result = tiny_solver::GaussNewtonOptimizer {}.optimize(&problem, &initial_values, None)?;
+    let t_cam_lidar = na::Isometry3::new(result["tvec"].to_vec3(), result["rvec"].to_vec3());
+
+    let distances: Vec<_> = frames
+        .iter()
+        .flat_map(|(t_cam_board, _, _, points)| {
+            let t_board_lidar = t_cam_board.inverse() * t_cam_lidar;
+            points
+                .iter()
+                .map(move |p| (t_board_lidar * na::Point3::from(*p)).z)
+        })
+        .collect();
+    let plane_rms = (distances.iter().map(|d| d * d).sum::<f64>() / distances.len() as f64).sqrt();
+    tracing::info!(
+        "camera-lidar calibration of {} scans, plane rms {:.4} m",
+        frames.len(),
+        plane_rms
+    );
+    Some(CameraLidarCalibration {
+        t_cam_lidar: t_cam_lidar.to_rvec_tvec(),
+        num_scans: frames.len(),
+        plane_rms,
+    })
+}
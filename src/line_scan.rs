@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+
+use nalgebra as na;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use tiny_solver::loss_functions::HuberLoss;
+
+use crate::detected_points::FrameFeature;
+use crate::observer::PipelineObserver;
+use crate::optimization::factors::LineScanReprojectionFactor;
+use crate::optimization::{rvec_name, tvec_name};
+use crate::types::{RvecTvec, ToRvecTvec};
+use crate::util::optimize_with_observer;
+
+/// Corners of a sweep below which its initial pose isn't solved.
+const MIN_SWEEP_CORNERS: usize = 10;
+/// Focals tried for the initialization, log spaced.
+const FOCAL_CANDIDATES: usize = 100;
+/// Max velocity along x or z over the one along y tried for the initialization, the motion
+/// has to be roughly perpendicular to the sensor line and parallel to the image plane.
+const MAX_SKEW: f64 = 0.25;
+const SKEW_CANDIDATES: usize = 10;
+
+/// Line-scan camera, a 1 x `width` sensor swept over the scene by a motion of `velocity` per
+/// line. A point `p` in the camera frame at line 0 is seen at the line `t` where `p - velocity *
+/// t` crosses the viewing plane `y = 0` of the sensor, at the column of its x along the line
+/// with the radial distortion `k1, k2`. An offset of the sensor from the optical axis only
+/// tilts the viewing plane, which the velocity and the board poses absorb.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LineScanModel {
+    pub f: f64,
+    pub cu: f64,
+    pub k1: f64,
+    pub k2: f64,
+    /// Motion of the camera between two lines in the camera frame in m, constant like the
+    /// encoder triggered lines of a conveyor or a linear stage.
+    pub velocity: [f64; 3],
+    pub width: u32,
+}
+
+/// Column and line of `p` with the intrinsics `[f, cu, k1, k2]`.
+pub(crate) fn line_scan_project<T: na::RealField>(
+    intrinsics: &na::DVector<T>,
+    velocity: &na::Vector3<T>,
+    p: &na::Vector3<T>,
+) -> na::Vector2<T> {
+    let t = p.y.clone() / velocity.y.clone();
+    let p_t = p - velocity * t.clone();
+    let x = p_t.x.clone() / p_t.z.clone();
+    let r2 = x.clone() * x.clone();
+    let distortion =
+        T::one() + intrinsics[2].clone() * r2.clone() + intrinsics[3].clone() * r2.clone() * r2;
+    na::Vector2::new(
+        intrinsics[1].clone() + intrinsics[0].clone() * x * distortion,
+        t,
+    )
+}
+
+impl LineScanModel {
+    fn intrinsics(&self) -> na::DVector<f64> {
+        na::dvector![self.f, self.cu, self.k1, self.k2]
+    }
+
+    /// Column and line of a point in the camera frame at line 0.
+    pub fn project_one(&self, p3d: &na::Vector3<f64>) -> na::Vector2<f64> {
+        line_scan_project(&self.intrinsics(), &na::Vector3::from(self.velocity), p3d)
+    }
+}
+
+/// Result of `calib_line_scan`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LineScanCalibration {
+    pub model: LineScanModel,
+    /// Board to camera poses at line 0 of every sweep.
+    pub rtvecs: HashMap<usize, RvecTvec>,
+    pub rms_error: f64,
+}
+
+/// Least squares solution of `a x = b`.
+fn least_squares(a: &na::DMatrix<f64>, b: &na::DVector<f64>) -> Option<na::DVector<f64>> {
+    (a.transpose() * a)
+        .cholesky()
+        .map(|c| c.solve(&(a.transpose() * b)))
+}
+
+/// The line of a sweep is affine in the board coordinates, `[alpha, beta, gamma]`, and the
+/// column a 1d homography of them, `u (d X + e Y + 1) = a X + b Y + c` with the principal
+/// point at `cu`. Exact without distortion, the line is the y row of the board pose over the
+/// speed along y and the homography the x and z rows sheared by the velocity over the depth.
+struct SweepFit {
+    line: na::Vector3<f64>,
+    column: na::Vector5<f64>,
+}
+
+/// Initial focal and velocity `[kx, 1, kz] * speed` of the sweeps.
+#[derive(Debug, Clone, Copy)]
+struct SweepMotion {
+    f: f64,
+    kx: f64,
+    kz: f64,
+}
+
+impl SweepFit {
+    fn new(frame: &FrameFeature, cu: f64) -> Option<SweepFit> {
+        let corners: Vec<_> = frame.features.values().collect();
+        if corners.len() < MIN_SWEEP_CORNERS {
+            return None;
+        }
+        let n = corners.len();
+        let rows = na::DMatrix::from_fn(n, 3, |i, j| match j {
+            0 => corners[i].p3d.x as f64,
+            1 => corners[i].p3d.y as f64,
+            _ => 1.0,
+        });
+        let lines = na::DVector::from_fn(n, |i, _| corners[i].p2d.y as f64);
+        let line = least_squares(&rows, &lines)?;
+        let columns = na::DMatrix::from_fn(n, 5, |i, j| {
+            let (x, y, u) = (
+                corners[i].p3d.x as f64,
+                corners[i].p3d.y as f64,
+                corners[i].p2d.x as f64 - cu,
+            );
+            [x, y, 1.0, -u * x, -u * y][j]
+        });
+        let us = na::DVector::from_fn(n, |i, _| corners[i].p2d.x as f64 - cu);
+        let column = least_squares(&columns, &us)?;
+        Some(SweepFit {
+            line: na::Vector3::from_column_slice(line.as_slice()),
+            column: na::Vector5::from_column_slice(column.as_slice()),
+        })
+    }
+
+    /// Parts of the first two columns of the rotation scaled by the depth and by the speed,
+    /// `r = depth * u + speed * w`.
+    fn rotation_columns(&self, motion: &SweepMotion) -> [(na::Vector3<f64>, na::Vector3<f64>); 2] {
+        let [a, b, _, d, e] = self.column.into();
+        let (alpha, beta) = (self.line[0], self.line[1]);
+        let shear = |l: f64| na::Vector3::new(motion.kx * l, l, motion.kz * l);
+        [
+            (na::Vector3::new(a / motion.f, 0.0, d), shear(alpha)),
+            (na::Vector3::new(b / motion.f, 0.0, e), shear(beta)),
+        ]
+    }
+
+    /// Depth and speed of the sweep which make the first two columns of the rotation closest
+    /// to orthonormal, and the error left, `None` if there is no real solution.
+    fn depth_speed(&self, motion: &SweepMotion) -> Option<(f64, f64, f64)> {
+        let [(u1, w1), (u2, w2)] = self.rotation_columns(motion);
+        // [depth^2, depth * speed, speed^2] of |r1| = |r2| = 1 and r1 . r2 = 0
+        let m = na::Matrix3::new(
+            u1.norm_squared(),
+            2.0 * u1.dot(&w1),
+            w1.norm_squared(),
+            u2.norm_squared(),
+            2.0 * u2.dot(&w2),
+            w2.norm_squared(),
+            u1.dot(&u2),
+            u1.dot(&w2) + w1.dot(&u2),
+            w1.dot(&w2),
+        );
+        let b = na::Vector3::new(1.0, 1.0, 0.0);
+        let x = m.svd(true, true).solve(&b, 1e-15).ok()?;
+        if x[0] <= 0.0 || x[2] <= 0.0 {
+            return None;
+        }
+        let (depth, speed) = (x[0].sqrt(), x[2].sqrt());
+        let x = na::Vector3::new(x[0], depth * speed, x[2]);
+        Some((depth, speed, (m * x - b).norm()))
+    }
+
+    fn pose(&self, motion: &SweepMotion, depth: f64, speed: f64) -> na::Isometry3<f64> {
+        let [(u1, w1), (u2, w2)] = self.rotation_columns(motion);
+        let (r1, r2) = (u1 * depth + w1 * speed, u2 * depth + w2 * speed);
+        let rotation =
+            na::Rotation3::from_matrix(&na::Matrix3::from_columns(&[r1, r2, r1.cross(&r2)]));
+        let gamma = speed * self.line[2];
+        let translation = na::Vector3::new(
+            depth * self.column[2] / motion.f + motion.kx * gamma,
+            gamma,
+            depth + motion.kz * gamma,
+        );
+        na::Isometry3::from_parts(translation.into(), rotation.into())
+    }
+}
+
+/// Focal in `[width / 10, width * 10]` and velocity within `MAX_SKEW` of the y axis with the
+/// most orthonormal board poses of the sweeps.
+fn init_motion(fits: &[&SweepFit], width: f64) -> Option<SweepMotion> {
+    let skews: Vec<_> = (-(SKEW_CANDIDATES as i64)..=SKEW_CANDIDATES as i64)
+        .map(|k| MAX_SKEW * k as f64 / SKEW_CANDIDATES as f64)
+        .collect();
+    let motions: Vec<_> = (0..=FOCAL_CANDIDATES)
+        .map(|k| width * 10f64.powf(2.0 * k as f64 / FOCAL_CANDIDATES as f64 - 1.0))
+        .flat_map(|f| {
+            skews
+                .iter()
+                .flat_map(|&kx| skews.iter().map(move |&kz| SweepMotion { f, kx, kz }))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    motions
+        .into_par_iter()
+        .map(|motion| {
+            let error: f64 = fits
+                .iter()
+                .map(|fit| fit.depth_speed(&motion).map_or(1.0, |(_, _, e)| e.min(1.0)))
+                .sum();
+            (motion, error)
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(motion, _)| motion)
+}
+
+/// Calibrates a line-scan camera from sweeps of the board, every sweep is an image of the lines
+/// stacked in the order they were read. The board has to be tilted in the sweeps for the focal
+/// to be observable, the velocity is shared by all the sweeps so they need the same motion.
+pub fn calib_line_scan(
+    frame_feature_list: &[Option<FrameFeature>],
+    observer: &dyn PipelineObserver,
+) -> Option<LineScanCalibration> {
+    let frames: Vec<_> = frame_feature_list
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| Some((i, f.as_ref()?)))
+        .collect();
+    let (_, first) = frames.first()?;
+    let width = first.img_w_h.0;
+    let cu = width as f64 / 2.0;
+    let fits: Vec<_> = frames
+        .iter()
+        .filter_map(|&(i, f)| Some((i, SweepFit::new(f, cu)?)))
+        .collect();
+    let motion = init_motion(&fits.iter().map(|f| &f.1).collect::<Vec<_>>(), width as f64)?;
+    let sweeps: Vec<_> = fits
+        .iter()
+        .filter_map(|(i, fit)| {
+            let (depth, speed, _) = fit.depth_speed(&motion)?;
+            Some((*i, speed, fit.pose(&motion, depth, speed)))
+        })
+        .collect();
+    if sweeps.is_empty() {
+        tracing::warn!("no sweep with a tilted board to initialize the line-scan camera from");
+        return None;
+    }
+    let mut speeds: Vec<_> = sweeps.iter().map(|s| s.1).collect();
+    speeds.sort_by(|a, b| a.total_cmp(b));
+    let speed = speeds[speeds.len() / 2];
+    tracing::debug!("line-scan init {:?} speed {:.6}", motion, speed);
+
+    let mut initial_values = HashMap::<String, na::DVector<f64>>::from([
+        (
+            "line_scan".to_string(),
+            na::dvector![motion.f, cu, 0.0, 0.0],
+        ),
+        (
+            "velocity".to_string(),
+            na::dvector![motion.kx * speed, speed, motion.kz * speed],
+        ),
+    ]);
+    let mut problem = tiny_solver::Problem::new();
+    for &(i, _, t_cam_board) in &sweeps {
+        let rvec_i = rvec_name(i);
+        let tvec_i = tvec_name(i);
+        let rtvec = t_cam_board.to_rvec_tvec();
+        initial_values.insert(rvec_i.clone(), rtvec.na_rvec());
+        initial_values.insert(tvec_i.clone(), rtvec.na_tvec());
+        for fp in frame_feature_list[i].as_ref()?.features.values() {
+            problem.add_residual_block(
+                2,
+                &[
+                    ("line_scan", 4),
+                    ("velocity", 3),
+                    (&rvec_i, 3),
+                    (&tvec_i, 3),
+                ],
+                Box::new(LineScanReprojectionFactor::new(&fp.p3d, &fp.p2d)),
+                Some(Box::new(HuberLoss::new(1.0))),
+            );
+        }
+    }
+    problem.set_variable_bounds("line_scan", 0, 0.0, 100000.0);
+    problem.set_variable_bounds("line_scan", 1, 0.0, width as f64);
+
+    let result =
+        optimize_with_observer(&problem, &initial_values, "calib_line_scan", None, observer)?;
+    let intrinsics = &result["line_scan"];
+    let velocity = &result["velocity"];
+    let model = LineScanModel {
+        f: intrinsics[0],
+        cu: intrinsics[1],
+        k1: intrinsics[2],
+        k2: intrinsics[3],
+        velocity: [velocity[0], velocity[1], velocity[2]],
+        width,
+    };
+    let rtvecs: HashMap<_, _> = sweeps
+        .iter()
+        .map(|&(i, _, _)| {
+            (
+                i,
+                RvecTvec::new(&result[&rvec_name(i)], &result[&tvec_name(i)]),
+            )
+        })
+        .collect();
+    let (mut squared_error, mut count) = (0.0, 0usize);
+    for (i, rtvec) in &rtvecs {
+        let t_cam_board = rtvec.to_na_isometry3();
+        for fp in frame_feature_list[*i].as_ref()?.features.values() {
+            let p3d = fp.p3d.as_dvec3();
+            let p_cam = t_cam_board * na::Point3::new(p3d.x, p3d.y, p3d.z);
+            let detected = na::Vector2::new(fp.p2d.x as f64, fp.p2d.y as f64);
+            squared_error += (model.project_one(&p_cam.coords) - detected).norm_squared();
+            count += 1;
+        }
+    }
+    let rms_error = (squared_error / count.max(1) as f64).sqrt();
+    tracing::info!(
+        "line-scan f {:.2} cu {:.2}, velocity {:?} m/line, rms {:.3} px",
+        model.f,
+        model.cu,
+        model.velocity,
+        rms_error
+    );
+    Some(LineScanCalibration {
+        model,
+        rtvecs,
+        rms_error,
+    })
+}
@@ -0,0 +1,14 @@
+use tracing_subscriber::EnvFilter;
+
+/// Log to stderr, filtered by `RUST_LOG` (default `info`), optionally as json lines.
+pub fn init_tracing(json_log: bool) {
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(env_filter)
+        .with_writer(std::io::stderr);
+    if json_log {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
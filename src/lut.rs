@@ -0,0 +1,126 @@
+use camera_intrinsic_model::GenericModel;
+use nalgebra as na;
+use rayon::prelude::*;
+
+/// Unit rays of every pixel, models without closed-form unprojection solve it iteratively for
+/// each point, the table trades memory (12 bytes per pixel) for the time.
+pub struct UnprojectionLut {
+    w: u32,
+    h: u32,
+    rays: Vec<Option<na::Vector3<f32>>>,
+}
+
+impl UnprojectionLut {
+    /// Pixels outside of the model's domain don't project back to themselves and have no ray.
+    pub fn new(model: &GenericModel<f64>) -> UnprojectionLut {
+        let (w, h) = (model.width() as u32, model.height() as u32);
+        let rays = (0..w * h)
+            .into_par_iter()
+            .map(|i| {
+                let p2d = na::Vector2::new((i % w) as f64, (i / w) as f64);
+                let p3d = model.unproject_one(&p2d);
+                if !p3d.iter().all(|v| v.is_finite())
+                    || (model.project_one(&p3d) - p2d).norm() >= 1.0
+                {
+                    return None;
+                }
+                Some(p3d.normalize().cast())
+            })
+            .collect();
+        UnprojectionLut { w, h, rays }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.w
+    }
+
+    pub fn height(&self) -> u32 {
+        self.h
+    }
+
+    /// Bilinear interpolation of the rays around `p2d`, `None` outside of the image or if any
+    /// neighbor has no ray.
+    pub fn unproject_one(&self, p2d: &na::Vector2<f64>) -> Option<na::Vector3<f64>> {
+        if !(0.0..=(self.w - 1) as f64).contains(&p2d.x)
+            || !(0.0..=(self.h - 1) as f64).contains(&p2d.y)
+        {
+            return None;
+        }
+        let (x0, y0) = (p2d.x.floor() as u32, p2d.y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(self.w - 1), (y0 + 1).min(self.h - 1));
+        let (dx, dy) = (p2d.x - x0 as f64, p2d.y - y0 as f64);
+        let ray = |x: u32, y: u32| self.rays[(y * self.w + x) as usize].map(|r| r.cast::<f64>());
+        let ray = ray(x0, y0)? * (1.0 - dx) * (1.0 - dy)
+            + ray(x1, y0)? * dx * (1.0 - dy)
+            + ray(x0, y1)? * (1.0 - dx) * dy
+            + ray(x1, y1)? * dx * dy;
+        Some(ray.normalize())
+    }
+
+    /// Same as `undistort::undistort_points` with the rays of the table.
+    pub fn undistort_points(
+        &self,
+        p2ds: &[na::Vector2<f64>],
+        camera_matrix: Option<&na::Matrix3<f64>>,
+    ) -> Vec<Option<na::Vector2<f64>>> {
+        p2ds.par_iter()
+            .map(|p2d| {
+                let p3d = self.unproject_one(p2d).filter(|p| p.z > 0.0)?;
+                let normalized = p3d.xy() / p3d.z;
+                Some(match camera_matrix {
+                    Some(k) => (k * normalized.push(1.0)).xy(),
+                    None => normalized,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camera_intrinsic_model::EUCM;
+
+    use super::*;
+    use crate::test_util::eucm;
+
+    #[test]
+    fn lut_matches_model_unprojection() {
+        // the corners of the wide camera are beyond the domain of the model
+        let wide = GenericModel::EUCM(EUCM::new(
+            &na::dvector![200.0, 200.0, 320.0, 240.0, 0.6, 1.0],
+            640,
+            480,
+        ));
+        for model in [eucm(), wide] {
+            let lut = UnprojectionLut::new(&model);
+            let (w, h) = (model.width(), model.height());
+            let (mut num_rays, mut num_invalid) = (0, 0);
+            for y in (0..h as u32).step_by(7) {
+                for x in (0..w as u32).step_by(7) {
+                    let p2d = na::Vector2::new(x as f64 + 0.3, y as f64 + 0.6);
+                    let p3d = model.unproject_one(&p2d);
+                    let valid = p3d.iter().all(|v| v.is_finite())
+                        && (model.project_one(&p3d) - p2d).norm() < 1.0;
+                    match lut.unproject_one(&p2d) {
+                        Some(ray) => {
+                            assert!(valid, "ray of the invalid pixel {:?}", p2d);
+                            assert!(ray.angle(&p3d) < 1e-4, "{:?}", p2d);
+                            num_rays += 1;
+                        }
+                        None => num_invalid += !valid as usize,
+                    }
+                }
+            }
+            assert!(num_rays > 0);
+            assert_eq!(num_invalid > 0, model.width() == 640.0);
+            for p2d in [
+                na::Vector2::new(-0.5, h / 2.0),
+                na::Vector2::new(w - 0.5, h / 2.0),
+                na::Vector2::new(w / 2.0, h),
+                na::Vector2::new(f64::NAN, 0.0),
+            ] {
+                assert!(lut.unproject_one(&p2d).is_none());
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use camera_intrinsic_model::*;
+use nalgebra as na;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::detected_points::FrameFeature;
+use crate::observer::NoopObserver;
+use crate::types::{CalibParams, RvecTvec};
+use crate::util::{calib_camera, intrinsics_covariance};
+
+/// How the observations of every Monte Carlo run are drawn.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Perturbation {
+    /// Reprojected corners of the solution with gaussian noise of the estimated corner noise.
+    Noise,
+    /// The frames drawn with replacement.
+    Resample,
+}
+
+/// Spreads of the intrinsic params over the Monte Carlo runs, in the layout of
+/// `camera.params()`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParamSpread {
+    pub cam_idx: usize,
+    pub perturbation: Perturbation,
+    /// Runs that converged.
+    pub num_runs: usize,
+    pub mean: Vec<f64>,
+    pub std_dev: Vec<f64>,
+    /// Square roots of the diagonal of `intrinsics_covariance` to compare with.
+    pub linearized_std_dev: Option<Vec<f64>>,
+}
+
+/// Standard normal sample with the Box-Muller transform.
+fn standard_normal(rng: &mut impl Rng) -> f64 {
+    let u0: f64 = 1.0 - rng.gen::<f64>();
+    let u1: f64 = rng.gen();
+    (-2.0 * u0.ln()).sqrt() * (2.0 * std::f64::consts::PI * u1).cos()
+}
+
+/// Reprojected corners of the frames with a board pose and the std of the corner noise per
+/// axis, corrected by the degrees of freedom like `intrinsics_covariance`.
+fn reprojected_frames(
+    model: &GenericModel<f64>,
+    rtvecs: &HashMap<usize, RvecTvec>,
+    frame_feature_list: &[Option<FrameFeature>],
+) -> (Vec<Option<FrameFeature>>, f64) {
+    let mut squared_error_sum = 0.0;
+    let mut residual_num = 0usize;
+    let frames: Vec<_> = frame_feature_list
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            let (f, rtvec) = (f.as_ref()?, rtvecs.get(&i)?);
+            let transform = rtvec.to_na_isometry3();
+            let mut f = f.clone();
+            for fp in f.features.values_mut() {
+                let p3 = transform * na::Point3::new(fp.p3d.x, fp.p3d.y, fp.p3d.z).cast::<f64>();
+                let p2 = model.project_one(&p3.coords);
+                squared_error_sum +=
+                    (p2 - na::Vector2::new(fp.p2d.x, fp.p2d.y).cast::<f64>()).norm_squared();
+                residual_num += 2;
+                fp.p2d = glam::Vec2::new(p2.x as f32, p2.y as f32);
+            }
+            Some(f)
+        })
+        .collect();
+    let dof = residual_num
+        .saturating_sub(model.params().len() + 6 * rtvecs.len())
+        .max(1);
+    (frames, (squared_error_sum / dof as f64).sqrt())
+}
+
+/// Empirical spreads of the intrinsics of `num_runs` calibrations of perturbed observations,
+/// each starting from the solution `model`. Unlike the linearized covariance, they also show
+/// the sensitivity to frames with too much weight and non-gaussian errors. Runs are seeded
+/// with `seed + run` so the spreads are reproducible.
+#[allow(clippy::too_many_arguments)]
+pub fn monte_carlo_spread(
+    cam_idx: usize,
+    model: &GenericModel<f64>,
+    rtvecs: &HashMap<usize, RvecTvec>,
+    frame_feature_list: &[Option<FrameFeature>],
+    calib_params: &CalibParams,
+    perturbation: Perturbation,
+    num_runs: usize,
+    seed: u64,
+) -> Option<ParamSpread> {
+    let one_focal = calib_params.one_focal || calib_params.fixed_focal.is_some();
+    let (reprojected, sigma) = reprojected_frames(model, rtvecs, frame_feature_list);
+    let valid: Vec<_> = rtvecs
+        .keys()
+        .filter_map(|&i| frame_feature_list.get(i)?.as_ref())
+        .collect();
+    let params: Vec<_> = (0..num_runs)
+        .into_par_iter()
+        .filter_map(|run| {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed + run as u64);
+            let frames: Vec<_> = match perturbation {
+                Perturbation::Noise => reprojected
+                    .iter()
+                    .map(|f| {
+                        let mut f = f.clone()?;
+                        for fp in f.features.values_mut() {
+                            fp.p2d.x += (standard_normal(&mut rng) * sigma) as f32;
+                            fp.p2d.y += (standard_normal(&mut rng) * sigma) as f32;
+                        }
+                        Some(f)
+                    })
+                    .collect(),
+                Perturbation::Resample => (0..valid.len())
+                    .map(|_| valid.choose(&mut rng).map(|&f| f.clone()))
+                    .collect(),
+            };
+            let (calibrated, _) = calib_camera(
+                &frames,
+                model,
+                one_focal,
+                calib_params.disabled_distortion_num,
+                calib_params.fixed_focal.is_some(),
+                calib_params.plateau,
+                &NoopObserver,
+            )?;
+            let params = calibrated.params();
+            params.iter().all(|p| p.is_finite()).then_some(params)
+        })
+        .collect();
+    if params.len() < 2 {
+        tracing::warn!("cam{} not enough converged Monte Carlo runs", cam_idx);
+        return None;
+    }
+    let n = params.len() as f64;
+    let mean = params.iter().sum::<na::DVector<f64>>() / n;
+    let variance = params
+        .iter()
+        .map(|p| (p - &mean).map(|d| d * d))
+        .sum::<na::DVector<f64>>()
+        / (n - 1.0);
+    let linearized_std_dev = intrinsics_covariance(model, rtvecs, frame_feature_list, one_focal)
+        .map(|covariance| {
+            covariance
+                .diagonal()
+                .iter()
+                .map(|v| v.max(0.0).sqrt())
+                .collect()
+        });
+    Some(ParamSpread {
+        cam_idx,
+        perturbation,
+        num_runs: params.len(),
+        mean: mean.iter().copied().collect(),
+        std_dev: variance.iter().map(|v| v.sqrt()).collect(),
+        linearized_std_dev,
+    })
+}
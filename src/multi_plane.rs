@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+
+use camera_intrinsic_model::*;
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+use tiny_solver::loss_functions::HuberLoss;
+
+use crate::board::{Board, BoardConfig};
+use crate::detected_points::{FeaturePoint, FrameFeature};
+use crate::observer::PipelineObserver;
+use crate::optimization::factors::{OtherCamReprojectionFactor, ReprojectionFactor};
+use crate::optimization::{rvec_name, tvec_name};
+use crate::types::{CalibParams, RvecTvec, ToRvecTvec};
+use crate::util::{
+    convert_model, init_and_calibrate_one_camera_with_trials, optimize_with_observer,
+    set_problem_parameter_bound, set_problem_parameter_disabled,
+};
+
+/// Planes of a frame with fewer corners don't constrain their pose.
+const MIN_PLANE_CORNERS: usize = 8;
+/// Frames seeing several planes below which the planar init is used instead.
+const MIN_DLT_FRAMES: usize = 3;
+
+/// Camera and target to camera poses.
+type MultiPlaneInit = (GenericModel<f64>, HashMap<usize, na::Isometry3<f64>>);
+
+fn plane_rvec_name(plane_idx: usize) -> String {
+    format!("plane_rvec{}", plane_idx)
+}
+
+fn plane_tvec_name(plane_idx: usize) -> String {
+    format!("plane_tvec{}", plane_idx)
+}
+
+/// A board of a multi-plane target and its pose in the target frame.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlaneConfig {
+    /// The boards of a target need different `first_id`s so their tags can be told apart.
+    pub board: BoardConfig,
+    pub rvec: [f64; 3],
+    /// In m.
+    pub tvec: [f64; 3],
+    /// The pose is only nominal, e.g. the angle of a hinge, and is calibrated with the camera.
+    #[serde(default)]
+    pub refine: bool,
+}
+
+/// Rigid target of several boards, e.g. a corner cube, a box or two hinged boards. The target
+/// frame is the frame of the first board, which can't be refined.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MultiPlaneConfig {
+    pub planes: Vec<PlaneConfig>,
+}
+
+pub struct TargetPlane {
+    /// Corners in the frame of the plane.
+    pub board: Board,
+    pub t_target_plane: na::Isometry3<f64>,
+    pub refine: bool,
+}
+
+pub struct MultiPlaneTarget {
+    pub planes: Vec<TargetPlane>,
+}
+
+impl MultiPlaneTarget {
+    pub fn from_config(config: &MultiPlaneConfig) -> MultiPlaneTarget {
+        let planes = config
+            .planes
+            .iter()
+            .map(|plane| TargetPlane {
+                board: Board::from_config(&plane.board),
+                t_target_plane: RvecTvec::new(
+                    &na::DVector::from_row_slice(&plane.rvec),
+                    &na::DVector::from_row_slice(&plane.tvec),
+                )
+                .to_na_isometry3(),
+                refine: plane.refine,
+            })
+            .collect();
+        MultiPlaneTarget { planes }
+    }
+
+    /// All the corners in the target frame, for the detection.
+    pub fn board(&self) -> Board {
+        let id_to_3d = self
+            .planes
+            .iter()
+            .flat_map(|plane| {
+                plane.board.id_to_3d.iter().map(|(&id, p)| {
+                    let p =
+                        plane.t_target_plane * na::Point3::new(p.x as f64, p.y as f64, p.z as f64);
+                    (id, glam::Vec3::new(p.x as f32, p.y as f32, p.z as f32))
+                })
+            })
+            .collect();
+        Board { id_to_3d }
+    }
+
+    fn plane_of(&self, id: u32) -> Option<usize> {
+        self.planes
+            .iter()
+            .position(|plane| plane.board.id_to_3d.contains_key(&id))
+    }
+
+    /// Sets the corners of the frames to the target frame, e.g. after refining the planes.
+    pub fn update_frames(&self, frame_feature_list: &mut [Option<FrameFeature>]) {
+        let board = self.board();
+        for f in frame_feature_list.iter_mut().flatten() {
+            for (id, fp) in f.features.iter_mut() {
+                if let Some(p3d) = board.id_to_3d.get(id) {
+                    fp.p3d = *p3d;
+                }
+            }
+        }
+    }
+
+    /// The corners of each frame on the plane with the most of them, in the frame of that plane.
+    /// A single plane is all the homography init of the planar targets can take.
+    fn planar_frames(
+        &self,
+        frame_feature_list: &[Option<FrameFeature>],
+    ) -> (Vec<Option<FrameFeature>>, HashMap<usize, usize>) {
+        let mut frame_planes = HashMap::new();
+        let frames = frame_feature_list
+            .iter()
+            .enumerate()
+            .map(|(i, f)| {
+                let f = f.as_ref()?;
+                let mut counts = vec![0; self.planes.len()];
+                for id in f.features.keys() {
+                    if let Some(plane_idx) = self.plane_of(*id) {
+                        counts[plane_idx] += 1;
+                    }
+                }
+                let plane_idx = (0..counts.len()).max_by_key(|&p| counts[p])?;
+                let board = &self.planes[plane_idx].board;
+                let features: HashMap<_, _> = f
+                    .features
+                    .iter()
+                    .filter_map(|(id, fp)| {
+                        let p3d = *board.id_to_3d.get(id)?;
+                        Some((*id, FeaturePoint { p3d, ..*fp }))
+                    })
+                    .collect();
+                if features.len() < MIN_PLANE_CORNERS {
+                    return None;
+                }
+                frame_planes.insert(i, plane_idx);
+                Some(FrameFeature {
+                    features,
+                    ..f.clone()
+                })
+            })
+            .collect();
+        (frames, frame_planes)
+    }
+
+    /// `fx`, `fy`, `cx`, `cy` and the target to camera pose of a frame from the direct linear
+    /// transform of its corners, which needs at least two planes in the frame. The distortion is
+    /// left out, which is fine for the narrow fov lenses the homography init of a single plane
+    /// struggles with.
+    fn dlt_init(&self, frame_feature: &FrameFeature) -> Option<([f64; 4], na::Isometry3<f64>)> {
+        let mut counts = vec![0; self.planes.len()];
+        for id in frame_feature.features.keys() {
+            if let Some(plane_idx) = self.plane_of(*id) {
+                counts[plane_idx] += 1;
+            }
+        }
+        if counts.iter().filter(|&&c| c >= MIN_PLANE_CORNERS).count() < 2 {
+            return None;
+        }
+        let (p3ds, p2ds): (Vec<_>, Vec<_>) = frame_feature
+            .features
+            .values()
+            .map(|fp| {
+                (
+                    na::Vector3::new(fp.p3d.x as f64, fp.p3d.y as f64, fp.p3d.z as f64),
+                    na::Vector2::new(fp.p2d.x as f64, fp.p2d.y as f64),
+                )
+            })
+            .unzip();
+        // normalized so the equations are well conditioned
+        let n = p3ds.len() as f64;
+        let c3 = p3ds.iter().sum::<na::Vector3<f64>>() / n;
+        let s3 = 3.0f64.sqrt() / (p3ds.iter().map(|p| (p - c3).norm()).sum::<f64>() / n);
+        let c2 = p2ds.iter().sum::<na::Vector2<f64>>() / n;
+        let s2 = 2.0f64.sqrt() / (p2ds.iter().map(|p| (p - c2).norm()).sum::<f64>() / n);
+        let mut ata = na::SMatrix::<f64, 12, 12>::zeros();
+        for (p3d, p2d) in p3ds.iter().zip(&p2ds) {
+            let x = ((p3d - c3) * s3).push(1.0);
+            let u = (p2d - c2) * s2;
+            for (row, coord) in [(0, u.x), (4, u.y)] {
+                let mut a = na::SVector::<f64, 12>::zeros();
+                a.fixed_rows_mut::<4>(row).copy_from(&x);
+                a.fixed_rows_mut::<4>(8).copy_from(&(-coord * x));
+                ata += a * a.transpose();
+            }
+        }
+        let eigen = ata.symmetric_eigen();
+        let p = eigen.eigenvectors.column(eigen.eigenvalues.imin());
+        let normalized = na::Matrix3x4::from_fn(|r, c| p[r * 4 + c]);
+        let t2_inv = na::Matrix3::new(1.0 / s2, 0.0, c2.x, 0.0, 1.0 / s2, c2.y, 0.0, 0.0, 1.0);
+        let mut t3 = na::Matrix4::identity() * s3;
+        t3.fixed_view_mut::<3, 1>(0, 3).copy_from(&(-c3 * s3));
+        t3[(3, 3)] = 1.0;
+        let projection = t2_inv * normalized * t3;
+        // m = k r with an upper triangular k, so m m^t = k k^t, the cholesky of the flipped one
+        let m = projection.fixed_view::<3, 3>(0, 0);
+        let flip = na::Matrix3::new(0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0);
+        let l = (flip * m * m.transpose() * flip).cholesky()?.l();
+        let k = flip * l * flip;
+        let k = k / k[(2, 2)];
+        let intrinsics = [k[(0, 0)], k[(1, 1)], k[(0, 2)], k[(1, 2)]];
+        if !intrinsics.iter().all(|v| v.is_finite() && *v > 0.0) {
+            return None;
+        }
+        // k^-1 p = s [r | t], the scale is signed so the target is in front of the camera
+        let k_inv = k.try_inverse()?;
+        let sr = k_inv * m;
+        let scale = sr.determinant().cbrt();
+        let svd = (sr / scale).svd(true, true);
+        let rotation = na::Rotation3::from_matrix_unchecked(svd.u? * svd.v_t?);
+        let translation = k_inv * projection.column(3) / scale;
+        let t_cam_target = na::Isometry3::from_parts(
+            translation.into(),
+            na::UnitQuaternion::from_rotation_matrix(&rotation),
+        );
+        ((t_cam_target * na::Point3::from(c3)).z > 0.0).then_some((intrinsics, t_cam_target))
+    }
+}
+
+/// Initializes the camera and the target to camera poses of the frames seeing several planes,
+/// with the median of their intrinsics, or from the planar init of the plane with the most
+/// corners of each frame when too few frames see several planes.
+fn init_multi_plane(
+    frame_feature_list: &[Option<FrameFeature>],
+    target: &MultiPlaneTarget,
+    target_model: &GenericModel<f64>,
+    calib_params: &CalibParams,
+    observer: &dyn PipelineObserver,
+) -> Option<MultiPlaneInit> {
+    let dlt: HashMap<_, _> = frame_feature_list
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| Some((i, target.dlt_init(f.as_ref()?)?)))
+        .collect();
+    if dlt.len() < MIN_DLT_FRAMES {
+        tracing::info!(
+            "{} frames see several planes, init from single planes",
+            dlt.len()
+        );
+        let (planar, frame_planes) = target.planar_frames(frame_feature_list);
+        let (model, rtvecs) = init_and_calibrate_one_camera_with_trials(
+            0,
+            &[planar],
+            target_model,
+            observer,
+            calib_params,
+            3,
+        )?;
+        let t_cam_targets = rtvecs
+            .iter()
+            .map(|(i, rtvec)| {
+                let t_target_plane = target.planes[frame_planes[i]].t_target_plane;
+                (*i, rtvec.to_na_isometry3() * t_target_plane.inverse())
+            })
+            .collect();
+        return Some((model, t_cam_targets));
+    }
+    let median = |k: usize| {
+        let mut v: Vec<_> = dlt.values().map(|(p, _)| p[k]).collect();
+        v.sort_by(|a, b| a.total_cmp(b));
+        v[v.len() / 2]
+    };
+    let (fx, fy) = if let Some(focal) = calib_params.fixed_focal {
+        (focal, focal)
+    } else if calib_params.one_focal {
+        let focal = (median(0) + median(1)) / 2.0;
+        (focal, focal)
+    } else {
+        (median(0), median(1))
+    };
+    let (w, h) = frame_feature_list.iter().flatten().next()?.img_w_h;
+    // the ucm without alpha is the pinhole of the transform
+    let pinhole = GenericModel::UCM(UCM::new(
+        &na::dvector![fx, fy, median(2), median(3), 0.0],
+        w,
+        h,
+    ));
+    tracing::debug!("dlt init {:?}", pinhole);
+    let mut init_model = *target_model;
+    init_model.set_w_h(w, h);
+    convert_model(
+        &pinhole,
+        &mut init_model,
+        calib_params.disabled_distortion_num,
+    );
+    let t_cam_targets = dlt
+        .into_iter()
+        .map(|(i, (_, t_cam_target))| (i, t_cam_target))
+        .collect();
+    Some((init_model, t_cam_targets))
+}
+
+/// Result of `calib_multi_plane`.
+pub struct MultiPlaneCalibration {
+    pub model: GenericModel<f64>,
+    /// Target to camera poses.
+    pub rtvecs: HashMap<usize, RvecTvec>,
+    /// Poses of the planes in the target frame, the refined ones calibrated.
+    pub t_target_planes: Vec<RvecTvec>,
+}
+
+impl MultiPlaneCalibration {
+    /// `config` with the calibrated poses of the planes.
+    pub fn config(&self, config: &MultiPlaneConfig) -> MultiPlaneConfig {
+        let planes = config
+            .planes
+            .iter()
+            .zip(&self.t_target_planes)
+            .map(|(plane, rtvec)| {
+                let (r, t) = (rtvec.na_rvec(), rtvec.na_tvec());
+                PlaneConfig {
+                    board: plane.board.clone(),
+                    rvec: [r[0], r[1], r[2]],
+                    tvec: [t[0], t[1], t[2]],
+                    refine: plane.refine,
+                }
+            })
+            .collect();
+        MultiPlaneConfig { planes }
+    }
+}
+
+/// Calibrates a camera with a multi-plane target. The corners of all planes are not coplanar, so
+/// the focal and the principal point are observable from fewer and less tilted views, e.g. of a
+/// narrow fov lens, and the camera is initialized from them without the homography of a plane,
+/// leaving out the frames of a single plane. The poses of the planes with `refine` are
+/// calibrated with the camera, the planes need to be seen together with the first one in some
+/// frames.
+pub fn calib_multi_plane(
+    frame_feature_list: &[Option<FrameFeature>],
+    target: &MultiPlaneTarget,
+    target_model: &GenericModel<f64>,
+    calib_params: &CalibParams,
+    observer: &dyn PipelineObserver,
+) -> Option<MultiPlaneCalibration> {
+    if target.planes.first().is_none_or(|plane| plane.refine) {
+        tracing::warn!("the first plane of a multi-plane target is its frame and can't be refined");
+        return None;
+    }
+    let (init_model, t_cam_targets) = init_multi_plane(
+        frame_feature_list,
+        target,
+        target_model,
+        calib_params,
+        observer,
+    )?;
+
+    let xy_same_focal = calib_params.one_focal || calib_params.fixed_focal.is_some();
+    let mut params = init_model.params();
+    if xy_same_focal {
+        // remove fy
+        params = params.remove_row(1);
+    };
+    let params_len = params.len();
+    let mut initial_values =
+        HashMap::<String, na::DVector<f64>>::from([("params".to_string(), params)]);
+    for (plane_idx, plane) in target.planes.iter().enumerate() {
+        if plane.refine {
+            let rtvec = plane.t_target_plane.to_rvec_tvec();
+            initial_values.insert(plane_rvec_name(plane_idx), rtvec.na_rvec());
+            initial_values.insert(plane_tvec_name(plane_idx), rtvec.na_tvec());
+        }
+    }
+    let mut problem = tiny_solver::Problem::new();
+    for (&i, t_cam_target) in &t_cam_targets {
+        let frame_feature = frame_feature_list[i].as_ref()?;
+        let rvec_i = rvec_name(i);
+        let tvec_i = tvec_name(i);
+        let rtvec = t_cam_target.to_rvec_tvec();
+        initial_values.insert(rvec_i.clone(), rtvec.na_rvec());
+        initial_values.insert(tvec_i.clone(), rtvec.na_tvec());
+        for (id, fp) in &frame_feature.features {
+            let Some(plane_idx) = target.plane_of(*id) else {
+                continue;
+            };
+            let plane = &target.planes[plane_idx];
+            if plane.refine {
+                let cost = OtherCamReprojectionFactor::new(
+                    &init_model,
+                    &plane.board.id_to_3d[id],
+                    &fp.p2d,
+                    xy_same_focal,
+                )
+                .with_blur(fp.blur.as_ref());
+                problem.add_residual_block(
+                    2,
+                    &[
+                        ("params", params_len),
+                        (&plane_rvec_name(plane_idx), 3),
+                        (&plane_tvec_name(plane_idx), 3),
+                        (&rvec_i, 3),
+                        (&tvec_i, 3),
+                    ],
+                    Box::new(cost),
+                    Some(Box::new(HuberLoss::new(1.0))),
+                );
+            } else {
+                let cost = ReprojectionFactor::new(&init_model, &fp.p3d, &fp.p2d, xy_same_focal)
+                    .with_blur(fp.blur.as_ref());
+                problem.add_residual_block(
+                    2,
+                    &[("params", params_len), (&rvec_i, 3), (&tvec_i, 3)],
+                    Box::new(cost),
+                    Some(Box::new(HuberLoss::new(1.0))),
+                );
+            }
+        }
+    }
+    set_problem_parameter_bound("params", &mut problem, &init_model, xy_same_focal);
+    set_problem_parameter_disabled(
+        "params",
+        &mut problem,
+        &mut initial_values,
+        &init_model,
+        xy_same_focal,
+        calib_params.disabled_distortion_num,
+    );
+    if calib_params.fixed_focal.is_some() {
+        problem.fix_variable("params", 0);
+    }
+
+    let mut result = optimize_with_observer(
+        &problem,
+        &initial_values,
+        "calib_multi_plane",
+        None,
+        observer,
+    )?;
+    let mut new_params = result.remove("params").unwrap();
+    if xy_same_focal {
+        new_params = new_params.clone().insert_row(1, new_params[0]);
+    };
+    let mut model = init_model;
+    model.set_params(&new_params);
+    let t_target_planes: Vec<_> = target
+        .planes
+        .iter()
+        .enumerate()
+        .map(|(plane_idx, plane)| {
+            if plane.refine {
+                let rtvec = RvecTvec::new(
+                    &result[&plane_rvec_name(plane_idx)],
+                    &result[&plane_tvec_name(plane_idx)],
+                );
+                let nominal = plane.t_target_plane.inverse() * rtvec.to_na_isometry3();
+                tracing::info!(
+                    "plane {} refined {:.3} deg and {:.4} m from its nominal pose",
+                    plane_idx,
+                    nominal.rotation.angle().to_degrees(),
+                    nominal.translation.vector.norm()
+                );
+                rtvec
+            } else {
+                plane.t_target_plane.to_rvec_tvec()
+            }
+        })
+        .collect();
+    let rtvecs = t_cam_targets
+        .keys()
+        .map(|&i| {
+            (
+                i,
+                RvecTvec::new(&result[&rvec_name(i)], &result[&tvec_name(i)]),
+            )
+        })
+        .collect();
+    Some(MultiPlaneCalibration {
+        model,
+        rtvecs,
+        t_target_planes,
+    })
+}
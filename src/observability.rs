@@ -0,0 +1,191 @@
+use camera_intrinsic_model::*;
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+
+/// Correlations larger than this are logged as nearly unobservable pairs.
+pub const MAX_CORRELATION: f64 = 0.9;
+
+/// Names of `camera.params()`.
+pub fn param_names(camera: &GenericModel<f64>) -> &'static [&'static str] {
+    match camera {
+        GenericModel::UCM(_) => &["fx", "fy", "cx", "cy", "alpha"],
+        GenericModel::EUCM(_) => &["fx", "fy", "cx", "cy", "alpha", "beta"],
+        GenericModel::EUCMT(_) => &["fx", "fy", "cx", "cy", "alpha", "beta", "t1", "t2"],
+        GenericModel::KannalaBrandt4(_) => &["fx", "fy", "cx", "cy", "k1", "k2", "k3", "k4"],
+        GenericModel::OpenCVModel5(_) => &["fx", "fy", "cx", "cy", "k1", "k2", "p1", "p2", "k3"],
+        GenericModel::Ftheta(_) => &["fx", "fy", "cx", "cy", "k2", "k3", "k4", "k5", "k6"],
+    }
+}
+
+/// Correlation matrix of the intrinsic params from their covariance.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParamCorrelation {
+    pub cam_idx: usize,
+    pub param_names: Vec<String>,
+    /// Row major, `correlation[i][j]` of `param_names[i]` and `param_names[j]`.
+    pub correlation: Vec<Vec<f64>>,
+}
+
+impl ParamCorrelation {
+    /// `covariance` in the layout of `camera.params()` as from `intrinsics_covariance`. With
+    /// `xy_same_focal` fy is a copy of fx and left out.
+    pub fn new(
+        cam_idx: usize,
+        camera: &GenericModel<f64>,
+        covariance: &na::DMatrix<f64>,
+        xy_same_focal: bool,
+    ) -> ParamCorrelation {
+        let indexes: Vec<_> = (0..covariance.nrows())
+            .filter(|&i| !(xy_same_focal && i == 1))
+            .collect();
+        let names = param_names(camera);
+        let std_dev = |i: usize| covariance[(i, i)].max(f64::MIN_POSITIVE).sqrt();
+        ParamCorrelation {
+            cam_idx,
+            param_names: indexes.iter().map(|&i| names[i].to_string()).collect(),
+            correlation: indexes
+                .iter()
+                .map(|&i| {
+                    indexes
+                        .iter()
+                        .map(|&j| covariance[(i, j)] / (std_dev(i) * std_dev(j)))
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    /// Pairs of params with an absolute correlation larger than `threshold`, strongest first.
+    pub fn correlated_pairs(&self, threshold: f64) -> Vec<(&str, &str, f64)> {
+        let mut pairs: Vec<_> = self
+            .correlation
+            .iter()
+            .enumerate()
+            .flat_map(|(i, row)| {
+                row.iter()
+                    .enumerate()
+                    .skip(i + 1)
+                    .filter(|(_, r)| r.abs() > threshold)
+                    .map(move |(j, &r)| (i, j, r))
+            })
+            .map(|(i, j, r)| {
+                (
+                    self.param_names[i].as_str(),
+                    self.param_names[j].as_str(),
+                    r,
+                )
+            })
+            .collect();
+        pairs.sort_by(|a, b| b.2.abs().partial_cmp(&a.2.abs()).unwrap());
+        pairs
+    }
+}
+
+/// Eigenvalues of the correlation matrix smaller than this are poorly constrained directions,
+/// about 98% correlation for a pair of params.
+pub const MIN_EIGENVALUE: f64 = 0.02;
+
+/// Params with a larger weight in a poorly constrained direction are reported.
+const MIN_DIRECTION_WEIGHT: f64 = 0.3;
+
+/// A combination of params that the frames barely constrain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeakDirection {
+    /// Eigenvalue of the correlation matrix, 0 if the combination is unobservable.
+    pub eigenvalue: f64,
+    /// Params of the combination with their weights, largest first.
+    pub params: Vec<(String, f64)>,
+    /// Capture advice to constrain it.
+    pub hint: String,
+}
+
+impl WeakDirection {
+    pub fn message(&self) -> String {
+        let names: Vec<_> = self.params.iter().map(|(n, _)| n.as_str()).collect();
+        let names = match names.split_last() {
+            Some((last, rest)) if !rest.is_empty() => format!("{} and {}", rest.join(", "), last),
+            _ => names.join(""),
+        };
+        // 1 - eigenvalue is the correlation of a pair of params
+        format!(
+            "{} are {:.1}% correlated; {}",
+            names,
+            (1.0 - self.eigenvalue).clamp(0.0, 1.0) * 100.0,
+            self.hint
+        )
+    }
+}
+
+/// Near rank deficiency of the intrinsics, from the eigen decomposition of the correlation
+/// matrix so the units of the params don't matter.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Observability {
+    pub cam_idx: usize,
+    /// Largest over smallest eigenvalue of the correlation matrix.
+    pub condition_number: f64,
+    pub weak_directions: Vec<WeakDirection>,
+}
+
+/// Which views break the ambiguity of the params in a weak direction.
+fn capture_hint(names: &[&str]) -> &'static str {
+    let has = |prefixes: &[&str]| {
+        names
+            .iter()
+            .any(|n| prefixes.iter().any(|p| n.starts_with(p)))
+    };
+    let focal = has(&["fx", "fy"]);
+    let center = has(&["cx", "cy"]);
+    let distortion = has(&["alpha", "beta", "k"]);
+    let tangential = has(&["p", "t"]);
+    if focal && distortion {
+        "add tilted board views"
+    } else if center && (tangential || distortion) {
+        "add views with the board in the image corners"
+    } else if distortion || tangential {
+        "add views with the board near the image border"
+    } else if focal {
+        "add tilted board views at different distances"
+    } else {
+        "add views of the board at more poses"
+    }
+}
+
+impl Observability {
+    pub fn new(correlation: &ParamCorrelation) -> Observability {
+        let n = correlation.param_names.len();
+        let matrix = na::DMatrix::from_fn(n, n, |i, j| correlation.correlation[i][j]);
+        let eigen = na::SymmetricEigen::new(matrix);
+        let max_eigenvalue = eigen.eigenvalues.max();
+        let min_eigenvalue = eigen.eigenvalues.min().max(0.0);
+        let mut weak_directions: Vec<_> = eigen
+            .eigenvalues
+            .iter()
+            .enumerate()
+            .filter(|(_, &e)| e < MIN_EIGENVALUE)
+            .map(|(k, &eigenvalue)| {
+                let mut params: Vec<_> = eigen
+                    .eigenvectors
+                    .column(k)
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, w)| w.abs() > MIN_DIRECTION_WEIGHT)
+                    .map(|(i, &w)| (correlation.param_names[i].clone(), w))
+                    .collect();
+                params.sort_by(|a, b| b.1.abs().partial_cmp(&a.1.abs()).unwrap());
+                let names: Vec<_> = params.iter().map(|(n, _)| n.as_str()).collect();
+                let hint = capture_hint(&names).to_string();
+                WeakDirection {
+                    eigenvalue: eigenvalue.max(0.0),
+                    params,
+                    hint,
+                }
+            })
+            .collect();
+        weak_directions.sort_by(|a, b| a.eigenvalue.partial_cmp(&b.eigenvalue).unwrap());
+        Observability {
+            cam_idx: correlation.cam_idx,
+            condition_number: max_eigenvalue / min_eigenvalue,
+            weak_directions,
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use camera_intrinsic_model::GenericModel;
+use image::DynamicImage;
+use nalgebra as na;
+
+use crate::detected_points::FrameFeature;
+use crate::types::RvecTvec;
+
+/// Callbacks for each stage of the calibration pipeline.
+/// All methods default to doing nothing, implement the ones you need.
+pub trait PipelineObserver: Sync {
+    /// Called for every loaded image, `frame_feature` is `None` if the board is not detected.
+    fn on_frame_detected(
+        &self,
+        _cam_idx: usize,
+        _time_ns: i64,
+        _img: &DynamicImage,
+        _frame_feature: Option<&FrameFeature>,
+    ) {
+    }
+
+    /// Called after the camera is initialized from the two key frames.
+    fn on_init_complete(
+        &self,
+        _cam_idx: usize,
+        _initial_model: &GenericModel<f64>,
+        _key_frames: [&FrameFeature; 2],
+    ) {
+    }
+
+    /// Opt in to `on_iteration_finished`. The solver is then stepped one iteration at a time,
+    /// which costs one more residual evaluation per iteration.
+    fn observe_iterations(&self) -> bool {
+        false
+    }
+
+    /// Called after every solver iteration of `stage` with the norm of the residuals.
+    fn on_iteration_finished(&self, _stage: &str, _iteration: usize, _error: f64) {}
+
+    /// Called after a camera is calibrated, `rtvec_map` holds the board poses of each frame index.
+    fn on_calibration_done(
+        &self,
+        _cam_idx: usize,
+        _model: &GenericModel<f64>,
+        _rtvec_map: &HashMap<usize, RvecTvec>,
+    ) {
+    }
+
+    /// Called for every frame in `validation` with the residual (projected - detected) of each feature.
+    fn on_frame_validated(
+        &self,
+        _cam_idx: usize,
+        _frame_feature: &FrameFeature,
+        _t_cam_board: &na::Isometry3<f64>,
+        _residuals: &[na::Vector2<f64>],
+    ) {
+    }
+
+    /// Called at the end of `validation` with the avg of 99% and the median reprojection error.
+    fn on_validation_done(
+        &self,
+        _cam_idx: usize,
+        _model: &GenericModel<f64>,
+        _avg_99_percent: f64,
+        _median_reprojection_error: f64,
+    ) {
+    }
+}
+
+pub struct NoopObserver;
+
+impl PipelineObserver for NoopObserver {}
@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+
+use nalgebra as na;
+use tiny_solver::Problem;
+
+/// Variables of the problem built by `calib_camera`.
+/// * `"params"`: camera params, without fy if `xy_same_focal`.
+/// * `"rvec{i}"`, `"tvec{i}"`: board to camera pose of frame `i`.
+pub struct CalibVariables<'a> {
+    pub params_len: usize,
+    pub xy_same_focal: bool,
+    pub frame_indexes: &'a [usize],
+}
+
+pub fn rvec_name(frame_idx: usize) -> String {
+    format!("rvec{}", frame_idx)
+}
+
+pub fn tvec_name(frame_idx: usize) -> String {
+    format!("tvec{}", frame_idx)
+}
+
+/// Add residual blocks to the calibration problem, e.g. a pose prior or a scale constraint.
+/// New variables must be inserted into `initial_values`.
+pub trait CustomResiduals: Sync {
+    fn add_residual_blocks(
+        &self,
+        problem: &mut Problem,
+        initial_values: &mut HashMap<String, na::DVector<f64>>,
+        variables: &CalibVariables,
+    );
+}
+
+impl<F> CustomResiduals for F
+where
+    F: Fn(&mut Problem, &mut HashMap<String, na::DVector<f64>>, &CalibVariables) + Sync,
+{
+    fn add_residual_blocks(
+        &self,
+        problem: &mut Problem,
+        initial_values: &mut HashMap<String, na::DVector<f64>>,
+        variables: &CalibVariables,
+    ) {
+        self(problem, initial_values, variables)
+    }
+}
@@ -1,14 +1,115 @@
+use crate::batch;
+use crate::blur::CornerBlur;
+use crate::detected_points::FeaturePoint;
+use crate::line_scan::line_scan_project;
+use crate::refraction::{port_normal, water_ray, FlatPortParams};
+use crate::spline::evaluate_segment;
+use crate::telecentric::telecentric_project;
 use crate::types::DVecVec3;
 
 use camera_intrinsic_model::*;
 use nalgebra as na;
+use num_dual::{Derivative, DualDVec64, DualSVec64};
+use rayon::prelude::*;
 use tiny_solver::factors::Factor;
 
+/// Copies the dual numbers of tiny-solver into fixed-size ones, `N` is the total size of `params`.
+fn to_static_duals<const N: usize>(
+    params: &[na::DVector<DualDVec64>],
+) -> Vec<na::DVector<DualSVec64<N>>> {
+    params
+        .iter()
+        .map(|p| {
+            p.map(|x| {
+                let eps = x.eps.unwrap_generic(na::Dyn(N), na::U1);
+                DualSVec64::new(
+                    x.re,
+                    Derivative::some(na::SVector::from_column_slice(eps.as_slice())),
+                )
+            })
+        })
+        .collect()
+}
+
+fn from_static_duals<const N: usize>(
+    residual: na::DVector<DualSVec64<N>>,
+) -> na::DVector<DualDVec64> {
+    residual.map(|r| {
+        let eps = r.eps.unwrap_generic(na::Const::<N>, na::U1);
+        DualDVec64::new(
+            r.re,
+            Derivative::some(na::DVector::from_column_slice(eps.as_slice())),
+        )
+    })
+}
+
+/// Implements `Factor` with the generic `residual` of a factor. The jacobian of the total param
+/// sizes in `$sizes` is computed with fixed-size dual numbers, which don't allocate per operation.
+macro_rules! impl_static_dual_factor {
+    ($factor:ty, [$($n:literal),*]) => {
+        impl Factor<f64> for $factor {
+            fn residual_func(&self, params: &[na::DVector<f64>]) -> na::DVector<f64> {
+                self.residual(params)
+            }
+        }
+        impl Factor<DualDVec64> for $factor {
+            fn residual_func(&self, params: &[na::DVector<DualDVec64>]) -> na::DVector<DualDVec64> {
+                match params.iter().map(|p| p.len()).sum::<usize>() {
+                    $($n => from_static_duals(self.residual(&to_static_duals::<$n>(params))),)*
+                    _ => self.residual(params),
+                }
+            }
+        }
+    };
+}
+
+/// `target.cast().new_from_params(params)` without casting the params of `target` first, with
+/// `xy_same_focal` the single focal of `params` is used for both fx and fy.
+fn model_from_params<T: na::RealField>(
+    target: &GenericModel<f64>,
+    params: &na::DVector<T>,
+    xy_same_focal: bool,
+) -> GenericModel<T> {
+    let expanded;
+    let params = if xy_same_focal {
+        expanded = na::DVector::from_iterator(
+            params.len() + 1,
+            std::iter::once(params[0].clone()).chain(params.iter().cloned()),
+        );
+        &expanded
+    } else {
+        params
+    };
+    match target {
+        GenericModel::EUCM(m) => GenericModel::EUCM(EUCM::new(params, m.width, m.height)),
+        GenericModel::UCM(m) => GenericModel::UCM(UCM::new(params, m.width, m.height)),
+        GenericModel::OpenCVModel5(m) => {
+            GenericModel::OpenCVModel5(OpenCVModel5::new(params, m.width, m.height))
+        }
+        GenericModel::KannalaBrandt4(m) => {
+            GenericModel::KannalaBrandt4(KannalaBrandt4::new(params, m.width, m.height))
+        }
+        GenericModel::EUCMT(m) => GenericModel::EUCMT(EUCMT::new(params, m.width, m.height)),
+        GenericModel::Ftheta(m) => GenericModel::Ftheta(Ftheta::new(params, m.width, m.height)),
+    }
+}
+
+/// `p2d_p - p2d` of a reprojection, computed on the stack.
+fn reprojection_residual<T: na::RealField>(
+    p2d_p: na::Vector2<T>,
+    p2d: &na::Vector2<f64>,
+) -> na::DVector<T> {
+    let [[u, v]] = (p2d_p - p2d.cast::<T>()).data.0;
+    na::dvector![u, v]
+}
+
 #[derive(Clone)]
 pub struct ModelConvertFactor {
     pub source: GenericModel<f64>,
     pub target: GenericModel<f64>,
     pub p3ds: Vec<na::Vector3<f64>>,
+    /// Projections of `p3ds` by the source, which don't change while solving.
+    pub p2ds: Vec<Option<na::Vector2<f64>>>,
 }
 
 impl ModelConvertFactor {
@@ -29,7 +130,7 @@ impl ModelConvertFactor {
                 p2ds.push(na::Vector2::new(c as f64, r as f64));
             }
         }
-        let p3ds = source.unproject(&p2ds);
+        let p3ds = batch::unproject(source, &p2ds);
         let p3ds: Vec<_> = p3ds
             .iter()
             .filter_map(|p| p.as_ref().map(|pp| pp.cast()))
@@ -37,6 +138,7 @@ impl ModelConvertFactor {
         ModelConvertFactor {
             source: source.cast(),
             target: target.cast(),
+            p2ds: batch::project(source, &p3ds),
             p3ds,
         }
     }
@@ -47,21 +149,20 @@ impl ModelConvertFactor {
 
 impl<T: na::RealField> Factor<T> for ModelConvertFactor {
     fn residual_func(&self, params: &[nalgebra::DVector<T>]) -> nalgebra::DVector<T> {
-        let model = self.target.cast::<T>().new_from_params(&params[0]);
-        let p3d_template: Vec<_> = self.p3ds.iter().map(|&p| p.cast::<T>()).collect();
-        let p2ds0 = self.source.cast::<T>().project(&p3d_template);
+        let model = model_from_params(&self.target, &params[0], false);
+        let p3d_template: Vec<_> = self.p3ds.par_iter().map(|p| p.cast::<T>()).collect();
+        // projected in parallel by the model
         let p2ds1 = model.project(&p3d_template);
-        let diff: Vec<_> = p2ds0
-            .iter()
+        let diff: Vec<_> = self
+            .p2ds
+            .par_iter()
             .zip(p2ds1)
-            .flat_map(|(p0, p1)| {
-                if let Some(p0) = p0 {
-                    if let Some(p1) = p1 {
-                        let pp = p0 - p1;
-                        return vec![pp[0].clone(), pp[1].clone()];
-                    }
+            .flat_map_iter(|(p0, p1)| match (p0, p1) {
+                (Some(p0), Some(p1)) => {
+                    let pp = p0.cast::<T>() - p1;
+                    [pp[0].clone(), pp[1].clone()]
                 }
-                vec![T::from_f64(10000.0).unwrap(), T::from_f64(10000.0).unwrap()]
+                _ => [T::from_f64(10000.0).unwrap(), T::from_f64(10000.0).unwrap()],
             })
             .collect();
         na::DVector::from_vec(diff)
@@ -86,33 +187,44 @@ impl UCMInitFocalAlphaFactor {
         UCMInitFocalAlphaFactor { target, p3d, p2d }
     }
 }
-impl<T: na::RealField> Factor<T> for UCMInitFocalAlphaFactor {
-    fn residual_func(&self, params: &[nalgebra::DVector<T>]) -> nalgebra::DVector<T> {
+impl UCMInitFocalAlphaFactor {
+    fn residual<T: na::RealField>(&self, params: &[na::DVector<T>]) -> na::DVector<T> {
         // params[[f, alpha], rvec, tvec]
-        let mut cam_params = self.target.cast::<T>().params();
-        cam_params[0] = params[0][0].clone();
-        cam_params[1] = params[0][0].clone();
-        cam_params[4] = params[0][1].clone();
-        let model = self.target.cast().new_from_params(&cam_params);
+        let model = match &self.target {
+            GenericModel::UCM(m) => GenericModel::UCM(UCM {
+                fx: params[0][0].clone(),
+                fy: params[0][0].clone(),
+                cx: T::from_f64(m.cx).unwrap(),
+                cy: T::from_f64(m.cy).unwrap(),
+                alpha: params[0][1].clone(),
+                width: m.width,
+                height: m.height,
+            }),
+            _ => {
+                let mut cam_params = self.target.cast::<T>().params();
+                cam_params[0] = params[0][0].clone();
+                cam_params[1] = params[0][0].clone();
+                cam_params[4] = params[0][1].clone();
+                model_from_params(&self.target, &cam_params, false)
+            }
+        };
         let rvec = params[1].to_vec3();
         let tvec = params[2].to_vec3();
         let transform = na::Isometry3::new(tvec, rvec);
-        let p3d_t = transform * self.p3d.cast();
-        let p3d_t = na::Vector3::new(p3d_t.x.clone(), p3d_t.y.clone(), p3d_t.z.clone());
+        let p3d_t = (transform * self.p3d.cast()).coords;
         let p2d_p = model.project_one(&p3d_t);
-        let p2d_tp = self.p2d.cast::<T>();
-        na::dvector![
-            p2d_p[0].clone() - p2d_tp[0].clone(),
-            p2d_p[1].clone() - p2d_tp[1].clone()
-        ]
+        reprojection_residual(p2d_p, &self.p2d)
     }
 }
+impl_static_dual_factor!(UCMInitFocalAlphaFactor, [8]);
 
 pub struct ReprojectionFactor {
     pub target: GenericModel<f64>,
     pub p3d: na::Point3<f64>,
     pub p2d: na::Vector2<f64>,
     pub xy_same_focal: bool,
+    /// Weight of the residual, the identity for a sharp corner.
+    pub sqrt_information: na::Matrix2<f64>,
 }
 
 impl ReprojectionFactor {
@@ -130,37 +242,71 @@ impl ReprojectionFactor {
             p3d,
             p2d,
             xy_same_focal,
+            sqrt_information: na::Matrix2::identity(),
         }
     }
+
+    /// Down-weights the residual along the motion blur of the corner.
+    pub fn with_blur(mut self, blur: Option<&CornerBlur>) -> Self {
+        if let Some(blur) = blur {
+            self.sqrt_information = blur.sqrt_information();
+        }
+        self
+    }
 }
-impl<T: na::RealField> Factor<T> for ReprojectionFactor {
-    fn residual_func(&self, params: &[nalgebra::DVector<T>]) -> nalgebra::DVector<T> {
+impl ReprojectionFactor {
+    fn residual<T: na::RealField>(&self, params: &[na::DVector<T>]) -> na::DVector<T> {
         // params[params, rvec, tvec]
-        let mut params0 = params[0].clone();
-        if self.xy_same_focal {
-            params0 = params0.clone().insert_row(1, params0[0].clone());
-        }
-        let model = self.target.cast().new_from_params(&params0);
+        let model = model_from_params(&self.target, &params[0], self.xy_same_focal);
         let rvec = params[1].to_vec3();
         let tvec = params[2].to_vec3();
         let transform = na::Isometry3::new(tvec, rvec);
-        let p3d_t = transform * self.p3d.cast();
-        let p3d_t = na::Vector3::new(p3d_t.x.clone(), p3d_t.y.clone(), p3d_t.z.clone());
+        let p3d_t = (transform * self.p3d.cast()).coords;
         let p2d_p = model.project_one(&p3d_t);
 
-        let p2d_tp = self.p2d.cast::<T>();
-        na::dvector![
-            p2d_p[0].clone() - p2d_tp[0].clone(),
-            p2d_p[1].clone() - p2d_tp[1].clone()
-        ]
+        reprojection_residual(
+            self.sqrt_information.cast() * (p2d_p - self.p2d.cast()),
+            &na::Vector2::zeros(),
+        )
+    }
+}
+impl_static_dual_factor!(ReprojectionFactor, [10, 11, 12, 13, 14, 15]);
+
+/// `ReprojectionFactor` with fixed intrinsics, only the board pose is optimized.
+pub struct PoseReprojectionFactor {
+    pub target: GenericModel<f64>,
+    pub p3d: na::Point3<f64>,
+    pub p2d: na::Vector2<f64>,
+}
+
+impl PoseReprojectionFactor {
+    pub fn new(target: &GenericModel<f64>, p3d: &glam::Vec3, p2d: &glam::Vec2) -> Self {
+        PoseReprojectionFactor {
+            target: *target,
+            p3d: na::Point3::new(p3d.x, p3d.y, p3d.z).cast(),
+            p2d: na::Vector2::new(p2d.x, p2d.y).cast(),
+        }
     }
 }
+impl PoseReprojectionFactor {
+    fn residual<T: na::RealField>(&self, params: &[na::DVector<T>]) -> na::DVector<T> {
+        // params[rvec, tvec]
+        let model = self.target.cast::<T>();
+        let transform = na::Isometry3::new(params[1].to_vec3(), params[0].to_vec3());
+        let p3d_t = transform * self.p3d.cast();
+        let p2d_p = model.project_one(&p3d_t.coords);
+        reprojection_residual(p2d_p, &self.p2d)
+    }
+}
+impl_static_dual_factor!(PoseReprojectionFactor, [6]);
 
 pub struct OtherCamReprojectionFactor {
     pub target: GenericModel<f64>,
     pub p3d: na::Point3<f64>,
     pub p2d: na::Vector2<f64>,
     pub xy_same_focal: bool,
+    /// Weight of the residual, the identity for a sharp corner.
+    pub sqrt_information: na::Matrix2<f64>,
 }
 
 impl OtherCamReprojectionFactor {
@@ -178,34 +324,38 @@ impl OtherCamReprojectionFactor {
             p3d,
             p2d,
             xy_same_focal,
+            sqrt_information: na::Matrix2::identity(),
+        }
+    }
+
+    /// Down-weights the residual along the motion blur of the corner.
+    pub fn with_blur(mut self, blur: Option<&CornerBlur>) -> Self {
+        if let Some(blur) = blur {
+            self.sqrt_information = blur.sqrt_information();
         }
+        self
     }
 }
-impl<T: na::RealField> Factor<T> for OtherCamReprojectionFactor {
-    fn residual_func(&self, params: &[nalgebra::DVector<T>]) -> nalgebra::DVector<T> {
+impl OtherCamReprojectionFactor {
+    fn residual<T: na::RealField>(&self, params: &[na::DVector<T>]) -> na::DVector<T> {
         // params[params, rvec, tvec]
-        let mut params0 = params[0].clone();
-        if self.xy_same_focal {
-            params0 = params0.clone().insert_row(1, params0[0].clone());
-        }
-        let model = self.target.cast().new_from_params(&params0);
+        let model = model_from_params(&self.target, &params[0], self.xy_same_focal);
         let rvec0 = params[1].to_vec3();
         let tvec0 = params[2].to_vec3();
         let t_0_b = na::Isometry3::new(tvec0, rvec0);
         let rvec1 = params[3].to_vec3();
         let tvec1 = params[4].to_vec3();
         let t_i_0 = na::Isometry3::new(tvec1, rvec1);
-        let p3d_t = t_i_0 * t_0_b * self.p3d.cast();
-        let p3d_t = na::Vector3::new(p3d_t.x.clone(), p3d_t.y.clone(), p3d_t.z.clone());
+        let p3d_t = (t_i_0 * t_0_b * self.p3d.cast()).coords;
         let p2d_p = model.project_one(&p3d_t);
 
-        let p2d_tp = self.p2d.cast::<T>();
-        na::dvector![
-            p2d_p[0].clone() - p2d_tp[0].clone(),
-            p2d_p[1].clone() - p2d_tp[1].clone()
-        ]
+        reprojection_residual(
+            self.sqrt_information.cast() * (p2d_p - self.p2d.cast()),
+            &na::Vector2::zeros(),
+        )
     }
 }
+impl_static_dual_factor!(OtherCamReprojectionFactor, [16, 17, 18, 19, 20, 21]);
 
 pub struct SE3Factor {
     pub t_0_b: na::Isometry3<f64>,
@@ -246,3 +396,562 @@ impl<T: na::RealField> Factor<T> for SE3Factor {
         ]
     }
 }
+
+/// Prior on the board to camera pose of a frame, params[rvec, tvec].
+pub struct PosePriorFactor {
+    pub t_cam_board: na::Isometry3<f64>,
+    pub rotation_weight: f64,
+    pub translation_weight: f64,
+}
+
+impl PosePriorFactor {
+    pub fn new(
+        t_cam_board: &na::Isometry3<f64>,
+        rotation_weight: f64,
+        translation_weight: f64,
+    ) -> PosePriorFactor {
+        PosePriorFactor {
+            t_cam_board: *t_cam_board,
+            rotation_weight,
+            translation_weight,
+        }
+    }
+}
+
+impl<T: na::RealField> Factor<T> for PosePriorFactor {
+    fn residual_func(&self, params: &[nalgebra::DVector<T>]) -> nalgebra::DVector<T> {
+        let rvec = params[0].to_vec3();
+        let tvec = params[1].to_vec3();
+        let t_cam_board = na::Isometry3::new(tvec, rvec);
+        let t_diff = self.t_cam_board.cast().inverse() * t_cam_board;
+        let r_diff = t_diff.rotation.scaled_axis() * T::from_f64(self.rotation_weight).unwrap();
+        let t_diff = t_diff.translation.vector * T::from_f64(self.translation_weight).unwrap();
+        na::dvector![
+            r_diff[0].clone(),
+            r_diff[1].clone(),
+            r_diff[2].clone(),
+            t_diff[0].clone(),
+            t_diff[1].clone(),
+            t_diff[2].clone(),
+        ]
+    }
+}
+
+/// Known distance between the camera centers of two frames, params[rvec0, tvec0, rvec1, tvec1].
+pub struct CameraDistanceFactor {
+    pub distance: f64,
+    pub weight: f64,
+}
+
+impl CameraDistanceFactor {
+    pub fn new(distance: f64, weight: f64) -> CameraDistanceFactor {
+        CameraDistanceFactor { distance, weight }
+    }
+}
+
+impl<T: na::RealField> Factor<T> for CameraDistanceFactor {
+    fn residual_func(&self, params: &[nalgebra::DVector<T>]) -> nalgebra::DVector<T> {
+        let t_cam0_board = na::Isometry3::new(params[1].to_vec3(), params[0].to_vec3());
+        let t_cam1_board = na::Isometry3::new(params[3].to_vec3(), params[2].to_vec3());
+        let c0 = t_cam0_board.inverse().translation.vector;
+        let c1 = t_cam1_board.inverse().translation.vector;
+        let d = (c0 - c1).norm();
+        na::dvector![(d - T::from_f64(self.distance).unwrap()) * T::from_f64(self.weight).unwrap()]
+    }
+}
+
+/// Reprojection of a rolling shutter camera, the pose moves with a constant velocity while the
+/// rows are read out. params[params, rvec, tvec, angular velocity, linear velocity, line delay],
+/// velocities in the camera frame (rad/s, m/s) and the line delay in us. Corners with a known
+/// capture time are moved by it and have no line delay param.
+pub struct RollingShutterReprojectionFactor {
+    pub target: GenericModel<f64>,
+    pub p3d: na::Point3<f64>,
+    pub p2d: na::Vector2<f64>,
+    pub xy_same_focal: bool,
+    /// Capture time of the corner after the time of the frame in s, `None` if it's from its row
+    /// and the line delay.
+    pub capture_dt: Option<f64>,
+}
+
+impl RollingShutterReprojectionFactor {
+    pub fn new(
+        target: &GenericModel<f64>,
+        fp: &FeaturePoint,
+        frame_time_ns: i64,
+        xy_same_focal: bool,
+    ) -> RollingShutterReprojectionFactor {
+        RollingShutterReprojectionFactor {
+            target: *target,
+            p3d: na::Point3::new(fp.p3d.x, fp.p3d.y, fp.p3d.z).cast(),
+            p2d: na::Vector2::new(fp.p2d.x, fp.p2d.y).cast(),
+            xy_same_focal,
+            capture_dt: fp
+                .time_ns
+                .map(|_| (fp.capture_time_ns(frame_time_ns) - frame_time_ns) as f64 * 1e-9),
+        }
+    }
+}
+
+impl<T: na::RealField> Factor<T> for RollingShutterReprojectionFactor {
+    fn residual_func(&self, params: &[nalgebra::DVector<T>]) -> nalgebra::DVector<T> {
+        let model = model_from_params(&self.target, &params[0], self.xy_same_focal);
+        let transform = na::Isometry3::new(params[2].to_vec3(), params[1].to_vec3());
+        let angular_velocity = params[3].to_vec3();
+        let linear_velocity = params[4].to_vec3();
+        let dt = match self.capture_dt {
+            Some(dt) => T::from_f64(dt).unwrap(),
+            // the middle row is the time of the frame
+            None => {
+                T::from_f64((self.p2d.y - self.target.height() / 2.0) * 1e-6).unwrap()
+                    * params[5][0].clone()
+            }
+        };
+        let p = (transform * self.p3d.cast()).coords;
+        let p = p.clone() + (angular_velocity.cross(&p) + linear_velocity) * dt;
+        let p2d_p = model.project_one(&p);
+        reprojection_residual(p2d_p, &self.p2d)
+    }
+}
+
+/// Reprojection of a frame `dt` °C from the reference temperature, the focal and principal point
+/// drift with a polynomial of the temperature. params[params, thermal drift, rvec, tvec], the
+/// drift is `[fx, fy, cx, cy]` of every degree, `[f, cx, cy]` with `xy_same_focal`.
+pub struct ThermalReprojectionFactor {
+    pub target: GenericModel<f64>,
+    pub p3d: na::Point3<f64>,
+    pub p2d: na::Vector2<f64>,
+    pub xy_same_focal: bool,
+    pub dt: f64,
+}
+
+impl ThermalReprojectionFactor {
+    pub fn new(
+        target: &GenericModel<f64>,
+        p3d: &glam::Vec3,
+        p2d: &glam::Vec2,
+        xy_same_focal: bool,
+        dt: f64,
+    ) -> ThermalReprojectionFactor {
+        ThermalReprojectionFactor {
+            target: *target,
+            p3d: na::Point3::new(p3d.x, p3d.y, p3d.z).cast(),
+            p2d: na::Vector2::new(p2d.x, p2d.y).cast(),
+            xy_same_focal,
+            dt,
+        }
+    }
+}
+
+impl<T: na::RealField> Factor<T> for ThermalReprojectionFactor {
+    fn residual_func(&self, params: &[nalgebra::DVector<T>]) -> nalgebra::DVector<T> {
+        let stride = if self.xy_same_focal { 3 } else { 4 };
+        let mut params0 = params[0].clone();
+        let dt = T::from_f64(self.dt).unwrap();
+        let mut dt_k = dt.clone();
+        for drift in params[1].as_slice().chunks(stride) {
+            for (i, d) in drift.iter().enumerate() {
+                params0[i] += d.clone() * dt_k.clone();
+            }
+            dt_k *= dt.clone();
+        }
+        let model = model_from_params(&self.target, &params0, self.xy_same_focal);
+        let transform = na::Isometry3::new(params[3].to_vec3(), params[2].to_vec3());
+        let p2d_p = model.project_one(&(transform * self.p3d.cast()).coords);
+        reprojection_residual(p2d_p, &self.p2d)
+    }
+}
+
+/// A corner seen through the flat port of an underwater housing, params[[x, y, distance] of the
+/// port, rvec, tvec]. The in-air ray `dir` of the detection is refracted into the water and the
+/// residual is the offset of the corner from the ray over its distance along it, scaled to px.
+pub struct FlatPortFactor {
+    pub dir: na::Vector3<f64>,
+    pub p3d: na::Point3<f64>,
+    pub params: FlatPortParams,
+    pub scale: f64,
+}
+
+impl FlatPortFactor {
+    pub fn new(
+        dir: &na::Vector3<f64>,
+        p3d: &glam::Vec3,
+        params: &FlatPortParams,
+        scale: f64,
+    ) -> FlatPortFactor {
+        FlatPortFactor {
+            dir: *dir,
+            p3d: na::Point3::new(p3d.x, p3d.y, p3d.z).cast(),
+            params: params.clone(),
+            scale,
+        }
+    }
+}
+impl FlatPortFactor {
+    fn residual<T: na::RealField>(&self, params: &[na::DVector<T>]) -> na::DVector<T> {
+        let normal = port_normal(&params[0]);
+        let (origin, dir) = water_ray(
+            &self.dir.cast(),
+            &normal,
+            params[0][2].clone(),
+            &self.params,
+        );
+        let transform = na::Isometry3::new(params[2].to_vec3(), params[1].to_vec3());
+        let v = (transform * self.p3d.cast()).coords - origin;
+        let along = v.dot(&dir);
+        let offset = (v - dir * along.clone()) * (T::from_f64(self.scale).unwrap() / along);
+        na::dvector![offset[0].clone(), offset[1].clone(), offset[2].clone()]
+    }
+}
+impl_static_dual_factor!(FlatPortFactor, [9]);
+
+/// Reprojection of a line-scan camera, the detected `p2d` is the column and the line.
+/// params[[f, cu, k1, k2], velocity, rvec, tvec].
+pub struct LineScanReprojectionFactor {
+    pub p3d: na::Point3<f64>,
+    pub p2d: na::Vector2<f64>,
+}
+
+impl LineScanReprojectionFactor {
+    pub fn new(p3d: &glam::Vec3, p2d: &glam::Vec2) -> LineScanReprojectionFactor {
+        LineScanReprojectionFactor {
+            p3d: na::Point3::new(p3d.x, p3d.y, p3d.z).cast(),
+            p2d: na::Vector2::new(p2d.x, p2d.y).cast(),
+        }
+    }
+}
+impl LineScanReprojectionFactor {
+    fn residual<T: na::RealField>(&self, params: &[na::DVector<T>]) -> na::DVector<T> {
+        let transform = na::Isometry3::new(params[3].to_vec3(), params[2].to_vec3());
+        let p = (transform * self.p3d.cast()).coords;
+        let p2d_p = line_scan_project(&params[0], &params[1].to_vec3(), &p);
+        reprojection_residual(p2d_p, &self.p2d)
+    }
+}
+impl_static_dual_factor!(LineScanReprojectionFactor, [13]);
+
+/// Reprojection of a telecentric camera, params[[mx, my, k1, k2, p1, p2], rvec, [tx, ty]].
+pub struct TelecentricReprojectionFactor {
+    pub principal_point: na::Vector2<f64>,
+    pub p3d: na::Point3<f64>,
+    pub p2d: na::Vector2<f64>,
+}
+
+impl TelecentricReprojectionFactor {
+    pub fn new(
+        principal_point: &na::Vector2<f64>,
+        p3d: &glam::Vec3,
+        p2d: &glam::Vec2,
+    ) -> TelecentricReprojectionFactor {
+        TelecentricReprojectionFactor {
+            principal_point: *principal_point,
+            p3d: na::Point3::new(p3d.x, p3d.y, p3d.z).cast(),
+            p2d: na::Vector2::new(p2d.x, p2d.y).cast(),
+        }
+    }
+}
+impl TelecentricReprojectionFactor {
+    fn residual<T: na::RealField>(&self, params: &[na::DVector<T>]) -> na::DVector<T> {
+        let rotation = na::Rotation3::new(params[1].to_vec3());
+        let p = rotation * self.p3d.cast::<T>().coords;
+        let p = na::Vector2::new(p.x.clone(), p.y.clone())
+            + na::Vector2::new(params[2][0].clone(), params[2][1].clone());
+        reprojection_residual(
+            telecentric_project(&params[0], &self.principal_point, &p),
+            &self.p2d,
+        )
+    }
+}
+impl_static_dual_factor!(TelecentricReprojectionFactor, [11]);
+
+/// The board to camera poses of two frames `dt` seconds apart are related by the velocity,
+/// params[rvec0, tvec0, rvec1, tvec1, angular velocity, linear velocity].
+pub struct ConstantVelocityFactor {
+    pub dt: f64,
+    pub rotation_weight: f64,
+    pub translation_weight: f64,
+}
+
+impl ConstantVelocityFactor {
+    pub fn new(dt: f64, rotation_weight: f64, translation_weight: f64) -> ConstantVelocityFactor {
+        ConstantVelocityFactor {
+            dt,
+            rotation_weight,
+            translation_weight,
+        }
+    }
+}
+
+impl<T: na::RealField> Factor<T> for ConstantVelocityFactor {
+    fn residual_func(&self, params: &[nalgebra::DVector<T>]) -> nalgebra::DVector<T> {
+        let t_cam0_board = na::Isometry3::new(params[1].to_vec3(), params[0].to_vec3());
+        let t_cam1_board = na::Isometry3::new(params[3].to_vec3(), params[2].to_vec3());
+        let t_1_0 = t_cam1_board * t_cam0_board.inverse();
+        let dt = T::from_f64(self.dt).unwrap();
+        let r_diff = (t_1_0.rotation.scaled_axis() - params[4].to_vec3() * dt.clone())
+            * T::from_f64(self.rotation_weight).unwrap();
+        let t_diff = (t_1_0.translation.vector - params[5].to_vec3() * dt)
+            * T::from_f64(self.translation_weight).unwrap();
+        na::dvector![
+            r_diff[0].clone(),
+            r_diff[1].clone(),
+            r_diff[2].clone(),
+            t_diff[0].clone(),
+            t_diff[1].clone(),
+            t_diff[2].clone(),
+        ]
+    }
+}
+
+/// Hand-eye loop of a frame, `T_base_board = T_base_flange * T_flange_cam * T_cam_board`,
+/// params[rvec, tvec of T_flange_cam, rvec, tvec of T_base_board].
+pub struct HandEyeFactor {
+    pub t_base_flange: na::Isometry3<f64>,
+    pub t_cam_board: na::Isometry3<f64>,
+    pub rotation_weight: f64,
+    pub translation_weight: f64,
+}
+
+impl HandEyeFactor {
+    pub fn new(
+        t_base_flange: &na::Isometry3<f64>,
+        t_cam_board: &na::Isometry3<f64>,
+        rotation_weight: f64,
+        translation_weight: f64,
+    ) -> HandEyeFactor {
+        HandEyeFactor {
+            t_base_flange: *t_base_flange,
+            t_cam_board: *t_cam_board,
+            rotation_weight,
+            translation_weight,
+        }
+    }
+}
+
+impl<T: na::RealField> Factor<T> for HandEyeFactor {
+    fn residual_func(&self, params: &[nalgebra::DVector<T>]) -> nalgebra::DVector<T> {
+        let t_flange_cam = na::Isometry3::new(params[1].to_vec3(), params[0].to_vec3());
+        let t_base_board = na::Isometry3::new(params[3].to_vec3(), params[2].to_vec3());
+        let t_diff = t_base_board.inverse()
+            * self.t_base_flange.cast()
+            * t_flange_cam
+            * self.t_cam_board.cast();
+        let r_diff = t_diff.rotation.scaled_axis() * T::from_f64(self.rotation_weight).unwrap();
+        let t_diff = t_diff.translation.vector * T::from_f64(self.translation_weight).unwrap();
+        na::dvector![
+            r_diff[0].clone(),
+            r_diff[1].clone(),
+            r_diff[2].clone(),
+            t_diff[0].clone(),
+            t_diff[1].clone(),
+            t_diff[2].clone(),
+        ]
+    }
+}
+
+/// A lidar point on the board, params[rvec, tvec] of `T_cam_lidar`. The residual is the
+/// distance to the board plane and the distances outside of the board extent in x and y.
+pub struct LidarBoardPointFactor {
+    pub p_lidar: na::Vector3<f64>,
+    pub t_board_cam: na::Isometry3<f64>,
+    pub board_min: na::Vector2<f64>,
+    pub board_max: na::Vector2<f64>,
+}
+
+impl LidarBoardPointFactor {
+    pub fn new(
+        p_lidar: &na::Vector3<f64>,
+        t_cam_board: &na::Isometry3<f64>,
+        board_min: &na::Vector2<f64>,
+        board_max: &na::Vector2<f64>,
+    ) -> LidarBoardPointFactor {
+        LidarBoardPointFactor {
+            p_lidar: *p_lidar,
+            t_board_cam: t_cam_board.inverse(),
+            board_min: *board_min,
+            board_max: *board_max,
+        }
+    }
+}
+
+impl<T: na::RealField> Factor<T> for LidarBoardPointFactor {
+    fn residual_func(&self, params: &[nalgebra::DVector<T>]) -> nalgebra::DVector<T> {
+        let t_cam_lidar = na::Isometry3::new(params[1].to_vec3(), params[0].to_vec3());
+        let p_board = self.t_board_cam.cast() * t_cam_lidar * na::Point3::from(self.p_lidar.cast());
+        let outside = |v: T, lower: f64, upper: f64| {
+            let (lower, upper) = (T::from_f64(lower).unwrap(), T::from_f64(upper).unwrap());
+            if v < lower {
+                lower - v
+            } else if v > upper {
+                v - upper
+            } else {
+                T::zero()
+            }
+        };
+        na::dvector![
+            p_board.z.clone(),
+            outside(p_board.x.clone(), self.board_min.x, self.board_max.x),
+            outside(p_board.y.clone(), self.board_min.y, self.board_max.y),
+        ]
+    }
+}
+
+/// Corner seen by a camera on a body moving along a `spline::PoseSpline` of the board to body
+/// poses, captured at `dt_s` seconds into a segment in camera time.
+/// params[knot0, knot1, knot2, knot3, rvec, tvec of T_cam_body, time offset in ms], the time of
+/// the body is the camera time plus the offset.
+pub struct SplineReprojectionFactor {
+    pub target: GenericModel<f64>,
+    pub p3d: na::Point3<f64>,
+    pub p2d: na::Vector2<f64>,
+    pub r_ref: na::Rotation3<f64>,
+    pub dt_s: f64,
+    pub knot_s: f64,
+}
+
+impl SplineReprojectionFactor {
+    pub fn new(
+        target: &GenericModel<f64>,
+        fp: &FeaturePoint,
+        r_ref: &na::Rotation3<f64>,
+        dt_s: f64,
+        knot_s: f64,
+    ) -> SplineReprojectionFactor {
+        SplineReprojectionFactor {
+            target: *target,
+            p3d: na::Point3::new(fp.p3d.x, fp.p3d.y, fp.p3d.z).cast(),
+            p2d: na::Vector2::new(fp.p2d.x, fp.p2d.y).cast(),
+            r_ref: *r_ref,
+            dt_s,
+            knot_s,
+        }
+    }
+}
+impl SplineReprojectionFactor {
+    fn residual<T: na::RealField>(&self, params: &[na::DVector<T>]) -> na::DVector<T> {
+        let model = self.target.cast::<T>();
+        let offset_s = params[6][0].clone() * T::from_f64(1e-3).unwrap();
+        let u = (T::from_f64(self.dt_s).unwrap() + offset_s) / T::from_f64(self.knot_s).unwrap();
+        let state = evaluate_segment(&params[0..4], u, self.knot_s);
+        let r_board_body = self.r_ref.matrix().cast::<T>() * state.rotation;
+        let p_body = r_board_body.transpose() * (self.p3d.coords.cast::<T>() - state.position);
+        let t_cam_body = na::Isometry3::new(params[5].to_vec3(), params[4].to_vec3());
+        let p_cam = t_cam_body * na::Point3::from(p_body);
+        reprojection_residual(model.project_one(&p_cam.coords), &self.p2d)
+    }
+}
+impl_static_dual_factor!(SplineReprojectionFactor, [31]);
+
+/// Gyro sample `u` into a segment of the body spline, params[knot0, knot1, knot2, knot3, gyro
+/// bias], the residual is divided by the noise of the gyro.
+pub struct SplineGyroFactor {
+    pub gyro: na::Vector3<f64>,
+    pub u: f64,
+    pub knot_s: f64,
+    pub noise: f64,
+}
+
+impl SplineGyroFactor {
+    pub fn new(gyro: &na::Vector3<f64>, u: f64, knot_s: f64, noise: f64) -> SplineGyroFactor {
+        SplineGyroFactor {
+            gyro: *gyro,
+            u,
+            knot_s,
+            noise,
+        }
+    }
+}
+impl SplineGyroFactor {
+    fn residual<T: na::RealField>(&self, params: &[na::DVector<T>]) -> na::DVector<T> {
+        let state = evaluate_segment(&params[0..4], T::from_f64(self.u).unwrap(), self.knot_s);
+        let diff = (state.angular_velocity + params[4].to_vec3() - self.gyro.cast::<T>())
+            / T::from_f64(self.noise).unwrap();
+        na::dvector![diff.x.clone(), diff.y.clone(), diff.z.clone()]
+    }
+}
+impl_static_dual_factor!(SplineGyroFactor, [27]);
+
+/// Accelerometer sample `u` into a segment of the body spline, the specific force
+/// `R_board_body^T * (a - g) + b`. params[knot0, knot1, knot2, knot3, accel bias, gravity in the
+/// board frame], the residual is divided by the noise of the accelerometer.
+pub struct SplineAccelFactor {
+    pub accel: na::Vector3<f64>,
+    pub r_ref: na::Rotation3<f64>,
+    pub u: f64,
+    pub knot_s: f64,
+    pub noise: f64,
+}
+
+impl SplineAccelFactor {
+    pub fn new(
+        accel: &na::Vector3<f64>,
+        r_ref: &na::Rotation3<f64>,
+        u: f64,
+        knot_s: f64,
+        noise: f64,
+    ) -> SplineAccelFactor {
+        SplineAccelFactor {
+            accel: *accel,
+            r_ref: *r_ref,
+            u,
+            knot_s,
+            noise,
+        }
+    }
+}
+impl SplineAccelFactor {
+    fn residual<T: na::RealField>(&self, params: &[na::DVector<T>]) -> na::DVector<T> {
+        let state = evaluate_segment(&params[0..4], T::from_f64(self.u).unwrap(), self.knot_s);
+        let r_board_body = self.r_ref.matrix().cast::<T>() * state.rotation;
+        let specific_force = r_board_body.transpose() * (state.acceleration - params[5].to_vec3());
+        let diff = (specific_force + params[4].to_vec3() - self.accel.cast::<T>())
+            / T::from_f64(self.noise).unwrap();
+        na::dvector![diff.x.clone(), diff.y.clone(), diff.z.clone()]
+    }
+}
+impl_static_dual_factor!(SplineAccelFactor, [30]);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::eucm;
+
+    fn other_cam_residual(factor: &OtherCamReprojectionFactor, model: &GenericModel<f64>) -> f64 {
+        let zero = na::dvector![0.0, 0.0, 0.0];
+        let params = [
+            model.params(),
+            zero.clone(),
+            zero.clone(),
+            zero.clone(),
+            zero,
+        ];
+        factor.residual_func(&params).norm()
+    }
+
+    #[test]
+    fn other_cam_blur_weight() {
+        let model = eucm();
+        let p3d = glam::Vec3::new(0.1, -0.05, 1.0);
+        let p2d_p = model.project_one(&na::Vector3::new(0.1, -0.05, 1.0));
+        let blur = CornerBlur {
+            angle: 0.0,
+            length: 6.0,
+            edge_angle: 0.0,
+        };
+        for (offset, weighted) in [(glam::Vec2::X, true), (glam::Vec2::Y, false)] {
+            let p2d = glam::Vec2::new(p2d_p.x as f32, p2d_p.y as f32) + offset;
+            let sharp = OtherCamReprojectionFactor::new(&model, &p3d, &p2d, false);
+            let blurred =
+                OtherCamReprojectionFactor::new(&model, &p3d, &p2d, false).with_blur(Some(&blur));
+            let sharp = other_cam_residual(&sharp, &model);
+            let blurred = other_cam_residual(&blurred, &model);
+            assert!((sharp - 1.0).abs() < 1e-4, "{sharp}");
+            if weighted {
+                // 1 / sqrt(1 + 6^2 / 12) along the blur
+                assert!((blurred - 0.5).abs() < 1e-4, "{blurred}");
+            } else {
+                assert!((blurred - sharp).abs() < 1e-4, "{blurred}");
+            }
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use faer::prelude::SpSolverLstsq;
-use log::debug;
 use nalgebra as na;
 use rand::seq::SliceRandom;
+use tracing::debug;
 
 use crate::detected_points::FrameFeature;
 
@@ -229,12 +229,27 @@ pub fn radial_distortion_homography(
             }
         }
     }
-    println!("lambda {}, d {}", best_lambda, best_distance);
-    println!("{}", best_homography_mat);
+    debug!(
+        lambda = best_lambda,
+        distance = best_distance,
+        "radial distortion homography {}",
+        best_homography_mat
+    );
     (best_lambda, best_homography_mat)
 }
 
 pub fn homography_to_focal(h_mat: &na::Matrix3<f32>) -> Option<f32> {
+    match homography_focal_candidates(h_mat) {
+        (Some(f0), Some(f1)) => Some((f0 * f1).sqrt()),
+        (Some(f0), None) => Some(f0),
+        (None, Some(f1)) => Some(f1),
+        _ => None,
+    }
+}
+
+/// Unit plane focals from the two constraints of the homography on the rotation, the one of
+/// the translation column and the one of the perspective row, `None` where it has no solution.
+pub fn homography_focal_candidates(h_mat: &na::Matrix3<f32>) -> (Option<f32>, Option<f32>) {
     let h0 = h_mat[(0, 0)];
     let h1 = h_mat[(0, 1)];
     let h2 = h_mat[(0, 2)];
@@ -279,10 +294,5 @@ pub fn homography_to_focal(h_mat: &na::Matrix3<f32>) -> Option<f32> {
     } else {
         None
     };
-    match (f0, f1) {
-        (Some(f0), Some(f1)) => Some((f0 * f1).sqrt()),
-        (Some(f0), None) => Some(f0),
-        (None, Some(f1)) => Some(f1),
-        _ => None,
-    }
+    (f0, f1)
 }
@@ -1,6 +1,12 @@
+pub mod custom;
 pub mod factors;
 pub mod homography;
 pub mod linear;
+pub mod problem;
+pub mod schur;
 
+pub use custom::*;
 pub use homography::*;
 pub use linear::*;
+pub use problem::*;
+pub use schur::*;
@@ -0,0 +1,212 @@
+use std::collections::{BTreeMap, HashMap};
+
+use camera_intrinsic_model::*;
+use nalgebra as na;
+use tiny_solver::loss_functions::HuberLoss;
+use tiny_solver::Problem;
+
+use super::custom::{rvec_name, tvec_name};
+use super::factors::ReprojectionFactor;
+use super::schur::Plateau;
+use crate::detected_points::FrameFeature;
+use crate::observer::PipelineObserver;
+use crate::types::{CalibParams, RvecTvec};
+use crate::util::{
+    optimize_with_observer, set_problem_parameter_bound, set_problem_parameter_disabled,
+    solve_board_pose,
+};
+
+/// Calibration problem of a single camera kept between solves, with the variables of
+/// `calib_camera`. Frames can be added and removed, and every solve starts from the params and
+/// board poses of the last one, so re-solves after outlier rejection or with new live frames
+/// only initialize the poses of the new frames. tiny-solver can't remove residual blocks, so
+/// removing a frame rebuilds the problem on the next solve while new frames are appended.
+pub struct IncrementalProblem {
+    camera: GenericModel<f64>,
+    xy_same_focal: bool,
+    disabled_distortions: usize,
+    fixed_focal: Option<f64>,
+    plateau: Option<Plateau>,
+    frames: BTreeMap<usize, FrameFeature>,
+    values: HashMap<String, na::DVector<f64>>,
+    problem: Option<Problem>,
+    /// Frames not in `problem` yet.
+    pending: Vec<usize>,
+}
+
+impl IncrementalProblem {
+    pub fn new(camera: &GenericModel<f64>, calib_params: &CalibParams) -> IncrementalProblem {
+        let xy_same_focal = calib_params.one_focal || calib_params.fixed_focal.is_some();
+        let mut params = camera.params();
+        if xy_same_focal {
+            // remove fy
+            params = params.remove_row(1);
+        }
+        if let Some(focal) = calib_params.fixed_focal {
+            params[0] = focal;
+        }
+        IncrementalProblem {
+            camera: *camera,
+            xy_same_focal,
+            disabled_distortions: calib_params.disabled_distortion_num,
+            fixed_focal: calib_params.fixed_focal,
+            plateau: calib_params.plateau,
+            frames: BTreeMap::new(),
+            values: HashMap::from([("params".to_string(), params)]),
+            problem: None,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Warm started from a calibration, with the frames that have a board pose.
+    pub fn from_calibration(
+        model: &GenericModel<f64>,
+        rtvecs: &HashMap<usize, RvecTvec>,
+        frame_feature_list: &[Option<FrameFeature>],
+        calib_params: &CalibParams,
+    ) -> IncrementalProblem {
+        let mut problem = IncrementalProblem::new(model, calib_params);
+        for (&i, rtvec) in rtvecs {
+            if let Some(Some(frame_feature)) = frame_feature_list.get(i) {
+                problem.set_board_pose(i, rtvec);
+                problem.add_frame(i, frame_feature.clone());
+            }
+        }
+        problem
+    }
+
+    /// Model with the current params.
+    pub fn model(&self) -> GenericModel<f64> {
+        let mut params = self.values["params"].clone();
+        if self.xy_same_focal {
+            params = params.clone().insert_row(1, params[0]);
+        }
+        let mut model = self.camera;
+        model.set_params(&params);
+        model
+    }
+
+    /// Current board poses by frame index.
+    pub fn rtvecs(&self) -> HashMap<usize, RvecTvec> {
+        self.frames
+            .keys()
+            .map(|&i| (i, self.board_pose(i).unwrap()))
+            .collect()
+    }
+
+    pub fn board_pose(&self, frame_idx: usize) -> Option<RvecTvec> {
+        Some(RvecTvec::new(
+            self.values.get(&rvec_name(frame_idx))?,
+            self.values.get(&tvec_name(frame_idx))?,
+        ))
+    }
+
+    /// Initial board pose of a frame, e.g. of the same frame in another camera.
+    pub fn set_board_pose(&mut self, frame_idx: usize, rtvec: &RvecTvec) {
+        self.values.insert(rvec_name(frame_idx), rtvec.na_rvec());
+        self.values.insert(tvec_name(frame_idx), rtvec.na_tvec());
+    }
+
+    pub fn frame_indexes(&self) -> impl Iterator<Item = &usize> {
+        self.frames.keys()
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Adds or replaces a frame. Its board pose is solved with the current model if it wasn't
+    /// set, returns false if that fails.
+    pub fn add_frame(&mut self, frame_idx: usize, frame_feature: FrameFeature) -> bool {
+        if self.frames.contains_key(&frame_idx) {
+            self.remove_frame(frame_idx);
+        }
+        if self.board_pose(frame_idx).is_none() {
+            let Some(rtvec) = solve_board_pose(&self.model(), &frame_feature) else {
+                return false;
+            };
+            self.set_board_pose(frame_idx, &rtvec);
+        }
+        self.frames.insert(frame_idx, frame_feature);
+        self.pending.push(frame_idx);
+        true
+    }
+
+    /// Removes a frame and its board pose.
+    pub fn remove_frame(&mut self, frame_idx: usize) -> Option<FrameFeature> {
+        let frame_feature = self.frames.remove(&frame_idx)?;
+        self.values.remove(&rvec_name(frame_idx));
+        self.values.remove(&tvec_name(frame_idx));
+        self.pending.retain(|&i| i != frame_idx);
+        self.problem = None;
+        Some(frame_feature)
+    }
+
+    fn add_residual_blocks(&mut self, frame_idx: usize) {
+        let (Some(problem), Some(frame_feature)) =
+            (self.problem.as_mut(), self.frames.get(&frame_idx))
+        else {
+            return;
+        };
+        let params_len = self.values["params"].len();
+        let rvec_name = rvec_name(frame_idx);
+        let tvec_name = tvec_name(frame_idx);
+        for fp in frame_feature.features.values() {
+            let cost = ReprojectionFactor::new(&self.camera, &fp.p3d, &fp.p2d, self.xy_same_focal)
+                .with_blur(fp.blur.as_ref());
+            problem.add_residual_block(
+                2,
+                &[("params", params_len), (&rvec_name, 3), (&tvec_name, 3)],
+                Box::new(cost),
+                Some(Box::new(HuberLoss::new(1.0))),
+            );
+        }
+    }
+
+    fn update_problem(&mut self) {
+        if self.problem.is_none() {
+            let mut problem = Problem::new();
+            set_problem_parameter_bound("params", &mut problem, &self.camera, self.xy_same_focal);
+            set_problem_parameter_disabled(
+                "params",
+                &mut problem,
+                &mut self.values,
+                &self.camera,
+                self.xy_same_focal,
+                self.disabled_distortions,
+            );
+            if self.fixed_focal.is_some() {
+                problem.fix_variable("params", 0);
+            }
+            self.problem = Some(problem);
+            self.pending = self.frames.keys().copied().collect();
+        }
+        for i in std::mem::take(&mut self.pending) {
+            self.add_residual_blocks(i);
+        }
+    }
+
+    /// Solves from the current values and keeps the result for the next solve. The values are
+    /// left unchanged if the optimization fails.
+    pub fn solve(
+        &mut self,
+        observer: &dyn PipelineObserver,
+    ) -> Option<(GenericModel<f64>, HashMap<usize, RvecTvec>)> {
+        if self.frames.is_empty() {
+            return None;
+        }
+        self.update_problem();
+        let result = optimize_with_observer(
+            self.problem.as_ref()?,
+            &self.values,
+            "incremental_problem",
+            self.plateau,
+            observer,
+        )?;
+        if result.values().any(|v| v.iter().any(|x| !x.is_finite())) {
+            return None;
+        }
+        self.values.extend(result);
+        Some((self.model(), self.rtvecs()))
+    }
+}
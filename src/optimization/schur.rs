@@ -0,0 +1,522 @@
+use std::collections::HashMap;
+
+use faer::sparse::{SparseColMat, SparseRowMat};
+use nalgebra as na;
+use rayon::prelude::*;
+use tiny_solver::linear::SparseCholeskySolver;
+use tiny_solver::sparse::SparseLinearSolver;
+use tiny_solver::{Optimizer, OptimizerOptions, Problem};
+use tracing::{debug, trace};
+
+/// Prefixes of the variables of a single frame, followed by the frame index.
+const FRAME_VARIABLES: [&str; 6] = [
+    "rvec",
+    "tvec",
+    "rvec_0_b_",
+    "tvec_0_b_",
+    "angular_velocity",
+    "linear_velocity",
+];
+
+/// Frame index of a per-frame variable like `rvec{i}` of `calib_camera`, `None` for the shared
+/// ones like `params` or the extrinsics `rvec_{i}_0`.
+fn frame_key(name: &str) -> Option<&str> {
+    let prefix = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    (prefix.len() < name.len() && FRAME_VARIABLES.contains(&prefix)).then(|| &name[prefix.len()..])
+}
+
+fn to_dvector(m: &faer::Mat<f64>) -> na::DVector<f64> {
+    na::DVector::from_fn(m.nrows(), |i, _| m[(i, 0)])
+}
+
+#[derive(Clone, Copy)]
+enum Column {
+    /// Index in the intrinsics and extrinsics.
+    Global(usize),
+    /// Frame block and index in it.
+    Pose(usize, usize),
+}
+
+/// Normal equations `H dx = b` of the rows of a frame, `c` the shared variables and `p` the
+/// variables of the frame.
+struct FrameBlock<T: na::RealField> {
+    h_cc: na::DMatrix<T>,
+    b_c: na::DVector<T>,
+    h_cp: na::DMatrix<T>,
+    h_pp: na::DMatrix<T>,
+    b_p: na::DVector<T>,
+}
+
+/// Columns of the jacobian split into the shared variables and the blocks of the frames.
+struct BlockLayout {
+    columns: Vec<Column>,
+    global_len: usize,
+    pose_lens: Vec<usize>,
+}
+
+impl BlockLayout {
+    fn new(problem: &Problem, values: &HashMap<String, na::DVector<f64>>) -> BlockLayout {
+        let mut columns = vec![Column::Global(0); problem.total_variable_dimension];
+        let mut global_len = 0;
+        let mut pose_lens = Vec::new();
+        let mut pose_blocks = HashMap::new();
+        let mut names: Vec<_> = problem.variable_name_to_col_idx_dict.iter().collect();
+        names.sort_by_key(|(_, &col)| col);
+        for (name, &col) in names {
+            let Some(len) = values.get(name).map(|v| v.len()) else {
+                continue;
+            };
+            for column in columns[col..col + len].iter_mut() {
+                *column = if let Some(key) = frame_key(name) {
+                    let block = *pose_blocks.entry(key).or_insert_with(|| {
+                        pose_lens.push(0);
+                        pose_lens.len() - 1
+                    });
+                    pose_lens[block] += 1;
+                    Column::Pose(block, pose_lens[block] - 1)
+                } else {
+                    global_len += 1;
+                    Column::Global(global_len - 1)
+                };
+            }
+        }
+        BlockLayout {
+            columns,
+            global_len,
+            pose_lens,
+        }
+    }
+
+    /// Normal equations of the rows of one frame block with `n` variables, or of the rows
+    /// without frame variables with `n = 0`.
+    fn frame_block<T: na::RealField + Copy>(
+        &self,
+        jacobian: &SparseRowMat<usize, f64>,
+        residuals: &na::DVector<T>,
+        rows: &[usize],
+        n: usize,
+    ) -> FrameBlock<T> {
+        let g = self.global_len;
+        let mut block = FrameBlock {
+            h_cc: na::DMatrix::zeros(g, g),
+            b_c: na::DVector::zeros(g),
+            h_cp: na::DMatrix::zeros(g, n),
+            h_pp: na::DMatrix::zeros(n, n),
+            b_p: na::DVector::zeros(n),
+        };
+        for &row in rows {
+            let entries = || {
+                jacobian
+                    .col_indices_of_row(row)
+                    .zip(jacobian.values_of_row(row).iter())
+                    .map(|(col, &v)| (self.columns[col], T::from_subset(&v)))
+            };
+            for (column0, v0) in entries() {
+                match column0 {
+                    Column::Global(i) => block.b_c[i] -= v0 * residuals[row],
+                    Column::Pose(_, i) => block.b_p[i] -= v0 * residuals[row],
+                }
+                for (column1, v1) in entries() {
+                    match (column0, column1) {
+                        (Column::Global(i), Column::Global(j)) => block.h_cc[(i, j)] += v0 * v1,
+                        (Column::Global(i), Column::Pose(_, j)) => block.h_cp[(i, j)] += v0 * v1,
+                        (Column::Pose(_, i), Column::Pose(_, j)) => block.h_pp[(i, j)] += v0 * v1,
+                        (Column::Pose(..), Column::Global(_)) => {}
+                    }
+                }
+            }
+        }
+        block
+    }
+
+    /// Gauss-Newton step with the frame blocks eliminated by the Schur complement, the normal
+    /// equations are built and solved in `T`. `None` if two frames share a residual or a block
+    /// is singular.
+    fn solve<T: na::RealField + Copy + Into<f64>>(
+        &self,
+        residuals: &na::DVector<f64>,
+        jacobian: &SparseColMat<usize, f64>,
+    ) -> Option<na::DVector<f64>> {
+        let residuals: na::DVector<T> = residuals.map(|v| T::from_subset(&v));
+        let residuals = &residuals;
+        let jacobian = jacobian.to_row_major().ok()?;
+        let mut block_rows = vec![Vec::new(); self.pose_lens.len()];
+        let mut shared_rows = Vec::new();
+        for row in 0..jacobian.nrows() {
+            let mut block = None;
+            for col in jacobian.col_indices_of_row(row) {
+                if let Column::Pose(k, _) = self.columns[col] {
+                    if block.is_some_and(|b| b != k) {
+                        return None;
+                    }
+                    block = Some(k);
+                }
+            }
+            match block {
+                Some(k) => block_rows[k].push(row),
+                None => shared_rows.push(row),
+            }
+        }
+
+        // S = H_cc - sum H_cp H_pp^-1 H_pc
+        let blocks = block_rows
+            .par_iter()
+            .zip(&self.pose_lens)
+            .map(|(rows, &n)| {
+                let block = self.frame_block(&jacobian, residuals, rows, n);
+                let h_pp_inv = block.h_pp.clone().cholesky()?.inverse();
+                let w = &block.h_cp * &h_pp_inv;
+                let s = &block.h_cc - &w * block.h_cp.transpose();
+                let rhs = &block.b_c - &w * &block.b_p;
+                Some((s, rhs, block, h_pp_inv))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let shared = self.frame_block(&jacobian, residuals, &shared_rows, 0);
+        let mut s = shared.h_cc;
+        let mut rhs = shared.b_c;
+        for (s_k, rhs_k, _, _) in &blocks {
+            s += s_k;
+            rhs += rhs_k;
+        }
+        let dx_c = match s.clone().cholesky() {
+            Some(cholesky) => cholesky.solve(&rhs),
+            None => s.lu().solve(&rhs)?,
+        };
+        let dx_p: Vec<_> = blocks
+            .iter()
+            .map(|(_, _, block, h_pp_inv)| h_pp_inv * (&block.b_p - block.h_cp.transpose() * &dx_c))
+            .collect();
+        Some(na::DVector::from_iterator(
+            self.columns.len(),
+            self.columns.iter().map(|column| match *column {
+                Column::Global(i) => dx_c[i].into(),
+                Column::Pose(k, i) => dx_p[k][i].into(),
+            }),
+        ))
+    }
+}
+
+/// Gauss-Newton like `tiny_solver::GaussNewtonOptimizer` for the arrowhead normal equations of
+/// the calibration. The board pose (and velocities) of a frame only share residuals with the
+/// intrinsics and extrinsics, so the small frame blocks are eliminated and only the reduced
+/// system of the shared variables is factorized, linear in the number of frames. Problems with
+/// residuals between frames fall back to the sparse Cholesky of the whole system.
+#[derive(Debug)]
+pub struct SchurOptimizer {
+    /// Only the linear solve is in f32: the normal equations of each step are built and solved
+    /// in f32 until converged, then refined in f64 to recover the accuracy. The residuals and
+    /// jacobians of the factors are always evaluated in f64, so it only saves the time of the
+    /// block elimination on targets with slow f64 like embedded arm. On by default with the
+    /// `f32-linear-solve` feature.
+    pub f32_linear_solve: bool,
+    /// Stop early on a plateau of the error, `None` only stops with the `OptimizerOptions`.
+    pub plateau: Option<Plateau>,
+}
+
+// not derived, `f32_linear_solve` depends on the features
+#[allow(clippy::derivable_impls)]
+impl Default for SchurOptimizer {
+    fn default() -> Self {
+        SchurOptimizer {
+            f32_linear_solve: cfg!(feature = "f32-linear-solve"),
+            plateau: None,
+        }
+    }
+}
+
+/// Big problems take long tails of iterations that decrease the error only a little each, but
+/// more than the relative threshold of `OptimizerOptions`. Stops when the error decreased less
+/// than `min_rel_decrease` over the last `window` iterations together. Off by default, it
+/// trades the last digits of the result for the time of the tail.
+#[derive(Debug, Clone, Copy)]
+pub struct Plateau {
+    pub window: usize,
+    pub min_rel_decrease: f64,
+}
+
+impl Default for Plateau {
+    fn default() -> Self {
+        Plateau {
+            window: 5,
+            min_rel_decrease: 1e-4,
+        }
+    }
+}
+
+impl Plateau {
+    /// `errors` of the iterations so far, the last one is the current.
+    pub fn is_reached(&self, errors: &[f64]) -> bool {
+        if self.window == 0 || errors.len() <= self.window {
+            return false;
+        }
+        let first = errors[errors.len() - 1 - self.window];
+        let last = errors[errors.len() - 1];
+        (first - last) / first < self.min_rel_decrease
+    }
+}
+
+impl Optimizer for SchurOptimizer {
+    fn optimize(
+        &self,
+        problem: &Problem,
+        initial_values: &HashMap<String, na::DVector<f64>>,
+        optimizer_option: Option<OptimizerOptions>,
+    ) -> Option<HashMap<String, na::DVector<f64>>> {
+        let mut params = initial_values.clone();
+        let opt_option = optimizer_option.unwrap_or_default();
+        let layout = BlockLayout::new(problem, initial_values);
+        let mut sparse_solver = SparseCholeskySolver::new();
+        let mut f32_linear_solve = self.f32_linear_solve;
+        let mut last_err: f64 = 1.0;
+        let mut errors = Vec::new();
+
+        for i in 0..opt_option.max_iteration {
+            let (residuals, jacobian) = problem.compute_residual_and_jacobian(&params);
+            let current_error = residuals.norm_l2();
+            trace!("iter:{} total err:{}", i, current_error);
+            if current_error < opt_option.min_error_threshold {
+                break;
+            } else if current_error.is_nan() {
+                debug!("current error is nan");
+                return None;
+            }
+            errors.push(current_error);
+            let plateau = self.plateau.is_some_and(|p| p.is_reached(&errors));
+            if plateau {
+                debug!("error plateau after {} iterations", i);
+            }
+            if i > 0 {
+                let decrease = (last_err - current_error).abs();
+                if plateau
+                    || decrease < opt_option.min_abs_error_decrease_threshold
+                    || decrease / last_err < opt_option.min_rel_error_decrease_threshold
+                {
+                    if !f32_linear_solve {
+                        break;
+                    }
+                    debug!("converged in f32, refine in f64");
+                    f32_linear_solve = false;
+                    errors.clear();
+                }
+            }
+            last_err = current_error;
+
+            let residuals_na = to_dvector(&residuals);
+            let dx = if f32_linear_solve {
+                layout.solve::<f32>(&residuals_na, &jacobian)
+            } else {
+                layout.solve::<f64>(&residuals_na, &jacobian)
+            };
+            let dx = match dx {
+                Some(dx) => dx,
+                None => {
+                    debug!("block elimination failed, solve the whole system");
+                    to_dvector(&sparse_solver.solve(&residuals, &jacobian)?)
+                }
+            };
+            self.apply_dx(
+                &dx,
+                &mut params,
+                &problem.variable_name_to_col_idx_dict,
+                &problem.fixed_variable_indexes,
+                &problem.variable_bounds,
+            );
+        }
+        Some(params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tiny_solver::factors::Factor;
+
+    use super::*;
+    use crate::observer::PipelineObserver;
+    use crate::optimization::factors::ReprojectionFactor;
+    use crate::optimization::{rvec_name, tvec_name};
+    use crate::test_util;
+    use crate::util::optimize_with_observer;
+
+    /// `[x, (x^2 - 2) / 2] * 1e4`, its minimum at 0 is flat to the fourth order so the steps
+    /// decrease the error less and less.
+    struct FlatMinimumFactor;
+
+    impl<T: na::RealField> Factor<T> for FlatMinimumFactor {
+        fn residual_func(&self, params: &[na::DVector<T>]) -> na::DVector<T> {
+            let x = params[0][0].clone();
+            let scale = T::from_f64(1e4).unwrap();
+            na::dvector![
+                x.clone() * scale.clone(),
+                (x.clone() * x - T::from_f64(2.0).unwrap()) * T::from_f64(0.5).unwrap() * scale
+            ]
+        }
+    }
+
+    #[derive(Default)]
+    struct IterationCounter(AtomicUsize);
+
+    impl PipelineObserver for IterationCounter {
+        fn observe_iterations(&self) -> bool {
+            true
+        }
+        fn on_iteration_finished(&self, _stage: &str, _iteration: usize, _error: f64) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn solve(plateau: Option<Plateau>) -> (usize, f64) {
+        let mut problem = Problem::new();
+        problem.add_residual_block(2, &[("x", 1)], Box::new(FlatMinimumFactor), None);
+        let initial_values = HashMap::from([("x".to_string(), na::dvector![2.0])]);
+        let counter = IterationCounter::default();
+        let result =
+            optimize_with_observer(&problem, &initial_values, "test", plateau, &counter).unwrap();
+        (counter.0.load(Ordering::Relaxed), result["x"][0])
+    }
+
+    #[test]
+    fn plateau_detection() {
+        let plateau = Plateau::default();
+        assert!(!plateau.is_reached(&[10.0, 9.0, 8.0, 7.0, 6.0]));
+        assert!(!plateau.is_reached(&[10.0, 9.0, 8.0, 7.0, 6.0, 5.0]));
+        assert!(plateau.is_reached(&[10.0, 9.0, 9.0, 9.0, 9.0, 9.0, 8.9999]));
+        let disabled = Plateau {
+            window: 0,
+            min_rel_decrease: 1.0,
+        };
+        assert!(!disabled.is_reached(&[1.0, 1.0, 1.0]));
+    }
+
+    #[test]
+    fn plateau_off_by_default() {
+        assert!(SchurOptimizer::default().plateau.is_none());
+    }
+
+    #[test]
+    fn plateau_stops_early() {
+        let (iterations, x) = solve(None);
+        let (plateau_iterations, plateau_x) = solve(Some(Plateau::default()));
+        assert!(
+            plateau_iterations < iterations,
+            "{} iterations with the plateau stop, {} without",
+            plateau_iterations,
+            iterations
+        );
+        // the tail only moves the flat minimum a little
+        assert!(x.abs() < plateau_x.abs());
+        assert!(plateau_x.abs() < 0.2);
+    }
+
+    /// Reprojection problem of synthetic frames like `calib_camera`, the initial values are the
+    /// true ones off by a few percent.
+    fn calibration_problem() -> (Problem, HashMap<String, na::DVector<f64>>, na::DVector<f64>) {
+        let model = test_util::eucm();
+        let (frames, rtvecs) = test_util::frames(&model, 12);
+        let mut problem = Problem::new();
+        let truth = model.params();
+        let mut initial_values = HashMap::from([(
+            "params".to_string(),
+            truth.component_mul(&na::dvector![1.03, 0.97, 1.01, 0.99, 0.9, 1.1]),
+        )]);
+        for (i, frame) in frames.iter().enumerate() {
+            for fp in frame.as_ref().unwrap().features.values() {
+                let cost = ReprojectionFactor::new(&model, &fp.p3d, &fp.p2d, false);
+                problem.add_residual_block(
+                    2,
+                    &[("params", 6), (&rvec_name(i), 3), (&tvec_name(i), 3)],
+                    Box::new(cost),
+                    None,
+                );
+            }
+            let rtvec = &rtvecs[&i];
+            initial_values.insert(rvec_name(i), rtvec.na_rvec().add_scalar(0.02));
+            initial_values.insert(tvec_name(i), rtvec.na_tvec().add_scalar(-0.01));
+        }
+        (problem, initial_values, truth)
+    }
+
+    #[test]
+    fn f32_linear_solve_matches_f64() {
+        let (problem, initial_values, truth) = calibration_problem();
+        let solve = |f32_linear_solve| {
+            let optimizer = SchurOptimizer {
+                f32_linear_solve,
+                plateau: None,
+            };
+            optimizer.optimize(&problem, &initial_values, None).unwrap()["params"].clone()
+        };
+        let f64_params = solve(false);
+        let f32_params = solve(true);
+        // the corners are f32
+        assert!((&f64_params - &truth).norm() < 1e-3, "{f64_params}");
+        for (f32_param, f64_param) in f32_params.iter().zip(f64_params.iter()) {
+            assert!(
+                (f32_param - f64_param).abs() < 1e-6 * f64_param.abs().max(1.0),
+                "f32 linear solve {f32_params} f64 {f64_params}"
+            );
+        }
+    }
+
+    #[test]
+    fn frame_variables() {
+        assert_eq!(frame_key("rvec12"), Some("12"));
+        assert_eq!(frame_key("linear_velocity3"), Some("3"));
+        assert_eq!(frame_key("rvec_1_0"), None);
+        assert_eq!(frame_key("params"), None);
+        assert_eq!(frame_key("rvec"), None);
+    }
+
+    #[test]
+    fn schur_step_matches_sparse_cholesky() {
+        let (problem, initial_values, _) = calibration_problem();
+        let layout = BlockLayout::new(&problem, &initial_values);
+        assert_eq!(layout.global_len, 6);
+        assert_eq!(layout.pose_lens, vec![6; 12]);
+        let (residuals, jacobian) = problem.compute_residual_and_jacobian(&initial_values);
+        let schur_dx = layout
+            .solve::<f64>(&to_dvector(&residuals), &jacobian)
+            .unwrap();
+        let cholesky_dx = to_dvector(
+            &SparseCholeskySolver::new()
+                .solve(&residuals, &jacobian)
+                .unwrap(),
+        );
+        assert!(
+            (&schur_dx - &cholesky_dx).norm() < 1e-8 * cholesky_dx.norm(),
+            "schur {schur_dx} cholesky {cholesky_dx}"
+        );
+    }
+
+    /// `a - b`, a residual between the poses of two frames.
+    struct DifferenceFactor;
+
+    impl<T: na::RealField> Factor<T> for DifferenceFactor {
+        fn residual_func(&self, params: &[na::DVector<T>]) -> na::DVector<T> {
+            &params[0] - &params[1]
+        }
+    }
+
+    #[test]
+    fn falls_back_on_residuals_between_frames() {
+        let (mut problem, initial_values, truth) = calibration_problem();
+        problem.add_residual_block(
+            3,
+            &[(&tvec_name(0), 3), (&tvec_name(1), 3)],
+            Box::new(DifferenceFactor),
+            None,
+        );
+        let layout = BlockLayout::new(&problem, &initial_values);
+        let (residuals, jacobian) = problem.compute_residual_and_jacobian(&initial_values);
+        assert!(layout
+            .solve::<f64>(&to_dvector(&residuals), &jacobian)
+            .is_none());
+        // the whole system is solved instead, the weak prior barely moves the result
+        let params = SchurOptimizer::default()
+            .optimize(&problem, &initial_values, None)
+            .unwrap()["params"]
+            .clone();
+        assert!((&params - &truth).norm() / truth.norm() < 1e-2, "{params}");
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+use camera_intrinsic_model::*;
+use serde::{Deserialize, Serialize};
+
+use crate::detected_points::FrameFeature;
+use crate::observer::PipelineObserver;
+use crate::optimization::IncrementalProblem;
+use crate::types::{CalibParams, RvecTvec};
+use crate::util::corner_residuals;
+
+/// Frames with a mean reprojection error larger than this times the median of the frames are
+/// outliers.
+pub const OUTLIER_FRAME_RATIO: f64 = 3.0;
+
+/// A frame with fewer corners than this fraction of the median frame only saw part of the board.
+const PARTIAL_BOARD_FRACTION: f64 = 0.5;
+
+/// A frame whose max error is larger than this times its median error has a few misdetected
+/// corners rather than uniformly bad ones.
+const MISDETECTION_RATIO: f64 = 5.0;
+
+/// Likely reason of the large reprojection errors of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutlierCause {
+    /// Few corners, e.g. the board at the image border or occluded.
+    PartialBoard,
+    /// A few corners far off while the others fit.
+    Misdetection,
+    /// All corners off in a single camera, e.g. motion blur.
+    Blur,
+    /// All corners off in a camera of a rig while it fits alone, e.g. a frame of another time.
+    SyncGlitch,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutlierFrame {
+    pub cam_idx: usize,
+    pub frame_idx: usize,
+    pub time_ns: i64,
+    pub num_points: usize,
+    pub mean_error: f64,
+    pub max_error: f64,
+    pub causes: Vec<OutlierCause>,
+}
+
+/// Frames with a mean reprojection error much larger than the others, worst first. `in_rig` if
+/// the board poses are shared by the cameras of a rig, so uniformly bad frames are more likely
+/// out of sync than blurred.
+pub fn rank_outlier_frames(
+    cam_idx: usize,
+    model: &GenericModel<f64>,
+    rtvecs: &HashMap<usize, RvecTvec>,
+    frame_feature_list: &[Option<FrameFeature>],
+    in_rig: bool,
+) -> Vec<OutlierFrame> {
+    let mut frame_errors: HashMap<usize, (i64, Vec<f64>)> = HashMap::new();
+    for r in corner_residuals(model, rtvecs, frame_feature_list) {
+        frame_errors
+            .entry(r.frame_idx)
+            .or_insert_with(|| (r.time_ns, Vec::new()))
+            .1
+            .push(r.norm);
+    }
+    let median = |mut v: Vec<f64>| {
+        v.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        v.get(v.len() / 2).copied().unwrap_or(0.0)
+    };
+    let median_mean_error = median(
+        frame_errors
+            .values()
+            .map(|(_, e)| e.iter().sum::<f64>() / e.len() as f64)
+            .collect(),
+    );
+    let median_num_points = median(frame_errors.values().map(|(_, e)| e.len() as f64).collect());
+    let mut outliers: Vec<_> = frame_errors
+        .into_iter()
+        .filter_map(|(frame_idx, (time_ns, errors))| {
+            let mean_error = errors.iter().sum::<f64>() / errors.len() as f64;
+            if mean_error <= median_mean_error * OUTLIER_FRAME_RATIO {
+                return None;
+            }
+            let max_error = errors.iter().copied().fold(0.0, f64::max);
+            let num_points = errors.len();
+            let mut causes = Vec::new();
+            if (num_points as f64) < median_num_points * PARTIAL_BOARD_FRACTION {
+                causes.push(OutlierCause::PartialBoard);
+            }
+            if max_error > median(errors) * MISDETECTION_RATIO {
+                causes.push(OutlierCause::Misdetection);
+            } else if in_rig {
+                causes.push(OutlierCause::SyncGlitch);
+            } else {
+                causes.push(OutlierCause::Blur);
+            }
+            Some(OutlierFrame {
+                cam_idx,
+                frame_idx,
+                time_ns,
+                num_points,
+                mean_error,
+                max_error,
+                causes,
+            })
+        })
+        .collect();
+    outliers.sort_by(|a, b| b.mean_error.partial_cmp(&a.mean_error).unwrap());
+    outliers
+}
+
+/// Model, board poses and dropped frame indexes.
+type Recalibration = (GenericModel<f64>, HashMap<usize, RvecTvec>, Vec<usize>);
+
+/// Calibrates the camera again from `model` without the outlier frames of `rank_outlier_frames`.
+/// Returns the new model, board poses and the dropped frame indexes, `None` without outliers or
+/// if the calibration fails.
+pub fn calib_camera_without_outlier_frames(
+    cam_idx: usize,
+    model: &GenericModel<f64>,
+    rtvecs: &HashMap<usize, RvecTvec>,
+    frame_feature_list: &[Option<FrameFeature>],
+    calib_params: &CalibParams,
+    observer: &dyn PipelineObserver,
+) -> Option<Recalibration> {
+    let outliers = rank_outlier_frames(cam_idx, model, rtvecs, frame_feature_list, false);
+    if outliers.is_empty() {
+        return None;
+    }
+    let mut problem =
+        IncrementalProblem::from_calibration(model, rtvecs, frame_feature_list, calib_params);
+    for o in &outliers {
+        problem.remove_frame(o.frame_idx);
+    }
+    let (model, rtvecs) = problem.solve(observer)?;
+    Some((
+        model,
+        rtvecs,
+        outliers.iter().map(|o| o.frame_idx).collect(),
+    ))
+}
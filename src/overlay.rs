@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use image::{DynamicImage, Rgb, RgbImage};
+use imageproc::drawing::{draw_filled_rect_mut, draw_hollow_circle_mut, draw_line_segment_mut};
+use imageproc::rect::Rect;
+use nalgebra as na;
+
+use crate::detected_points::FrameFeature;
+
+const DETECTED_COLOR: Rgb<u8> = Rgb([0, 255, 0]);
+const REPROJECTED_COLOR: Rgb<u8> = Rgb([255, 0, 0]);
+const ID_COLOR: Rgb<u8> = Rgb([255, 255, 0]);
+
+/// 3x5 bitmap of each digit, one row per 3 bits.
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+fn draw_number_mut(canvas: &mut RgbImage, number: u32, x: i32, y: i32, scale: u32, color: Rgb<u8>) {
+    for (i, c) in number.to_string().chars().enumerate() {
+        let digit = DIGITS[c.to_digit(10).unwrap() as usize];
+        let x0 = x + (i as u32 * 4 * scale) as i32;
+        for (row, bits) in digit.iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) != 0 {
+                    draw_filled_rect_mut(
+                        canvas,
+                        Rect::at(x0 + (col * scale) as i32, y + (row as u32 * scale) as i32)
+                            .of_size(scale, scale),
+                        color,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Draw the detected corners (green) with their ids, and the reprojections (red) if given.
+pub fn draw_detection_overlay(
+    img: &DynamicImage,
+    frame_feature: &FrameFeature,
+    reprojections: Option<&HashMap<u32, na::Vector2<f64>>>,
+) -> RgbImage {
+    let mut canvas = img.to_rgb8();
+    let scale = (canvas.width() / 640).max(1);
+    for (id, fp) in &frame_feature.features {
+        let (x, y) = (fp.p2d.x, fp.p2d.y);
+        draw_hollow_circle_mut(
+            &mut canvas,
+            (x.round() as i32, y.round() as i32),
+            3 * scale as i32,
+            DETECTED_COLOR,
+        );
+        draw_number_mut(
+            &mut canvas,
+            *id,
+            (x + 4.0) as i32,
+            (y + 4.0) as i32,
+            scale,
+            ID_COLOR,
+        );
+        if let Some(p) = reprojections.and_then(|r| r.get(id)) {
+            let (px, py) = (p.x as f32, p.y as f32);
+            draw_line_segment_mut(&mut canvas, (x, y), (px, py), REPROJECTED_COLOR);
+            let s = 2.0 * scale as f32;
+            draw_line_segment_mut(
+                &mut canvas,
+                (px - s, py - s),
+                (px + s, py + s),
+                REPROJECTED_COLOR,
+            );
+            draw_line_segment_mut(
+                &mut canvas,
+                (px - s, py + s),
+                (px + s, py - s),
+                REPROJECTED_COLOR,
+            );
+        }
+    }
+    canvas
+}
@@ -0,0 +1,196 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use camera_intrinsic_model::*;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::board::Board;
+use crate::data_loader::load_image;
+use crate::detected_points::{
+    image_to_option_feature_frame, FrameFeature, TagDetection, MIN_CORNERS,
+};
+use crate::observer::{NoopObserver, PipelineObserver};
+use crate::types::CalibParams;
+use crate::util::{init_and_calibrate_one_camera_with_trials, validation};
+
+/// Wall time of a stage of the pipeline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageProfile {
+    pub stage: String,
+    pub seconds: f64,
+    /// Peak resident memory of the process at the end of the stage, `None` if unknown.
+    pub peak_rss_bytes: Option<u64>,
+}
+
+/// Stage breakdown of a calibration, written by `profile`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PipelineProfile {
+    pub cam_idx: usize,
+    pub images: usize,
+    pub detected_frames: usize,
+    pub threads: usize,
+    pub stages: Vec<StageProfile>,
+}
+
+impl PipelineProfile {
+    /// Adds `seconds` to `stage`, the stages are kept in the order they first ran.
+    fn add(&mut self, stage: &str, seconds: f64, peak_rss_bytes: Option<u64>) {
+        if let Some(s) = self.stages.iter_mut().find(|s| s.stage == stage) {
+            s.seconds += seconds;
+            s.peak_rss_bytes = peak_rss_bytes;
+        } else {
+            self.stages.push(StageProfile {
+                stage: stage.to_string(),
+                seconds,
+                peak_rss_bytes,
+            });
+        }
+    }
+
+    fn time<T>(&mut self, stage: &str, f: impl FnOnce() -> T) -> T {
+        let now = Instant::now();
+        let result = f();
+        self.add(stage, now.elapsed().as_secs_f64(), peak_rss_bytes());
+        result
+    }
+
+    pub fn total_seconds(&self) -> f64 {
+        self.stages.iter().map(|s| s.seconds).sum()
+    }
+
+    /// Peak resident memory of the whole run.
+    pub fn peak_rss_bytes(&self) -> Option<u64> {
+        self.stages.iter().filter_map(|s| s.peak_rss_bytes).max()
+    }
+
+    /// Logs the time and share of every stage and the memory high-water mark.
+    pub fn log(&self) {
+        let total = self.total_seconds();
+        let mb = |bytes: Option<u64>| {
+            bytes.map_or("n/a".to_string(), |b| {
+                format!("{:.1} MB", b as f64 / 1024.0 / 1024.0)
+            })
+        };
+        info!(
+            "cam{}: {} images, {} with the board, {} threads",
+            self.cam_idx, self.images, self.detected_frames, self.threads
+        );
+        for s in &self.stages {
+            info!(
+                "{:<12} {:>9.3} s {:>6.1}%   peak {}",
+                s.stage,
+                s.seconds,
+                s.seconds / total.max(f64::EPSILON) * 100.0,
+                mb(s.peak_rss_bytes)
+            );
+        }
+        info!(
+            "{:<12} {:>9.3} s           peak {}",
+            "total",
+            total,
+            mb(self.peak_rss_bytes())
+        );
+    }
+}
+
+/// Peak resident memory of the process, `VmHWM` of `/proc/self/status` so only on linux.
+pub fn peak_rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb: u64 = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+/// Marks the end of the initialization and the peak memory then, the rest of the calibration
+/// is the solve.
+struct InitObserver(Mutex<Option<(Instant, Option<u64>)>>);
+
+impl PipelineObserver for InitObserver {
+    fn on_init_complete(
+        &self,
+        _cam_idx: usize,
+        _initial_model: &GenericModel<f64>,
+        _key_frames: [&FrameFeature; 2],
+    ) {
+        *self.0.lock().unwrap() = Some((Instant::now(), peak_rss_bytes()));
+    }
+}
+
+/// Calibrates `cam_idx` from the images of `paths` one stage after another and times every
+/// stage. The images are read and detected `queue_size` at a time so io and detection are
+/// timed separately with a bounded memory. The init is timed until the key frames are
+/// converted to the target model, so failed trials count as init.
+pub fn profile_pipeline(
+    cam_idx: usize,
+    paths: &[PathBuf],
+    tag_detector: &dyn TagDetection,
+    board: &Board,
+    target_model: &GenericModel<f64>,
+    calib_params: &CalibParams,
+    queue_size: usize,
+) -> PipelineProfile {
+    let mut profile = PipelineProfile {
+        cam_idx,
+        images: paths.len(),
+        threads: rayon::current_num_threads(),
+        ..Default::default()
+    };
+    let mut frames = Vec::with_capacity(paths.len());
+    for (chunk_idx, chunk) in paths.chunks(queue_size.max(1)).enumerate() {
+        let images: Vec<_> = profile.time("io", || {
+            chunk
+                .par_iter()
+                .map(|path| {
+                    load_image(path)
+                        .map_err(|e| warn!("failed to load {}: {}", path.display(), e))
+                        .ok()
+                })
+                .collect()
+        });
+        profile.time("detection", || {
+            frames.par_extend(images.par_iter().enumerate().map(|(i, img)| {
+                let time_ns = ((chunk_idx * queue_size.max(1) + i) as i64) * 100_000_000;
+                image_to_option_feature_frame(
+                    tag_detector,
+                    img.as_ref()?,
+                    board,
+                    MIN_CORNERS,
+                    time_ns,
+                )
+            }))
+        });
+    }
+    profile.detected_frames = frames.iter().flatten().count();
+    let cams_frames = vec![frames];
+    let observer = InitObserver(Mutex::new(None));
+    let start = Instant::now();
+    let calibrated = init_and_calibrate_one_camera_with_trials(
+        0,
+        &cams_frames,
+        target_model,
+        &observer,
+        calib_params,
+        3,
+    );
+    let end = Instant::now();
+    let (init_end, init_peak) = observer.0.lock().unwrap().unwrap_or((end, None));
+    profile.add("init", (init_end - start).as_secs_f64(), init_peak);
+    profile.add("solve", (end - init_end).as_secs_f64(), peak_rss_bytes());
+    let Some((model, rtvec_map)) = calibrated else {
+        warn!("cam{} calibration failed", cam_idx);
+        return profile;
+    };
+    profile.time("validation", || {
+        validation(cam_idx, &model, &rtvec_map, &cams_frames[0], &NoopObserver)
+    });
+    profile
+}
@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+
+use camera_intrinsic_model::*;
+use image::GrayImage;
+use nalgebra as na;
+
+use crate::detected_points::{FeaturePoint, FrameFeature, MIN_CORNERS};
+use crate::observer::PipelineObserver;
+use crate::types::{CalibParams, Extrinsics, RvecTvec, ToRvecTvec};
+use crate::util::{
+    calib_all_camera_with_extrinsics, init_and_calibrate_one_camera_with_trials,
+    init_camera_extrinsic,
+};
+
+/// Camera pixels with less difference between the white and the black captures are in a shadow
+/// or outside of the projection.
+const MIN_CONTRAST: i16 = 20;
+/// Bits with less difference between the pattern and its inverse are on the edge of a stripe.
+const MIN_BIT_CONTRAST: i16 = 5;
+/// Radius in px of the camera pixels around a corner its local homography is fitted to.
+const WINDOW_RADIUS: i32 = 12;
+/// Decoded pixels around a corner below which it isn't mapped to the projector.
+const MIN_WINDOW_PIXELS: usize = 64;
+
+/// Bits of the gray code of `size` columns or rows.
+pub fn gray_code_bits(size: u32) -> usize {
+    (u32::BITS - (size.max(2) - 1).leading_zeros()) as usize
+}
+
+/// Patterns to project for `ProjectorCodes::decode`, a white and a black image, then every bit
+/// of the gray code of the columns and then of the rows, the most significant first, each as a
+/// pattern and its inverse.
+pub fn gray_code_patterns(width: u32, height: u32) -> Vec<GrayImage> {
+    let mut patterns = vec![
+        GrayImage::from_pixel(width, height, image::Luma([255])),
+        GrayImage::from_pixel(width, height, image::Luma([0])),
+    ];
+    for (size, along_x) in [(width, true), (height, false)] {
+        for bit in (0..gray_code_bits(size)).rev() {
+            let pattern = GrayImage::from_fn(width, height, |x, y| {
+                let i = if along_x { x } else { y };
+                image::Luma([(((i ^ (i >> 1)) >> bit) & 1) as u8 * 255])
+            });
+            let mut inverse = pattern.clone();
+            image::imageops::invert(&mut inverse);
+            patterns.push(pattern);
+            patterns.push(inverse);
+        }
+    }
+    patterns
+}
+
+/// Projector column and row seen by every camera pixel of a board pose.
+pub struct ProjectorCodes {
+    pub width: u32,
+    pub height: u32,
+    /// Row major, `None` where the code couldn't be decoded.
+    pub codes: Vec<Option<[u32; 2]>>,
+}
+
+impl ProjectorCodes {
+    /// Decodes the captures of `gray_code_patterns` of a projector of `projector_w_h`. Each bit
+    /// is read by comparing the capture of the pattern with the one of its inverse, which
+    /// doesn't depend on the albedo of the board or the lighting.
+    pub fn decode(captures: &[GrayImage], projector_w_h: (u32, u32)) -> Option<ProjectorCodes> {
+        let (x_bits, y_bits) = (
+            gray_code_bits(projector_w_h.0),
+            gray_code_bits(projector_w_h.1),
+        );
+        if captures.len() != 2 + 2 * (x_bits + y_bits) {
+            tracing::warn!(
+                "{} captures, {} are needed for a {}x{} projector",
+                captures.len(),
+                2 + 2 * (x_bits + y_bits),
+                projector_w_h.0,
+                projector_w_h.1
+            );
+            return None;
+        }
+        let (width, height) = captures[0].dimensions();
+        if captures.iter().any(|c| c.dimensions() != (width, height)) {
+            tracing::warn!("the captures of a board pose have different sizes");
+            return None;
+        }
+        let value = |c: usize, x: u32, y: u32| captures[c].get_pixel(x, y)[0] as i16;
+        let codes = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                if value(0, x, y) - value(1, x, y) < MIN_CONTRAST {
+                    return None;
+                }
+                let mut decoded = [0u32; 2];
+                let mut c = 2;
+                for (axis, bits) in [x_bits, y_bits].into_iter().enumerate() {
+                    let mut gray = 0u32;
+                    for _ in 0..bits {
+                        let difference = value(c, x, y) - value(c + 1, x, y);
+                        if difference.abs() < MIN_BIT_CONTRAST {
+                            return None;
+                        }
+                        gray = (gray << 1) | (difference > 0) as u32;
+                        c += 2;
+                    }
+                    // gray to binary
+                    let mut binary = gray;
+                    let mut shift = gray >> 1;
+                    while shift != 0 {
+                        binary ^= shift;
+                        shift >>= 1;
+                    }
+                    decoded[axis] = binary;
+                }
+                (decoded[0] < projector_w_h.0 && decoded[1] < projector_w_h.1).then_some(decoded)
+            })
+            .collect();
+        Some(ProjectorCodes {
+            width,
+            height,
+            codes,
+        })
+    }
+
+    fn at(&self, x: i32, y: i32) -> Option<[u32; 2]> {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return None;
+        }
+        self.codes[(y as u32 * self.width + x as u32) as usize]
+    }
+}
+
+/// Homography fitted to the decoded camera pixels around `center`, which is mapped by it to the
+/// projector. The board is planar, so the camera to projector map around a corner is a
+/// homography whatever the distortions of both, and the fit over the window brings the decoded
+/// codes, whole projector pixels, to subpixel.
+fn map_to_projector(codes: &ProjectorCodes, center: &glam::Vec2) -> Option<na::Vector2<f64>> {
+    let (cx, cy) = (center.x.round() as i32, center.y.round() as i32);
+    let pairs: Vec<_> = (cy - WINDOW_RADIUS..=cy + WINDOW_RADIUS)
+        .flat_map(|y| (cx - WINDOW_RADIUS..=cx + WINDOW_RADIUS).map(move |x| (x, y)))
+        .filter_map(|(x, y)| {
+            let code = codes.at(x, y)?;
+            Some((
+                na::Vector2::new(x as f64, y as f64),
+                na::Vector2::new(code[0] as f64, code[1] as f64),
+            ))
+        })
+        .collect();
+    if pairs.len() < MIN_WINDOW_PIXELS {
+        return None;
+    }
+    // normalized around the corner and the mean of the codes
+    let n = pairs.len() as f64;
+    let origin = na::Vector2::new(center.x as f64, center.y as f64);
+    let mean = pairs.iter().map(|(_, q)| q).sum::<na::Vector2<f64>>() / n;
+    let spread = (pairs
+        .iter()
+        .map(|(_, q)| (q - mean).norm_squared())
+        .sum::<f64>()
+        / n)
+        .sqrt()
+        .max(f64::EPSILON);
+    let mut ata = na::SMatrix::<f64, 9, 9>::zeros();
+    for (p, q) in &pairs {
+        let p = ((p - origin) / WINDOW_RADIUS as f64).push(1.0);
+        let q = (q - mean) / spread;
+        for (row, coord) in [(0, q.x), (3, q.y)] {
+            let mut a = na::SVector::<f64, 9>::zeros();
+            a.fixed_rows_mut::<3>(row).copy_from(&p);
+            a.fixed_rows_mut::<3>(6).copy_from(&(-coord * p));
+            ata += a * a.transpose();
+        }
+    }
+    let eigen = ata.symmetric_eigen();
+    let h = eigen.eigenvectors.column(eigen.eigenvalues.imin());
+    // the corner is the origin of the normalized camera pixels, the last column of h
+    if h[8].abs() < f64::EPSILON {
+        return None;
+    }
+    Some(mean + na::Vector2::new(h[2], h[5]) / h[8] * spread)
+}
+
+/// The corners of a camera frame in the projector of `projector_w_h`, as if the projector were
+/// a camera seeing the board.
+pub fn projector_frame(
+    camera_frame: &FrameFeature,
+    codes: &ProjectorCodes,
+    projector_w_h: (u32, u32),
+) -> Option<FrameFeature> {
+    let features: HashMap<_, _> = camera_frame
+        .features
+        .iter()
+        .filter_map(|(id, fp)| {
+            let p = map_to_projector(codes, &fp.p2d)?;
+            let inside = p.x >= 0.0
+                && p.y >= 0.0
+                && p.x < projector_w_h.0 as f64
+                && p.y < projector_w_h.1 as f64;
+            inside.then(|| {
+                (
+                    *id,
+                    FeaturePoint {
+                        p2d: glam::Vec2::new(p.x as f32, p.y as f32),
+                        p3d: fp.p3d,
+                        time_ns: None,
+                        blur: None,
+                    },
+                )
+            })
+        })
+        .collect();
+    (features.len() >= MIN_CORNERS).then_some(FrameFeature {
+        time_ns: camera_frame.time_ns,
+        img_w_h: projector_w_h,
+        features,
+        exposure: None,
+    })
+}
+
+/// Result of `calib_projector`.
+pub struct ProjectorCalibration {
+    pub camera: GenericModel<f64>,
+    pub projector: GenericModel<f64>,
+    /// Transforms points from the camera to the projector.
+    pub t_projector_camera: RvecTvec,
+}
+
+impl ProjectorCalibration {
+    /// The camera and the projector as the cam0 and cam1 of a rig, e.g. to rectify them for
+    /// structured light.
+    pub fn extrinsics(&self) -> Extrinsics {
+        Extrinsics::new(&[
+            na::Isometry3::identity().to_rvec_tvec(),
+            self.t_projector_camera.clone(),
+        ])
+    }
+}
+
+/// Calibrates a projector and a camera seeing the board it projects on. The projector is the
+/// second camera of a rig, seeing the corners of the board where the codes decoded around them
+/// put them, so it goes through the init and the joint refinement of the camera rigs.
+pub fn calib_projector(
+    camera_frames: &[Option<FrameFeature>],
+    projector_frames: &[Option<FrameFeature>],
+    camera_model: &GenericModel<f64>,
+    projector_model: &GenericModel<f64>,
+    calib_params: &CalibParams,
+    observer: &dyn PipelineObserver,
+) -> Option<ProjectorCalibration> {
+    let frames = [camera_frames.to_vec(), projector_frames.to_vec()];
+    let mut models = Vec::new();
+    let mut cam_rtvecs = Vec::new();
+    for (cam_idx, (target_model, name)) in
+        [(camera_model, "camera"), (projector_model, "projector")]
+            .into_iter()
+            .enumerate()
+    {
+        let Some((model, rtvecs)) = init_and_calibrate_one_camera_with_trials(
+            cam_idx,
+            &frames,
+            target_model,
+            observer,
+            calib_params,
+            3,
+        ) else {
+            tracing::warn!("{} calibration failed", name);
+            return None;
+        };
+        tracing::info!("{} params {:?}", name, model.params().as_slice());
+        models.push(model);
+        cam_rtvecs.push(rtvecs);
+    }
+    let t_i_0 = init_camera_extrinsic(&cam_rtvecs)?;
+    let (models, t_i_0, _) = calib_all_camera_with_extrinsics(
+        &models,
+        &t_i_0,
+        &cam_rtvecs,
+        &frames,
+        calib_params.one_focal || calib_params.fixed_focal.is_some(),
+        calib_params.disabled_distortion_num,
+        calib_params.fixed_focal.is_some(),
+        calib_params.plateau,
+        observer,
+    )?;
+    let t_projector_camera = t_i_0[1].to_na_isometry3();
+    tracing::info!(
+        "projector camera rvec: {} tvec: {}",
+        t_projector_camera.rotation.scaled_axis(),
+        t_projector_camera.translation.vector
+    );
+    Some(ProjectorCalibration {
+        camera: models[0],
+        projector: models[1],
+        t_projector_camera: t_projector_camera.to_rvec_tvec(),
+    })
+}
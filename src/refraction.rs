@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+
+use camera_intrinsic_model::*;
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+use tiny_solver::loss_functions::HuberLoss;
+
+use crate::detected_points::FrameFeature;
+use crate::observer::PipelineObserver;
+use crate::optimization::factors::FlatPortFactor;
+use crate::optimization::{rvec_name, tvec_name};
+use crate::types::RvecTvec;
+use crate::util::optimize_with_observer;
+
+/// Newton iterations of `FlatPort::project_one`.
+const PROJECTION_ITERATIONS: usize = 20;
+/// Step of the numerical jacobian of `FlatPort::project_one` in px.
+const PROJECTION_STEP: f64 = 1e-3;
+/// Initial distance of the camera center to the port in m.
+const INITIAL_DISTANCE: f64 = 0.01;
+/// The port is at most this far from the camera center in m.
+const MAX_DISTANCE: f64 = 1.0;
+
+/// Refractive indices and thickness of a flat port, which are known from the housing.
+#[derive(Debug, Clone)]
+pub struct FlatPortParams {
+    pub n_glass: f64,
+    pub n_water: f64,
+    /// Thickness of the port glass in m.
+    pub glass_thickness: f64,
+}
+
+impl Default for FlatPortParams {
+    fn default() -> Self {
+        FlatPortParams {
+            // acrylic port in sea water
+            n_glass: 1.49,
+            n_water: 1.34,
+            glass_thickness: 0.01,
+        }
+    }
+}
+
+/// Camera behind the flat port of an underwater housing. The rays of the in-air `model` are
+/// refracted at the air-glass and the glass-water interfaces, which are parallel planes with
+/// the unit `normal` pointing out of the housing in the camera frame.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlatPort {
+    pub model: GenericModel<f64>,
+    pub normal: [f64; 3],
+    /// Distance of the camera center to the inner side of the port in m.
+    pub distance: f64,
+    pub glass_thickness: f64,
+    pub n_glass: f64,
+    pub n_water: f64,
+    /// Rms reprojection error of the calibration frames in px.
+    pub rms_error: f64,
+}
+
+/// Refracts the unit `dir` at an interface with the unit `normal` pointing along `dir`, `eta`
+/// is the refractive index before over the one after. Going into a denser medium there is no
+/// total internal reflection.
+fn refract<T: na::RealField>(
+    dir: &na::Vector3<T>,
+    normal: &na::Vector3<T>,
+    eta: T,
+) -> na::Vector3<T> {
+    let cos_i = dir.dot(normal);
+    let cos_t =
+        (T::one() - eta.clone() * eta.clone() * (T::one() - cos_i.clone() * cos_i.clone())).sqrt();
+    dir * eta.clone() + normal * (cos_t - eta * cos_i)
+}
+
+/// Origin on the outer side of the port and unit direction in the water of the ray of the unit
+/// in-air `dir` from the camera center.
+pub(crate) fn water_ray<T: na::RealField>(
+    dir: &na::Vector3<T>,
+    normal: &na::Vector3<T>,
+    distance: T,
+    params: &FlatPortParams,
+) -> (na::Vector3<T>, na::Vector3<T>) {
+    let p_inner = dir * (distance / dir.dot(normal));
+    let dir_glass = refract(dir, normal, T::from_f64(1.0 / params.n_glass).unwrap());
+    let p_outer = p_inner
+        + &dir_glass * (T::from_f64(params.glass_thickness).unwrap() / dir_glass.dot(normal));
+    let dir_water = refract(
+        &dir_glass,
+        normal,
+        T::from_f64(params.n_glass / params.n_water).unwrap(),
+    );
+    (p_outer, dir_water)
+}
+
+/// Unit port normal of the `[x, y, distance]` port params, the z of the normal is fixed to 1
+/// before the normalization so the port faces the camera.
+pub(crate) fn port_normal<T: na::RealField>(port: &na::DVector<T>) -> na::Vector3<T> {
+    na::Vector3::new(port[0].clone(), port[1].clone(), T::one()).normalize()
+}
+
+impl FlatPort {
+    fn params(&self) -> FlatPortParams {
+        FlatPortParams {
+            n_glass: self.n_glass,
+            n_water: self.n_water,
+            glass_thickness: self.glass_thickness,
+        }
+    }
+
+    /// Origin on the outer side of the port and unit direction in the water of the ray of a
+    /// pixel, in the camera frame.
+    pub fn unproject_one(&self, p2d: &na::Vector2<f64>) -> (na::Vector3<f64>, na::Vector3<f64>) {
+        let dir = self.model.unproject_one(p2d).normalize();
+        let normal = na::Vector3::from(self.normal);
+        water_ray(&dir, &normal, self.distance, &self.params())
+    }
+
+    /// Direction from the ray of `p2d` to `p3d` minus the ray direction.
+    fn ray_error(&self, p2d: &na::Vector2<f64>, p3d: &na::Vector3<f64>) -> na::Vector2<f64> {
+        let (origin, dir) = self.unproject_one(p2d);
+        ((p3d - origin).normalize() - dir).xy()
+    }
+
+    /// Projects a point in the water, the refracted ray has no closed form so the pixel is
+    /// solved by Newton's method from the in-air projection. `None` if it doesn't converge,
+    /// e.g. for points behind the port.
+    pub fn project_one(&self, p3d: &na::Vector3<f64>) -> Option<na::Vector2<f64>> {
+        if p3d.dot(&na::Vector3::from(self.normal)) <= self.distance + self.glass_thickness {
+            return None;
+        }
+        let mut p2d = self.model.project_one(p3d);
+        for _ in 0..PROJECTION_ITERATIONS {
+            let error = self.ray_error(&p2d, p3d);
+            let dx = self.ray_error(&(p2d + na::Vector2::new(PROJECTION_STEP, 0.0)), p3d);
+            let dy = self.ray_error(&(p2d + na::Vector2::new(0.0, PROJECTION_STEP)), p3d);
+            let jacobian = na::Matrix2::from_columns(&[dx - error, dy - error]) / PROJECTION_STEP;
+            let step = jacobian.try_inverse()? * error;
+            p2d -= step;
+            if step.norm() < 1e-6 {
+                return p2d.iter().all(|v| v.is_finite()).then_some(p2d);
+            }
+        }
+        None
+    }
+}
+
+/// Calibrates the flat port of an underwater housing from the board captures in the water.
+/// `air_model` is the calibration of the camera in the air, which the housing doesn't change,
+/// and `rtvecs` the board poses of the calibration in the water as the initial values. The
+/// distance of the port is only observable with the board at several distances from it.
+pub fn calib_flat_port(
+    frame_feature_list: &[Option<FrameFeature>],
+    air_model: &GenericModel<f64>,
+    rtvecs: &HashMap<usize, RvecTvec>,
+    params: &FlatPortParams,
+    observer: &dyn PipelineObserver,
+) -> Option<FlatPort> {
+    let frames: Vec<_> = frame_feature_list
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| rtvecs.contains_key(i))
+        .filter_map(|(i, f)| Some((i, f.as_ref()?)))
+        .collect();
+    if frames.is_empty() {
+        tracing::warn!("no frames for flat port calibration");
+        return None;
+    }
+    let mut initial_values = HashMap::<String, na::DVector<f64>>::from([(
+        "flat_port".to_string(),
+        na::dvector![0.0, 0.0, INITIAL_DISTANCE],
+    )]);
+    // the angular error in the water is about the one in the air over the index of the water
+    let scale = air_model.params()[0] * params.n_water;
+    let mut problem = tiny_solver::Problem::new();
+    for &(i, frame_feature) in &frames {
+        let rvec_i = rvec_name(i);
+        let tvec_i = tvec_name(i);
+        initial_values.insert(rvec_i.clone(), rtvecs[&i].na_rvec());
+        initial_values.insert(tvec_i.clone(), rtvecs[&i].na_tvec());
+        for fp in frame_feature.features.values() {
+            let p2d = na::Vector2::new(fp.p2d.x as f64, fp.p2d.y as f64);
+            let cost = FlatPortFactor::new(
+                &air_model.unproject_one(&p2d).normalize(),
+                &fp.p3d,
+                params,
+                scale,
+            );
+            problem.add_residual_block(
+                3,
+                &[("flat_port", 3), (&rvec_i, 3), (&tvec_i, 3)],
+                Box::new(cost),
+                Some(Box::new(HuberLoss::new(1.0))),
+            );
+        }
+    }
+    problem.set_variable_bounds("flat_port", 2, 0.0, MAX_DISTANCE);
+
+    let result =
+        optimize_with_observer(&problem, &initial_values, "calib_flat_port", None, observer)?;
+    let port = &result["flat_port"];
+    let mut flat_port = FlatPort {
+        model: *air_model,
+        normal: port_normal(port).into(),
+        distance: port[2],
+        glass_thickness: params.glass_thickness,
+        n_glass: params.n_glass,
+        n_water: params.n_water,
+        rms_error: 0.0,
+    };
+    let (mut squared_error, mut count) = (0.0, 0usize);
+    for &(i, frame_feature) in &frames {
+        let t_cam_board =
+            RvecTvec::new(&result[&rvec_name(i)], &result[&tvec_name(i)]).to_na_isometry3();
+        for fp in frame_feature.features.values() {
+            let p3d = fp.p3d.as_dvec3();
+            let p_cam = t_cam_board * na::Point3::new(p3d.x, p3d.y, p3d.z);
+            if let Some(p2d) = flat_port.project_one(&p_cam.coords) {
+                let detected = na::Vector2::new(fp.p2d.x as f64, fp.p2d.y as f64);
+                squared_error += (p2d - detected).norm_squared();
+                count += 1;
+            }
+        }
+    }
+    flat_port.rms_error = (squared_error / count.max(1) as f64).sqrt();
+    tracing::info!(
+        "flat port at {:.4} m, normal ({:.4}, {:.4}, {:.4}), rms {:.3} px",
+        flat_port.distance,
+        flat_port.normal[0],
+        flat_port.normal[1],
+        flat_port.normal[2],
+        flat_port.rms_error
+    );
+    Some(flat_port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::eucm;
+
+    #[test]
+    fn refraction_follows_snell() {
+        let normal = na::Vector3::new(0.0, 0.0, 1.0);
+        let dir = na::Vector3::new(0.5, 0.2, 1.0).normalize();
+        let refracted = refract(&dir, &normal, 1.0 / 1.49);
+        let sin = |d: &na::Vector3<f64>| d.cross(&normal).norm();
+        assert!((refracted.norm() - 1.0).abs() < 1e-12);
+        assert!((sin(&dir) - 1.49 * sin(&refracted)).abs() < 1e-12);
+        // stays in the plane of the ray and the normal
+        assert!(dir.cross(&normal).dot(&refracted).abs() < 1e-12);
+    }
+
+    #[test]
+    fn project_unproject_round_trip() {
+        let params = FlatPortParams::default();
+        let port = FlatPort {
+            model: eucm(),
+            normal: na::Vector3::new(0.05, -0.03, 1.0).normalize().into(),
+            distance: 0.02,
+            glass_thickness: params.glass_thickness,
+            n_glass: params.n_glass,
+            n_water: params.n_water,
+            rms_error: 0.0,
+        };
+        let p3d = na::Vector3::new(0.3, -0.2, 1.2);
+        let p2d = port.project_one(&p3d).unwrap();
+        let (origin, dir) = port.unproject_one(&p2d);
+        assert!((p3d - origin).normalize().cross(&dir).norm() < 1e-6);
+        // a flat port narrows the field of view, the point is imaged farther from the center
+        let center = na::Vector2::new(640.0, 400.0);
+        assert!((p2d - center).norm() > (port.model.project_one(&p3d) - center).norm());
+        assert!(port
+            .project_one(&na::Vector3::new(0.0, 0.0, 0.01))
+            .is_none());
+    }
+}
@@ -0,0 +1,222 @@
+use image::{DynamicImage, ImageBuffer, Pixel};
+use nalgebra as na;
+use num_traits::{Bounded, NumCast, ToPrimitive};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    Nearest,
+    #[default]
+    Bilinear,
+    /// Cubic convolution with a = -0.75 like OpenCV.
+    Bicubic,
+    Lanczos3,
+}
+
+/// How the source is sampled outside of the image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Border {
+    /// Every channel is this value, in the units of the image type.
+    Constant(f64),
+    /// Repeat the edge pixels, `aaa|abcd|ddd`.
+    Replicate,
+    /// Mirror without repeating the edge pixels, `cb|abcd|cb`.
+    Reflect,
+}
+
+impl Default for Border {
+    fn default() -> Self {
+        Border::Constant(0.0)
+    }
+}
+
+const MAX_TAPS: usize = 6;
+
+impl Interpolation {
+    fn radius(&self) -> i64 {
+        match self {
+            Interpolation::Nearest => 0,
+            Interpolation::Bilinear => 1,
+            Interpolation::Bicubic => 2,
+            Interpolation::Lanczos3 => 3,
+        }
+    }
+
+    fn kernel(&self, d: f64) -> f64 {
+        let d = d.abs();
+        match self {
+            Interpolation::Nearest => 1.0,
+            Interpolation::Bilinear => (1.0 - d).max(0.0),
+            Interpolation::Bicubic => {
+                let a = -0.75;
+                if d <= 1.0 {
+                    ((a + 2.0) * d - (a + 3.0)) * d * d + 1.0
+                } else if d < 2.0 {
+                    ((a * d - 5.0 * a) * d + 8.0 * a) * d - 4.0 * a
+                } else {
+                    0.0
+                }
+            }
+            Interpolation::Lanczos3 => {
+                if d < 1e-8 {
+                    1.0
+                } else if d < 3.0 {
+                    let pd = std::f64::consts::PI * d;
+                    3.0 * pd.sin() * (pd / 3.0).sin() / (pd * pd)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    /// First tap and the normalized weights of the taps around `x`.
+    fn taps(&self, x: f64) -> (i64, usize, [f64; MAX_TAPS]) {
+        let mut weights = [0.0; MAX_TAPS];
+        if *self == Interpolation::Nearest {
+            weights[0] = 1.0;
+            return (x.round() as i64, 1, weights);
+        }
+        let r = self.radius();
+        let start = x.floor() as i64 - r + 1;
+        let num = 2 * r as usize;
+        for (i, w) in weights.iter_mut().take(num).enumerate() {
+            *w = self.kernel(x - (start + i as i64) as f64);
+        }
+        let sum: f64 = weights.iter().sum();
+        weights.iter_mut().for_each(|w| *w /= sum);
+        (start, num, weights)
+    }
+}
+
+impl Border {
+    /// Source index of `i` for an axis of length `n`, `None` for the constant border.
+    fn index(&self, i: i64, n: i64) -> Option<i64> {
+        if (0..n).contains(&i) {
+            return Some(i);
+        }
+        match self {
+            Border::Constant(_) => None,
+            Border::Replicate => Some(i.clamp(0, n - 1)),
+            Border::Reflect => {
+                if n == 1 {
+                    return Some(0);
+                }
+                let period = 2 * (n - 1);
+                let i = i.rem_euclid(period);
+                Some(if i < n { i } else { period - i })
+            }
+        }
+    }
+
+    fn value(&self) -> f64 {
+        match self {
+            Border::Constant(v) => *v,
+            _ => 0.0,
+        }
+    }
+}
+
+fn remap_buffer<P>(
+    src: &ImageBuffer<P, Vec<P::Subpixel>>,
+    xmap: &na::DMatrix<f32>,
+    ymap: &na::DMatrix<f32>,
+    interpolation: Interpolation,
+    border: Border,
+) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel + Send + Sync,
+    P::Subpixel: Send + Sync,
+{
+    let (w, h) = (src.width() as i64, src.height() as i64);
+    let channels = P::CHANNEL_COUNT as usize;
+    let (min_v, max_v) = (
+        P::Subpixel::min_value().to_f64().unwrap(),
+        P::Subpixel::max_value().to_f64().unwrap(),
+    );
+    let is_integer = <P::Subpixel as NumCast>::from(0.5)
+        .unwrap()
+        .to_f64()
+        .unwrap()
+        == 0.0;
+    let to_subpixel = |v: f64| -> P::Subpixel {
+        let v = if is_integer { v.round() } else { v };
+        NumCast::from(v.clamp(min_v, max_v)).unwrap()
+    };
+    let border_value = to_subpixel(border.value());
+    let (rows, cols) = xmap.shape();
+    // maps are row major buffers, same as camera_intrinsic_model::remap
+    ImageBuffer::from_par_fn(cols as u32, rows as u32, |x, y| {
+        let idx = y as usize * cols + x as usize;
+        let (sx, sy) = (xmap.as_slice()[idx] as f64, ymap.as_slice()[idx] as f64);
+        if sx.is_nan() || sy.is_nan() {
+            return *P::from_slice(&[border_value; 4][..channels]);
+        }
+        let (x0, nx, wx) = interpolation.taps(sx);
+        let (y0, ny, wy) = interpolation.taps(sy);
+        let mut acc = [0.0f64; 4];
+        for (j, wy) in wy.iter().take(ny).enumerate() {
+            let row = border.index(y0 + j as i64, h);
+            for (i, wx) in wx.iter().take(nx).enumerate() {
+                let weight = wx * wy;
+                match (border.index(x0 + i as i64, w), row) {
+                    (Some(c), Some(r)) => {
+                        let p = src.get_pixel(c as u32, r as u32);
+                        for (a, v) in acc.iter_mut().zip(p.channels()) {
+                            *a += weight * v.to_f64().unwrap();
+                        }
+                    }
+                    _ => acc
+                        .iter_mut()
+                        .take(channels)
+                        .for_each(|a| *a += weight * border.value()),
+                }
+            }
+        }
+        let values = acc.map(to_subpixel);
+        *P::from_slice(&values[..channels])
+    })
+}
+
+macro_rules! remap_dynamic {
+    ($src:expr, $xmap:expr, $ymap:expr, $interpolation:expr, $border:expr, $($img_type:ident),*) => {
+        match $src {
+            $(
+                DynamicImage::$img_type(img) => DynamicImage::$img_type(remap_buffer(
+                    img,
+                    $xmap,
+                    $ymap,
+                    $interpolation,
+                    $border,
+                )),
+            )*
+            _ => panic!("Not support this image type."),
+        }
+    };
+}
+
+/// Sample `src` at (`xmap`, `ymap`) for each output pixel, NaN in the maps gives the border value.
+pub fn remap(
+    src: &DynamicImage,
+    xmap: &na::DMatrix<f32>,
+    ymap: &na::DMatrix<f32>,
+    interpolation: Interpolation,
+    border: Border,
+) -> DynamicImage {
+    remap_dynamic!(
+        src,
+        xmap,
+        ymap,
+        interpolation,
+        border,
+        ImageLuma8,
+        ImageLumaA8,
+        ImageRgb8,
+        ImageRgba8,
+        ImageLuma16,
+        ImageLumaA16,
+        ImageRgb16,
+        ImageRgba16,
+        ImageRgb32F,
+        ImageRgba32F
+    )
+}
@@ -0,0 +1,167 @@
+use image::GrayImage;
+use nalgebra as na;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Pixel values of an 8 bit image.
+const LEVELS: usize = 256;
+
+/// Inverse camera response `U`, the pixel value `k` is the irradiance times the exposure time
+/// `U(k)` up to scale. The values are scaled to [0, 255] like the `pcalib.txt` of DSO.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InverseResponse {
+    pub values: Vec<f64>,
+    /// `gamma` of the closest `U(k) = 255 * (k / 255)^gamma`, about 2.2 for an sRGB encoding.
+    pub gamma: f64,
+    /// Rms of `U(I) - t * B` over the unsaturated pixels, in the scaled pixel values.
+    pub rms_error: f64,
+    /// Pixel values without an unsaturated observation, interpolated from the observed ones.
+    pub unobserved: Vec<u8>,
+}
+
+impl InverseResponse {
+    /// A response has to increase with the pixel value, a step down usually means the scene
+    /// or the camera moved between the exposures.
+    pub fn is_monotonic(&self) -> bool {
+        self.values.windows(2).all(|v| v[1] >= v[0])
+    }
+}
+
+/// Fills the values without observation by linear interpolation of the closest observed ones,
+/// and extrapolation of the last two at the ends.
+fn fill_unobserved(values: &mut [f64], observed: &[bool]) {
+    let observed: Vec<_> = (0..values.len()).filter(|&k| observed[k]).collect();
+    let line = |k0: usize, k1: usize, k: usize, values: &[f64]| {
+        values[k0] + (values[k1] - values[k0]) * (k as f64 - k0 as f64) / (k1 - k0) as f64
+    };
+    for k in 0..values.len() {
+        let upper = observed.partition_point(|&o| o < k);
+        if observed.get(upper) == Some(&k) {
+            continue;
+        }
+        let (k0, k1) = if upper == 0 {
+            (observed[0], observed[1])
+        } else if upper == observed.len() {
+            (observed[upper - 2], observed[upper - 1])
+        } else {
+            (observed[upper - 1], observed[upper])
+        };
+        values[k] = line(k0, k1, k, values);
+    }
+}
+
+/// Estimates the inverse response from `(exposure time, image)` captures of a static scene, e.g.
+/// the board or a gray chart, with a fixed camera and lighting. `U(I_i(x)) = t_i * B(x)` of the
+/// unsaturated pixels is solved in least squares for the response `U` and the irradiance `B` of
+/// every pixel, the model of the photometric calibration of DSO. `B` is linear in `U`, so it's
+/// eliminated per pixel and `U` is solved directly instead of alternating between the two.
+/// `None` if the exposure times are all the same, the images are not of the same size or the
+/// pixel values are not linked by pixels seen at several exposures.
+pub fn calib_inverse_response(captures: &[(f64, GrayImage)]) -> Option<InverseResponse> {
+    let (_, first) = captures.first()?;
+    let exposures: Vec<_> = captures.iter().map(|c| c.0).collect();
+    if captures
+        .iter()
+        .any(|c| c.1.dimensions() != first.dimensions())
+    {
+        tracing::warn!("the captures are not of the same size");
+        return None;
+    } else if exposures.iter().all(|&t| t == exposures[0]) {
+        tracing::warn!("the captures need at least two exposure times");
+        return None;
+    }
+    // with B(x) = sum(t_i * U(k_i)) / sum(t_i^2) the residuals of a pixel are (I - t t^T / t^T t)
+    // applied to its U(k_i), the normal matrix of U adds up the projection of every pixel
+    let (normal, counts) = (0..first.as_raw().len())
+        .into_par_iter()
+        .fold(
+            || {
+                (
+                    na::DMatrix::<f64>::zeros(LEVELS, LEVELS),
+                    vec![0usize; LEVELS],
+                )
+            },
+            |(mut normal, mut counts), x| {
+                let samples: Vec<_> = captures
+                    .iter()
+                    .map(|(t, img)| (*t, img.as_raw()[x] as usize))
+                    .filter(|&(_, k)| k < LEVELS - 1)
+                    .collect();
+                if samples.len() < 2 {
+                    return (normal, counts);
+                }
+                let tt: f64 = samples.iter().map(|(t, _)| t * t).sum();
+                for &(ti, ki) in &samples {
+                    normal[(ki, ki)] += 1.0;
+                    counts[ki] += 1;
+                    for &(tj, kj) in &samples {
+                        normal[(ki, kj)] -= ti * tj / tt;
+                    }
+                }
+                (normal, counts)
+            },
+        )
+        .reduce(
+            || (na::DMatrix::zeros(LEVELS, LEVELS), vec![0; LEVELS]),
+            |(a, mut a_counts), (b, b_counts)| {
+                for (a, b) in a_counts.iter_mut().zip(b_counts) {
+                    *a += b;
+                }
+                (a + b, a_counts)
+            },
+        );
+    let observed: Vec<_> = counts.iter().map(|&c| c > 0).collect();
+    let free: Vec<_> = (0..LEVELS).filter(|&k| observed[k]).collect();
+    if free.len() < 2 {
+        tracing::warn!("the captures have less than two unsaturated pixel values");
+        return None;
+    }
+    let (&gauge, free) = free.split_last()?;
+    // the scale of U and B is free, fix U of the highest observed value to 1
+    let a = na::DMatrix::from_fn(free.len(), free.len(), |r, c| normal[(free[r], free[c])]);
+    let b = na::DVector::from_fn(free.len(), |r, _| -normal[(free[r], gauge)]);
+    let Some(solution) = a.cholesky().map(|a| a.solve(&b)) else {
+        tracing::warn!("the pixel values are not linked by pixels seen at several exposures");
+        return None;
+    };
+    let mut values = vec![0.0; LEVELS];
+    values[gauge] = 1.0;
+    for (&k, v) in free.iter().zip(solution.iter()) {
+        values[k] = *v;
+    }
+    fill_unobserved(&mut values, &observed);
+    let max = values[LEVELS - 1];
+    if max <= 0.0 {
+        tracing::warn!("the response doesn't increase with the pixel value");
+        return None;
+    }
+    // scaled to U(255) = 255, the irradiance can't be negative below the darkest observed value
+    let scale = (LEVELS - 1) as f64 / max;
+    for v in values.iter_mut() {
+        *v = (*v * scale).max(0.0);
+    }
+    let u = na::DVector::from_column_slice(&values);
+    let observation_num = counts.iter().sum::<usize>().max(1) as f64;
+    let rms_error = (u.dot(&(&normal * &u)).max(0.0) / observation_num).sqrt();
+    // log(U / 255) = gamma * log(k / 255)
+    let (mut xy, mut xx) = (0.0, 0.0);
+    for (k, &v) in values.iter().enumerate().take(LEVELS - 1).skip(1) {
+        if v > 0.0 {
+            let (x, y) = (
+                (k as f64 / (LEVELS - 1) as f64).ln(),
+                (v / (LEVELS - 1) as f64).ln(),
+            );
+            xy += x * y;
+            xx += x * x;
+        }
+    }
+    Some(InverseResponse {
+        values,
+        gamma: xy / xx.max(f64::EPSILON),
+        rms_error,
+        unobserved: (0..LEVELS)
+            .filter(|&k| !observed[k])
+            .map(|k| k as u8)
+            .collect(),
+    })
+}
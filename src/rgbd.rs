@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use camera_intrinsic_model::*;
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+
+use crate::detected_points::FrameFeature;
+use crate::types::{RvecTvec, ToRvecTvec};
+
+/// Depth image registered to the depth camera in the units of the sensor, 0 where there is no
+/// depth.
+pub type DepthImage = image::ImageBuffer<image::Luma<u16>, Vec<u16>>;
+
+/// Pixels around a corner the depth is the median of, the corner itself is on a tag edge.
+const DEPTH_WINDOW: i64 = 2;
+
+pub struct DepthColorParams {
+    /// Depth of a unit of the depth images in m.
+    pub depth_scale: f64,
+    /// Max time difference of a depth image and a frame of the depth camera.
+    pub max_time_diff_ns: i64,
+}
+
+impl Default for DepthColorParams {
+    fn default() -> Self {
+        DepthColorParams {
+            depth_scale: 0.001,
+            max_time_diff_ns: 20_000_000,
+        }
+    }
+}
+
+/// Result of `depth_color_alignment`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepthColorAlignment {
+    pub depth_cam: usize,
+    pub color_cam: usize,
+    /// Transforms points from the depth camera to the color camera.
+    pub t_color_depth: RvecTvec,
+    pub num_frames: usize,
+    pub num_corners: usize,
+    /// Distance of the corners sensed by the depth camera and projected to the color camera to
+    /// the corners detected by the color camera, in px.
+    pub mean_error: f64,
+    pub median_error: f64,
+    /// Mean of the sensed minus the calibrated depth of the corners in m, a depth offset of the
+    /// sensor shows here.
+    pub depth_bias: f64,
+    pub depth_rms: f64,
+}
+
+/// Median of the valid depths around `p2d` in m.
+fn sample_depth(depth: &DepthImage, p2d: &na::Vector2<f64>, depth_scale: f64) -> Option<f64> {
+    let (x, y) = (p2d.x.round() as i64, p2d.y.round() as i64);
+    let mut values: Vec<_> = (y - DEPTH_WINDOW..=y + DEPTH_WINDOW)
+        .flat_map(|y| (x - DEPTH_WINDOW..=x + DEPTH_WINDOW).map(move |x| (x, y)))
+        .filter(|&(x, y)| x >= 0 && y >= 0 && x < depth.width() as i64 && y < depth.height() as i64)
+        .map(|(x, y)| depth.get_pixel(x as u32, y as u32)[0])
+        .filter(|&v| v > 0)
+        .collect();
+    if values.is_empty() {
+        return None;
+    }
+    let mid = values.len() / 2;
+    Some(*values.select_nth_unstable(mid).1 as f64 * depth_scale)
+}
+
+/// Validates the depth to color alignment of an RGB-D device calibrated as a rig. The corners
+/// detected by the depth camera, usually the IR camera the depth is registered to, are lifted
+/// to 3d with the sensed depth of the `depth_images` `(time_ns, image)`, moved to the color
+/// camera with `t_color_depth` and compared to the color detections of the same frame. The
+/// sensed depths are also compared to the board poses `depth_rtvecs` of the depth camera.
+#[allow(clippy::too_many_arguments)]
+pub fn depth_color_alignment(
+    depth_cam: usize,
+    color_cam: usize,
+    depth_model: &GenericModel<f64>,
+    color_model: &GenericModel<f64>,
+    t_color_depth: &na::Isometry3<f64>,
+    depth_rtvecs: &HashMap<usize, RvecTvec>,
+    depth_frames: &[Option<FrameFeature>],
+    color_frames: &[Option<FrameFeature>],
+    depth_images: &[(i64, DepthImage)],
+    params: &DepthColorParams,
+) -> Option<DepthColorAlignment> {
+    let (mut errors, mut depth_errors, mut num_frames) = (Vec::new(), Vec::new(), 0);
+    for (time_ns, depth) in depth_images {
+        let Some((frame_idx, _)) = depth_frames
+            .iter()
+            .enumerate()
+            .filter_map(|(i, f)| Some((i, (f.as_ref()?.time_ns - time_ns).abs())))
+            .filter(|(i, dt)| *dt <= params.max_time_diff_ns && depth_rtvecs.contains_key(i))
+            .min_by_key(|(_, dt)| *dt)
+        else {
+            continue;
+        };
+        let (Some(depth_frame), Some(color_frame)) = (
+            depth_frames[frame_idx].as_ref(),
+            color_frames.get(frame_idx).and_then(|f| f.as_ref()),
+        ) else {
+            continue;
+        };
+        let t_depth_board = depth_rtvecs[&frame_idx].to_na_isometry3();
+        let frame_errors = errors.len();
+        for (id, fp) in &depth_frame.features {
+            let p2d = na::Vector2::new(fp.p2d.x as f64, fp.p2d.y as f64);
+            let Some(z) = sample_depth(depth, &p2d, params.depth_scale) else {
+                continue;
+            };
+            let p3d = fp.p3d.as_dvec3();
+            let board_z = (t_depth_board * na::Point3::new(p3d.x, p3d.y, p3d.z)).z;
+            depth_errors.push(z - board_z);
+            let ray = depth_model.unproject_one(&p2d);
+            let Some(color_fp) = color_frame.features.get(id).filter(|_| ray.z > 0.0) else {
+                continue;
+            };
+            let p_color = t_color_depth * na::Point3::from(ray * (z / ray.z));
+            if p_color.z <= 0.0 {
+                continue;
+            }
+            let projected = color_model.project_one(&p_color.coords);
+            let detected = na::Vector2::new(color_fp.p2d.x as f64, color_fp.p2d.y as f64);
+            errors.push((projected - detected).norm());
+        }
+        if errors.len() > frame_errors {
+            num_frames += 1;
+        }
+    }
+    if errors.is_empty() {
+        tracing::warn!(
+            "no corners with depth seen by both cam{} and cam{}",
+            depth_cam,
+            color_cam
+        );
+        return None;
+    }
+    let mean_error = errors.iter().sum::<f64>() / errors.len() as f64;
+    errors.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let n = depth_errors.len() as f64;
+    Some(DepthColorAlignment {
+        depth_cam,
+        color_cam,
+        t_color_depth: t_color_depth.to_rvec_tvec(),
+        num_frames,
+        num_corners: errors.len(),
+        mean_error,
+        median_error: errors[errors.len() / 2],
+        depth_bias: depth_errors.iter().sum::<f64>() / n,
+        depth_rms: (depth_errors.iter().map(|e| e * e).sum::<f64>() / n).sqrt(),
+    })
+}
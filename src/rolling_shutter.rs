@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+
+use camera_intrinsic_model::*;
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+use tiny_solver::loss_functions::HuberLoss;
+
+use crate::detected_points::FrameFeature;
+use crate::observer::PipelineObserver;
+use crate::optimization::factors::{
+    ConstantVelocityFactor, ReprojectionFactor, RollingShutterReprojectionFactor,
+};
+use crate::optimization::{rvec_name, tvec_name};
+use crate::types::RvecTvec;
+use crate::util::{optimize_with_observer, set_problem_parameter_bound};
+
+fn angular_velocity_name(frame_idx: usize) -> String {
+    format!("angular_velocity{}", frame_idx)
+}
+
+fn linear_velocity_name(frame_idx: usize) -> String {
+    format!("linear_velocity{}", frame_idx)
+}
+
+/// Readout timing of a rolling shutter camera.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RollingShutter {
+    /// Time between the exposure of two consecutive rows.
+    pub line_delay_ns: f64,
+    /// Time between the exposure of the first and the last row.
+    pub readout_time_ns: f64,
+}
+
+/// Result of `calib_rolling_shutter`.
+#[derive(Debug, Clone)]
+pub struct RollingShutterCalibration {
+    pub model: GenericModel<f64>,
+    /// Board to camera poses at the time of the middle row.
+    pub rtvecs: HashMap<usize, RvecTvec>,
+    /// Time between the exposure of two consecutive rows.
+    pub line_delay_ns: f64,
+}
+
+impl RollingShutterCalibration {
+    pub fn rolling_shutter(&self) -> RollingShutter {
+        RollingShutter {
+            line_delay_ns: self.line_delay_ns,
+            readout_time_ns: self.line_delay_ns * self.model.height(),
+        }
+    }
+}
+
+/// Line delay of the capture times of the corners, the slope of their time after the frame over
+/// their row.
+fn capture_time_line_delay_ns(frame_feature_list: &[Option<FrameFeature>]) -> Option<f64> {
+    let (mut dt_row, mut row_row) = (0.0, 0.0);
+    for frame_feature in frame_feature_list.iter().flatten() {
+        let middle_row = frame_feature.img_w_h.1 as f64 / 2.0;
+        for fp in frame_feature.features.values() {
+            let dt = (fp.capture_time_ns(frame_feature.time_ns) - frame_feature.time_ns) as f64;
+            let row = fp.p2d.y as f64 - middle_row;
+            dt_row += dt * row;
+            row_row += row * row;
+        }
+    }
+    (row_row > 0.0).then(|| dt_row / row_row)
+}
+
+/// Refines a global shutter calibration `generic_camera` and `rtvecs` of the same frames with a
+/// rolling shutter model. Every row is exposed `line_delay` after the previous one and the
+/// camera moves with a constant velocity during the readout. The velocity of a frame is tied
+/// to the poses of the frames right before and after it, so the frames have to be a continuous
+/// sequence, e.g. a video of a moving board, and frames without a close neighbor keep the
+/// global shutter model. The line delay is estimated unless the corners have their capture
+/// times, e.g. set from the known line delay with `FrameFeature::set_rolling_shutter_times`.
+pub fn calib_rolling_shutter(
+    frame_feature_list: &[Option<FrameFeature>],
+    generic_camera: &GenericModel<f64>,
+    rtvecs: &HashMap<usize, RvecTvec>,
+    xy_same_focal: bool,
+    observer: &dyn PipelineObserver,
+) -> Option<RollingShutterCalibration> {
+    let mut frames: Vec<_> = frame_feature_list
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| Some((i, f.as_ref()?.time_ns)))
+        .filter(|(i, _)| rtvecs.contains_key(i))
+        .collect();
+    frames.sort_by_key(|f| f.1);
+    let mut dts: Vec<_> = frames.windows(2).map(|w| w[1].1 - w[0].1).collect();
+    dts.sort();
+    let Some(&median_dt) = dts.get(dts.len() / 2) else {
+        tracing::warn!("not enough frames for rolling shutter calibration");
+        return None;
+    };
+    if median_dt <= 0 {
+        tracing::warn!("rolling shutter calibration needs increasing frame timestamps");
+        return None;
+    }
+    // skip gaps of missing detections
+    let max_dt = median_dt * 3 / 2;
+    let neighbors: HashMap<usize, Vec<(usize, f64)>> = frames
+        .iter()
+        .enumerate()
+        .filter_map(|(k, &(i, t))| {
+            let close: Vec<_> = [k.checked_sub(1), Some(k + 1)]
+                .into_iter()
+                .flatten()
+                .filter_map(|n| frames.get(n))
+                .filter(|(_, tn)| (tn - t).abs() <= max_dt)
+                .map(|&(j, tn)| (j, (tn - t) as f64 * 1e-9))
+                .collect();
+            (!close.is_empty()).then_some((i, close))
+        })
+        .collect();
+    if neighbors.is_empty() {
+        tracing::warn!("no consecutive frames for rolling shutter calibration");
+        return None;
+    }
+
+    let mut params = generic_camera.params();
+    if xy_same_focal {
+        // remove fy
+        params = params.remove_row(1);
+    };
+    let params_len = params.len();
+    let mut initial_values =
+        HashMap::<String, na::DVector<f64>>::from([("params".to_string(), params)]);
+    let mut problem = tiny_solver::Problem::new();
+    // corners with a capture time need no line delay
+    let mut estimate_line_delay = false;
+    let focal = (generic_camera.params()[0] + generic_camera.params()[1]) / 2.0;
+    for &(i, _) in &frames {
+        let rvec_i = rvec_name(i);
+        let tvec_i = tvec_name(i);
+        initial_values.insert(rvec_i.clone(), rtvecs[&i].na_rvec());
+        initial_values.insert(tvec_i.clone(), rtvecs[&i].na_tvec());
+        let frame_feature = frame_feature_list[i].as_ref().unwrap();
+        let Some(close) = neighbors.get(&i) else {
+            for fp in frame_feature.features.values() {
+                let cost = ReprojectionFactor::new(generic_camera, &fp.p3d, &fp.p2d, xy_same_focal);
+                problem.add_residual_block(
+                    2,
+                    &[("params", params_len), (&rvec_i, 3), (&tvec_i, 3)],
+                    Box::new(cost),
+                    Some(Box::new(HuberLoss::new(1.0))),
+                );
+            }
+            continue;
+        };
+        let angular_velocity_name = angular_velocity_name(i);
+        let linear_velocity_name = linear_velocity_name(i);
+        for fp in frame_feature.features.values() {
+            let cost = RollingShutterReprojectionFactor::new(
+                generic_camera,
+                fp,
+                frame_feature.time_ns,
+                xy_same_focal,
+            );
+            let mut variables = vec![
+                ("params", params_len),
+                (rvec_i.as_str(), 3),
+                (tvec_i.as_str(), 3),
+                (angular_velocity_name.as_str(), 3),
+                (linear_velocity_name.as_str(), 3),
+            ];
+            if cost.capture_dt.is_none() {
+                variables.push(("line_delay_us", 1));
+                estimate_line_delay = true;
+            }
+            problem.add_residual_block(
+                2,
+                &variables,
+                Box::new(cost),
+                Some(Box::new(HuberLoss::new(1.0))),
+            );
+        }
+        // init from the finite differences of the global shutter poses
+        let t_cam_board = rtvecs[&i].to_na_isometry3();
+        let (mut angular_velocity, mut linear_velocity) =
+            (na::Vector3::zeros(), na::Vector3::zeros());
+        for &(j, dt) in close {
+            let cost = ConstantVelocityFactor::new(dt, focal, focal);
+            problem.add_residual_block(
+                6,
+                &[
+                    (&rvec_i, 3),
+                    (&tvec_i, 3),
+                    (&rvec_name(j), 3),
+                    (&tvec_name(j), 3),
+                    (&angular_velocity_name, 3),
+                    (&linear_velocity_name, 3),
+                ],
+                Box::new(cost),
+                Some(Box::new(HuberLoss::new(1.0))),
+            );
+            let t_j_i = rtvecs[&j].to_na_isometry3() * t_cam_board.inverse();
+            angular_velocity += t_j_i.rotation.scaled_axis() / dt / close.len() as f64;
+            linear_velocity += t_j_i.translation.vector / dt / close.len() as f64;
+        }
+        initial_values.insert(
+            angular_velocity_name,
+            na::dvector![angular_velocity.x, angular_velocity.y, angular_velocity.z],
+        );
+        initial_values.insert(
+            linear_velocity_name,
+            na::dvector![linear_velocity.x, linear_velocity.y, linear_velocity.z],
+        );
+    }
+    set_problem_parameter_bound("params", &mut problem, generic_camera, xy_same_focal);
+    if estimate_line_delay {
+        initial_values.insert("line_delay_us".to_string(), na::dvector![0.0]);
+        // the readout can't take longer than a frame
+        problem.set_variable_bounds(
+            "line_delay_us",
+            0,
+            0.0,
+            median_dt as f64 * 1e-3 / generic_camera.height(),
+        );
+    }
+
+    let mut result = optimize_with_observer(
+        &problem,
+        &initial_values,
+        "calib_rolling_shutter",
+        None,
+        observer,
+    )?;
+    let mut new_params = result.remove("params").unwrap();
+    if xy_same_focal {
+        new_params = new_params.clone().insert_row(1, new_params[0]);
+    };
+    let mut model = *generic_camera;
+    model.set_params(&new_params);
+    let line_delay_ns = match result.get("line_delay_us") {
+        Some(line_delay_us) => line_delay_us[0] * 1e3,
+        None => capture_time_line_delay_ns(frame_feature_list)?,
+    };
+    tracing::info!(
+        "line delay {:.1} ns, readout time {:.3} ms",
+        line_delay_ns,
+        line_delay_ns * model.height() * 1e-6
+    );
+    let rtvecs = frames
+        .iter()
+        .map(|&(i, _)| {
+            (
+                i,
+                RvecTvec::new(
+                    &result.remove(&rvec_name(i)).unwrap(),
+                    &result.remove(&tvec_name(i)).unwrap(),
+                ),
+            )
+        })
+        .collect();
+    Some(RollingShutterCalibration {
+        model,
+        rtvecs,
+        line_delay_ns,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detected_points::FeaturePoint;
+    use crate::observer::NoopObserver;
+    use crate::test_util;
+    use tiny_solver::factors::Factor;
+
+    const LINE_DELAY_NS: f64 = 20_000.0;
+
+    /// Frames of a board moving with a constant velocity seen by a rolling shutter camera, and
+    /// the poses at the time of the middle row.
+    fn rolling_shutter_frames(
+        model: &GenericModel<f64>,
+    ) -> (Vec<Option<FrameFeature>>, HashMap<usize, RvecTvec>) {
+        let board = test_util::board();
+        let angular_velocity = na::Vector3::new(0.3, -0.5, 0.2);
+        let linear_velocity = na::Vector3::new(0.4, 0.2, -0.1);
+        let dt = test_util::FRAME_DT_NS as f64 * 1e-9;
+        let step = na::Isometry3::new(linear_velocity * dt, angular_velocity * dt);
+        let mut pose = test_util::board_poses(&board, 1)[0];
+        let mut frames = Vec::new();
+        let mut rtvecs = HashMap::new();
+        for i in 0..12 {
+            let time_ns = i as i64 * test_util::FRAME_DT_NS;
+            let mut frame = test_util::project_frame(model, &board, &pose, time_ns);
+            for fp in frame.features.values_mut() {
+                let p = pose * na::Point3::new(fp.p3d.x as f64, fp.p3d.y as f64, fp.p3d.z as f64);
+                // the row and the time of the corner depend on each other
+                for _ in 0..5 {
+                    let dt = (fp.p2d.y as f64 - model.height() / 2.0) * LINE_DELAY_NS * 1e-9;
+                    let p = p.coords + (angular_velocity.cross(&p.coords) + linear_velocity) * dt;
+                    let p2d = model.project_one(&p);
+                    fp.p2d = glam::Vec2::new(p2d.x as f32, p2d.y as f32);
+                }
+            }
+            frames.push(Some(frame));
+            rtvecs.insert(i, test_util::rtvec(&pose));
+            pose = step * pose;
+        }
+        (frames, rtvecs)
+    }
+
+    #[test]
+    fn capture_time_moves_corner() {
+        let model = test_util::eucm();
+        let fp = FeaturePoint {
+            p2d: glam::Vec2::new(300.0, 700.0),
+            p3d: glam::Vec3::new(0.1, 0.2, 0.0),
+            time_ns: None,
+            blur: None,
+        };
+        let mut timed = FrameFeature {
+            time_ns: 1_000_000,
+            img_w_h: (1280, 800),
+            features: HashMap::from([(0, fp)]),
+            exposure: None,
+        };
+        timed.set_rolling_shutter_times(LINE_DELAY_NS);
+        let timed_fp = timed.features[&0];
+        assert_eq!(
+            timed_fp.capture_time_ns(timed.time_ns),
+            1_000_000 + (300.0 * LINE_DELAY_NS) as i64
+        );
+
+        let mut params = vec![
+            model.params(),
+            na::dvector![0.1, -0.2, 0.05],
+            na::dvector![-0.3, -0.3, 1.0],
+            na::dvector![0.5, -0.4, 0.3],
+            na::dvector![0.2, 0.1, -0.3],
+        ];
+        let from_time =
+            RollingShutterReprojectionFactor::new(&model, &timed_fp, timed.time_ns, false)
+                .residual_func(&params);
+        params.push(na::dvector![LINE_DELAY_NS * 1e-3]);
+        let from_row = RollingShutterReprojectionFactor::new(&model, &fp, timed.time_ns, false)
+            .residual_func(&params);
+        assert!((from_time - &from_row).norm() < 1e-9);
+        params[5][0] = 0.0;
+        let global_shutter =
+            RollingShutterReprojectionFactor::new(&model, &fp, 0, false).residual_func(&params);
+        assert!((global_shutter - from_row).norm() > 1.0);
+    }
+
+    #[test]
+    fn estimates_line_delay() {
+        let model = test_util::eucm();
+        let (frames, rtvecs) = rolling_shutter_frames(&model);
+        let calib = calib_rolling_shutter(&frames, &model, &rtvecs, false, &NoopObserver).unwrap();
+        assert!(
+            (calib.line_delay_ns - LINE_DELAY_NS).abs() < 0.05 * LINE_DELAY_NS,
+            "line delay {}",
+            calib.line_delay_ns
+        );
+    }
+
+    #[test]
+    fn known_capture_times() {
+        let model = test_util::eucm();
+        let (mut frames, rtvecs) = rolling_shutter_frames(&model);
+        for f in frames.iter_mut().flatten() {
+            f.set_rolling_shutter_times(LINE_DELAY_NS);
+        }
+        let calib = calib_rolling_shutter(&frames, &model, &rtvecs, false, &NoopObserver).unwrap();
+        assert!((calib.line_delay_ns - LINE_DELAY_NS).abs() < 1.0);
+        let params_diff = (calib.model.params() - model.params()).abs().max();
+        assert!(params_diff < 0.5, "params off by {}", params_diff);
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+
+use camera_intrinsic_model::*;
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+
+use crate::detected_points::FrameFeature;
+use crate::types::RvecTvec;
+
+/// Ratios farther than this from 1 are reported as a board size mismatch.
+pub const MAX_SCALE_ERROR: f64 = 0.01;
+
+/// Tag sizes and distances between the tags measured in the images, relative to the board
+/// config. A board printed with another tag spacing than the configured one fits the board
+/// poses with tags of the wrong size, so the two ratios differ.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoardScaleCheck {
+    pub cam_idx: usize,
+    pub num_frames: usize,
+    /// Measured over configured length of the tag edges.
+    pub tag_size_ratio: f64,
+    /// Measured over configured distance between the same corners of neighboring tags.
+    pub tag_pitch_ratio: f64,
+}
+
+impl BoardScaleCheck {
+    /// Relative error of the configured tag spacing.
+    pub fn spacing_error(&self) -> f64 {
+        self.tag_pitch_ratio / self.tag_size_ratio - 1.0
+    }
+}
+
+/// Written to `scale_check.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScaleCheck {
+    pub boards: Vec<BoardScaleCheck>,
+    pub baseline: Option<BaselineScaleCheck>,
+}
+
+/// Baseline of two cameras against the one measured on the rig. A board printed at another
+/// scale than the configured one scales the board poses and the baseline with it, which the
+/// reprojection errors can't show.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaselineScaleCheck {
+    pub cam0: usize,
+    pub cam1: usize,
+    pub baseline_m: f64,
+    pub expected_baseline_m: f64,
+}
+
+impl BaselineScaleCheck {
+    pub fn new(t_i_0: &[RvecTvec], cam0: usize, cam1: usize, expected_baseline_m: f64) -> Self {
+        let t_1_0 = t_i_0[cam1].to_na_isometry3() * t_i_0[cam0].to_na_isometry3().inverse();
+        BaselineScaleCheck {
+            cam0,
+            cam1,
+            baseline_m: t_1_0.translation.vector.norm(),
+            expected_baseline_m,
+        }
+    }
+
+    /// Printed over configured board size.
+    pub fn board_scale(&self) -> f64 {
+        self.expected_baseline_m / self.baseline_m
+    }
+}
+
+/// Board point of the corner ray on the board plane of `t_cam_board`.
+fn board_point(
+    model: &GenericModel<f64>,
+    t_cam_board: &na::Isometry3<f64>,
+    p2d: &na::Vector2<f64>,
+) -> Option<na::Vector3<f64>> {
+    let ray = model.unproject_one(p2d);
+    let normal = t_cam_board.rotation * na::Vector3::z();
+    let depth = normal.dot(&t_cam_board.translation.vector) / normal.dot(&ray);
+    (depth.is_finite() && depth > 0.0)
+        .then(|| (t_cam_board.inverse() * na::Point3::from(ray * depth)).coords)
+}
+
+/// Measures the tags of every frame with a board pose by intersecting the corner rays with the
+/// board plane. Corner ids are `tag_id * 4 + corner` as in `Board::init_aprilgrid`.
+pub fn board_scale_check(
+    cam_idx: usize,
+    model: &GenericModel<f64>,
+    rtvecs: &HashMap<usize, RvecTvec>,
+    frame_feature_list: &[Option<FrameFeature>],
+) -> Option<BoardScaleCheck> {
+    let mut num_frames = 0;
+    // sum of measured * configured and configured^2 for the least squares ratio
+    let mut size = (0.0f64, 0.0f64);
+    let mut pitch = (0.0f64, 0.0f64);
+    for (i, rtvec) in rtvecs {
+        let Some(Some(frame)) = frame_feature_list.get(*i) else {
+            continue;
+        };
+        num_frames += 1;
+        let t_cam_board = rtvec.to_na_isometry3();
+        let points: HashMap<_, _> = frame
+            .features
+            .iter()
+            .filter_map(|(&id, fp)| {
+                let measured = board_point(
+                    model,
+                    &t_cam_board,
+                    &na::Vector2::new(fp.p2d.x as f64, fp.p2d.y as f64),
+                )?;
+                Some((
+                    id,
+                    (
+                        measured,
+                        na::Vector3::new(fp.p3d.x, fp.p3d.y, fp.p3d.z).cast::<f64>(),
+                    ),
+                ))
+            })
+            .collect();
+        let add = |sums: &mut (f64, f64), id0: u32, id1: u32| {
+            if let (Some((m0, c0)), Some((m1, c1))) = (points.get(&id0), points.get(&id1)) {
+                let configured = (c1 - c0).norm();
+                sums.0 += (m1 - m0).norm() * configured;
+                sums.1 += configured * configured;
+            }
+        };
+        for &id in points.keys() {
+            add(&mut size, id, id / 4 * 4 + (id + 1) % 4);
+            add(&mut pitch, id, id + 4);
+        }
+    }
+    if size.1 == 0.0 || pitch.1 == 0.0 {
+        return None;
+    }
+    Some(BoardScaleCheck {
+        cam_idx,
+        num_frames,
+        tag_size_ratio: size.0 / size.1,
+        tag_pitch_ratio: pitch.0 / pitch.1,
+    })
+}
+
+/// Distance between two board corners measured independently of the board config, e.g. with a
+/// caliper.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownDistance {
+    pub corner0: u32,
+    pub corner1: u32,
+    pub distance_m: f64,
+}
+
+/// Metric errors of a known distance reconstructed with the calibration in every frame with
+/// both corners.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownDistanceError {
+    pub cam_idx: usize,
+    pub corner0: u32,
+    pub corner1: u32,
+    pub distance_m: f64,
+    pub num_frames: usize,
+    pub mean_distance_m: f64,
+    pub rms_error_m: f64,
+    pub max_error_m: f64,
+}
+
+/// Reconstructs the corners of the known distances by intersecting their rays with the board
+/// plane of the frames with a board pose.
+pub fn known_distance_errors(
+    cam_idx: usize,
+    model: &GenericModel<f64>,
+    rtvecs: &HashMap<usize, RvecTvec>,
+    frame_feature_list: &[Option<FrameFeature>],
+    known_distances: &[KnownDistance],
+) -> Vec<KnownDistanceError> {
+    known_distances
+        .iter()
+        .filter_map(|known| {
+            let distances: Vec<_> = rtvecs
+                .iter()
+                .filter_map(|(i, rtvec)| {
+                    let frame = frame_feature_list.get(*i)?.as_ref()?;
+                    let t_cam_board = rtvec.to_na_isometry3();
+                    let point = |id: u32| {
+                        let p2d = frame.features.get(&id)?.p2d;
+                        board_point(
+                            model,
+                            &t_cam_board,
+                            &na::Vector2::new(p2d.x as f64, p2d.y as f64),
+                        )
+                    };
+                    Some((point(known.corner0)? - point(known.corner1)?).norm())
+                })
+                .collect();
+            if distances.is_empty() {
+                return None;
+            }
+            let errors: Vec<_> = distances.iter().map(|d| d - known.distance_m).collect();
+            let n = distances.len() as f64;
+            Some(KnownDistanceError {
+                cam_idx,
+                corner0: known.corner0,
+                corner1: known.corner1,
+                distance_m: known.distance_m,
+                num_frames: distances.len(),
+                mean_distance_m: distances.iter().sum::<f64>() / n,
+                rms_error_m: (errors.iter().map(|e| e * e).sum::<f64>() / n).sqrt(),
+                max_error_m: errors.iter().map(|e| e.abs()).fold(0.0, f64::max),
+            })
+        })
+        .collect()
+}
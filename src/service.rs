@@ -0,0 +1,531 @@
+//! Long-running REST service for calibration stations.
+//!
+//! * `POST /detect` with an image as body returns the detected `FrameFeature` as json.
+//! * `POST /jobs` with a `CalibrationJobRequest` as body queues a calibration job and returns its id.
+//! * `GET /jobs/{id}` returns the `JobStatus` of the job.
+//!
+//! Requests are served by a pool of http workers and jobs are run by a pool of job workers from
+//! a bounded queue, a full queue is answered with 503 and a body above the size limit with 413.
+//! Finished jobs are dropped after a time to live.
+use std::collections::HashMap;
+use std::io::Read;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use aprilgrid::detector::TagDetector;
+use camera_intrinsic_model::GenericModel;
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::board::Board;
+use crate::detected_points::{image_to_option_feature_frame, FrameFeature, MIN_CORNERS};
+use crate::observer::NoopObserver;
+use crate::types::{CalibParams, ErrorHistogram};
+use crate::util::{init_and_calibrate_one_camera_with_trials, intrinsics_covariance, validation};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CalibrationJobRequest {
+    /// "ucm", "eucm", "kb4", "opencv5", "eucmt" or "ftheta"
+    pub model: String,
+    pub frames: Vec<FrameFeature>,
+    #[serde(default)]
+    pub one_focal: bool,
+    #[serde(default)]
+    pub disabled_distortion_num: usize,
+    #[serde(default)]
+    pub fixed_focal: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalibrationJobResult {
+    pub intrinsic: GenericModel<f64>,
+    pub covariance: Option<Vec<Vec<f64>>>,
+    pub avg_reprojection_error: f64,
+    pub median_reprojection_error: f64,
+    pub p99_reprojection_error: f64,
+    pub histogram: ErrorHistogram,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done { result: CalibrationJobResult },
+    Failed { reason: String },
+}
+
+pub fn run_calibration_job(
+    request: &CalibrationJobRequest,
+) -> Result<CalibrationJobResult, String> {
+    let model = GenericModel::from_str(&request.model)
+        .map_err(|_| format!("unknown model {}", request.model))?;
+    let frames: Vec<Option<FrameFeature>> = request
+        .frames
+        .iter()
+        .map(|f| {
+            if f.features.len() < MIN_CORNERS {
+                None
+            } else {
+                Some(f.clone())
+            }
+        })
+        .collect();
+    if frames.iter().flatten().count() < 2 {
+        return Err("need at least two frames with enough corners".to_string());
+    }
+    let calib_params = CalibParams {
+        fixed_focal: request.fixed_focal,
+        disabled_distortion_num: request.disabled_distortion_num,
+        one_focal: request.one_focal,
+        plateau: None,
+    };
+    let cams_detected_feature_frames = vec![frames];
+    let (intrinsic, rtvec_map) = init_and_calibrate_one_camera_with_trials(
+        0,
+        &cams_detected_feature_frames,
+        &model,
+        &NoopObserver,
+        &calib_params,
+        3,
+    )
+    .ok_or("calibration failed")?;
+    let xy_same_focal = calib_params.one_focal || calib_params.fixed_focal.is_some();
+    let covariance = intrinsics_covariance(
+        &intrinsic,
+        &rtvec_map,
+        &cams_detected_feature_frames[0],
+        xy_same_focal,
+    )
+    .map(|m| m.row_iter().map(|r| r.iter().cloned().collect()).collect());
+    let report = validation(
+        0,
+        &intrinsic,
+        &rtvec_map,
+        &cams_detected_feature_frames[0],
+        &NoopObserver,
+    );
+    Ok(CalibrationJobResult {
+        intrinsic,
+        covariance,
+        avg_reprojection_error: report.avg_99_percent,
+        median_reprojection_error: report.median_error,
+        p99_reprojection_error: report.p99_error,
+        histogram: report.histogram,
+    })
+}
+
+/// Limits of the service.
+#[derive(Debug, Clone)]
+pub struct ServiceOptions {
+    /// Threads serving http requests.
+    pub http_workers: usize,
+    /// Threads running calibration jobs, at least one.
+    pub job_workers: usize,
+    /// Jobs waiting for a job worker, more are rejected with 503.
+    pub max_queued_jobs: usize,
+    /// Time finished jobs are kept for `GET /jobs/{id}`.
+    pub job_ttl: Duration,
+    /// Largest request body in bytes, larger ones are rejected with 413.
+    pub max_body_bytes: usize,
+}
+
+impl Default for ServiceOptions {
+    fn default() -> Self {
+        ServiceOptions {
+            http_workers: 4,
+            job_workers: 2,
+            max_queued_jobs: 16,
+            job_ttl: Duration::from_secs(3600),
+            max_body_bytes: 64 << 20,
+        }
+    }
+}
+
+struct Job {
+    status: JobStatus,
+    finished: Option<Instant>,
+}
+
+/// Status of the jobs by id, finished ones are evicted after `ttl`.
+struct JobStore {
+    jobs: HashMap<u64, Job>,
+    ttl: Duration,
+}
+
+impl JobStore {
+    fn new(ttl: Duration) -> JobStore {
+        JobStore {
+            jobs: HashMap::new(),
+            ttl,
+        }
+    }
+
+    fn set(&mut self, job_id: u64, status: JobStatus) {
+        let finished = match status {
+            JobStatus::Queued | JobStatus::Running => None,
+            JobStatus::Done { .. } | JobStatus::Failed { .. } => Some(Instant::now()),
+        };
+        self.jobs.insert(job_id, Job { status, finished });
+    }
+
+    fn get(&self, job_id: u64) -> Option<&JobStatus> {
+        self.jobs.get(&job_id).map(|job| &job.status)
+    }
+
+    fn remove(&mut self, job_id: u64) {
+        self.jobs.remove(&job_id);
+    }
+
+    fn evict_expired(&mut self, now: Instant) {
+        let ttl = self.ttl;
+        self.jobs.retain(|_, job| {
+            job.finished
+                .is_none_or(|finished| now.saturating_duration_since(finished) < ttl)
+        });
+    }
+}
+
+type Jobs = Arc<Mutex<JobStore>>;
+type JobQueue = Arc<Mutex<Receiver<(u64, CalibrationJobRequest)>>>;
+type RunJob = fn(&CalibrationJobRequest) -> Result<CalibrationJobResult, String>;
+
+/// Runs the queued jobs with `run` until the service is dropped. A job that panics fails and
+/// the worker goes on with the next one.
+fn job_worker(queue: JobQueue, jobs: Jobs, run: RunJob) {
+    loop {
+        // the lock is released before the job runs, the other workers keep taking jobs
+        let next = queue.lock().unwrap().recv();
+        let Ok((job_id, job_request)) = next else {
+            return;
+        };
+        jobs.lock().unwrap().set(job_id, JobStatus::Running);
+        let status = match std::panic::catch_unwind(|| run(&job_request)) {
+            Ok(Ok(result)) => JobStatus::Done { result },
+            Ok(Err(reason)) => JobStatus::Failed { reason },
+            Err(_) => JobStatus::Failed {
+                reason: "calibration panicked".to_string(),
+            },
+        };
+        jobs.lock().unwrap().set(job_id, status);
+    }
+}
+
+pub struct CalibrationService {
+    detector: TagDetector,
+    board: Board,
+    options: ServiceOptions,
+    jobs: Jobs,
+    queue: SyncSender<(u64, CalibrationJobRequest)>,
+    next_job_id: AtomicU64,
+}
+
+fn json_response<T: Serialize>(value: &T, status_code: u16) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(serde_json::to_string_pretty(value).unwrap())
+        .with_status_code(status_code)
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+fn error_response(reason: &str, status_code: u16) -> Response<std::io::Cursor<Vec<u8>>> {
+    json_response(&HashMap::from([("error", reason)]), status_code)
+}
+
+impl CalibrationService {
+    /// Starts the job workers, they stop when the service is dropped. Fails without job
+    /// workers, the jobs would be queued and never run.
+    pub fn new(
+        detector: TagDetector,
+        board: Board,
+        options: ServiceOptions,
+    ) -> Result<CalibrationService, String> {
+        CalibrationService::with_job_runner(detector, board, options, run_calibration_job)
+    }
+
+    fn with_job_runner(
+        detector: TagDetector,
+        board: Board,
+        options: ServiceOptions,
+        run: RunJob,
+    ) -> Result<CalibrationService, String> {
+        if options.job_workers == 0 {
+            return Err("the service needs at least one job worker".to_string());
+        }
+        let jobs = Arc::new(Mutex::new(JobStore::new(options.job_ttl)));
+        let (queue, receiver) = sync_channel(options.max_queued_jobs);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..options.job_workers {
+            let receiver = receiver.clone();
+            let jobs = jobs.clone();
+            std::thread::spawn(move || job_worker(receiver, jobs, run));
+        }
+        Ok(CalibrationService {
+            detector,
+            board,
+            options,
+            jobs,
+            queue,
+            next_job_id: AtomicU64::new(0),
+        })
+    }
+
+    /// Block and serve requests on `address`, e.g. "0.0.0.0:8080".
+    pub fn serve(&self, address: &str) -> Result<(), String> {
+        let server = Server::http(address).map_err(|e| e.to_string())?;
+        tracing::info!("Listening on {}", address);
+        self.serve_with(&server);
+        Ok(())
+    }
+
+    fn serve_with(&self, server: &Server) {
+        std::thread::scope(|scope| {
+            for _ in 0..self.options.http_workers.max(1) {
+                scope.spawn(|| {
+                    while let Ok(request) = server.recv() {
+                        self.handle(request);
+                    }
+                });
+            }
+        });
+    }
+
+    fn handle(&self, mut request: Request) {
+        let url = request.url().to_string();
+        let path = url.split('?').next().unwrap_or_default();
+        tracing::debug!("{} {}", request.method(), url);
+        let max_body_bytes = self.options.max_body_bytes;
+        let response = if request.body_length().unwrap_or(0) > max_body_bytes {
+            error_response("request body too large", 413)
+        } else {
+            let mut body = Vec::new();
+            // without a Content-Length the body is chunked, read at most one byte past the limit
+            match request
+                .as_reader()
+                .take(max_body_bytes as u64 + 1)
+                .read_to_end(&mut body)
+            {
+                Err(e) => error_response(&e.to_string(), 400),
+                Ok(len) if len > max_body_bytes => error_response("request body too large", 413),
+                Ok(_) => match (request.method(), path) {
+                    (Method::Post, "/detect") => self.detect(&body),
+                    (Method::Post, "/jobs") => self.submit(&body),
+                    (Method::Get, p) if p.starts_with("/jobs/") => {
+                        self.status(&p["/jobs/".len()..])
+                    }
+                    _ => error_response("not found", 404),
+                },
+            }
+        };
+        if let Err(e) = request.respond(response) {
+            tracing::error!("failed to respond: {}", e);
+        }
+    }
+
+    fn detect(&self, body: &[u8]) -> Response<std::io::Cursor<Vec<u8>>> {
+        match image::load_from_memory(body) {
+            Ok(img) => {
+                match image_to_option_feature_frame(&self.detector, &img, &self.board, 0, 0) {
+                    Some(frame_feature) => json_response(&frame_feature, 200),
+                    None => error_response("no corners detected", 422),
+                }
+            }
+            Err(e) => error_response(&e.to_string(), 400),
+        }
+    }
+
+    fn submit(&self, body: &[u8]) -> Response<std::io::Cursor<Vec<u8>>> {
+        let job_request: CalibrationJobRequest = match serde_json::from_slice(body) {
+            Ok(r) => r,
+            Err(e) => return error_response(&e.to_string(), 400),
+        };
+        let job_id = self.next_job_id.fetch_add(1, Ordering::SeqCst);
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            jobs.evict_expired(Instant::now());
+            jobs.set(job_id, JobStatus::Queued);
+        }
+        match self.queue.try_send((job_id, job_request)) {
+            Ok(()) => json_response(&HashMap::from([("job_id", job_id)]), 202),
+            Err(e) => {
+                self.jobs.lock().unwrap().remove(job_id);
+                match e {
+                    TrySendError::Full(_) => error_response("job queue is full", 503),
+                    TrySendError::Disconnected(_) => error_response("no job workers", 503),
+                }
+            }
+        }
+    }
+
+    fn status(&self, job_id: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+        let Ok(job_id) = job_id.parse::<u64>() else {
+            return error_response("invalid job id", 400);
+        };
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.evict_expired(Instant::now());
+        match jobs.get(job_id) {
+            Some(status) => json_response(status, 200),
+            None => error_response("job not found", 404),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use aprilgrid::TagFamily;
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    fn service(options: ServiceOptions) -> CalibrationService {
+        CalibrationService::new(
+            TagDetector::new(&TagFamily::T36H11, None),
+            test_util::board(),
+            options,
+        )
+        .unwrap()
+    }
+
+    fn wait_for_finished(service: &CalibrationService, job_id: u64) -> JobStatus {
+        for _ in 0..100 {
+            match service.jobs.lock().unwrap().get(job_id) {
+                Some(JobStatus::Queued | JobStatus::Running) | None => {}
+                Some(status) => return status.clone(),
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("job {} did not finish", job_id);
+    }
+
+    fn failed() -> JobStatus {
+        JobStatus::Failed {
+            reason: String::new(),
+        }
+    }
+
+    #[test]
+    fn finished_jobs_expire() {
+        let mut store = JobStore::new(Duration::from_secs(10));
+        store.set(0, JobStatus::Running);
+        store.set(1, failed());
+        store.set(2, failed());
+        let now = Instant::now();
+        store.jobs.get_mut(&2).unwrap().finished = now.checked_sub(Duration::from_secs(11));
+        store.evict_expired(now);
+        assert!(matches!(store.get(0), Some(JobStatus::Running)));
+        assert!(matches!(store.get(1), Some(JobStatus::Failed { .. })));
+        assert!(store.get(2).is_none());
+    }
+
+    #[test]
+    fn full_queue_rejected() {
+        // no job workers take the queued job
+        let (queue, _receiver) = sync_channel(1);
+        let options = ServiceOptions::default();
+        let service = CalibrationService {
+            detector: TagDetector::new(&TagFamily::T36H11, None),
+            board: test_util::board(),
+            jobs: Arc::new(Mutex::new(JobStore::new(options.job_ttl))),
+            options,
+            queue,
+            next_job_id: AtomicU64::new(0),
+        };
+        let body = br#"{"model": "eucm", "frames": []}"#;
+        assert_eq!(service.submit(body).status_code().0, 202);
+        assert_eq!(service.submit(body).status_code().0, 503);
+        let jobs = service.jobs.lock().unwrap();
+        assert!(matches!(jobs.get(0), Some(JobStatus::Queued)));
+        assert!(jobs.get(1).is_none());
+    }
+
+    #[test]
+    fn jobs_run_by_workers() {
+        let service = service(ServiceOptions::default());
+        let body = br#"{"model": "eucm", "frames": []}"#;
+        assert_eq!(service.submit(body).status_code().0, 202);
+        let JobStatus::Failed { reason } = wait_for_finished(&service, 0) else {
+            panic!("job did not fail");
+        };
+        assert!(reason.contains("two frames"), "{reason}");
+    }
+
+    #[test]
+    fn panicking_job_fails_and_worker_survives() {
+        let service = CalibrationService::with_job_runner(
+            TagDetector::new(&TagFamily::T36H11, None),
+            test_util::board(),
+            ServiceOptions {
+                job_workers: 1,
+                ..Default::default()
+            },
+            |request| {
+                if request.one_focal {
+                    panic!("covariance failed");
+                }
+                Err("done".to_string())
+            },
+        )
+        .unwrap();
+        let panicking = br#"{"model": "eucm", "frames": [], "one_focal": true}"#;
+        assert_eq!(service.submit(panicking).status_code().0, 202);
+        let JobStatus::Failed { reason } = wait_for_finished(&service, 0) else {
+            panic!("job did not fail");
+        };
+        assert!(reason.contains("panicked"), "{reason}");
+        // the only worker still runs the next job
+        let body = br#"{"model": "eucm", "frames": []}"#;
+        assert_eq!(service.submit(body).status_code().0, 202);
+        assert!(matches!(
+            wait_for_finished(&service, 1),
+            JobStatus::Failed { reason } if reason == "done"
+        ));
+    }
+
+    #[test]
+    fn no_job_workers_rejected() {
+        let service = CalibrationService::new(
+            TagDetector::new(&TagFamily::T36H11, None),
+            test_util::board(),
+            ServiceOptions {
+                job_workers: 0,
+                ..Default::default()
+            },
+        );
+        assert!(service.is_err());
+    }
+
+    fn status_line(address: std::net::SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        let response = String::from_utf8_lossy(&response);
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn large_body_rejected() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        let service = service(ServiceOptions {
+            max_body_bytes: 16,
+            ..Default::default()
+        });
+        std::thread::spawn(move || service.serve_with(&server));
+        let declared = status_line(
+            address,
+            "POST /detect HTTP/1.1\r\nContent-Length: 1000000\r\nConnection: close\r\n\r\n",
+        );
+        assert!(declared.contains("413"), "{declared}");
+        let chunked = status_line(
+            address,
+            "POST /jobs HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+             20\r\n0123456789abcdef0123456789abcdef\r\n0\r\n\r\n",
+        );
+        assert!(chunked.contains("413"), "{chunked}");
+        let small = status_line(address, "GET /jobs/7 HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(small.contains("404"), "{small}");
+    }
+}
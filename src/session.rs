@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use camera_intrinsic_model::*;
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+
+use crate::detected_points::FrameFeature;
+use crate::types::RvecTvec;
+
+/// Sessions with a reprojection rms larger than this times the median of all sessions are
+/// reported as bad.
+const BAD_SESSION_RATIO: f64 = 2.0;
+
+/// Detections of several sessions of the same rig `[session][cam][frame]` as one dataset. The
+/// frames are concatenated so every session keeps its own board poses while the intrinsics and
+/// extrinsics are shared. Also returns the frame indexes of every session.
+pub fn merge_sessions(
+    sessions: Vec<Vec<Vec<Option<FrameFeature>>>>,
+) -> (Vec<Vec<Option<FrameFeature>>>, Vec<Range<usize>>) {
+    let cam_num = sessions.iter().map(|s| s.len()).max().unwrap_or(0);
+    let mut merged = vec![Vec::new(); cam_num];
+    let mut ranges = Vec::new();
+    for session in sessions {
+        let begin = merged.first().map(|f: &Vec<_>| f.len()).unwrap_or(0);
+        let len = session.iter().map(|f| f.len()).max().unwrap_or(0);
+        let mut session_frames = session.into_iter();
+        for frames in merged.iter_mut() {
+            frames.extend(session_frames.next().unwrap_or_default());
+            // keep the frames of the cameras aligned
+            frames.resize(begin + len, None);
+        }
+        ranges.push(begin..begin + len);
+    }
+    (merged, ranges)
+}
+
+/// Reprojection errors of a camera in a session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionResidual {
+    pub session: usize,
+    pub cam_idx: usize,
+    pub num_frames: usize,
+    pub num_corners: usize,
+    pub rms: f64,
+    pub median: f64,
+}
+
+/// Reprojection errors of `cam_idx` in every session of `merge_sessions`, sessions much worse
+/// than the others are logged.
+pub fn session_residuals(
+    cam_idx: usize,
+    model: &GenericModel<f64>,
+    rtvecs: &HashMap<usize, RvecTvec>,
+    frame_feature_list: &[Option<FrameFeature>],
+    sessions: &[Range<usize>],
+) -> Vec<SessionResidual> {
+    let residuals: Vec<_> = sessions
+        .iter()
+        .enumerate()
+        .map(|(session, range)| {
+            let mut num_frames = 0;
+            let mut errors = Vec::new();
+            for i in range.clone() {
+                let (Some(rtvec), Some(Some(frame))) = (rtvecs.get(&i), frame_feature_list.get(i))
+                else {
+                    continue;
+                };
+                num_frames += 1;
+                let transform = rtvec.to_na_isometry3();
+                errors.extend(frame.features.values().map(|fp| {
+                    let p3 = transform * na::Point3::new(fp.p3d.x, fp.p3d.y, fp.p3d.z).cast();
+                    (model.project_one(&p3.coords)
+                        - na::Vector2::new(fp.p2d.x as f64, fp.p2d.y as f64))
+                    .norm()
+                }));
+            }
+            errors.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            SessionResidual {
+                session,
+                cam_idx,
+                num_frames,
+                num_corners: errors.len(),
+                rms: (errors.iter().map(|e| e * e).sum::<f64>() / errors.len().max(1) as f64)
+                    .sqrt(),
+                median: errors.get(errors.len() / 2).copied().unwrap_or(0.0),
+            }
+        })
+        .collect();
+    let mut rms: Vec<_> = residuals
+        .iter()
+        .filter(|r| r.num_corners > 0)
+        .map(|r| r.rms)
+        .collect();
+    rms.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    if let Some(&median_rms) = rms.get(rms.len() / 2) {
+        for r in &residuals {
+            if r.rms > median_rms * BAD_SESSION_RATIO {
+                tracing::warn!(
+                    "cam{} session {} reprojection rms {:.3} px is much larger than {:.3} px of the others",
+                    cam_idx,
+                    r.session,
+                    r.rms,
+                    median_rms
+                );
+            }
+        }
+    }
+    residuals
+}
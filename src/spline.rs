@@ -0,0 +1,303 @@
+//! Uniform cubic B-spline of the pose of a moving body for continuous-time calibration. The
+//! rotation `r_ref * exp(phi(t))` and the position `p(t)` interpolate the control points
+//! `[phi, p]`, the rotation vectors are relative to a reference rotation of the whole
+//! trajectory so they stay far from the wrap at pi for the motions of a calibration sequence.
+use nalgebra as na;
+
+/// Size of a control point, the rotation vector and the position.
+pub const KNOT_DIM: usize = 6;
+
+/// Below this squared angle the taylor series of `so3_exp` and `so3_right_jacobian` are used,
+/// the closed forms divide by the angle.
+const SMALL_ANGLE2: f64 = 1e-8;
+
+/// Basis of a segment at `u` in [0, 1) and its first and second derivatives by `u`.
+pub fn basis<T: na::RealField>(u: T) -> [[T; 4]; 3] {
+    let c = |v: f64| T::from_f64(v).unwrap();
+    let u2 = u.clone() * u.clone();
+    let u3 = u2.clone() * u.clone();
+    let v = c(1.0) - u.clone();
+    let sixth = c(1.0 / 6.0);
+    [
+        [
+            v.clone() * v.clone() * v.clone() * sixth.clone(),
+            (u3.clone() * c(3.0) - u2.clone() * c(6.0) + c(4.0)) * sixth.clone(),
+            (-u3.clone() * c(3.0) + u2.clone() * c(3.0) + u.clone() * c(3.0) + c(1.0))
+                * sixth.clone(),
+            u3 * sixth,
+        ],
+        [
+            -v.clone() * v.clone() * c(0.5),
+            u2.clone() * c(1.5) - u.clone() * c(2.0),
+            -u2.clone() * c(1.5) + u.clone() + c(0.5),
+            u2 * c(0.5),
+        ],
+        [
+            v,
+            u.clone() * c(3.0) - c(2.0),
+            -u.clone() * c(3.0) + c(1.0),
+            u,
+        ],
+    ]
+}
+
+pub fn skew<T: na::RealField>(v: &na::Vector3<T>) -> na::Matrix3<T> {
+    na::Matrix3::new(
+        T::zero(),
+        -v.z.clone(),
+        v.y.clone(),
+        v.z.clone(),
+        T::zero(),
+        -v.x.clone(),
+        -v.y.clone(),
+        v.x.clone(),
+        T::zero(),
+    )
+}
+
+/// `sin(a) / a`, `(1 - cos(a)) / a^2` and `(a - sin(a)) / a^3` of the angle of `phi`.
+fn so3_coefficients<T: na::RealField>(phi: &na::Vector3<T>) -> (T, T, T) {
+    let c = |v: f64| T::from_f64(v).unwrap();
+    let angle2 = phi.norm_squared();
+    if angle2 < c(SMALL_ANGLE2) {
+        (
+            c(1.0) - angle2.clone() / c(6.0),
+            c(0.5) - angle2.clone() / c(24.0),
+            c(1.0 / 6.0) - angle2 / c(120.0),
+        )
+    } else {
+        let angle = angle2.clone().sqrt();
+        let (sin, cos) = (angle.clone().sin(), angle.clone().cos());
+        (
+            sin.clone() / angle.clone(),
+            (c(1.0) - cos) / angle2.clone(),
+            (angle.clone() - sin) / (angle2 * angle),
+        )
+    }
+}
+
+/// Rotation matrix of the rotation vector `phi`, also for the duals of a zero angle.
+pub fn so3_exp<T: na::RealField>(phi: &na::Vector3<T>) -> na::Matrix3<T> {
+    let (a, b, _) = so3_coefficients(phi);
+    let k = skew(phi);
+    na::Matrix3::identity() + k.clone() * a + k.clone() * k * b
+}
+
+/// `exp(phi + d) = exp(phi) * exp(so3_right_jacobian(phi) * d)` for a small `d`, the angular
+/// velocity in the body frame of `exp(phi(t))` is `so3_right_jacobian(phi) * phi'`.
+pub fn so3_right_jacobian<T: na::RealField>(phi: &na::Vector3<T>) -> na::Matrix3<T> {
+    let (_, b, c) = so3_coefficients(phi);
+    let k = skew(phi);
+    na::Matrix3::identity() - k.clone() * b + k.clone() * k * c
+}
+
+/// Pose and motion of the body at a time of a segment.
+pub struct SplineState<T: na::RealField> {
+    /// `exp(phi)`, the rotation of the body is `r_ref * rotation`.
+    pub rotation: na::Matrix3<T>,
+    pub position: na::Vector3<T>,
+    /// In the body frame, rad/s.
+    pub angular_velocity: na::Vector3<T>,
+    /// In the world frame, m/s^2.
+    pub acceleration: na::Vector3<T>,
+}
+
+/// Evaluates the segment of the four control points `knots` at `u`, `knot_s` seconds apart.
+pub fn evaluate_segment<T: na::RealField>(
+    knots: &[na::DVector<T>],
+    u: T,
+    knot_s: f64,
+) -> SplineState<T> {
+    let [b, db, ddb] = basis(u);
+    let mut phi = na::Vector3::zeros();
+    let mut phi_d = na::Vector3::zeros();
+    let mut position = na::Vector3::zeros();
+    let mut acceleration = na::Vector3::zeros();
+    for (i, knot) in knots.iter().enumerate() {
+        let rotation_vector = knot.fixed_rows::<3>(0).clone_owned();
+        let knot_position = knot.fixed_rows::<3>(3).clone_owned();
+        phi += rotation_vector.clone() * b[i].clone();
+        phi_d += rotation_vector * db[i].clone();
+        position += knot_position.clone() * b[i].clone();
+        acceleration += knot_position * ddb[i].clone();
+    }
+    let inv_knot_s = T::from_f64(1.0 / knot_s).unwrap();
+    SplineState {
+        rotation: so3_exp(&phi),
+        angular_velocity: so3_right_jacobian(&phi) * phi_d * inv_knot_s.clone(),
+        position,
+        acceleration: acceleration * inv_knot_s.clone() * inv_knot_s,
+    }
+}
+
+/// Pose trajectory `T_world_body(t)` of control points `knot_ns` apart from `start_ns`.
+#[derive(Debug, Clone)]
+pub struct PoseSpline {
+    pub start_ns: i64,
+    pub knot_ns: i64,
+    pub r_ref: na::Rotation3<f64>,
+    pub knots: Vec<na::Vector6<f64>>,
+}
+
+/// Weight of the third differences of the control points in `PoseSpline::fit`, keeps the
+/// control points without poses around them in line with their neighbors without biasing the
+/// accelerations.
+const FIT_SMOOTHNESS: f64 = 1e-3;
+
+impl PoseSpline {
+    /// Spline covering `[start_ns, end_ns]`.
+    pub fn new(start_ns: i64, end_ns: i64, knot_ns: i64, r_ref: na::Rotation3<f64>) -> PoseSpline {
+        let segments = ((end_ns - start_ns) as f64 / knot_ns as f64)
+            .ceil()
+            .max(1.0) as usize;
+        PoseSpline {
+            start_ns,
+            knot_ns,
+            r_ref,
+            knots: vec![na::Vector6::zeros(); segments + 3],
+        }
+    }
+
+    pub fn end_ns(&self) -> i64 {
+        self.start_ns + (self.knots.len() as i64 - 3) * self.knot_ns
+    }
+
+    pub fn knot_s(&self) -> f64 {
+        self.knot_ns as f64 * 1e-9
+    }
+
+    /// Index of the first control point of the segment of `time_ns` and the start time of the
+    /// segment, `None` outside of the spline.
+    pub fn segment(&self, time_ns: i64) -> Option<(usize, i64)> {
+        if time_ns < self.start_ns || time_ns >= self.end_ns() {
+            return None;
+        }
+        let i = ((time_ns - self.start_ns) / self.knot_ns) as usize;
+        Some((i, self.start_ns + i as i64 * self.knot_ns))
+    }
+
+    /// Pose, angular velocity in the body frame and acceleration in the world frame.
+    pub fn evaluate(
+        &self,
+        time_ns: i64,
+    ) -> Option<(na::Isometry3<f64>, na::Vector3<f64>, na::Vector3<f64>)> {
+        let (i, segment_ns) = self.segment(time_ns)?;
+        let knots: Vec<_> = self.knots[i..i + 4]
+            .iter()
+            .map(|k| na::DVector::from_column_slice(k.as_slice()))
+            .collect();
+        let u = (time_ns - segment_ns) as f64 / self.knot_ns as f64;
+        let state = evaluate_segment(&knots, u, self.knot_s());
+        let rotation = self.r_ref * na::Rotation3::from_matrix(&state.rotation);
+        Some((
+            na::Isometry3::from_parts(state.position.into(), rotation.into()),
+            state.angular_velocity,
+            state.acceleration,
+        ))
+    }
+
+    /// Least squares fit of `T_world_body` poses in `[start_ns, end_ns]`, the reference rotation
+    /// is the one of the middle pose.
+    pub fn fit(
+        poses: &[(i64, na::Isometry3<f64>)],
+        start_ns: i64,
+        end_ns: i64,
+        knot_ns: i64,
+    ) -> Option<PoseSpline> {
+        let mut poses: Vec<_> = poses.to_vec();
+        poses.sort_by_key(|p| p.0);
+        let r_ref = poses.get(poses.len() / 2)?.1.rotation.to_rotation_matrix();
+        let mut spline = PoseSpline::new(start_ns, end_ns, knot_ns, r_ref);
+        let n = spline.knots.len();
+        let mut ata = na::DMatrix::<f64>::zeros(n, n);
+        let mut aty = na::DMatrix::<f64>::zeros(n, KNOT_DIM);
+        let mut fitted = 0;
+        for (time_ns, pose) in &poses {
+            let Some((i, segment_ns)) = spline.segment(*time_ns) else {
+                continue;
+            };
+            let [b, _, _] = basis((time_ns - segment_ns) as f64 / knot_ns as f64);
+            let phi = (r_ref.inverse() * pose.rotation.to_rotation_matrix()).scaled_axis();
+            let y = na::Vector6::new(
+                phi.x,
+                phi.y,
+                phi.z,
+                pose.translation.x,
+                pose.translation.y,
+                pose.translation.z,
+            );
+            for r in 0..4 {
+                for c in 0..4 {
+                    ata[(i + r, i + c)] += b[r] * b[c];
+                }
+                for d in 0..KNOT_DIM {
+                    aty[(i + r, d)] += b[r] * y[d];
+                }
+            }
+            fitted += 1;
+        }
+        if fitted < 4 {
+            return None;
+        }
+        for i in 0..n - 3 {
+            let d = [-1.0, 3.0, -3.0, 1.0];
+            for r in 0..4 {
+                for c in 0..4 {
+                    ata[(i + r, i + c)] += FIT_SMOOTHNESS * d[r] * d[c];
+                }
+            }
+        }
+        let x = ata.cholesky()?.solve(&aty);
+        for (i, knot) in spline.knots.iter_mut().enumerate() {
+            *knot = na::Vector6::from_iterator(x.row(i).iter().cloned());
+        }
+        Some(spline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn so3_matches_nalgebra() {
+        for phi in [
+            na::Vector3::new(0.3, -0.2, 0.5),
+            na::Vector3::new(1e-6, 0.0, -2e-6),
+        ] {
+            let expected = na::Rotation3::from_scaled_axis(phi);
+            assert!((so3_exp(&phi) - expected.matrix()).norm() < 1e-12);
+            // exp(phi + d) = exp(phi) exp(J_r d)
+            let d = na::Vector3::new(1e-6, -2e-6, 1.5e-6);
+            let lhs = so3_exp(&(phi + d));
+            let rhs = so3_exp(&phi) * so3_exp(&(so3_right_jacobian(&phi) * d));
+            assert!((lhs - rhs).norm() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn fit_and_derivatives() {
+        // constant angular velocity around z and constant acceleration along x
+        let (w, a) = (0.8, 0.5);
+        let knot_ns = 50_000_000;
+        let poses: Vec<_> = (0..=200)
+            .map(|i| {
+                let time_ns = i * 10_000_000;
+                let t = time_ns as f64 * 1e-9;
+                let pose = na::Isometry3::new(
+                    na::Vector3::new(0.5 * a * t * t, 0.1, 0.0),
+                    na::Vector3::new(0.0, 0.0, w * t - 0.8),
+                );
+                (time_ns, pose)
+            })
+            .collect();
+        let spline = PoseSpline::fit(&poses, 0, 2_000_000_000, knot_ns).unwrap();
+        for (time_ns, pose) in poses.iter().skip(10).step_by(17).take(10) {
+            let (fitted, angular_velocity, acceleration) = spline.evaluate(*time_ns).unwrap();
+            assert!((fitted.translation.vector - pose.translation.vector).norm() < 1e-4);
+            assert!(fitted.rotation.angle_to(&pose.rotation) < 1e-4);
+            assert!((angular_velocity - na::Vector3::new(0.0, 0.0, w)).norm() < 1e-3);
+            assert!((acceleration - na::Vector3::new(a, 0.0, 0.0)).norm() < 1e-2);
+        }
+    }
+}
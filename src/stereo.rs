@@ -0,0 +1,367 @@
+use camera_intrinsic_model::GenericModel;
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::detected_points::FrameFeature;
+use crate::imu::align_vectors;
+use crate::lut::UnprojectionLut;
+use crate::observer::PipelineObserver;
+use crate::types::{CalibParams, RvecTvec};
+use crate::undistort::{undistort_points_with_lut, Undistorter};
+use crate::util::{
+    calib_all_camera_with_extrinsics, init_and_calibrate_one_camera_with_trials,
+    init_camera_extrinsic,
+};
+
+/// Rectified stereo pair, both cameras share the same pinhole camera and orientation.
+/// `r0` and `r1` rotate the original camera frames to the rectified ones.
+pub struct StereoRectification {
+    pub r0: na::Rotation3<f64>,
+    pub r1: na::Rotation3<f64>,
+    pub camera_matrix: na::Matrix3<f64>,
+    /// Translation of `T_1_0` in the rectified frames, along x or y.
+    pub baseline: na::Vector3<f64>,
+    pub w_h: (u32, u32),
+}
+
+impl StereoRectification {
+    /// `t_1_0` transforms points from cam0 to cam1.
+    pub fn new(
+        model0: &GenericModel<f64>,
+        model1: &GenericModel<f64>,
+        t_1_0: &na::Isometry3<f64>,
+        w_h: Option<(u32, u32)>,
+    ) -> StereoRectification {
+        let w_h = w_h.unwrap_or((model0.width() as u32, model0.height() as u32));
+        let tvec = t_1_0.translation.vector;
+        let (r0, r1, camera_matrix) = camera_intrinsic_model::stereo_rectify(
+            model0,
+            model1,
+            &t_1_0.rotation.scaled_axis(),
+            &tvec,
+            Some(w_h),
+        );
+        StereoRectification {
+            r0,
+            r1,
+            camera_matrix,
+            baseline: r1 * tvec,
+            w_h,
+        }
+    }
+
+    pub fn is_vertical(&self) -> bool {
+        self.baseline.y.abs() > self.baseline.x.abs()
+    }
+
+    /// OpenCV `P1` and `P2`.
+    pub fn projection_matrices(&self) -> (na::Matrix3x4<f64>, na::Matrix3x4<f64>) {
+        let mut p0 = na::Matrix3x4::zeros();
+        p0.fixed_view_mut::<3, 3>(0, 0)
+            .copy_from(&self.camera_matrix);
+        let mut p1 = p0;
+        let f = self.camera_matrix[(0, 0)];
+        if self.is_vertical() {
+            p1[(1, 3)] = self.baseline.y * f;
+        } else {
+            p1[(0, 3)] = self.baseline.x * f;
+        }
+        (p0, p1)
+    }
+
+    /// OpenCV `Q`, reprojects (x, y, disparity, 1) to 3D.
+    pub fn disparity_to_depth(&self) -> na::Matrix4<f64> {
+        let k = &self.camera_matrix;
+        let b = if self.is_vertical() {
+            self.baseline.y
+        } else {
+            self.baseline.x
+        };
+        na::Matrix4::new(
+            1.0,
+            0.0,
+            0.0,
+            -k[(0, 2)],
+            0.0,
+            1.0,
+            0.0,
+            -k[(1, 2)],
+            0.0,
+            0.0,
+            0.0,
+            k[(0, 0)],
+            0.0,
+            0.0,
+            -1.0 / b,
+            0.0,
+        )
+    }
+
+    /// Remap tables of both cameras.
+    pub fn undistorters(
+        &self,
+        model0: &GenericModel<f64>,
+        model1: &GenericModel<f64>,
+    ) -> (Undistorter, Undistorter) {
+        (
+            Undistorter::new(model0, &self.camera_matrix, self.w_h, Some(self.r0)),
+            Undistorter::new(model1, &self.camera_matrix, self.w_h, Some(self.r1)),
+        )
+    }
+}
+
+/// Epipolar distances in px of the corners seen by `cam0` and `cam1`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EpipolarStats {
+    pub cam0: usize,
+    pub cam1: usize,
+    pub count: usize,
+    pub mean_error: f64,
+    pub median_error: f64,
+    pub max_error: f64,
+}
+
+fn mean_focal(model: &GenericModel<f64>) -> f64 {
+    let params = model.params();
+    (params[0] + params[1]) / 2.0
+}
+
+/// Symmetric epipolar distance of each corner detected by both cameras in the same frame, the
+/// average of the distances of both points to the epipolar lines of each other, in px.
+/// Unlike the reprojection error it grows with errors of the extrinsics. The corners are
+/// unprojected with the tables of `luts` if given.
+pub fn epipolar_errors(
+    model0: &GenericModel<f64>,
+    model1: &GenericModel<f64>,
+    luts: [Option<&UnprojectionLut>; 2],
+    t_1_0: &na::Isometry3<f64>,
+    frames0: &[Option<FrameFeature>],
+    frames1: &[Option<FrameFeature>],
+) -> Vec<f64> {
+    let essential = t_1_0.translation.vector.cross_matrix() * t_1_0.rotation.to_rotation_matrix();
+    let (f0, f1) = (mean_focal(model0), mean_focal(model1));
+    frames0
+        .iter()
+        .zip(frames1)
+        .filter_map(|(frame0, frame1)| Some((frame0.as_ref()?, frame1.as_ref()?)))
+        .flat_map(|(frame0, frame1)| {
+            let (p0, p1): (Vec<_>, Vec<_>) = frame0
+                .features
+                .iter()
+                .filter_map(|(id, fp0)| {
+                    let fp1 = frame1.features.get(id)?;
+                    Some((fp0.p2d.as_dvec2(), fp1.p2d.as_dvec2()))
+                })
+                .map(|(p0, p1)| (na::Vector2::new(p0.x, p0.y), na::Vector2::new(p1.x, p1.y)))
+                .unzip();
+            let n0 = undistort_points_with_lut(model0, luts[0], &p0, None);
+            let n1 = undistort_points_with_lut(model1, luts[1], &p1, None);
+            n0.into_iter()
+                .zip(n1)
+                .filter_map(|(n0, n1)| Some((n0?.push(1.0), n1?.push(1.0))))
+                .collect::<Vec<_>>()
+        })
+        .map(|(x0, x1)| {
+            let line1 = essential * x0;
+            let line0 = essential.transpose() * x1;
+            let e = x1.dot(&line1).abs();
+            let d1 = e / line1.xy().norm() * f1;
+            let d0 = e / line0.xy().norm() * f0;
+            (d0 + d1) / 2.0
+        })
+        .collect()
+}
+
+impl EpipolarStats {
+    pub fn new(cam0: usize, cam1: usize, errors: &[f64]) -> EpipolarStats {
+        let mut sorted = errors.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let count = sorted.len();
+        EpipolarStats {
+            cam0,
+            cam1,
+            count,
+            mean_error: sorted.iter().sum::<f64>() / count.max(1) as f64,
+            median_error: sorted.get(count / 2).copied().unwrap_or(0.0),
+            max_error: sorted.last().copied().unwrap_or(0.0),
+        }
+    }
+}
+
+/// Board frames are binned by their distance to cam0 every this many m.
+pub const DEPTH_BIN_SIZE: f64 = 0.5;
+
+/// Depth errors of the corners triangulated by a stereo pair in a range of board distances.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepthErrorBin {
+    pub min_distance: f64,
+    pub max_distance: f64,
+    pub num_frames: usize,
+    pub num_corners: usize,
+    /// Distance along the ray of cam0 from the triangulated corner to the board fitted to all
+    /// triangulated corners of the frame, in m.
+    pub depth_rms: f64,
+    /// Of the depth errors over the corner distances.
+    pub relative_depth_rms: f64,
+    /// Mean of the triangulated board sizes over the configured one minus 1, a wrong baseline
+    /// scales the triangulated boards.
+    pub board_scale_error: f64,
+}
+
+/// Depth errors of `cam0` and `cam1`, from near to far.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StereoDepthStats {
+    pub cam0: usize,
+    pub cam1: usize,
+    pub bins: Vec<DepthErrorBin>,
+}
+
+/// Midpoint of the closest points of the two rays, in cam0.
+fn triangulate(
+    ray0: &na::Vector3<f64>,
+    ray1: &na::Vector3<f64>,
+    t_0_1: &na::Isometry3<f64>,
+) -> Option<na::Vector3<f64>> {
+    let (d0, d1, c1) = (ray0, t_0_1.rotation * ray1, t_0_1.translation.vector);
+    // [d0 -d1] [a b]^T = c1 in the least squares sense
+    let a = na::Matrix3x2::from_columns(&[*d0, -d1]);
+    let ab = (a.transpose() * a).try_inverse()? * a.transpose() * c1;
+    (ab[0] > 0.0 && ab[1] > 0.0).then(|| (d0 * ab[0] + c1 + d1 * ab[1]) / 2.0)
+}
+
+/// Triangulates the corners seen by both cameras in the same frame with the calibrated
+/// intrinsics and `t_1_0`, fits the board to them and measures the depth errors. Unlike the
+/// reprojection error it shows the accuracy of the depth from the rig end to end.
+pub fn stereo_depth_errors(
+    cam0: usize,
+    cam1: usize,
+    model0: &GenericModel<f64>,
+    model1: &GenericModel<f64>,
+    t_1_0: &na::Isometry3<f64>,
+    frames0: &[Option<FrameFeature>],
+    frames1: &[Option<FrameFeature>],
+) -> StereoDepthStats {
+    let t_0_1 = t_1_0.inverse();
+    // bin -> (board scale errors, errors, relative errors)
+    let mut bins: HashMap<_, (Vec<_>, Vec<_>, Vec<_>)> = HashMap::new();
+    for (frame0, frame1) in frames0
+        .iter()
+        .zip(frames1)
+        .filter_map(|(frame0, frame1)| Some((frame0.as_ref()?, frame1.as_ref()?)))
+    {
+        let pairs: Vec<_> = frame0
+            .features
+            .iter()
+            .filter_map(|(id, fp0)| {
+                let fp1 = frame1.features.get(id)?;
+                let (p0, p1) = (fp0.p2d.as_dvec2(), fp1.p2d.as_dvec2());
+                let ray0 = model0.unproject_one(&na::Vector2::new(p0.x, p0.y));
+                let ray1 = model1.unproject_one(&na::Vector2::new(p1.x, p1.y));
+                let p3d = fp0.p3d.as_dvec3();
+                Some((
+                    na::Vector3::new(p3d.x, p3d.y, p3d.z),
+                    triangulate(&ray0, &ray1, &t_0_1)?,
+                ))
+            })
+            .collect();
+        if pairs.len() < 4 {
+            continue;
+        }
+        let (r, t) = align_vectors(&pairs);
+        let n = pairs.len() as f64;
+        let mean_board = pairs.iter().map(|p| p.0).sum::<na::Vector3<f64>>() / n;
+        let mean_triangulated = pairs.iter().map(|p| p.1).sum::<na::Vector3<f64>>() / n;
+        let (dot, norm2) = pairs.iter().fold((0.0, 0.0), |(dot, norm2), (b, p)| {
+            let b = r * (b - mean_board);
+            (
+                dot + b.dot(&(p - mean_triangulated)),
+                norm2 + b.norm_squared(),
+            )
+        });
+        let bin = bins
+            .entry((mean_triangulated.norm() / DEPTH_BIN_SIZE) as usize)
+            .or_default();
+        bin.0.push(dot / norm2 - 1.0);
+        for (board_point, triangulated) in &pairs {
+            let fitted = r * board_point + t;
+            let distance = triangulated.norm();
+            let error = (triangulated - fitted).dot(&(triangulated / distance));
+            bin.1.push(error);
+            bin.2.push(error / distance);
+        }
+    }
+    let rms = |v: &[f64]| (v.iter().map(|e| e * e).sum::<f64>() / v.len() as f64).sqrt();
+    let mut bins: Vec<_> = bins
+        .into_iter()
+        .map(
+            |(i, (scale_errors, errors, relative_errors))| DepthErrorBin {
+                min_distance: i as f64 * DEPTH_BIN_SIZE,
+                max_distance: (i + 1) as f64 * DEPTH_BIN_SIZE,
+                num_frames: scale_errors.len(),
+                num_corners: errors.len(),
+                depth_rms: rms(&errors),
+                relative_depth_rms: rms(&relative_errors),
+                board_scale_error: scale_errors.iter().sum::<f64>() / scale_errors.len() as f64,
+            },
+        )
+        .collect();
+    bins.sort_by(|a, b| a.min_distance.partial_cmp(&b.min_distance).unwrap());
+    StereoDepthStats { cam0, cam1, bins }
+}
+
+/// Result of `calibrate_stereo`.
+pub struct StereoCalibration {
+    pub model0: GenericModel<f64>,
+    pub model1: GenericModel<f64>,
+    /// Transforms points from cam0 to cam1.
+    pub t_1_0: na::Isometry3<f64>,
+    /// `T_0_b` of the frames, transforms board points to cam0.
+    pub board_rtvecs: HashMap<usize, RvecTvec>,
+}
+
+/// `frames0[i]` and `frames1[i]` see the same board at the same time. Each camera is calibrated
+/// alone first, then both intrinsics and `T_1_0` are refined jointly with shared board poses.
+/// `None` if any camera fails or the cameras never see the board at the same time.
+pub fn calibrate_stereo(
+    frames0: &[Option<FrameFeature>],
+    frames1: &[Option<FrameFeature>],
+    target_model: &GenericModel<f64>,
+    calib_params: &CalibParams,
+    observer: &dyn PipelineObserver,
+    max_trials: usize,
+) -> Option<StereoCalibration> {
+    let frames = [frames0.to_vec(), frames1.to_vec()];
+    let (models, cam_rtvecs): (Vec<_>, Vec<_>) = (0..2)
+        .map(|cam_idx| {
+            init_and_calibrate_one_camera_with_trials(
+                cam_idx,
+                &frames,
+                target_model,
+                observer,
+                calib_params,
+                max_trials,
+            )
+        })
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+    let t_i_0_init = init_camera_extrinsic(&cam_rtvecs)?;
+    let (models, t_i_0, board_rtvecs) = calib_all_camera_with_extrinsics(
+        &models,
+        &t_i_0_init,
+        &cam_rtvecs,
+        &frames,
+        calib_params.one_focal || calib_params.fixed_focal.is_some(),
+        calib_params.disabled_distortion_num,
+        calib_params.fixed_focal.is_some(),
+        calib_params.plateau,
+        observer,
+    )?;
+    Some(StereoCalibration {
+        model0: models[0],
+        model1: models[1],
+        t_1_0: t_i_0[1].to_na_isometry3(),
+        board_rtvecs,
+    })
+}
@@ -0,0 +1,87 @@
+use camera_intrinsic_model::GenericModel;
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::detected_points::FrameFeature;
+use crate::lut::UnprojectionLut;
+use crate::undistort::undistort_points_with_lut;
+
+/// Corners with less points aren't used as a line.
+pub const MIN_LINE_POINTS: usize = 4;
+
+/// Plumb-line check, rows and columns of the board should be straight after undistortion.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StraightnessStats {
+    pub num_lines: usize,
+    /// Of the rms distances of the points to their fitted lines, in px.
+    pub mean_error: f64,
+    pub median_error: f64,
+    pub max_error: f64,
+}
+
+impl StraightnessStats {
+    pub fn new(errors: &[f64]) -> StraightnessStats {
+        let mut sorted = errors.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let num_lines = sorted.len();
+        StraightnessStats {
+            num_lines,
+            mean_error: sorted.iter().sum::<f64>() / num_lines.max(1) as f64,
+            median_error: sorted.get(num_lines / 2).copied().unwrap_or(0.0),
+            max_error: sorted.last().copied().unwrap_or(0.0),
+        }
+    }
+}
+
+/// Pixels of the corners on the same row or column of the board.
+pub fn board_lines(frame: &FrameFeature) -> Vec<Vec<na::Vector2<f64>>> {
+    let mut rows: HashMap<u32, Vec<_>> = HashMap::new();
+    let mut cols: HashMap<u32, Vec<_>> = HashMap::new();
+    for fp in frame.features.values() {
+        let p = na::Vector2::new(fp.p2d.x as f64, fp.p2d.y as f64);
+        rows.entry(fp.p3d.y.to_bits()).or_default().push(p);
+        cols.entry(fp.p3d.x.to_bits()).or_default().push(p);
+    }
+    rows.into_values()
+        .chain(cols.into_values())
+        .filter(|line| line.len() >= MIN_LINE_POINTS)
+        .collect()
+}
+
+/// Rms distance of the points to their total least squares line.
+fn line_fit_rms(points: &[na::Vector2<f64>]) -> f64 {
+    let n = points.len() as f64;
+    let mean = points.iter().sum::<na::Vector2<f64>>() / n;
+    let cov = points
+        .iter()
+        .map(|p| (p - mean) * (p - mean).transpose())
+        .sum::<na::Matrix2<f64>>()
+        / n;
+    // the smallest eigenvalue is the mean squared distance along the normal
+    cov.symmetric_eigenvalues().min().max(0.0).sqrt()
+}
+
+/// Straightness error of each board line, in px of a pinhole camera with the same focal length.
+/// Model independent, the lines bend if the distortion isn't fully removed. The corners are
+/// unprojected with `lut` if given.
+pub fn straightness_errors(
+    model: &GenericModel<f64>,
+    lut: Option<&UnprojectionLut>,
+    frames: &[Option<FrameFeature>],
+) -> Vec<f64> {
+    let params = model.params();
+    let focal = (params[0] + params[1]) / 2.0;
+    frames
+        .iter()
+        .flatten()
+        .flat_map(board_lines)
+        .filter_map(|line| {
+            let normalized: Option<Vec<_>> = undistort_points_with_lut(model, lut, &line, None)
+                .into_iter()
+                .collect();
+            let normalized = normalized?;
+            Some(line_fit_rms(&normalized) * focal)
+        })
+        .collect()
+}
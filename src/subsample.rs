@@ -0,0 +1,296 @@
+use camera_intrinsic_model::*;
+use nalgebra as na;
+
+use crate::detected_points::FrameFeature;
+use crate::observer::NoopObserver;
+use crate::types::CalibParams;
+use crate::util::{
+    frame_intrinsics_information, init_and_calibrate_one_camera_with_trials, solve_board_pose,
+};
+
+/// Cells of the coverage grid per image side, a cell is a bit of a `u64`.
+const GRID: usize = 8;
+/// Frames picked by coverage the intrinsics are first calibrated with to predict the
+/// information of every frame.
+const SEED_FRAMES: usize = 20;
+
+/// Board center and size in the image, and the direction and amount of the foreshortening of
+/// the board as a tilt vector, all normalized to about [0, 1]. Pose diversity is measured on
+/// this, so frames can be compared before the camera is calibrated.
+pub(crate) fn frame_descriptor(frame_feature: &FrameFeature) -> Option<na::Vector5<f64>> {
+    let n = frame_feature.features.len() as f64;
+    if n < 3.0 {
+        return None;
+    }
+    let (mut p2d_mean, mut p3d_mean) = (na::Vector2::zeros(), na::Vector2::zeros());
+    for fp in frame_feature.features.values() {
+        p2d_mean += na::Vector2::new(fp.p2d.x as f64, fp.p2d.y as f64) / n;
+        p3d_mean += na::Vector2::new(fp.p3d.x as f64, fp.p3d.y as f64) / n;
+    }
+    // affine from the board plane to the image, p2d = a * p3d + b
+    let (mut pq, mut qq) = (na::Matrix2::zeros(), na::Matrix2::zeros());
+    for fp in frame_feature.features.values() {
+        let p = na::Vector2::new(fp.p2d.x as f64, fp.p2d.y as f64) - p2d_mean;
+        let q = na::Vector2::new(fp.p3d.x as f64, fp.p3d.y as f64) - p3d_mean;
+        pq += p * q.transpose();
+        qq += q * q.transpose();
+    }
+    let a = pq * qq.try_inverse()?;
+    let svd = a.svd(true, false);
+    let (s_max, s_min) = (svd.singular_values.max(), svd.singular_values.min());
+    if s_max <= 0.0 {
+        return None;
+    }
+    let tilt = (s_min / s_max).acos() / std::f64::consts::FRAC_PI_2;
+    // the image direction the board is compressed in, the same for opposite directions
+    let u = svd.u?;
+    let min_idx = svd.singular_values.imin();
+    let angle = 2.0 * u[(1, min_idx)].atan2(u[(0, min_idx)]);
+    let (w, h) = (
+        frame_feature.img_w_h.0 as f64,
+        frame_feature.img_w_h.1 as f64,
+    );
+    let diagonal = (w * w + h * h).sqrt();
+    Some(na::Vector5::new(
+        p2d_mean.x / w,
+        p2d_mean.y / h,
+        a.determinant().abs().sqrt() / diagonal,
+        tilt * angle.cos(),
+        tilt * angle.sin(),
+    ))
+}
+
+/// The `GRID` x `GRID` cells of the image with a corner of the frame.
+fn frame_cells(frame_feature: &FrameFeature) -> u64 {
+    let (w, h) = (
+        frame_feature.img_w_h.0 as f32,
+        frame_feature.img_w_h.1 as f32,
+    );
+    frame_feature.features.values().fold(0, |cells, fp| {
+        let c = ((fp.p2d.x / w * GRID as f32).max(0.0) as usize).min(GRID - 1);
+        let r = ((fp.p2d.y / h * GRID as f32).max(0.0) as usize).min(GRID - 1);
+        cells | 1 << (r * GRID + c)
+    })
+}
+
+/// Keeps the `max_frames` most informative frames of `cams_frames` `[cam][frame]` for a bounded
+/// solve time on long recordings, and returns the number of frames removed. Frames are picked
+/// greedily, every pick maximizes the image cells newly covered by corners plus the distance of
+/// the board appearance to the frames already picked, starting from the frame with the most
+/// corners. Every camera loses the same frames so the rig keeps its shared board poses.
+pub fn select_informative_frames(
+    cams_frames: &mut [Vec<Option<FrameFeature>>],
+    max_frames: usize,
+) -> usize {
+    let len = cams_frames.iter().map(|f| f.len()).max().unwrap_or(0);
+    // [frame][cam]
+    let candidates: Vec<_> = (0..len)
+        .filter_map(|i| {
+            let cams: Vec<_> = cams_frames
+                .iter()
+                .map(|frames| {
+                    let f = frames.get(i)?.as_ref()?;
+                    Some((frame_cells(f), frame_descriptor(f)?))
+                })
+                .collect();
+            cams.iter().any(|c| c.is_some()).then_some((i, cams))
+        })
+        .collect();
+    if candidates.len() <= max_frames {
+        return 0;
+    }
+    let corner_num = |i: usize| -> usize {
+        cams_frames
+            .iter()
+            .filter_map(|frames| frames.get(i)?.as_ref())
+            .map(|f| f.features.len())
+            .sum()
+    };
+    let mut covered = vec![0u64; cams_frames.len()];
+    // distance of each candidate to the closest picked frame, capped at 1 when they share no camera
+    let mut min_distances = vec![1.0f64; candidates.len()];
+    let mut picked = vec![false; candidates.len()];
+    let mut next = (0..candidates.len())
+        .max_by_key(|&c| corner_num(candidates[c].0))
+        .unwrap();
+    for _ in 0..max_frames {
+        picked[next] = true;
+        for (cam_idx, cam) in candidates[next].1.iter().enumerate() {
+            if let Some((cells, _)) = cam {
+                covered[cam_idx] |= cells;
+            }
+        }
+        let next_cams = &candidates[next].1;
+        for (c, (_, cams)) in candidates.iter().enumerate() {
+            let distance = cams
+                .iter()
+                .zip(next_cams)
+                .filter_map(|(a, b)| Some((a.as_ref()?.1 - b.as_ref()?.1).norm()))
+                .reduce(f64::max);
+            if let Some(distance) = distance {
+                min_distances[c] = min_distances[c].min(distance);
+            }
+        }
+        let score = |c: usize| {
+            let new_cells: u32 = candidates[c]
+                .1
+                .iter()
+                .zip(&covered)
+                .filter_map(|(cam, covered)| Some((cam.as_ref()?.0 & !covered).count_ones()))
+                .sum();
+            new_cells as f64 / (GRID * GRID) as f64 + min_distances[c]
+        };
+        let Some(best) = (0..candidates.len())
+            .filter(|&c| !picked[c])
+            .max_by(|&a, &b| score(a).total_cmp(&score(b)))
+        else {
+            break;
+        };
+        next = best;
+    }
+    let mut removed = 0;
+    for (c, (i, _)) in candidates.iter().enumerate() {
+        if picked[c] {
+            continue;
+        }
+        for frames in cams_frames.iter_mut() {
+            if let Some(frame) = frames.get_mut(*i) {
+                *frame = None;
+            }
+        }
+        removed += 1;
+    }
+    removed
+}
+
+/// Log determinant of a symmetric positive definite matrix, `None` if it isn't.
+fn log_det(m: &na::DMatrix<f64>) -> Option<f64> {
+    let l = m.clone().cholesky()?.unpack();
+    Some(2.0 * l.diagonal().iter().map(|d| d.ln()).sum::<f64>())
+}
+
+/// `select_informative_frames` by the predicted covariance of the intrinsics instead of the
+/// appearance of the board. The intrinsics are calibrated with a seed of `SEED_FRAMES` frames
+/// picked by coverage, the board pose of every frame is solved with them, and frames are picked
+/// greedily, every pick maximizing the decrease of the log determinant of the covariance, the
+/// sum over the cameras of `log det(I + I_f) - log det(I)` with `I` the information of the
+/// frames already picked and `I_f` the one of the frame. Falls back to
+/// `select_informative_frames` if the seed doesn't calibrate.
+pub fn select_frames_by_information_gain(
+    cams_frames: &mut [Vec<Option<FrameFeature>>],
+    max_frames: usize,
+    target_model: &GenericModel<f64>,
+    calib_params: &CalibParams,
+) -> usize {
+    let len = cams_frames.iter().map(|f| f.len()).max().unwrap_or(0);
+    let candidates: Vec<_> = (0..len)
+        .filter(|&i| {
+            cams_frames
+                .iter()
+                .any(|frames| matches!(frames.get(i), Some(Some(_))))
+        })
+        .collect();
+    if candidates.len() <= max_frames {
+        return 0;
+    }
+    let mut seed = cams_frames.to_vec();
+    select_informative_frames(&mut seed, SEED_FRAMES.min(max_frames));
+    let xy_same_focal = calib_params.one_focal || calib_params.fixed_focal.is_some();
+    let mut models = Vec::new();
+    for cam_idx in 0..cams_frames.len() {
+        // only cam0 has the fixed focal, as in the calibration
+        let cam_calib_params = CalibParams {
+            fixed_focal: if cam_idx == 0 {
+                calib_params.fixed_focal
+            } else {
+                None
+            },
+            disabled_distortion_num: calib_params.disabled_distortion_num,
+            one_focal: calib_params.one_focal,
+            plateau: calib_params.plateau,
+        };
+        let Some((model, _)) = init_and_calibrate_one_camera_with_trials(
+            cam_idx,
+            &seed,
+            target_model,
+            &NoopObserver,
+            &cam_calib_params,
+            3,
+        ) else {
+            tracing::warn!(
+                "cam{} seed frames didn't calibrate, select frames by coverage",
+                cam_idx
+            );
+            return select_informative_frames(cams_frames, max_frames);
+        };
+        models.push(model);
+    }
+    // [candidate][cam]
+    let informations: Vec<Vec<_>> = candidates
+        .iter()
+        .map(|&i| {
+            cams_frames
+                .iter()
+                .zip(&models)
+                .map(|(frames, model)| {
+                    let f = frames.get(i)?.as_ref()?;
+                    let rtvec = solve_board_pose(model, f)?;
+                    Some(frame_intrinsics_information(model, &rtvec, f, xy_same_focal)?.0)
+                })
+                .collect()
+        })
+        .collect();
+    // a small prior keeps the information of the first picks invertible
+    let mut picked_informations: Vec<_> = models
+        .iter()
+        .enumerate()
+        .map(|(cam_idx, model)| {
+            let params_len = model.params().len() - xy_same_focal as usize;
+            let max_diagonal = informations
+                .iter()
+                .filter_map(|cams| cams[cam_idx].as_ref())
+                .map(|info| info.diagonal().max())
+                .fold(1.0, f64::max);
+            na::DMatrix::<f64>::identity(params_len, params_len) * max_diagonal * 1e-9
+        })
+        .collect();
+    let mut picked = vec![false; candidates.len()];
+    for _ in 0..max_frames {
+        let picked_log_dets: Vec<_> = picked_informations.iter().map(log_det).collect();
+        let gain = |c: usize| {
+            informations[c]
+                .iter()
+                .zip(&picked_informations)
+                .zip(&picked_log_dets)
+                .filter_map(|((info, picked_info), picked_log_det)| {
+                    Some(log_det(&(picked_info + info.as_ref()?))? - (*picked_log_det)?)
+                })
+                .sum::<f64>()
+        };
+        let Some(best) = (0..candidates.len())
+            .filter(|&c| !picked[c])
+            .max_by(|&a, &b| gain(a).total_cmp(&gain(b)))
+        else {
+            break;
+        };
+        picked[best] = true;
+        for (info, picked_info) in informations[best].iter().zip(&mut picked_informations) {
+            if let Some(info) = info {
+                *picked_info += info;
+            }
+        }
+    }
+    let mut removed = 0;
+    for (c, &i) in candidates.iter().enumerate() {
+        if picked[c] {
+            continue;
+        }
+        for frames in cams_frames.iter_mut() {
+            if let Some(frame) = frames.get_mut(i) {
+                *frame = None;
+            }
+        }
+        removed += 1;
+    }
+    removed
+}
@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+use tiny_solver::loss_functions::HuberLoss;
+
+use crate::detected_points::FrameFeature;
+use crate::observer::PipelineObserver;
+use crate::optimization::factors::TelecentricReprojectionFactor;
+use crate::optimization::{rvec_name, tvec_name};
+use crate::types::{RvecTvec, ToRvecTvec};
+use crate::util::optimize_with_observer;
+
+/// Corners of a frame below which its initial pose isn't solved.
+const MIN_FRAME_CORNERS: usize = 6;
+/// Frames tilted less than this in rad are left out, the projection only changes with the
+/// cosine of the tilt so their tilt can't be solved.
+const MIN_TILT: f64 = 0.05;
+
+/// Telecentric camera, the orthographic projection of the x and y of a point in the camera
+/// frame with the radial `k1, k2` and tangential `p1, p2` distortion of them in m, magnified by
+/// `mx, my` in px/m around the principal point `cx, cy`. The depth doesn't change the
+/// projection, and the principal point is only observable through the distortion, so it's kept
+/// at the image center and an off-center distortion is taken by `p1, p2`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelecentricModel {
+    pub mx: f64,
+    pub my: f64,
+    pub cx: f64,
+    pub cy: f64,
+    pub k1: f64,
+    pub k2: f64,
+    pub p1: f64,
+    pub p2: f64,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Projection of `p` in the camera frame without the depth with the params
+/// `[mx, my, k1, k2, p1, p2]` around `principal_point`.
+pub(crate) fn telecentric_project<T: na::RealField>(
+    params: &na::DVector<T>,
+    principal_point: &na::Vector2<f64>,
+    p: &na::Vector2<T>,
+) -> na::Vector2<T> {
+    let (x, y) = (p.x.clone(), p.y.clone());
+    let two = T::from_f64(2.0).unwrap();
+    let r2 = x.clone() * x.clone() + y.clone() * y.clone();
+    let radial =
+        T::one() + params[2].clone() * r2.clone() + params[3].clone() * r2.clone() * r2.clone();
+    let (p1, p2) = (params[4].clone(), params[5].clone());
+    let xd = x.clone() * radial.clone()
+        + two.clone() * p1.clone() * x.clone() * y.clone()
+        + p2.clone() * (r2.clone() + two.clone() * x.clone() * x.clone());
+    let yd =
+        y.clone() * radial + p1 * (r2 + two.clone() * y.clone() * y.clone()) + two * p2 * x * y;
+    na::Vector2::new(
+        T::from_f64(principal_point.x).unwrap() + params[0].clone() * xd,
+        T::from_f64(principal_point.y).unwrap() + params[1].clone() * yd,
+    )
+}
+
+impl TelecentricModel {
+    fn params(&self) -> na::DVector<f64> {
+        na::dvector![self.mx, self.my, self.k1, self.k2, self.p1, self.p2]
+    }
+
+    /// Projection of a point in the camera frame, any depth.
+    pub fn project_one(&self, p3d: &na::Vector3<f64>) -> na::Vector2<f64> {
+        telecentric_project(
+            &self.params(),
+            &na::Vector2::new(self.cx, self.cy),
+            &p3d.xy(),
+        )
+    }
+}
+
+/// Result of `calib_telecentric`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelecentricCalibration {
+    pub model: TelecentricModel,
+    /// Board to camera poses with the depth left at 0. The tilt of a board is only known up to
+    /// its sign, a board tilted the other way looks the same.
+    pub rtvecs: HashMap<usize, RvecTvec>,
+    pub rms_error: f64,
+}
+
+/// Affine map `p2d = a * [x, y] + b` of the board to the image of a frame.
+fn affine_fit(frame: &FrameFeature) -> Option<(na::Matrix2<f64>, na::Vector2<f64>)> {
+    let corners: Vec<_> = frame.features.values().collect();
+    if corners.len() < MIN_FRAME_CORNERS {
+        return None;
+    }
+    let design = na::DMatrix::from_fn(corners.len(), 3, |i, j| match j {
+        0 => corners[i].p3d.x as f64,
+        1 => corners[i].p3d.y as f64,
+        _ => 1.0,
+    });
+    let normal = (design.transpose() * &design).cholesky()?;
+    let fit = |values: na::DVector<f64>| normal.solve(&(design.transpose() * values));
+    let u = fit(na::DVector::from_fn(corners.len(), |i, _| {
+        corners[i].p2d.x as f64
+    }));
+    let v = fit(na::DVector::from_fn(corners.len(), |i, _| {
+        corners[i].p2d.y as f64
+    }));
+    Some((
+        na::Matrix2::new(u[0], u[1], v[0], v[1]),
+        na::Vector2::new(u[2], v[2]),
+    ))
+}
+
+/// Board pose of the affine map of a frame, the first two columns of the rotation are the
+/// columns of `a` over the magnification with the z that makes them unit length. The z of the
+/// first one is taken positive.
+fn affine_pose(
+    a: &na::Matrix2<f64>,
+    b: &na::Vector2<f64>,
+    magnification: f64,
+    principal_point: &na::Vector2<f64>,
+) -> na::Isometry3<f64> {
+    let p = a / magnification;
+    let (p1, p2) = (p.column(0).into_owned(), p.column(1).into_owned());
+    let z1 = (1.0 - p1.norm_squared()).max(0.0).sqrt();
+    // r1 . r2 = 0 gives the sign of the z of the second one
+    let z2 = (1.0 - p2.norm_squared()).max(0.0).sqrt() * -p1.dot(&p2).signum();
+    let r1 = na::Vector3::new(p1.x, p1.y, z1);
+    let r2 = na::Vector3::new(p2.x, p2.y, z2);
+    let rotation = na::Rotation3::from_matrix(&na::Matrix3::from_columns(&[r1, r2, r1.cross(&r2)]));
+    let t = (b - principal_point) / magnification;
+    na::Isometry3::from_parts(na::Vector3::new(t.x, t.y, 0.0).into(), rotation.into())
+}
+
+/// Calibrates a telecentric camera. The board to image map of a telecentric lens is affine, its
+/// larger singular value is the magnification whatever the tilt of the board, so unlike the
+/// perspective models the initialization doesn't recover a focal from homographies. The boards
+/// still need to be tilted for the aspect ratio and the distortion to be separable from the
+/// poses.
+pub fn calib_telecentric(
+    frame_feature_list: &[Option<FrameFeature>],
+    observer: &dyn PipelineObserver,
+) -> Option<TelecentricCalibration> {
+    let fits: Vec<_> = frame_feature_list
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| Some((i, f.as_ref()?)))
+        .filter_map(|(i, f)| Some((i, f.img_w_h, affine_fit(f)?)))
+        .collect();
+    let Some(&(_, (width, height), _)) = fits.first() else {
+        tracing::warn!("no frame with enough corners for telecentric calibration");
+        return None;
+    };
+    let mut magnifications: Vec<_> = fits
+        .iter()
+        .map(|(_, _, (a, _))| a.singular_values().max())
+        .collect();
+    magnifications.sort_by(|a, b| a.total_cmp(b));
+    let magnification = magnifications[magnifications.len() / 2];
+    let principal_point = na::Vector2::new(width as f64 / 2.0, height as f64 / 2.0);
+    tracing::debug!("telecentric init magnification {:.1} px/m", magnification);
+
+    let mut initial_values = HashMap::<String, na::DVector<f64>>::from([(
+        "params".to_string(),
+        na::dvector![magnification, magnification, 0.0, 0.0, 0.0, 0.0],
+    )]);
+    let mut problem = tiny_solver::Problem::new();
+    let mut frames = Vec::new();
+    for (i, _, (a, b)) in &fits {
+        let t_cam_board = affine_pose(a, b, magnification, &principal_point);
+        if t_cam_board.rotation.angle() < MIN_TILT {
+            tracing::debug!("frame {} is not tilted enough", i);
+            continue;
+        }
+        let rvec_i = rvec_name(*i);
+        let tvec_i = tvec_name(*i);
+        // the depth doesn't change the projection, only x and y of the translation
+        initial_values.insert(rvec_i.clone(), t_cam_board.to_rvec_tvec().na_rvec());
+        initial_values.insert(
+            tvec_i.clone(),
+            na::dvector![t_cam_board.translation.x, t_cam_board.translation.y],
+        );
+        for fp in frame_feature_list[*i].as_ref()?.features.values() {
+            problem.add_residual_block(
+                2,
+                &[("params", 6), (&rvec_i, 3), (&tvec_i, 2)],
+                Box::new(TelecentricReprojectionFactor::new(
+                    &principal_point,
+                    &fp.p3d,
+                    &fp.p2d,
+                )),
+                Some(Box::new(HuberLoss::new(1.0))),
+            );
+        }
+        frames.push(*i);
+    }
+    if frames.is_empty() {
+        tracing::warn!("no tilted board for telecentric calibration");
+        return None;
+    }
+    problem.set_variable_bounds("params", 0, 0.0, f64::MAX);
+    problem.set_variable_bounds("params", 1, 0.0, f64::MAX);
+
+    let result = optimize_with_observer(
+        &problem,
+        &initial_values,
+        "calib_telecentric",
+        None,
+        observer,
+    )?;
+    let params = &result["params"];
+    let model = TelecentricModel {
+        mx: params[0],
+        my: params[1],
+        cx: principal_point.x,
+        cy: principal_point.y,
+        k1: params[2],
+        k2: params[3],
+        p1: params[4],
+        p2: params[5],
+        width,
+        height,
+    };
+    let rtvecs: HashMap<_, _> = frames
+        .iter()
+        .map(|&i| {
+            let t = &result[&tvec_name(i)];
+            (
+                i,
+                RvecTvec::new(&result[&rvec_name(i)], &na::dvector![t[0], t[1], 0.0]),
+            )
+        })
+        .collect();
+    let (mut squared_error, mut count) = (0.0, 0usize);
+    for (i, rtvec) in &rtvecs {
+        let t_cam_board = rtvec.to_na_isometry3();
+        for fp in frame_feature_list[*i].as_ref()?.features.values() {
+            let p3d = fp.p3d.as_dvec3();
+            let p_cam = t_cam_board * na::Point3::new(p3d.x, p3d.y, p3d.z);
+            let detected = na::Vector2::new(fp.p2d.x as f64, fp.p2d.y as f64);
+            squared_error += (model.project_one(&p_cam.coords) - detected).norm_squared();
+            count += 1;
+        }
+    }
+    let rms_error = (squared_error / count.max(1) as f64).sqrt();
+    tracing::info!(
+        "telecentric magnification ({:.2}, {:.2}) px/m, rms {:.3} px",
+        model.mx,
+        model.my,
+        rms_error
+    );
+    Some(TelecentricCalibration {
+        model,
+        rtvecs,
+        rms_error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detected_points::FeaturePoint;
+    use crate::observer::NoopObserver;
+    use crate::test_util::{board, board_poses};
+
+    fn telecentric() -> TelecentricModel {
+        TelecentricModel {
+            mx: 1500.0,
+            my: 1490.0,
+            cx: 640.0,
+            cy: 400.0,
+            k1: 0.05,
+            k2: -0.02,
+            p1: 0.002,
+            p2: -0.001,
+            width: 1280,
+            height: 800,
+        }
+    }
+
+    fn project_frame(model: &TelecentricModel, pose: &na::Isometry3<f64>) -> FrameFeature {
+        let features = board()
+            .id_to_3d
+            .iter()
+            .filter_map(|(&id, p3d)| {
+                let p = pose * na::Point3::new(p3d.x as f64, p3d.y as f64, p3d.z as f64);
+                let p2d = model.project_one(&p.coords);
+                (p2d.x > 0.0 && p2d.y > 0.0 && p2d.x < 1280.0 && p2d.y < 800.0).then(|| {
+                    let p2d = glam::Vec2::new(p2d.x as f32, p2d.y as f32);
+                    let fp = FeaturePoint {
+                        p2d,
+                        p3d: *p3d,
+                        time_ns: None,
+                        blur: None,
+                    };
+                    (id, fp)
+                })
+            })
+            .collect();
+        FrameFeature {
+            time_ns: 0,
+            img_w_h: (model.width, model.height),
+            features,
+            exposure: None,
+        }
+    }
+
+    #[test]
+    fn affine_pose_of_tilted_board() {
+        let pose = na::Isometry3::from_parts(
+            na::Vector3::new(0.05, -0.02, 0.0).into(),
+            na::UnitQuaternion::from_scaled_axis(na::Vector3::new(0.3, -0.2, 0.1)),
+        );
+        let model = TelecentricModel {
+            k1: 0.0,
+            k2: 0.0,
+            p1: 0.0,
+            p2: 0.0,
+            my: 1500.0,
+            ..telecentric()
+        };
+        let (a, b) = affine_fit(&project_frame(&model, &pose)).unwrap();
+        let principal_point = na::Vector2::new(model.cx, model.cy);
+        let estimated = affine_pose(&a, &b, model.mx, &principal_point);
+        assert!((estimated.translation.vector - pose.translation.vector).norm() < 1e-6);
+        // the tilt is only known up to its sign, the first two columns project the same
+        let (r, r_estimated) = (pose.rotation.to_rotation_matrix(), estimated.rotation);
+        let r_estimated = r_estimated.to_rotation_matrix();
+        for c in 0..2 {
+            let (col, col_estimated) = (r.matrix().column(c), r_estimated.matrix().column(c));
+            assert!((col.xy() - col_estimated.xy()).norm() < 1e-5);
+            assert!((col.z.abs() - col_estimated.z.abs()).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn calibrates_synthetic_telecentric() {
+        let model = telecentric();
+        let frames: Vec<_> = board_poses(&board(), 12)
+            .iter()
+            .map(|pose| Some(project_frame(&model, pose)))
+            .collect();
+        let calibration = calib_telecentric(&frames, &NoopObserver).unwrap();
+        let estimated = &calibration.model;
+        assert!(
+            calibration.rms_error < 1e-2,
+            "rms {}",
+            calibration.rms_error
+        );
+        assert!((estimated.mx - model.mx).abs() < 0.1, "{:?}", estimated);
+        assert!((estimated.my - model.my).abs() < 0.1, "{:?}", estimated);
+        assert!((estimated.k1 - model.k1).abs() < 1e-3, "{:?}", estimated);
+        assert!((estimated.p1 - model.p1).abs() < 1e-4, "{:?}", estimated);
+    }
+}
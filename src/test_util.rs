@@ -0,0 +1,117 @@
+//! Synthetic cameras and board sequences for the unit tests.
+
+use std::collections::HashMap;
+
+use camera_intrinsic_model::*;
+use nalgebra as na;
+
+use crate::board::{Board, BoardConfig};
+use crate::detected_points::{FeaturePoint, FrameFeature};
+use crate::types::RvecTvec;
+
+/// Time between two frames of the synthetic sequences, 30 fps.
+pub const FRAME_DT_NS: i64 = 33_333_333;
+
+pub fn eucm() -> GenericModel<f64> {
+    GenericModel::EUCM(EUCM::new(
+        &na::dvector![480.0, 482.0, 640.0, 400.0, 0.6, 1.1],
+        1280,
+        800,
+    ))
+}
+
+pub fn board() -> Board {
+    Board::from_config(&BoardConfig::default())
+}
+
+pub fn rtvec(pose: &na::Isometry3<f64>) -> RvecTvec {
+    let rvec = pose.rotation.scaled_axis();
+    let tvec = pose.translation.vector;
+    RvecTvec::new(
+        &na::dvector![rvec.x, rvec.y, rvec.z],
+        &na::dvector![tvec.x, tvec.y, tvec.z],
+    )
+}
+
+fn board_center(board: &Board) -> na::Vector3<f64> {
+    board
+        .id_to_3d
+        .values()
+        .map(|p| na::Vector3::new(p.x as f64, p.y as f64, p.z as f64))
+        .sum::<na::Vector3<f64>>()
+        / board.id_to_3d.len() as f64
+}
+
+/// Board to camera poses tilted in every direction around the optical axis, about 1 m away.
+pub fn board_poses(board: &Board, num: usize) -> Vec<na::Isometry3<f64>> {
+    let center = board_center(board);
+    (0..num)
+        .map(|i| {
+            let a = i as f64 * 2.4;
+            let rotation = na::UnitQuaternion::from_scaled_axis(na::Vector3::new(
+                0.5 * a.sin(),
+                0.5 * a.cos(),
+                0.2 * (0.7 * a).sin(),
+            ));
+            let position =
+                na::Vector3::new(0.25 * a.cos(), 0.15 * a.sin(), 0.9 + 0.1 * (i % 3) as f64);
+            na::Isometry3::from_parts((position - rotation * center).into(), rotation)
+        })
+        .collect()
+}
+
+/// Corners of the board at `pose` seen by `model`, the corners outside of the image are missing.
+pub fn project_frame(
+    model: &GenericModel<f64>,
+    board: &Board,
+    pose: &na::Isometry3<f64>,
+    time_ns: i64,
+) -> FrameFeature {
+    let features = board
+        .id_to_3d
+        .iter()
+        .filter_map(|(&id, p3d)| {
+            let p = pose * na::Point3::new(p3d.x as f64, p3d.y as f64, p3d.z as f64);
+            let p2d = model.project_one(&p.coords);
+            (p2d.x > 0.0 && p2d.y > 0.0 && p2d.x < model.width() && p2d.y < model.height()).then(
+                || {
+                    (
+                        id,
+                        FeaturePoint {
+                            p2d: glam::Vec2::new(p2d.x as f32, p2d.y as f32),
+                            p3d: *p3d,
+                            time_ns: None,
+                            blur: None,
+                        },
+                    )
+                },
+            )
+        })
+        .collect();
+    FrameFeature {
+        time_ns,
+        img_w_h: (model.width() as u32, model.height() as u32),
+        features,
+        exposure: None,
+    }
+}
+
+/// `num` frames of `board_poses` and their poses.
+pub fn frames(
+    model: &GenericModel<f64>,
+    num: usize,
+) -> (Vec<Option<FrameFeature>>, HashMap<usize, RvecTvec>) {
+    let board = board();
+    let poses = board_poses(&board, num);
+    let frames = poses
+        .iter()
+        .enumerate()
+        .map(|(i, pose)| Some(project_frame(model, &board, pose, i as i64 * FRAME_DT_NS)))
+        .collect();
+    let rtvecs = poses
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (i, rtvec(p)))
+        .collect();
+    (frames, rtvecs)
+}
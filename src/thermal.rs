@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+
+use camera_intrinsic_model::*;
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+use tiny_solver::loss_functions::HuberLoss;
+
+use crate::detected_points::FrameFeature;
+use crate::observer::PipelineObserver;
+use crate::optimization::factors::ThermalReprojectionFactor;
+use crate::optimization::{rvec_name, tvec_name};
+use crate::types::RvecTvec;
+use crate::util::{optimize_with_observer, set_problem_parameter_bound};
+
+/// Temperature range of the frames below which the drift can't be told from the noise.
+const MIN_TEMPERATURE_RANGE: f64 = 1.0;
+
+/// Drift of the focal and the principal point with the temperature. At `t` °C `fx, fy, cx, cy`
+/// are the ones of `model` plus `coefficients[k] * (t - reference_temperature)^(k + 1)` of
+/// every degree `k`, the distortion doesn't change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThermalDrift {
+    pub model: GenericModel<f64>,
+    pub reference_temperature: f64,
+    /// `[fx, fy, cx, cy]` in px/°C^(k + 1) of every degree `k`.
+    pub coefficients: Vec<[f64; 4]>,
+    /// Temperatures of the calibration frames, the drift is extrapolated outside of it.
+    pub temperature_range: (f64, f64),
+}
+
+impl ThermalDrift {
+    pub fn model_at(&self, temperature: f64) -> GenericModel<f64> {
+        let dt = temperature - self.reference_temperature;
+        let mut params = self.model.params();
+        for (k, c) in self.coefficients.iter().enumerate() {
+            let dt_k = dt.powi(k as i32 + 1);
+            for i in 0..4 {
+                params[i] += c[i] * dt_k;
+            }
+        }
+        self.model.new_from_params(&params)
+    }
+}
+
+/// Temperature at `time_ns` linearly interpolated from the `(time_ns, temperature)` samples,
+/// `None` outside of them.
+pub fn temperature_at(samples: &[(i64, f64)], time_ns: i64) -> Option<f64> {
+    let upper = samples.partition_point(|s| s.0 < time_ns);
+    let (t1, v1) = *samples.get(upper)?;
+    if t1 == time_ns {
+        return Some(v1);
+    }
+    let (t0, v0) = samples[upper.checked_sub(1)?];
+    Some(v0 + (v1 - v0) * (time_ns - t0) as f64 / (t1 - t0) as f64)
+}
+
+/// Fits a polynomial of `degree` of the drift of the focal and the principal point with the
+/// temperature to the frames of a calibration, `temperatures` are the `(time_ns, temperature)`
+/// readings sorted by time. The reference temperature is the mean of the frames, which need to
+/// cover a range of temperatures for the drift to be observable.
+pub fn calib_thermal_drift(
+    frame_feature_list: &[Option<FrameFeature>],
+    generic_camera: &GenericModel<f64>,
+    rtvecs: &HashMap<usize, RvecTvec>,
+    temperatures: &[(i64, f64)],
+    degree: usize,
+    xy_same_focal: bool,
+    observer: &dyn PipelineObserver,
+) -> Option<ThermalDrift> {
+    let frames: Vec<_> = frame_feature_list
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| rtvecs.contains_key(i))
+        .filter_map(|(i, f)| {
+            let f = f.as_ref()?;
+            Some((i, f, temperature_at(temperatures, f.time_ns)?))
+        })
+        .collect();
+    let (min_t, max_t) = frames.iter().fold((f64::MAX, f64::MIN), |(lo, hi), f| {
+        (lo.min(f.2), hi.max(f.2))
+    });
+    if degree == 0 {
+        return None;
+    } else if frames.is_empty() || max_t - min_t < MIN_TEMPERATURE_RANGE {
+        tracing::warn!(
+            "the frames with a temperature reading span less than {} °C",
+            MIN_TEMPERATURE_RANGE
+        );
+        return None;
+    }
+    let reference_temperature = frames.iter().map(|f| f.2).sum::<f64>() / frames.len() as f64;
+
+    let mut params = generic_camera.params();
+    if xy_same_focal {
+        // remove fy
+        params = params.remove_row(1);
+    };
+    let params_len = params.len();
+    let stride = if xy_same_focal { 3 } else { 4 };
+    let mut initial_values = HashMap::<String, na::DVector<f64>>::from([
+        ("params".to_string(), params),
+        (
+            "thermal_drift".to_string(),
+            na::DVector::zeros(stride * degree),
+        ),
+    ]);
+    let mut problem = tiny_solver::Problem::new();
+    for &(i, frame_feature, temperature) in &frames {
+        let rvec_i = rvec_name(i);
+        let tvec_i = tvec_name(i);
+        initial_values.insert(rvec_i.clone(), rtvecs[&i].na_rvec());
+        initial_values.insert(tvec_i.clone(), rtvecs[&i].na_tvec());
+        for fp in frame_feature.features.values() {
+            let cost = ThermalReprojectionFactor::new(
+                generic_camera,
+                &fp.p3d,
+                &fp.p2d,
+                xy_same_focal,
+                temperature - reference_temperature,
+            );
+            problem.add_residual_block(
+                2,
+                &[
+                    ("params", params_len),
+                    ("thermal_drift", stride * degree),
+                    (&rvec_i, 3),
+                    (&tvec_i, 3),
+                ],
+                Box::new(cost),
+                Some(Box::new(HuberLoss::new(1.0))),
+            );
+        }
+    }
+    set_problem_parameter_bound("params", &mut problem, generic_camera, xy_same_focal);
+
+    let mut result = optimize_with_observer(
+        &problem,
+        &initial_values,
+        "calib_thermal_drift",
+        None,
+        observer,
+    )?;
+    let mut new_params = result.remove("params").unwrap();
+    if xy_same_focal {
+        new_params = new_params.clone().insert_row(1, new_params[0]);
+    };
+    let mut model = *generic_camera;
+    model.set_params(&new_params);
+    let drift = &result["thermal_drift"];
+    let coefficients: Vec<_> = drift
+        .as_slice()
+        .chunks(stride)
+        .map(|c| {
+            if xy_same_focal {
+                [c[0], c[0], c[1], c[2]]
+            } else {
+                [c[0], c[1], c[2], c[3]]
+            }
+        })
+        .collect();
+    tracing::info!(
+        "focal drift {:.4} px/°C, principal point drift ({:.4}, {:.4}) px/°C at {:.1} °C",
+        coefficients[0][0],
+        coefficients[0][2],
+        coefficients[0][3],
+        reference_temperature
+    );
+    Some(ThermalDrift {
+        model,
+        reference_temperature,
+        coefficients,
+        temperature_range: (min_t, max_t),
+    })
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use image::{DynamicImage, GrayImage};
+
+use crate::detected_points::TagDetection;
+
+/// Share of the darkest and the brightest pixels clipped by the contrast stretch.
+const STRETCH_CLIP: f64 = 0.005;
+/// Standard deviation of the stretched values below which a neighborhood is flat, so the noise
+/// of the flat areas isn't thresholded to black and white.
+const MIN_LOCAL_STD: f32 = 0.1;
+/// Images with a shorter side are upsampled for the detection.
+const MIN_SIDE: u32 = 480;
+const MAX_UPSAMPLE: u32 = 4;
+
+/// Polarity of the board in the image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Polarity {
+    /// Tries both and keeps the one with more tags.
+    #[default]
+    Auto,
+    /// Black tags on white like a printed board, e.g. a board heated behind the white squares.
+    Normal,
+    /// White tags on black, e.g. a heated board with cold white squares.
+    Inverted,
+}
+
+/// Detects the board in thermal and IR images, where it's low contrast, with an uneven
+/// temperature and often inverted. The images are contrast stretched, adaptively thresholded
+/// and upsampled before the detection of `detector`, so the corner refinement has enough
+/// pixels around each corner of a low resolution sensor.
+pub struct ThermalTagDetector<D: TagDetection> {
+    detector: D,
+    polarity: Polarity,
+}
+
+impl<D: TagDetection> ThermalTagDetector<D> {
+    pub fn new(detector: D, polarity: Polarity) -> ThermalTagDetector<D> {
+        ThermalTagDetector { detector, polarity }
+    }
+}
+
+/// Gray values of any bit depth stretched to [0, 1] between the `STRETCH_CLIP` percentiles.
+fn stretch(img: &DynamicImage) -> (u32, u32, Vec<f32>) {
+    let luma = img.to_luma16();
+    let mut histogram = vec![0usize; u16::MAX as usize + 1];
+    for p in luma.as_raw() {
+        histogram[*p as usize] += 1;
+    }
+    let clip = (luma.as_raw().len() as f64 * STRETCH_CLIP) as usize;
+    // first value with more than `clip` pixels up to it
+    let percentile = |values: &mut dyn Iterator<Item = usize>| {
+        let mut count = 0;
+        for v in values {
+            count += histogram[v];
+            if count > clip {
+                return v as f32;
+            }
+        }
+        0.0
+    };
+    let low = percentile(&mut (0..histogram.len()));
+    let high = percentile(&mut (0..histogram.len()).rev());
+    let range = (high - low).max(1.0);
+    let values = luma
+        .as_raw()
+        .iter()
+        .map(|&p| ((p as f32 - low) / range).clamp(0.0, 1.0))
+        .collect();
+    (luma.width(), luma.height(), values)
+}
+
+/// Box sums of `values` with `radius` from the integral image, clamped at the borders.
+fn box_mean(w: usize, h: usize, values: &[f32], radius: usize) -> Vec<f32> {
+    let mut integral = vec![0.0f64; (w + 1) * (h + 1)];
+    for y in 0..h {
+        let mut row = 0.0;
+        for x in 0..w {
+            row += values[y * w + x] as f64;
+            integral[(y + 1) * (w + 1) + x + 1] = integral[y * (w + 1) + x + 1] + row;
+        }
+    }
+    let mut means = vec![0.0; w * h];
+    for y in 0..h {
+        let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(h));
+        for x in 0..w {
+            let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(w));
+            let sum = integral[y1 * (w + 1) + x1]
+                - integral[y0 * (w + 1) + x1]
+                - integral[y1 * (w + 1) + x0]
+                + integral[y0 * (w + 1) + x0];
+            means[y * w + x] = (sum / ((y1 - y0) * (x1 - x0)) as f64) as f32;
+        }
+    }
+    means
+}
+
+/// Soft adaptive threshold, every pixel is pushed to black or white by how far it is from the
+/// mean of its neighborhood in local standard deviations. Unlike a hard threshold it keeps the
+/// edges smooth for the subpixel refinement of the corners.
+fn adaptive_threshold(w: usize, h: usize, values: &[f32], inverted: bool) -> GrayImage {
+    let radius = (w.min(h) / 16).max(3);
+    let mean = box_mean(w, h, values, radius);
+    let squares: Vec<_> = values.iter().map(|v| v * v).collect();
+    let mean_squares = box_mean(w, h, &squares, radius);
+    let pixels = values
+        .iter()
+        .zip(mean.iter().zip(&mean_squares))
+        .map(|(v, (m, m2))| {
+            let std = (m2 - m * m).max(0.0).sqrt();
+            let z = (v - m) / std.max(MIN_LOCAL_STD);
+            let z = if inverted { -z } else { z };
+            (127.5 + 127.5 * z.tanh()).round() as u8
+        })
+        .collect();
+    GrayImage::from_raw(w as u32, h as u32, pixels).unwrap()
+}
+
+impl<D: TagDetection> ThermalTagDetector<D> {
+    fn detect_with_polarity(
+        &self,
+        w: u32,
+        h: u32,
+        values: &[f32],
+        inverted: bool,
+    ) -> HashMap<u32, [(f32, f32); 4]> {
+        let thresholded = adaptive_threshold(w as usize, h as usize, values, inverted);
+        let scale = MIN_SIDE.div_ceil(w.min(h)).clamp(1, MAX_UPSAMPLE);
+        let img = if scale > 1 {
+            image::imageops::resize(
+                &thresholded,
+                w * scale,
+                h * scale,
+                image::imageops::FilterType::Triangle,
+            )
+        } else {
+            thresholded
+        };
+        // pixel centers of the upsampled image back to the original one
+        let s = scale as f32;
+        let to_original = |(x, y): (f32, f32)| ((x + 0.5) / s - 0.5, (y + 0.5) / s - 0.5);
+        self.detector
+            .detect_tags(&DynamicImage::ImageLuma8(img))
+            .into_iter()
+            .map(|(id, corners)| (id, corners.map(to_original)))
+            .collect()
+    }
+}
+
+impl<D: TagDetection> TagDetection for ThermalTagDetector<D> {
+    fn detect_tags(&self, img: &DynamicImage) -> HashMap<u32, [(f32, f32); 4]> {
+        let (w, h, values) = stretch(img);
+        match self.polarity {
+            Polarity::Normal => self.detect_with_polarity(w, h, &values, false),
+            Polarity::Inverted => self.detect_with_polarity(w, h, &values, true),
+            Polarity::Auto => {
+                let normal = self.detect_with_polarity(w, h, &values, false);
+                let inverted = self.detect_with_polarity(w, h, &values, true);
+                if inverted.len() > normal.len() {
+                    inverted
+                } else {
+                    normal
+                }
+            }
+        }
+    }
+
+    fn corner_blurs(&self) -> bool {
+        self.detector.corner_blurs()
+    }
+}
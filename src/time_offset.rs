@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+
+use crate::detected_points::FrameFeature;
+use crate::imu::search_offset;
+use crate::types::RvecTvec;
+
+/// Board poses `T_cam_board` of a camera sorted by the frame time.
+fn pose_trajectory(
+    frame_feature_list: &[Option<FrameFeature>],
+    rtvecs: &HashMap<usize, RvecTvec>,
+) -> Vec<(i64, na::Isometry3<f64>)> {
+    let mut poses: Vec<_> = rtvecs
+        .iter()
+        .filter_map(|(&i, rtvec)| {
+            Some((
+                frame_feature_list.get(i)?.as_ref()?.time_ns,
+                rtvec.to_na_isometry3(),
+            ))
+        })
+        .collect();
+    poses.sort_by_key(|p| p.0);
+    poses
+}
+
+fn median_frame_interval(poses: &[(i64, na::Isometry3<f64>)]) -> Option<i64> {
+    let mut dts: Vec<_> = poses.windows(2).map(|w| w[1].0 - w[0].0).collect();
+    dts.sort();
+    dts.get(dts.len() / 2).copied().filter(|&dt| dt > 0)
+}
+
+/// Pose at `time_ns` between two frames at most `max_dt` apart.
+fn interpolate_pose(
+    poses: &[(i64, na::Isometry3<f64>)],
+    time_ns: i64,
+    max_dt: i64,
+) -> Option<na::Isometry3<f64>> {
+    let i = poses.partition_point(|p| p.0 <= time_ns);
+    if i == 0 || i == poses.len() {
+        return None;
+    }
+    let ((t0, pose0), (t1, pose1)) = (&poses[i - 1], &poses[i]);
+    if t1 - t0 > max_dt {
+        return None;
+    }
+    let w = (time_ns - t0) as f64 / (t1 - t0) as f64;
+    Some(pose0.lerp_slerp(pose1, w))
+}
+
+/// Time offset of cam i to cam0, `t_cam0 = t_cam_i + offset`, from the board pose trajectories
+/// of both cameras, each with its own frame times. The rotation angles between consecutive
+/// frames of cameras on a rig are the same whatever the extrinsics, so they're matched with
+/// the angles of the interpolated cam0 trajectory within `max_time_offset_ns`.
+pub fn estimate_time_offset(
+    frames0: &[Option<FrameFeature>],
+    rtvecs0: &HashMap<usize, RvecTvec>,
+    frames_i: &[Option<FrameFeature>],
+    rtvecs_i: &HashMap<usize, RvecTvec>,
+    max_time_offset_ns: i64,
+) -> Option<i64> {
+    let poses0 = pose_trajectory(frames0, rtvecs0);
+    let poses_i = pose_trajectory(frames_i, rtvecs_i);
+    let dt = median_frame_interval(&poses0)?.min(median_frame_interval(&poses_i)?);
+    // skip gaps of missing detections
+    let max_dt = dt * 3 / 2;
+    let motions: Vec<_> = poses_i
+        .windows(2)
+        .filter(|w| w[1].0 - w[0].0 <= max_dt)
+        .map(|w| (w[0].0, w[1].0, (w[1].1 * w[0].1.inverse()).rotation.angle()))
+        .collect();
+    let cost = |offset_ns: i64| {
+        let diffs: Vec<_> = motions
+            .iter()
+            .filter_map(|&(t0, t1, angle)| {
+                let pose0 = interpolate_pose(&poses0, t0 + offset_ns, max_dt)?;
+                let pose1 = interpolate_pose(&poses0, t1 + offset_ns, max_dt)?;
+                Some((angle - (pose1 * pose0.inverse()).rotation.angle()).powi(2))
+            })
+            .collect();
+        // offsets with only a few overlapping frames aren't comparable
+        (diffs.len() * 2 >= motions.len() && !diffs.is_empty())
+            .then(|| diffs.iter().sum::<f64>() / diffs.len() as f64)
+    };
+    let step = (dt / 10).max(1);
+    let offset = search_offset(0, max_time_offset_ns, step, cost)?;
+    search_offset(offset, step, (step / 10).max(1), cost)
+}
+
+/// For every frame of cam0, the index of the cam i frame closest in time after shifting it by
+/// `time_offset_ns`, if it's within half a frame. Every cam i frame is associated with at most
+/// one cam0 frame, the closest one.
+fn associate_frames(
+    frames0: &[Option<FrameFeature>],
+    frames_i: &[Option<FrameFeature>],
+    time_offset_ns: i64,
+) -> Vec<Option<usize>> {
+    let mut times_i: Vec<_> = frames_i
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| Some((f.as_ref()?.time_ns + time_offset_ns, i)))
+        .collect();
+    times_i.sort();
+    let mut dts: Vec<_> = times_i.windows(2).map(|w| w[1].0 - w[0].0).collect();
+    dts.sort();
+    let max_diff = dts.get(dts.len() / 2).copied().unwrap_or(0) / 2;
+    // (time difference, cam0 index, cam i index) of the closest cam i frame of every cam0 frame
+    let mut candidates: Vec<_> = frames0
+        .iter()
+        .enumerate()
+        .filter_map(|(idx0, f)| {
+            let t = f.as_ref()?.time_ns;
+            let k = times_i.partition_point(|p| p.0 < t);
+            [k.checked_sub(1), Some(k)]
+                .into_iter()
+                .flatten()
+                .filter_map(|k| times_i.get(k))
+                .map(|p| ((p.0 - t).abs(), idx0, p.1))
+                .min()
+                .filter(|c| c.0 <= max_diff)
+        })
+        .collect();
+    candidates.sort();
+    let mut indexes = vec![None; frames0.len()];
+    let mut used = vec![false; frames_i.len()];
+    for (_, idx0, idx_i) in candidates {
+        if !used[idx_i] {
+            used[idx_i] = true;
+            indexes[idx0] = Some(idx_i);
+        }
+    }
+    indexes
+}
+
+/// Frame indexes of every camera after associating the frames with cam0 by the time shifted by
+/// `time_offsets_ns`, `new_index -> old_index`. Frames of the other cameras without a cam0
+/// frame at the same time get new indexes after the cam0 frames, so they don't share the board
+/// pose of any other frame.
+pub fn align_frames(
+    cams_frames: &[Vec<Option<FrameFeature>>],
+    time_offsets_ns: &[i64],
+) -> Vec<Vec<Option<usize>>> {
+    let Some(frames0) = cams_frames.first() else {
+        return Vec::new();
+    };
+    let mut aligned = vec![(0..frames0.len()).map(Some).collect::<Vec<_>>()];
+    for (frames_i, &offset) in cams_frames.iter().zip(time_offsets_ns).skip(1) {
+        let mut indexes = associate_frames(frames0, frames_i, offset);
+        let mut used = vec![false; frames_i.len()];
+        for &i in indexes.iter().flatten() {
+            used[i] = true;
+        }
+        let end = aligned.iter().map(|a| a.len()).max().unwrap_or(0);
+        indexes.resize(end, None);
+        indexes.extend(
+            frames_i
+                .iter()
+                .enumerate()
+                .filter(|(i, f)| f.is_some() && !used[*i])
+                .map(|(i, _)| Some(i)),
+        );
+        aligned.push(indexes);
+    }
+    let len = aligned.iter().map(|a| a.len()).max().unwrap_or(0);
+    for indexes in &mut aligned {
+        indexes.resize(len, None);
+    }
+    aligned
+}
+
+/// Frames with slower board rotations than this in rad/s don't show the trigger delay.
+const MIN_ANGULAR_SPEED: f64 = 0.2;
+
+/// Residual trigger delay of a camera nominally synced with cam0.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TriggerDelay {
+    pub cam_idx: usize,
+    /// Cam i triggers this much later than cam0.
+    pub delay_ns: f64,
+    /// Standard deviation of the delays of the frames.
+    pub jitter_ns: f64,
+    pub num_frames: usize,
+}
+
+/// Trigger delay of cam i to cam0 whose frames of the same index are taken at the same time,
+/// from the board poses of each camera alone. A delay `d` turns the board seen by cam i by
+/// `w * d` more, `w` the angular velocity of the board in cam0. The rotation of the rig is
+/// unknown, so the rotation error `e` and `d` of `R_i_b * R_0_b^-1 = R_i_0 * exp(e + w * d)`
+/// are solved together, and the jitter is the spread of the delays of the single frames.
+pub fn estimate_trigger_delay(
+    cam_idx: usize,
+    frames0: &[Option<FrameFeature>],
+    rtvecs0: &HashMap<usize, RvecTvec>,
+    rtvecs_i: &HashMap<usize, RvecTvec>,
+) -> Option<TriggerDelay> {
+    let rotation = |rtvecs: &HashMap<usize, RvecTvec>, i: usize| {
+        Some(rtvecs.get(&i)?.to_na_isometry3().rotation)
+    };
+    let time = |i: usize| Some(frames0.get(i)?.as_ref()?.time_ns);
+    // (relative rotation of the rig, angular velocity of the board in cam0)
+    let samples: Vec<_> = rtvecs0
+        .keys()
+        .filter_map(|&k| {
+            let (prev, next) = (k.checked_sub(1)?, k + 1);
+            let r_0_prev = rotation(rtvecs0, prev)?;
+            let r_0_next = rotation(rtvecs0, next)?;
+            let dt = (time(next)? - time(prev)?) as f64 * 1e-9;
+            let w = (r_0_next * r_0_prev.inverse()).scaled_axis() / dt;
+            let r_i_0 = rotation(rtvecs_i, k)? * rotation(rtvecs0, k)?.inverse();
+            (dt > 0.0).then_some((r_i_0, w))
+        })
+        .collect();
+    let r_i_0 = samples.first()?.0;
+    let residuals: Vec<_> = samples
+        .iter()
+        .map(|(r, w)| ((r_i_0.inverse() * r).scaled_axis(), *w))
+        .collect();
+    // [I w] [e d]^T = r
+    let mut ata = na::Matrix4::<f64>::zeros();
+    let mut atb = na::Vector4::<f64>::zeros();
+    for (r, w) in &residuals {
+        let a = na::Matrix3x4::from_columns(&[
+            na::Vector3::x(),
+            na::Vector3::y(),
+            na::Vector3::z(),
+            *w,
+        ]);
+        ata += a.transpose() * a;
+        atb += a.transpose() * r;
+    }
+    let x = ata.try_inverse()? * atb;
+    let e = x.xyz();
+    let delays: Vec<_> = residuals
+        .iter()
+        .filter(|(_, w)| w.norm() > MIN_ANGULAR_SPEED)
+        .map(|(r, w)| (r - e).dot(w) / w.norm_squared())
+        .collect();
+    if delays.len() < 3 {
+        tracing::warn!(
+            "cam{} not enough board rotation for the trigger delay",
+            cam_idx
+        );
+        return None;
+    }
+    let mean = delays.iter().sum::<f64>() / delays.len() as f64;
+    let variance = delays.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / delays.len() as f64;
+    Some(TriggerDelay {
+        cam_idx,
+        delay_ns: x[3] * 1e9,
+        jitter_ns: variance.sqrt() * 1e9,
+        num_frames: delays.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(times_ns: &[i64]) -> Vec<Option<FrameFeature>> {
+        times_ns
+            .iter()
+            .map(|&time_ns| {
+                Some(FrameFeature {
+                    time_ns,
+                    img_w_h: (640, 480),
+                    features: HashMap::new(),
+                    exposure: None,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn frames_associated_once() {
+        // cam0 frames 1 and 2 are both closest to cam1 frame 1
+        let frames0 = frames(&[0, 92, 105, 300]);
+        let frames1 = frames(&[0, 100, 200, 300]);
+        let indexes = associate_frames(&frames0, &frames1, 0);
+        assert_eq!(indexes, vec![Some(0), None, Some(1), Some(3)]);
+    }
+
+    #[test]
+    fn aligned_frames_keep_every_detection() {
+        let frames0 = frames(&[0, 92, 105, 300]);
+        let frames1 = frames(&[0, 100, 200, 300]);
+        let aligned = align_frames(&[frames0, frames1.clone()], &[0, 0]);
+        let mut old: Vec<_> = aligned[1].iter().flatten().copied().collect();
+        old.sort();
+        assert_eq!(old, vec![0, 1, 2, 3]);
+        assert_eq!(aligned[0].len(), aligned[1].len());
+        // the unmatched cam1 frame gets its own index after the cam0 frames
+        assert_eq!(aligned[1][4], Some(2));
+    }
+
+    #[test]
+    fn frames_associated_with_offset() {
+        let frames0 = frames(&[1000, 1100, 1200]);
+        let frames1 = frames(&[0, 100, 200]);
+        assert_eq!(
+            associate_frames(&frames0, &frames1, 1000),
+            vec![Some(0), Some(1), Some(2)]
+        );
+    }
+}
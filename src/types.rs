@@ -2,10 +2,14 @@ use camera_intrinsic_model::GenericModel;
 use nalgebra as na;
 use serde::{Deserialize, Serialize};
 
+use crate::optimization::Plateau;
+
 pub struct CalibParams {
     pub fixed_focal: Option<f64>,
     pub disabled_distortion_num: usize,
     pub one_focal: bool,
+    /// Stops the optimizations early on a plateau of the error, `None` runs them to convergence.
+    pub plateau: Option<Plateau>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,6 +36,103 @@ impl RvecTvec {
     }
 }
 
+/// Reprojection errors of the corners between `radius_begin` and `radius_end` px from the principal point.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RadiusBin {
+    pub radius_begin: f64,
+    pub radius_end: f64,
+    pub count: usize,
+    pub mean_error: f64,
+    pub median_error: f64,
+}
+
+/// Reprojection errors of the corners detected in a `cell_size` x `cell_size` cell of the image
+/// at `row`, `col`. The mean of `(du, dv)` shows a systematic shift of the region.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResidualCell {
+    pub row: usize,
+    pub col: usize,
+    pub count: usize,
+    pub mean_error: f64,
+    pub mean_du: f64,
+    pub mean_dv: f64,
+}
+
+/// Reprojection error of a corner, `(du, dv)` is the reprojection minus the detection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CornerResidual {
+    pub frame_idx: usize,
+    pub time_ns: i64,
+    pub corner_id: u32,
+    pub u: f64,
+    pub v: f64,
+    pub du: f64,
+    pub dv: f64,
+    pub norm: f64,
+}
+
+/// Reprojection errors of a frame in px.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameReport {
+    pub frame_idx: usize,
+    pub time_ns: i64,
+    pub num_points: usize,
+    pub mean_error: f64,
+    pub median_error: f64,
+    pub max_error: f64,
+}
+
+/// Reprojection errors of the histogram bins in px.
+pub const HISTOGRAM_BIN_SIZE: f64 = 0.05;
+pub const HISTOGRAM_MAX_ERROR: f64 = 5.0;
+
+/// Counts of the reprojection errors in bins of `bin_size` px up to `max_error`, the larger
+/// ones are counted in `overflow`, so a heavy tail of misdetections isn't averaged away.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorHistogram {
+    pub bin_size: f64,
+    pub max_error: f64,
+    pub counts: Vec<usize>,
+    pub overflow: usize,
+}
+
+impl ErrorHistogram {
+    pub fn new(errors: &[f64], bin_size: f64, max_error: f64) -> ErrorHistogram {
+        let mut counts = vec![0; (max_error / bin_size).ceil() as usize];
+        let mut overflow = 0;
+        for &e in errors {
+            match counts.get_mut((e / bin_size) as usize) {
+                Some(count) if e < max_error => *count += 1,
+                _ => overflow += 1,
+            }
+        }
+        ErrorHistogram {
+            bin_size,
+            max_error,
+            counts,
+            overflow,
+        }
+    }
+}
+
+/// Result of `validation`, reprojection errors in px.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalibrationReport {
+    pub cam_idx: usize,
+    pub num_points: usize,
+    pub mean_error: f64,
+    pub median_error: f64,
+    pub p95_error: f64,
+    pub p99_error: f64,
+    pub max_error: f64,
+    /// Mean of the smallest 99% of the errors.
+    pub avg_99_percent: f64,
+    pub histogram: ErrorHistogram,
+    pub frames: Vec<FrameReport>,
+    /// Frames with detections but without a board pose of the calibration.
+    pub rejected_frames: Vec<usize>,
+}
+
 pub type Intrinsics = Vec<GenericModel<f64>>;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -45,6 +146,11 @@ impl Extrinsics {
             rtvecs: rtvecs.to_vec(),
         }
     }
+
+    /// `T_i_0` of each camera, transforms points from cam0 to cam i.
+    pub fn rtvecs(&self) -> &[RvecTvec] {
+        &self.rtvecs
+    }
 }
 
 pub trait ToRvecTvec {
@@ -0,0 +1,799 @@
+use camera_intrinsic_model::{GenericModel, OpenCVModel5};
+use image::DynamicImage;
+use nalgebra as na;
+use rayon::prelude::*;
+
+use crate::batch;
+use crate::lut::UnprojectionLut;
+use crate::remap::{remap, Border, Interpolation};
+
+fn centered_camera_matrix(focal: f64, w_h: (u32, u32)) -> na::Matrix3<f64> {
+    na::Matrix3::new(
+        focal,
+        0.0,
+        (w_h.0 as f64 - 1.0) / 2.0,
+        0.0,
+        focal,
+        (w_h.1 as f64 - 1.0) / 2.0,
+        0.0,
+        0.0,
+        1.0,
+    )
+}
+
+/// In degree, the interpolated rays of a lookup table are this close to the rays of the model.
+const LUT_RAY_TOLERANCE: f64 = 1.0;
+
+/// In degree, wider rays can't be undistorted to a pinhole camera.
+pub const MAX_RAY_ANGLE: f64 = 80.0;
+
+/// Like OpenCV `getOptimalNewCameraMatrix`, `alpha` 0.0 keeps only valid pixels and 1.0 keeps all
+/// source pixels. Returns the camera matrix and the valid region (x, y, w, h) of the new image.
+/// Source rays wider than `MAX_RAY_ANGLE` from the optical axis are clamped to it.
+pub fn optimal_new_camera_matrix(
+    model: &GenericModel<f64>,
+    alpha: f64,
+    new_w_h: Option<(u32, u32)>,
+) -> (na::Matrix3<f64>, (u32, u32, u32, u32)) {
+    const N: usize = 9;
+    let (w, h) = (model.width(), model.height());
+    let (new_w, new_h) = new_w_h.unwrap_or((w as u32, h as u32));
+    let p2ds: Vec<_> = (0..N)
+        .flat_map(|r| (0..N).map(move |c| (r, c)))
+        .map(|(r, c)| {
+            na::Vector2::new(
+                c as f64 * (w - 1.0) / (N - 1) as f64,
+                r as f64 * (h - 1.0) / (N - 1) as f64,
+            )
+        })
+        .collect();
+    let normalized: Vec<_> = batch::unproject(model, &p2ds)
+        .iter()
+        .zip(&p2ds)
+        .map(|(p3d, p2d)| {
+            // pixels outside of the model's domain don't project back to themselves
+            let p = p3d.filter(|p| (model.project_one(p) - p2d).norm() < 1.0)?;
+            let r = p.xy().norm();
+            if r == 0.0 {
+                return Some(na::Vector2::zeros());
+            }
+            let theta = r.atan2(p.z).min(MAX_RAY_ANGLE.to_radians());
+            Some(p.xy() / r * theta.tan())
+        })
+        .collect();
+    let normalized = &normalized;
+    let column = |c: usize| (0..N).filter_map(move |r| normalized[r * N + c]);
+    let row = |r: usize| (0..N).filter_map(move |c| normalized[r * N + c]);
+    let (mut outer_min, mut outer_max) =
+        (na::Vector2::repeat(f64::MAX), na::Vector2::repeat(f64::MIN));
+    for p in normalized.iter().flatten() {
+        outer_min = outer_min.inf(p);
+        outer_max = outer_max.sup(p);
+    }
+    // inner rect is bounded by the border rows and columns
+    let inner_min = na::Vector2::new(
+        column(0).map(|p| p.x).fold(f64::MIN, f64::max),
+        row(0).map(|p| p.y).fold(f64::MIN, f64::max),
+    );
+    let inner_max = na::Vector2::new(
+        column(N - 1).map(|p| p.x).fold(f64::MAX, f64::min),
+        row(N - 1).map(|p| p.y).fold(f64::MAX, f64::min),
+    );
+    let fit = |min: &na::Vector2<f64>, max: &na::Vector2<f64>| {
+        let f = na::Vector2::new(
+            new_w as f64 / (max.x - min.x),
+            new_h as f64 / (max.y - min.y),
+        );
+        (f, -f.component_mul(min))
+    };
+    let (f0, c0) = fit(&inner_min, &inner_max);
+    let (f1, c1) = fit(&outer_min, &outer_max);
+    let f = f0 * (1.0 - alpha) + f1 * alpha;
+    let c = c0 * (1.0 - alpha) + c1 * alpha;
+    let camera_matrix = na::Matrix3::new(f.x, 0.0, c.x, 0.0, f.y, c.y, 0.0, 0.0, 1.0);
+
+    let roi_min = (f.component_mul(&inner_min) + c).map(|v| v.ceil().max(0.0));
+    let roi_max = (f.component_mul(&inner_max) + c)
+        .zip_map(&na::Vector2::new(new_w as f64, new_h as f64), |v, m| {
+            v.floor().min(m)
+        });
+    let roi = (
+        roi_min.x as u32,
+        roi_min.y as u32,
+        (roi_max.x - roi_min.x).max(0.0) as u32,
+        (roi_max.y - roi_min.y).max(0.0) as u32,
+    );
+    (camera_matrix, roi)
+}
+
+/// Like OpenCV `undistortPoints`, pixels to the normalized image plane (x/z, y/z), or to the
+/// pixels of `camera_matrix` if given. `None` for pixels outside of the image or the model's
+/// domain and for rays behind the camera.
+pub fn undistort_points(
+    model: &GenericModel<f64>,
+    p2ds: &[na::Vector2<f64>],
+    camera_matrix: Option<&na::Matrix3<f64>>,
+) -> Vec<Option<na::Vector2<f64>>> {
+    let (w, h) = (model.width(), model.height());
+    p2ds.par_iter()
+        .map(|p2d| {
+            if !(0.0..=w - 1.0).contains(&p2d.x) || !(0.0..=h - 1.0).contains(&p2d.y) {
+                return None;
+            }
+            let p3d = model.unproject_one(p2d);
+            if p3d.z <= 0.0 || (model.project_one(&p3d) - p2d).norm() >= 1.0 {
+                return None;
+            }
+            let normalized = p3d.xy() / p3d.z;
+            Some(match camera_matrix {
+                Some(k) => (k * normalized.push(1.0)).xy(),
+                None => normalized,
+            })
+        })
+        .collect()
+}
+
+/// `undistort_points` with the rays of `lut` if given, for many points of models without
+/// closed-form unprojection.
+pub fn undistort_points_with_lut(
+    model: &GenericModel<f64>,
+    lut: Option<&UnprojectionLut>,
+    p2ds: &[na::Vector2<f64>],
+    camera_matrix: Option<&na::Matrix3<f64>>,
+) -> Vec<Option<na::Vector2<f64>>> {
+    match lut {
+        Some(lut) => lut.undistort_points(p2ds, camera_matrix),
+        None => undistort_points(model, p2ds, camera_matrix),
+    }
+}
+
+/// Inverse of `undistort_points`, normalized points, or pixels of `camera_matrix` if given, to
+/// the pixels of the model. `None` for points projected outside of the image, and for all of
+/// them if `camera_matrix` isn't invertible, e.g. with a zero focal.
+pub fn distort_points(
+    model: &GenericModel<f64>,
+    p2ds: &[na::Vector2<f64>],
+    camera_matrix: Option<&na::Matrix3<f64>>,
+) -> Vec<Option<na::Vector2<f64>>> {
+    let k_inv = match camera_matrix.map(|k| k.try_inverse()) {
+        Some(None) => {
+            tracing::warn!("camera matrix is not invertible");
+            return vec![None; p2ds.len()];
+        }
+        Some(k_inv) => k_inv,
+        None => None,
+    };
+    let (w, h) = (model.width(), model.height());
+    p2ds.par_iter()
+        .map(|p2d| {
+            let p3d = match k_inv {
+                Some(k_inv) => k_inv * p2d.push(1.0),
+                None => p2d.push(1.0),
+            };
+            let p = model.project_one(&p3d);
+            let inside = (0.0..=w - 1.0).contains(&p.x) && (0.0..=h - 1.0).contains(&p.y);
+            inside.then_some(p)
+        })
+        .collect()
+}
+
+/// Where the rays of the target image come from.
+#[derive(Debug, Clone, Copy)]
+pub enum TargetProjection {
+    Pinhole(na::Matrix3<f64>),
+    /// Longitude and latitude linear in x and y, fields of view in radians.
+    Equirectangular {
+        hfov: f64,
+        vfov: f64,
+    },
+    /// Longitude linear in x, pinhole in y, horizontal field of view in radians.
+    Cylindrical {
+        hfov: f64,
+    },
+}
+
+impl TargetProjection {
+    /// Ray of the pixel (x, y) in the target camera frame, for image size `w_h`.
+    pub fn ray(&self, x: f64, y: f64, w_h: (u32, u32)) -> na::Vector3<f64> {
+        let (w, h) = (w_h.0 as f64, w_h.1 as f64);
+        match self {
+            TargetProjection::Pinhole(k) => na::Vector3::new(
+                (x - k[(0, 2)]) / k[(0, 0)],
+                (y - k[(1, 2)]) / k[(1, 1)],
+                1.0,
+            ),
+            TargetProjection::Equirectangular { hfov, vfov } => {
+                let lon = (x / (w - 1.0) - 0.5) * hfov;
+                let lat = (y / (h - 1.0) - 0.5) * vfov;
+                na::Vector3::new(lat.cos() * lon.sin(), lat.sin(), lat.cos() * lon.cos())
+            }
+            TargetProjection::Cylindrical { hfov } => {
+                let focal = (w - 1.0) / hfov;
+                let lon = (x - (w - 1.0) / 2.0) / focal;
+                na::Vector3::new(lon.sin(), (y - (h - 1.0) / 2.0) / focal, lon.cos())
+            }
+        }
+    }
+}
+
+/// Remap tables from a calibrated camera to a target camera.
+pub struct Undistorter {
+    pub projection: TargetProjection,
+    pub w_h: (u32, u32),
+    pub interpolation: Interpolation,
+    pub border: Border,
+    rotation_inv: na::Rotation3<f64>,
+    xmap: na::DMatrix<f32>,
+    ymap: na::DMatrix<f32>,
+}
+
+impl Undistorter {
+    /// `rotation` rotates the target camera, e.g. for rectification.
+    pub fn new(
+        model: &GenericModel<f64>,
+        camera_matrix: &na::Matrix3<f64>,
+        w_h: (u32, u32),
+        rotation: Option<na::Rotation3<f64>>,
+    ) -> Undistorter {
+        let projection = TargetProjection::Pinhole(*camera_matrix);
+        let rotation_inv = rotation.unwrap_or_else(na::Rotation3::identity).inverse();
+        let rays: Vec<_> = (0..w_h.1)
+            .flat_map(|y| (0..w_h.0).map(move |x| (x, y)))
+            .map(|(x, y)| rotation_inv * projection.ray(x as f64, y as f64, w_h))
+            .collect();
+        Undistorter::from_rays(model, projection, rotation_inv, &rays, w_h)
+    }
+
+    /// Maps of the rays of the target pixels in row major order.
+    fn from_rays(
+        model: &GenericModel<f64>,
+        projection: TargetProjection,
+        rotation_inv: na::Rotation3<f64>,
+        rays: &[na::Vector3<f64>],
+        w_h: (u32, u32),
+    ) -> Undistorter {
+        // remap reads the maps as row major buffers
+        let (xvec, yvec): (Vec<_>, Vec<_>) = batch::project(model, rays)
+            .iter()
+            .map(|p2d| match p2d {
+                Some(p2d) => (p2d.x as f32, p2d.y as f32),
+                None => (f32::NAN, f32::NAN),
+            })
+            .unzip();
+        Undistorter {
+            projection,
+            w_h,
+            interpolation: Interpolation::default(),
+            border: Border::default(),
+            rotation_inv,
+            xmap: na::DMatrix::from_vec(w_h.1 as usize, w_h.0 as usize, xvec),
+            ymap: na::DMatrix::from_vec(w_h.1 as usize, w_h.0 as usize, yvec),
+        }
+    }
+
+    /// Remap to any target projection.
+    pub fn with_projection(
+        model: &GenericModel<f64>,
+        projection: TargetProjection,
+        w_h: (u32, u32),
+    ) -> Undistorter {
+        if let TargetProjection::Pinhole(camera_matrix) = projection {
+            return Undistorter::new(model, &camera_matrix, w_h, None);
+        }
+        let rays: Vec<_> = (0..w_h.1)
+            .flat_map(|y| (0..w_h.0).map(move |x| (x, y)))
+            .map(|(x, y)| projection.ray(x as f64, y as f64, w_h))
+            .collect();
+        Undistorter::from_rays(model, projection, na::Rotation3::identity(), &rays, w_h)
+    }
+
+    /// Target focal is between cropping the invalid pixels (0.0) and keeping the whole field of view (1.0).
+    pub fn with_balance(
+        model: &GenericModel<f64>,
+        balance: f64,
+        w_h: Option<(u32, u32)>,
+    ) -> Undistorter {
+        let w_h = w_h.unwrap_or((model.width() as u32, model.height() as u32));
+        let camera_matrix = model.estimate_new_camera_matrix_for_undistort(balance, Some(w_h));
+        Undistorter::new(model, &camera_matrix, w_h, None)
+    }
+
+    /// Centered target camera with the horizontal field of view `hfov_deg`.
+    pub fn with_fov(
+        model: &GenericModel<f64>,
+        hfov_deg: f64,
+        w_h: Option<(u32, u32)>,
+    ) -> Undistorter {
+        let w_h = w_h.unwrap_or((model.width() as u32, model.height() as u32));
+        // from the centers of the border pixels like `centered_camera_matrix` and `fov_deg`
+        let focal = (w_h.0 as f64 - 1.0) / 2.0 / (hfov_deg.to_radians() / 2.0).tan();
+        Undistorter::new(model, &centered_camera_matrix(focal, w_h), w_h, None)
+    }
+
+    /// Centered target camera with `zoom` times the calibrated focal.
+    pub fn with_zoom(model: &GenericModel<f64>, zoom: f64, w_h: Option<(u32, u32)>) -> Undistorter {
+        let w_h = w_h.unwrap_or((model.width() as u32, model.height() as u32));
+        let params = model.params();
+        let focal = zoom * (params[0] + params[1]) / 2.0;
+        Undistorter::new(model, &centered_camera_matrix(focal, w_h), w_h, None)
+    }
+
+    /// Horizontal and vertical field of view of the target camera in degrees, between the
+    /// centers of the border pixels.
+    pub fn fov_deg(&self) -> (f64, f64) {
+        let (w, h) = (self.w_h.0 as f64, self.w_h.1 as f64);
+        match self.projection {
+            TargetProjection::Pinhole(k) => (
+                ((k[(0, 2)] / k[(0, 0)]).atan() + ((w - 1.0 - k[(0, 2)]) / k[(0, 0)]).atan())
+                    .to_degrees(),
+                ((k[(1, 2)] / k[(1, 1)]).atan() + ((h - 1.0 - k[(1, 2)]) / k[(1, 1)]).atan())
+                    .to_degrees(),
+            ),
+            TargetProjection::Equirectangular { hfov, vfov } => {
+                (hfov.to_degrees(), vfov.to_degrees())
+            }
+            TargetProjection::Cylindrical { hfov } => {
+                let half_h = (h - 1.0) / 2.0 * hfov / (w - 1.0);
+                (hfov.to_degrees(), 2.0 * half_h.atan().to_degrees())
+            }
+        }
+    }
+
+    /// The target camera as a model without distortion, only for pinhole targets.
+    pub fn pinhole_model(&self) -> Option<GenericModel<f64>> {
+        let TargetProjection::Pinhole(k) = &self.projection else {
+            return None;
+        };
+        let params = na::dvector![
+            k[(0, 0)],
+            k[(1, 1)],
+            k[(0, 2)],
+            k[(1, 2)],
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            0.0
+        ];
+        Some(GenericModel::OpenCVModel5(OpenCVModel5::new(
+            &params, self.w_h.0, self.w_h.1,
+        )))
+    }
+
+    pub fn maps(&self) -> (&na::DMatrix<f32>, &na::DMatrix<f32>) {
+        (&self.xmap, &self.ymap)
+    }
+
+    pub fn with_interpolation(mut self, interpolation: Interpolation, border: Border) -> Self {
+        self.interpolation = interpolation;
+        self.border = border;
+        self
+    }
+
+    /// Drops the target pixels whose rays don't come back from the source pixels they map to
+    /// with the rays of `lut`, e.g. the pixels beyond the domain of a fisheye model.
+    pub fn with_lut(mut self, lut: &UnprojectionLut) -> Self {
+        let max_angle = LUT_RAY_TOLERANCE.to_radians();
+        let w = self.w_h.0 as usize;
+        // the maps are row major buffers
+        let xmap = self.xmap.as_mut_slice();
+        let ymap = self.ymap.as_mut_slice();
+        for (i, (x, y)) in xmap.iter_mut().zip(ymap.iter_mut()).enumerate() {
+            let source = na::Vector2::new(*x as f64, *y as f64);
+            let ray = self.rotation_inv
+                * self
+                    .projection
+                    .ray((i % w) as f64, (i / w) as f64, self.w_h);
+            if !lut
+                .unproject_one(&source)
+                .is_some_and(|r| r.angle(&ray) < max_angle)
+            {
+                *x = f32::NAN;
+                *y = f32::NAN;
+            }
+        }
+        self
+    }
+
+    pub fn undistort(&self, img: &DynamicImage) -> DynamicImage {
+        remap(img, &self.xmap, &self.ymap, self.interpolation, self.border)
+    }
+}
+
+/// Undistort every png and jpg in `input_folder` into `output_folder` with the same file names.
+/// Returns the number of written images.
+#[cfg(feature = "io")]
+pub fn undistort_folder(
+    undistorter: &Undistorter,
+    input_folder: &str,
+    output_folder: &str,
+) -> usize {
+    undistort_folder_with(
+        |img| undistorter.undistort(img),
+        input_folder,
+        output_folder,
+    )
+}
+
+/// `undistort_folder` with another implementation of `Undistorter::undistort`, e.g. on the gpu.
+#[cfg(feature = "io")]
+pub fn undistort_folder_with(
+    undistort: impl Fn(&DynamicImage) -> DynamicImage + Sync,
+    input_folder: &str,
+    output_folder: &str,
+) -> usize {
+    use indicatif::ParallelProgressIterator;
+
+    let mut img_paths: Vec<_> = ["png", "jpg", "jpeg"]
+        .iter()
+        .flat_map(|ext| glob::glob(&format!("{}/*.{}", input_folder, ext)).expect("failed"))
+        .filter_map(|p| p.ok())
+        .collect();
+    img_paths.sort();
+    std::fs::create_dir_all(output_folder).expect("Valid path");
+    img_paths
+        .par_iter()
+        .progress_count(img_paths.len() as u64)
+        .filter(|path| {
+            let Ok(img) = image::ImageReader::open(path).unwrap().decode() else {
+                tracing::warn!("failed to decode {}", path.display());
+                return false;
+            };
+            let output_path = std::path::Path::new(output_folder).join(path.file_name().unwrap());
+            undistort(&img).save(output_path).is_ok()
+        })
+        .count()
+}
+
+/// Width, height and frame rate of the first video stream from the csv output of `ffprobe`.
+#[cfg(feature = "io")]
+fn parse_video_probe(probe: &str) -> Result<(u32, u32, f64), String> {
+    let fields: Vec<&str> = probe.trim().split(',').collect();
+    let [w, h, frame_rate] = fields[..] else {
+        return Err(format!("unexpected ffprobe output '{}'", probe.trim()));
+    };
+    let w = w
+        .parse()
+        .map_err(|_| format!("invalid video width '{}'", w))?;
+    let h = h
+        .parse()
+        .map_err(|_| format!("invalid video height '{}'", h))?;
+    let frame_rate = match frame_rate.split_once('/') {
+        Some((num, den)) => num
+            .parse::<f64>()
+            .ok()
+            .zip(den.parse::<f64>().ok())
+            .map(|(n, d)| n / d),
+        None => frame_rate.parse().ok(),
+    }
+    .filter(|r| r.is_finite() && *r > 0.0)
+    .ok_or(format!("invalid video frame rate '{}'", frame_rate))?;
+    Ok((w, h, frame_rate))
+}
+
+/// Presentation time in seconds of every frame from the csv output of `ffprobe`, frames without
+/// one are a frame after the previous.
+#[cfg(feature = "io")]
+fn parse_frame_times(probe: &str, frame_rate: f64) -> Vec<f64> {
+    let mut times: Vec<f64> = Vec::new();
+    for line in probe.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let time = line
+            .trim_end_matches(',')
+            .parse()
+            .ok()
+            .unwrap_or_else(|| times.last().map_or(0.0, |t| t + 1.0 / frame_rate));
+        times.push(time);
+    }
+    times
+}
+
+#[cfg(feature = "io")]
+fn ebml_element(id: u32, data: &[u8]) -> Vec<u8> {
+    let mut element: Vec<u8> = id
+        .to_be_bytes()
+        .into_iter()
+        .skip_while(|&b| b == 0)
+        .collect();
+    element.extend(ebml_size(data.len() as u64));
+    element.extend_from_slice(data);
+    element
+}
+
+/// Sizes are always written in 8 bytes.
+#[cfg(feature = "io")]
+fn ebml_size(size: u64) -> [u8; 8] {
+    (size | 1 << 56).to_be_bytes()
+}
+
+#[cfg(feature = "io")]
+fn ebml_uint(id: u32, v: u64) -> Vec<u8> {
+    ebml_element(id, &v.to_be_bytes())
+}
+
+/// Header of a Matroska stream of rgb24 frames, the only container `ffmpeg` reads raw frames
+/// with a time stamp each from.
+#[cfg(feature = "io")]
+fn matroska_header(w: u32, h: u32) -> Vec<u8> {
+    let ebml = [
+        ebml_uint(0x4286, 1),
+        ebml_uint(0x42F7, 1),
+        ebml_uint(0x42F2, 4),
+        ebml_uint(0x42F3, 8),
+        ebml_element(0x4282, b"matroska"),
+        ebml_uint(0x4287, 4),
+        ebml_uint(0x4285, 2),
+    ]
+    .concat();
+    // time stamps in us
+    let info = ebml_uint(0x2AD7B1, 1000);
+    let video = [
+        ebml_uint(0xB0, w as u64),
+        ebml_uint(0xBA, h as u64),
+        ebml_element(0x2EB524, b"RGB\x18"),
+    ]
+    .concat();
+    let track = [
+        ebml_uint(0xD7, 1),
+        ebml_uint(0x73C5, 1),
+        ebml_uint(0x83, 1),
+        ebml_element(0x86, b"V_UNCOMPRESSED"),
+        ebml_element(0xE0, &video),
+    ]
+    .concat();
+    let mut header = ebml_element(0x1A45DFA3, &ebml);
+    // segment of unknown size
+    header.extend([
+        0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    ]);
+    header.extend(ebml_element(0x1549A966, &info));
+    header.extend(ebml_element(0x1654AE6B, &ebml_element(0xAE, &track)));
+    header
+}
+
+/// Cluster of a single key frame at `time_us`, without the frame data following it.
+#[cfg(feature = "io")]
+fn matroska_frame_header(time_us: u64, frame_len: usize) -> Vec<u8> {
+    let timestamp = ebml_uint(0xE7, time_us);
+    // track 1, time relative to the cluster 0, key frame
+    let block_header = [0x81, 0x00, 0x00, 0x80];
+    let block_len = block_header.len() + frame_len;
+    let mut cluster = vec![0x1F, 0x43, 0xB6, 0x75];
+    cluster.extend(ebml_size((timestamp.len() + 1 + 8 + block_len) as u64));
+    cluster.extend(timestamp);
+    cluster.push(0xA3);
+    cluster.extend(ebml_size(block_len as u64));
+    cluster.extend(block_header);
+    cluster
+}
+
+/// Undistort a video with the `ffmpeg` and `ffprobe` executables, the time stamp of every frame
+/// and the audio are kept. Returns the number of frames.
+#[cfg(feature = "io")]
+pub fn undistort_video(
+    undistorter: &Undistorter,
+    input: &str,
+    output: &str,
+) -> Result<usize, String> {
+    use std::io::{Read, Write};
+    use std::process::{Command, Stdio};
+
+    let ffprobe = |entries: &str| {
+        Command::new("ffprobe")
+            .args(["-v", "error", "-select_streams", "v:0"])
+            .args(["-show_entries", entries])
+            .args(["-of", "csv=p=0", input])
+            .output()
+            .map(|out| String::from_utf8_lossy(&out.stdout).to_string())
+            .map_err(|e| format!("ffprobe is required for videos: {}", e))
+    };
+    let (w, h, frame_rate) = parse_video_probe(&ffprobe("stream=width,height,r_frame_rate")?)?;
+    let frame_times = parse_frame_times(&ffprobe("frame=best_effort_timestamp_time")?, frame_rate);
+
+    // one raw frame for every decoded frame, without duplicating or dropping any for a constant
+    // frame rate
+    let mut decoder = Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-i", input])
+        .args(["-fps_mode", "passthrough"])
+        .args(["-f", "rawvideo", "-pix_fmt", "rgb24", "-"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("ffmpeg is required for videos: {}", e))?;
+    let (new_w, new_h) = undistorter.w_h;
+    let mut encoder = match Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-y", "-copyts"])
+        .args(["-f", "matroska", "-i", "-", "-i", input])
+        .args(["-map", "0:v", "-map", "1:a?", "-c:a", "copy"])
+        .args(["-fps_mode", "passthrough"])
+        .args(["-c:v", "libx264", "-pix_fmt", "yuv420p", output])
+        .stdin(Stdio::piped())
+        .spawn()
+    {
+        Ok(encoder) => encoder,
+        Err(e) => {
+            let _ = decoder.kill();
+            let _ = decoder.wait();
+            return Err(format!("ffmpeg is required for videos: {}", e));
+        }
+    };
+
+    let mut reader = decoder.stdout.take().unwrap();
+    let mut writer = encoder.stdin.take().unwrap();
+    let mut buffer = vec![0u8; (w * h * 3) as usize];
+    let mut count = 0;
+    let mut result = writer.write_all(&matroska_header(new_w, new_h));
+    while result.is_ok() && reader.read_exact(&mut buffer).is_ok() {
+        let frame = image::RgbImage::from_raw(w, h, buffer.clone()).unwrap();
+        let undistorted = undistorter
+            .undistort(&DynamicImage::ImageRgb8(frame))
+            .to_rgb8();
+        let time = frame_times.get(count).copied().unwrap_or_else(|| {
+            frame_times.last().map_or(count as f64 / frame_rate, |t| {
+                t + (count + 1 - frame_times.len()) as f64 / frame_rate
+            })
+        });
+        let time_us = (time * 1e6).round().max(0.0) as u64;
+        result = writer
+            .write_all(&matroska_frame_header(time_us, undistorted.len()))
+            .and_then(|_| writer.write_all(&undistorted));
+        if result.is_ok() {
+            count += 1;
+        }
+    }
+    // the decoder blocks on a full pipe if it isn't read to the end
+    drop(reader);
+    drop(writer);
+    if result.is_err() {
+        let _ = decoder.kill();
+    }
+    let _ = decoder.wait();
+    let encoded = encoder.wait();
+    result.map_err(|e| format!("ffmpeg encoder stopped at frame {}: {}", count, e))?;
+    match encoded {
+        Ok(status) if status.success() => Ok(count),
+        Ok(status) => Err(format!("ffmpeg encoder failed with {}", status)),
+        Err(e) => Err(format!("ffmpeg encoder: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::eucm;
+
+    /// Id, data range and the end of the EBML element at `pos`.
+    #[cfg(feature = "io")]
+    fn read_element(bytes: &[u8], pos: usize) -> (u32, std::ops::Range<usize>) {
+        let id_len = bytes[pos].leading_zeros() as usize + 1;
+        let id = bytes[pos..pos + id_len]
+            .iter()
+            .fold(0u32, |id, &b| id << 8 | b as u32);
+        let size_pos = pos + id_len;
+        assert_eq!(bytes[size_pos], 0x01);
+        let size =
+            u64::from_be_bytes(bytes[size_pos..size_pos + 8].try_into().unwrap()) & !(0xFF << 56);
+        let start = size_pos + 8;
+        (id, start..start + size as usize)
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn video_probe() {
+        assert_eq!(
+            parse_video_probe("1920,1080,30000/1001\n").unwrap(),
+            (1920, 1080, 30000.0 / 1001.0)
+        );
+        assert!(parse_video_probe("").is_err());
+        assert!(parse_video_probe("1920,1080").is_err());
+        assert!(parse_video_probe("1920,abc,30/1").is_err());
+        assert!(parse_video_probe("1920,1080,0/0").is_err());
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn variable_frame_times() {
+        let times = parse_frame_times("0.000000\n0.040000,\nN/A\n0.200000\n", 25.0);
+        assert_eq!(times, vec![0.0, 0.04, 0.08, 0.2]);
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn matroska_stream() {
+        let header = matroska_header(4, 2);
+        let (id, ebml) = read_element(&header, 0);
+        assert_eq!(id, 0x1A45DFA3);
+        let mut pos = ebml.start;
+        let mut doc_type = None;
+        while pos < ebml.end {
+            let (child_id, child) = read_element(&header, pos);
+            if child_id == 0x4282 {
+                doc_type = Some(&header[child.clone()]);
+            }
+            pos = child.end;
+        }
+        assert_eq!(pos, ebml.end);
+        assert_eq!(doc_type, Some(&b"matroska"[..]));
+        // unknown size segment
+        assert_eq!(
+            &header[ebml.end..ebml.end + 5],
+            &[0x18, 0x53, 0x80, 0x67, 0x01]
+        );
+        let (info_id, info) = read_element(&header, ebml.end + 12);
+        assert_eq!(info_id, 0x1549A966);
+        let (tracks_id, tracks) = read_element(&header, info.end);
+        assert_eq!(tracks_id, 0x1654AE6B);
+        assert_eq!(tracks.end, header.len());
+
+        let frame = [7u8; 4 * 2 * 3];
+        let mut cluster = matroska_frame_header(1_234_567, frame.len());
+        cluster.extend(frame);
+        let (cluster_id, content) = read_element(&cluster, 0);
+        assert_eq!(cluster_id, 0x1F43B675);
+        assert_eq!(content.end, cluster.len());
+        let (timestamp_id, timestamp) = read_element(&cluster, content.start);
+        assert_eq!(timestamp_id, 0xE7);
+        assert_eq!(
+            u64::from_be_bytes(cluster[timestamp.clone()].try_into().unwrap()),
+            1_234_567
+        );
+        let (block_id, block) = read_element(&cluster, timestamp.end);
+        assert_eq!(block_id, 0xA3);
+        assert_eq!(block.end, cluster.len());
+        assert_eq!(&cluster[block.start + 4..], &frame);
+    }
+
+    #[test]
+    fn distort_undistort_round_trip() {
+        let model = eucm();
+        let k = centered_camera_matrix(400.0, (1280, 800));
+        let p2ds = vec![
+            na::Vector2::new(100.0, 700.0),
+            na::Vector2::new(640.0, 20.0),
+        ];
+        let undistorted: Vec<_> = undistort_points(&model, &p2ds, Some(&k))
+            .into_iter()
+            .map(Option::unwrap)
+            .collect();
+        let distorted = distort_points(&model, &undistorted, Some(&k));
+        for (p, d) in p2ds.iter().zip(distorted) {
+            assert!((p - d.unwrap()).norm() < 1e-6);
+        }
+        let singular = centered_camera_matrix(0.0, (1280, 800));
+        assert!(distort_points(&model, &undistorted, Some(&singular))
+            .iter()
+            .all(Option::is_none));
+    }
+
+    #[test]
+    fn with_fov_matches_fov() {
+        let model = eucm();
+        for hfov in [60.0, 90.0, 120.0] {
+            let undistorter = Undistorter::with_fov(&model, hfov, Some((64, 40)));
+            let (h, v) = undistorter.fov_deg();
+            assert!((h - hfov).abs() < 1e-9, "{h} != {hfov}");
+            let half_v = (hfov.to_radians() / 2.0).tan() * 39.0 / 63.0;
+            assert!((v - 2.0 * half_v.atan().to_degrees()).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn lut_drops_only_invalid_pixels() {
+        let model = eucm();
+        let lut = UnprojectionLut::new(&model);
+        let pinhole = Undistorter::with_fov(&model, 60.0, Some((64, 40)));
+        let masked = Undistorter::with_fov(&model, 60.0, Some((64, 40))).with_lut(&lut);
+        assert_eq!(pinhole.maps(), masked.maps());
+
+        let projection = TargetProjection::Equirectangular {
+            hfov: 1.8 * std::f64::consts::PI,
+            vfov: 0.9 * std::f64::consts::PI,
+        };
+        let panorama = Undistorter::with_projection(&model, projection, (64, 32));
+        let masked = Undistorter::with_projection(&model, projection, (64, 32)).with_lut(&lut);
+        let (xmap, _) = masked.maps();
+        assert!(xmap.iter().any(|x| x.is_nan()));
+        assert!(xmap
+            .iter()
+            .zip(panorama.maps().0.iter())
+            .all(|(m, p)| m.is_nan() || m == p));
+    }
+}
@@ -1,20 +1,27 @@
 use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 
+use crate::batch;
 use crate::detected_points::{FeaturePoint, FrameFeature};
-use crate::optimization::{homography_to_focal, init_pose, radial_distortion_homography};
-use crate::types::{CalibParams, Intrinsics, RvecTvec, ToRvecTvec};
-use crate::visualization::rerun_shift;
+use crate::init_diagnostic::InitAttempt;
+use crate::observer::{NoopObserver, PipelineObserver};
+use crate::optimization::{
+    rvec_name, tvec_name, CalibVariables, CustomResiduals, Plateau, SchurOptimizer,
+};
+use crate::types::{
+    CalibParams, CalibrationReport, CornerResidual, ErrorHistogram, FrameReport, Intrinsics,
+    RadiusBin, ResidualCell, RvecTvec, ToRvecTvec, HISTOGRAM_BIN_SIZE, HISTOGRAM_MAX_ERROR,
+};
 
 use super::optimization::factors::*;
 use super::types::Vec3DVec;
 use camera_intrinsic_model::*;
-use log::debug;
 use nalgebra as na;
 use rand::seq::SliceRandom;
-use rerun::RecordingStream;
+use rayon::prelude::*;
 use tiny_solver::loss_functions::HuberLoss;
-use tiny_solver::Optimizer;
+use tiny_solver::{Optimizer, OptimizerOptions};
+use tracing::{debug, info, instrument, trace, warn};
 
 pub fn rtvec_to_na_dvec(
     rtvec: ((f64, f64, f64), (f64, f64, f64)),
@@ -25,7 +32,7 @@ pub fn rtvec_to_na_dvec(
     )
 }
 
-fn set_problem_parameter_bound(
+pub(crate) fn set_problem_parameter_bound(
     params_name: &str,
     problem: &mut tiny_solver::Problem,
     generic_camera: &GenericModel<f64>,
@@ -37,7 +44,7 @@ fn set_problem_parameter_bound(
     problem.set_variable_bounds(params_name, 2 - shift, 0.0, generic_camera.width());
     problem.set_variable_bounds(params_name, 3 - shift, 0.0, generic_camera.height());
     for (distortion_idx, (lower, upper)) in generic_camera.distortion_params_bound() {
-        log::trace!(
+        trace!(
             "set params bound {} {} {}",
             distortion_idx - shift,
             lower,
@@ -46,7 +53,7 @@ fn set_problem_parameter_bound(
         problem.set_variable_bounds(params_name, distortion_idx - shift, lower, upper);
     }
 }
-fn set_problem_parameter_disabled(
+pub(crate) fn set_problem_parameter_disabled(
     params_name: &str,
     problem: &mut tiny_solver::Problem,
     init_values: &mut HashMap<String, na::DVector<f64>>,
@@ -59,7 +66,7 @@ fn set_problem_parameter_disabled(
         let distortion_idx = generic_camera.params().len() - 1 - shift - i;
         problem.fix_variable(params_name, distortion_idx);
         let params = init_values.get_mut(params_name).unwrap();
-        log::trace!(
+        trace!(
             "shift {} distortion {} {:?}",
             shift,
             distortion_idx,
@@ -71,14 +78,14 @@ fn set_problem_parameter_disabled(
 
 fn features_avg_center(features: &HashMap<u32, FeaturePoint>) -> glam::Vec2 {
     features
-        .iter()
-        .map(|(_, p)| p.p2d)
+        .values()
+        .map(|p| p.p2d)
         .reduce(|acc, e| acc + e)
         .unwrap()
         / features.len() as f32
 }
 fn features_covered_area(features: &HashMap<u32, FeaturePoint>) -> f32 {
-    let (xmin, ymin, xmax, ymax) = features.iter().map(|(_, p)| p.p2d).fold(
+    let (xmin, ymin, xmax, ymax) = features.values().map(|p| p.p2d).fold(
         (f32::MAX, f32::MAX, f32::MIN, f32::MIN),
         |acc, e| {
             let xmin = acc.0.min(e.x);
@@ -91,68 +98,95 @@ fn features_covered_area(features: &HashMap<u32, FeaturePoint>) -> f32 {
     (xmax - xmin) * (ymax - ymin)
 }
 
+/// Same as `SchurOptimizer::optimize`, but reports every iteration to the observer if it asks
+/// for it.
+pub(crate) fn optimize_with_observer(
+    problem: &tiny_solver::Problem,
+    initial_values: &HashMap<String, na::DVector<f64>>,
+    stage: &str,
+    plateau: Option<Plateau>,
+    observer: &dyn PipelineObserver,
+) -> Option<HashMap<String, na::DVector<f64>>> {
+    let optimizer = SchurOptimizer {
+        plateau,
+        ..Default::default()
+    };
+    if !observer.observe_iterations() {
+        return optimizer.optimize(problem, initial_values, None);
+    }
+    let options = OptimizerOptions::default();
+    let one_step = OptimizerOptions {
+        max_iteration: 1,
+        ..Default::default()
+    };
+    let residual_norm = |values: &HashMap<String, na::DVector<f64>>| {
+        problem.compute_residual_and_jacobian(values).0.norm_l2()
+    };
+    let mut values = initial_values.clone();
+    let mut last_err = residual_norm(&values);
+    let mut errors = vec![last_err];
+    for iteration in 0..options.max_iteration {
+        values = optimizer.optimize(problem, &values, Some(one_step.clone()))?;
+        let current_err = residual_norm(&values);
+        if current_err.is_nan() {
+            return None;
+        }
+        observer.on_iteration_finished(stage, iteration, current_err);
+        errors.push(current_err);
+        let decrease = (last_err - current_err).abs();
+        if current_err < options.min_error_threshold
+            || optimizer.plateau.is_some_and(|p| p.is_reached(&errors))
+            || decrease < options.min_abs_error_decrease_threshold
+            || decrease / last_err < options.min_rel_error_decrease_threshold
+        {
+            break;
+        }
+        last_err = current_err;
+    }
+    if optimizer.f32_linear_solve {
+        // the steps above are in f32
+        let optimizer = SchurOptimizer {
+            f32_linear_solve: false,
+            ..optimizer
+        };
+        values = optimizer.optimize(problem, &values, None)?;
+    }
+    Some(values)
+}
+
 fn vec2_distance2(v0: &glam::Vec2, v1: &glam::Vec2) -> f32 {
     let v = v0 - v1;
     v.x * v.x + v.y * v.y
 }
+/// Tries of `try_init_camera` on a frame pair before giving up on it.
+pub const INIT_ATTEMPTS: usize = 10;
+
+#[instrument(skip_all, name = "init")]
 pub fn try_init_camera(
     frame_feature0: &FrameFeature,
     frame_feature1: &FrameFeature,
     fixed_focal: Option<f64>,
 ) -> Option<GenericModel<f64>> {
-    // initialize focal length and undistorted p2d for init poses
-    let (lambda, h_mat) = radial_distortion_homography(frame_feature0, frame_feature1);
-
-    // focal
-    let f_option = homography_to_focal(&h_mat);
-    if f_option.is_none() {
-        println!("Initialization failed, try again.");
-        return None;
-    }
-    let unit_plane_focal = f_option.unwrap() as f64;
-    println!("focal {}", unit_plane_focal);
-
-    // poses
-    let (rvec0, tvec0) = rtvec_to_na_dvec(init_pose(frame_feature0, lambda));
-    let (rvec1, tvec1) = rtvec_to_na_dvec(init_pose(frame_feature1, lambda));
-    let rtvec0 = RvecTvec::new(&rvec0, &tvec0);
-    let rtvec1 = RvecTvec::new(&rvec1, &tvec1);
-
-    let half_w = frame_feature0.img_w_h.0 as f64 / 2.0;
-    let half_h = frame_feature0.img_w_h.1 as f64 / 2.0;
-    let half_img_size = half_h.max(half_w);
-    let init_f = if let Some(focal) = fixed_focal {
-        focal
+    let attempt = InitAttempt::new(frame_feature0, frame_feature1, fixed_focal);
+    if let Some(failure) = &attempt.failure {
+        warn!("Initialization failed, {}. Try again.", failure);
     } else {
-        unit_plane_focal * half_img_size
-    };
-    println!("init f {}", init_f);
-    let init_alpha = lambda.abs() as f64;
-    if let Some(initial_camera) = init_ucm(
-        frame_feature0,
-        frame_feature1,
-        &rtvec0,
-        &rtvec1,
-        init_f,
-        init_alpha,
-        fixed_focal.is_some(),
-    ) {
-        println!("Initialized {:?}", initial_camera);
-        if initial_camera.params()[0] == 0.0 {
-            println!("Failed to initialize UCM. Try again.");
-            None
-        } else {
-            Some(initial_camera)
-        }
-    } else {
-        None
+        debug!("Initialized {:?}", attempt.ucm);
     }
+    attempt.ucm
 }
 
+/// Two different frames to initialize the camera with, of the frames with the most corners the
+/// one covering the largest area and the one farthest from their average center, or two random
+/// ones. If a single frame has the most corners, e.g. partial views of a projector, all frames
+/// with detections are candidates. `None` with less than two frames with detections.
 pub fn find_best_two_frames_idx(
     detected_feature_frames: &[Option<FrameFeature>],
     random_pick: bool,
-) -> (usize, usize) {
+) -> Option<(usize, usize)> {
+    if detected_feature_frames.iter().flatten().count() < 2 {
+        return None;
+    }
     let mut max_detection = 0;
     let mut max_detection_idxs = Vec::new();
     for (i, f) in detected_feature_frames.iter().enumerate() {
@@ -169,15 +203,22 @@ pub fn find_best_two_frames_idx(
             }
         }
     }
+    if max_detection_idxs.len() < 2 {
+        max_detection_idxs = detected_feature_frames
+            .iter()
+            .enumerate()
+            .filter_map(|(i, f)| f.as_ref().map(|_| i))
+            .collect();
+    }
     if random_pick {
         let mut rng = rand::thread_rng();
         max_detection_idxs.shuffle(&mut rng);
-        return (max_detection_idxs[0], max_detection_idxs[1]);
+        return Some((max_detection_idxs[0], max_detection_idxs[1]));
     }
     let mut v0: Vec<_> = max_detection_idxs
         .iter()
         .map(|&i| {
-            let p_avg = features_avg_center(&detected_feature_frames[i].clone().unwrap().features);
+            let p_avg = features_avg_center(&detected_feature_frames[i].as_ref().unwrap().features);
             (i, p_avg)
         })
         .collect();
@@ -192,16 +233,20 @@ pub fn find_best_two_frames_idx(
     let mut v1: Vec<_> = max_detection_idxs
         .iter()
         .map(|&i| {
-            let area = features_covered_area(&detected_feature_frames[i].clone().unwrap().features);
+            let area =
+                features_covered_area(&detected_feature_frames[i].as_ref().unwrap().features);
             (i, area)
         })
         .collect();
     v1.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
 
     // (*v0[0].0, *v0.last().unwrap().0)
-    (v1.last().unwrap().0, v0.last().unwrap().0)
+    let largest = v1.last().unwrap().0;
+    let farthest = v0.iter().rev().map(|&(i, _)| i).find(|&i| i != largest)?;
+    Some((largest, farthest))
 }
 
+#[instrument(skip_all)]
 pub fn convert_model(
     source_model: &GenericModel<f64>,
     target_model: &mut GenericModel<f64>,
@@ -261,6 +306,7 @@ pub fn convert_model(
     target_model.set_params(result_params);
 }
 
+#[instrument(skip_all)]
 pub fn init_ucm(
     frame_feature0: &FrameFeature,
     frame_feature1: &FrameFeature,
@@ -316,7 +362,7 @@ pub fn init_ucm(
         init_focal_alpha_problem.fix_variable("params", 0);
     }
 
-    println!("init ucm init f {}", initial_values.get("params").unwrap());
+    debug!("init ucm init f {}", initial_values.get("params").unwrap());
 
     // optimize
     init_focal_alpha_problem.set_variable_bounds("params", 0, init_f / 3.0, init_f * 3.0);
@@ -324,8 +370,8 @@ pub fn init_ucm(
     if let Some(mut second_round_values) =
         optimizer.optimize(&init_focal_alpha_problem, &initial_values, None)
     {
-        println!(
-            "params after {:?}\n",
+        debug!(
+            "params after {:?}",
             second_round_values.get("params").unwrap()
         );
 
@@ -345,6 +391,8 @@ pub fn init_ucm(
                 true,
                 0,
                 fixed_focal,
+                None,
+                &NoopObserver,
             )
             .unwrap()
             .0,
@@ -360,6 +408,33 @@ pub fn calib_camera(
     xy_same_focal: bool,
     disabled_distortions: usize,
     fixed_focal: bool,
+    plateau: Option<Plateau>,
+    observer: &dyn PipelineObserver,
+) -> Option<(GenericModel<f64>, HashMap<usize, RvecTvec>)> {
+    calib_camera_with_custom_residuals(
+        frame_feature_list,
+        generic_camera,
+        xy_same_focal,
+        disabled_distortions,
+        fixed_focal,
+        plateau,
+        observer,
+        &[],
+    )
+}
+
+/// `calib_camera` with extra residual blocks added to the problem before solving.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all, fields(frames = frame_feature_list.len()))]
+pub fn calib_camera_with_custom_residuals(
+    frame_feature_list: &[Option<FrameFeature>],
+    generic_camera: &GenericModel<f64>,
+    xy_same_focal: bool,
+    disabled_distortions: usize,
+    fixed_focal: bool,
+    plateau: Option<Plateau>,
+    observer: &dyn PipelineObserver,
+    custom_residuals: &[&dyn CustomResiduals],
 ) -> Option<(GenericModel<f64>, HashMap<usize, RvecTvec>)> {
     let mut params = generic_camera.params();
     if xy_same_focal {
@@ -370,45 +445,75 @@ pub fn calib_camera(
     let mut initial_values =
         HashMap::<String, na::DVector<f64>>::from([("params".to_string(), params)]);
     debug!("init {:?}", initial_values);
-    let mut problem = tiny_solver::Problem::new();
-    let mut valid_indexes = Vec::new();
-    for (i, frame_feature) in frame_feature_list.iter().enumerate() {
-        if let Some(frame_feature) = frame_feature {
-            let mut p3ds = Vec::new();
-            let mut p2ds = Vec::new();
-            let rvec_name = format!("rvec{}", i);
-            let tvec_name = format!("tvec{}", i);
-            for fp in frame_feature.features.values() {
-                let cost = ReprojectionFactor::new(generic_camera, &fp.p3d, &fp.p2d, xy_same_focal);
-                problem.add_residual_block(
-                    2,
-                    &[("params", params_len), (&rvec_name, 3), (&tvec_name, 3)],
-                    Box::new(cost),
-                    Some(Box::new(HuberLoss::new(1.0))),
-                );
-                p3ds.push(fp.p3d);
-                p2ds.push(na::Vector2::new(fp.p2d.x as f64, fp.p2d.y as f64));
-            }
-            let undistorted = generic_camera.unproject(&p2ds);
-            let (p3ds, p2ds_z): (Vec<_>, Vec<_>) = undistorted
-                .iter()
-                .zip(p3ds)
-                .filter_map(|(p2, p3)| {
-                    p2.as_ref()
-                        .map(|p2| (p3, glam::Vec2::new(p2.x as f32, p2.y as f32)))
-                })
-                .unzip();
-            valid_indexes.push(i);
-            let (rvec, tvec) =
-                rtvec_to_na_dvec(sqpnp_simple::sqpnp_solve_glam(&p3ds, &p2ds_z).unwrap());
+    let frames: Vec<_> = frame_feature_list
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| Some((i, f.as_ref()?)))
+        .collect();
+    // the corners of all frames are unprojected together for the pose init, and the same
+    // list is reused for the residuals
+    let corners: Vec<_> = frames
+        .iter()
+        .flat_map(|(_, f)| f.features.values())
+        .collect();
+    let p2ds: Vec<_> = corners
+        .iter()
+        .map(|fp| na::Vector2::new(fp.p2d.x as f64, fp.p2d.y as f64))
+        .collect();
+    let rays = batch::unproject(generic_camera, &p2ds);
+    let frame_ranges: Vec<_> = frames
+        .iter()
+        .scan(0, |begin, (_, f)| {
+            *begin += f.features.len();
+            Some(*begin - f.features.len()..*begin)
+        })
+        .collect();
+    let poses: Vec<_> = frame_ranges
+        .par_iter()
+        .map_init(
+            || (Vec::new(), Vec::new()),
+            |(p3ds, p2ds_z), range| {
+                p3ds.clear();
+                p2ds_z.clear();
+                for (fp, ray) in corners[range.clone()].iter().zip(&rays[range.clone()]) {
+                    if let Some(ray) = ray {
+                        p3ds.push(fp.p3d);
+                        p2ds_z.push(glam::Vec2::new(ray.x as f32, ray.y as f32));
+                    }
+                }
+                rtvec_to_na_dvec(sqpnp_simple::sqpnp_solve_glam(p3ds, p2ds_z).unwrap())
+            },
+        )
+        .collect();
 
-            initial_values.entry(rvec_name).or_insert(rvec);
-            initial_values.entry(tvec_name).or_insert(tvec);
+    let mut problem = tiny_solver::Problem::new();
+    let mut valid_indexes = Vec::with_capacity(frames.len());
+    for (((i, _), range), (rvec, tvec)) in frames.iter().zip(frame_ranges).zip(poses) {
+        let rvec_name = rvec_name(*i);
+        let tvec_name = tvec_name(*i);
+        for fp in &corners[range] {
+            let cost = ReprojectionFactor::new(generic_camera, &fp.p3d, &fp.p2d, xy_same_focal)
+                .with_blur(fp.blur.as_ref());
+            problem.add_residual_block(
+                2,
+                &[("params", params_len), (&rvec_name, 3), (&tvec_name, 3)],
+                Box::new(cost),
+                Some(Box::new(HuberLoss::new(1.0))),
+            );
         }
+        valid_indexes.push(*i);
+        initial_values.entry(rvec_name).or_insert(rvec);
+        initial_values.entry(tvec_name).or_insert(tvec);
     }
 
-    let optimizer = tiny_solver::GaussNewtonOptimizer {};
-    // let initial_values = optimizer.optimize(&problem, &initial_values, None);
+    let variables = CalibVariables {
+        params_len,
+        xy_same_focal,
+        frame_indexes: &valid_indexes,
+    };
+    for custom in custom_residuals {
+        custom.add_residual_blocks(&mut problem, &mut initial_values, &variables);
+    }
 
     set_problem_parameter_bound("params", &mut problem, generic_camera, xy_same_focal);
     set_problem_parameter_disabled(
@@ -419,15 +524,23 @@ pub fn calib_camera(
         xy_same_focal,
         disabled_distortions,
     );
-    let result_option = optimizer.optimize(&problem, &initial_values, None);
+    let result_option =
+        optimize_with_observer(&problem, &initial_values, "calib_camera", plateau, observer);
     // check is some
     result_option.as_ref()?;
     let mut result = result_option.unwrap();
     if fixed_focal {
-        println!("set focal and opt again.");
+        debug!("set focal and opt again.");
         problem.fix_variable("params", 0);
         result.get_mut("params").unwrap()[0] = generic_camera.params()[0];
-        result = optimizer.optimize(&problem, &result, None).unwrap();
+        result = optimize_with_observer(
+            &problem,
+            &result,
+            "calib_camera_fixed_focal",
+            plateau,
+            observer,
+        )
+        .unwrap();
     }
 
     let mut new_params = result.get("params").unwrap().clone();
@@ -435,20 +548,17 @@ pub fn calib_camera(
         // remove fy
         new_params = new_params.clone().insert_row(1, new_params[0]);
     };
-    println!("params {}", new_params);
+    info!("params {}", new_params);
     let mut calibrated_camera = *generic_camera;
     calibrated_camera.set_params(&new_params);
     let rtvec_vec: HashMap<usize, RvecTvec> = valid_indexes
         .iter()
         .map(|&i| {
-            let rvec_name = format!("rvec{}", i);
-            let tvec_name = format!("tvec{}", i);
-
             (
                 i,
                 RvecTvec::new(
-                    &result.remove(&rvec_name).unwrap(),
-                    &result.remove(&tvec_name).unwrap(),
+                    &result.remove(&rvec_name(i)).unwrap(),
+                    &result.remove(&tvec_name(i)).unwrap(),
                 ),
             )
         })
@@ -456,73 +566,148 @@ pub fn calib_camera(
     Some((calibrated_camera, rtvec_vec))
 }
 
-pub fn na_isometry3_to_rerun_transform3d(transform: &na::Isometry3<f64>) -> rerun::Transform3D {
-    let t = (
-        transform.translation.x as f32,
-        transform.translation.y as f32,
-        transform.translation.z as f32,
-    );
-    let q_xyzw = (
-        transform.rotation.quaternion().i as f32,
-        transform.rotation.quaternion().j as f32,
-        transform.rotation.quaternion().k as f32,
-        transform.rotation.quaternion().w as f32,
-    );
-    rerun::Transform3D::from_translation_rotation(t, rerun::Quaternion::from_xyzw(q_xyzw.into()))
+/// Board pose of a frame with fixed intrinsics, SQPnP on the unprojected corners refined with
+/// the reprojection errors.
+pub fn solve_board_pose(
+    model: &GenericModel<f64>,
+    frame_feature: &FrameFeature,
+) -> Option<RvecTvec> {
+    let (p3ds, p2ds): (Vec<_>, Vec<_>) = frame_feature
+        .features
+        .values()
+        .map(|fp| (fp.p3d, na::Vector2::new(fp.p2d.x as f64, fp.p2d.y as f64)))
+        .unzip();
+    let (p3ds, p2ds_z): (Vec<_>, Vec<_>) = batch::unproject(model, &p2ds)
+        .iter()
+        .zip(p3ds)
+        .filter_map(|(ray, p3)| {
+            let ray = ray.filter(|ray| ray.z > 0.0)?;
+            Some((
+                p3,
+                glam::Vec2::new((ray.x / ray.z) as f32, (ray.y / ray.z) as f32),
+            ))
+        })
+        .unzip();
+    if p3ds.len() < 4 {
+        return None;
+    }
+    let (rvec, tvec) = rtvec_to_na_dvec(sqpnp_simple::sqpnp_solve_glam(&p3ds, &p2ds_z)?);
+
+    let mut problem = tiny_solver::Problem::new();
+    for fp in frame_feature.features.values() {
+        let cost = PoseReprojectionFactor::new(model, &fp.p3d, &fp.p2d);
+        problem.add_residual_block(
+            2,
+            &[("rvec", 3), ("tvec", 3)],
+            Box::new(cost),
+            Some(Box::new(HuberLoss::new(1.0))),
+        );
+    }
+    let initial_values = HashMap::from([("rvec".to_string(), rvec), ("tvec".to_string(), tvec)]);
+    let mut result =
+        tiny_solver::GaussNewtonOptimizer {}.optimize(&problem, &initial_values, None)?;
+    Some(RvecTvec::new(
+        &result.remove("rvec")?,
+        &result.remove("tvec")?,
+    ))
 }
 
-pub fn init_camera_extrinsic(cam_rtvecs: &[HashMap<usize, RvecTvec>]) -> Vec<RvecTvec> {
-    (0..cam_rtvecs.len())
-        .map(|cam_i| {
-            if cam_i == 0 {
-                return RvecTvec::new(
-                    &na::Vector3::zeros().to_dvec(),
-                    &na::Vector3::zeros().to_dvec(),
-                );
-            }
-            let cam_0_keys: HashSet<_> = cam_rtvecs[0].keys().cloned().collect();
-            let cam_i_keys: HashSet<_> = cam_rtvecs[cam_i].keys().cloned().collect();
-            let key_intersection: Vec<_> = cam_0_keys.intersection(&cam_i_keys).collect();
-            let t_0_b_and_t_i_b: Vec<_> = key_intersection
-                .into_iter()
-                .map(|k| {
-                    let t_0_b = cam_rtvecs[0].get(k).unwrap().to_na_isometry3();
-                    let t_i_b = cam_rtvecs[cam_i].get(k).unwrap().to_na_isometry3();
-                    (t_0_b, t_i_b)
-                })
-                .collect();
-            let mut problem = tiny_solver::Problem::new();
-            let t_i_0_init = t_0_b_and_t_i_b[0].1 * t_0_b_and_t_i_b[0].0.inverse();
-            for (t_0_b, t_i_b) in &t_0_b_and_t_i_b {
-                let cost = SE3Factor::new(t_0_b, t_i_b);
-                problem.add_residual_block(
-                    6,
-                    &[("rvec", 3), ("tvec", 3)],
-                    Box::new(cost),
-                    Some(Box::new(HuberLoss::new(0.5))),
-                );
-            }
-            let rvec = t_i_0_init.rotation.scaled_axis().to_dvec();
-            let tvec = na::dvector![
-                t_i_0_init.translation.x,
-                t_i_0_init.translation.y,
-                t_i_0_init.translation.z,
-            ];
-            let initial_values = HashMap::<String, na::DVector<f64>>::from([
-                ("rvec".to_string(), rvec),
-                ("tvec".to_string(), tvec),
-            ]);
-
-            let optimizer = tiny_solver::GaussNewtonOptimizer {};
-            let result = optimizer.optimize(&problem, &initial_values, None).unwrap();
-            println!("extrinsic cam{} cam0", cam_i);
-            println!("rvec: {}", result["rvec"]);
-            println!("tvec: {}", result["tvec"]);
-            RvecTvec::new(result.get("rvec").unwrap(), result.get("tvec").unwrap())
+/// `T_j_i` from the board poses of the frames seen by both cameras, `None` without common frames.
+fn init_relative_pose(
+    rtvecs_i: &HashMap<usize, RvecTvec>,
+    rtvecs_j: &HashMap<usize, RvecTvec>,
+) -> Option<na::Isometry3<f64>> {
+    let t_i_b_and_t_j_b: Vec<_> = rtvecs_i
+        .iter()
+        .filter_map(|(k, t_i_b)| {
+            let t_j_b = rtvecs_j.get(k)?;
+            Some((t_i_b.to_na_isometry3(), t_j_b.to_na_isometry3()))
         })
-        .collect()
+        .collect();
+    let (t_i_b, t_j_b) = t_i_b_and_t_j_b.first()?;
+    let t_j_i_init = t_j_b * t_i_b.inverse();
+    let mut problem = tiny_solver::Problem::new();
+    for (t_i_b, t_j_b) in &t_i_b_and_t_j_b {
+        let cost = SE3Factor::new(t_i_b, t_j_b);
+        problem.add_residual_block(
+            6,
+            &[("rvec", 3), ("tvec", 3)],
+            Box::new(cost),
+            Some(Box::new(HuberLoss::new(0.5))),
+        );
+    }
+    let rvec = t_j_i_init.rotation.scaled_axis().to_dvec();
+    let tvec = na::dvector![
+        t_j_i_init.translation.x,
+        t_j_i_init.translation.y,
+        t_j_i_init.translation.z,
+    ];
+    let initial_values = HashMap::<String, na::DVector<f64>>::from([
+        ("rvec".to_string(), rvec),
+        ("tvec".to_string(), tvec),
+    ]);
+
+    let optimizer = tiny_solver::GaussNewtonOptimizer {};
+    let result = optimizer.optimize(&problem, &initial_values, None)?;
+    Some(RvecTvec::new(result.get("rvec").unwrap(), result.get("tvec").unwrap()).to_na_isometry3())
+}
+
+/// `T_i_0` of each camera. A camera doesn't need to see the board together with cam0, the poses
+/// are chained along the camera pairs sharing the most frames (maximum spanning tree from cam0).
+/// `None` if some cameras never see the board together with the others.
+#[instrument(skip_all)]
+pub fn init_camera_extrinsic(cam_rtvecs: &[HashMap<usize, RvecTvec>]) -> Option<Vec<RvecTvec>> {
+    let cam_num = cam_rtvecs.len();
+    let shared_frames = |i: usize, j: usize| {
+        cam_rtvecs[i]
+            .keys()
+            .filter(|k| cam_rtvecs[j].contains_key(k))
+            .count()
+    };
+    let mut t_i_0: Vec<Option<na::Isometry3<f64>>> = vec![None; cam_num];
+    t_i_0[0] = Some(na::Isometry3::identity());
+    for _ in 1..cam_num {
+        let t_i_0_ref = &t_i_0;
+        let edge = (0..cam_num)
+            .filter(|&parent| t_i_0_ref[parent].is_some())
+            .flat_map(|parent| {
+                (0..cam_num)
+                    .filter(|&child| t_i_0_ref[child].is_none())
+                    .map(move |child| (parent, child))
+            })
+            .map(|(parent, child)| (parent, child, shared_frames(parent, child)))
+            .filter(|&(_, _, shared)| shared > 0)
+            .max_by_key(|&(_, _, shared)| shared);
+        let Some((parent, child, shared)) = edge else {
+            let missing: Vec<_> = (0..cam_num).filter(|&i| t_i_0[i].is_none()).collect();
+            warn!(
+                "cameras {:?} never see the board together with the others",
+                missing
+            );
+            return None;
+        };
+        let t_child_parent = init_relative_pose(&cam_rtvecs[parent], &cam_rtvecs[child])?;
+        let t = t_child_parent * t_i_0[parent].unwrap();
+        info!(
+            "extrinsic cam{} cam0 from cam{} with {} shared frames, rvec: {} tvec: {}",
+            child,
+            parent,
+            shared,
+            t.rotation.scaled_axis(),
+            t.translation.vector
+        );
+        t_i_0[child] = Some(t);
+    }
+    Some(
+        t_i_0
+            .into_iter()
+            .map(|t| t.unwrap().to_rvec_tvec())
+            .collect(),
+    )
 }
 
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all)]
 pub fn calib_all_camera_with_extrinsics(
     cameras: &[GenericModel<f64>],
     t_cam_i_0: &[RvecTvec],
@@ -531,6 +716,8 @@ pub fn calib_all_camera_with_extrinsics(
     xy_same_focal: bool,
     disabled_distortions: usize,
     cam0_fixed_focal: bool,
+    plateau: Option<Plateau>,
+    observer: &dyn PipelineObserver,
 ) -> Option<(Intrinsics, Vec<RvecTvec>, HashMap<usize, RvecTvec>)> {
     let mut problem = tiny_solver::Problem::new();
     let mut initial_values = HashMap::<String, na::DVector<f64>>::new();
@@ -562,7 +749,8 @@ pub fn calib_all_camera_with_extrinsics(
             for fp in frame_feature.features.values() {
                 if cam_idx == 0 {
                     let cost =
-                        ReprojectionFactor::new(generic_camera, &fp.p3d, &fp.p2d, xy_same_focal);
+                        ReprojectionFactor::new(generic_camera, &fp.p3d, &fp.p2d, xy_same_focal)
+                            .with_blur(fp.blur.as_ref());
                     problem.add_residual_block(
                         2,
                         &[
@@ -579,7 +767,8 @@ pub fn calib_all_camera_with_extrinsics(
                         &fp.p3d,
                         &fp.p2d,
                         xy_same_focal,
-                    );
+                    )
+                    .with_blur(fp.blur.as_ref());
                     problem.add_residual_block(
                         2,
                         &[
@@ -626,12 +815,16 @@ pub fn calib_all_camera_with_extrinsics(
         );
     }
     if cam0_fixed_focal {
-        println!("set focal");
+        debug!("set focal");
         problem.fix_variable("params0", 0);
     }
-    let optimizer = tiny_solver::GaussNewtonOptimizer {};
-
-    let result_option = optimizer.optimize(&problem, &initial_values, None);
+    let result_option = optimize_with_observer(
+        &problem,
+        &initial_values,
+        "calib_all_camera_with_extrinsics",
+        plateau,
+        observer,
+    );
     if let Some(mut result) = result_option {
         let mut result_intrinsics = Vec::new();
         let mut result_t_i_0 = Vec::new();
@@ -642,7 +835,7 @@ pub fn calib_all_camera_with_extrinsics(
                 // remove fy
                 new_params = new_params.clone().insert_row(1, new_params[0]);
             };
-            println!("params {}", new_params);
+            info!(cam_idx, "params {}", new_params);
             let mut calibrated_camera = *generic_camera;
             calibrated_camera.set_params(&new_params);
             result_intrinsics.push(calibrated_camera);
@@ -678,20 +871,113 @@ pub fn calib_all_camera_with_extrinsics(
     }
 }
 
+/// Corners with a reprojection error larger than this times the median of the camera are
+/// dropped by `refine_all_camera_with_extrinsics`.
+pub const RIG_OUTLIER_RATIO: f64 = 5.0;
+
+/// Final bundle adjustment of the rig after `calib_all_camera_with_extrinsics`. Every frame a
+/// camera detected the board in joins with the board pose of the rig, also the frames the
+/// camera couldn't get a pose from alone, and the outlier corners of each camera are dropped
+/// before all intrinsics, extrinsics and board poses are refined again.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all)]
+pub fn refine_all_camera_with_extrinsics(
+    cameras: &[GenericModel<f64>],
+    t_cam_i_0: &[RvecTvec],
+    board_rtvecs: &HashMap<usize, RvecTvec>,
+    cams_detected_feature_frames: &[Vec<Option<FrameFeature>>],
+    xy_same_focal: bool,
+    disabled_distortions: usize,
+    cam0_fixed_focal: bool,
+    plateau: Option<Plateau>,
+    observer: &dyn PipelineObserver,
+) -> Option<(Intrinsics, Vec<RvecTvec>, HashMap<usize, RvecTvec>)> {
+    let mut cam_rtvecs = Vec::new();
+    let mut inlier_frames = Vec::new();
+    for (cam_idx, model) in cameras.iter().enumerate() {
+        let t_i_0 = t_cam_i_0[cam_idx].to_na_isometry3();
+        let mut rtvecs: HashMap<_, _> = board_rtvecs
+            .iter()
+            .filter(|(i, _)| {
+                matches!(
+                    cams_detected_feature_frames[cam_idx].get(**i),
+                    Some(Some(_))
+                )
+            })
+            .map(|(&i, t_0_b)| (i, (t_i_0 * t_0_b.to_na_isometry3()).to_rvec_tvec()))
+            .collect();
+        let errors: HashMap<_, _> = rtvecs
+            .iter()
+            .map(|(&i, rtvec)| {
+                let frame = cams_detected_feature_frames[cam_idx][i].as_ref().unwrap();
+                let errors: HashMap<_, _> = reproject_frame(model, rtvec, frame)
+                    .into_iter()
+                    .map(|(id, p)| {
+                        let p2d = frame.features[&id].p2d;
+                        (
+                            id,
+                            (p - na::Vector2::new(p2d.x as f64, p2d.y as f64)).norm(),
+                        )
+                    })
+                    .collect();
+                (i, errors)
+            })
+            .collect();
+        let mut sorted: Vec<_> = errors.values().flat_map(|e| e.values().copied()).collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let max_error = sorted.get(sorted.len() / 2).copied().unwrap_or(0.0) * RIG_OUTLIER_RATIO;
+        let mut num_outliers = 0;
+        let frames: Vec<_> = cams_detected_feature_frames[cam_idx]
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                let mut frame = frame.clone()?;
+                if let Some(errors) = errors.get(&i) {
+                    frame.features.retain(|id, _| errors[id] <= max_error);
+                    num_outliers += errors.len() - frame.features.len();
+                }
+                Some(frame)
+            })
+            .collect();
+        // frames without corners left would leave their board poses out of the problem
+        rtvecs.retain(|&i, _| frames[i].as_ref().is_some_and(|f| !f.features.is_empty()));
+        info!(
+            "cam{} rig bundle adjustment with {} frames, {} outlier corners above {:.3} px",
+            cam_idx,
+            rtvecs.len(),
+            num_outliers,
+            max_error
+        );
+        cam_rtvecs.push(rtvecs);
+        inlier_frames.push(frames);
+    }
+    calib_all_camera_with_extrinsics(
+        cameras,
+        t_cam_i_0,
+        &cam_rtvecs,
+        &inlier_frames,
+        xy_same_focal,
+        disabled_distortions,
+        cam0_fixed_focal,
+        plateau,
+        observer,
+    )
+}
+
+#[instrument(skip_all, fields(cam_idx))]
 pub fn validation(
     cam_idx: usize,
     final_result: &GenericModel<f64>,
     rtvec_list: &HashMap<usize, RvecTvec>,
     detected_feature_frames: &[Option<FrameFeature>],
-    recording_option: Option<&rerun::RecordingStream>,
-) -> (f64, f64) {
-    let time_reprojection_errors_p2ds: Vec<_> = rtvec_list
+    observer: &dyn PipelineObserver,
+) -> CalibrationReport {
+    let mut frame_reprojection_errors: Vec<_> = rtvec_list
         .iter()
         .filter_map(|(&i, rtvec)| {
-            detected_feature_frames[i].as_ref()?;
-            let f = detected_feature_frames[i].clone().unwrap();
+            let f = detected_feature_frames[i].as_ref()?;
             let transform = rtvec.to_na_isometry3();
-            let (reprojection, p2ds): (Vec<_>, Vec<_>) = f
+            let residuals: Vec<_> = f
                 .features
                 .values()
                 .map(|feature| {
@@ -699,49 +985,40 @@ pub fn validation(
                     let p3p = transform * p3.cast();
                     let p3p = na::Vector3::new(p3p.x, p3p.y, p3p.z);
                     let p2p = final_result.project_one(&p3p);
-                    let dx = p2p.x - feature.p2d.x as f64;
-                    let dy = p2p.y - feature.p2d.y as f64;
-                    ((dx * dx + dy * dy).sqrt(), (feature.p2d.x, feature.p2d.y))
+                    p2p - na::Vector2::new(feature.p2d.x as f64, feature.p2d.y as f64)
                 })
-                .unzip();
-            if let Some(recording) = recording_option {
-                let p3p_rerun: Vec<_> = f
-                    .features
-                    .values()
-                    .map(|feature| {
-                        let p3 = na::Point3::new(feature.p3d.x, feature.p3d.y, feature.p3d.z);
-                        let p3p = transform.cast() * p3;
-                        (p3p.x, p3p.y, p3p.z)
-                    })
-                    .collect();
-                recording.set_time_nanos("stable", f.time_ns);
-                recording
-                    .log(
-                        format!("/cam{}/board", cam_idx),
-                        &rerun::Points3D::new(p3p_rerun),
-                    )
-                    .unwrap();
-                let avg_err = reprojection.iter().sum::<f64>() / reprojection.len() as f64;
-                recording
-                    .log(
-                        format!("/cam{}/board/reprojection_err", cam_idx),
-                        &rerun::TextLog::new(format!("{} px", avg_err)),
-                    )
-                    .unwrap();
-            };
-            Some((f.time_ns, reprojection, p2ds))
+                .collect();
+            observer.on_frame_validated(cam_idx, f, &transform, &residuals);
+            let reprojection: Vec<_> = residuals.iter().map(|r| r.norm()).collect();
+            Some((i, f.time_ns, reprojection))
         })
         .collect();
-    let mut reprojection_errors: Vec<_> = time_reprojection_errors_p2ds
+    frame_reprojection_errors.sort_by_key(|f| f.0);
+    let frames: Vec<_> = frame_reprojection_errors
         .iter()
-        .flat_map(|f| f.1.clone())
+        .map(|(frame_idx, time_ns, errors)| {
+            let mut sorted = errors.clone();
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            FrameReport {
+                frame_idx: *frame_idx,
+                time_ns: *time_ns,
+                num_points: sorted.len(),
+                mean_error: sorted.iter().sum::<f64>() / sorted.len().max(1) as f64,
+                median_error: sorted.get(sorted.len() / 2).copied().unwrap_or(0.0),
+                max_error: sorted.last().copied().unwrap_or(0.0),
+            }
+        })
         .collect();
-    println!("total pts: {}", reprojection_errors.len());
+    let mut reprojection_errors: Vec<_> = frame_reprojection_errors
+        .iter()
+        .flat_map(|f| f.2.clone())
+        .collect();
+    info!(total_pts = reprojection_errors.len(), "validation");
     reprojection_errors.sort_by(|&a, b| a.partial_cmp(b).unwrap());
     let median_reprojection_error = reprojection_errors[reprojection_errors.len() / 2];
-    println!(
-        "Median reprojection error: {} px",
-        median_reprojection_error
+    info!(
+        median_reprojection_error,
+        "Median reprojection error: {} px", median_reprojection_error
     );
     let len_99_percent = reprojection_errors.len() * 99 / 100;
     let avg_99_percent = reprojection_errors
@@ -749,62 +1026,304 @@ pub fn validation(
         .take(len_99_percent)
         .map(|p| *p / len_99_percent as f64)
         .sum::<f64>();
-    println!("Avg reprojection error of 99%: {} px", avg_99_percent);
-    if let Some(recording) = recording_option {
-        let topic = format!("/cam{}/rep_err", cam_idx);
-        let color_gradient = colorous::ORANGE_RED;
-        let min_v = 0.2;
-        for (time_ns, reps, p2ds) in &time_reprojection_errors_p2ds {
-            let (colors, text): (Vec<_>, Vec<_>) = reps
-                .iter()
-                .zip(p2ds)
-                .map(|(&r, _)| {
-                    let v = (r - min_v).clamp(0.0, 1.0);
-                    let c = color_gradient.eval_continuous(v);
-                    ((c.r, c.g, c.b, 255), format!("{}", r))
+    info!(
+        avg_99_percent,
+        "Avg reprojection error of 99%: {} px", avg_99_percent
+    );
+    observer.on_validation_done(
+        cam_idx,
+        final_result,
+        avg_99_percent,
+        median_reprojection_error,
+    );
+    let rejected_frames = detected_feature_frames
+        .iter()
+        .enumerate()
+        .filter(|(i, f)| f.is_some() && !rtvec_list.contains_key(i))
+        .map(|(i, _)| i)
+        .collect();
+    CalibrationReport {
+        cam_idx,
+        num_points: reprojection_errors.len(),
+        mean_error: reprojection_errors.iter().sum::<f64>() / reprojection_errors.len() as f64,
+        median_error: median_reprojection_error,
+        p95_error: reprojection_errors[reprojection_errors.len() * 95 / 100],
+        p99_error: reprojection_errors[reprojection_errors.len() * 99 / 100],
+        max_error: *reprojection_errors.last().unwrap(),
+        avg_99_percent,
+        histogram: ErrorHistogram::new(
+            &reprojection_errors,
+            HISTOGRAM_BIN_SIZE,
+            HISTOGRAM_MAX_ERROR,
+        ),
+        frames,
+        rejected_frames,
+    }
+}
+
+/// Project the board points of the frame with the board pose `rtvec`, keyed by corner id.
+pub fn reproject_frame(
+    model: &GenericModel<f64>,
+    rtvec: &RvecTvec,
+    frame_feature: &FrameFeature,
+) -> HashMap<u32, na::Vector2<f64>> {
+    let transform = rtvec.to_na_isometry3();
+    frame_feature
+        .features
+        .iter()
+        .map(|(&id, feature)| {
+            let p3 = na::Point3::new(feature.p3d.x, feature.p3d.y, feature.p3d.z);
+            let p3p = transform * p3.cast();
+            (
+                id,
+                model.project_one(&na::Vector3::new(p3p.x, p3p.y, p3p.z)),
+            )
+        })
+        .collect()
+}
+
+/// Reprojection error of every corner of the frames with a board pose, sorted by frame and
+/// corner id.
+pub fn corner_residuals(
+    model: &GenericModel<f64>,
+    rtvec_list: &HashMap<usize, RvecTvec>,
+    detected_feature_frames: &[Option<FrameFeature>],
+) -> Vec<CornerResidual> {
+    let mut residuals: Vec<_> = rtvec_list
+        .iter()
+        .filter_map(|(&i, rtvec)| Some((i, rtvec, detected_feature_frames[i].as_ref()?)))
+        .flat_map(|(i, rtvec, f)| {
+            reproject_frame(model, rtvec, f)
+                .into_iter()
+                .map(move |(id, p2p)| {
+                    let p2d = f.features[&id].p2d;
+                    let (du, dv) = (p2p.x - p2d.x as f64, p2p.y - p2d.y as f64);
+                    CornerResidual {
+                        frame_idx: i,
+                        time_ns: f.time_ns,
+                        corner_id: id,
+                        u: p2d.x as f64,
+                        v: p2d.y as f64,
+                        du,
+                        dv,
+                        norm: du.hypot(dv),
+                    }
                 })
-                .unzip();
-            recording.set_time_nanos("stable", *time_ns);
-            recording
-                .log(
-                    topic.to_string(),
-                    &rerun::Points2D::new(rerun_shift(p2ds))
-                        .with_colors(colors)
-                        .with_radii([rerun::Radius::new_ui_points(1.0)])
-                        .with_labels(text),
-                )
-                .unwrap();
+        })
+        .collect();
+    residuals.sort_by_key(|r| (r.frame_idx, r.corner_id));
+    residuals
+}
+
+/// Bin the reprojection errors by the distance of the detected corners to the principal point.
+/// A rising tail means the model has too few distortion params.
+pub fn residual_vs_radius(
+    model: &GenericModel<f64>,
+    rtvec_list: &HashMap<usize, RvecTvec>,
+    detected_feature_frames: &[Option<FrameFeature>],
+    bin_size: f64,
+) -> Vec<RadiusBin> {
+    let params = model.params();
+    let principal_point = na::Vector2::new(params[2], params[3]);
+    let mut binned_errors: Vec<Vec<f64>> = Vec::new();
+    for (&i, rtvec) in rtvec_list {
+        let Some(f) = detected_feature_frames[i].as_ref() else {
+            continue;
+        };
+        let transform = rtvec.to_na_isometry3();
+        for feature in f.features.values() {
+            let p3 = na::Point3::new(feature.p3d.x, feature.p3d.y, feature.p3d.z);
+            let p3p = transform * p3.cast();
+            let p2p = model.project_one(&na::Vector3::new(p3p.x, p3p.y, p3p.z));
+            let p2d = na::Vector2::new(feature.p2d.x as f64, feature.p2d.y as f64);
+            let bin = ((p2d - principal_point).norm() / bin_size) as usize;
+            if binned_errors.len() <= bin {
+                binned_errors.resize(bin + 1, Vec::new());
+            }
+            binned_errors[bin].push((p2p - p2d).norm());
         }
     }
-    (avg_99_percent, median_reprojection_error)
+    binned_errors
+        .iter_mut()
+        .enumerate()
+        .map(|(bin, errors)| {
+            errors.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let count = errors.len();
+            RadiusBin {
+                radius_begin: bin as f64 * bin_size,
+                radius_end: (bin + 1) as f64 * bin_size,
+                count,
+                mean_error: if count > 0 {
+                    errors.iter().sum::<f64>() / count as f64
+                } else {
+                    0.0
+                },
+                median_error: errors.get(count / 2).cloned().unwrap_or(0.0),
+            }
+        })
+        .collect()
 }
 
+/// Bin the corner residuals by the `cell_size` x `cell_size` cell of the image they're detected
+/// in, row major with empty cells. A low overall error can hide a region that is badly wrong.
+pub fn residual_grid(
+    residuals: &[CornerResidual],
+    img_w_h: (u32, u32),
+    cell_size: u32,
+) -> Vec<ResidualCell> {
+    let rows = img_w_h.1.div_ceil(cell_size) as usize;
+    let cols = img_w_h.0.div_ceil(cell_size) as usize;
+    let mut cells: Vec<_> = (0..rows * cols)
+        .map(|i| ResidualCell {
+            row: i / cols,
+            col: i % cols,
+            count: 0,
+            mean_error: 0.0,
+            mean_du: 0.0,
+            mean_dv: 0.0,
+        })
+        .collect();
+    for r in residuals {
+        if r.u < 0.0 || r.v < 0.0 {
+            continue;
+        }
+        let (row, col) = (
+            r.v as usize / cell_size as usize,
+            r.u as usize / cell_size as usize,
+        );
+        if row < rows && col < cols {
+            let cell = &mut cells[row * cols + col];
+            cell.count += 1;
+            cell.mean_error += r.norm;
+            cell.mean_du += r.du;
+            cell.mean_dv += r.dv;
+        }
+    }
+    for cell in cells.iter_mut().filter(|c| c.count > 0) {
+        cell.mean_error /= cell.count as f64;
+        cell.mean_du /= cell.count as f64;
+        cell.mean_dv /= cell.count as f64;
+    }
+    cells
+}
+
+/// Information of a frame on the intrinsic parameters, `J^T J` with its board pose
+/// marginalized out, and its residuals. fy is removed from the params with `xy_same_focal`.
+pub fn frame_intrinsics_information(
+    camera: &GenericModel<f64>,
+    rtvec: &RvecTvec,
+    frame_feature: &FrameFeature,
+    xy_same_focal: bool,
+) -> Option<(na::DMatrix<f64>, na::DVector<f64>)> {
+    let mut params = camera.params();
+    if xy_same_focal {
+        // remove fy
+        params = params.remove_row(1);
+    };
+    let params_len = params.len();
+    let mut problem = tiny_solver::Problem::new();
+    for fp in frame_feature.features.values() {
+        let cost = ReprojectionFactor::new(camera, &fp.p3d, &fp.p2d, xy_same_focal)
+            .with_blur(fp.blur.as_ref());
+        problem.add_residual_block(
+            2,
+            &[("params", params_len), ("rvec", 3), ("tvec", 3)],
+            Box::new(cost),
+            None,
+        );
+    }
+    let values = HashMap::<String, na::DVector<f64>>::from([
+        ("params".to_string(), params),
+        ("rvec".to_string(), rtvec.na_rvec()),
+        ("tvec".to_string(), rtvec.na_tvec()),
+    ]);
+    let (residuals, jac) = problem.compute_residual_and_jacobian(&values);
+    let jac = jac.to_dense();
+    let jac = na::DMatrix::<f64>::from_fn(jac.nrows(), jac.ncols(), |r, c| *jac.get(r, c));
+    let residuals = na::DVector::<f64>::from_fn(residuals.nrows(), |r, _| residuals.read(r, 0));
+
+    // schur complement of the pose block
+    let hessian = jac.transpose() * &jac;
+    let h_pp = hessian.view((0, 0), (params_len, params_len));
+    let h_px = hessian.view((0, params_len), (params_len, 6));
+    let h_xx = hessian.view((params_len, params_len), (6, 6)).clone_owned();
+    let h_xx_inv = h_xx.try_inverse()?;
+    Some((h_pp - h_px * h_xx_inv * h_px.transpose(), residuals))
+}
+
+/// Covariance of the intrinsic parameters at the solution, with the board poses
+/// marginalized out. The returned matrix matches the layout of `camera.params()`.
+pub fn intrinsics_covariance(
+    camera: &GenericModel<f64>,
+    rtvec_map: &HashMap<usize, RvecTvec>,
+    detected_feature_frames: &[Option<FrameFeature>],
+    xy_same_focal: bool,
+) -> Option<na::DMatrix<f64>> {
+    // without fy
+    let params_len = camera.params().len() - xy_same_focal as usize;
+    let mut information = na::DMatrix::<f64>::zeros(params_len, params_len);
+    let mut squared_error_sum = 0.0;
+    let mut residual_num = 0;
+    for (&i, rtvec) in rtvec_map {
+        let Some(frame_feature) = detected_feature_frames[i].as_ref() else {
+            continue;
+        };
+        let (frame_information, residuals) =
+            frame_intrinsics_information(camera, rtvec, frame_feature, xy_same_focal)?;
+        information += frame_information;
+        squared_error_sum += residuals.norm_squared();
+        residual_num += residuals.len();
+    }
+    let dof = residual_num.checked_sub(params_len + 6 * rtvec_map.len())?;
+    if dof == 0 {
+        return None;
+    }
+    let sigma2 = squared_error_sum / dof as f64;
+    let covariance = information.try_inverse()? * sigma2;
+    if !xy_same_focal {
+        return Some(covariance);
+    }
+    // fy shares the estimate of fx
+    let full_len = params_len + 1;
+    let mut expand = na::DMatrix::<f64>::zeros(full_len, params_len);
+    expand[(0, 0)] = 1.0;
+    expand[(1, 0)] = 1.0;
+    for i in 1..params_len {
+        expand[(i + 1, i)] = 1.0;
+    }
+    Some(&expand * covariance * expand.transpose())
+}
+
+#[instrument(skip_all, fields(cam_idx))]
 pub fn init_and_calibrate_one_camera(
     cam_idx: usize,
     cams_detected_feature_frames: &[Vec<Option<FrameFeature>>],
     target_model: &GenericModel<f64>,
-    recording: &RecordingStream,
+    observer: &dyn PipelineObserver,
     calib_params: &CalibParams,
     // fixed_focal: Option<f64>,
     // disabled_distortion_num: usize,
     // one_focal: bool,
     random_pick_two_frame: bool,
 ) -> Option<(GenericModel<f64>, HashMap<usize, RvecTvec>)> {
-    let (frame0, frame1) = find_best_two_frames_idx(
+    let Some((frame0, frame1)) = find_best_two_frames_idx(
         &cams_detected_feature_frames[cam_idx],
         random_pick_two_frame,
-    );
+    ) else {
+        warn!("cam{} has less than two frames with detections", cam_idx);
+        return None;
+    };
 
-    let frame_feature0 = &cams_detected_feature_frames[cam_idx][frame0]
-        .clone()
+    let frame_feature0 = cams_detected_feature_frames[cam_idx][frame0]
+        .as_ref()
         .unwrap();
-    let frame_feature1 = &cams_detected_feature_frames[cam_idx][frame1]
-        .clone()
+    let frame_feature1 = cams_detected_feature_frames[cam_idx][frame1]
+        .as_ref()
         .unwrap();
 
     let mut initial_camera = GenericModel::UCM(UCM::zeros());
-    for i in 0..10 {
-        log::trace!("Initialize ucm {}", i);
+    for i in 0..INIT_ATTEMPTS {
+        trace!("Initialize ucm {}", i);
         if let Some(initialized_ucm) =
             try_init_camera(frame_feature0, frame_feature1, calib_params.fixed_focal)
         {
@@ -813,7 +1332,7 @@ pub fn init_and_calibrate_one_camera(
         }
     }
     if initial_camera.params()[0] == 0.0 {
-        println!("calibration failed.");
+        warn!("calibration failed.");
         return None;
     }
     let mut final_model = *target_model;
@@ -826,7 +1345,8 @@ pub fn init_and_calibrate_one_camera(
         &mut final_model,
         calib_params.disabled_distortion_num,
     );
-    println!("Converted {:?}", final_model);
+    debug!("Converted {:?}", final_model);
+    observer.on_init_complete(cam_idx, &final_model, [frame_feature0, frame_feature1]);
     let (one_focal, fixed_focal) = if let Some(focal) = calib_params.fixed_focal {
         // if fixed focal then set one focal true
         let mut p = final_model.params();
@@ -844,16 +1364,81 @@ pub fn init_and_calibrate_one_camera(
         one_focal,
         calib_params.disabled_distortion_num,
         fixed_focal,
+        calib_params.plateau,
+        observer,
     );
-    if calib_result.is_some() {
-        let key_frames = [Some(frame_feature0.clone()), Some(frame_feature1.clone())];
-        key_frames.iter().enumerate().for_each(|(i, k)| {
-            let topic = format!("/cam{}/keyframe{}", cam_idx, i);
-            recording.set_time_nanos("stable", k.clone().unwrap().time_ns);
-            recording
-                .log(topic, &rerun::TextLog::new("keyframe"))
-                .unwrap();
-        });
+    if let Some((model, rtvec_map)) = &calib_result {
+        observer.on_calibration_done(cam_idx, model, rtvec_map);
     }
     calib_result
 }
+
+/// Retry `init_and_calibrate_one_camera`, picking random key frames after the first failure.
+pub fn init_and_calibrate_one_camera_with_trials(
+    cam_idx: usize,
+    cams_detected_feature_frames: &[Vec<Option<FrameFeature>>],
+    target_model: &GenericModel<f64>,
+    observer: &dyn PipelineObserver,
+    calib_params: &CalibParams,
+    max_trials: usize,
+) -> Option<(GenericModel<f64>, HashMap<usize, RvecTvec>)> {
+    for trial in 0..max_trials {
+        let calibrated_result = init_and_calibrate_one_camera(
+            cam_idx,
+            cams_detected_feature_frames,
+            target_model,
+            observer,
+            calib_params,
+            trial > 0,
+        );
+        if calibrated_result.is_some() {
+            return calibrated_result;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{eucm, frames};
+
+    #[test]
+    fn two_different_init_frames() {
+        let (mut frames, _) = frames(&eucm(), 6);
+        // frame 2 has the most corners, the others lose a few
+        for (i, frame) in frames.iter_mut().enumerate() {
+            if i != 2 {
+                let frame = frame.as_mut().unwrap();
+                let id = *frame.features.keys().max().unwrap();
+                frame.features.remove(&id);
+            }
+        }
+        for random_pick in [false, true] {
+            let (idx0, idx1) = find_best_two_frames_idx(&frames, random_pick).unwrap();
+            assert_ne!(idx0, idx1);
+        }
+
+        let mut one_frame = vec![None; 6];
+        one_frame[4] = frames[4].clone();
+        for random_pick in [false, true] {
+            assert!(find_best_two_frames_idx(&one_frame, random_pick).is_none());
+        }
+        let cams_frames = [one_frame];
+        let calib_params = CalibParams {
+            fixed_focal: None,
+            disabled_distortion_num: 0,
+            one_focal: false,
+            plateau: None,
+        };
+        let calibrated = init_and_calibrate_one_camera(
+            0,
+            &cams_frames,
+            &eucm(),
+            &NoopObserver,
+            &calib_params,
+            true,
+        );
+        assert!(calibrated.is_none());
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use camera_intrinsic_model::*;
+use serde::{Deserialize, Serialize};
+
+use crate::detected_points::FrameFeature;
+use crate::util::{corner_residuals, solve_board_pose};
+
+/// Reprojection errors of new images of the board with the board poses solved by PnP and the
+/// intrinsics of an existing calibration kept fixed, a periodic health check of a deployed
+/// camera. The errors grow when the lens has moved, e.g. after a shock or a refocus.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationStats {
+    pub cam_idx: usize,
+    pub num_frames: usize,
+    /// Frames whose board pose couldn't be solved, many of them also mean a broken calibration.
+    pub num_failed_frames: usize,
+    pub num_corners: usize,
+    pub rms: f64,
+    pub median: f64,
+    pub p95: f64,
+    pub max: f64,
+    /// Frame with the largest rms.
+    pub worst_frame_time_ns: Option<i64>,
+    pub worst_frame_rms: f64,
+}
+
+impl ValidationStats {
+    /// `None` without frames or if they don't have the resolution of the calibration.
+    pub fn new(
+        cam_idx: usize,
+        model: &GenericModel<f64>,
+        frames: &[Option<FrameFeature>],
+    ) -> Option<ValidationStats> {
+        let num_frames = frames.iter().flatten().count();
+        if num_frames == 0 {
+            tracing::warn!("cam{} has no frames with detections", cam_idx);
+            return None;
+        }
+        let model_w_h = (model.width() as u32, model.height() as u32);
+        if let Some(f) = frames.iter().flatten().find(|f| f.img_w_h != model_w_h) {
+            tracing::warn!(
+                "cam{} images are {}x{} but the calibration is {}x{}, rescale it first",
+                cam_idx,
+                f.img_w_h.0,
+                f.img_w_h.1,
+                model_w_h.0,
+                model_w_h.1
+            );
+            return None;
+        }
+        let rtvecs: HashMap<_, _> = frames
+            .iter()
+            .enumerate()
+            .filter_map(|(i, f)| Some((i, solve_board_pose(model, f.as_ref()?)?)))
+            .collect();
+        let residuals = corner_residuals(model, &rtvecs, frames);
+        let rms = |errors: &mut dyn Iterator<Item = f64>| {
+            let (sum, count) = errors.fold((0.0, 0), |(s, c), e| (s + e * e, c + 1));
+            (sum / (count as f64).max(1.0)).sqrt()
+        };
+        let (worst_frame_idx, worst_frame_rms) = rtvecs
+            .keys()
+            .map(|&i| {
+                let mut errors = residuals
+                    .iter()
+                    .filter(|r| r.frame_idx == i)
+                    .map(|r| r.norm);
+                (Some(i), rms(&mut errors))
+            })
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .unwrap_or((None, 0.0));
+        let mut errors: Vec<_> = residuals.iter().map(|r| r.norm).collect();
+        errors.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let percentile = |p: f64| {
+            errors
+                .get(((errors.len() as f64 * p) as usize).min(errors.len().saturating_sub(1)))
+                .copied()
+                .unwrap_or(0.0)
+        };
+        Some(ValidationStats {
+            cam_idx,
+            num_frames,
+            num_failed_frames: num_frames - rtvecs.len(),
+            num_corners: errors.len(),
+            rms: rms(&mut errors.iter().copied()),
+            median: percentile(0.5),
+            p95: percentile(0.95),
+            max: errors.last().copied().unwrap_or(0.0),
+            worst_frame_time_ns: worst_frame_idx
+                .and_then(|i| frames[i].as_ref())
+                .map(|f| f.time_ns),
+            worst_frame_rms,
+        })
+    }
+
+    /// The rms is below `max_rms` px and the board pose of most frames could be solved.
+    pub fn passes(&self, max_rms: f64) -> bool {
+        self.num_corners > 0 && self.rms <= max_rms && self.num_failed_frames * 2 < self.num_frames
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use camera_intrinsic_model::*;
+use nalgebra as na;
+use serde::{Deserialize, Serialize};
+use tiny_solver::loss_functions::HuberLoss;
+use tiny_solver::Optimizer;
+
+use crate::detected_points::FrameFeature;
+use crate::optimization::factors::PoseReprojectionFactor;
+use crate::types::{DVecVec3, RvecTvec, ToRvecTvec};
+
+/// Surveyed pose of the board in the vehicle (or world) frame in the image taken at `time_ns`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SurveyedBoard {
+    pub time_ns: i64,
+    /// Transforms points from the board to the vehicle.
+    pub t_vehicle_board: RvecTvec,
+}
+
+/// Result of `align_to_vehicle`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VehicleAlignment {
+    /// Transforms points from the camera to the vehicle.
+    pub t_vehicle_cam: RvecTvec,
+    pub num_boards: usize,
+    pub reprojection_rms: f64,
+}
+
+/// Pose of a camera fixed on a vehicle from boards placed at surveyed poses, e.g. on the ground
+/// around the vehicle. The board corners of every image with a surveyed board are moved to the
+/// vehicle frame and the camera pose minimizes their reprojection errors with the calibrated
+/// `model`. `rtvecs` are the board poses of the calibration to initialize it.
+pub fn align_to_vehicle(
+    model: &GenericModel<f64>,
+    rtvecs: &HashMap<usize, RvecTvec>,
+    frame_feature_list: &[Option<FrameFeature>],
+    surveyed_boards: &[SurveyedBoard],
+) -> Option<VehicleAlignment> {
+    let t_vehicle_boards: HashMap<_, _> = surveyed_boards
+        .iter()
+        .map(|b| (b.time_ns, b.t_vehicle_board.to_na_isometry3()))
+        .collect();
+    let frames: Vec<_> = frame_feature_list
+        .iter()
+        .enumerate()
+        .filter_map(|(i, f)| {
+            let f = f.as_ref()?;
+            Some((i, f, *t_vehicle_boards.get(&f.time_ns)?))
+        })
+        .collect();
+    // the first board seen by the calibration
+    let Some(t_vehicle_cam) = frames.iter().find_map(|(i, _, t_vehicle_board)| {
+        Some(t_vehicle_board * rtvecs.get(i)?.to_na_isometry3().inverse())
+    }) else {
+        tracing::warn!("no image of a surveyed board for the vehicle alignment");
+        return None;
+    };
+
+    let mut problem = tiny_solver::Problem::new();
+    let mut points = Vec::new();
+    for (_, frame, t_vehicle_board) in &frames {
+        for fp in frame.features.values() {
+            let p3d = t_vehicle_board * na::Point3::new(fp.p3d.x, fp.p3d.y, fp.p3d.z).cast::<f64>();
+            let p2d = na::Vector2::new(fp.p2d.x, fp.p2d.y).cast::<f64>();
+            let cost = PoseReprojectionFactor {
+                target: *model,
+                p3d,
+                p2d,
+            };
+            problem.add_residual_block(
+                2,
+                &[("rvec", 3), ("tvec", 3)],
+                Box::new(cost),
+                Some(Box::new(HuberLoss::new(1.0))),
+            );
+            points.push((p3d, p2d));
+        }
+    }
+    let init = t_vehicle_cam.inverse().to_rvec_tvec();
+    let initial_values = HashMap::from([
+        ("rvec".to_string(), init.na_rvec()),
+        ("tvec".to_string(), init.na_tvec()),
+    ]);
+    let result = tiny_solver::GaussNewtonOptimizer {}.optimize(&problem, &initial_values, None)?;
+    let t_cam_vehicle = na::Isometry3::new(result["tvec"].to_vec3(), result["rvec"].to_vec3());
+
+    let squared_errors: Vec<_> = points
+        .iter()
+        .map(|(p3d, p2d)| (model.project_one(&(t_cam_vehicle * p3d).coords) - p2d).norm_squared())
+        .collect();
+    let reprojection_rms =
+        (squared_errors.iter().sum::<f64>() / squared_errors.len() as f64).sqrt();
+    tracing::info!(
+        "vehicle alignment of {} boards, reprojection rms {:.3} px",
+        frames.len(),
+        reprojection_rms
+    );
+    Some(VehicleAlignment {
+        t_vehicle_cam: t_cam_vehicle.inverse().to_rvec_tvec(),
+        num_boards: frames.len(),
+        reprojection_rms,
+    })
+}
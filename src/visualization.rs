@@ -1,32 +1,22 @@
+use camera_intrinsic_model::GenericModel;
 use image::DynamicImage;
+use nalgebra as na;
 use rand::prelude::*;
 use rand_chacha::ChaCha8Rng;
-use rerun::RecordingStream;
+#[cfg(feature = "rerun")]
+pub use rerun::RecordingStream;
+#[cfg(feature = "rerun")]
 use std::io::Cursor;
 
+use crate::batch;
+use crate::coverage::CaptureSuggestion;
 use crate::detected_points::FrameFeature;
+use crate::observer::PipelineObserver;
+use crate::types::RadiusBin;
 
-pub fn log_image_as_compressed(
-    recording: &RecordingStream,
-    topic: &str,
-    img: &DynamicImage,
-    format: image::ImageFormat,
-) {
-    let mut bytes: Vec<u8> = Vec::new();
-
-    img.to_luma8()
-        .write_to(&mut Cursor::new(&mut bytes), format)
-        .unwrap();
-
-    recording
-        .log(
-            format!("{}/image", topic),
-            &rerun::Image::from_file_contents(bytes, None).unwrap(),
-        )
-        .unwrap();
-}
+pub type Color = (u8, u8, u8, u8);
 
-pub fn id_to_color(id: usize) -> (u8, u8, u8, u8) {
+pub fn id_to_color(id: usize) -> Color {
     let mut rng = ChaCha8Rng::seed_from_u64(id as u64);
     let color_num = rng.gen_range(0..2u32.pow(24));
     (
@@ -42,41 +32,654 @@ pub fn rerun_shift(p2ds: &[(f32, f32)]) -> Vec<(f32, f32)> {
     p2ds.iter().map(|(x, y)| (*x + 0.5, *y + 0.5)).collect()
 }
 
+/// Errors under 0.2 px are yellow, over 1.2 px are red.
+pub fn reprojection_error_to_color(reprojection_error: f64) -> Color {
+    let min_v = 0.2;
+    let v = (reprojection_error - min_v).clamp(0.0, 1.0);
+    let c = colorous::ORANGE_RED.eval_continuous(v);
+    (c.r, c.g, c.b, 255)
+}
+
+/// Logging backend. Every method does nothing by default, `NoopVisualizer` is the headless one.
+/// Logs are on the timeline set by `set_time_nanos` unless `is_static`.
+pub trait Visualizer: Sync {
+    fn set_time_nanos(&self, _time_ns: i64) {}
+    fn log_image(&self, _topic: &str, _img: &DynamicImage, _is_static: bool) {}
+    fn log_points2d(
+        &self,
+        _topic: &str,
+        _p2ds: &[(f32, f32)],
+        _colors: &[Color],
+        _labels: &[String],
+        _radius: f32,
+    ) {
+    }
+    fn log_points3d(
+        &self,
+        _topic: &str,
+        _p3ds: &[(f32, f32, f32)],
+        _colors: &[Color],
+        _labels: &[String],
+        _is_static: bool,
+    ) {
+    }
+    fn log_arrows2d(
+        &self,
+        _topic: &str,
+        _origins: &[(f32, f32)],
+        _vectors: &[(f32, f32)],
+        _colors: &[Color],
+        _is_static: bool,
+    ) {
+    }
+    fn log_boxes2d(
+        &self,
+        _topic: &str,
+        _mins: &[(f32, f32)],
+        _sizes: &[(f32, f32)],
+        _labels: &[String],
+    ) {
+    }
+    fn log_text(&self, _topic: &str, _text: &str) {}
+    fn log_bar_chart(&self, _topic: &str, _values: &[f64]) {}
+    /// Static transform of `topic` in its parent.
+    fn log_transform(&self, _topic: &str, _transform: &na::Isometry3<f64>) {}
+    /// Static pinhole camera at `topic`.
+    fn log_pinhole(&self, _topic: &str, _model: &GenericModel<f64>) {}
+}
+
+pub struct NoopVisualizer;
+
+impl Visualizer for NoopVisualizer {}
+
+pub fn log_image_at(visualizer: &dyn Visualizer, topic: &str, time_ns: i64, img: &DynamicImage) {
+    visualizer.set_time_nanos(time_ns);
+    visualizer.log_image(&format!("{}/image", topic), img, false);
+}
+
 pub fn log_feature_frames(
-    recording: &RecordingStream,
+    visualizer: &dyn Visualizer,
     topic: &str,
     detected_feature_frames: &[Option<FrameFeature>],
 ) {
-    for f in detected_feature_frames {
-        let ((pts, colors_labels), time_ns): ((Vec<_>, Vec<_>), i64) = if let Some(f) = f {
+    for f in detected_feature_frames.iter().flatten() {
+        let (pts, (colors, labels)): (Vec<_>, (Vec<_>, Vec<_>)) = f
+            .features
+            .iter()
+            .map(|(id, p)| {
+                (
+                    (p.p2d.x, p.p2d.y),
+                    (id_to_color(*id as usize), format!("{:?}", p.p3d)),
+                )
+            })
+            .unzip();
+        visualizer.set_time_nanos(f.time_ns);
+        visualizer.log_points2d(
+            &format!("{}/pts", topic),
+            &rerun_shift(&pts),
+            &colors,
+            &labels,
+            2.0,
+        );
+    }
+}
+
+pub fn log_board_points(
+    visualizer: &dyn Visualizer,
+    topic: &str,
+    time_ns: i64,
+    p3ds: &[(f32, f32, f32)],
+    avg_err: f64,
+) {
+    visualizer.set_time_nanos(time_ns);
+    visualizer.log_points3d(topic, p3ds, &[], &[], false);
+    visualizer.log_text(
+        &format!("{}/reprojection_err", topic),
+        &format!("{} px", avg_err),
+    );
+}
+
+pub fn log_reprojection_errors(
+    visualizer: &dyn Visualizer,
+    topic: &str,
+    time_ns: i64,
+    reprojection_errors: &[f64],
+    p2ds: &[(f32, f32)],
+) {
+    let (colors, text): (Vec<_>, Vec<_>) = reprojection_errors
+        .iter()
+        .map(|&r| (reprojection_error_to_color(r), format!("{}", r)))
+        .unzip();
+    visualizer.set_time_nanos(time_ns);
+    visualizer.log_points2d(topic, &rerun_shift(p2ds), &colors, &text, 1.0);
+}
+
+/// Residuals are usually sub-pixel, scale them up to be visible.
+pub const RESIDUAL_ARROW_SCALE: f64 = 20.0;
+
+/// Arrows from the detected corners along the residuals (projected - detected), times `scale`.
+pub fn log_residual_arrows(
+    visualizer: &dyn Visualizer,
+    topic: &str,
+    time_ns: i64,
+    p2ds: &[(f32, f32)],
+    residuals: &[na::Vector2<f64>],
+    scale: f64,
+) {
+    let (vectors, colors): (Vec<_>, Vec<_>) = residuals
+        .iter()
+        .map(|r| {
             (
-                f.features
-                    .iter()
-                    .map(|(id, p)| {
-                        let color = id_to_color(*id as usize);
-                        (
-                            (p.p2d.x, p.p2d.y),
-                            (color, format!("{:?}", p.p3d).to_string()),
-                        )
-                    })
-                    .unzip(),
-                f.time_ns,
+                ((r.x * scale) as f32, (r.y * scale) as f32),
+                reprojection_error_to_color(r.norm()),
             )
-        } else {
-            continue;
-        };
-        let (colors, labels): (Vec<_>, Vec<_>) = colors_labels.iter().cloned().unzip();
-        let pts = rerun_shift(&pts);
+        })
+        .unzip();
+    visualizer.set_time_nanos(time_ns);
+    visualizer.log_arrows2d(topic, &rerun_shift(p2ds), &vectors, &colors, false);
+}
+
+pub fn log_coverage_heatmap(visualizer: &dyn Visualizer, topic: &str, heatmap: &image::RgbImage) {
+    visualizer.log_image(
+        &format!("{}/coverage", topic),
+        &DynamicImage::ImageRgb8(heatmap.clone()),
+        true,
+    );
+}
+
+pub fn log_capture_suggestion(
+    visualizer: &dyn Visualizer,
+    topic: &str,
+    suggestion: &CaptureSuggestion,
+) {
+    let (x, y, w, h) = suggestion.region;
+    visualizer.log_boxes2d(
+        &format!("{}/suggestion", topic),
+        &[(x, y)],
+        &[(w, h)],
+        std::slice::from_ref(&suggestion.message),
+    );
+}
+
+/// Mean reprojection error of each radius bin as a bar chart.
+pub fn log_residual_vs_radius(visualizer: &dyn Visualizer, topic: &str, bins: &[RadiusBin]) {
+    let means: Vec<_> = bins.iter().map(|b| b.mean_error).collect();
+    visualizer.log_bar_chart(&format!("{}/residual_vs_radius", topic), &means);
+}
+
+pub fn log_text(visualizer: &dyn Visualizer, topic: &str, time_ns: i64, text: &str) {
+    visualizer.set_time_nanos(time_ns);
+    visualizer.log_text(topic, text);
+}
+
+/// Arrows from the ideal pinhole projection to the distorted pixel on a grid of `grid_size` pixels.
+pub fn log_distortion_field(
+    visualizer: &dyn Visualizer,
+    topic: &str,
+    model: &GenericModel<f64>,
+    grid_size: u32,
+) {
+    let params = model.params();
+    let (fx, fy, cx, cy) = (params[0], params[1], params[2], params[3]);
+    let (w, h) = (model.width(), model.height());
+    let p2ds: Vec<_> = (0..h as u32)
+        .step_by(grid_size as usize)
+        .flat_map(|r| {
+            (0..w as u32)
+                .step_by(grid_size as usize)
+                .map(move |c| na::Vector2::new(c as f64, r as f64))
+        })
+        .collect();
+    let (origins, vectors): (Vec<_>, Vec<_>) = batch::unproject(model, &p2ds)
+        .iter()
+        .zip(&p2ds)
+        .filter_map(|(p3d, p2d)| {
+            let p3d = p3d.as_ref()?;
+            // rays beyond ~80 degree can't be projected to a pinhole image
+            if p3d.z < 0.2 * p3d.norm() {
+                return None;
+            }
+            let pinhole = na::Vector2::new(fx * p3d.x / p3d.z + cx, fy * p3d.y / p3d.z + cy);
+            let d = p2d - pinhole;
+            Some((
+                (pinhole.x as f32 + 0.5, pinhole.y as f32 + 0.5),
+                (d.x as f32, d.y as f32),
+            ))
+        })
+        .unzip();
+    let max_norm = vectors
+        .iter()
+        .map(|(x, y)| (x * x + y * y).sqrt())
+        .fold(f32::EPSILON, f32::max);
+    let colors: Vec<_> = vectors
+        .iter()
+        .map(|(x, y)| {
+            let c = colorous::VIRIDIS.eval_continuous(((x * x + y * y).sqrt() / max_norm) as f64);
+            (c.r, c.g, c.b, 255)
+        })
+        .collect();
+    visualizer.log_arrows2d(
+        &format!("{}/distortion", topic),
+        &origins,
+        &vectors,
+        &colors,
+        true,
+    );
+}
+
+/// Log the original and undistorted image side by side for each `(time_ns, image)`.
+pub fn log_undistorted_previews(
+    visualizer: &dyn Visualizer,
+    topic: &str,
+    model: &GenericModel<f64>,
+    images: &[(i64, DynamicImage)],
+) {
+    let (w, h) = (model.width() as u32, model.height() as u32);
+    let new_camera_mat = model.estimate_new_camera_matrix_for_undistort(1.0, Some((w, h)));
+    let (xmap, ymap) = model.init_undistort_map(&new_camera_mat, (w, h), None);
+    for (time_ns, img) in images {
+        let undistorted = camera_intrinsic_model::remap(img, &xmap, &ymap);
+        let mut side_by_side = image::GrayImage::new(w * 2, h);
+        image::imageops::replace(&mut side_by_side, &img.to_luma8(), 0, 0);
+        image::imageops::replace(&mut side_by_side, &undistorted.to_luma8(), w as i64, 0);
+        log_image_at(
+            visualizer,
+            &format!("{}/undistorted", topic),
+            *time_ns,
+            &DynamicImage::ImageLuma8(side_by_side),
+        );
+    }
+}
 
-        recording.set_time_nanos("stable", time_ns);
-        recording
+#[cfg(feature = "rerun")]
+struct BlueprintBuilder {
+    stream: RecordingStream,
+}
+
+#[cfg(feature = "rerun")]
+impl BlueprintBuilder {
+    /// Blueprint entities are stored at `{registry}/{uuid}`.
+    fn new_id(registry: &str) -> ([u8; 16], String) {
+        let bytes: [u8; 16] = rand::random();
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let path = format!(
+            "{}/{}-{}-{}-{}-{}",
+            registry,
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        );
+        (bytes, path)
+    }
+
+    fn view(&self, class: &str, name: &str, origin: &str, query: &[String]) -> String {
+        use rerun::external::re_types::blueprint::archetypes::{
+            SpaceViewBlueprint, SpaceViewContents,
+        };
+        use rerun::external::re_types::datatypes::EntityPath;
+        let (_, path) = Self::new_id("space_view");
+        self.stream
+            .log(
+                path.as_str(),
+                &SpaceViewBlueprint::new(class)
+                    .with_display_name(name)
+                    .with_space_origin(EntityPath(origin.into())),
+            )
+            .unwrap();
+        self.stream
             .log(
-                format!("{}/pts", topic),
-                &rerun::Points2D::new(pts)
-                    .with_colors(colors)
-                    .with_labels(labels)
-                    .with_radii([rerun::Radius::new_ui_points(2.0)]),
+                format!("{}/SpaceViewContents", path),
+                &SpaceViewContents::new(query.iter().map(|q| q.as_str())),
             )
             .unwrap();
+        path
+    }
+
+    fn container(
+        &self,
+        kind: re_types_blueprint::blueprint::components::ContainerKind,
+        contents: &[String],
+        shares: &[f32],
+    ) -> ([u8; 16], String) {
+        use re_types_blueprint::blueprint::archetypes::ContainerBlueprint;
+        use rerun::external::re_types::datatypes::EntityPath;
+        let (id, path) = Self::new_id("container");
+        let mut container = ContainerBlueprint::new(kind)
+            .with_contents(contents.iter().map(|c| EntityPath(c.as_str().into())));
+        if !shares.is_empty() {
+            container = container.with_col_shares(shares.iter().copied());
+        }
+        self.stream.log(path.as_str(), &container).unwrap();
+        (id, path)
+    }
+}
+
+/// Send the default layout: camera images on the left, the board poses in 3D in the middle,
+/// and the per camera analysis, residual plots and text logs on the right.
+#[cfg(feature = "rerun")]
+pub fn send_default_blueprint(recording: &RecordingStream, cam_num: usize) {
+    use re_types_blueprint::blueprint::archetypes::ViewportBlueprint;
+    use re_types_blueprint::blueprint::components::{ContainerKind, RootContainer};
+    use rerun::external::re_log_types::BlueprintActivationCommand;
+
+    let Some(store_info) = recording.store_info() else {
+        return;
+    };
+    let (stream, storage) = rerun::RecordingStreamBuilder::new(store_info.application_id)
+        .blueprint()
+        .memory()
+        .unwrap();
+    stream.set_time_sequence("blueprint", 0);
+    let builder = BlueprintBuilder { stream };
+
+    let cams: Vec<_> = (0..cam_num).map(|i| format!("/cam{}", i)).collect();
+    let images: Vec<_> = cams
+        .iter()
+        .map(|cam| {
+            builder.view(
+                "2D",
+                cam,
+                cam,
+                &[
+                    format!("+ {}/**", cam),
+                    format!("- {}/coverage", cam),
+                    format!("- {}/distortion", cam),
+                    format!("- {}/undistorted/**", cam),
+                ],
+            )
+        })
+        .collect();
+    let analysis: Vec<_> = cams
+        .iter()
+        .flat_map(|cam| {
+            ["coverage", "distortion", "undistorted"].map(|name| {
+                let origin = format!("{}/{}", cam, name);
+                builder.view(
+                    "2D",
+                    &format!("{} {}", cam, name),
+                    &origin,
+                    &[format!("+ {}/**", origin)],
+                )
+            })
+        })
+        .collect();
+    let bar_charts: Vec<_> = cams
+        .iter()
+        .map(|cam| {
+            let origin = format!("{}/residual_vs_radius", cam);
+            builder.view(
+                "BarChart",
+                &format!("{} residual vs radius", cam),
+                &origin,
+                &[format!("+ {}", origin)],
+            )
+        })
+        .collect();
+    let board = builder.view("3D", "board", "/", &["+ /**".to_string()]);
+    let text = builder.view("TextLog", "logs", "/", &["+ /**".to_string()]);
+
+    let (_, images) = builder.container(ContainerKind::Tabs, &images, &[]);
+    let (_, analysis) = builder.container(ContainerKind::Tabs, &analysis, &[]);
+    let (_, bar_charts) = builder.container(ContainerKind::Tabs, &bar_charts, &[]);
+    let (_, right) = builder.container(ContainerKind::Vertical, &[analysis, bar_charts, text], &[]);
+    let (root, _) = builder.container(
+        ContainerKind::Horizontal,
+        &[images, board, right],
+        &[2.0, 2.0, 1.5],
+    );
+    builder
+        .stream
+        .log(
+            "viewport",
+            &ViewportBlueprint::new()
+                .with_root_container(RootContainer(root.into()))
+                .with_auto_layout(false)
+                .with_auto_space_views(false),
+        )
+        .unwrap();
+    builder.stream.flush_blocking();
+    let blueprint_id = builder.stream.store_info().unwrap().store_id;
+    recording.send_blueprint(
+        storage.take(),
+        BlueprintActivationCommand::make_active(blueprint_id),
+    );
+}
+
+#[cfg(feature = "rerun")]
+pub fn na_isometry3_to_rerun_transform3d(transform: &na::Isometry3<f64>) -> rerun::Transform3D {
+    let t = (
+        transform.translation.x as f32,
+        transform.translation.y as f32,
+        transform.translation.z as f32,
+    );
+    let q_xyzw = (
+        transform.rotation.quaternion().i as f32,
+        transform.rotation.quaternion().j as f32,
+        transform.rotation.quaternion().k as f32,
+        transform.rotation.quaternion().w as f32,
+    );
+    rerun::Transform3D::from_translation_rotation(t, rerun::Quaternion::from_xyzw(q_xyzw.into()))
+}
+
+#[cfg(feature = "rerun")]
+impl Visualizer for RecordingStream {
+    fn set_time_nanos(&self, time_ns: i64) {
+        RecordingStream::set_time_nanos(self, "stable", time_ns);
+    }
+
+    fn log_image(&self, topic: &str, img: &DynamicImage, is_static: bool) {
+        let mut bytes: Vec<u8> = Vec::new();
+        if is_static {
+            img.write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+                .unwrap();
+            self.log_static(
+                topic,
+                &rerun::Image::from_file_contents(bytes, None).unwrap(),
+            )
+            .unwrap();
+        } else {
+            img.to_luma8()
+                .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Jpeg)
+                .unwrap();
+            self.log(
+                topic,
+                &rerun::Image::from_file_contents(bytes, None).unwrap(),
+            )
+            .unwrap();
+        }
+    }
+
+    fn log_points2d(
+        &self,
+        topic: &str,
+        p2ds: &[(f32, f32)],
+        colors: &[Color],
+        labels: &[String],
+        radius: f32,
+    ) {
+        self.log(
+            topic,
+            &rerun::Points2D::new(p2ds.iter().cloned())
+                .with_colors(colors.iter().cloned())
+                .with_labels(labels.iter().cloned())
+                .with_radii([rerun::Radius::new_ui_points(radius)]),
+        )
+        .unwrap();
+    }
+
+    fn log_points3d(
+        &self,
+        topic: &str,
+        p3ds: &[(f32, f32, f32)],
+        colors: &[Color],
+        labels: &[String],
+        is_static: bool,
+    ) {
+        let points = rerun::Points3D::new(p3ds.iter().cloned())
+            .with_colors(colors.iter().cloned())
+            .with_labels(labels.iter().cloned());
+        if is_static {
+            self.log_static(topic, &points).unwrap();
+        } else {
+            self.log(topic, &points).unwrap();
+        }
+    }
+
+    fn log_arrows2d(
+        &self,
+        topic: &str,
+        origins: &[(f32, f32)],
+        vectors: &[(f32, f32)],
+        colors: &[Color],
+        is_static: bool,
+    ) {
+        let arrows = rerun::Arrows2D::from_vectors(vectors.iter().cloned())
+            .with_origins(origins.iter().cloned())
+            .with_colors(colors.iter().cloned());
+        if is_static {
+            self.log_static(topic, &arrows).unwrap();
+        } else {
+            self.log(topic, &arrows).unwrap();
+        }
+    }
+
+    fn log_boxes2d(
+        &self,
+        topic: &str,
+        mins: &[(f32, f32)],
+        sizes: &[(f32, f32)],
+        labels: &[String],
+    ) {
+        self.log(
+            topic,
+            &rerun::Boxes2D::from_mins_and_sizes(mins.iter().cloned(), sizes.iter().cloned())
+                .with_colors([(0, 255, 0, 255)])
+                .with_labels(labels.iter().cloned()),
+        )
+        .unwrap();
+    }
+
+    fn log_text(&self, topic: &str, text: &str) {
+        self.log(topic, &rerun::TextLog::new(text)).unwrap();
+    }
+
+    fn log_bar_chart(&self, topic: &str, values: &[f64]) {
+        self.log_static(topic, &rerun::BarChart::new(values.to_vec()))
+            .unwrap();
+    }
+
+    fn log_transform(&self, topic: &str, transform: &na::Isometry3<f64>) {
+        self.log_static(topic, &na_isometry3_to_rerun_transform3d(transform))
+            .unwrap();
+    }
+
+    fn log_pinhole(&self, topic: &str, model: &GenericModel<f64>) {
+        let params = model.params();
+        self.log_static(
+            topic,
+            &rerun::Pinhole::from_focal_length_and_resolution(
+                [params[0] as f32, params[1] as f32],
+                [model.width() as f32, model.height() as f32],
+            )
+            .with_principal_point([params[2] as f32, params[3] as f32])
+            .with_camera_xyz(rerun::components::ViewCoordinates::RDF)
+            .with_image_plane_distance(0.1),
+        )
+        .unwrap();
+    }
+}
+
+/// Logs images, key frames and validation results to a `Visualizer`.
+pub struct VisualizerObserver<V: Visualizer> {
+    pub visualizer: V,
+}
+
+#[cfg(feature = "rerun")]
+pub type RerunObserver = VisualizerObserver<RecordingStream>;
+
+impl<V: Visualizer> PipelineObserver for VisualizerObserver<V> {
+    fn on_frame_detected(
+        &self,
+        cam_idx: usize,
+        time_ns: i64,
+        img: &DynamicImage,
+        _frame_feature: Option<&FrameFeature>,
+    ) {
+        log_image_at(&self.visualizer, &format!("/cam{}", cam_idx), time_ns, img);
+    }
+
+    fn on_init_complete(
+        &self,
+        cam_idx: usize,
+        _initial_model: &GenericModel<f64>,
+        key_frames: [&FrameFeature; 2],
+    ) {
+        key_frames.iter().enumerate().for_each(|(i, k)| {
+            let topic = format!("/cam{}/keyframe{}", cam_idx, i);
+            log_text(&self.visualizer, &topic, k.time_ns, "keyframe");
+        });
+    }
+
+    fn on_frame_validated(
+        &self,
+        cam_idx: usize,
+        frame_feature: &FrameFeature,
+        t_cam_board: &na::Isometry3<f64>,
+        residuals: &[na::Vector2<f64>],
+    ) {
+        let reprojection_errors: Vec<_> = residuals.iter().map(|r| r.norm()).collect();
+        let (p3ds, p2ds): (Vec<_>, Vec<_>) = frame_feature
+            .features
+            .values()
+            .map(|feature| {
+                let p3 = na::Point3::new(feature.p3d.x, feature.p3d.y, feature.p3d.z);
+                let p3p = t_cam_board.cast() * p3;
+                ((p3p.x, p3p.y, p3p.z), (feature.p2d.x, feature.p2d.y))
+            })
+            .unzip();
+        let avg_err = reprojection_errors.iter().sum::<f64>() / reprojection_errors.len() as f64;
+        log_board_points(
+            &self.visualizer,
+            &format!("/cam{}/board", cam_idx),
+            frame_feature.time_ns,
+            &p3ds,
+            avg_err,
+        );
+        log_reprojection_errors(
+            &self.visualizer,
+            &format!("/cam{}/rep_err", cam_idx),
+            frame_feature.time_ns,
+            &reprojection_errors,
+            &p2ds,
+        );
+        log_residual_arrows(
+            &self.visualizer,
+            &format!("/cam{}/residuals", cam_idx),
+            frame_feature.time_ns,
+            &p2ds,
+            residuals,
+            RESIDUAL_ARROW_SCALE,
+        );
+        // all board poses at once, to spot the badly estimated ones
+        self.visualizer.log_points3d(
+            &format!("/cam{}/board_poses/{}", cam_idx, frame_feature.time_ns),
+            &p3ds,
+            &[reprojection_error_to_color(avg_err)],
+            &[format!("{} {:.3} px", frame_feature.time_ns, avg_err)],
+            true,
+        );
+    }
+
+    fn on_validation_done(
+        &self,
+        cam_idx: usize,
+        model: &GenericModel<f64>,
+        _avg_99_percent: f64,
+        _median_reprojection_error: f64,
+    ) {
+        self.visualizer
+            .log_pinhole(&format!("/cam{}/image", cam_idx), model);
     }
 }
@@ -0,0 +1,216 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use camera_intrinsic_model::GenericModel;
+
+use crate::board::Board;
+use crate::data_loader::{detect_frames, path_to_timestamp};
+use crate::detected_points::{FrameFeature, TagDetection};
+use crate::observer::NoopObserver;
+use crate::optimization::IncrementalProblem;
+use crate::types::{CalibParams, RvecTvec};
+use crate::util::init_and_calibrate_one_camera_with_trials;
+
+/// Images modified more recently than this may still be being written.
+const SETTLE_TIME: Duration = Duration::from_secs(1);
+
+fn is_settled(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age >= SETTLE_TIME)
+}
+
+/// Time [ns] of a watched image, its name if it's a number and else its modification time, so
+/// it doesn't change when images sorting before it are added to the folder.
+pub fn frame_time_ns(path: &Path) -> i64 {
+    let time_ns = path_to_timestamp(path);
+    if time_ns != 0 {
+        return time_ns;
+    }
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map_or(0, |since_epoch| since_epoch.as_nanos() as i64)
+}
+
+/// Detections of the images of a dataset folder that keeps growing, e.g. from a capture rig
+/// dumping frames, so every image is detected once however often the folder is scanned.
+pub struct DetectionCache {
+    /// `[cam]` of the detections in the order the images were detected.
+    cams: Vec<Vec<Option<FrameFeature>>>,
+    /// `[cam]` of the paths of the detected images.
+    detected: Vec<HashSet<PathBuf>>,
+}
+
+impl DetectionCache {
+    pub fn new(cam_num: usize) -> DetectionCache {
+        DetectionCache {
+            cams: vec![Vec::new(); cam_num],
+            detected: vec![HashSet::new(); cam_num],
+        }
+    }
+
+    /// Detects the board in the `(time_ns, path)` images `[cam][image]` that aren't cached yet,
+    /// leaving the ones still being written for the next update. Returns the number of new
+    /// frames with detections.
+    pub fn update(
+        &mut self,
+        cams_frames: Vec<Vec<(i64, PathBuf)>>,
+        tag_detector: &dyn TagDetection,
+        board: &Board,
+        queue_size: usize,
+    ) -> usize {
+        let mut detected = 0;
+        for (cam_idx, ((cache, paths), frames)) in self
+            .cams
+            .iter_mut()
+            .zip(&mut self.detected)
+            .zip(cams_frames)
+            .enumerate()
+        {
+            let mut new_frames: Vec<_> = frames
+                .into_iter()
+                .filter(|(_, path)| !paths.contains(path) && is_settled(path))
+                .collect();
+            if new_frames.is_empty() {
+                continue;
+            }
+            // detect_frames returns the frames sorted by time
+            new_frames.sort_by_key(|f| f.0);
+            let features = detect_frames(
+                new_frames.clone(),
+                cam_idx,
+                tag_detector,
+                board,
+                queue_size,
+                &NoopObserver,
+            );
+            for ((_, path), frame_feature) in new_frames.into_iter().zip(features) {
+                detected += frame_feature.is_some() as usize;
+                paths.insert(path);
+                cache.push(frame_feature);
+            }
+        }
+        detected
+    }
+
+    /// `[cam][frame]` in the order the images were detected, the frames of an update sorted by
+    /// time, so the index of a frame doesn't change as the folder grows.
+    pub fn frames(&self) -> Vec<Vec<Option<FrameFeature>>> {
+        self.cams.clone()
+    }
+}
+
+/// Calibration of a camera of the watched folder, every calibration after the first one is
+/// warm started from the last one and only solves the board poses of the new frames.
+#[derive(Default)]
+pub struct WatchCalibration {
+    problem: Option<IncrementalProblem>,
+    /// Frames of the camera in `problem`.
+    frame_num: usize,
+}
+
+impl WatchCalibration {
+    /// Calibrates `cam_idx` with the `[cam][frame]` frames of `DetectionCache::frames`, from
+    /// scratch if there's no calibration yet or the warm started one fails.
+    pub fn calibrate(
+        &mut self,
+        cam_idx: usize,
+        cams_frames: &[Vec<Option<FrameFeature>>],
+        target_model: &GenericModel<f64>,
+        calib_params: &CalibParams,
+    ) -> Option<(GenericModel<f64>, HashMap<usize, RvecTvec>)> {
+        let frames = &cams_frames[cam_idx];
+        if let Some(problem) = &mut self.problem {
+            for (i, frame_feature) in frames.iter().enumerate().skip(self.frame_num) {
+                if let Some(frame_feature) = frame_feature {
+                    problem.add_frame(i, frame_feature.clone());
+                }
+            }
+            self.frame_num = frames.len();
+            if let Some(calibrated) = problem.solve(&NoopObserver) {
+                return Some(calibrated);
+            }
+            tracing::warn!("cam{} warm started calibration failed", cam_idx);
+        }
+        let (model, rtvec_map) = init_and_calibrate_one_camera_with_trials(
+            cam_idx,
+            cams_frames,
+            target_model,
+            &NoopObserver,
+            calib_params,
+            3,
+        )?;
+        self.problem = Some(IncrementalProblem::from_calibration(
+            &model,
+            &rtvec_map,
+            frames,
+            calib_params,
+        ));
+        self.frame_num = frames.len();
+        Some((model, rtvec_map))
+    }
+
+    /// Whether the next calibration is warm started.
+    pub fn is_warm(&self) -> bool {
+        self.problem.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{eucm, frames};
+
+    #[test]
+    fn frame_time_by_name_or_mtime() {
+        let folder = std::env::temp_dir().join("ccrs_watch_time_test");
+        std::fs::create_dir_all(&folder).unwrap();
+        let named = folder.join("1403709080937837056.png");
+        let unnamed = folder.join("b.png");
+        for path in [&named, &unnamed] {
+            std::fs::write(path, []).unwrap();
+        }
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        std::fs::File::options()
+            .write(true)
+            .open(&unnamed)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_eq!(frame_time_ns(&named), 1403709080937837056);
+        // an image sorting before it doesn't change its time
+        std::fs::write(folder.join("a.png"), []).unwrap();
+        assert_eq!(frame_time_ns(&unnamed), 1_700_000_000_000_000_000);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn warm_started_with_new_frames() {
+        let model = eucm();
+        let (all_frames, _) = frames(&model, 16);
+        let calib_params = CalibParams {
+            fixed_focal: None,
+            disabled_distortion_num: 0,
+            one_focal: false,
+            plateau: None,
+        };
+        let mut calibration = WatchCalibration::default();
+        let (_, rtvec_map) = calibration
+            .calibrate(0, &[all_frames[..8].to_vec()], &model, &calib_params)
+            .unwrap();
+        assert!(calibration.is_warm());
+        assert!(rtvec_map.keys().all(|&i| i < 8));
+
+        let (calibrated, rtvec_map) = calibration
+            .calibrate(0, std::slice::from_ref(&all_frames), &model, &calib_params)
+            .unwrap();
+        assert!(rtvec_map.contains_key(&15));
+        let params = model.params();
+        assert!((calibrated.params() - &params).norm() / params.norm() < 1e-4);
+    }
+}
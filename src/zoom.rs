@@ -0,0 +1,82 @@
+use camera_intrinsic_model::*;
+use serde::{Deserialize, Serialize};
+
+/// Images of a lens at a zoom or focus position in the euroc format. The position is any
+/// value that increases with the setting, e.g. the focal length in mm or the motor step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoomDataset {
+    pub position: f64,
+    pub path: String,
+}
+
+/// Intrinsics of the lens calibrated at a position.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoomPosition {
+    pub position: f64,
+    pub model: GenericModel<f64>,
+    pub reprojection_mean: f64,
+}
+
+/// Intrinsics of a zoom lens as a table over the position, the intrinsics between two
+/// calibrated positions are interpolated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoomCalibration {
+    pub positions: Vec<ZoomPosition>,
+}
+
+impl ZoomCalibration {
+    /// Sorts the positions, panics if the models are not the same model at the same resolution.
+    pub fn new(mut positions: Vec<ZoomPosition>) -> ZoomCalibration {
+        positions.sort_by(|a, b| a.position.total_cmp(&b.position));
+        if let Some(first) = positions.first() {
+            for p in &positions[1..] {
+                if std::mem::discriminant(&p.model) != std::mem::discriminant(&first.model) {
+                    panic!("the zoom positions are calibrated with different models.")
+                } else if p.model.width() != first.model.width()
+                    || p.model.height() != first.model.height()
+                {
+                    panic!("the zoom positions have different resolutions.")
+                }
+            }
+        }
+        ZoomCalibration { positions }
+    }
+
+    /// Intrinsics at `position`, the params are linearly interpolated between the closest
+    /// calibrated positions and clamped to the calibrated range.
+    pub fn model_at(&self, position: f64) -> Option<GenericModel<f64>> {
+        let (first, last) = (self.positions.first()?, self.positions.last()?);
+        if position <= first.position {
+            return Some(first.model);
+        } else if position >= last.position {
+            return Some(last.model);
+        }
+        let upper = self.positions.partition_point(|p| p.position < position);
+        let (p0, p1) = (&self.positions[upper - 1], &self.positions[upper]);
+        let t = (position - p0.position) / (p1.position - p0.position);
+        let params = p0.model.params() * (1.0 - t) + p1.model.params() * t;
+        Some(p0.model.new_from_params(&params))
+    }
+
+    /// The focal should change in one direction over the position, a step the other way
+    /// usually means a failed calibration.
+    pub fn warnings(&self) -> Vec<String> {
+        let focals: Vec<_> = self.positions.iter().map(|p| p.model.params()[0]).collect();
+        let steps: Vec<_> = focals.windows(2).map(|f| f[1] - f[0]).collect();
+        let increasing = steps.iter().filter(|&&s| s > 0.0).count() * 2 >= steps.len();
+        steps
+            .iter()
+            .enumerate()
+            .filter(|(_, &s)| (s > 0.0) != increasing && s != 0.0)
+            .map(|(i, _)| {
+                format!(
+                    "focal {:.2} at position {} and {:.2} at position {} break the trend",
+                    focals[i],
+                    self.positions[i].position,
+                    focals[i + 1],
+                    self.positions[i + 1].position
+                )
+            })
+            .collect()
+    }
+}